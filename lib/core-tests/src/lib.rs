@@ -0,0 +1 @@
+// nothing but the tests in tests/, of lib built with core alone
@@ -0,0 +1,69 @@
+// what lib offers with --no-default-features --features core, run
+// without the features lib's own tests turn on: the compat fixtures
+// decode and hash as pinned, their signatures verify, and the compat
+// chain validates block by block into a new chain
+use lib::params::Network;
+use lib::sha256::Hash;
+use lib::types::{Block, Blockchain, Transaction};
+use lib::utils::{MerkleRoot, Saveable};
+
+const BLOCK_HEX: &str = include_str!("../../compat/block.hex");
+const TRANSACTION_HEX: &str = include_str!("../../compat/transaction.hex");
+const BLOCKCHAIN: &[u8] = include_bytes!("../../compat/blockchain.cbor");
+const BLOCK_HASH: &str = "44d09bba98495a920e42f4e549d8b45775fa18b9f87a2c36cf77c342a32be9e9";
+const TRANSACTION_HASH: &str = "f769e2adeca7c699a1be86e803dff4f261e3692344a908665d153128fe5cb303";
+
+#[test]
+fn fixtures_decode_and_hash_as_pinned() {
+    let block = Block::from_hex(BLOCK_HEX).unwrap();
+    assert_eq!(block.hash().to_hex(), BLOCK_HASH);
+    assert!(block.hash().matches_target(block.header.target));
+    assert_eq!(
+        MerkleRoot::calculate(&block.transactions),
+        block.header.merkle_root
+    );
+    let transaction = Transaction::from_hex(TRANSACTION_HEX).unwrap();
+    assert_eq!(transaction.hash().to_hex(), TRANSACTION_HASH);
+    assert_eq!(block.transactions[1], transaction);
+    assert!(Transaction::from_hex(&format!("{}00", TRANSACTION_HEX.trim())).is_err());
+}
+
+#[test]
+fn spend_signature_verifies() {
+    let chain = Blockchain::load(BLOCKCHAIN).unwrap();
+    let transaction = Transaction::from_hex(TRANSACTION_HEX).unwrap();
+    let input = &transaction.inputs[0];
+    let outpoint = input.prev_transaction_output_hash;
+    // the coinbase of the first block, spent by the last
+    let spent = chain.blocks().next().unwrap().transactions[0].outputs[0].clone();
+    assert_eq!(spent.hash(), outpoint);
+    assert!(
+        input
+            .signature
+            .verify(&transaction.sighash(&outpoint), &spent.pubkey)
+    );
+    assert!(
+        !input
+            .signature
+            .verify(&transaction.sighash(&Hash::zero()), &spent.pubkey)
+    );
+}
+
+#[test]
+fn compat_chain_validates_block_by_block() {
+    let saved = Blockchain::load(BLOCKCHAIN).unwrap();
+    saved.verify().unwrap();
+    assert_eq!(saved.network(), Network::Regtest);
+    let mut chain = Blockchain::with_params(saved.params().clone());
+    for block in saved.blocks() {
+        chain.add_block(block.clone()).unwrap();
+    }
+    assert_eq!(chain.tip_hash().to_hex(), BLOCK_HASH);
+    assert_eq!(chain.utxo_commitment(), saved.utxo_commitment());
+    // a block again, or one with a transaction dropped, is refused
+    let tip = saved.blocks().last().unwrap().clone();
+    assert!(chain.add_block(tip.clone()).is_err());
+    let mut damaged = tip;
+    damaged.transactions.pop();
+    assert!(chain.add_block(damaged).is_err());
+}
@@ -0,0 +1,366 @@
+// criterion timings of the hot paths, over fixtures built the same
+// way every run so timings compare across commits:
+//
+// cargo run --release --features bench --bin bench -- --bench [filter]
+//
+// with --features bench,parallel verify_transactions also times a
+// PARALLEL_INPUTS block on thread pools of 1, 2, 4 ... cores, whose
+// thrpt should grow close to the thread count.
+//
+// "time: [low estimate high]" is one iteration, the estimate within a
+// 95% confidence interval; "thrpt" is the same per transaction or
+// input. each run is saved in target/criterion and the next compares
+// against it, so run on the base commit, then with the change; a
+// change inside the noise threshold is reported as no change.
+// --save-baseline <name> and --baseline <name> keep a named run.
+// --test instead of --bench runs each one once, a smoke test that the
+// fixture builds and every timed call still succeeds. before the
+// timings the allocations of one verify_transactions, add_block and
+// header hash are printed, which the timings don't show
+use chrono::{DateTime, Utc};
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use lib::crypto::Signature;
+use lib::error::SbdError;
+use lib::sha256::Hash;
+use lib::test_utils::{ChainBuilder, seeded_key};
+use lib::types::{Block, Blockchain, Transaction, TransactionOutput};
+use lib::utils::MerkleRoot;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use uuid::Uuid;
+
+// the system allocator, counting allocations
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn allocations<T>(f: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    drop(f());
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+// outputs the coinbase of the fixture's first block pays
+const FUNDED: usize = 6_010;
+// keys the funded outputs go to in turn
+const KEYS: usize = 64;
+const BLOCK_INPUTS: usize = 1_000;
+#[cfg(feature = "parallel")]
+const PARALLEL_INPUTS: usize = 2_000;
+const INPUTS_PER_TRANSACTION: usize = 4;
+// insertion and replacing a conflicting entry are timed
+// in mempools of each size
+const MEMPOOL_SIZES: [usize; 2] = [500, 5_000];
+const FEE: u64 = 100;
+
+// a chain of one block funding FUNDED outputs, from ChainBuilder so
+// every run builds the same bytes
+struct Fixture {
+    builder: ChainBuilder,
+    funded: Vec<TransactionOutput>,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        let mut builder = ChainBuilder::new(1).with_keys(KEYS);
+        let reward = builder.chain().params().reward_at_height(0);
+        let funded: Vec<TransactionOutput> = (0..FUNDED)
+            .map(|i| {
+                let mut value = reward / FUNDED as u64;
+                if i == 0 {
+                    value += reward % FUNDED as u64;
+                }
+                builder.output(value, i % KEYS)
+            })
+            .collect();
+        let payouts = funded.clone();
+        builder
+            .mine_block(|template| template.payouts = payouts)
+            .expect("BUG: invalid fixture block");
+        Fixture { builder, funded }
+    }
+
+    fn chain(&self) -> &Blockchain {
+        self.builder.chain()
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        self.builder.timestamp(2)
+    }
+
+    // the funded outputs at indices, to one output of their first
+    // key; its id is fixed, so a spend is the same every time
+    fn spend(&self, indices: &[usize]) -> Transaction {
+        let spent: Vec<Hash> = indices.iter().map(|&i| self.funded[i].hash()).collect();
+        let value: u64 = indices.iter().map(|&i| self.funded[i].value).sum();
+        let output = TransactionOutput {
+            value: value - FEE,
+            unique_id: Uuid::from_u128((FUNDED + 1 + indices[0]) as u128),
+            pubkey: self.funded[indices[0]].pubkey.clone(),
+            spendable_after_height: None,
+        };
+        self.builder
+            .sign(&spent, vec![output])
+            .expect("BUG: invalid fixture transaction")
+    }
+
+    // the next block, spending the first count outputs
+    fn spending_block(&mut self, count: usize) -> Block {
+        let spends: Vec<Transaction> = (0..count)
+            .collect::<Vec<_>>()
+            .chunks(INPUTS_PER_TRANSACTION)
+            .map(|indices| self.spend(indices))
+            .collect();
+        self.builder
+            .block(|template| template.transactions = spends)
+            .expect("BUG: invalid fixture block")
+    }
+
+    // the chain with a mempool of size transactions, spending
+    // the outputs after those of spending_block
+    fn mempool_chain(&self, size: usize) -> Blockchain {
+        let mut chain = self.chain().clone();
+        for index in BLOCK_INPUTS..BLOCK_INPUTS + size {
+            chain
+                .add_to_mempool_at(self.now(), self.spend(&[index]))
+                .expect("BUG: invalid fixture transaction");
+        }
+        chain
+    }
+
+    // a clone of chain that owns its utxos: the first write to a
+    // clone copies the utxos it shares, which isn't what is timed
+    fn unshared(&self, chain: &Blockchain, index: usize) -> Blockchain {
+        let mut chain = chain.clone();
+        chain
+            .add_to_mempool_at(self.now(), self.spend(&[index]))
+            .expect("BUG: invalid fixture transaction");
+        chain
+    }
+}
+
+// transactions of one output each, for merkle roots of any size
+fn transactions(count: usize) -> Vec<Transaction> {
+    let pubkey = seeded_key(1, 0).public_key();
+    (0..count)
+        .map(|i| {
+            let output = TransactionOutput {
+                value: i as u64,
+                unique_id: Uuid::from_u128(i as u128),
+                pubkey: pubkey.clone(),
+                spendable_after_height: None,
+            };
+            Transaction::new(vec![], vec![output])
+        })
+        .collect()
+}
+
+fn benches(c: &mut Criterion) {
+    let mut fixture = Fixture::new();
+    let block = fixture.spending_block(BLOCK_INPUTS);
+    let coinbase_heights = fixture.chain().immature_coinbases();
+    // one signature, by the wrong key, in the middle of the block
+    let mut forged = block.clone();
+    let middle = forged.transactions.len() / 2;
+    let transaction = &mut forged.transactions[middle];
+    let sighash = transaction.sighash(&transaction.inputs[1].prev_transaction_output_hash);
+    transaction.inputs[1].signature = Signature::sign_input(&sighash, fixture.builder.key(0));
+    assert!(
+        matches!(
+            forged.verify_transactions(
+                fixture.chain().params(),
+                1,
+                fixture.chain().utxos(),
+                &coinbase_heights
+            ),
+            Err(SbdError::InvalidSignature)
+        ),
+        "BUG: a forged signature passed"
+    );
+    let verify = allocations(|| {
+        block
+            .verify_transactions(
+                fixture.chain().params(),
+                1,
+                fixture.chain().utxos(),
+                &coinbase_heights,
+            )
+            .expect("BUG: invalid fixture block")
+    });
+    let mut chain = fixture.unshared(fixture.chain(), FUNDED - 1);
+    let copy = block.clone();
+    let add = allocations(|| chain.add_block(copy).expect("BUG: invalid fixture block"));
+    let header = allocations(|| block.header.hash());
+    println!(
+        "allocations for {BLOCK_INPUTS} inputs: verify_transactions {verify}, add_block {add}; \
+         header hash {header}"
+    );
+
+    let mut group = c.benchmark_group("header_hash");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("hash", |b| b.iter(|| block.header.hash()));
+    group.finish();
+
+    let mut group = c.benchmark_group("merkle_root");
+    for count in [100, 1_000, 10_000] {
+        let transactions = transactions(count);
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(count),
+            &transactions,
+            |b, transactions| b.iter(|| MerkleRoot::calculate(transactions)),
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("verify_transactions");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(BLOCK_INPUTS as u64));
+    group.bench_function(BenchmarkId::new("inputs", BLOCK_INPUTS), |b| {
+        b.iter(|| {
+            block
+                .verify_transactions(
+                    fixture.chain().params(),
+                    1,
+                    fixture.chain().utxos(),
+                    &coinbase_heights,
+                )
+                .expect("BUG: invalid fixture block")
+        })
+    });
+    #[cfg(feature = "parallel")]
+    {
+        let block = fixture.spending_block(PARALLEL_INPUTS);
+        let cores = std::thread::available_parallelism().map_or(1, usize::from);
+        group.throughput(Throughput::Elements(PARALLEL_INPUTS as u64));
+        for threads in std::iter::successors(Some(1), |n| Some(n * 2)).take_while(|&n| n <= cores) {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .expect("BUG: no thread pool");
+            let id = BenchmarkId::new(format!("inputs/{PARALLEL_INPUTS}/threads"), threads);
+            group.bench_function(id, |b| {
+                b.iter(|| {
+                    pool.install(|| {
+                        block
+                            .verify_transactions(
+                                fixture.chain().params(),
+                                1,
+                                fixture.chain().utxos(),
+                                &coinbase_heights,
+                            )
+                            .expect("BUG: invalid fixture block")
+                    })
+                })
+            });
+        }
+    }
+    group.finish();
+
+    // the walk over the spends alone, which verify_transactions
+    // makes once and calculate_miner_fees makes for itself
+    let mut group = c.benchmark_group("miner_fees");
+    group.throughput(Throughput::Elements(BLOCK_INPUTS as u64));
+    group.bench_function(BenchmarkId::new("inputs", BLOCK_INPUTS), |b| {
+        b.iter(|| {
+            block
+                .calculate_miner_fees(fixture.chain().utxos())
+                .expect("BUG: invalid fixture block")
+        })
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("add_block");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(BLOCK_INPUTS as u64));
+    group.bench_function(BenchmarkId::new("inputs", BLOCK_INPUTS), |b| {
+        b.iter_batched(
+            || {
+                let chain = fixture.unshared(fixture.chain(), FUNDED - 1);
+                (chain, block.clone())
+            },
+            |(mut chain, block)| {
+                chain.add_block(block).expect("BUG: invalid fixture block");
+                chain
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("add_to_mempool");
+    group.throughput(Throughput::Elements(1));
+    let transaction = fixture.spend(&[FUNDED - 2]);
+    for size in MEMPOOL_SIZES {
+        let mempool_chain = fixture.mempool_chain(size);
+        group.bench_function(BenchmarkId::new("mempool", size), |b| {
+            b.iter_batched(
+                || {
+                    let chain = fixture.unshared(&mempool_chain, FUNDED - 1);
+                    (chain, transaction.clone())
+                },
+                // returned, so the chain is dropped outside the timing
+                |(mut chain, transaction)| {
+                    chain
+                        .add_to_mempool_at(fixture.now(), transaction)
+                        .expect("BUG: invalid fixture transaction");
+                    chain
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+
+    // replacing the entry spending the first output of mempool_chain
+    let mut group = c.benchmark_group("mempool_conflict");
+    group.throughput(Throughput::Elements(1));
+    let replacement = fixture.spend(&[BLOCK_INPUTS, FUNDED - 2]);
+    for size in MEMPOOL_SIZES {
+        let mempool_chain = fixture.mempool_chain(size);
+        let replaced = fixture.spend(&[BLOCK_INPUTS]).hash();
+        let mut chain = mempool_chain.clone();
+        chain
+            .add_to_mempool_at(fixture.now(), replacement.clone())
+            .expect("BUG: invalid fixture transaction");
+        assert!(
+            chain.mempool_get(&replaced).is_none()
+                && chain.mempool_get(&replacement.hash()).is_some()
+                && chain.mempool().len() == size,
+            "BUG: conflicting transaction not replaced"
+        );
+        group.bench_function(BenchmarkId::new("mempool", size), |b| {
+            b.iter_batched(
+                || {
+                    let chain = fixture.unshared(&mempool_chain, FUNDED - 1);
+                    (chain, replacement.clone())
+                },
+                |(mut chain, transaction)| {
+                    chain
+                        .add_to_mempool_at(fixture.now(), transaction)
+                        .expect("BUG: invalid fixture transaction");
+                    chain
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(hot_paths, benches);
+criterion_main!(hot_paths);
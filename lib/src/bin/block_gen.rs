@@ -1,19 +1,36 @@
-use chrono::Utc;
+use clap::Parser;
+use lib::cli::{FormatArgs, Verbosity, usage_error};
 use lib::crypto::PrivateKey;
-use lib::sha256::Hash;
-use lib::types::{Block, BlockHeader, Transaction, TransactionOutput};
-use lib::utils::{MerkleRoot, Saveable};
-use std::env;
-use std::process::exit;
+use lib::error::Report;
+use lib::types::{Block, Transaction, TransactionOutput};
+use lib::utils::{FileFormat, Saveable};
+use std::fs;
+use std::path::PathBuf;
 use uuid::Uuid;
 
-fn main() {
-    let path = if let Some(arg) = env::args().nth(1) {
-        arg
-    } else {
-        eprintln!("Usage: block_gen <block_file>");
-        exit(1);
-    };
+/// Write a block holding one coinbase-like transaction, for fixtures
+#[derive(Parser)]
+#[command(name = "block_gen", version)]
+struct Cli {
+    /// Where to write the block
+    block_file: PathBuf,
+    #[command(flatten)]
+    format: FormatArgs,
+    /// Compress the block with zstd, which json can't be
+    #[arg(long)]
+    compress: bool,
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+fn main() -> Result<(), Report> {
+    let cli = Cli::parse();
+    lib::logging::init(cli.verbosity.verbose);
+    let format = cli.format.format;
+    if cli.compress && format == FileFormat::Json {
+        usage_error::<Cli>("--compress writes cbor, not json");
+    }
+    let path = cli.block_file.display();
     let private_key = PrivateKey::new_key();
     let transactions = vec![Transaction::new(
         vec![],
@@ -21,12 +38,28 @@ fn main() {
             unique_id: Uuid::new_v4(),
             value: lib::INITIAL_REWARD * 10u64.pow(8),
             pubkey: private_key.public_key(),
+            spendable_after_height: None,
         }],
     )];
-    let merkle_root = MerkleRoot::calculate(&transactions);
-    let block = Block::new(
-        BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, lib::MIN_TARGET),
-        transactions,
-    );
-    block.save_to_file(path).expect("Failed to save block");
+    let block = Block::builder().transactions(transactions).build()?;
+    let failed = |e| Report::new(format!("{path}: {e}"));
+    if !cli.compress {
+        return block
+            .save_to_file_as(&cli.block_file, format)
+            .map_err(failed);
+    }
+    block
+        .save_compressed_to_file(&cli.block_file)
+        .map_err(failed)?;
+    if cli.verbosity.verbose > 0 {
+        let mut raw = vec![];
+        block.save(&mut raw)?;
+        let compressed = fs::metadata(&cli.block_file).map_err(failed)?.len();
+        println!(
+            "compressed {} to {compressed} bytes, ratio {:.2}",
+            raw.len(),
+            raw.len() as f64 / compressed as f64
+        );
+    }
+    Ok(())
 }
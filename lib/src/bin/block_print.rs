@@ -1,19 +1,182 @@
-use lib::types::Block;
-use lib::utils::Saveable;
-use std::env;
-use std::fs::File;
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::Parser;
+use lib::U256;
+use lib::cli::{FormatArgs, JsonArgs, Verbosity};
+use lib::sha256::{Hash, HashKeyedMap};
+use lib::types::{Block, Blockchain, Transaction, TransactionOutput};
+use lib::utils::{MerkleRoot, Saveable, format_value};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 
-fn main() {
-    let path = if let Some(arg) = env::args().nth(1) {
-        arg
-    } else {
-        eprintln!("Usage: block_print <block_file>");
+#[derive(Serialize)]
+struct BlockSummary {
+    hash: Hash,
+    prev_hash: Hash,
+    merkle_root: MerkleRoot,
+    timestamp: DateTime<Utc>,
+    nonce: u64,
+    target: U256,
+    size: u64,
+    transactions: Vec<TransactionLine>,
+    // only known with --chain, and if every input was found
+    fees: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct TransactionLine {
+    txid: Hash,
+    coinbase: bool,
+    inputs: usize,
+    outputs: usize,
+    output_value: u64,
+    fee: Option<u64>,
+}
+
+/// Print the header and transactions of a block file
+#[derive(Parser)]
+#[command(name = "block_print", version)]
+struct Cli {
+    block_file: PathBuf,
+    #[command(flatten)]
+    format: FormatArgs,
+    /// Blockchain file to look up inputs in, for the fees
+    #[arg(long, value_name = "BLOCKCHAIN_FILE")]
+    chain: Option<PathBuf>,
+    #[command(flatten)]
+    json: JsonArgs,
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+// every output the chain ever created, spent or not
+fn chain_outputs(path: &Path) -> HashKeyedMap<TransactionOutput> {
+    let blockchain = Blockchain::load_from_file(path).unwrap_or_else(|e| {
+        eprintln!("{}: {e}", path.display());
         exit(1);
+    });
+    blockchain
+        .blocks()
+        .flat_map(|block| &block.transactions)
+        .flat_map(|transaction| &transaction.outputs)
+        .map(|output| (output.hash(), output.clone()))
+        .collect()
+}
+
+fn summarize(block: &Block, mut outputs: Option<HashKeyedMap<TransactionOutput>>) -> BlockSummary {
+    let mut transactions = vec![];
+    for (index, transaction) in block.transactions.iter().enumerate() {
+        let coinbase = index == 0;
+        let output_value = transaction.outputs.iter().map(|output| output.value).sum();
+        let fee = match &outputs {
+            Some(outputs) if !coinbase => transaction
+                .inputs
+                .iter()
+                .map(|input| {
+                    outputs
+                        .get(&input.prev_transaction_output_hash)
+                        .map(|output| output.value)
+                })
+                .sum::<Option<u64>>()
+                .map(|input_value| input_value.saturating_sub(output_value)),
+            _ => None,
+        };
+        // later transactions may spend this one's outputs
+        if let Some(outputs) = &mut outputs {
+            outputs.extend(
+                transaction
+                    .outputs
+                    .iter()
+                    .map(|output| (output.hash(), output.clone())),
+            );
+        }
+        transactions.push(TransactionLine {
+            txid: transaction.hash(),
+            coinbase,
+            inputs: transaction.inputs.len(),
+            outputs: transaction.outputs.len(),
+            output_value,
+            fee,
+        });
+    }
+    let fees = match outputs {
+        Some(_) => transactions
+            .iter()
+            .skip(1)
+            .map(|transaction| transaction.fee)
+            .sum(),
+        None => None,
     };
+    BlockSummary {
+        hash: block.hash(),
+        prev_hash: block.header.prev_block_hash,
+        merkle_root: block.header.merkle_root,
+        timestamp: block.header.timestamp,
+        nonce: block.header.nonce,
+        target: block.header.target,
+        size: encoded_size(block),
+        transactions,
+        fees,
+    }
+}
+
+fn encoded_size(block: &Block) -> u64 {
+    let mut bytes = vec![];
+    ciborium::into_writer(block, &mut bytes).expect("BUG: encoding failed");
+    bytes.len() as u64
+}
 
-    if let Ok(file) = File::open(path) {
-        let block = Block::load(file).expect("Failed to load block");
-        println!("{:#?}", block);
+fn print(summary: &BlockSummary) {
+    println!("hash          {}", summary.hash.to_hex());
+    println!("previous      {}", summary.prev_hash.to_hex());
+    println!("merkle root   {:0>64}", summary.merkle_root.to_string());
+    println!(
+        "timestamp     {}",
+        summary
+            .timestamp
+            .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+    );
+    println!("nonce         {}", summary.nonce);
+    println!("target        {:064x}", summary.target);
+    println!("size          {} bytes", summary.size);
+    match summary.fees {
+        Some(fees) => println!("fees          {}", format_value(fees)),
+        None => println!("fees          unknown, --chain looks up the inputs"),
+    }
+    println!("transactions  {}", summary.transactions.len());
+    for transaction in &summary.transactions {
+        let fee = match transaction.fee {
+            _ if transaction.coinbase => "coinbase".to_string(),
+            Some(fee) => format!("fee {}", format_value(fee)),
+            None => "fee unknown".to_string(),
+        };
+        println!(
+            "  {}  {:>3} in  {:>3} out  {:>20}  {fee}",
+            transaction.txid.to_hex(),
+            transaction.inputs,
+            transaction.outputs,
+            format_value(transaction.output_value)
+        );
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    lib::logging::init(cli.verbosity.verbose);
+    let (path, format) = (&cli.block_file, cli.format.format);
+    let block = Block::load_from_file_as(path, format).unwrap_or_else(|e| {
+        let path = path.display();
+        if Transaction::load_from_file_as(&cli.block_file, format).is_ok() {
+            eprintln!("{path} holds a transaction, not a block, see tx_print");
+        } else {
+            eprintln!("{path}: {e}");
+        }
+        exit(1);
+    });
+    let summary = summarize(&block, cli.chain.as_deref().map(chain_outputs));
+    if cli.json.json {
+        println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+    } else {
+        print(&summary);
     }
 }
@@ -0,0 +1,117 @@
+use clap::Parser;
+use lib::cli::{JsonArgs, Verbosity};
+use lib::error::Report;
+use lib::replay::{Replay, replay};
+use lib::types::{Block, Blockchain};
+use lib::utils::{Saveable, is_compressed};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+/// Check a chain file is consistent, and repair it
+///
+/// every block is validated again from genesis, the utxo set and the
+/// target worked out again and compared to the stored ones; exits 1
+/// if anything differs, unless --repair writes the chain up to its
+/// first invalid block with its utxos and target worked out again
+#[derive(Parser)]
+#[command(name = "chain_doctor", version)]
+struct Cli {
+    blockchain_file: PathBuf,
+    /// Write the chain up to its first invalid block to --out
+    #[arg(long, requires = "out")]
+    repair: bool,
+    /// Where --repair writes the chain
+    #[arg(long, value_name = "FIXED_CHAIN_FILE", requires = "repair")]
+    out: Option<PathBuf>,
+    #[command(flatten)]
+    json: JsonArgs,
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+fn print(path: &Path, replay: &Replay, utxos: usize) {
+    println!(
+        "{}: {} blocks, {utxos} utxos",
+        path.display(),
+        replay.stored_height
+    );
+    for divergence in &replay.divergences {
+        println!("{divergence}");
+    }
+    if !replay.state_compared {
+        println!(
+            "{} blocks after the invalid one not checked, nor the utxos and target",
+            replay.stored_height - replay.replayed_height - 1
+        );
+    }
+    match replay.divergences.len() {
+        0 => println!("no discrepancies"),
+        count if replay.valid_height == replay.stored_height => {
+            println!("{count} discrepancies, every block is valid")
+        }
+        count => println!(
+            "{count} discrepancies, the first {} blocks are valid",
+            replay.valid_height
+        ),
+    }
+}
+
+// the valid blocks again, connected one by one so the utxos and
+// target are worked out from them rather than copied
+fn repair(stored: &Blockchain, valid_height: u64) -> Result<Blockchain, Report> {
+    let mut repaired = Blockchain::with_params(stored.params().clone());
+    for block in stored.blocks().take(valid_height as usize) {
+        // validated by the replay already
+        repaired.add_trusted_block(Block::clone(block))?;
+    }
+    Ok(repaired)
+}
+
+fn failed(path: &Path) -> impl Fn(io::Error) -> Report + '_ {
+    move |e| Report::new(format!("{}: {e}", path.display()))
+}
+
+fn main() -> Result<(), Report> {
+    let cli = Cli::parse();
+    lib::logging::init(cli.verbosity.verbose);
+    let path = &cli.blockchain_file;
+    let stored = Blockchain::load_from_file(path).map_err(failed(path))?;
+    // its blocks start past genesis, on top of utxos it has no blocks for
+    if let Some(base) = stored.snapshot_base() {
+        return Err(Report::new(format!(
+            "{}: imported from a utxo snapshot at height {}, it can't be replayed",
+            path.display(),
+            base.height
+        )));
+    }
+
+    let replay = replay(&stored, None);
+    if cli.json.json {
+        println!("{}", serde_json::to_string_pretty(&replay).unwrap());
+    } else {
+        print(path, &replay, stored.utxo_count());
+    }
+    let Some(out) = &cli.out else {
+        if !replay.is_clean() {
+            exit(1);
+        }
+        return Ok(());
+    };
+    let repaired = repair(&stored, replay.valid_height)?;
+    // compressed as the file it repairs was
+    if is_compressed(path).unwrap_or(false) {
+        repaired.save_compressed_to_file(out)
+    } else {
+        repaired.save_to_file(out)
+    }
+    .map_err(failed(out))?;
+    // the json report stays the only thing on stdout
+    eprintln!(
+        "wrote {} blocks and {} utxos to {}",
+        repaired.block_height(),
+        repaired.utxo_count(),
+        out.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,216 @@
+use chrono::{DateTime, Utc};
+use clap::{Parser, ValueEnum};
+use lib::U256;
+use lib::cli::Verbosity;
+use lib::error::Report;
+use lib::sha256::{Hash, HashKeyedMap};
+use lib::types::{Block, Blockchain, read_block_record};
+use lib::utils::{MerkleRoot, Saveable};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Result as IoResult, Write};
+use std::path::{Path, PathBuf};
+
+// one json object per line, for loading into analysis tools
+#[derive(Serialize)]
+struct BlockRecord {
+    height: u64,
+    hash: Hash,
+    prev_hash: Hash,
+    merkle_root: MerkleRoot,
+    timestamp: DateTime<Utc>,
+    nonce: u64,
+    target: U256,
+    transactions: usize,
+    size: u64,
+}
+
+#[derive(Serialize)]
+struct TransactionRecord {
+    height: u64,
+    timestamp: DateTime<Utc>,
+    txid: Hash,
+    coinbase: bool,
+    inputs: usize,
+    outputs: usize,
+    // null for coinbase transactions
+    input_value: Option<u64>,
+    output_value: u64,
+    fee: Option<u64>,
+    size: u64,
+}
+
+#[derive(Serialize, Clone)]
+struct UtxoRecord {
+    hash: Hash,
+    txid: Hash,
+    height: u64,
+    value: u64,
+    address: String,
+    coinbase: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum What {
+    Blocks,
+    #[value(name = "txs")]
+    Transactions,
+    Utxos,
+}
+
+/// Export a chain as json lines, one block, transaction or utxo each
+#[derive(Parser)]
+#[command(name = "chain_export", version)]
+struct Cli {
+    /// A blockchain file, or a data dir read a block at a time from its blocks.dat
+    #[arg(value_name = "BLOCKCHAIN_FILE|DATA_DIR")]
+    path: PathBuf,
+    /// What to export
+    #[arg(long, value_enum)]
+    what: What,
+    /// First height to export
+    #[arg(long, default_value_t = 0)]
+    from_height: u64,
+    /// Last height to export, and the height utxos are as of
+    #[arg(long, default_value_t = u64::MAX, hide_default_value = true)]
+    to_height: u64,
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+// call f with each block in order, streaming the blocks of a
+// data directory and loading a blockchain file whole
+fn each_block(path: &Path, mut f: impl FnMut(u64, &Block) -> IoResult<bool>) -> IoResult<()> {
+    if path.is_dir() {
+        let mut reader = BufReader::new(File::open(path.join("blocks.dat"))?);
+        let mut height = 0;
+        while let Some(block) = read_block_record(&mut reader)? {
+            if !f(height, &block)? {
+                break;
+            }
+            height += 1;
+        }
+        return Ok(());
+    }
+    let blockchain = Blockchain::load_from_file(path)?;
+    for (height, block) in blockchain.blocks().enumerate() {
+        if !f(height as u64, block)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn write_line<W: Write, T: Serialize>(out: &mut W, record: &T) -> IoResult<()> {
+    serde_json::to_writer(&mut *out, record)?;
+    out.write_all(b"\n")
+}
+
+fn encoded_size<T: Serialize>(value: &T) -> u64 {
+    let mut bytes = vec![];
+    ciborium::into_writer(value, &mut bytes).expect("BUG: encoding failed");
+    bytes.len() as u64
+}
+
+fn main() -> Result<(), Report> {
+    let Cli {
+        path,
+        what,
+        from_height,
+        to_height,
+        verbosity,
+    } = Cli::parse();
+    lib::logging::init(verbosity.verbose);
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    // unspent outputs so far, for fees and the utxo export
+    let mut utxos: HashKeyedMap<UtxoRecord> = HashKeyedMap::default();
+    let result = each_block(&path, |height, block| {
+        // utxos are exported as of to_height
+        if height > to_height {
+            return Ok(false);
+        }
+        let timestamp = block.header.timestamp;
+        let in_range = height >= from_height;
+        if what == What::Blocks && in_range {
+            write_line(
+                &mut out,
+                &BlockRecord {
+                    height,
+                    hash: block.hash(),
+                    prev_hash: block.header.prev_block_hash,
+                    merkle_root: block.header.merkle_root,
+                    timestamp,
+                    nonce: block.header.nonce,
+                    target: block.header.target,
+                    transactions: block.transactions.len(),
+                    size: encoded_size(block),
+                },
+            )?;
+        }
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            let coinbase = index == 0;
+            let txid = transaction.hash();
+            let mut input_value = 0;
+            for input in &transaction.inputs {
+                if let Some(spent) = utxos.remove(&input.prev_transaction_output_hash) {
+                    input_value += spent.value;
+                }
+            }
+            let output_value: u64 = transaction.outputs.iter().map(|o| o.value).sum();
+            for output in &transaction.outputs {
+                utxos.insert(
+                    output.hash(),
+                    UtxoRecord {
+                        hash: output.hash(),
+                        txid,
+                        height,
+                        value: output.value,
+                        address: output.pubkey.to_address(),
+                        coinbase,
+                    },
+                );
+            }
+            if what == What::Transactions && in_range {
+                let input_value = (!coinbase).then_some(input_value);
+                write_line(
+                    &mut out,
+                    &TransactionRecord {
+                        height,
+                        timestamp,
+                        txid,
+                        coinbase,
+                        inputs: transaction.inputs.len(),
+                        outputs: transaction.outputs.len(),
+                        input_value,
+                        output_value,
+                        fee: input_value.map(|value| value.saturating_sub(output_value)),
+                        size: transaction.size(),
+                    },
+                )?;
+            }
+        }
+        Ok(true)
+    })
+    .and_then(|()| {
+        if what == What::Utxos {
+            let mut records: Vec<&UtxoRecord> = utxos
+                .values()
+                .filter(|utxo| utxo.height >= from_height)
+                .collect();
+            records.sort_unstable_by_key(|utxo| (utxo.height, utxo.hash));
+            for record in records {
+                write_line(&mut out, record)?;
+            }
+        }
+        out.flush()
+    });
+    match result {
+        // piped into head and the like
+        Err(e) if e.kind() != ErrorKind::BrokenPipe => Err(Report::new(format!(
+            "Failed to export {}: {e}",
+            path.display()
+        ))),
+        _ => Ok(()),
+    }
+}
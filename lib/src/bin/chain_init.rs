@@ -0,0 +1,134 @@
+use chrono::{DateTime, Duration, Utc};
+use clap::{ArgGroup, Parser};
+use lib::cli::{NetworkArgs, Verbosity};
+use lib::crypto::{PrivateKey, PublicKey};
+use lib::error::Report;
+use lib::params::Network;
+use lib::sha256::Hash;
+use lib::types::{Block, Blockchain, Transaction, TransactionOutput};
+use lib::utils::{Saveable, format_value};
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+// the time of the genesis block of a seeded chain, so the
+// same seed always gives the same bytes
+const SEEDED_START: &str = "2024-01-01T00:00:00Z";
+
+/// Mine a genesis block and n more, each paying its reward to one key
+#[derive(Parser)]
+#[command(name = "chain_init", version)]
+#[command(group(ArgGroup::new("key").required(true).args(["fund", "new_key"])))]
+struct Cli {
+    /// Where to write the chain
+    #[arg(long, value_name = "CHAIN_FILE")]
+    out: PathBuf,
+    /// Blocks to mine after the genesis block
+    #[arg(long, value_name = "N")]
+    blocks: u64,
+    /// Address to pay the rewards to
+    #[arg(long, value_name = "ADDRESS")]
+    fund: Option<PublicKey>,
+    /// Pay a new key, its secret written here in hex, derived from the seed if given
+    #[arg(long, value_name = "KEY_FILE")]
+    new_key: Option<PathBuf>,
+    // regtest if not given
+    #[command(flatten)]
+    network: NetworkArgs,
+    /// Derive everything random from this, so the same seed gives the same chain
+    #[arg(long)]
+    seed: Option<String>,
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+// a hash of the seed and what it is for, for the parts of
+// the chain that are otherwise random
+fn seeded(seed: &str, part: &str) -> Hash {
+    Hash::hash_bytes(format!("{seed}/{part}").as_bytes())
+}
+
+fn main() -> Result<(), Report> {
+    let Cli {
+        out,
+        blocks,
+        fund,
+        new_key,
+        network,
+        seed,
+        verbosity,
+    } = Cli::parse();
+    lib::logging::init(verbosity.verbose);
+    let pubkey = match (fund, new_key) {
+        (Some(pubkey), _) => pubkey,
+        (None, Some(key_path)) => {
+            let key = match &seed {
+                Some(seed) => PrivateKey::from_hex(&seeded(seed, "key").to_hex())
+                    .ok_or_else(|| Report::new("the seed gives no valid key, try another"))?,
+                None => PrivateKey::new_key(),
+            };
+            fs::write(&key_path, key.to_hex() + "\n")
+                .map_err(|e| Report::new(format!("{}: {e}", key_path.display())))?;
+            key.public_key()
+        }
+        (None, None) => unreachable!("clap requires one of --fund and --new-key"),
+    };
+
+    let mut chain = Blockchain::with_params(network.params_or(Network::Regtest));
+    // spaced as the target wants, so it never adjusts
+    let spacing = chain.params().ideal_block_time as i64;
+    let after = |blocks: u64| Duration::seconds(spacing.saturating_mul(blocks as i64));
+    let start: DateTime<Utc> = match &seed {
+        Some(_) => SEEDED_START.parse().expect("BUG: invalid start time"),
+        None => Utc::now() - after(blocks + 1),
+    };
+    for height in 0..=blocks {
+        let unique_id = match &seed {
+            Some(seed) => {
+                let hash = seeded(seed, &format!("coinbase/{height}")).to_hex();
+                Uuid::from_u128(u128::from_str_radix(&hash[..32], 16).expect("BUG: invalid hex"))
+            }
+            None => Uuid::new_v4(),
+        };
+        let coinbase = Transaction::new(
+            vec![],
+            vec![TransactionOutput {
+                value: chain.params().reward_at_height(height),
+                unique_id,
+                pubkey: pubkey.clone(),
+                spendable_after_height: None,
+            }],
+        );
+        let mut block = Block::builder()
+            .timestamp(start + after(height))
+            .prev_hash(chain.blocks().last().map_or(Hash::zero(), Block::hash))
+            .target(chain.target())
+            .transactions(vec![coinbase])
+            .build()?;
+        while !block.header.mine(1_000_000) {}
+        chain.add_block(block)?;
+    }
+    chain.verify()?;
+    chain
+        .save_to_file(&out)
+        .map_err(|e| Report::new(format!("{}: {e}", out.display())))?;
+
+    let params = chain.params();
+    let balance = params.cumulative_supply_at_height(blocks);
+    // the coinbases the next block may spend, coinbase_maturity
+    // blocks below it or more
+    let mature = (blocks + 1)
+        .checked_sub(params.coinbase_maturity)
+        .map_or(0, |height| {
+            params.cumulative_supply_at_height(height.min(blocks))
+        });
+    println!(
+        "{} has {}, {} of it mature",
+        pubkey.to_address(),
+        format_value(balance),
+        format_value(mature)
+    );
+    let tip = chain.blocks().last().expect("BUG: no genesis block");
+    println!("tip {} at height {blocks}", tip.hash().to_hex());
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use chrono::Utc;
+use clap::Parser;
+use lib::cli::Verbosity;
+use lib::crypto::PrivateKey;
+use lib::error::Report;
+use lib::sha256::Hash;
+use lib::types::{Blockchain, CheckpointBundle, SignedCheckpoint};
+use lib::utils::Saveable;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Sign that the block at a height is the one with a hash, adding the
+/// checkpoint to a bundle for nodes trusting the key
+#[derive(Parser)]
+#[command(name = "checkpoint_sign", version)]
+struct Cli {
+    /// File holding the secret of the operator key in hex
+    private_key_file: PathBuf,
+    /// Bundle to add the checkpoint to, made if it doesn't exist
+    bundle_file: PathBuf,
+    /// Height of the block
+    #[arg(long)]
+    height: u64,
+    /// Hash of the block
+    #[arg(long, required_unless_present = "chain")]
+    hash: Option<Hash>,
+    /// Saved blockchain to take the hash of the block at the height from
+    #[arg(long, value_name = "BLOCKCHAIN_FILE", conflicts_with = "hash")]
+    chain: Option<PathBuf>,
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey, Report> {
+    let secret =
+        fs::read_to_string(path).map_err(|e| Report::new(format!("{}: {e}", path.display())))?;
+    PrivateKey::from_hex(secret.trim())
+        .ok_or_else(|| Report::new(format!("{}: not a private key in hex", path.display())))
+}
+
+// the hash of the block at height in a saved chain
+fn hash_at(path: &Path, height: u64) -> Result<Hash, Report> {
+    let blockchain = Blockchain::load_from_file(path)
+        .map_err(|e| Report::new(format!("{}: {e}", path.display())))?;
+    blockchain
+        .blocks()
+        .nth(height as usize)
+        .map(|block| block.hash())
+        .ok_or_else(|| Report::new(format!("{}: no block at height {height}", path.display())))
+}
+
+fn main() -> Result<(), Report> {
+    let cli = Cli::parse();
+    lib::logging::init(cli.verbosity.verbose);
+    let key = load_key(&cli.private_key_file)?;
+    let hash = match (cli.hash, &cli.chain) {
+        (Some(hash), _) => hash,
+        (None, Some(path)) => hash_at(path, cli.height)?,
+        (None, None) => unreachable!("BUG: clap requires a hash or a chain"),
+    };
+    let path = &cli.bundle_file;
+    let mut bundle = if path.exists() {
+        CheckpointBundle::load_from_file(path)
+            .map_err(|e| Report::new(format!("{}: {e}", path.display())))?
+    } else {
+        CheckpointBundle::default()
+    };
+    // nodes would refuse the whole bundle
+    if let Some(other) = bundle
+        .checkpoints
+        .iter()
+        .find(|checkpoint| checkpoint.height == cli.height && checkpoint.hash != hash)
+    {
+        return Err(Report::new(format!(
+            "{}: already has {} at height {}",
+            path.display(),
+            other.hash,
+            cli.height
+        )));
+    }
+    let checkpoint = SignedCheckpoint::sign(cli.height, hash, Utc::now(), &key);
+    bundle.checkpoints.push(checkpoint);
+    bundle
+        .save_to_file(path)
+        .map_err(|e| Report::new(format!("{}: {e}", path.display())))?;
+    println!(
+        "signed {hash} at height {} by {}, {} checkpoints in {}",
+        cli.height,
+        key.public_key().to_address(),
+        bundle.checkpoints.len(),
+        path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,268 @@
+use chrono::{DateTime, Duration, Utc};
+use clap::{Parser, Subcommand};
+use lib::cli::Verbosity;
+use lib::crypto::{PrivateKey, Signature};
+use lib::error::Report;
+use lib::params::NetworkParams;
+use lib::replay::{Divergence, replay};
+use lib::sha256::Hash;
+use lib::types::{
+    Block, BlockHeader, Blockchain, Transaction, TransactionInput, TransactionOutput,
+};
+use lib::utils::Saveable;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+// hashes of the committed fixtures; a change that moves any of them
+// forks the chain, so they are only updated along with the fixtures,
+// for a deliberate and versioned format change, as are those of
+// tests/compat.rs
+const BLOCK_HASH: &str = "44d09bba98495a920e42f4e549d8b45775fa18b9f87a2c36cf77c342a32be9e9";
+const TRANSACTION_HASH: &str = "f769e2adeca7c699a1be86e803dff4f261e3692344a908665d153128fe5cb303";
+// the block of diverged_blockchain.cbor a replay must reject
+const DIVERGED_HEIGHT: u64 = 2;
+
+/// Write the format compatibility fixtures, or check them against this build
+#[derive(Parser)]
+#[command(name = "compat_fixtures", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write the fixtures into the dir
+    Write { dir: PathBuf },
+    /// Check the fixtures in the dir still load and hash the same
+    Check { dir: PathBuf },
+}
+
+fn key(byte: u8) -> PrivateKey {
+    PrivateKey::from_hex(&hex::encode([byte; 32])).expect("BUG: invalid fixture key")
+}
+
+fn output(value: u64, id: u128, key: &PrivateKey) -> TransactionOutput {
+    TransactionOutput {
+        value,
+        unique_id: Uuid::from_u128(id),
+        pubkey: key.public_key(),
+        spendable_after_height: None,
+    }
+}
+
+// a regtest chain of three blocks, the last spending the first
+// coinbase; the same every time, signatures being rfc 6979. diverged,
+// the spend is signed by the wrong key and its block added trusted,
+// as a chain stored by a laxer version would be
+fn fixture_chain(diverged: bool) -> Result<Blockchain, Report> {
+    // the first coinbase is mature by the third block
+    let params = NetworkParams {
+        coinbase_maturity: 2,
+        ..NetworkParams::regtest()
+    };
+    let miner = key(1);
+    let payee = key(2);
+    let start: DateTime<Utc> = "2024-01-01T00:00:00Z"
+        .parse()
+        .expect("BUG: invalid fixture time");
+    let mut blockchain = Blockchain::with_params(params.clone());
+    let mut spendable = None;
+    for height in 0..3u64 {
+        let reward = params.reward_at_height(height);
+        let mut transactions = vec![Transaction::new(
+            vec![],
+            vec![output(reward, height as u128, &miner)],
+        )];
+        if height == 2 {
+            let spent: TransactionOutput = spendable.take().expect("BUG: nothing to spend");
+            let spent_hash = spent.hash();
+            let mut spend = Transaction::new(
+                vec![TransactionInput {
+                    prev_transaction_output_hash: spent_hash,
+                    signature: Signature::placeholder(),
+                }],
+                vec![
+                    output(spent.value / 2, 100, &payee),
+                    output(spent.value / 2 - 1000, 101, &miner),
+                ],
+            );
+            spend.inputs[0].signature = Signature::sign_input(
+                &spend.sighash(&spent_hash),
+                if diverged { &payee } else { &miner },
+            );
+            transactions.push(spend);
+            // the fee goes to the miner
+            transactions[0].outputs[0].value += 1000;
+        }
+        if height == 0 {
+            spendable = Some(transactions[0].outputs[0].clone());
+        }
+        let mut block = Block::builder()
+            .timestamp(start + Duration::seconds(10 * height as i64))
+            .prev_hash(blockchain.blocks().last().map_or(Hash::zero(), Block::hash))
+            .target(blockchain.target())
+            .transactions(transactions)
+            .build()?;
+        while !block.header.mine(1_000_000) {}
+        if diverged && height == DIVERGED_HEIGHT {
+            blockchain.add_trusted_block(block)?;
+        } else {
+            blockchain.add_block(block)?;
+        }
+    }
+    Ok(blockchain)
+}
+
+fn write(dir: &Path) -> Result<(), Report> {
+    fs::create_dir_all(dir)?;
+    let blockchain = fixture_chain(false)?;
+    let block = blockchain
+        .blocks()
+        .last()
+        .expect("BUG: empty fixture chain");
+    let transaction = &block.transactions[1];
+    block.save_to_file(dir.join("block.cbor"))?;
+    block.header.save_to_file(dir.join("header.cbor"))?;
+    transaction.save_to_file(dir.join("transaction.cbor"))?;
+    blockchain.save_to_file(dir.join("blockchain.cbor"))?;
+    fixture_chain(true)?.save_to_file(dir.join("diverged_blockchain.cbor"))?;
+    fs::write(dir.join("block.hex"), block.to_hex())?;
+    fs::write(dir.join("transaction.hex"), transaction.to_hex())?;
+    println!("const BLOCK_HASH: &str = \"{}\";", block.hash().to_hex());
+    println!(
+        "const TRANSACTION_HASH: &str = \"{}\";",
+        transaction.hash().to_hex()
+    );
+    Ok(())
+}
+
+fn load<T: Saveable>(dir: &Path, name: &str) -> Result<(T, Vec<u8>), Report> {
+    let path = dir.join(name);
+    let context = |e: &dyn std::fmt::Display| Report::new(format!("{}: {e}", path.display()));
+    let bytes = fs::read(&path).map_err(|e| context(&e))?;
+    let value = T::load(&bytes[..]).map_err(|e| context(&e))?;
+    Ok((value, bytes))
+}
+
+// a fixture of a canonical form, which must save back to the same bytes
+fn load_canonical<T: Saveable>(dir: &Path, name: &str) -> Result<T, Report> {
+    let (value, bytes) = load::<T>(dir, name)?;
+    let mut saved = vec![];
+    value.save(&mut saved)?;
+    if saved != bytes {
+        return Err(Report::new(format!("{name} saves to different bytes")));
+    }
+    Ok(value)
+}
+
+fn expect_hash(name: &str, found: Hash, expected: &str) -> Result<(), Report> {
+    if found.to_hex() != expected {
+        return Err(Report::new(format!(
+            "{name} hashes to {}, expected {expected}",
+            found.to_hex()
+        )));
+    }
+    Ok(())
+}
+
+// the committed hex must be exactly what the value encodes to
+fn expect_hex(dir: &Path, name: &str, hex: String) -> Result<(), Report> {
+    let committed = fs::read_to_string(dir.join(name))?;
+    if committed.trim() != hex {
+        return Err(Report::new(format!(
+            "{name} differs from the canonical encoding"
+        )));
+    }
+    Ok(())
+}
+
+// the utxos of each block applied in turn, as add_block does, must
+// be those a rebuild over every block finds
+fn check_utxo_deltas(name: &str, blockchain: &Blockchain) -> Result<(), Report> {
+    let entries = |blockchain: &Blockchain| {
+        let mut entries: Vec<(Hash, TransactionOutput)> = blockchain
+            .utxos()
+            .iter()
+            .map(|(hash, output, _)| (*hash, output.clone()))
+            .collect();
+        entries.sort_unstable_by_key(|(hash, _)| *hash);
+        entries
+    };
+    let mut applied = Blockchain::with_params(blockchain.params().clone());
+    for block in blockchain.blocks() {
+        applied.apply_block_utxo_delta(block);
+    }
+    let mut rebuilt = blockchain.clone();
+    rebuilt.rebuild_utxos();
+    if entries(&applied) != entries(&rebuilt) || entries(blockchain) != entries(&rebuilt) {
+        return Err(Report::new(format!(
+            "{name} has different utxos applied a block at a time than rebuilt"
+        )));
+    }
+    Ok(())
+}
+
+// replayed through add_block, the chain must come out as stored,
+// and the diverged one be rejected at its spend and nowhere before
+fn check_replay(dir: &Path) -> Result<(), Report> {
+    let (blockchain, _) = load::<Blockchain>(dir, "blockchain.cbor")?;
+    if let Some(divergence) = replay(&blockchain, None).divergences.first() {
+        return Err(Report::new(format!(
+            "blockchain.cbor diverges on replay: {divergence}"
+        )));
+    }
+    let (diverged, _) = load::<Blockchain>(dir, "diverged_blockchain.cbor")?;
+    match replay(&diverged, None).divergences.as_slice() {
+        [Divergence::Rejected { height, .. }] if height == &DIVERGED_HEIGHT => Ok(()),
+        divergences => Err(Report::new(format!(
+            "diverged_blockchain.cbor should be rejected at height {DIVERGED_HEIGHT} only, found {divergences:?}"
+        ))),
+    }
+}
+
+fn check(dir: &Path) -> Result<(), Report> {
+    let block: Block = load_canonical(dir, "block.cbor")?;
+    expect_hash("block.cbor", block.hash(), BLOCK_HASH)?;
+    expect_hex(dir, "block.hex", block.to_hex())?;
+    if Block::from_hex(&block.to_hex())? != block {
+        return Err(Report::new("block.hex decodes to a different block"));
+    }
+    let header: BlockHeader = load_canonical(dir, "header.cbor")?;
+    expect_hash("header.cbor", header.hash(), BLOCK_HASH)?;
+    let transaction: Transaction = load_canonical(dir, "transaction.cbor")?;
+    expect_hash("transaction.cbor", transaction.hash(), TRANSACTION_HASH)?;
+    expect_hex(dir, "transaction.hex", transaction.to_hex())?;
+    if Transaction::from_hex(&transaction.to_hex())? != transaction {
+        return Err(Report::new(
+            "transaction.hex decodes to a different transaction",
+        ));
+    }
+    // the utxo set is a map, saved in no particular order
+    let (blockchain, _) = load::<Blockchain>(dir, "blockchain.cbor")?;
+    let tip = blockchain.blocks().last().map_or(Hash::zero(), Block::hash);
+    expect_hash("blockchain.cbor", tip, BLOCK_HASH)?;
+    blockchain.verify()?;
+    check_utxo_deltas("blockchain.cbor", &blockchain)?;
+    check_replay(dir)?;
+    // and the fixtures are still what the code builds
+    let rebuilt = fixture_chain(false)?;
+    check_utxo_deltas("the fixture chain", &rebuilt)?;
+    if rebuilt.blocks().last() != Some(&block) {
+        return Err(Report::new("the fixture chain builds to a different block"));
+    }
+    println!("fixtures in {} are compatible", dir.display());
+    Ok(())
+}
+
+fn main() -> Result<(), Report> {
+    let cli = Cli::parse();
+    lib::logging::init(cli.verbosity.verbose);
+    match cli.command {
+        Command::Write { dir } => write(&dir),
+        Command::Check { dir } => check(&dir),
+    }
+}
@@ -0,0 +1,786 @@
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use lib::U256;
+use lib::cli::{JsonArgs, Verbosity};
+use lib::crypto::PublicKey;
+use lib::params::Network;
+use lib::sha256::{Hash, HashKeyedMap};
+use lib::types::{
+    Block, Blockchain, FeeBucket, FeeSummary, MempoolSnapshot, Transaction, TransactionOutput,
+};
+use lib::utils::{MerkleRoot, Saveable, format_value};
+use serde::Serialize;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+/// Query a saved chain file
+///
+/// the mempool is read from the mempool.cbor next to the chain file, as a
+/// node or wallet saves it
+#[derive(Parser)]
+#[command(name = "explorer", version)]
+struct Cli {
+    blockchain_file: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+    #[command(flatten)]
+    json: JsonArgs,
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// The tip of the chain
+    Tip {
+        /// Last blocks to estimate the network hashrate over
+        #[arg(long, value_name = "BLOCKS", default_value_t = HASHRATE_WINDOW, value_parser = clap::value_parser!(u64).range(2..))]
+        window: u64,
+    },
+    /// A block and its transactions
+    Block {
+        #[arg(value_name = "HASH|HEIGHT")]
+        id: String,
+        #[command(flatten)]
+        page: PageArgs,
+    },
+    /// A transaction of the chain or the mempool
+    Tx { txid: Hash },
+    /// The balance of an address, or its utxos or history
+    Address {
+        address: PublicKey,
+        /// Its unspent outputs
+        #[arg(long, conflicts_with = "history")]
+        utxos: bool,
+        /// The transactions paying or spending it
+        #[arg(long)]
+        history: bool,
+        #[command(flatten)]
+        page: PageArgs,
+    },
+    /// The coins issued so far
+    Supply,
+    /// The addresses holding the most coins
+    RichList {
+        #[command(flatten)]
+        page: PageArgs,
+    },
+    /// The transactions of the mempool
+    Mempool {
+        #[command(flatten)]
+        page: PageArgs,
+    },
+    /// The fee rates the mempool pays, and those to be mined soon
+    Fees {
+        /// Most fee rate ranges to group the mempool in
+        #[arg(long, default_value_t = FEE_BUCKETS, value_parser = clap::value_parser!(u64).range(1..))]
+        buckets: u64,
+    },
+}
+
+#[derive(Args)]
+struct PageArgs {
+    /// Most items to show
+    #[arg(long, default_value_t = 100)]
+    limit: usize,
+    /// Items to skip first
+    #[arg(long, default_value_t = 0)]
+    offset: usize,
+}
+
+// blocks the hashrate is estimated over unless told otherwise
+const HASHRATE_WINDOW: u64 = 120;
+// fee rate ranges the mempool is grouped in unless told otherwise
+const FEE_BUCKETS: u64 = 10;
+
+fn fail(message: impl Display) -> ! {
+    eprintln!("{message}");
+    exit(1);
+}
+
+// where each block, transaction and output of the chain is, worked
+// out once on load; the chain keeps none of these indexes itself
+struct Explorer {
+    chain: Blockchain,
+    heights: HashKeyedMap<u64>,
+    // height and position in the block of each transaction
+    transactions: HashKeyedMap<(u64, usize)>,
+    // every output the chain created, with the txid creating it
+    outputs: HashKeyedMap<(Hash, TransactionOutput)>,
+    // txid of the transaction spending each spent output
+    spenders: HashKeyedMap<Hash>,
+}
+
+impl Explorer {
+    fn new(chain: Blockchain) -> Self {
+        let mut explorer = Explorer {
+            chain,
+            heights: HashKeyedMap::default(),
+            transactions: HashKeyedMap::default(),
+            outputs: HashKeyedMap::default(),
+            spenders: HashKeyedMap::default(),
+        };
+        for (height, block) in explorer.chain.blocks().enumerate() {
+            let height = height as u64;
+            explorer.heights.insert(block.hash(), height);
+            for (index, transaction) in block.transactions.iter().enumerate() {
+                let txid = transaction.hash();
+                explorer.transactions.insert(txid, (height, index));
+                for input in &transaction.inputs {
+                    explorer
+                        .spenders
+                        .insert(input.prev_transaction_output_hash, txid);
+                }
+                for output in &transaction.outputs {
+                    explorer
+                        .outputs
+                        .insert(output.hash(), (txid, output.clone()));
+                }
+            }
+        }
+        explorer
+    }
+
+    fn block(&self, height: u64) -> &Block {
+        self.chain
+            .blocks()
+            .nth(height as usize)
+            .expect("BUG: indexed block missing")
+    }
+
+    // the output spent by an input, from the chain or the utxos of
+    // a snapshot the chain was imported from
+    fn spent_output(&self, hash: &Hash) -> Option<&TransactionOutput> {
+        self.outputs
+            .get(hash)
+            .map(|(_, output)| output)
+            .or_else(|| self.chain.utxo(hash))
+    }
+}
+
+#[derive(Serialize)]
+struct Page<T> {
+    total: usize,
+    offset: usize,
+    items: Vec<T>,
+}
+
+impl<T> Page<T> {
+    fn of(items: impl Iterator<Item = T>, offset: usize, limit: usize) -> Self {
+        let mut total = 0;
+        let mut page = vec![];
+        for (index, item) in items.enumerate() {
+            total += 1;
+            if index >= offset && page.len() < limit {
+                page.push(item);
+            }
+        }
+        Page {
+            total,
+            offset,
+            items: page,
+        }
+    }
+
+    fn print_range(&self, what: &str) {
+        if self.items.is_empty() {
+            println!("{what:<13} {}", self.total);
+        } else {
+            println!(
+                "{what:<13} {}, showing {} to {}",
+                self.total,
+                self.offset + 1,
+                self.offset + self.items.len()
+            );
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TipRecord {
+    network: Network,
+    height: Option<u64>,
+    hash: Option<Hash>,
+    timestamp: Option<DateTime<Utc>>,
+    // of the next block
+    target: U256,
+    // hashes per second over the last window blocks
+    hashrate: f64,
+    window: u64,
+    utxos: usize,
+    mempool: usize,
+}
+
+#[derive(Serialize)]
+struct BlockRecord {
+    height: u64,
+    hash: Hash,
+    prev_hash: Hash,
+    merkle_root: MerkleRoot,
+    timestamp: DateTime<Utc>,
+    nonce: u64,
+    target: U256,
+    confirmations: u64,
+    transactions: Page<Hash>,
+}
+
+#[derive(Serialize)]
+struct TransactionRecord {
+    txid: Hash,
+    // None while in the mempool
+    height: Option<u64>,
+    confirmations: u64,
+    coinbase: bool,
+    size: u64,
+    inputs: Vec<InputRecord>,
+    outputs: Vec<OutputRecord>,
+    // None for coinbase transactions, or if an input is unknown
+    fee: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct InputRecord {
+    output_hash: Hash,
+    value: Option<u64>,
+    address: Option<String>,
+}
+
+#[derive(Serialize)]
+struct OutputRecord {
+    hash: Hash,
+    value: u64,
+    address: String,
+    // txid of the confirmed transaction spending it
+    spent_by: Option<Hash>,
+}
+
+#[derive(Serialize)]
+struct AddressRecord {
+    address: String,
+    balance: u64,
+    received: u64,
+    spent: u64,
+    utxos: usize,
+    transactions: u64,
+    // heights of the first and last blocks paying or spending from it
+    first_seen: Option<u64>,
+    last_seen: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct UtxoRecord {
+    hash: Hash,
+    // None for the outputs of a snapshot base
+    txid: Option<Hash>,
+    value: u64,
+    reserved: bool,
+}
+
+#[derive(Serialize)]
+struct HistoryRecord {
+    txid: Hash,
+    height: u64,
+    timestamp: DateTime<Utc>,
+    // paid to the address less spent from it
+    change: i128,
+}
+
+#[derive(Serialize)]
+struct SupplyRecord {
+    height: Option<u64>,
+    // value of every unspent output
+    supply: u64,
+    // block rewards the schedule allows up to the tip
+    scheduled: u64,
+    // block rewards the schedule will ever allow
+    max: u64,
+    utxos: usize,
+}
+
+#[derive(Serialize)]
+struct BalanceRecord {
+    address: String,
+    balance: u64,
+}
+
+#[derive(Serialize)]
+struct MempoolRecord {
+    txid: Hash,
+    received: DateTime<Utc>,
+    fee: Option<u64>,
+    size: u64,
+    inputs: usize,
+    outputs: usize,
+}
+
+#[derive(Serialize)]
+struct FeesRecord {
+    summary: FeeSummary,
+    histogram: Vec<FeeBucket>,
+}
+
+fn show<T: Serialize>(json: bool, record: &T, print: impl FnOnce(&T)) {
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(record).expect("BUG: json encoding failed")
+        );
+    } else {
+        print(record);
+    }
+}
+
+fn tip(explorer: &Explorer, window: u64) -> TipRecord {
+    let chain = &explorer.chain;
+    let last = chain.blocks().last();
+    TipRecord {
+        network: chain.network(),
+        height: chain.block_height().checked_sub(1),
+        hash: last.map(Block::hash),
+        timestamp: last.map(|block| block.header.timestamp),
+        target: chain.target(),
+        hashrate: chain.estimated_hashrate(window),
+        window,
+        utxos: chain.utxo_count(),
+        mempool: chain.mempool().len(),
+    }
+}
+
+fn print_tip(tip: &TipRecord) {
+    println!("network       {}", tip.network);
+    match (tip.height, tip.hash, tip.timestamp) {
+        (Some(height), Some(hash), Some(timestamp)) => {
+            println!("height        {height}");
+            println!("hash          {}", hash.to_hex());
+            println!("timestamp     {timestamp}");
+        }
+        _ => println!("height        none, no blocks"),
+    }
+    println!("next target   {:x}", tip.target);
+    println!(
+        "hashrate      {:.2} H/s over the last {} blocks",
+        tip.hashrate, tip.window
+    );
+    println!("utxos         {}", tip.utxos);
+    println!("mempool       {}", tip.mempool);
+}
+
+fn block(explorer: &Explorer, id: &str, offset: usize, limit: usize) -> BlockRecord {
+    let height = match id.parse::<u64>() {
+        Ok(height) if height < explorer.chain.block_height() => height,
+        Ok(height) => fail(format!("no block at height {height}")),
+        Err(_) => Hash::from_hex(id)
+            .and_then(|hash| explorer.heights.get(&hash).copied())
+            .unwrap_or_else(|| fail(format!("no block {id}"))),
+    };
+    let block = explorer.block(height);
+    BlockRecord {
+        height,
+        hash: block.hash(),
+        prev_hash: block.header.prev_block_hash,
+        merkle_root: block.header.merkle_root,
+        timestamp: block.header.timestamp,
+        nonce: block.header.nonce,
+        target: block.header.target,
+        confirmations: explorer.chain.block_height() - height,
+        transactions: Page::of(
+            block.transactions.iter().map(Transaction::hash),
+            offset,
+            limit,
+        ),
+    }
+}
+
+fn print_block(block: &BlockRecord) {
+    println!("height        {}", block.height);
+    println!("hash          {}", block.hash.to_hex());
+    println!("previous      {}", block.prev_hash.to_hex());
+    println!("merkle root   {}", block.merkle_root);
+    println!("timestamp     {}", block.timestamp);
+    println!("nonce         {}", block.nonce);
+    println!("target        {:x}", block.target);
+    println!("confirmations {}", block.confirmations);
+    block.transactions.print_range("transactions");
+    for txid in &block.transactions.items {
+        println!("  {}", txid.to_hex());
+    }
+}
+
+fn transaction(explorer: &Explorer, txid: Hash) -> TransactionRecord {
+    let (transaction, height) = match explorer.transactions.get(&txid) {
+        Some(&(height, index)) => (&explorer.block(height).transactions[index], Some(height)),
+        None => match explorer.chain.mempool_get(&txid) {
+            Some((_, transaction)) => (transaction, None),
+            None => fail(format!("no transaction {txid}")),
+        },
+    };
+    let inputs: Vec<InputRecord> = transaction
+        .inputs
+        .iter()
+        .map(|input| {
+            let spent = explorer.spent_output(&input.prev_transaction_output_hash);
+            InputRecord {
+                output_hash: input.prev_transaction_output_hash,
+                value: spent.map(|output| output.value),
+                address: spent.map(|output| output.pubkey.to_address()),
+            }
+        })
+        .collect();
+    let coinbase = inputs.is_empty();
+    let input_value = inputs
+        .iter()
+        .try_fold(0u64, |sum, input| sum.checked_add(input.value?));
+    let output_value = transaction
+        .outputs
+        .iter()
+        .fold(0u64, |sum, output| sum.saturating_add(output.value));
+    TransactionRecord {
+        txid,
+        height,
+        confirmations: height.map_or(0, |height| explorer.chain.block_height() - height),
+        coinbase,
+        size: transaction.size(),
+        inputs,
+        outputs: transaction
+            .outputs
+            .iter()
+            .map(|output| {
+                let hash = output.hash();
+                OutputRecord {
+                    hash,
+                    value: output.value,
+                    address: output.pubkey.to_address(),
+                    spent_by: explorer.spenders.get(&hash).copied(),
+                }
+            })
+            .collect(),
+        fee: input_value
+            .filter(|_| !coinbase)
+            .and_then(|value| value.checked_sub(output_value)),
+    }
+}
+
+fn print_transaction(transaction: &TransactionRecord) {
+    println!("txid          {}", transaction.txid.to_hex());
+    match transaction.height {
+        Some(height) => {
+            println!("height        {height}");
+            println!("confirmations {}", transaction.confirmations);
+        }
+        None => println!("height        none, in the mempool"),
+    }
+    println!("size          {} bytes", transaction.size);
+    if transaction.coinbase {
+        println!("inputs        none, coinbase");
+    } else {
+        println!("inputs        {}", transaction.inputs.len());
+    }
+    for input in &transaction.inputs {
+        match (input.value, &input.address) {
+            (Some(value), Some(address)) => println!(
+                "  {}  {:>20}  {address}",
+                input.output_hash.to_hex(),
+                format_value(value)
+            ),
+            _ => println!("  {}  {:>20}", input.output_hash.to_hex(), "unknown"),
+        }
+    }
+    println!("outputs       {}", transaction.outputs.len());
+    for output in &transaction.outputs {
+        println!(
+            "  {}  {:>20}  {}",
+            output.hash.to_hex(),
+            format_value(output.value),
+            output.address
+        );
+        if let Some(txid) = output.spent_by {
+            println!("    spent by {}", txid.to_hex());
+        }
+    }
+    match transaction.fee {
+        Some(fee) => println!("fee           {}", format_value(fee)),
+        None if transaction.coinbase => {}
+        None => println!("fee           unknown"),
+    }
+}
+
+// unspent outputs of the key by hash, so pages of them stay in place
+fn utxos_of(explorer: &Explorer, pubkey: &PublicKey) -> Vec<UtxoRecord> {
+    let mut utxos: Vec<UtxoRecord> = explorer
+        .chain
+        .utxos()
+        .iter()
+        .filter(|(_, output, _)| output.pubkey == *pubkey)
+        .map(|(hash, output, reserved)| UtxoRecord {
+            hash: *hash,
+            txid: explorer.outputs.get(hash).map(|(txid, _)| *txid),
+            value: output.value,
+            reserved,
+        })
+        .collect();
+    utxos.sort_by_key(|utxo| utxo.hash);
+    utxos
+}
+
+// confirmed transactions paying or spending from the key, newest first
+fn history_of<'a>(
+    explorer: &'a Explorer,
+    pubkey: &'a PublicKey,
+) -> impl Iterator<Item = HistoryRecord> + 'a {
+    let blocks: Vec<&Block> = explorer.chain.blocks().collect();
+    blocks
+        .into_iter()
+        .enumerate()
+        .rev()
+        .flat_map(move |(height, block)| {
+            block.transactions.iter().filter_map(move |transaction| {
+                let paid: i128 = transaction
+                    .outputs
+                    .iter()
+                    .filter(|output| output.pubkey == *pubkey)
+                    .map(|output| output.value as i128)
+                    .sum();
+                let mut touched = paid != 0;
+                let mut spent = 0i128;
+                for input in &transaction.inputs {
+                    if let Some(output) = explorer.spent_output(&input.prev_transaction_output_hash)
+                        && output.pubkey == *pubkey
+                    {
+                        touched = true;
+                        spent += output.value as i128;
+                    }
+                }
+                touched.then(|| HistoryRecord {
+                    txid: transaction.hash(),
+                    height: height as u64,
+                    timestamp: block.header.timestamp,
+                    change: paid - spent,
+                })
+            })
+        })
+}
+
+fn address(explorer: &Explorer, pubkey: &PublicKey) -> AddressRecord {
+    let stats = explorer.chain.address_stats(pubkey);
+    AddressRecord {
+        address: pubkey.to_address(),
+        balance: stats.balance,
+        received: stats.received,
+        spent: stats.spent,
+        utxos: utxos_of(explorer, pubkey).len(),
+        transactions: stats.transactions,
+        first_seen: stats.first_seen,
+        last_seen: stats.last_seen,
+    }
+}
+
+fn print_address(address: &AddressRecord) {
+    println!("address       {}", address.address);
+    println!("balance       {}", format_value(address.balance));
+    println!("received      {}", format_value(address.received));
+    println!("spent         {}", format_value(address.spent));
+    println!("utxos         {}", address.utxos);
+    println!("transactions  {}", address.transactions);
+    if let (Some(first), Some(last)) = (address.first_seen, address.last_seen) {
+        println!("seen          heights {first} to {last}");
+    }
+}
+
+fn print_utxos(utxos: &Page<UtxoRecord>) {
+    utxos.print_range("utxos");
+    for utxo in &utxos.items {
+        let reserved = if utxo.reserved { "  reserved" } else { "" };
+        println!(
+            "  {}  {:>20}{reserved}",
+            utxo.hash.to_hex(),
+            format_value(utxo.value)
+        );
+    }
+}
+
+fn print_history(history: &Page<HistoryRecord>) {
+    history.print_range("transactions");
+    for record in &history.items {
+        let sign = if record.change < 0 { "-" } else { "+" };
+        let change = u64::try_from(record.change.unsigned_abs()).unwrap_or(u64::MAX);
+        println!(
+            "  {}  {:>8}  {}  {sign}{}",
+            record.txid.to_hex(),
+            record.height,
+            record.timestamp,
+            format_value(change)
+        );
+    }
+}
+
+fn supply(explorer: &Explorer) -> SupplyRecord {
+    let chain = &explorer.chain;
+    let height = chain.block_height().checked_sub(1);
+    SupplyRecord {
+        height,
+        supply: chain.circulating_supply(),
+        scheduled: height.map_or(0, |height| {
+            chain.params().cumulative_supply_at_height(height)
+        }),
+        max: chain.params().max_supply(),
+        utxos: chain.utxo_count(),
+    }
+}
+
+fn print_supply(supply: &SupplyRecord) {
+    match supply.height {
+        Some(height) => println!("height        {height}"),
+        None => println!("height        none, no blocks"),
+    }
+    println!("supply        {}", format_value(supply.supply));
+    println!("scheduled     {}", format_value(supply.scheduled));
+    println!("max           {}", format_value(supply.max));
+    println!("utxos         {}", supply.utxos);
+}
+
+// largest balance first, ties by address
+fn rich_list(explorer: &Explorer, offset: usize, limit: usize) -> Page<BalanceRecord> {
+    let mut balances: Vec<BalanceRecord> = explorer
+        .chain
+        .iter_balances()
+        .map(|(pubkey, balance)| BalanceRecord {
+            address: pubkey.to_address(),
+            balance,
+        })
+        .collect();
+    balances.sort_by(|a, b| b.balance.cmp(&a.balance).then(a.address.cmp(&b.address)));
+    Page::of(balances.into_iter(), offset, limit)
+}
+
+fn print_rich_list(balances: &Page<BalanceRecord>) {
+    balances.print_range("addresses");
+    for (index, entry) in balances.items.iter().enumerate() {
+        println!(
+            "  {:>6}  {}  {:>20}",
+            balances.offset + index + 1,
+            entry.address,
+            format_value(entry.balance)
+        );
+    }
+}
+
+// highest fee first, the order a miner takes them in
+fn mempool(explorer: &Explorer, offset: usize, limit: usize) -> Page<MempoolRecord> {
+    let chain = &explorer.chain;
+    Page::of(
+        chain
+            .mempool_by_priority()
+            .map(|(received, transaction)| MempoolRecord {
+                txid: transaction.hash(),
+                received: *received,
+                fee: chain.transaction_fee(transaction),
+                size: transaction.size(),
+                inputs: transaction.inputs.len(),
+                outputs: transaction.outputs.len(),
+            }),
+        offset,
+        limit,
+    )
+}
+
+fn print_mempool(mempool: &Page<MempoolRecord>) {
+    mempool.print_range("mempool");
+    for entry in &mempool.items {
+        let fee = entry.fee.map_or("unknown".to_string(), format_value);
+        println!(
+            "  {}  {:>20}  {:>6} bytes  {}",
+            entry.txid.to_hex(),
+            fee,
+            entry.size,
+            entry.received
+        );
+    }
+}
+
+fn fees(explorer: &Explorer, buckets: u64) -> FeesRecord {
+    FeesRecord {
+        summary: explorer.chain.fee_summary(),
+        histogram: explorer.chain.fee_histogram(buckets as usize),
+    }
+}
+
+// rates are in units per byte
+fn print_fees(fees: &FeesRecord) {
+    let summary = &fees.summary;
+    println!("next block    {}/byte", summary.next_block);
+    println!("3 blocks      {}/byte", summary.within_3_blocks);
+    println!("6 blocks      {}/byte", summary.within_6_blocks);
+    if fees.histogram.is_empty() {
+        println!("mempool empty");
+    }
+    for bucket in &fees.histogram {
+        println!(
+            "  {:>10} - {:<10}  {:>6} transactions  {:>8} bytes",
+            bucket.min_fee_rate, bucket.max_fee_rate, bucket.count, bucket.size
+        );
+    }
+}
+
+// a chain file with the mempool saved next to it, if there is one
+fn load_chain(path: &Path) -> Blockchain {
+    let mut chain = Blockchain::load_from_file(path)
+        .unwrap_or_else(|e| fail(format!("{}: {e}", path.display())));
+    let mempool_path = path.with_file_name("mempool.cbor");
+    if mempool_path.exists() {
+        let mempool = MempoolSnapshot::load_from_file(&mempool_path)
+            .unwrap_or_else(|e| fail(format!("{}: {e}", mempool_path.display())));
+        for (_, e) in chain.restore_mempool(mempool) {
+            eprintln!("dropping saved mempool transaction: {e}");
+        }
+    }
+    chain
+}
+
+fn main() {
+    let cli = Cli::parse();
+    lib::logging::init(cli.verbosity.verbose);
+    let json = cli.json.json;
+    let explorer = Explorer::new(load_chain(&cli.blockchain_file));
+    match cli.command {
+        Command::Tip { window } => show(json, &tip(&explorer, window), print_tip),
+        Command::Block { id, page } => show(
+            json,
+            &block(&explorer, &id, page.offset, page.limit),
+            print_block,
+        ),
+        Command::Tx { txid } => show(json, &transaction(&explorer, txid), print_transaction),
+        Command::Address {
+            address: pubkey,
+            utxos,
+            history,
+            page,
+        } => {
+            if utxos {
+                let utxos = utxos_of(&explorer, &pubkey).into_iter();
+                show(json, &Page::of(utxos, page.offset, page.limit), print_utxos);
+            } else if history {
+                let history = history_of(&explorer, &pubkey);
+                show(
+                    json,
+                    &Page::of(history, page.offset, page.limit),
+                    print_history,
+                );
+            } else {
+                show(json, &address(&explorer, &pubkey), print_address);
+            }
+        }
+        Command::Supply => show(json, &supply(&explorer), print_supply),
+        Command::RichList { page } => show(
+            json,
+            &rich_list(&explorer, page.offset, page.limit),
+            print_rich_list,
+        ),
+        Command::Mempool { page } => show(
+            json,
+            &mempool(&explorer, page.offset, page.limit),
+            print_mempool,
+        ),
+        Command::Fees { buckets } => show(json, &fees(&explorer, buckets), print_fees),
+    }
+}
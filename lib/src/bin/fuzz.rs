@@ -0,0 +1,358 @@
+use clap::Parser;
+use clap::builder::PossibleValuesParser;
+use lib::error::Report;
+use lib::fuzz::{self, TARGETS};
+use lib::network::{InvItem, InvKind, Message};
+use lib::params::NetworkParams;
+use lib::sha256::Hash;
+use lib::types::{Block, Blockchain, Transaction};
+use lib::utils::{MerkleProof, Saveable};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Seed the fuzz targets, smoke test them, and replay their inputs
+///
+/// a smoke test of the targets cargo fuzz runs for real, in lib/fuzz;
+/// the targets are block, transaction, blockchain and message
+#[derive(Parser)]
+#[command(name = "fuzz", version)]
+enum Cli {
+    /// Write the seeds of each target from the compat fixtures
+    Seed {
+        compat_dir: PathBuf,
+        fuzz_dir: PathBuf,
+    },
+    /// Replay the seeds and regressions of each target, then mutations of them
+    Check {
+        fuzz_dir: PathBuf,
+        /// Mutated inputs per target
+        #[arg(long, default_value_t = DEFAULT_ITERATIONS)]
+        iterations: u64,
+        /// Seed of the mutations
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+    },
+    /// Replay single inputs, like a crash cargo fuzz found
+    Run {
+        #[arg(value_parser = PossibleValuesParser::new(TARGETS))]
+        target: String,
+        #[arg(required = true)]
+        input_files: Vec<PathBuf>,
+    },
+}
+
+// mutated inputs per target that check runs by default, a few seconds
+const DEFAULT_ITERATIONS: u64 = 2000;
+// bytes a saved file's checksum trailer takes
+const CHECKSUM_SIZE: usize = 32;
+// bytes one input may have held at once, above what the size caps
+// let through
+const MEMORY_LIMIT: usize = 384 * 1024 * 1024;
+
+// counts the bytes held, so an input that makes a target allocate
+// without bound fails the check as a panic does
+struct CountingAlloc;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+fn grew(bytes: usize) {
+    let live = LIVE.fetch_add(bytes, Ordering::Relaxed) + bytes;
+    PEAK.fetch_max(live, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            grew(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            if new_size > layout.size() {
+                grew(new_size - layout.size());
+            } else {
+                LIVE.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+            }
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+// what f returns and the most bytes it held at once
+fn with_peak<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = LIVE.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let result = f();
+    (result, PEAK.load(Ordering::Relaxed) - before)
+}
+
+fn saved<T: Saveable>(value: &T) -> Result<Vec<u8>, Report> {
+    let mut bytes = vec![];
+    value.save(&mut bytes)?;
+    Ok(bytes)
+}
+
+// a saved value in every form load reads: checksummed, with the
+// trailer off for the target to seal again, compressed, wrapped whole
+// in zstd, and bare cbor
+fn saveable_seeds<T: Saveable>(value: &T) -> Result<Vec<(&'static str, Vec<u8>)>, Report> {
+    let file = saved(value)?;
+    let mut compressed = vec![];
+    value.save_compressed(&mut compressed)?;
+    let mut cbor = vec![];
+    ciborium::into_writer(value, &mut cbor).map_err(|e| Report::new(e.to_string()))?;
+    Ok(vec![
+        ("unsealed", file[..file.len() - CHECKSUM_SIZE].to_vec()),
+        (
+            "compressed-unsealed",
+            compressed[..compressed.len() - CHECKSUM_SIZE].to_vec(),
+        ),
+        ("zstd", zstd::encode_all(&file[..], 3)?),
+        ("cbor", cbor),
+        ("saved", file),
+    ])
+}
+
+// message payloads, which the target frames itself, and one whole frame
+fn message_seeds(blockchain: &Blockchain) -> Result<Vec<(&'static str, Vec<u8>)>, Report> {
+    let block = blockchain
+        .blocks()
+        .last()
+        .ok_or_else(|| Report::new("empty fixture chain"))?;
+    let transaction = block.transactions[1].clone();
+    let proof = MerkleProof::new(&block.transactions, 1)
+        .ok_or_else(|| Report::new("fixture block without a transaction"))?;
+    let headers = blockchain.blocks().map(|b| b.header.clone()).collect();
+    let items = vec![
+        InvItem {
+            kind: InvKind::Block,
+            hash: block.hash(),
+        },
+        InvItem {
+            kind: InvKind::Tx,
+            hash: transaction.hash(),
+        },
+    ];
+    let encode = |message: Message| message.encode().map_err(|e| Report::new(e.to_string()));
+    let frame = Message::NewBlock(block.clone())
+        .frame(NetworkParams::regtest().magic)
+        .map_err(|e| Report::new(e.to_string()))?;
+    Ok(vec![
+        ("new-block", encode(Message::NewBlock(block.clone()))?),
+        (
+            "new-transaction",
+            encode(Message::NewTransaction(transaction.clone()))?,
+        ),
+        ("headers", encode(Message::Headers(headers))?),
+        (
+            "get-headers",
+            encode(Message::GetHeaders {
+                locator: vec![block.hash(), Hash::zero()],
+            })?,
+        ),
+        ("inv", encode(Message::Inv(items))?),
+        (
+            "node-list",
+            encode(Message::NodeList(vec!["127.0.0.1:9000".into()]))?,
+        ),
+        (
+            "filtered-block",
+            encode(Message::FilteredBlock {
+                header: block.header.clone(),
+                transactions: vec![(transaction, proof)],
+            })?,
+        ),
+        ("ping", encode(Message::Ping(7))?),
+        ("frame-new-block", frame),
+    ])
+}
+
+fn seed(compat: &Path, dir: &Path) -> Result<(), Report> {
+    let load = |name: &str| {
+        let path = compat.join(name);
+        fs::read(&path).map_err(|e| Report::new(format!("{}: {e}", path.display())))
+    };
+    let block = Block::load(&load("block.cbor")?[..])?;
+    let transaction = Transaction::load(&load("transaction.cbor")?[..])?;
+    let blockchain = Blockchain::load(&load("blockchain.cbor")?[..])?;
+    let seeds = [
+        ("block", saveable_seeds(&block)?),
+        ("transaction", saveable_seeds(&transaction)?),
+        ("blockchain", saveable_seeds(&blockchain)?),
+        ("message", message_seeds(&blockchain)?),
+    ];
+    for (target, inputs) in seeds {
+        let target_dir = dir.join("seeds").join(target);
+        fs::create_dir_all(&target_dir)?;
+        for (name, data) in &inputs {
+            fs::write(target_dir.join(name), data)?;
+        }
+        println!("{} seeds for {target}", inputs.len());
+    }
+    Ok(())
+}
+
+// the files of dir/kind/target, none if it doesn't exist
+fn inputs(dir: &Path, kind: &str, target: &str) -> Result<Vec<(PathBuf, Vec<u8>)>, Report> {
+    let target_dir = dir.join(kind).join(target);
+    if !target_dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut paths: Vec<PathBuf> = fs::read_dir(&target_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| Ok((path.clone(), fs::read(&path)?)))
+        .collect()
+}
+
+// xorshift64*, so a seed always mutates the same way
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+}
+
+// bytes that sit on the edges of cbor headers and lengths
+const INTERESTING: [u8; 10] = [0x00, 0x01, 0x17, 0x18, 0x1b, 0x5b, 0x7f, 0x80, 0x9b, 0xff];
+
+// a few random edits of one input, now and then spliced with another
+fn mutate(rng: &mut Rng, inputs: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = inputs[rng.below(inputs.len())].clone();
+    for _ in 0..1 + rng.below(4) {
+        let at = rng.below(data.len());
+        match rng.below(7) {
+            0 if !data.is_empty() => data[at] ^= 1 << rng.below(8),
+            1 if !data.is_empty() => data[at] = rng.next() as u8,
+            2 if !data.is_empty() => data[at] = INTERESTING[rng.below(INTERESTING.len())],
+            3 => {
+                let end = (at + 1 + rng.below(16)).min(data.len());
+                data.drain(at..end);
+            }
+            4 => {
+                let bytes: Vec<u8> = (0..1 + rng.below(8)).map(|_| rng.next() as u8).collect();
+                data.splice(at..at, bytes);
+            }
+            5 => data.truncate(at),
+            _ => {
+                let other = &inputs[rng.below(inputs.len())];
+                let from = rng.below(other.len());
+                data.truncate(at);
+                data.extend_from_slice(&other[from..]);
+            }
+        }
+    }
+    data
+}
+
+// run one input, saving it as an artifact if the target panics or
+// allocates past the limit
+fn run_input(dir: &Path, target: &str, data: &[u8]) -> Result<bool, Report> {
+    let (result, allocated) =
+        with_peak(|| panic::catch_unwind(AssertUnwindSafe(|| fuzz::run(target, data))));
+    if result.is_ok() && allocated <= MEMORY_LIMIT {
+        return Ok(true);
+    }
+    let artifacts = dir.join("artifacts").join(target);
+    fs::create_dir_all(&artifacts)?;
+    let path = artifacts.join(format!("crash-{}", &Hash::hash_bytes(data).to_hex()[..16]));
+    fs::write(&path, data)?;
+    match result {
+        Ok(_) => eprintln!(
+            "{target} allocated {allocated} bytes, input saved to {}",
+            path.display()
+        ),
+        Err(_) => eprintln!("{target} panicked, input saved to {}", path.display()),
+    }
+    Ok(false)
+}
+
+fn check(dir: &Path, iterations: u64, seed: u64) -> Result<(), Report> {
+    let mut failed = false;
+    for target in TARGETS {
+        let seeds = inputs(dir, "seeds", target)?;
+        let regressions = inputs(dir, "regressions", target)?;
+        if seeds.is_empty() {
+            return Err(Report::new(format!(
+                "no seeds for {target} in {}",
+                dir.display()
+            )));
+        }
+        for (path, data) in seeds.iter().chain(&regressions) {
+            if !run_input(dir, target, data)? {
+                eprintln!("  replaying {}", path.display());
+                failed = true;
+            }
+        }
+        let corpus: Vec<Vec<u8>> = seeds.into_iter().map(|(_, data)| data).collect();
+        let mut rng = Rng(seed | 1);
+        for _ in 0..iterations {
+            failed |= !run_input(dir, target, &mutate(&mut rng, &corpus))?;
+        }
+        println!(
+            "{target}: {} seeds, {} regressions, {iterations} mutations",
+            corpus.len(),
+            regressions.len()
+        );
+    }
+    if failed {
+        return Err(Report::new("inputs failed, they are saved under artifacts"));
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), Report> {
+    match Cli::parse() {
+        Cli::Seed {
+            compat_dir,
+            fuzz_dir,
+        } => seed(&compat_dir, &fuzz_dir),
+        Cli::Check {
+            fuzz_dir,
+            iterations,
+            seed,
+        } => check(&fuzz_dir, iterations, seed),
+        Cli::Run {
+            target,
+            input_files,
+        } => {
+            for path in input_files {
+                let file = path.display();
+                let data = fs::read(&path).map_err(|e| Report::new(format!("{file}: {e}")))?;
+                let (_, allocated) = with_peak(|| fuzz::run(&target, &data));
+                if allocated > MEMORY_LIMIT {
+                    return Err(Report::new(format!("{file}: allocated {allocated} bytes")));
+                }
+                println!("{file}: ok, at most {allocated} bytes allocated");
+            }
+            Ok(())
+        }
+    }
+}
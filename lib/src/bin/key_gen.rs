@@ -0,0 +1,218 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use lib::cli::Verbosity;
+use lib::crypto::{PrivateKey, PublicKey};
+use lib::error::Report;
+use lib::sha256::Hash;
+use lib::utils::Saveable;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// Make, extract and inspect keys
+///
+/// hex, the default, is the secret or the address in hex, which tx_gen,
+/// tx_sign and chain_init read; pem is pkcs#8 or subject public key info.
+/// files are never overwritten without --force, and private keys are only
+/// readable by their owner
+#[derive(Parser)]
+#[command(name = "key_gen", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write a new private key
+    New {
+        #[command(flatten)]
+        out: OutArgs,
+        /// Derive the key from this instead of making a random one
+        #[arg(long)]
+        seed: Option<String>,
+    },
+    /// Write the public key of a private key
+    Pub {
+        private_key_file: PathBuf,
+        #[command(flatten)]
+        out: OutArgs,
+    },
+    /// Print what a key file of any format holds
+    Inspect { key_file: PathBuf },
+}
+
+#[derive(Args)]
+struct OutArgs {
+    /// Where to write the key
+    #[arg(long, value_name = "KEY_FILE")]
+    out: PathBuf,
+    /// Encoding of the key
+    #[arg(long, value_enum, default_value_t = KeyFormat::Hex)]
+    format: KeyFormat,
+    /// Overwrite the file if it exists
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum KeyFormat {
+    Cbor,
+    Hex,
+    Pem,
+}
+
+impl KeyFormat {
+    fn name(self) -> &'static str {
+        match self {
+            KeyFormat::Cbor => "cbor",
+            KeyFormat::Hex => "hex",
+            KeyFormat::Pem => "pem",
+        }
+    }
+}
+
+enum Key {
+    Private(PrivateKey),
+    Public(PublicKey),
+}
+
+impl Key {
+    fn public_key(&self) -> PublicKey {
+        match self {
+            Key::Private(key) => key.public_key(),
+            Key::Public(pubkey) => pubkey.clone(),
+        }
+    }
+
+    fn encode(&self, format: KeyFormat) -> Vec<u8> {
+        match (self, format) {
+            (Key::Private(key), KeyFormat::Cbor) => saved(key),
+            (Key::Public(pubkey), KeyFormat::Cbor) => saved(pubkey),
+            (Key::Private(key), KeyFormat::Hex) => (key.to_hex() + "\n").into_bytes(),
+            (Key::Public(pubkey), KeyFormat::Hex) => (pubkey.to_address() + "\n").into_bytes(),
+            (Key::Private(key), KeyFormat::Pem) => key.to_pem().into_bytes(),
+            (Key::Public(pubkey), KeyFormat::Pem) => pubkey.to_pem().into_bytes(),
+        }
+    }
+
+    // whichever key a file of any format holds, with the format;
+    // text first, so saved files are the only ones loaded as such
+    fn decode(data: &[u8]) -> Option<(Key, KeyFormat)> {
+        if let Some(key) = std::str::from_utf8(data).ok().and_then(Key::decode_text) {
+            return Some(key);
+        }
+        if let Ok(key) = PrivateKey::load(data) {
+            return Some((Key::Private(key), KeyFormat::Cbor));
+        }
+        PublicKey::load(data)
+            .ok()
+            .map(|pubkey| (Key::Public(pubkey), KeyFormat::Cbor))
+    }
+
+    fn decode_text(text: &str) -> Option<(Key, KeyFormat)> {
+        let text = text.trim();
+        if text.starts_with("-----BEGIN") {
+            return PrivateKey::from_pem(text)
+                .map(Key::Private)
+                .or_else(|| PublicKey::from_pem(text).map(Key::Public))
+                .map(|key| (key, KeyFormat::Pem));
+        }
+        // 32 bytes of secret, or 33 of compressed public key
+        PrivateKey::from_hex(text)
+            .filter(|_| text.len() == 64)
+            .map(Key::Private)
+            .or_else(|| PublicKey::from_address(text).map(Key::Public))
+            .map(|key| (key, KeyFormat::Hex))
+    }
+
+    fn load(path: &Path) -> Result<(Key, KeyFormat), Report> {
+        let data = fs::read(path).map_err(|e| Report::new(format!("{}: {e}", path.display())))?;
+        Key::decode(&data).ok_or_else(|| Report::new(format!("{}: not a key file", path.display())))
+    }
+}
+
+fn saved<T: Saveable>(value: &T) -> Vec<u8> {
+    let mut bytes = vec![];
+    value.save(&mut bytes).expect("BUG: key encoding failed");
+    bytes
+}
+
+// write a key file, private ones readable by their owner only
+fn write_key(out: &OutArgs, key: &Key) -> Result<(), Report> {
+    let path = out.out.display();
+    let failed = |e: std::io::Error| Report::new(format!("{path}: {e}"));
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if out.force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    let private = matches!(key, Key::Private(_));
+    #[cfg(unix)]
+    if private {
+        options.mode(0o600);
+    }
+    let mut file = options.open(&out.out).map_err(|e| match e.kind() {
+        ErrorKind::AlreadyExists => Report::new(format!("{path} exists, --force overwrites it")),
+        _ => failed(e),
+    })?;
+    // mode only applies to files it creates
+    #[cfg(unix)]
+    if private {
+        file.set_permissions(fs::Permissions::from_mode(0o600))
+            .map_err(failed)?;
+    }
+    file.write_all(&key.encode(out.format)).map_err(failed)?;
+    file.sync_all().map_err(failed)
+}
+
+fn print_public(pubkey: &PublicKey) {
+    println!("public key  {}", pubkey.to_uncompressed_hex());
+    println!("address     {}", pubkey.to_address());
+}
+
+fn main() -> Result<(), Report> {
+    let cli = Cli::parse();
+    lib::logging::init(cli.verbosity.verbose);
+    match cli.command {
+        Command::New { out, seed } => {
+            let key = match seed {
+                Some(seed) => PrivateKey::from_hex(&Hash::hash_bytes(seed.as_bytes()).to_hex())
+                    .ok_or_else(|| Report::new("the seed gives no valid key, try another"))?,
+                None => PrivateKey::new_key(),
+            };
+            let pubkey = key.public_key();
+            write_key(&out, &Key::Private(key))?;
+            print_public(&pubkey);
+        }
+        Command::Pub {
+            private_key_file,
+            out,
+        } => {
+            let Key::Private(key) = Key::load(&private_key_file)?.0 else {
+                return Err(Report::new(format!(
+                    "{} holds a public key already",
+                    private_key_file.display()
+                )));
+            };
+            let pubkey = key.public_key();
+            write_key(&out, &Key::Public(pubkey.clone()))?;
+            print_public(&pubkey);
+        }
+        Command::Inspect { key_file } => {
+            let (key, format) = Key::load(&key_file)?;
+            let kind = match key {
+                Key::Private(_) => "private",
+                Key::Public(_) => "public",
+            };
+            println!("kind        {kind}, {}", format.name());
+            print_public(&key.public_key());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use clap::Parser;
+use lib::cli::Verbosity;
+use lib::error::Report;
+use lib::replay::replay;
+use lib::types::Blockchain;
+use lib::utils::Saveable;
+use std::path::PathBuf;
+use std::process::exit;
+
+/// Replay a chain from genesis and report where it diverges from the stored chain
+///
+/// every block goes through add_block into an empty chain of the same
+/// params, and the acceptance of each block, the height, utxo set and
+/// target are compared to the stored chain; the report is json on
+/// stdout. exits 1 if the chain diverges anywhere, or with
+/// --expect-divergence-at, unless its first divergent block is there
+#[derive(Parser)]
+#[command(name = "replay", version)]
+struct Cli {
+    blockchain_file: PathBuf,
+    /// Last height to replay, the stored utxos and target are only compared at the tip
+    #[arg(long, value_name = "HEIGHT")]
+    until: Option<u64>,
+    /// Expect the first divergent block at this height, as a planned consensus change would
+    #[arg(long, value_name = "HEIGHT")]
+    expect_divergence_at: Option<u64>,
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+fn main() -> Result<(), Report> {
+    let cli = Cli::parse();
+    lib::logging::init(cli.verbosity.verbose);
+    let path = &cli.blockchain_file;
+    let stored = Blockchain::load_from_file(path)
+        .map_err(|e| Report::new(format!("{}: {e}", path.display())))?;
+    if let Some(base) = stored.snapshot_base() {
+        return Err(Report::new(format!(
+            "{}: imported from a utxo snapshot at height {}, it can't be replayed",
+            path.display(),
+            base.height
+        )));
+    }
+
+    let replay = replay(&stored, cli.until);
+    println!("{}", serde_json::to_string_pretty(&replay).unwrap());
+    match (cli.expect_divergence_at, replay.first_divergent_block) {
+        (None, _) if replay.is_clean() => {}
+        (None, _) => exit(1),
+        (Some(expected), Some(found)) if expected == found => {
+            eprintln!("diverged at height {found}, as expected");
+        }
+        (Some(expected), found) => {
+            let found = found.map_or("no block".to_string(), |height| format!("height {height}"));
+            eprintln!("expected the first divergent block at height {expected}, found {found}");
+            exit(1);
+        }
+    }
+    Ok(())
+}
@@ -1,16 +1,74 @@
-use lib::crypto::PrivateKey;
-use lib::types::{Transaction, TransactionOutput};
-use lib::utils::Saveable;
-use std::env;
-use std::process::exit;
+use clap::{Args, Parser, Subcommand};
+use lib::cli::{FormatArgs, Verbosity};
+use lib::crypto::{PrivateKey, PublicKey};
+use lib::error::Report;
+use lib::sha256::Hash;
+use lib::types::{Blockchain, PartiallySignedTransaction, Transaction, TransactionOutput};
+use lib::utils::{FileFormat, Saveable, format_value};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
-fn main() {
-    let path = if let Some(arg) = env::args().nth(1) {
-        arg
-    } else {
-        eprintln!("Usage: tx_gen <tx_file>");
-        exit(1);
-    };
+
+/// Write transactions, for fixtures and for spending from a chain file
+#[derive(Parser)]
+#[command(name = "tx_gen", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Pay a new key from no inputs, for fixtures
+    CoinbaseLike {
+        tx_file: PathBuf,
+        #[command(flatten)]
+        format: FormatArgs,
+    },
+    /// Pay from the outputs of a key, the change going back to it
+    Spend(SpendOptions),
+}
+
+#[derive(Args)]
+struct SpendOptions {
+    /// Blockchain file whose utxos to spend
+    #[arg(long, value_name = "BLOCKCHAIN_FILE")]
+    chain: PathBuf,
+    /// File holding the secret of the key in hex
+    #[arg(long, value_name = "PRIVATE_KEY_FILE")]
+    key: PathBuf,
+    /// Address to pay
+    #[arg(long, value_name = "ADDRESS")]
+    to: PublicKey,
+    /// Value to pay, in base units
+    #[arg(long)]
+    amount: u64,
+    /// Fee to leave, in base units
+    #[arg(long, default_value_t = 0)]
+    fee: u64,
+    /// Blocks from the next one that may include it, after which it lapses
+    #[arg(long, value_name = "BLOCKS", value_parser = clap::value_parser!(u64).range(1..))]
+    expires_in: Option<u64>,
+    /// Where to write the transaction
+    #[arg(long, value_name = "TX_FILE")]
+    out: PathBuf,
+    #[command(flatten)]
+    format: FormatArgs,
+}
+
+fn main() -> Result<(), Report> {
+    let cli = Cli::parse();
+    lib::logging::init(cli.verbosity.verbose);
+    match cli.command {
+        Command::CoinbaseLike { tx_file, format } => coinbase_like(&tx_file, format.format),
+        Command::Spend(options) => spend(options),
+    }
+}
+
+fn coinbase_like(path: &Path, format: FileFormat) -> Result<(), Report> {
     let private_key = PrivateKey::new_key();
     let transaction = Transaction::new(
         vec![],
@@ -18,9 +76,134 @@ fn main() {
             unique_id: Uuid::new_v4(),
             value: lib::INITIAL_REWARD * 10u64.pow(8),
             pubkey: private_key.public_key(),
+            spendable_after_height: None,
         }],
     );
     transaction
-        .save_to_file(path)
-        .expect("Failed to save transaction");
+        .save_to_file_as(path, format)
+        .map_err(failed(path))
+}
+
+fn failed(path: &Path) -> impl Fn(io::Error) -> Report + '_ {
+    move |e| Report::new(format!("{}: {e}", path.display()))
+}
+
+fn spend(options: SpendOptions) -> Result<(), Report> {
+    let SpendOptions {
+        chain: chain_path,
+        key: key_path,
+        to,
+        amount,
+        fee,
+        expires_in,
+        out,
+        format,
+    } = options;
+    let secret = fs::read_to_string(&key_path).map_err(failed(&key_path))?;
+    let key = PrivateKey::from_hex(secret.trim())
+        .ok_or_else(|| Report::new(format!("{}: not a private key in hex", key_path.display())))?;
+    let chain = Blockchain::load_from_file(&chain_path).map_err(failed(&chain_path))?;
+
+    // the next block is at the chain's height
+    let expires_at_height = expires_in.map(|blocks| chain.chain_height().saturating_add(blocks));
+    let transaction = build_spend(&chain, &key, to, amount, fee, expires_at_height)?;
+    transaction
+        .save_to_file_as(&out, format.format)
+        .map_err(failed(&out))?;
+    println!("{transaction}, fee {}", format_value(fee));
+    Ok(())
+}
+
+// pay amount to the address from the largest outputs of the key
+// the next block may spend first, the change going back to it,
+// signed and checked against the chain's utxos
+fn build_spend(
+    chain: &Blockchain,
+    key: &PrivateKey,
+    to: PublicKey,
+    amount: u64,
+    fee: u64,
+    expires_at_height: Option<u64>,
+) -> Result<Transaction, Report> {
+    let pubkey = key.public_key();
+    let address = pubkey.to_address();
+    let height = chain.chain_height();
+    let immature = chain.immature_coinbases();
+    let mut coins: Vec<(Hash, TransactionOutput)> = chain
+        .utxos()
+        .iter()
+        .filter(|(hash, output, reserved)| {
+            !reserved
+                && output.pubkey == pubkey
+                && output.is_spendable_at(height)
+                && !immature.contains_key(*hash)
+        })
+        .map(|(hash, output, _)| (*hash, output.clone()))
+        .collect();
+    if coins.is_empty() {
+        return Err(Report::new(format!("no spendable outputs of {address}")));
+    }
+    let needed = amount
+        .checked_add(fee)
+        .ok_or_else(|| Report::new("amount and fee overflow"))?;
+    coins.sort_by_key(|(_, output)| std::cmp::Reverse(output.value));
+    let mut selected = vec![];
+    let mut total = 0u64;
+    for coin in coins {
+        if total >= needed {
+            break;
+        }
+        total = total.saturating_add(coin.1.value);
+        selected.push(coin);
+    }
+    if total < needed {
+        return Err(Report::new(format!(
+            "insufficient funds: {address} has {} spendable, {} needed",
+            format_value(total),
+            format_value(needed)
+        )));
+    }
+
+    let mut outputs = vec![TransactionOutput {
+        value: amount,
+        unique_id: Uuid::new_v4(),
+        pubkey: to,
+        spendable_after_height: None,
+    }];
+    let change = total - needed;
+    if change > 0 {
+        outputs.push(TransactionOutput {
+            value: change,
+            unique_id: Uuid::new_v4(),
+            pubkey,
+            spendable_after_height: None,
+        });
+    }
+    let mut unsigned = PartiallySignedTransaction::new(selected, outputs);
+    unsigned.expires_at_height = expires_at_height;
+    unsigned.sign(key)?;
+    let transaction = unsigned.finalize()?;
+
+    // as the chain will see it: known inputs signed by their owners
+    // and paying the fee asked for
+    for input in &transaction.inputs {
+        let output = chain
+            .utxo(&input.prev_transaction_output_hash)
+            .ok_or_else(|| {
+                Report::new(format!(
+                    "{} is not a utxo",
+                    input.prev_transaction_output_hash
+                ))
+            })?;
+        if !input.signature.verify(
+            &transaction.sighash(&input.prev_transaction_output_hash),
+            &output.pubkey,
+        ) {
+            return Err(Report::new("BUG: spend signed wrongly"));
+        }
+    }
+    if chain.transaction_fee(&transaction) != Some(fee) {
+        return Err(Report::new("BUG: spend pays the wrong fee"));
+    }
+    Ok(transaction)
 }
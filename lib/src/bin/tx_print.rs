@@ -1,17 +1,167 @@
-use lib::types::Transaction;
-use lib::utils::Saveable;
-use std::env;
-use std::fs::File;
+use clap::Parser;
+use lib::cli::{FormatArgs, JsonArgs, Verbosity};
+use lib::sha256::{Hash, HashKeyedMap};
+use lib::types::{Block, Blockchain, Transaction, TransactionOutput};
+use lib::utils::{Saveable, format_value};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
 use std::process::exit;
-fn main() {
-    let path = if let Some(arg) = env::args().nth(1) {
-        arg
-    } else {
-        eprintln!("Usage: tx_print <tx_file>");
+
+#[derive(Serialize)]
+struct TransactionSummary {
+    txid: Hash,
+    size: u64,
+    coinbase: bool,
+    inputs: Vec<InputSummary>,
+    outputs: Vec<OutputSummary>,
+    output_value: u64,
+    // only known with --chain, and if every input was found
+    input_value: Option<u64>,
+    fee: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct InputSummary {
+    output_hash: Hash,
+    value: Option<u64>,
+    address: Option<String>,
+}
+
+#[derive(Serialize)]
+struct OutputSummary {
+    hash: Hash,
+    value: u64,
+    address: String,
+}
+
+/// Print the inputs and outputs of a transaction file
+#[derive(Parser)]
+#[command(name = "tx_print", version)]
+struct Cli {
+    tx_file: PathBuf,
+    #[command(flatten)]
+    format: FormatArgs,
+    /// Blockchain file to look up inputs in, for their values and the fee
+    #[arg(long, value_name = "BLOCKCHAIN_FILE")]
+    chain: Option<PathBuf>,
+    #[command(flatten)]
+    json: JsonArgs,
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+// every output the chain ever created, spent or not
+fn chain_outputs(path: &Path) -> HashKeyedMap<TransactionOutput> {
+    let blockchain = Blockchain::load_from_file(path).unwrap_or_else(|e| {
+        eprintln!("{}: {e}", path.display());
         exit(1);
+    });
+    blockchain
+        .blocks()
+        .flat_map(|block| &block.transactions)
+        .flat_map(|transaction| &transaction.outputs)
+        .map(|output| (output.hash(), output.clone()))
+        .collect()
+}
+
+fn summarize(
+    transaction: &Transaction,
+    outputs: Option<&HashKeyedMap<TransactionOutput>>,
+) -> TransactionSummary {
+    let inputs: Vec<InputSummary> = transaction
+        .inputs
+        .iter()
+        .map(|input| {
+            let spent =
+                outputs.and_then(|outputs| outputs.get(&input.prev_transaction_output_hash));
+            InputSummary {
+                output_hash: input.prev_transaction_output_hash,
+                value: spent.map(|output| output.value),
+                address: spent.map(|output| output.pubkey.to_address()),
+            }
+        })
+        .collect();
+    let output_value = transaction.outputs.iter().map(|output| output.value).sum();
+    let coinbase = inputs.is_empty();
+    let input_value = match (coinbase, outputs) {
+        (false, Some(_)) => inputs.iter().map(|input| input.value).sum(),
+        _ => None,
     };
-    if let Ok(file) = File::open(path) {
-        let tx = Transaction::load(file).expect("Failed to load transaction");
-        println!("{:#?}", tx);
+    TransactionSummary {
+        txid: transaction.hash(),
+        size: transaction.size(),
+        coinbase,
+        inputs,
+        outputs: transaction
+            .outputs
+            .iter()
+            .map(|output| OutputSummary {
+                hash: output.hash(),
+                value: output.value,
+                address: output.pubkey.to_address(),
+            })
+            .collect(),
+        output_value,
+        input_value,
+        fee: input_value.map(|value: u64| value.saturating_sub(output_value)),
+    }
+}
+
+fn print(summary: &TransactionSummary) {
+    println!("txid          {}", summary.txid.to_hex());
+    println!("size          {} bytes", summary.size);
+    if summary.coinbase {
+        println!("inputs        none, coinbase");
+    } else {
+        println!("inputs        {}", summary.inputs.len());
+    }
+    for input in &summary.inputs {
+        match (input.value, &input.address) {
+            (Some(value), Some(address)) => {
+                println!(
+                    "  {}  {:>20}  {address}",
+                    input.output_hash.to_hex(),
+                    format_value(value)
+                )
+            }
+            _ => println!("  {}  {:>20}", input.output_hash.to_hex(), "unknown"),
+        }
+    }
+    println!("outputs       {}", summary.outputs.len());
+    for output in &summary.outputs {
+        println!(
+            "  {}  {:>20}  {}",
+            output.hash.to_hex(),
+            format_value(output.value),
+            output.address
+        );
+    }
+    println!("output value  {}", format_value(summary.output_value));
+    match summary.fee {
+        Some(fee) => println!("fee           {}", format_value(fee)),
+        None if summary.coinbase => {}
+        None => println!("fee           unknown, --chain looks up the inputs"),
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    lib::logging::init(cli.verbosity.verbose);
+    let (path, format) = (&cli.tx_file, cli.format.format);
+    let transaction = Transaction::load_from_file_as(path, format).unwrap_or_else(|e| {
+        let path = path.display();
+        if Block::load_from_file_as(&cli.tx_file, format).is_ok() {
+            eprintln!("{path} holds a block, not a transaction, see block_print");
+        } else {
+            eprintln!("{path}: {e}");
+        }
+        exit(1);
+    });
+    let outputs = cli.chain.as_deref().map(chain_outputs);
+    let summary = summarize(&transaction, outputs.as_ref());
+    if cli.json.json {
+        println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+    } else {
+        print(&summary);
     }
 }
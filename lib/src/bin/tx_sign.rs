@@ -0,0 +1,177 @@
+use clap::Parser;
+use lib::cli::{FormatArgs, Verbosity};
+use lib::crypto::{PrivateKey, Signature};
+use lib::error::SbdError;
+use lib::sha256::{Hash, HashKeyedMap};
+use lib::types::{Blockchain, Transaction, TransactionOutput, read_snapshot};
+use lib::utils::Saveable;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+/// Sign the inputs of a transaction spending outputs of a key, or check them
+#[derive(Parser)]
+#[command(name = "tx_sign", version)]
+struct Cli {
+    /// Transaction to sign, or to check with --check-only
+    tx_file: PathBuf,
+    /// File holding the secret of the key in hex
+    #[arg(required_unless_present = "check_only")]
+    private_key_file: Option<PathBuf>,
+    /// Blockchain or utxo snapshot file the inputs spend outputs of
+    #[arg(long, value_name = "BLOCKCHAIN_OR_SNAPSHOT_FILE")]
+    utxos: PathBuf,
+    /// Where to write the signed transaction
+    #[arg(
+        long,
+        value_name = "SIGNED_TX_FILE",
+        required_unless_present = "check_only"
+    )]
+    out: Option<PathBuf>,
+    /// Check the signatures instead, exiting 1 if any is missing or invalid
+    #[arg(long, conflicts_with_all = ["private_key_file", "out"])]
+    check_only: bool,
+    // of both transaction files
+    #[command(flatten)]
+    format: FormatArgs,
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+// the unspent outputs of a utxo snapshot, or of a saved chain
+fn load_utxos(path: &Path) -> HashKeyedMap<TransactionOutput> {
+    let snapshot = File::open(path)
+        .map_err(SbdError::from)
+        .and_then(|file| read_snapshot(BufReader::new(file)));
+    if let Ok((_, entries)) = snapshot {
+        return entries
+            .into_iter()
+            .map(|entry| (entry.hash, entry.output))
+            .collect();
+    }
+    let blockchain = Blockchain::load_from_file(path).unwrap_or_else(|e| {
+        eprintln!(
+            "{}: neither a utxo snapshot nor a blockchain: {e}",
+            path.display()
+        );
+        exit(1);
+    });
+    blockchain
+        .utxos()
+        .iter()
+        .map(|(hash, output, _)| (*hash, output.clone()))
+        .collect()
+}
+
+fn load_key(path: &Path) -> PrivateKey {
+    let secret = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("{}: {e}", path.display());
+        exit(1);
+    });
+    PrivateKey::from_hex(secret.trim()).unwrap_or_else(|| {
+        eprintln!("{}: not a private key in hex", path.display());
+        exit(1);
+    })
+}
+
+// whether each input carries a valid signature by the owner of the
+// output it spends, printing why not for those that don't
+fn check(transaction: &Transaction, utxos: &HashKeyedMap<TransactionOutput>) -> bool {
+    let mut valid = true;
+    for (index, input) in transaction.inputs.iter().enumerate() {
+        let hash = input.prev_transaction_output_hash;
+        let sighash = transaction.sighash(&hash);
+        match utxos.get(&hash) {
+            Some(output) if input.signature.verify(&sighash, &output.pubkey) => {
+                println!(
+                    "input {index} {hash}: valid, {}",
+                    output.pubkey.to_address()
+                )
+            }
+            Some(output) => {
+                valid = false;
+                println!(
+                    "input {index} {hash}: invalid signature for {}",
+                    output.pubkey.to_address()
+                )
+            }
+            None => {
+                valid = false;
+                println!("input {index} {hash}: spends an unknown or spent output")
+            }
+        }
+    }
+    valid
+}
+
+// sign the inputs spending outputs of the key, reporting those it
+// can't; inputs already signed by their owners are left as they are
+fn sign(
+    transaction: &mut Transaction,
+    key: &PrivateKey,
+    utxos: &HashKeyedMap<TransactionOutput>,
+) -> Vec<usize> {
+    let pubkey = key.public_key();
+    let mut signed = vec![];
+    let sighashes: Vec<Hash> = transaction
+        .inputs
+        .iter()
+        .map(|input| transaction.sighash(&input.prev_transaction_output_hash))
+        .collect();
+    for ((index, input), sighash) in transaction.inputs.iter_mut().enumerate().zip(sighashes) {
+        let hash = input.prev_transaction_output_hash;
+        match utxos.get(&hash) {
+            Some(output) if input.signature.verify(&sighash, &output.pubkey) => {}
+            Some(output) if output.pubkey == pubkey => {
+                input.signature = Signature::sign_input(&sighash, key);
+                if !input.signature.verify(&sighash, &output.pubkey) {
+                    eprintln!("BUG: input {index} {hash}: signature doesn't verify");
+                    exit(1);
+                }
+                signed.push(index);
+            }
+            Some(output) => eprintln!(
+                "input {index} {hash}: not signed, it belongs to {}",
+                output.pubkey.to_address()
+            ),
+            None => eprintln!("input {index} {hash}: not signed, unknown or spent output"),
+        }
+    }
+    // the signatures changed the transaction
+    transaction.thaw();
+    signed
+}
+
+fn main() {
+    let cli = Cli::parse();
+    lib::logging::init(cli.verbosity.verbose);
+    let format = cli.format.format;
+    let mut transaction =
+        Transaction::load_from_file_as(&cli.tx_file, format).unwrap_or_else(|e| {
+            eprintln!("{}: {e}", cli.tx_file.display());
+            exit(1);
+        });
+    let utxos = load_utxos(&cli.utxos);
+
+    let (Some(key_path), Some(out)) = (cli.private_key_file, cli.out) else {
+        if !check(&transaction, &utxos) {
+            exit(1);
+        }
+        return;
+    };
+    let key = load_key(&key_path);
+    let signed = sign(&mut transaction, &key, &utxos);
+    println!(
+        "signed {} of {} inputs, txid {}",
+        signed.len(),
+        transaction.inputs.len(),
+        transaction.hash()
+    );
+    transaction
+        .save_to_file_as(&out, format)
+        .unwrap_or_else(|e| {
+            eprintln!("{}: {e}", out.display());
+            exit(1);
+        });
+}
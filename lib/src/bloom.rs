@@ -0,0 +1,123 @@
+use crate::U256;
+use crate::canonical::to_bytes;
+use crate::crypto::PublicKey;
+use crate::sha256::Hash;
+use crate::types::{Block, Transaction};
+use crate::utils::MerkleProof;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::LN_2;
+
+// probabilistic set of what a light client watches, output hashes
+// and public keys, that a peer matches transactions against. it
+// can say yes to something never inserted, but never no to
+// something that was
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    hash_count: u32,
+    // varies the bits set per element between filters, so
+    // they don't all share the same false positives
+    tweak: u32,
+}
+
+impl BloomFilter {
+    // a filter sized to hold elements with about the given rate of
+    // false positives, within MAX_BLOOM_FILTER_SIZE and
+    // MAX_BLOOM_HASH_FUNCS
+    pub fn new(elements: usize, false_positive_rate: f64, tweak: u32) -> Self {
+        let elements = elements.max(1) as f64;
+        let rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 1.0);
+        let bits = -elements * rate.ln() / (LN_2 * LN_2);
+        let bytes = ((bits / 8.0).ceil() as usize).clamp(1, crate::MAX_BLOOM_FILTER_SIZE);
+        let hash_count = ((bytes * 8) as f64 / elements * LN_2).round() as u32;
+        BloomFilter {
+            bits: vec![0; bytes],
+            hash_count: hash_count.clamp(1, crate::MAX_BLOOM_HASH_FUNCS),
+            tweak,
+        }
+    }
+
+    // false for a filter a peer sent that is empty or
+    // larger than we keep
+    pub fn is_within_limits(&self) -> bool {
+        !self.bits.is_empty()
+            && self.bits.len() <= crate::MAX_BLOOM_FILTER_SIZE
+            && (1..=crate::MAX_BLOOM_HASH_FUNCS).contains(&self.hash_count)
+    }
+
+    pub fn insert(&mut self, data: &[u8]) {
+        for index in self.indexes(data) {
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    pub fn contains(&self, data: &[u8]) -> bool {
+        self.indexes(data)
+            .all(|index| self.bits[index / 8] & (1 << (index % 8)) != 0)
+    }
+
+    // watch for an output by its hash, and for inputs spending it
+    pub fn insert_hash(&mut self, hash: &Hash) {
+        self.insert(&to_bytes(hash));
+    }
+
+    pub fn contains_hash(&self, hash: &Hash) -> bool {
+        self.contains(&to_bytes(hash))
+    }
+
+    // watch for outputs paying a key
+    pub fn insert_pubkey(&mut self, pubkey: &PublicKey) {
+        self.insert(&to_bytes(pubkey));
+    }
+
+    pub fn contains_pubkey(&self, pubkey: &PublicKey) -> bool {
+        self.contains(&to_bytes(pubkey))
+    }
+
+    // whether a transaction concerns what the filter watches: its
+    // hash, an output's hash or key, or an input spending a watched
+    // output. the hashes of matching outputs are inserted, so that
+    // the transaction spending them later matches as well
+    pub fn is_relevant_and_update(&mut self, transaction: &Transaction) -> bool {
+        let mut relevant = self.contains_hash(&transaction.hash());
+        for output in &transaction.outputs {
+            let hash = output.hash();
+            if self.contains_hash(&hash) || self.contains_pubkey(&output.pubkey) {
+                self.insert_hash(&hash);
+                relevant = true;
+            }
+        }
+        relevant
+            || transaction
+                .inputs
+                .iter()
+                .any(|input| self.contains_hash(&input.prev_transaction_output_hash))
+    }
+
+    // the transactions of a block the filter matches, each with
+    // its proof against the block's merkle root
+    pub fn filter_block(&mut self, block: &Block) -> Vec<(Transaction, MerkleProof)> {
+        let mut matched = vec![];
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            if !self.is_relevant_and_update(transaction) {
+                continue;
+            }
+            if let Some(proof) = MerkleProof::new(&block.transactions, index) {
+                matched.push((transaction.clone(), proof));
+            }
+        }
+        matched
+    }
+
+    // hash_count bit indexes from one sha256 of the tweak and
+    // the data, as two halves stepped through the filter
+    fn indexes(&self, data: &[u8]) -> impl Iterator<Item = usize> + use<> {
+        let mut seeded = self.tweak.to_be_bytes().to_vec();
+        seeded.extend_from_slice(data);
+        let hash = U256::from(Hash::hash_bytes(&seeded));
+        let bits = (self.bits.len() * 8) as u64;
+        let first = hash.low_u64() % bits;
+        let step = (hash >> 64).low_u64() % bits;
+        (0..self.hash_count as u64).map(move |i| ((first + i * step) % bits) as usize)
+    }
+}
@@ -0,0 +1,156 @@
+use crate::U256;
+use crate::error::{Result, SbdError};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+
+// the bytes Hash::hash takes the sha256 of, written out by hand so
+// block and transaction hashes never depend on what a serde backend
+// happens to emit. the encoding is cbor (rfc 8949) with definite
+// lengths, the shortest form of every integer and no floats:
+//
+// - structs are maps of their field names, in declaration order
+// - BlockHeader leaves out its version while it's 0 and its
+//   utxo_commitment while it's zero, Transaction its expires_at_height
+//   and TransactionOutput its spendable_after_height while they're
+//   None, as they were before they had them
+// - u64 is an unsigned integer
+// - U256 and Hash are arrays of their 4 u64 limbs, least significant first
+// - DateTime is rfc 3339 text in utc ending in Z, with 0, 3, 6 or 9
+//   fractional digits, as few as hold the nanoseconds
+// - Uuid is a 16 byte string
+// - PublicKey is its der subject public key info as an array of
+//   unsigned integers, Signature its 64 bytes of r and s likewise
+//
+// these are the bytes ciborium wrote for the same types, so hashes
+// taken before the encoding was pinned stay the same; changing any
+// of it changes every block hash and forks the chain. the hashes of
+// Block, BlockHeader, Transaction, TransactionInput and TransactionOutput
+// are fixed for good; saved files only change with SCHEMA_VERSION and
+// are migrated on load. `compat_fixtures check compat` holds both, and
+// tests/digests.rs the hashes under cargo test
+pub trait Canonical {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+pub fn to_bytes<T: Canonical + ?Sized>(value: &T) -> Vec<u8> {
+    let mut out = vec![];
+    value.encode(&mut out);
+    out
+}
+
+// the single string form of a value, e.g. a raw transaction
+pub fn to_hex<T: Canonical + ?Sized>(value: &T) -> String {
+    hex::encode(to_bytes(value))
+}
+
+// parse the hex of a canonical encoding; bytes after the value and
+// other encodings of it are refused, so the string stands for one
+// value only and the hash of what it decodes to never changes
+pub fn from_hex<T: Canonical + DeserializeOwned>(hex: &str, kind: &'static str) -> Result<T> {
+    let bytes = hex::decode(hex.trim()).map_err(|e| SbdError::InvalidHex(e.to_string()))?;
+    let invalid = |reason: String| SbdError::InvalidEncoding { kind, reason };
+    let mut data = &bytes[..];
+    let value: T = ciborium::from_reader(&mut data).map_err(SbdError::decode(kind))?;
+    if !data.is_empty() {
+        return Err(invalid(format!("{} trailing bytes", data.len())));
+    }
+    if to_bytes(&value) != bytes {
+        return Err(invalid("not in canonical form".to_string()));
+    }
+    Ok(value)
+}
+
+const UNSIGNED: u8 = 0;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+
+// major type and argument, in the shortest form that holds it
+fn head(out: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    if value < 24 {
+        out.push(major | value as u8);
+    } else if value <= u8::MAX as u64 {
+        out.push(major | 24);
+        out.push(value as u8);
+    } else if value <= u16::MAX as u64 {
+        out.push(major | 25);
+        out.extend_from_slice(&(value as u16).to_be_bytes());
+    } else if value <= u32::MAX as u64 {
+        out.push(major | 26);
+        out.extend_from_slice(&(value as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+fn text(out: &mut Vec<u8>, text: &str) {
+    head(out, TEXT, text.len() as u64);
+    out.extend_from_slice(text.as_bytes());
+}
+
+// a struct as the map of its fields
+pub fn encode_struct(out: &mut Vec<u8>, fields: &[(&str, &dyn Canonical)]) {
+    head(out, MAP, fields.len() as u64);
+    for (name, value) in fields {
+        text(out, name);
+        value.encode(out);
+    }
+}
+
+// bytes as an array of unsigned integers, not a byte string
+pub fn encode_byte_array(out: &mut Vec<u8>, bytes: &[u8]) {
+    head(out, ARRAY, bytes.len() as u64);
+    for byte in bytes {
+        head(out, UNSIGNED, *byte as u64);
+    }
+}
+
+impl Canonical for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        head(out, UNSIGNED, *self);
+    }
+}
+
+impl Canonical for U256 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+    }
+}
+
+impl Canonical for DateTime<Utc> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        text(out, &self.to_rfc3339_opts(SecondsFormat::AutoSi, true));
+    }
+}
+
+impl Canonical for Uuid {
+    fn encode(&self, out: &mut Vec<u8>) {
+        head(out, BYTES, 16);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl<T: Canonical> Canonical for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        head(out, ARRAY, self.len() as u64);
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+impl<T: Canonical, const N: usize> Canonical for [T; N] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self[..].encode(out);
+    }
+}
+
+impl<T: Canonical> Canonical for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self[..].encode(out);
+    }
+}
@@ -0,0 +1,53 @@
+// the arguments every binary shares, flattened into their clap
+// parsers so a flag is spelled and behaves the same everywhere;
+// clap exits 2 on a usage error, binaries exit 1 when the work fails
+use crate::params::{Network, NetworkParams};
+#[cfg(feature = "json")]
+use crate::utils::FileFormat;
+use clap::error::ErrorKind;
+use clap::{ArgAction, Args, CommandFactory};
+use std::fmt::Display;
+
+#[derive(Args, Clone, Copy, Debug, Default)]
+pub struct Verbosity {
+    /// Log more of what happens, twice for everything
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+}
+
+#[derive(Args, Clone, Copy, Debug, Default)]
+pub struct NetworkArgs {
+    /// Network whose rules and magic to use
+    #[arg(long, value_enum, global = true)]
+    pub network: Option<Network>,
+}
+
+impl NetworkArgs {
+    // the network asked for, or the one a binary runs on by default
+    pub fn params_or(&self, default: Network) -> NetworkParams {
+        self.network.unwrap_or(default).params()
+    }
+}
+
+#[derive(Args, Clone, Copy, Debug, Default)]
+pub struct JsonArgs {
+    /// Print json instead of text
+    #[arg(long, global = true)]
+    pub json: bool,
+}
+
+#[cfg(feature = "json")]
+#[derive(Args, Clone, Copy, Debug, Default)]
+pub struct FormatArgs {
+    /// Encoding of the file
+    #[arg(long, value_enum, default_value_t)]
+    pub format: FileFormat,
+}
+
+// a usage error clap can't see, like two flags that only conflict
+// for some values, reported and exiting 2 as clap's own are
+pub fn usage_error<C: CommandFactory>(message: impl Display) -> ! {
+    C::command()
+        .error(ErrorKind::ArgumentConflict, message)
+        .exit()
+}
@@ -1,32 +1,99 @@
+use crate::canonical::{Canonical, encode_byte_array};
+use crate::error::SbdError;
 use crate::sha256::Hash;
+use crate::utils::Saveable;
+use ecdsa::elliptic_curve::pkcs8::{
+    DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding,
+};
 use ecdsa::signature::Signer;
 use ecdsa::{Signature as ECDSASignature, SigningKey, VerifyingKey, signature::Verifier};
 use k256::Secp256k1;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Signature(pub ECDSASignature<Secp256k1>);
 
+// hashed as the bytes they encode to, so equal values hash alike
+impl std::hash::Hash for Signature {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_bytes().hash(state);
+    }
+}
+
+// signed messages are this magic followed by the sha256 of the
+// message, 60 bytes that can never pass for a 32 byte sighash
+const MESSAGE_MAGIC: &[u8] = b"Ssebidecoin Signed Message:\n";
+
+fn message_preimage(message: &[u8]) -> Vec<u8> {
+    let mut preimage = MESSAGE_MAGIC.to_vec();
+    preimage.extend(hex::decode(sha256::digest(message)).unwrap());
+    preimage
+}
+
 impl Signature {
-    //sign a crate::types::TransactionOutput from its sha256 hash
-    pub fn sign_output(output_hash: &Hash, private_key: &PrivateKey) -> Self {
+    // sign an input from its sighash, see Transaction::sighash
+    pub fn sign_input(sighash: &Hash, private_key: &PrivateKey) -> Self {
         let signing_key = &private_key.0;
-        let signature = signing_key.sign(&output_hash.as_bytes());
+        let signature = signing_key.sign(&sighash.as_bytes());
         Signature(signature)
     }
 
-    //verify a signature
-    pub fn verify(&self, output_hash: &Hash, public_key: &PublicKey) -> bool {
+    // verify the signature of an input against its sighash
+    pub fn verify(&self, sighash: &Hash, public_key: &PublicKey) -> bool {
+        public_key.0.verify(&sighash.as_bytes(), &self.0).is_ok()
+    }
+
+    // sign an arbitrary message, e.g. to prove owning an address
+    pub fn sign_message(message: &[u8], private_key: &PrivateKey) -> Self {
+        Signature(private_key.0.sign(&message_preimage(message)))
+    }
+
+    pub fn verify_message(&self, message: &[u8], public_key: &PublicKey) -> bool {
         public_key
             .0
-            .verify(&output_hash.as_bytes(), &self.0)
+            .verify(&message_preimage(message), &self.0)
             .is_ok()
     }
+
+    // a fixed valid signature, for fixtures that need one
+    pub fn placeholder() -> Self {
+        Signature(ECDSASignature::from_slice(&[1; 64]).expect("BUG: invalid placeholder"))
+    }
+
+    // stands in for a signature when sizing unsigned transactions; r
+    // and s are encoded a byte at a time, bytes under 24 in one byte
+    // and the rest in two, so no signature encodes longer than this
+    pub fn widest() -> Self {
+        Signature(ECDSASignature::from_slice(&[0x80; 64]).expect("BUG: invalid widest"))
+    }
+
+    // hex of the 64 byte r and s
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0.to_bytes())
+    }
+
+    pub fn from_hex(signature: &str) -> Option<Signature> {
+        let bytes = hex::decode(signature).ok()?;
+        ECDSASignature::from_slice(&bytes).ok().map(Signature)
+    }
+}
+
+impl Canonical for Signature {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_byte_array(out, &self.0.to_bytes());
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct PublicKey(VerifyingKey<Secp256k1>);
 
+impl std::hash::Hash for PublicKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_encoded_point(true).as_bytes().hash(state);
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PrivateKey(#[serde(with = "signkey_serde")] pub SigningKey<Secp256k1>);
 mod signkey_serde {
@@ -48,7 +115,7 @@ mod signkey_serde {
         D: serde::Deserializer<'de>,
     {
         let bytes: Vec<u8> = Vec::<u8>::deserialize(deserializer)?;
-        Ok(super::SigningKey::from_slice(&bytes).unwrap())
+        super::SigningKey::from_slice(&bytes).map_err(serde::de::Error::custom)
     }
 }
 
@@ -58,6 +125,83 @@ impl PrivateKey {
     }
 
     pub fn public_key(&self) -> PublicKey {
-        PublicKey(self.0.verifying_key().clone())
+        PublicKey(*self.0.verifying_key())
+    }
+
+    // hex of the 32 byte secret scalar
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0.to_bytes())
+    }
+
+    pub fn from_hex(secret: &str) -> Option<PrivateKey> {
+        let bytes = hex::decode(secret).ok()?;
+        SigningKey::from_slice(&bytes).ok().map(PrivateKey)
+    }
+
+    // pkcs#8 pem, as openssl writes it
+    pub fn to_pem(&self) -> String {
+        self.0
+            .to_pkcs8_pem(LineEnding::LF)
+            .expect("BUG: private key encoding failed")
+            .to_string()
+    }
+
+    pub fn from_pem(pem: &str) -> Option<PrivateKey> {
+        SigningKey::from_pkcs8_pem(pem).ok().map(PrivateKey)
+    }
+}
+
+impl Saveable for PrivateKey {
+    const TYPE_TAG: &'static str = "PrivateKey";
+}
+
+impl PublicKey {
+    // hex of the compressed SEC1 encoding, used as an address
+    pub fn to_address(&self) -> String {
+        hex::encode(self.0.to_encoded_point(true).as_bytes())
+    }
+
+    pub fn from_address(address: &str) -> Option<PublicKey> {
+        let bytes = hex::decode(address).ok()?;
+        VerifyingKey::from_sec1_bytes(&bytes).ok().map(PublicKey)
+    }
+
+    // hex of the uncompressed SEC1 encoding
+    pub fn to_uncompressed_hex(&self) -> String {
+        hex::encode(self.0.to_encoded_point(false).as_bytes())
+    }
+
+    // subject public key info pem, as openssl writes it
+    pub fn to_pem(&self) -> String {
+        self.0
+            .to_public_key_pem(LineEnding::LF)
+            .expect("BUG: public key encoding failed")
+    }
+
+    pub fn from_pem(pem: &str) -> Option<PublicKey> {
+        VerifyingKey::from_public_key_pem(pem).ok().map(PublicKey)
+    }
+}
+
+// an address, as clap and config files take them
+impl FromStr for PublicKey {
+    type Err = SbdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PublicKey::from_address(s).ok_or(SbdError::InvalidPublicKey)
+    }
+}
+
+impl Saveable for PublicKey {
+    const TYPE_TAG: &'static str = "PublicKey";
+}
+
+impl Canonical for PublicKey {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let der = self
+            .0
+            .to_public_key_der()
+            .expect("BUG: public key encoding failed");
+        encode_byte_array(out, der.as_bytes());
     }
 }
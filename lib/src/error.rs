@@ -1,3 +1,7 @@
+use crate::params::Network;
+use crate::sha256::Hash;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,6 +12,8 @@ pub enum SbdError {
     InvalidBlock,
     #[error("Invalid block header")]
     InvalidBlockHeader,
+    #[error("Invalid proof of work")]
+    InvalidProofOfWork,
     #[error("Invalid transaction input")]
     InvalidTransactionInput,
     #[error("Invalid transaction output")]
@@ -22,6 +28,121 @@ pub enum SbdError {
     InvalidPublicKey,
     #[error("Invalid private key")]
     InvalidPrivateKey,
+    #[error("Input {0} spends an unknown or already spent output")]
+    MissingInput(Hash),
+    #[error("Input {0} is spent twice")]
+    DuplicateInput(Hash),
+    #[error("Input {hash} spends an output locked until height {unlock_height}")]
+    LockedOutput { hash: Hash, unlock_height: u64 },
+    #[error("Input {hash} spends a coinbase output immature until height {mature_height}")]
+    ImmatureCoinbase { hash: Hash, mature_height: u64 },
+    #[error("Transaction {txid} expired at height {expires_at_height}")]
+    ExpiredTransaction { txid: Hash, expires_at_height: u64 },
+    #[error("Outputs of {outputs} exceed inputs of {inputs}")]
+    OutputsExceedInputs { inputs: u64, outputs: u64 },
+    #[error("Inputs {0:?} are not signed yet")]
+    UnsignedInputs(Vec<usize>),
+    #[error("Block {0} is unknown")]
+    UnknownBlock(Hash),
+    #[error("Block {0} is not on the best chain")]
+    NotOnBestChain(Hash),
+    #[error("Wrong network: expected {expected}, found {found}")]
+    WrongNetwork { expected: Network, found: Network },
+    #[error("Utxo commitment {found} doesn't match the utxos, expected {expected}")]
+    UtxoCommitmentMismatch { expected: Hash, found: Hash },
+    #[error("Snapshot commitment {found} doesn't match its contents, expected {expected}")]
+    SnapshotMismatch { expected: Hash, found: String },
+    #[error("Checkpoint at height {height} isn't signed by a trusted key")]
+    InvalidCheckpointSignature { height: u64 },
+    #[error("Block {found} at height {height} conflicts with the checkpoint {expected}")]
+    CheckpointConflict {
+        height: u64,
+        expected: Hash,
+        found: Hash,
+    },
+    #[error("Snapshot of {0} is neither committed to by its header nor trusted")]
+    UntrustedSnapshot(Hash),
+    #[error("Checksum mismatch: expected {expected}, file has {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("Invalid hex: {0}")]
+    InvalidHex(String),
+    #[error("Failed to decode {kind}: {reason}")]
+    InvalidEncoding { kind: &'static str, reason: String },
+    #[error("Failed to decode {kind}: {}", decode_reason(.source))]
+    Decode {
+        kind: &'static str,
+        source: ciborium::de::Error<IoError>,
+    },
+    #[error("Failed to encode {kind}: {}", encode_reason(.source))]
+    Encode {
+        kind: &'static str,
+        source: ciborium::ser::Error<IoError>,
+    },
+    #[error("Block storage failed: {0}")]
+    Storage(#[from] IoError),
 }
 
 pub type Result<T> = std::result::Result<T, SbdError>;
+
+impl SbdError {
+    // for map_err on ciborium results, naming what was decoded
+    pub fn decode(kind: &'static str) -> impl FnOnce(ciborium::de::Error<IoError>) -> Self {
+        move |source| SbdError::Decode { kind, source }
+    }
+    pub fn encode(kind: &'static str) -> impl FnOnce(ciborium::ser::Error<IoError>) -> Self {
+        move |source| SbdError::Encode { kind, source }
+    }
+}
+
+// so functions returning io results can use ? on ours; an io error
+// comes back out as itself, anything else is kept as the cause
+impl From<SbdError> for IoError {
+    fn from(e: SbdError) -> Self {
+        match e {
+            SbdError::Storage(e) => e,
+            e => IoError::new(IoErrorKind::InvalidData, e),
+        }
+    }
+}
+
+// ciborium's own Display is its Debug
+fn decode_reason(e: &ciborium::de::Error<IoError>) -> String {
+    match e {
+        ciborium::de::Error::Io(e) if e.kind() == IoErrorKind::UnexpectedEof => {
+            "it ends early".to_string()
+        }
+        ciborium::de::Error::Io(e) => e.to_string(),
+        ciborium::de::Error::Syntax(offset) => format!("malformed cbor at byte {offset}"),
+        ciborium::de::Error::Semantic(_, reason) => reason.clone(),
+        ciborium::de::Error::RecursionLimitExceeded => "nested too deeply".to_string(),
+    }
+}
+
+fn encode_reason(e: &ciborium::ser::Error<IoError>) -> String {
+    match e {
+        ciborium::ser::Error::Io(e) => e.to_string(),
+        ciborium::ser::Error::Value(reason) => reason.clone(),
+    }
+}
+
+// the error of a main returning Result; std prints it with Debug,
+// which for this is the message rather than the variant
+pub struct Report(String);
+
+impl Report {
+    pub fn new(message: impl Display) -> Self {
+        Report(message.to_string())
+    }
+}
+
+impl<E: std::error::Error> From<E> for Report {
+    fn from(e: E) -> Self {
+        Report(e.to_string())
+    }
+}
+
+impl Debug for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
@@ -0,0 +1,74 @@
+// the entry points the fuzz targets in lib/fuzz and the fuzz binary
+// drive: each takes any bytes and may only fail with an error, never
+// panic or allocate without bound; whatever loads must save and load
+// again
+use crate::network::Message;
+use crate::params::NetworkParams;
+use crate::sha256::checksum;
+use crate::types::{Block, Blockchain, Transaction};
+use crate::utils::{Saveable, file_checksum};
+
+// the fuzzed types, one target each
+pub const TARGETS: [&str; 4] = ["block", "transaction", "blockchain", "message"];
+
+// run the target named on the data, false if there is no such target
+pub fn run(target: &str, data: &[u8]) -> bool {
+    match target {
+        "block" => block(data),
+        "transaction" => transaction(data),
+        "blockchain" => blockchain(data),
+        "message" => message(data),
+        _ => return false,
+    }
+    true
+}
+
+pub fn block(data: &[u8]) {
+    round_trip::<Block>(data);
+}
+
+pub fn transaction(data: &[u8]) {
+    round_trip::<Transaction>(data);
+}
+
+pub fn blockchain(data: &[u8]) {
+    round_trip::<Blockchain>(data);
+}
+
+// the data as a whole frame, then as the payload of a frame with
+// the right header, which a checksum would otherwise keep the
+// fuzzer from ever getting past
+pub fn message(data: &[u8]) {
+    let magic = NetworkParams::regtest().magic;
+    let _ = Message::read_from(magic, &mut &data[..]);
+    let mut frame = Vec::with_capacity(16 + data.len());
+    frame.extend_from_slice(&magic);
+    frame.extend_from_slice(&(data.len() as u64).to_be_bytes());
+    frame.extend_from_slice(&checksum(data));
+    frame.extend_from_slice(data);
+    if let Ok(message) = Message::read_from(magic, &mut &frame[..]) {
+        let frame = message.frame(magic).expect("message failed to encode");
+        Message::read_from(magic, &mut &frame[..]).expect("message failed to decode again");
+    }
+}
+
+// the data as it is, then sealed with the checksum a saved file ends
+// in, so a mutated header or body gets past it
+fn round_trip<T: Saveable>(data: &[u8]) {
+    load_and_save::<T>(data);
+    let mut sealed = data.to_vec();
+    sealed.extend_from_slice(&file_checksum(data));
+    load_and_save::<T>(&sealed);
+}
+
+fn load_and_save<T: Saveable>(data: &[u8]) {
+    if let Ok(value) = T::load(data) {
+        let mut saved = vec![];
+        value
+            .save(&mut saved)
+            .unwrap_or_else(|e| panic!("{} failed to save: {e}", T::TYPE_TAG));
+        if let Err(e) = T::load(&saved[..]) {
+            panic!("{} failed to load again: {e}", T::TYPE_TAG);
+        }
+    }
+}
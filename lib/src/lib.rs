@@ -1,15 +1,52 @@
-use serde::{Deserialize, Serialize};
-use uint::construct_uint;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-construct_uint! {
-    //construct an unsigned 256-bit integer
-    //consisting of 4 x 64-bit words
-    #[derive(Serialize, Deserialize)]
-    pub struct U256(4);
+pub use uint256::U256;
+
+// construct_uint! expands to code clippy flags as a manual div_ceil,
+// allowed for the expansion only
+#[allow(clippy::manual_div_ceil)]
+mod uint256 {
+    uint::construct_uint! {
+        //construct an unsigned 256-bit integer
+        //consisting of 4 x 64-bit words
+        pub struct U256(4);
+    }
+}
+
+// the cbor form is the words, as hashes are taken over it; human
+// readable formats like json get a hex string instead
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "U256")]
+struct U256Words([u64; 4]);
+
+impl Serialize for U256 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&format!("{self:064x}"))
+        } else {
+            U256Words(self.0).serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for U256 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let hex = String::deserialize(deserializer)?;
+            U256::from_str_radix(&hex, 16)
+                .map_err(|_| D::Error::custom(format!("invalid hex number {hex}")))
+        } else {
+            Ok(U256(U256Words::deserialize(deserializer)?.0))
+        }
+    }
 }
 
+// the rules of NetworkParams::mainnet(), which chains carry with them
 // initial reward in bitcoin - multiply by 10^8 to get satoshis
 pub const INITIAL_REWARD: u64 = 50;
+// base units in one coin
+pub const UNITS_PER_COIN: u64 = 10u64.pow(8);
 // halving interval in blocks
 pub const HALVING_INTERVAL: u64 = 210;
 // ideal block time in seconds
@@ -25,10 +62,50 @@ pub const MIN_TARGET: U256 = U256([
 pub const DIFFICULTY_UPDATE_INTERVAL: u64 = 50;
 // maximum mempool transaction age in seconds
 pub const MAX_MEMPOOL_TRANSACTION_AGE: u64 = 600;
+// confirmations before a coinbase output counts as spendable
+pub const COINBASE_MATURITY: u64 = 100;
+// maximum amount of transactions allowed in a block template
+pub const BLOCK_TRANSACTION_CAP: usize = 20;
+// maximum amount of headers sent in a single Headers message
+pub const MAX_HEADERS_PER_MESSAGE: usize = 2000;
+// maximum amount of addresses in a single NodeList message
+pub const MAX_NODE_LIST: usize = 1000;
+// maximum amount of items in a single Inv, GetData or NotFound message
+pub const MAX_INV_ITEMS: usize = 50_000;
+// wire protocol version, peers that don't send
+// one in their Version message are version 0
+pub const PROTOCOL_VERSION: u32 = 1;
+// maximum size of a network message payload in bytes
+pub const MAX_MESSAGE_SIZE: u64 = 32 * 1024 * 1024;
+// maximum size of a compressed saved file once decompressed in bytes,
+// as a few bytes of zstd can claim gigabytes
+pub const MAX_DECOMPRESSED_SIZE: u64 = 256 * 1024 * 1024;
+// maximum size of a bloom filter a peer may load in bytes
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+// maximum amount of hash functions of a loaded bloom filter
+pub const MAX_BLOOM_HASH_FUNCS: u32 = 50;
+// maximum amount of transactions watched for double spends
+pub const MAX_WATCHED_TRANSACTIONS: usize = 10_000;
+// maximum amount of chain events kept until they are taken
+pub const MAX_CHAIN_EVENTS: usize = 1000;
 
+pub mod bloom;
+pub mod canonical;
+#[cfg(feature = "cli")]
+pub mod cli;
 pub mod crypto;
 pub mod error;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+pub mod light_client;
+#[cfg(feature = "logging")]
+pub mod logging;
+#[cfg(feature = "network")]
 pub mod network;
+pub mod params;
+pub mod replay;
 pub mod sha256;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
 pub mod types;
 pub mod utils;
@@ -0,0 +1,250 @@
+use crate::U256;
+use crate::error::{Result, SbdError};
+use crate::params::NetworkParams;
+use crate::sha256::{Hash, HashKeyedMap};
+use crate::types::{BlockHeader, HeaderChain, Transaction, work};
+use crate::utils::MerkleProof;
+use tracing::info;
+
+// blocks including or following a transaction's block
+pub type Confirmations = u64;
+
+// follows the chain on block headers alone: proof of work, linkage
+// and the retarget schedule, the best tip being the branch with the
+// most work. needs no clock, files or utxos, so it runs under wasm
+pub struct LightClient {
+    params: NetworkParams,
+    // height of the first header held, 0 unless from a checkpoint
+    base: u64,
+    best: HeaderChain,
+    // headers off the best chain, with their heights
+    forks: HashKeyedMap<(u64, BlockHeader)>,
+}
+
+impl LightClient {
+    // a client whose first header has to be the genesis header
+    pub fn new(params: NetworkParams) -> Self {
+        LightClient {
+            params,
+            base: 0,
+            best: HeaderChain::new(),
+            forks: HashKeyedMap::default(),
+        }
+    }
+
+    // a client trusting the header at height and following on from
+    // it. the retarget after it needs the header a whole interval
+    // back, so height has to be where one starts
+    pub fn from_checkpoint(
+        params: NetworkParams,
+        height: u64,
+        header: BlockHeader,
+    ) -> Result<Self> {
+        let interval = params.difficulty_update_interval;
+        if interval != 0 && !height.is_multiple_of(interval) {
+            return Err(SbdError::InvalidBlockHeader);
+        }
+        Ok(LightClient {
+            params,
+            base: height,
+            best: HeaderChain::from(vec![header]),
+            forks: HashKeyedMap::default(),
+        })
+    }
+
+    pub fn params(&self) -> &NetworkParams {
+        &self.params
+    }
+
+    // height of the next header on the best chain
+    pub fn height(&self) -> u64 {
+        self.base + self.best.height()
+    }
+
+    pub fn tip(&self) -> Option<&BlockHeader> {
+        self.best.tip()
+    }
+
+    // height of a header on the best chain
+    pub fn height_of(&self, hash: &Hash) -> Option<u64> {
+        self.best.height_of(hash).map(|height| self.base + height)
+    }
+
+    // validate a header against its parent and keep it, switching
+    // the best chain if its branch now has the most work
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<()> {
+        let hash = header.hash();
+        if self.find(&hash).is_some() {
+            return Ok(());
+        }
+        let Some((parent_height, parent)) = self
+            .find(&header.prev_block_hash)
+            .map(|(height, parent)| (height, parent.clone()))
+        else {
+            if self.best.height() == 0 && header.prev_block_hash == Hash::zero() {
+                return self.add_genesis(header);
+            }
+            return Err(SbdError::UnknownBlock(header.prev_block_hash));
+        };
+        if header.timestamp <= parent.timestamp {
+            return Err(SbdError::InvalidBlockHeader);
+        }
+        if !hash.matches_target(header.target) {
+            return Err(SbdError::InvalidProofOfWork);
+        }
+        let height = parent_height + 1;
+        if header.target != self.expected_target(&parent, height)? {
+            return Err(SbdError::InvalidBlockHeader);
+        }
+
+        if self.best.tip().map(BlockHeader::hash) == Some(header.prev_block_hash) {
+            return self.best.add_header(header);
+        }
+        self.forks.insert(hash, (height, header));
+        self.reorg_to(hash)
+    }
+
+    // whether the transaction is in the block on the best chain,
+    // and how deeply
+    pub fn verify_transaction_inclusion(
+        &self,
+        tx_hash: &Hash,
+        proof: &MerkleProof,
+        block_hash: &Hash,
+    ) -> Result<Confirmations> {
+        let Some(height) = self.best.height_of(block_hash) else {
+            if self.forks.contains_key(block_hash) {
+                return Err(SbdError::NotOnBestChain(*block_hash));
+            }
+            return Err(SbdError::UnknownBlock(*block_hash));
+        };
+        let header = self
+            .best
+            .get(height)
+            .ok_or(SbdError::UnknownBlock(*block_hash))?;
+        if !proof.verify(tx_hash, &header.merkle_root) {
+            return Err(SbdError::InvalidMerkleRoot);
+        }
+        Ok(self.best.height() - height)
+    }
+
+    // add the header of a FilteredBlock and check the transactions
+    // sent with it, returning them if every proof holds
+    pub fn add_filtered_block(
+        &mut self,
+        header: BlockHeader,
+        transactions: Vec<(Transaction, MerkleProof)>,
+    ) -> Result<Vec<Transaction>> {
+        let merkle_root = header.merkle_root;
+        self.add_header(header)?;
+        let mut verified = vec![];
+        for (transaction, proof) in transactions {
+            if !proof.verify(&transaction.hash(), &merkle_root) {
+                return Err(SbdError::InvalidMerkleRoot);
+            }
+            verified.push(transaction);
+        }
+        Ok(verified)
+    }
+
+    fn add_genesis(&mut self, header: BlockHeader) -> Result<()> {
+        if header.target != self.params.min_target {
+            return Err(SbdError::InvalidBlockHeader);
+        }
+        if !header.hash().matches_target(header.target) {
+            return Err(SbdError::InvalidProofOfWork);
+        }
+        self.best.add_header(header)
+    }
+
+    // a header held on any branch, with its height
+    fn find(&self, hash: &Hash) -> Option<(u64, &BlockHeader)> {
+        if let Some(height) = self.best.height_of(hash) {
+            return self
+                .best
+                .get(height)
+                .map(|header| (self.base + height, header));
+        }
+        self.forks
+            .get(hash)
+            .map(|(height, header)| (*height, header))
+    }
+
+    // the header at height on the branch ending in hash
+    fn ancestor(&self, mut hash: Hash, height: u64) -> Option<&BlockHeader> {
+        loop {
+            if self.best.height_of(&hash).is_some() {
+                // joined the best chain, which has the rest
+                return self.best.get(height.checked_sub(self.base)?);
+            }
+            let (at, header) = self.forks.get(&hash)?;
+            if *at <= height {
+                return (*at == height).then_some(header);
+            }
+            hash = header.prev_block_hash;
+        }
+    }
+
+    // the target the header at height after parent must have,
+    // as Blockchain adjusts it
+    fn expected_target(&self, parent: &BlockHeader, height: u64) -> Result<U256> {
+        // an interval of 0 never adjusts
+        let interval = self.params.difficulty_update_interval;
+        if interval == 0 || !height.is_multiple_of(interval) {
+            return Ok(parent.target);
+        }
+        let start = self
+            .ancestor(parent.hash(), height - interval)
+            .ok_or(SbdError::UnknownBlock(parent.hash()))?;
+        Ok(self
+            .params
+            .retarget(parent.target, start.timestamp, parent.timestamp))
+    }
+
+    // make the branch ending in the fork header hash the best chain
+    // if it has more work than the headers it would replace, which
+    // become a fork in turn
+    fn reorg_to(&mut self, hash: Hash) -> Result<()> {
+        let mut branch = vec![];
+        let mut branch_work = U256::zero();
+        let mut cursor = hash;
+        while let Some((_, header)) = self.forks.get(&cursor) {
+            branch_work = branch_work.saturating_add(work(header.target));
+            branch.push(cursor);
+            cursor = header.prev_block_hash;
+        }
+        let fork_point = self
+            .best
+            .height_of(&cursor)
+            .ok_or(SbdError::UnknownBlock(cursor))?;
+        let displaced = self.best.headers()[fork_point as usize + 1..].to_vec();
+        let displaced_work = displaced.iter().fold(U256::zero(), |total, header| {
+            total.saturating_add(work(header.target))
+        });
+        // a tie keeps the branch seen first
+        if branch_work <= displaced_work {
+            return Ok(());
+        }
+
+        info!(
+            tip = %hash,
+            fork_height = self.base + fork_point,
+            replaced = displaced.len(),
+            "switching to a branch with more work"
+        );
+        let first_displaced = self.base + fork_point + 1;
+        for (offset, header) in displaced.into_iter().enumerate() {
+            self.forks
+                .insert(header.hash(), (first_displaced + offset as u64, header));
+        }
+        self.best.truncate(fork_point + 1);
+        for hash in branch.into_iter().rev() {
+            let (_, header) = self
+                .forks
+                .remove(&hash)
+                .ok_or(SbdError::UnknownBlock(hash))?;
+            self.best.add_header(header)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use std::io::{self, IsTerminal};
+use tracing_subscriber::EnvFilter;
+
+fn level(verbosity: u8) -> &'static str {
+    match verbosity {
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    }
+}
+
+// print the library's events to stderr, apart from what a binary
+// prints itself; RUST_LOG picks what is shown if set, otherwise
+// warnings and each -v one level more
+pub fn init(verbosity: u8) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level(verbosity)));
+    // a second init, as from a test, keeps the first
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .try_init();
+}
+
+// for a daemon whose events are its output: print them to stdout,
+// those of the crate named target from info on and the library's as
+// init does
+pub fn init_daemon(target: &str, verbosity: u8) {
+    let level = level(verbosity);
+    let own = if verbosity < 2 { "info" } else { level };
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("{level},{target}={own}")));
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stdout)
+        .with_ansi(io::stdout().is_terminal())
+        .try_init();
+}
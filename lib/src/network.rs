@@ -1,15 +1,117 @@
+use crate::bloom::BloomFilter;
 use crate::crypto::PublicKey;
-use crate::types::{Block, Transaction, TransactionOutput};
+use crate::sha256::{Hash, checksum};
+use crate::types::{Block, BlockHeader, Transaction, TransactionOutput};
+use crate::utils::MerkleProof;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::io::{Error as IoError, Read, Write};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::ops::BitOr;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Optional protocol features a node supports, as bit flags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Services(pub u64);
+
+impl Services {
+    pub const NONE: Services = Services(0);
+    /// CompactBlock, GetBlockTxn and BlockTxn
+    pub const COMPACT_BLOCKS: Services = Services(1);
+    /// GetHeaders, Headers and FetchBlockByHash
+    pub const HEADERS_FIRST: Services = Services(1 << 1);
+    /// Inv, GetData and NotFound
+    pub const INVENTORY: Services = Services(1 << 2);
+    /// FilterLoad, FilterClear and FilteredBlock
+    pub const BLOOM: Services = Services(1 << 3);
+    /// Everything this version of the node supports
+    pub const ALL: Services = Services(0b1111);
+
+    pub fn contains(self, other: Services) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersection(self, other: Services) -> Services {
+        Services(self.0 & other.0)
+    }
+}
+
+impl BitOr for Services {
+    type Output = Services;
+
+    fn bitor(self, other: Services) -> Services {
+        Services(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PeerInfo {
+    pub address: String,
+    pub version: u32,
+    pub services: Services,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub misbehavior_score: u32,
+}
+
+/// What an announced hash refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum InvKind {
+    Tx,
+    Block,
+}
+
+/// A transaction or block identified by its hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct InvItem {
+    pub kind: InvKind,
+    pub hash: Hash,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum Message {
+    /// First message on every connection, identifying the
+    /// network, the sender's protocol version and services
+    /// and its block height
+    Version {
+        magic: [u8; 4],
+        #[serde(default)]
+        version: u32,
+        #[serde(default)]
+        services: Services,
+        block_height: u64,
+    },
     /// Fetch all UTXOs belonging to a public key
     FetchUTXOs(PublicKey),
     /// UTXOs belonging to a public key. Bool determines if marked
     UTXOs(Vec<(TransactionOutput, bool)>),
-    /// Send a transaction to the network
+    /// Send a transaction to the network, answered with
+    /// TransactionAccepted or TransactionRejected
     SubmitTransaction(Transaction),
+    /// This is the response to SubmitTransaction when the
+    /// transaction is in the mempool
+    TransactionAccepted(Hash),
+    /// This is the response to SubmitTransaction with the
+    /// reason the node refused the transaction
+    TransactionRejected { hash: Hash, reason: String },
+    /// Send a transaction as the hex of its canonical encoding,
+    /// answered like SubmitTransaction; the hash is zero if the
+    /// hex doesn't decode to a transaction
+    SendRawTransaction(String),
+    /// Ask a node for a transaction in its mempool or chain
+    GetRawTransaction(Hash),
+    /// This is the response to GetRawTransaction, the hex of
+    /// the transaction or None if the node doesn't know it
+    RawTransaction { hash: Hash, hex: Option<String> },
+    /// Ask a node how far a transaction is confirmed
+    GetTransactionStatus(Hash),
+    /// This is the response to GetTransactionStatus: None if
+    /// the node doesn't know the transaction, 0 while it is
+    /// in the mempool, else the number of confirmations
+    TransactionStatus {
+        hash: Hash,
+        confirmations: Option<u64>,
+    },
     /// Broadcast a new transaction to other nodes
     NewTransaction(Transaction),
     /// Ask the node to prepare the optimal block template
@@ -39,8 +141,78 @@ pub enum Message {
     Difference(i32),
     /// Ask a node to send a block with the specified height
     FetchBlock(usize),
+    /// Ask a node to send the block with the specified hash
+    FetchBlockByHash(Hash),
+    /// Ask a node for the headers following the first
+    /// locator hash it has in its chain
+    GetHeaders { locator: Vec<Hash> },
+    /// This is the response to GetHeaders, at most
+    /// MAX_HEADERS_PER_MESSAGE headers in chain order
+    Headers(Vec<BlockHeader>),
     /// Broadcast a new block to other nodes
     NewBlock(Block),
+    /// Ban a peer IP address for the specified number of seconds
+    SetBan(String, u64),
+    /// This is the response to a SetBan the node could not
+    /// carry out, an invalid address or too long a ban
+    BanRefused { address: String, reason: String },
+    /// Ask a node to report all the peer addresses it has banned
+    ListBanned,
+    /// This is the response to ListBanned, with ban expiry times
+    BannedList(Vec<(String, DateTime<Utc>)>),
+    /// Add a peer address to a node's address book as a
+    /// manual peer, which is dialed before any other
+    AddNode(String),
+    /// Ask a node to report its connected peers and
+    /// their bandwidth usage
+    GetPeerInfo,
+    /// This is the response to GetPeerInfo
+    PeerList(Vec<PeerInfo>),
+    /// Announce a new block by its header and transaction
+    /// hashes, so peers can rebuild it from their mempool
+    CompactBlock {
+        header: BlockHeader,
+        txids: Vec<Hash>,
+    },
+    /// Ask for the transactions of an announced compact block
+    /// (identified by its header hash) missing from the mempool
+    GetBlockTxn {
+        block_hash: Hash,
+        indexes: Vec<usize>,
+    },
+    /// This is the response to GetBlockTxn, in the requested order
+    BlockTxn {
+        block_hash: Hash,
+        transactions: Vec<Transaction>,
+    },
+    /// Announce transactions and blocks the sender has,
+    /// at most MAX_INV_ITEMS of them
+    Inv(Vec<InvItem>),
+    /// Ask for announced items, answered with NewTransaction
+    /// for transactions and CompactBlock for blocks
+    GetData(Vec<InvItem>),
+    /// This is the response to GetData for items the
+    /// sender doesn't have (anymore)
+    NotFound(Vec<InvItem>),
+    /// Ask a peer for its mempool, answered with Inv messages
+    /// of the transactions paying at least min_fee
+    GetMempool { min_fee: Option<u64> },
+    /// Have only the transactions matching the filter relayed,
+    /// blocks announced and sent for GetData as FilteredBlock
+    FilterLoad(BloomFilter),
+    /// Drop the loaded filter, relaying everything again
+    FilterClear,
+    /// A block for a peer with a filter loaded: its header and
+    /// the transactions matching the filter, each with its
+    /// proof against the header's merkle root
+    FilteredBlock {
+        header: BlockHeader,
+        transactions: Vec<(Transaction, MerkleProof)>,
+    },
+    /// Check that a peer is still alive, sent periodically
+    Ping(u64),
+    /// This is the response to Ping, echoing its nonce
+    Pong(u64),
 }
 
 impl Message {
@@ -52,19 +224,101 @@ impl Message {
     pub fn decode(data: &[u8]) -> Result<Self, ciborium::de::Error<IoError>> {
         ciborium::from_reader(data)
     }
-    pub fn send(&self, stream: &mut impl Write) -> Result<(), ciborium::ser::Error<IoError>> {
+    // services the sender must have advertised to send this
+    pub fn required_services(&self) -> Services {
+        use Message::*;
+        match self {
+            CompactBlock { .. } | GetBlockTxn { .. } | BlockTxn { .. } => Services::COMPACT_BLOCKS,
+            GetHeaders { .. } | Headers(_) | FetchBlockByHash(_) => Services::HEADERS_FIRST,
+            Inv(_) | GetData(_) | NotFound(_) | GetMempool { .. } => Services::INVENTORY,
+            FilterLoad(_) | FilterClear | FilteredBlock { .. } => Services::BLOOM,
+            _ => Services::NONE,
+        }
+    }
+    // frame: network magic, payload length, payload checksum, payload
+    pub fn frame(&self, magic: [u8; 4]) -> Result<Vec<u8>, ciborium::ser::Error<IoError>> {
         let bytes = self.encode()?;
-        let len = bytes.len() as u64;
-        stream.write_all(&len.to_be_bytes())?;
-        stream.write_all(&bytes)?;
+        let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + bytes.len());
+        frame.extend_from_slice(&magic);
+        frame.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+        frame.extend_from_slice(&checksum(&bytes));
+        frame.extend_from_slice(&bytes);
+        Ok(frame)
+    }
+    pub fn write_to(
+        &self,
+        magic: [u8; 4],
+        stream: &mut impl Write,
+    ) -> Result<(), ciborium::ser::Error<IoError>> {
+        stream.write_all(&self.frame(magic)?)?;
         Ok(())
     }
-    pub fn receive(stream: &mut impl Read) -> Result<Self, ciborium::de::Error<IoError>> {
-        let mut len_bytes = [0u8; 8];
-        stream.read_exact(&mut len_bytes)?;
-        let len = u64::from_be_bytes(len_bytes) as usize;
+    // read one frame, a wrong magic, an oversize length or a
+    // checksum mismatch fail with InvalidData before decoding
+    pub fn read_from(
+        magic: [u8; 4],
+        stream: &mut impl Read,
+    ) -> Result<Self, ciborium::de::Error<IoError>> {
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        stream.read_exact(&mut header)?;
+        let (len, expected_checksum) = parse_frame_header(magic, &header)?;
         let mut data = vec![0u8; len];
         stream.read_exact(&mut data)?;
-        Self::decode(&data)
+        Self::decode_checked(&data, expected_checksum)
+    }
+    #[cfg(feature = "async")]
+    pub async fn async_write_to(
+        &self,
+        magic: [u8; 4],
+        stream: &mut (impl AsyncWrite + Unpin),
+    ) -> Result<(), ciborium::ser::Error<IoError>> {
+        stream.write_all(&self.frame(magic)?).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+    #[cfg(feature = "async")]
+    pub async fn async_read_from(
+        magic: [u8; 4],
+        stream: &mut (impl AsyncRead + Unpin),
+    ) -> Result<Self, ciborium::de::Error<IoError>> {
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        stream.read_exact(&mut header).await?;
+        let (len, expected_checksum) = parse_frame_header(magic, &header)?;
+        let mut data = vec![0u8; len];
+        stream.read_exact(&mut data).await?;
+        Self::decode_checked(&data, expected_checksum)
+    }
+    fn decode_checked(
+        data: &[u8],
+        expected_checksum: [u8; 4],
+    ) -> Result<Self, ciborium::de::Error<IoError>> {
+        if checksum(data) != expected_checksum {
+            return Err(invalid_frame("message checksum mismatch"));
+        }
+        Self::decode(data)
+    }
+}
+
+// magic, payload length and payload checksum
+const FRAME_HEADER_SIZE: usize = 16;
+
+fn invalid_frame(reason: &str) -> ciborium::de::Error<IoError> {
+    ciborium::de::Error::Io(IoError::new(IoErrorKind::InvalidData, reason))
+}
+
+// payload length and checksum of a frame header
+fn parse_frame_header(
+    magic: [u8; 4],
+    header: &[u8; FRAME_HEADER_SIZE],
+) -> Result<(usize, [u8; 4]), ciborium::de::Error<IoError>> {
+    // refuse messages from other networks
+    if header[..4] != magic {
+        return Err(invalid_frame("network magic mismatch"));
+    }
+    let len = u64::from_be_bytes(header[4..12].try_into().unwrap());
+    // refuse to allocate for payloads above the limit
+    if len > crate::MAX_MESSAGE_SIZE {
+        return Err(invalid_frame("message exceeds maximum size"));
     }
+    Ok((len as usize, header[12..16].try_into().unwrap()))
 }
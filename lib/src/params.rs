@@ -0,0 +1,256 @@
+use crate::U256;
+use crate::sha256::Hash;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+impl Network {
+    pub fn params(&self) -> NetworkParams {
+        match self {
+            Network::Mainnet => NetworkParams::mainnet(),
+            Network::Testnet => NetworkParams::testnet(),
+            Network::Regtest => NetworkParams::regtest(),
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Network::Mainnet => write!(f, "mainnet"),
+            Network::Testnet => write!(f, "testnet"),
+            Network::Regtest => write!(f, "regtest"),
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "regtest" => Ok(Network::Regtest),
+            _ => Err(format!(
+                "unknown network {s}, expected mainnet|testnet|regtest"
+            )),
+        }
+    }
+}
+
+// rules and identity of a network; the rules are the crate
+// constants on mainnet and testnet, regtest mines at once and halves
+// sooner, chains with other rules are made with Blockchain::with_params
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct NetworkParams {
+    pub network: Network,
+    // prefixed to every network message so nodes of
+    // different networks can never talk to each other
+    pub magic: [u8; 4],
+    // easiest target a block may have, and the first target
+    pub min_target: U256,
+    // reward in coins, halved every halving_interval blocks
+    pub initial_reward: u64,
+    pub halving_interval: u64,
+    // seconds between blocks the target is adjusted towards,
+    // every difficulty_update_interval blocks
+    pub ideal_block_time: u64,
+    pub difficulty_update_interval: u64,
+    // seconds before a mempool transaction is dropped
+    pub max_mempool_transaction_age: u64,
+    // confirmations before a coinbase output counts as spendable
+    pub coinbase_maturity: u64,
+    // maximum amount of transactions in a block template
+    pub block_transaction_cap: usize,
+    // consensus changes rolled out by miners signaling for them; no
+    // network has had any, so chains saved before there were
+    // deployments load with the right ones
+    #[serde(default)]
+    pub deployments: Vec<Deployment>,
+    // utxo snapshots vouched for by the release, which a node may sync
+    // from when the header of their tip doesn't commit to the utxos
+    #[serde(default)]
+    pub trusted_snapshots: Vec<TrustedSnapshot>,
+}
+
+// the target of regtest blocks, half of all hashes meet it
+pub const REGTEST_MIN_TARGET: U256 = U256([u64::MAX, u64::MAX, u64::MAX, u64::MAX >> 1]);
+
+// the deployment that makes headers commit to the utxos after their
+// block; until it's active a header may leave its commitment zero
+pub const UTXO_COMMITMENT_DEPLOYMENT: &str = "utxo_commitment";
+
+// a consensus change miners signal for by setting bit of the header
+// version, tallied over each window of difficulty_update_interval
+// blocks: the first window from start_height counts, a window with
+// threshold signaling blocks locks it in, and it's active from the
+// window after; not locked in by timeout_height, it has failed
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Deployment {
+    // what validation asks Blockchain::deployment_state for
+    pub name: String,
+    pub bit: u8,
+    pub start_height: u64,
+    pub timeout_height: u64,
+    pub threshold: u64,
+}
+
+impl Deployment {
+    // the version bit, none for a bit past the version's 32
+    pub fn mask(&self) -> u32 {
+        1u32.checked_shl(self.bit.into()).unwrap_or(0)
+    }
+
+    pub fn signaled_by(&self, version: u32) -> bool {
+        version & self.mask() != 0
+    }
+}
+
+// the utxos of a chain height blocks long ending in the block tip,
+// as the commitment Blockchain::utxo_commitment gives for them
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct TrustedSnapshot {
+    pub height: u64,
+    pub tip: Hash,
+    pub utxo_commitment: Hash,
+}
+
+impl NetworkParams {
+    pub fn mainnet() -> Self {
+        NetworkParams {
+            network: Network::Mainnet,
+            magic: [0x53, 0x42, 0x44, 0x4d],
+            min_target: crate::MIN_TARGET,
+            initial_reward: crate::INITIAL_REWARD,
+            halving_interval: crate::HALVING_INTERVAL,
+            ideal_block_time: crate::IDEAL_BLOCK_TIME,
+            difficulty_update_interval: crate::DIFFICULTY_UPDATE_INTERVAL,
+            max_mempool_transaction_age: crate::MAX_MEMPOOL_TRANSACTION_AGE,
+            coinbase_maturity: crate::COINBASE_MATURITY,
+            block_transaction_cap: crate::BLOCK_TRANSACTION_CAP,
+            deployments: vec![],
+            trusted_snapshots: vec![],
+        }
+    }
+
+    pub fn testnet() -> Self {
+        NetworkParams {
+            network: Network::Testnet,
+            magic: [0x53, 0x42, 0x44, 0x54],
+            ..Self::mainnet()
+        }
+    }
+
+    // for tests on one machine: any block is mined in a few tries and
+    // the target never adjusts, as blocks come as fast as they're mined
+    pub fn regtest() -> Self {
+        NetworkParams {
+            network: Network::Regtest,
+            magic: [0x53, 0x42, 0x44, 0x52],
+            min_target: REGTEST_MIN_TARGET,
+            halving_interval: 150,
+            difficulty_update_interval: 0,
+            ..Self::mainnet()
+        }
+    }
+
+    // reward in base units of the block at height; a halving
+    // interval of 0 never halves
+    pub fn reward_at_height(&self, height: u64) -> u64 {
+        let halvings = height.checked_div(self.halving_interval).unwrap_or(0);
+        self.initial_reward
+            .saturating_mul(crate::UNITS_PER_COIN)
+            .checked_shr(u32::try_from(halvings).unwrap_or(u32::MAX))
+            .unwrap_or(0)
+    }
+
+    // the rewards of every block up to and including height, summed a
+    // halving at a time; past 63 halvings the reward is 0. saturates at
+    // u64::MAX, where a halving interval of 0 ends up on a long chain
+    pub fn cumulative_supply_at_height(&self, height: u64) -> u64 {
+        let initial = u128::from(self.reward_at_height(0));
+        let blocks = u128::from(height) + 1;
+        let supply = if self.halving_interval == 0 {
+            initial.saturating_mul(blocks)
+        } else {
+            let interval = u128::from(self.halving_interval);
+            let halvings = blocks / interval;
+            let full: u128 = (0..halvings.min(64))
+                .map(|halving| interval.saturating_mul(initial >> halving))
+                .fold(0, u128::saturating_add);
+            let reward = u32::try_from(halvings)
+                .ok()
+                .and_then(|halvings| initial.checked_shr(halvings))
+                .unwrap_or(0);
+            let rest = (blocks % interval).saturating_mul(reward);
+            full.saturating_add(rest)
+        };
+        u64::try_from(supply).unwrap_or(u64::MAX)
+    }
+
+    // every coin the schedule will ever pay out
+    pub fn max_supply(&self) -> u64 {
+        self.cumulative_supply_at_height(u64::MAX)
+    }
+
+    // the target after a window of blocks mined to target, the first
+    // stamped start and the last end: scaled by how long they took
+    // against the ideal, by no more than 4 times either way, and never
+    // easier than min_target
+    pub fn retarget(&self, target: U256, start: DateTime<Utc>, end: DateTime<Utc>) -> U256 {
+        // blocks added as trusted can go back in time,
+        // which counts as no time at all
+        let time_diff_seconds = (end - start).num_seconds().max(0);
+        // calculate the ideal number of seconds
+        let target_seconds = self
+            .ideal_block_time
+            .saturating_mul(self.difficulty_update_interval);
+
+        //multiply the current target with the actual time divided by the ideal time
+        let new_target = scale_target(target, time_diff_seconds as u64, target_seconds);
+
+        //clamp new target to be within the range of 4 * target and target / 4
+        let new_target = if new_target < target / 4 {
+            target / 4
+        } else if new_target > target.saturating_mul(4.into()) {
+            target.saturating_mul(4.into())
+        } else {
+            new_target
+        };
+
+        //if the new target is more than the minimum target, set it to the minimum target
+        new_target.min(self.min_target)
+    }
+}
+
+// target * actual / ideal rounded down, without overflowing past
+// U256::MAX; target is split at ideal, so the remainder times actual
+// fits. rounds like the decimal form it replaced for any ideal of
+// only 2s and 5s, such as the 500 seconds of every preset
+fn scale_target(target: U256, actual: u64, ideal: u64) -> U256 {
+    let ideal = U256::from(ideal.max(1));
+    let actual = U256::from(actual);
+    let (quotient, remainder) = target.div_mod(ideal);
+    quotient
+        .checked_mul(actual)
+        .and_then(|scaled| scaled.checked_add(remainder * actual / ideal))
+        .unwrap_or(U256::MAX)
+}
+
+impl Default for NetworkParams {
+    fn default() -> Self {
+        Self::mainnet()
+    }
+}
@@ -0,0 +1,200 @@
+// a stored chain's blocks added again from genesis to an empty chain
+// of the same params, through add_block as a node connects them, and
+// everywhere the stored chain differs from what that works out; run
+// over old chains, it shows a consensus change still accepts them
+use crate::U256;
+use crate::sha256::{Hash, HashKeyedMap};
+use crate::types::{Block, Blockchain};
+use serde::Serialize;
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Divergence {
+    // stored, but add_block rejects it; the blocks after it can't be
+    // replayed
+    Rejected {
+        height: u64,
+        hash: Hash,
+        reason: String,
+    },
+    // stored, but spent or never created
+    StaleUtxo {
+        hash: Hash,
+    },
+    // created and unspent, but not stored
+    MissingUtxo {
+        hash: Hash,
+        height: u64,
+        txid: Hash,
+    },
+    // stored, but not as the transaction created it
+    ChangedUtxo {
+        hash: Hash,
+        height: u64,
+        txid: Hash,
+    },
+    // of the next block
+    Target {
+        found: U256,
+        expected: U256,
+    },
+}
+
+impl Divergence {
+    // the block that diverges, if it's a block rather than the state
+    pub fn block_height(&self) -> Option<u64> {
+        match self {
+            Divergence::Rejected { height, .. } => Some(*height),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Divergence::Rejected {
+                height,
+                hash,
+                reason,
+            } => write!(f, "block {height} {}: {reason}", hash.to_hex()),
+            Divergence::StaleUtxo { hash } => {
+                write!(
+                    f,
+                    "utxo {}: stored, but spent or never created",
+                    hash.to_hex()
+                )
+            }
+            Divergence::MissingUtxo { hash, height, txid } => write!(
+                f,
+                "utxo {}: created at height {height} by {}, but not stored",
+                hash.to_hex(),
+                txid.to_hex()
+            ),
+            Divergence::ChangedUtxo { hash, height, txid } => write!(
+                f,
+                "utxo {}: stored, but not as {} at height {height} created it",
+                hash.to_hex(),
+                txid.to_hex()
+            ),
+            Divergence::Target { found, expected } => {
+                write!(f, "target {found:064x}, the schedule gives {expected:064x}")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Replay {
+    pub stored_height: u64,
+    pub replayed_height: u64,
+    // blocks from genesis accepted
+    pub valid_height: u64,
+    // the lowest height of a block that diverges
+    pub first_divergent_block: Option<u64>,
+    // the stored utxos and target are of the tip, so they're only
+    // compared when every block was replayed
+    pub state_compared: bool,
+    pub divergences: Vec<Divergence>,
+}
+
+impl Replay {
+    pub fn is_clean(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+// replay the stored blocks up to and including until, or all of them;
+// a chain imported from a utxo snapshot starts past genesis and is the
+// caller's to refuse
+pub fn replay(stored: &Blockchain, until: Option<u64>) -> Replay {
+    let mut replayed = Blockchain::with_params(stored.params().clone());
+    let mut divergences = vec![];
+    // the height and txid creating each output
+    let mut created: HashKeyedMap<(u64, Hash)> = HashKeyedMap::default();
+    let mut rejected = false;
+    for (height, block) in stored.blocks().enumerate() {
+        let height = height as u64;
+        if until.is_some_and(|until| height > until) {
+            break;
+        }
+        if let Err(e) = replayed.add_block(Block::clone(block)) {
+            divergences.push(Divergence::Rejected {
+                height,
+                hash: block.hash(),
+                reason: e.to_string(),
+            });
+            rejected = true;
+            break;
+        }
+        for transaction in &block.transactions {
+            for output in &transaction.outputs {
+                created.insert(output.hash(), (height, transaction.hash()));
+            }
+        }
+    }
+
+    let state_compared = !rejected && replayed.block_height() == stored.block_height();
+    if state_compared {
+        divergences.extend(utxo_divergences(stored, &replayed, &created));
+        if stored.target() != replayed.target() {
+            divergences.push(Divergence::Target {
+                found: stored.target(),
+                expected: replayed.target(),
+            });
+        }
+    }
+    let first_divergent_block = divergences
+        .iter()
+        .filter_map(Divergence::block_height)
+        .min();
+    Replay {
+        stored_height: stored.block_height(),
+        replayed_height: replayed.block_height(),
+        valid_height: first_divergent_block.unwrap_or(replayed.block_height()),
+        first_divergent_block,
+        state_compared,
+        divergences,
+    }
+}
+
+fn utxo_divergences(
+    stored: &Blockchain,
+    replayed: &Blockchain,
+    created: &HashKeyedMap<(u64, Hash)>,
+) -> Vec<Divergence> {
+    let mut divergences = vec![];
+    for (hash, output, _) in stored.utxos().iter() {
+        match replayed.utxo(hash) {
+            None => divergences.push(Divergence::StaleUtxo { hash: *hash }),
+            Some(replayed_output) if replayed_output != output || output.hash() != *hash => {
+                let (height, txid) = created[hash];
+                divergences.push(Divergence::ChangedUtxo {
+                    hash: *hash,
+                    height,
+                    txid,
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    for (hash, _, _) in replayed.utxos().iter() {
+        if !stored.contains_utxo(hash) {
+            let (height, txid) = created[hash];
+            divergences.push(Divergence::MissingUtxo {
+                hash: *hash,
+                height,
+                txid,
+            });
+        }
+    }
+    // the sets are maps, in no particular order
+    divergences.sort_by_key(|divergence| match divergence {
+        Divergence::StaleUtxo { hash }
+        | Divergence::MissingUtxo { hash, .. }
+        | Divergence::ChangedUtxo { hash, .. } => *hash,
+        _ => unreachable!("only utxo divergences are sorted"),
+    });
+    divergences
+}
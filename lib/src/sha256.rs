@@ -1,46 +1,154 @@
 use crate::U256;
-use serde::{Deserialize, Serialize};
+use crate::canonical::Canonical;
+use crate::error::SbdError;
+use rustc_hash::FxBuildHasher;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use sha256::digest;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
 
-#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
-
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Hash(U256);
 
+// same wire form as U256 wrapped in a newtype, and the
+// Display hex in human readable formats
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Hash")]
+struct RawHash(U256);
+
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&format!("{:064x}", self.0))
+        } else {
+            RawHash(self.0).serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let hex = String::deserialize(deserializer)?;
+            Hash::from_hex(&hex).ok_or_else(|| D::Error::custom(format!("invalid hash {hex}")))
+        } else {
+            Ok(Hash(RawHash::deserialize(deserializer)?.0))
+        }
+    }
+}
+
+// the hasher of every map and set keyed by Hash, swapped here for
+// all of them. the keys are sha256 outputs, spread evenly already, so
+// fx only has to fold them rather than mix them as SipHash does. what
+// SipHash's random key guards against, many keys in one bucket, would
+// take hashes chosen for their low bits, and each one costs a valid
+// signature over an output, proof of work for a block, or a fee to
+// stay in the mempool
+pub type HashKeyedState = FxBuildHasher;
+pub type HashKeyedMap<V> = HashMap<Hash, V, HashKeyedState>;
+pub type HashKeyedSet = HashSet<Hash, HashKeyedState>;
+
+impl Canonical for Hash {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+    }
+}
+
 impl fmt::Display for Hash {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:x}", self.0)
     }
 }
 
+impl FromStr for Hash {
+    type Err = SbdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Hash::from_hex(s).ok_or(SbdError::InvalidHash)
+    }
+}
+
+// first four bytes of the sha256 of raw bytes, used
+// to detect corrupted network messages
+pub fn checksum(data: &[u8]) -> [u8; 4] {
+    let hash_bytes = hex::decode(digest(data)).unwrap();
+    [hash_bytes[0], hash_bytes[1], hash_bytes[2], hash_bytes[3]]
+}
+
+// the number the hash is, for sums of hashes
+impl From<Hash> for U256 {
+    fn from(hash: Hash) -> Self {
+        hash.0
+    }
+}
+
 impl From<[u8; 32]> for U256 {
     fn from(arr: [u8; 32]) -> Self {
         U256::from_big_endian(&arr)
     }
 }
 
+// the most a thread's hash buffer keeps between hashes; after
+// encoding something larger, like a big block, it is freed instead
+const MAX_HASH_BUFFER: usize = 64 * 1024;
+
+thread_local! {
+    static HASH_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
 impl Hash {
-    //hash anything that can be serde serialized via ciborium
-    pub fn hash<T: serde::Serialize>(data: &T) -> Self {
-        let mut serialized: Vec<u8> = vec![];
-        if let Err(e) = ciborium::into_writer(data, &mut serialized) {
-            panic!(
-                "Failed to serialize data: {:?}. \
-                This should not happen",
-                e
-            );
-        }
-        let hash = digest(&serialized);
-        let hash_bytes = hex::decode(hash).unwrap();
+    // hash the canonical encoding, see crate::canonical, written
+    // into a buffer each thread keeps for it
+    #[allow(clippy::self_named_constructors)]
+    pub fn hash<T: Canonical + ?Sized>(data: &T) -> Self {
+        HASH_BUFFER.with(|buffer| match buffer.try_borrow_mut() {
+            Ok(mut buffer) => {
+                let hash = Self::hash_with_buf(data, &mut buffer);
+                if buffer.capacity() > MAX_HASH_BUFFER {
+                    *buffer = Vec::new();
+                }
+                hash
+            }
+            // an encode hashing something itself
+            Err(_) => Self::hash_with_buf(data, &mut Vec::new()),
+        })
+    }
+
+    // hash the canonical encoding written into buf, which is
+    // cleared first and left holding the encoding
+    pub fn hash_with_buf<T: Canonical + ?Sized>(data: &T, buf: &mut Vec<u8>) -> Self {
+        buf.clear();
+        data.encode(buf);
+        Self::hash_bytes(buf)
+    }
+
+    // hash raw bytes as they are
+    pub fn hash_bytes(data: &[u8]) -> Self {
+        let hash_bytes = hex::decode(digest(data)).unwrap();
         let hash_array: [u8; 32] = hash_bytes.as_slice().try_into().unwrap();
         Hash(U256::from(hash_array))
     }
 
-    // convert to bytes
+    // the 32 bytes of the digest, in the order hash_bytes read them
     pub fn as_bytes(&self) -> [u8; 32] {
-        let bytes: Vec<u8> = vec![0; 32];
-        self.0.to_little_endian();
-        bytes.as_slice().try_into().unwrap()
+        self.0.to_big_endian()
+    }
+
+    // all 64 digits, Display leaves out leading zeros
+    pub fn to_hex(&self) -> String {
+        format!("{:064x}", self.0)
+    }
+
+    // parse the hex form Display or to_hex prints
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.is_empty() || hex.len() > 64 {
+            return None;
+        }
+        U256::from_str_radix(hex, 16).ok().map(Hash)
     }
 
     //check if a hash matches a target
@@ -53,3 +161,57 @@ impl Hash {
         Hash(U256::zero())
     }
 }
+
+// the hash of a value worked out once, by freeze, and kept from
+// then on; a value must not change after it's frozen, which debug
+// builds check on every read. the cache isn't part of the value:
+// it's equal, hashed and serialized as if it weren't there
+#[derive(Clone, Debug, Default)]
+pub(crate) struct HashCache(OnceLock<Hash>);
+
+impl HashCache {
+    pub(crate) fn get<T: Canonical + ?Sized>(&self, value: &T) -> Hash {
+        match self.0.get() {
+            Some(&hash) => {
+                debug_assert_eq!(hash, Hash::hash(value), "BUG: changed after freeze");
+                hash
+            }
+            None => Hash::hash(value),
+        }
+    }
+
+    pub(crate) fn freeze<T: Canonical + ?Sized>(&self, value: &T) {
+        self.0.get_or_init(|| Hash::hash(value));
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.0 = OnceLock::new();
+    }
+}
+
+impl PartialEq for HashCache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for HashCache {}
+
+impl std::hash::Hash for HashCache {
+    fn hash<H: std::hash::Hasher>(&self, _: &mut H) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn as_bytes_are_the_digest() {
+        let hash = Hash::hash_bytes(b"abc");
+        assert_eq!(
+            hex::encode(hash.as_bytes()),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_ne!(hash.as_bytes(), Hash::hash_bytes(b"abd").as_bytes());
+    }
+}
@@ -0,0 +1,400 @@
+// deterministic chains for tests, benches and fixtures: keys derived
+// from a seed, blocks mined at once on a target half of all hashes
+// meet, and spends between the keys, so the same calls always build
+// the same bytes. other crates of the workspace get it with
+//
+// lib = { path = "../lib", features = ["test-utils"] }
+//
+// under [dev-dependencies]
+use crate::U256;
+use crate::crypto::{PrivateKey, PublicKey};
+use crate::error::{Result, SbdError};
+use crate::params::{NetworkParams, REGTEST_MIN_TARGET};
+use crate::sha256::Hash;
+use crate::types::{Block, Blockchain, PartiallySignedTransaction, Transaction, TransactionOutput};
+use crate::utils::MerkleRoot;
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+// a block needs two tries on average, and one that misses it is
+// easy to find for a block with bad proof of work
+pub const INSTANT_TARGET: U256 = REGTEST_MIN_TARGET;
+// keys a builder starts with
+pub const DEFAULT_KEYS: usize = 8;
+// when the first block of every builder is mined
+const START: &str = "2024-01-01T00:00:00Z";
+
+// regtest, whose target is INSTANT_TARGET, adjusting it as mainnet
+// does and with coinbases the next block may spend, so tests can
+// spend what they fund at once
+pub fn instant_params() -> NetworkParams {
+    NetworkParams {
+        difficulty_update_interval: crate::DIFFICULTY_UPDATE_INTERVAL,
+        coinbase_maturity: 1,
+        ..NetworkParams::regtest()
+    }
+}
+
+// the index-th key of a seed
+pub fn seeded_key(seed: u64, index: usize) -> PrivateKey {
+    let secret = Hash::hash_bytes(format!("{seed}/key/{index}").as_bytes());
+    PrivateKey::from_hex(&secret.to_hex()).expect("BUG: seed gives no valid key")
+}
+
+// the next block before it is mined: the coinbase pays out payouts,
+// then whatever is left of the reward and fees to the miner key. the
+// version signals as Blockchain::block_version, unless changed
+pub struct BlockTemplate {
+    pub version: u32,
+    pub timestamp: DateTime<Utc>,
+    pub miner: usize,
+    pub payouts: Vec<TransactionOutput>,
+    pub transactions: Vec<Transaction>,
+}
+
+// what invalid_block gets wrong, for tests that a block is rejected
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Defect {
+    // the header commits to other transactions
+    MerkleRoot,
+    // the hash misses the target
+    ProofOfWork,
+    // a transaction pays out more than it spends
+    Overspend,
+    // the header commits to other utxos than the block leaves
+    UtxoCommitment,
+}
+
+// a Blockchain grown a block at a time from seeded keys; outputs get
+// unique ids from a counter, so they depend on the order of the calls
+// and nothing else
+pub struct ChainBuilder {
+    chain: Blockchain,
+    seed: u64,
+    keys: Vec<PrivateKey>,
+    // of the keys, as deriving them is slow
+    pubkeys: Vec<PublicKey>,
+    next_id: u128,
+    start: DateTime<Utc>,
+}
+
+impl ChainBuilder {
+    pub fn new(seed: u64) -> Self {
+        Self::with_params(seed, instant_params())
+    }
+
+    // a chain of other rules, mining slower if their target is harder
+    pub fn with_params(seed: u64, params: NetworkParams) -> Self {
+        ChainBuilder {
+            chain: Blockchain::with_params(params),
+            seed,
+            keys: vec![],
+            pubkeys: vec![],
+            next_id: 0,
+            start: START.parse().expect("BUG: invalid start time"),
+        }
+        .with_keys(DEFAULT_KEYS)
+    }
+
+    // count keys instead of DEFAULT_KEYS
+    pub fn with_keys(mut self, count: usize) -> Self {
+        self.keys = (0..count)
+            .map(|index| seeded_key(self.seed, index))
+            .collect();
+        self.pubkeys = self.keys.iter().map(PrivateKey::public_key).collect();
+        self
+    }
+
+    pub fn key(&self, index: usize) -> &PrivateKey {
+        &self.keys[index]
+    }
+
+    pub fn keys(&self) -> &[PrivateKey] {
+        &self.keys
+    }
+
+    pub fn chain(&self) -> &Blockchain {
+        &self.chain
+    }
+
+    pub fn chain_mut(&mut self) -> &mut Blockchain {
+        &mut self.chain
+    }
+
+    pub fn into_chain(self) -> Blockchain {
+        self.chain
+    }
+
+    // the time of the block at height, spaced as the target wants
+    pub fn timestamp(&self, height: u64) -> DateTime<Utc> {
+        let spacing = self.chain.params().ideal_block_time as i64;
+        self.start + Duration::seconds(spacing.saturating_mul(height as i64))
+    }
+
+    // an output of value to a key, with the next unique id
+    pub fn output(&mut self, value: u64, key: usize) -> TransactionOutput {
+        self.next_id += 1;
+        TransactionOutput {
+            value,
+            unique_id: Uuid::from_u128(self.next_id),
+            pubkey: self.pubkeys[key].clone(),
+            spendable_after_height: None,
+        }
+    }
+
+    // unspent outputs of a key in the chain, not reserved by the
+    // mempool, the largest first
+    pub fn spendable(&self, key: usize) -> Vec<(Hash, TransactionOutput)> {
+        let pubkey = &self.pubkeys[key];
+        let mut outputs: Vec<(Hash, TransactionOutput)> = self
+            .chain
+            .utxos()
+            .iter()
+            .filter(|(_, output, reserved)| !reserved && output.pubkey == *pubkey)
+            .map(|(hash, output, _)| (*hash, output.clone()))
+            .collect();
+        outputs.sort_by_key(|(hash, output)| (std::cmp::Reverse(output.value), *hash));
+        outputs
+    }
+
+    // a transaction spending the outputs, each input signed by the
+    // key that owns the output it spends
+    pub fn sign(&self, spent: &[Hash], outputs: Vec<TransactionOutput>) -> Result<Transaction> {
+        let prev_outputs = spent
+            .iter()
+            .map(|hash| {
+                let output = self.chain.utxo(hash).ok_or(SbdError::MissingInput(*hash))?;
+                if !self.pubkeys.contains(&output.pubkey) {
+                    return Err(SbdError::InvalidPrivateKey);
+                }
+                Ok((*hash, output.clone()))
+            })
+            .collect::<Result<_>>()?;
+        let mut unsigned = PartiallySignedTransaction::new(prev_outputs, outputs);
+        for key in &self.keys {
+            unsigned.sign(key)?;
+        }
+        unsigned.finalize()
+    }
+
+    // amount from one key to another, from the largest outputs of the
+    // sender first with the change back to it; neither mined nor
+    // added to the mempool
+    pub fn spend(&mut self, from: usize, to: usize, amount: u64, fee: u64) -> Result<Transaction> {
+        let needed = amount
+            .checked_add(fee)
+            .ok_or(SbdError::InvalidTransactionOutput)?;
+        let mut spent = vec![];
+        let mut total = 0u64;
+        for (hash, output) in self.spendable(from) {
+            if total >= needed {
+                break;
+            }
+            spent.push(hash);
+            total = total.saturating_add(output.value);
+        }
+        if total < needed {
+            return Err(SbdError::OutputsExceedInputs {
+                inputs: total,
+                outputs: needed,
+            });
+        }
+        let mut outputs = vec![self.output(amount, to)];
+        if total > needed {
+            outputs.push(self.output(total - needed, from));
+        }
+        self.sign(&spent, outputs)
+    }
+
+    // the next block mined but not added, as build leaves its
+    // template; its coinbase claims the fees of the transactions
+    pub fn block(&mut self, build: impl FnOnce(&mut BlockTemplate)) -> Result<Block> {
+        let mut template = BlockTemplate {
+            version: self.chain.block_version(),
+            timestamp: self.timestamp(self.chain.block_height()),
+            miner: 0,
+            payouts: vec![],
+            transactions: vec![],
+        };
+        build(&mut template);
+        let fees = self.fees(&template.transactions)?;
+        let coinbase = self.coinbase(&template, fees)?;
+        let mut transactions = vec![coinbase];
+        transactions.extend(template.transactions);
+        self.mined(template.version, template.timestamp, transactions)
+    }
+
+    // a block of the next height a test changed, its header committed
+    // again to its transactions and the utxos they leave, and mined
+    // anew; the target and timestamp are left as the test set them
+    pub fn reseal(&self, block: &mut Block) {
+        for transaction in &mut block.transactions {
+            transaction.thaw();
+        }
+        block.header.merkle_root = MerkleRoot::calculate(&block.transactions);
+        block.header.utxo_commitment = self.chain.utxo_commitment_after(&block.transactions);
+        block.header.thaw();
+        while !block.header.mine(1_000_000) {}
+    }
+
+    // the next block, added to the chain
+    pub fn mine_block(&mut self, build: impl FnOnce(&mut BlockTemplate)) -> Result<Block> {
+        let block = self.block(build)?;
+        self.chain.add_block(block.clone())?;
+        Ok(block)
+    }
+
+    // count blocks paying only their reward to the first key
+    pub fn mine_blocks(&mut self, count: u64) -> Result<()> {
+        for _ in 0..count {
+            self.mine_block(|_| {})?;
+        }
+        Ok(())
+    }
+
+    // a block whose coinbase pays a key an output of each value,
+    // which must add up to no more than the reward
+    pub fn fund(&mut self, key: usize, values: &[u64]) -> Result<Vec<TransactionOutput>> {
+        let payouts: Vec<TransactionOutput> = values
+            .iter()
+            .map(|&value| self.output(value, key))
+            .collect();
+        let funded = payouts.clone();
+        self.mine_block(|template| template.payouts = payouts)?;
+        Ok(funded)
+    }
+
+    // the next block with the defect, mined but not added
+    pub fn invalid_block(&mut self, defect: Defect) -> Result<Block> {
+        match defect {
+            Defect::MerkleRoot => {
+                let mut block = self.block(|_| {})?;
+                let coinbase = &mut block.transactions[0];
+                coinbase.outputs[0].value = coinbase.outputs[0].value.wrapping_add(1);
+                coinbase.thaw();
+                Ok(block)
+            }
+            Defect::ProofOfWork => {
+                let mut block = self.block(|_| {})?;
+                while block.header.hash().matches_target(block.header.target) {
+                    block.header.nonce = block.header.nonce.wrapping_add(1);
+                    block.header.thaw();
+                }
+                Ok(block)
+            }
+            Defect::Overspend => self.overspending_block(),
+            Defect::UtxoCommitment => {
+                let mut block = self.block(|_| {})?;
+                block.header.utxo_commitment = self.chain.utxo_commitment();
+                while !block.header.mine(1_000_000) {}
+                Ok(block)
+            }
+        }
+    }
+
+    // the largest output of any key spent to one more than its value
+    fn overspending_block(&mut self) -> Result<Block> {
+        let (key, hash, value) = (0..self.keys.len())
+            .filter_map(|key| {
+                let (hash, output) = self.spendable(key).into_iter().next()?;
+                Some((key, hash, output.value))
+            })
+            .max_by_key(|&(_, hash, value)| (value, hash))
+            .ok_or(SbdError::InvalidTransactionInput)?;
+        let overspent = self.output(value.saturating_add(1), key);
+        let transaction = self.sign(&[hash], vec![overspent])?;
+        let reward = self.output(self.chain.calculate_block_reward(), 0);
+        let timestamp = self.timestamp(self.chain.block_height());
+        self.mined(
+            self.chain.block_version(),
+            timestamp,
+            vec![Transaction::new(vec![], vec![reward]), transaction],
+        )
+    }
+
+    // what the transactions pay in fees, each against the chain's
+    // utxos and the outputs of those before it
+    fn fees(&self, transactions: &[Transaction]) -> Result<u64> {
+        let mut created: Vec<(Hash, u64)> = vec![];
+        let mut fees = 0u64;
+        for transaction in transactions {
+            let mut inputs = 0u64;
+            for input in &transaction.inputs {
+                let hash = input.prev_transaction_output_hash;
+                let value = match self.chain.utxo(&hash) {
+                    Some(output) => output.value,
+                    None => created
+                        .iter()
+                        .find(|(created, _)| *created == hash)
+                        .map(|(_, value)| *value)
+                        .ok_or(SbdError::MissingInput(hash))?,
+                };
+                inputs = inputs
+                    .checked_add(value)
+                    .ok_or(SbdError::InvalidTransactionInput)?;
+            }
+            let outputs = transaction
+                .outputs
+                .iter()
+                .try_fold(0u64, |sum, output| sum.checked_add(output.value))
+                .ok_or(SbdError::InvalidTransactionOutput)?;
+            let fee = inputs
+                .checked_sub(outputs)
+                .ok_or(SbdError::OutputsExceedInputs { inputs, outputs })?;
+            fees = fees
+                .checked_add(fee)
+                .ok_or(SbdError::InvalidTransactionInput)?;
+            created.extend(
+                transaction
+                    .outputs
+                    .iter()
+                    .map(|output| (output.hash(), output.value)),
+            );
+        }
+        Ok(fees)
+    }
+
+    fn coinbase(&mut self, template: &BlockTemplate, fees: u64) -> Result<Transaction> {
+        let claimed = self
+            .chain
+            .params()
+            .reward_at_height(self.chain.block_height())
+            .checked_add(fees)
+            .ok_or(SbdError::InvalidTransactionOutput)?;
+        let paid_out = template
+            .payouts
+            .iter()
+            .try_fold(0u64, |sum, output| sum.checked_add(output.value))
+            .ok_or(SbdError::InvalidTransactionOutput)?;
+        let rest = claimed
+            .checked_sub(paid_out)
+            .ok_or(SbdError::OutputsExceedInputs {
+                inputs: claimed,
+                outputs: paid_out,
+            })?;
+        let mut outputs = template.payouts.clone();
+        // a coinbase needs an output, even if the reward has run out
+        if rest > 0 || outputs.is_empty() {
+            outputs.push(self.output(rest, template.miner));
+        }
+        Ok(Transaction::new(vec![], outputs))
+    }
+
+    fn mined(
+        &self,
+        version: u32,
+        timestamp: DateTime<Utc>,
+        transactions: Vec<Transaction>,
+    ) -> Result<Block> {
+        let mut block = Block::builder()
+            .version(version)
+            .timestamp(timestamp)
+            .prev_hash(self.chain.blocks().last().map_or(Hash::zero(), Block::hash))
+            .target(self.chain.target())
+            .utxo_commitment(self.chain.utxo_commitment_after(&transactions))
+            .transactions(transactions)
+            .build()?;
+        while !block.header.mine(1_000_000) {}
+        Ok(block)
+    }
+}
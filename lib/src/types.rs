@@ -1,7 +1,50 @@
+//! A Blockchain's state is its own: blocks, utxos and mempool change
+//! only through its methods, which keep them consistent, so a field
+//! can't be reached from outside
+//!
+//! ```compile_fail,E0616
+//! let chain = lib::types::Blockchain::new();
+//! let _ = chain.utxos.len();
+//! ```
+//!
+//! ```
+//! let chain = lib::types::Blockchain::new();
+//! assert_eq!(chain.utxos().len(), 0);
+//! ```
 mod block;
 mod blockchain;
+mod checkpoint;
+mod deployment;
+mod headers;
+mod mempool;
+#[cfg(feature = "store-sled")]
+mod sled_store;
+mod snapshot;
+mod store;
 mod transaction;
+mod watch;
 
-pub use block::{Block, BlockHeader};
-pub use blockchain::Blockchain;
-pub use transaction::{Transaction, TransactionInput, TransactionOutput};
+pub use block::{Block, BlockBuilder, BlockHeader, BlockHeaderBuilder};
+pub use blockchain::{
+    AddressStats, Blockchain, ChainSnapshot, FeeBucket, FeeSummary, ImportReport, UtxoDelta,
+    UtxoView,
+};
+pub use checkpoint::{CheckpointBundle, SignedCheckpoint};
+pub use deployment::DeploymentState;
+pub use headers::{HeaderChain, work};
+#[cfg(feature = "store-sled")]
+pub use sled_store::SledStore;
+pub use snapshot::{
+    SnapshotBase, SnapshotEntry, SnapshotHeader, UtxoCommitment, read_snapshot, write_snapshot,
+};
+#[cfg(feature = "std-fs")]
+pub use store::BlockStore;
+pub use store::{
+    ChainBatch, ChainStore, ChainTip, MANIFEST_INTERVAL, Manifest, MemoryStore, UtxoChange,
+    read_block_record,
+};
+pub use transaction::{
+    MempoolEntry, MempoolSnapshot, PartialInput, PartiallySignedTransaction, Transaction,
+    TransactionInput, TransactionOutput,
+};
+pub use watch::{ChainEvent, ConflictLocation};
@@ -1,15 +1,18 @@
-use super::{Transaction, TransactionOutput};
+use super::{Transaction, TransactionInput, UtxoView};
 use crate::U256;
+use crate::canonical::{self, Canonical, encode_struct};
+use crate::crypto::PublicKey;
 use crate::error::{Result, SbdError};
-use crate::sha256::Hash;
+use crate::params::NetworkParams;
+use crate::sha256::{Hash, HashCache, HashKeyedMap, HashKeyedSet};
 use crate::utils::MerkleRoot;
 use crate::utils::Saveable;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult, Write};
+use std::fmt;
+use tracing::{debug, debug_span};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
 pub struct Block {
     pub header: BlockHeader,
     pub transactions: Vec<Transaction>,
@@ -23,133 +26,294 @@ impl Block {
         }
     }
 
+    pub fn builder() -> BlockBuilder {
+        BlockBuilder::default()
+    }
+
+    // a block is identified by its header, which commits
+    // to the transactions through the merkle root, so header
+    // chains link up before any body arrives. it was the hash of
+    // the whole block before headers-first sync; chains saved then
+    // are refused on load, see Blockchain::migrate
     pub fn hash(&self) -> Hash {
-        Hash::hash(self)
+        self.header.hash()
+    }
+
+    // freeze the header and every transaction, for a block that
+    // won't change again, as Transaction::freeze
+    pub fn freeze(&self) {
+        self.header.freeze();
+        for transaction in &self.transactions {
+            transaction.freeze();
+        }
+    }
+
+    // hex of the canonical encoding, the raw block
+    pub fn to_hex(&self) -> String {
+        canonical::to_hex(self)
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        canonical::from_hex(hex, "block")
     }
 
+    // coinbase_heights holds the heights of the coinbase outputs the
+    // block might spend, at least those of the last coinbase_maturity
+    // blocks, as Blockchain::immature_coinbases gives them
     pub fn verify_transactions(
         &self,
+        params: &NetworkParams,
         predicted_block_height: u64,
-        utxos: &HashMap<Hash, (bool, TransactionOutput)>,
+        utxos: UtxoView<'_>,
+        coinbase_heights: &HashKeyedMap<u64>,
     ) -> Result<()> {
-        let mut inputs: HashMap<Hash, TransactionOutput> = HashMap::new();
+        let _span = debug_span!(
+            "verify_transactions",
+            transactions = self.transactions.len()
+        )
+        .entered();
         // reject completely empty blocks
         if self.transactions.is_empty() {
             return Err(SbdError::InvalidTransaction);
         }
-
-        // verify coinbase transaction
-        self.verify_coinbase_transaction(predicted_block_height, utxos)?;
-        for transaction in self.transactions.iter().skip(1) {
-            let mut input_value = 0;
-            let mut output_value = 0;
-            for input in &transaction.inputs {
-                let prev_output = utxos
-                    .get(&input.prev_transaction_output_hash)
-                    .map(|(_, output)| output);
-                if prev_output.is_none() {
-                    return Err(SbdError::InvalidTransaction);
-                }
-
-                let prev_output = prev_output.unwrap();
-                // prevent same-block double-spending
-                if inputs.contains_key(&input.prev_transaction_output_hash) {
-                    return Err(SbdError::InvalidTransaction);
-                }
-                // check if the signature is valid
-                if !input
-                    .signature
-                    .verify(&input.prev_transaction_output_hash, &prev_output.pubkey)
-                {
-                    return Err(SbdError::InvalidSignature);
-                }
-                input_value += prev_output.value;
-                inputs.insert(input.prev_transaction_output_hash, prev_output.clone());
+        let coinbase = self.coinbase()?;
+        // one walk over the spends gives the fees the coinbase
+        // may claim and the signatures to check
+        let spends = self.spends(utxos)?;
+        verify_coinbase_value(params, predicted_block_height, coinbase, spends.fees)?;
+        // expired transactions can't be included from their expiry height on
+        for transaction in &self.transactions {
+            if let Some(expires_at_height) = transaction.expires_at_height
+                && transaction.is_expired_at(predicted_block_height)
+            {
+                debug!(txid = %transaction.hash(), expires_at_height, "includes an expired transaction");
+                return Err(SbdError::ExpiredTransaction {
+                    txid: transaction.hash(),
+                    expires_at_height,
+                });
             }
-            for output in &transaction.outputs {
-                output_value += output.value;
+        }
+        // locked outputs wait for a block at their unlock height
+        for input in self.transactions.iter().skip(1).flat_map(|tx| &tx.inputs) {
+            let hash = input.prev_transaction_output_hash;
+            match utxos
+                .get(&hash)
+                .and_then(|output| output.spendable_after_height)
+            {
+                Some(unlock_height) if predicted_block_height < unlock_height => {
+                    debug!(input = %hash, unlock_height, "spends a locked output");
+                    return Err(SbdError::LockedOutput {
+                        hash,
+                        unlock_height,
+                    });
+                }
+                _ => {}
             }
-            // It is fine for output value to be less than input value
-            // as the difference is the fee for the miner
-            if input_value < output_value {
-                return Err(SbdError::InvalidTransaction);
+        }
+        // coinbase outputs wait coinbase_maturity blocks
+        for input in self.transactions.iter().skip(1).flat_map(|tx| &tx.inputs) {
+            let hash = input.prev_transaction_output_hash;
+            if let Some(&coinbase_height) = coinbase_heights.get(&hash)
+                && predicted_block_height.saturating_sub(coinbase_height) < params.coinbase_maturity
+            {
+                let mature_height = coinbase_height.saturating_add(params.coinbase_maturity);
+                debug!(input = %hash, mature_height, "spends an immature coinbase");
+                return Err(SbdError::ImmatureCoinbase {
+                    hash,
+                    mature_height,
+                });
             }
         }
+        // check if the signatures are valid
+        if let Some(index) = first_bad_signature(&spends.signatures) {
+            let (transaction, input, _) = spends.signatures[index];
+            debug!(
+                txid = %transaction.hash(),
+                input = %input.prev_transaction_output_hash,
+                "bad signature"
+            );
+            return Err(SbdError::InvalidSignature);
+        }
         Ok(())
     }
 
     pub fn verify_coinbase_transaction(
         &self,
+        params: &NetworkParams,
         predicted_block_height: u64,
-        utxos: &HashMap<Hash, (bool, TransactionOutput)>,
+        utxos: UtxoView<'_>,
     ) -> Result<()> {
-        // coinbase tx is the first transaction in the block
-        let coinbase_transaction = &self.transactions[0];
-        if coinbase_transaction.inputs.len() != 0 {
+        let coinbase = self.coinbase()?;
+        let miner_fees = self.calculate_miner_fees(utxos)?;
+        verify_coinbase_value(params, predicted_block_height, coinbase, miner_fees)
+    }
+
+    pub fn calculate_miner_fees(&self, utxos: UtxoView<'_>) -> Result<u64> {
+        Ok(self.spends(utxos)?.fees)
+    }
+
+    // coinbase tx is the first transaction in the block
+    fn coinbase(&self) -> Result<&Transaction> {
+        let Some(coinbase_transaction) = self.transactions.first() else {
             return Err(SbdError::InvalidTransaction);
-        }
-        if coinbase_transaction.outputs.len() == 0 {
+        };
+        if !coinbase_transaction.inputs.is_empty() {
             return Err(SbdError::InvalidTransaction);
         }
-        let miner_fees = self.calculate_miner_fees(utxos)?;
-        let block_reward = crate::INITIAL_REWARD * 10u64.pow(8)
-            / 2u64.pow((predicted_block_height / crate::HALVING_INTERVAL) as u32);
-        let total_coinbase_outputs: u64 = coinbase_transaction
-            .outputs
-            .iter()
-            .map(|output| output.value)
-            .sum();
-        if total_coinbase_outputs != block_reward + miner_fees {
+        if coinbase_transaction.outputs.is_empty() {
             return Err(SbdError::InvalidTransaction);
         }
-        Ok(())
+        Ok(coinbase_transaction)
     }
 
-    pub fn calculate_miner_fees(
-        &self,
-        utxos: &HashMap<Hash, (bool, TransactionOutput)>,
-    ) -> Result<u64> {
-        let mut inputs: HashMap<Hash, TransactionOutput> = HashMap::new();
-        let mut outputs: HashMap<Hash, TransactionOutput> = HashMap::new();
+    // spends are checked in block order, as each one is against
+    // those before it; the signatures wait until all of them pass
+    fn spends<'a>(&'a self, utxos: UtxoView<'a>) -> Result<Spends<'a>> {
+        // outputs spent and created so far in the block
+        let mut inputs = HashKeyedSet::default();
+        let mut outputs = HashKeyedSet::default();
+        let mut spends = Spends {
+            fees: 0,
+            signatures: Vec::new(),
+        };
         // Check every transaction after coinbase
         for transaction in self.transactions.iter().skip(1) {
+            let mut input_value = 0u64;
             for input in &transaction.inputs {
-                // inputs do not contain
-                // the values of the outputs
-                // so we need to match inputs
-                // to outputs
-                let prev_output = utxos
-                    .get(&input.prev_transaction_output_hash)
-                    .map(|(_, output)| output);
-                if prev_output.is_none() {
+                let prev_output = utxos.get(&input.prev_transaction_output_hash);
+                let Some(prev_output) = prev_output else {
+                    debug!(
+                        txid = %transaction.hash(),
+                        input = %input.prev_transaction_output_hash,
+                        "spends an unknown output"
+                    );
                     return Err(SbdError::InvalidTransaction);
-                }
-                let prev_output = prev_output.unwrap();
-                if inputs.contains_key(&input.prev_transaction_output_hash) {
+                };
+                // prevent same-block double-spending
+                if !inputs.insert(input.prev_transaction_output_hash) {
+                    debug!(
+                        txid = %transaction.hash(),
+                        input = %input.prev_transaction_output_hash,
+                        "spends an output already spent in the block"
+                    );
                     return Err(SbdError::InvalidTransaction);
                 }
-                inputs.insert(input.prev_transaction_output_hash, prev_output.clone());
+                spends
+                    .signatures
+                    .push((transaction, input, &prev_output.pubkey));
+                input_value = input_value
+                    .checked_add(prev_output.value)
+                    .ok_or(SbdError::InvalidTransactionInput)?;
             }
+            // utxos are keyed by output hash, so a second output of
+            // the same hash would replace the first when connected
             for output in &transaction.outputs {
-                if outputs.contains_key(&output.hash()) {
+                if !outputs.insert(output.hash()) {
+                    debug!(txid = %transaction.hash(), "creates an output twice in the block");
                     return Err(SbdError::InvalidTransaction);
                 }
-                outputs.insert(output.hash(), output.clone());
             }
+            let output_value = output_sum(transaction)?;
+            // It is fine for output value to be less than input value
+            // as the difference is the fee for the miner
+            let Some(fee) = input_value.checked_sub(output_value) else {
+                debug!(
+                    txid = %transaction.hash(),
+                    input_value,
+                    output_value,
+                    "outputs exceed inputs"
+                );
+                return Err(SbdError::InvalidTransaction);
+            };
+            spends.fees = spends
+                .fees
+                .checked_add(fee)
+                .ok_or(SbdError::InvalidTransactionInput)?;
         }
-        let input_value: u64 = inputs.values().map(|output| output.value).sum();
-        let output_value: u64 = outputs.values().map(|output| output.value).sum();
-        Ok(input_value - output_value)
+        Ok(spends)
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+// what walking the spends of a block finds: the fees its
+// transactions pay, each its inputs less its outputs, and the
+// signature of every input
+struct Spends<'a> {
+    fees: u64,
+    signatures: Vec<SignatureCheck<'a>>,
+}
+
+// the coinbase may pay out the block reward and the fees, no more
+// and no less
+fn verify_coinbase_value(
+    params: &NetworkParams,
+    predicted_block_height: u64,
+    coinbase: &Transaction,
+    miner_fees: u64,
+) -> Result<()> {
+    let block_reward = params.reward_at_height(predicted_block_height);
+    let total_coinbase_outputs = output_sum(coinbase)?;
+    if block_reward.checked_add(miner_fees) != Some(total_coinbase_outputs) {
+        debug!(block_reward, miner_fees, "coinbase pays the wrong amount");
+        return Err(SbdError::InvalidTransaction);
+    }
+    Ok(())
+}
+
+// an input of a block transaction and the key of the output it spends
+type SignatureCheck<'a> = (&'a Transaction, &'a TransactionInput, &'a PublicKey);
+
+// the first input, in block order, whose signature doesn't verify.
+// with parallel the checks are spread over rayon's threads, but the
+// lowest failing index is still the one found, whichever thread fails
+// first, so a block is rejected for the same input either way
+#[cfg(feature = "parallel")]
+fn first_bad_signature(checks: &[SignatureCheck<'_>]) -> Option<usize> {
+    use rayon::prelude::*;
+    checks
+        .par_iter()
+        .position_first(|&(transaction, input, pubkey)| !signed(transaction, input, pubkey))
+}
+
+#[cfg(not(feature = "parallel"))]
+fn first_bad_signature(checks: &[SignatureCheck<'_>]) -> Option<usize> {
+    checks
+        .iter()
+        .position(|&(transaction, input, pubkey)| !signed(transaction, input, pubkey))
+}
+
+fn signed(transaction: &Transaction, input: &TransactionInput, pubkey: &PublicKey) -> bool {
+    input.signature.verify(
+        &transaction.sighash(&input.prev_transaction_output_hash),
+        pubkey,
+    )
+}
+
+// outputs can add up past u64::MAX, which no transaction may
+fn output_sum(transaction: &Transaction) -> Result<u64> {
+    transaction
+        .outputs
+        .iter()
+        .try_fold(0u64, |sum, output| sum.checked_add(output.value))
+        .ok_or(SbdError::InvalidTransactionOutput)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct BlockHeader {
+    // bits of the deployments the miner signals for; left out of
+    // the encoding while 0, as headers were before they had one
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub version: u32,
     pub timestamp: DateTime<Utc>,
     pub nonce: u64,
     pub prev_block_hash: Hash,
     pub merkle_root: MerkleRoot,
     pub target: U256,
+    // the utxos after the block, as UtxoCommitment; zero claims
+    // none and is left out of the encoding, as headers were before
+    #[serde(default = "Hash::zero", skip_serializing_if = "is_zero_hash")]
+    pub utxo_commitment: Hash,
+    #[serde(skip)]
+    cached_hash: HashCache,
 }
 
 impl BlockHeader {
@@ -161,19 +325,44 @@ impl BlockHeader {
         target: U256,
     ) -> Self {
         BlockHeader {
+            version: 0,
             timestamp,
             nonce,
             prev_block_hash,
             merkle_root,
             target,
+            utxo_commitment: Hash::zero(),
+            cached_hash: HashCache::default(),
+        }
+    }
+
+    // the merkle root is the one thing without a sensible default
+    pub fn builder(merkle_root: MerkleRoot) -> BlockHeaderBuilder {
+        BlockHeaderBuilder {
+            merkle_root,
+            ..BlockHeaderBuilder::default()
         }
     }
 
+    // encoded and hashed again on every call until frozen
     pub fn hash(&self) -> Hash {
-        Hash::hash(self)
+        self.cached_hash.get(self)
     }
 
+    // keep the hash from now on, as Transaction::freeze; mining
+    // thaws the header, as it changes the nonce
+    pub fn freeze(&self) {
+        self.cached_hash.freeze(self);
+    }
+
+    pub fn thaw(&mut self) {
+        self.cached_hash.clear();
+    }
+
+    #[cfg(feature = "mining")]
     pub fn mine(&mut self, steps: usize) -> bool {
+        let _span = debug_span!("mine", nonce = self.nonce, steps).entered();
+        self.thaw();
         // if the block already matches target, return early
         if self.hash().matches_target(self.target) {
             return true;
@@ -183,9 +372,18 @@ impl BlockHeader {
                 self.nonce = new_nonce;
             } else {
                 self.nonce = 0;
-                self.timestamp = Utc::now()
+                // a later timestamp gives the nonces another go
+                #[cfg(feature = "clock")]
+                {
+                    self.timestamp = Utc::now();
+                }
+                #[cfg(not(feature = "clock"))]
+                {
+                    self.timestamp += chrono::Duration::seconds(1);
+                }
             }
             if self.hash().matches_target(self.target) {
+                debug!(nonce = self.nonce, hash = %self.hash(), "found a hash under the target");
                 return true;
             }
         }
@@ -193,15 +391,242 @@ impl BlockHeader {
     }
 }
 
-// save and load expecting CBOR from ciborium as format
-impl Saveable for Block {
-    fn load<I: Read>(reader: I) -> IoResult<Self> {
-        ciborium::de::from_reader(reader)
-            .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Failed to deserialize Block"))
+// a genesis header of no transactions, stamped at the epoch
+impl Default for BlockHeader {
+    fn default() -> Self {
+        BlockHeader::new(
+            DateTime::UNIX_EPOCH,
+            0,
+            Hash::zero(),
+            MerkleRoot::calculate(&[]),
+            crate::MIN_TARGET,
+        )
+    }
+}
+
+fn is_zero(version: &u32) -> bool {
+    *version == 0
+}
+
+fn is_zero_hash(hash: &Hash) -> bool {
+    *hash == Hash::zero()
+}
+
+// a header by name rather than position; unset, the previous
+// hash and utxo commitment are zero, the target MIN_TARGET, the
+// version and nonce 0 and the timestamp the time of build, or the
+// epoch without a clock
+#[derive(Clone, Debug)]
+pub struct BlockHeaderBuilder {
+    version: u32,
+    timestamp: Option<DateTime<Utc>>,
+    nonce: u64,
+    prev_block_hash: Hash,
+    merkle_root: MerkleRoot,
+    target: U256,
+    utxo_commitment: Hash,
+}
+
+impl Default for BlockHeaderBuilder {
+    fn default() -> Self {
+        BlockHeaderBuilder {
+            version: 0,
+            timestamp: None,
+            nonce: 0,
+            prev_block_hash: Hash::zero(),
+            merkle_root: MerkleRoot::calculate(&[]),
+            target: crate::MIN_TARGET,
+            utxo_commitment: Hash::zero(),
+        }
+    }
+}
+
+impl BlockHeaderBuilder {
+    pub fn version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+
+    pub fn prev_hash(mut self, prev_block_hash: Hash) -> Self {
+        self.prev_block_hash = prev_block_hash;
+        self
+    }
+
+    pub fn target(mut self, target: U256) -> Self {
+        self.target = target;
+        self
+    }
+
+    pub fn utxo_commitment(mut self, utxo_commitment: Hash) -> Self {
+        self.utxo_commitment = utxo_commitment;
+        self
+    }
+
+    pub fn build(self) -> BlockHeader {
+        #[cfg(feature = "clock")]
+        let timestamp = self.timestamp.unwrap_or_else(Utc::now);
+        #[cfg(not(feature = "clock"))]
+        let timestamp = self.timestamp.unwrap_or_default();
+        BlockHeader {
+            version: self.version,
+            utxo_commitment: self.utxo_commitment,
+            ..BlockHeader::new(
+                timestamp,
+                self.nonce,
+                self.prev_block_hash,
+                self.merkle_root,
+                self.target,
+            )
+        }
+    }
+}
+
+// a block whose header commits to its transactions, with the
+// header defaults of BlockHeaderBuilder
+#[derive(Clone, Debug, Default)]
+pub struct BlockBuilder {
+    header: BlockHeaderBuilder,
+    transactions: Vec<Transaction>,
+}
+
+impl BlockBuilder {
+    pub fn version(mut self, version: u32) -> Self {
+        self.header = self.header.version(version);
+        self
     }
 
-    fn save<O: Write>(&self, writer: O) -> IoResult<()> {
-        ciborium::ser::into_writer(self, writer)
-            .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Failed to serialize Block"))
+    pub fn timestamp(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.header = self.header.timestamp(timestamp);
+        self
     }
+
+    pub fn nonce(mut self, nonce: u64) -> Self {
+        self.header = self.header.nonce(nonce);
+        self
+    }
+
+    pub fn prev_hash(mut self, prev_block_hash: Hash) -> Self {
+        self.header = self.header.prev_hash(prev_block_hash);
+        self
+    }
+
+    pub fn target(mut self, target: U256) -> Self {
+        self.header = self.header.target(target);
+        self
+    }
+
+    // of the utxos after the block, Blockchain::utxo_commitment_after
+    pub fn utxo_commitment(mut self, utxo_commitment: Hash) -> Self {
+        self.header = self.header.utxo_commitment(utxo_commitment);
+        self
+    }
+
+    // the coinbase first
+    pub fn transactions(mut self, transactions: Vec<Transaction>) -> Self {
+        self.transactions = transactions;
+        self
+    }
+
+    pub fn transaction(mut self, transaction: Transaction) -> Self {
+        self.transactions.push(transaction);
+        self
+    }
+
+    // fails without transactions, no block is valid without a coinbase
+    pub fn build(self) -> Result<Block> {
+        if self.transactions.is_empty() {
+            return Err(SbdError::InvalidBlock);
+        }
+        let header = BlockHeaderBuilder {
+            merkle_root: MerkleRoot::calculate(&self.transactions),
+            ..self.header
+        };
+        Ok(Block::new(header.build(), self.transactions))
+    }
+}
+
+// one line for logs and listings, {:#?} shows every field
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "block {}, {} txs, nonce {}, target {:064x}, {}",
+            self.hash().to_hex(),
+            self.transactions.len(),
+            self.header.nonce,
+            self.header.target,
+            self.header
+                .timestamp
+                .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+        )
+    }
+}
+
+impl fmt::Display for BlockHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "header {}, previous {}, nonce {}, target {:064x}, {}",
+            self.hash().to_hex(),
+            self.prev_block_hash.to_hex(),
+            self.nonce,
+            self.target,
+            self.timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+        )
+    }
+}
+
+impl Canonical for Block {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_struct(
+            out,
+            &[
+                ("header", &self.header),
+                ("transactions", &self.transactions),
+            ],
+        );
+    }
+}
+
+impl Canonical for BlockHeader {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let version = u64::from(self.version);
+        let fields: [(&str, &dyn Canonical); 7] = [
+            ("version", &version),
+            ("timestamp", &self.timestamp),
+            ("nonce", &self.nonce),
+            ("prev_block_hash", &self.prev_block_hash),
+            ("merkle_root", &self.merkle_root),
+            ("target", &self.target),
+            ("utxo_commitment", &self.utxo_commitment),
+        ];
+        // headers of version 0 and without a commitment hash as they
+        // did before the fields
+        let start = if self.version == 0 { 1 } else { 0 };
+        let end = if self.utxo_commitment == Hash::zero() {
+            fields.len() - 1
+        } else {
+            fields.len()
+        };
+        encode_struct(out, &fields[start..end]);
+    }
+}
+
+// save and load expecting CBOR from ciborium as format
+impl Saveable for Block {
+    const TYPE_TAG: &'static str = "Block";
+}
+
+impl Saveable for BlockHeader {
+    const TYPE_TAG: &'static str = "BlockHeader";
 }
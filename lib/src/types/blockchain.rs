@@ -1,311 +1,2003 @@
-use super::{Block, Transaction, TransactionOutput};
+#[cfg(feature = "std-fs")]
+use super::BlockStore;
+use super::deployment::{self, DeploymentState};
+use super::mempool::Mempool;
+use super::watch::{ChainEvent, ConflictLocation, WatchList};
+use super::{
+    Block, BlockHeader, ChainBatch, ChainStore, ChainTip, CheckpointBundle, HeaderChain,
+    SnapshotBase, SnapshotEntry, SnapshotHeader, Transaction, TransactionOutput, UtxoCommitment,
+    read_block_record, read_snapshot, write_snapshot,
+};
+#[cfg(feature = "mempool-policy")]
+use super::{MempoolEntry, MempoolSnapshot};
 use crate::U256;
+use crate::crypto::PublicKey;
 use crate::error::{Result, SbdError};
-use crate::sha256::Hash;
-use crate::utils::MerkleRoot;
-use crate::utils::Saveable;
-use bigdecimal::BigDecimal;
+use crate::light_client::LightClient;
+use crate::params::{Network, NetworkParams, UTXO_COMMITMENT_DEPLOYMENT};
+use crate::sha256::{Hash, HashKeyedMap, HashKeyedSet};
+use crate::utils::{MerkleProof, MerkleRoot};
+use crate::utils::{Saveable, deserialize_all, no_migration};
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult, Write};
-#[derive(Serialize, Deserialize, Clone, Debug)]
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{BufReader, ErrorKind, Read, Result as IoResult, Write};
+#[cfg(feature = "std-fs")]
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+#[cfg(feature = "mempool-policy")]
+use tracing::info;
+use tracing::{debug, error, info_span, warn};
+
+// utxos by hash, each reserved or not by a mempool transaction;
+// outputs are shared with block deltas, and a copy of the set for
+// a write while it's shared copies pointers rather than outputs
+type UtxoSet = HashKeyedMap<(bool, Arc<TransactionOutput>)>;
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Blockchain {
-    blocks: Vec<Block>,
+    // shared with clones and snapshots, and copied on
+    // the next write while any of them are still held
+    blocks: Arc<Vec<Arc<Block>>>,
     target: U256,
-    utxos: HashMap<Hash, (bool, TransactionOutput)>,
-    #[serde(default, skip_serializing)]
-    mempool: Vec<(DateTime<Utc>, Transaction)>,
+    #[serde(deserialize_with = "unreserved")]
+    utxos: Arc<UtxoSet>,
+    // of the utxos, worked out over the whole set the first time it's
+    // asked for and kept up to date by each block from then on
+    #[serde(skip)]
+    utxo_commitment: OnceLock<UtxoCommitment>,
+    #[serde(skip)]
+    mempool: Mempool,
+    params: NetworkParams,
+    // set for chains imported from a utxo snapshot, which have
+    // the utxos but none of the blocks up to the base
+    #[serde(default)]
+    snapshot_base: Option<SnapshotBase>,
+    // hashes the blocks at these heights must have, from signed
+    // checkpoints; applied again each time the chain is opened
+    #[serde(skip)]
+    checkpoints: BTreeMap<u64, Hash>,
+    // transactions to report double spends of, and the reports not
+    // yet taken, the oldest dropped past MAX_CHAIN_EVENTS
+    #[serde(skip)]
+    watched: WatchList,
+    #[serde(skip)]
+    events: VecDeque<ChainEvent>,
+    // where add_block persists blocks, if opened over a store
+    #[serde(skip)]
+    store: Option<Box<dyn ChainStore>>,
+}
+
+// a clone is a scratch copy in memory, it doesn't share the store;
+// blocks and utxos are shared until either side changes them
+impl Clone for Blockchain {
+    fn clone(&self) -> Self {
+        Blockchain {
+            blocks: self.blocks.clone(),
+            target: self.target,
+            utxos: self.utxos.clone(),
+            utxo_commitment: self.utxo_commitment.clone(),
+            mempool: self.mempool.clone(),
+            params: self.params.clone(),
+            snapshot_base: self.snapshot_base.clone(),
+            checkpoints: self.checkpoints.clone(),
+            watched: self.watched.clone(),
+            events: self.events.clone(),
+            store: None,
+        }
+    }
+}
+
+// what Blockchain::import_blocks did
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub imported: u64,
+    pub rejected: u64,
+    // of the chain afterwards
+    pub height: u64,
+}
+
+// what a key was paid and spent over the chain, from
+// Blockchain::address_stats
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AddressStats {
+    pub received: u64,
+    pub spent: u64,
+    // received less spent, as balance_of has it
+    pub balance: u64,
+    // heights of the first and last blocks paying or spending from it
+    pub first_seen: Option<u64>,
+    pub last_seen: Option<u64>,
+    // transactions paying or spending from it
+    pub transactions: u64,
+}
+
+// mempool transactions paying a fee rate from min_fee_rate to
+// max_fee_rate, from Blockchain::fee_histogram
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FeeBucket {
+    pub min_fee_rate: u64,
+    pub max_fee_rate: u64,
+    pub count: u64,
+    // their sizes together, in bytes
+    pub size: u64,
+}
+
+// the fee rate to be mined within 1, 3 and 6 blocks if the mempool
+// were mined as it stands, from Blockchain::fee_summary; 0 where
+// those blocks have room for every mempool transaction
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FeeSummary {
+    pub next_block: u64,
+    pub within_3_blocks: u64,
+    pub within_6_blocks: u64,
+}
+
+impl Default for Blockchain {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Blockchain {
     pub fn new() -> Self {
+        Self::with_network(Network::Mainnet)
+    }
+
+    pub fn with_network(network: Network) -> Self {
+        Self::with_params(network.params())
+    }
+
+    // an empty chain following the given rules
+    pub fn with_params(params: NetworkParams) -> Self {
         Blockchain {
-            blocks: vec![],
-            utxos: HashMap::new(),
-            target: crate::MIN_TARGET,
-            mempool: vec![],
+            blocks: Arc::new(vec![]),
+            utxos: Arc::new(UtxoSet::default()),
+            utxo_commitment: OnceLock::new(),
+            target: params.min_target,
+            mempool: Mempool::default(),
+            params,
+            snapshot_base: None,
+            checkpoints: BTreeMap::new(),
+            watched: WatchList::default(),
+            events: VecDeque::new(),
+            store: None,
+        }
+    }
+
+    // open the chain kept in a BlockStore directory
+    #[cfg(feature = "std-fs")]
+    pub fn open<P: AsRef<Path>>(dir: P, network: Network) -> Result<Self> {
+        Self::with_store(BlockStore::open(dir)?, network)
+    }
+
+    // the chain held by a store; blocks added later are committed to it
+    pub fn with_store<S: ChainStore + 'static>(store: S, network: Network) -> Result<Self> {
+        let mut blockchain = Blockchain::with_network(network);
+        if let Some(tip) = store.tip()? {
+            if tip.network != network {
+                return Err(SbdError::WrongNetwork {
+                    expected: network,
+                    found: tip.network,
+                });
+            }
+            blockchain.target = tip.target;
+        }
+        let (blocks, utxos) = store.load()?;
+        blockchain.blocks = Arc::new(blocks.into_iter().map(Arc::new).collect());
+        blockchain.verify()?;
+        blockchain.utxos = Arc::new(
+            utxos
+                .into_iter()
+                .map(|(hash, output)| (hash, (false, Arc::new(output))))
+                .collect(),
+        );
+        blockchain.store = Some(Box::new(store));
+        Ok(blockchain)
+    }
+
+    // make everything committed to the store durable
+    pub fn flush_store(&mut self) -> Result<()> {
+        if let Some(store) = &mut self.store {
+            store.flush()?;
+        }
+        Ok(())
+    }
+
+    // write the utxo set, sorted by hash, with the commitment
+    // over it, which is returned
+    pub fn export_utxo_snapshot<W: Write>(&self, writer: W) -> Result<Hash> {
+        let mut coinbase_heights = self
+            .snapshot_base
+            .as_ref()
+            .map(|base| base.coinbase_heights.clone())
+            .unwrap_or_default();
+        let base_height = self.base_height();
+        for (height, block) in self.blocks.iter().enumerate() {
+            if let Some(coinbase) = block.transactions.first() {
+                for output in &coinbase.outputs {
+                    coinbase_heights.insert(output.hash(), base_height + height as u64);
+                }
+            }
+        }
+        let mut entries: Vec<SnapshotEntry> = self
+            .utxos
+            .iter()
+            .map(|(hash, (_, output))| SnapshotEntry {
+                hash: *hash,
+                output: TransactionOutput::clone(output),
+                coinbase_height: coinbase_heights.get(hash).copied(),
+            })
+            .collect();
+        entries.sort_unstable_by_key(|entry| entry.hash);
+        let header = SnapshotHeader {
+            network: self.params.network,
+            height: self.chain_height(),
+            tip: self.tip_hash(),
+            target: self.target,
+            count: entries.len() as u64,
+        };
+        write_snapshot(writer, &header, &entries)
+    }
+
+    // a chain with the utxos of a snapshot and none of its blocks;
+    // transactions spending them validate, blocks need headers
+    // from elsewhere
+    pub fn import_utxo_snapshot<R: Read>(reader: R) -> Result<Self> {
+        let (header, entries) = read_snapshot(reader)?;
+        Ok(Self::with_snapshot(
+            header.network.params(),
+            &header,
+            entries,
+        ))
+    }
+
+    // import_utxo_snapshot, for a snapshot of the chain the header is
+    // the tip of, which must commit to the utxos in it; a snapshot is
+    // then as good as the header, which can be checked against headers
+    // from peers
+    pub fn import_utxo_snapshot_for<R: Read>(reader: R, tip: &BlockHeader) -> Result<Self> {
+        let blockchain = Self::import_utxo_snapshot(reader)?;
+        let base = blockchain
+            .snapshot_base
+            .as_ref()
+            .expect("BUG: imported without a base");
+        if base.tip != tip.hash() {
+            debug!(snapshot_tip = %base.tip, header = %tip.hash(), "snapshot of another tip");
+            return Err(SbdError::InvalidBlock);
+        }
+        blockchain.check_snapshot_commitment(tip.utxo_commitment)?;
+        Ok(blockchain)
+    }
+
+    // a chain carrying on from a snapshot of the chain headers leads
+    // up to, without the blocks before it; they can be validated later
+    // and added with connect_history. the headers are validated as a
+    // LightClient does and may go past the snapshot, whose utxos must
+    // be those the header of its tip commits to, or else those of a
+    // trusted snapshot in the params
+    pub fn from_snapshot<R: Read>(headers: HeaderChain, reader: R) -> Result<Self> {
+        let (header, entries) = read_snapshot(reader)?;
+        Self::carry_on_from(header.network.params(), headers, &header, entries)
+    }
+
+    // from_snapshot, for a chain with other rules than its network's
+    pub fn from_snapshot_with_params<R: Read>(
+        params: NetworkParams,
+        headers: HeaderChain,
+        reader: R,
+    ) -> Result<Self> {
+        let (header, entries) = read_snapshot(reader)?;
+        if header.network != params.network {
+            return Err(SbdError::WrongNetwork {
+                expected: params.network,
+                found: header.network,
+            });
+        }
+        Self::carry_on_from(params, headers, &header, entries)
+    }
+
+    fn with_snapshot(
+        params: NetworkParams,
+        header: &SnapshotHeader,
+        entries: Vec<SnapshotEntry>,
+    ) -> Self {
+        let mut blockchain = Blockchain::with_params(params);
+        blockchain.target = header.target;
+        let mut base = SnapshotBase {
+            height: header.height,
+            tip: header.tip,
+            coinbase_heights: HashKeyedMap::default(),
+            headers: HeaderChain::new(),
+            utxo_commitment: Hash::zero(),
+        };
+        for entry in entries {
+            if let Some(height) = entry.coinbase_height {
+                base.coinbase_heights.insert(entry.hash, height);
+            }
+            Arc::make_mut(&mut blockchain.utxos)
+                .insert(entry.hash, (false, Arc::new(entry.output)));
+        }
+        base.utxo_commitment = blockchain.utxo_commitment();
+        blockchain.snapshot_base = Some(base);
+        blockchain
+    }
+
+    fn carry_on_from(
+        params: NetworkParams,
+        headers: HeaderChain,
+        header: &SnapshotHeader,
+        entries: Vec<SnapshotEntry>,
+    ) -> Result<Self> {
+        let mut blockchain = Self::with_snapshot(params, header, entries);
+        let mut client = LightClient::new(blockchain.params.clone());
+        for block_header in headers.headers().iter().take(header.height as usize) {
+            client.add_header(block_header.clone())?;
+        }
+        let Some(tip) = client.tip().filter(|tip| tip.hash() == header.tip) else {
+            return Err(SbdError::UnknownBlock(header.tip));
+        };
+        if client.height() != header.height {
+            debug!(
+                height = header.height,
+                headers = client.height(),
+                "snapshot at another height"
+            );
+            return Err(SbdError::InvalidBlock);
+        }
+        if tip.utxo_commitment != Hash::zero() {
+            blockchain.check_snapshot_commitment(tip.utxo_commitment)?;
+        } else {
+            let trusted = blockchain
+                .params
+                .trusted_snapshots
+                .iter()
+                .find(|trusted| trusted.height == header.height && trusted.tip == header.tip)
+                .ok_or(SbdError::UntrustedSnapshot(header.tip))?;
+            blockchain.check_snapshot_commitment(trusted.utxo_commitment)?;
+        }
+
+        let mut headers = headers;
+        headers.truncate(header.height);
+        let base = blockchain
+            .snapshot_base
+            .as_mut()
+            .expect("BUG: imported without a base");
+        base.headers = headers;
+        // the target the snapshot claims has to follow from the headers
+        blockchain.target = blockchain
+            .tip_header()
+            .map_or(blockchain.target, |tip| tip.target);
+        let target = blockchain.adjusted_target();
+        if target != header.target {
+            debug!(snapshot = %header.target, headers = %target, "snapshot with another target");
+            return Err(SbdError::InvalidBlock);
+        }
+        blockchain.target = target;
+        Ok(blockchain)
+    }
+
+    // that the utxos of a snapshot are those of the claimed commitment
+    fn check_snapshot_commitment(&self, claimed: Hash) -> Result<()> {
+        // keyed by other than their hashes, the set isn't the one committed to
+        if let Some((hash, _)) = self
+            .utxos
+            .iter()
+            .find(|(hash, (_, output))| output.hash() != **hash)
+        {
+            debug!(utxo = %hash, "snapshot output under another hash");
+            return Err(SbdError::InvalidTransactionOutput);
+        }
+        let expected = self.utxo_commitment();
+        if claimed != expected {
+            return Err(SbdError::UtxoCommitmentMismatch {
+                expected,
+                found: claimed,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn snapshot_base(&self) -> Option<&SnapshotBase> {
+        self.snapshot_base.as_ref()
+    }
+
+    // give a chain from a snapshot the blocks below its base, from
+    // history, a chain of them from genesis validated in the background
+    // as they were fetched. history must end at the base tip with the
+    // utxos the snapshot had, which proves the snapshot; the chain is
+    // then as if it had been synced block by block
+    pub fn connect_history(&mut self, history: Blockchain) -> Result<()> {
+        let Some(base) = &self.snapshot_base else {
+            debug!("not a chain from a snapshot");
+            return Err(SbdError::InvalidBlock);
+        };
+        if history.snapshot_base.is_some() || history.tip_hash() != base.tip {
+            debug!(base = %base.tip, history = %history.tip_hash(), "history ends elsewhere");
+            return Err(SbdError::InvalidBlock);
+        }
+        let found = history.utxo_commitment();
+        if found != base.utxo_commitment {
+            return Err(SbdError::UtxoCommitmentMismatch {
+                expected: base.utxo_commitment,
+                found,
+            });
+        }
+        let mut blocks = Arc::unwrap_or_clone(history.blocks);
+        blocks.extend(self.blocks.iter().cloned());
+        self.blocks = Arc::new(blocks);
+        self.snapshot_base = None;
+        Ok(())
+    }
+
+    pub fn checkpoints(&self) -> &BTreeMap<u64, Hash> {
+        &self.checkpoints
+    }
+
+    // install the checkpoints of the bundle, each signed by one of the
+    // trusted keys, returning how many are new. none are installed if
+    // one is badly signed, or names another block than the chain or
+    // an installed checkpoint has at its height
+    pub fn apply_signed_checkpoints(
+        &mut self,
+        bundle: &CheckpointBundle,
+        trusted_keys: &[PublicKey],
+    ) -> Result<usize> {
+        let mut checkpoints = self.checkpoints.clone();
+        for checkpoint in &bundle.checkpoints {
+            let height = checkpoint.height;
+            if checkpoint.signer(trusted_keys).is_none() {
+                return Err(SbdError::InvalidCheckpointSignature { height });
+            }
+            let found = checkpoints
+                .get(&height)
+                .copied()
+                .or_else(|| self.header_at(height).map(BlockHeader::hash));
+            if let Some(found) = found.filter(|found| *found != checkpoint.hash) {
+                return Err(SbdError::CheckpointConflict {
+                    height,
+                    expected: checkpoint.hash,
+                    found,
+                });
+            }
+            checkpoints.insert(height, checkpoint.hash);
+        }
+        let added = checkpoints.len() - self.checkpoints.len();
+        debug!(added, "applied signed checkpoints");
+        self.checkpoints = checkpoints;
+        Ok(added)
+    }
+
+    // report a transaction spending an output the transaction with
+    // the txid spends, from when the chain has seen that transaction
+    // on. false if MAX_WATCHED_TRANSACTIONS are watched already
+    pub fn watch_transaction(&mut self, txid: Hash) -> bool {
+        if !self.watched.contains(&txid) && self.watched.len() >= crate::MAX_WATCHED_TRANSACTIONS {
+            return false;
+        }
+        self.watched.insert(txid);
+        if let Some(transaction) = self.find_transaction(&txid).cloned() {
+            self.watched.see(&transaction);
+        }
+        true
+    }
+
+    // false if the transaction wasn't watched
+    pub fn unwatch_transaction(&mut self, txid: &Hash) -> bool {
+        self.watched.remove(txid)
+    }
+
+    // the events since they were last taken, oldest first
+    pub fn take_events(&mut self) -> Vec<ChainEvent> {
+        self.events.drain(..).collect()
+    }
+
+    fn push_events(&mut self, events: Vec<ChainEvent>) {
+        for event in events {
+            let ChainEvent::DoubleSpendDetected {
+                original,
+                conflicting,
+                location,
+                ..
+            } = &event;
+            warn!(%original, %conflicting, ?location, "double spend of a watched transaction");
+            if self.events.len() >= crate::MAX_CHAIN_EVENTS {
+                self.events.pop_front();
+            }
+            self.events.push_back(event);
+        }
+    }
+
+    // a read only copy of the chain as it is now, for readers on
+    // other threads; it costs the same whatever the chain's length
+    pub fn snapshot(&self) -> ChainSnapshot {
+        ChainSnapshot {
+            blocks: Arc::clone(&self.blocks),
+            utxos: Arc::clone(&self.utxos),
+            target: self.target,
+            tip: self.tip_hash(),
+            params: self.params.clone(),
+        }
+    }
+
+    // hash of the last block, or of the snapshot tip
+    pub fn tip_hash(&self) -> Hash {
+        match (self.blocks.last(), &self.snapshot_base) {
+            (Some(block), _) => block.hash(),
+            (None, Some(base)) => base.tip,
+            (None, None) => Hash::zero(),
+        }
+    }
+
+    // header of the last block, or of the snapshot tip if
+    // the chain came with its headers
+    fn tip_header(&self) -> Option<&BlockHeader> {
+        match (self.blocks.last(), &self.snapshot_base) {
+            (Some(block), _) => Some(&block.header),
+            (None, Some(base)) => base.headers.tip(),
+            (None, None) => None,
+        }
+    }
+
+    // the header at height, from the blocks or, below a
+    // snapshot base, the headers the chain came with
+    fn header_at(&self, height: u64) -> Option<&BlockHeader> {
+        match height.checked_sub(self.base_height()) {
+            Some(index) => self.blocks.get(index as usize).map(|block| &block.header),
+            None => self.snapshot_base.as_ref()?.headers.get(height),
+        }
+    }
+
+    // blocks below the first held, those of a snapshot base
+    fn base_height(&self) -> u64 {
+        self.snapshot_base.as_ref().map_or(0, |base| base.height)
+    }
+
+    // network
+    pub fn network(&self) -> Network {
+        self.params.network
+    }
+
+    pub fn params(&self) -> &NetworkParams {
+        &self.params
+    }
+
+    // fail if the chain belongs to a different network
+    pub fn check_network(&self, expected: Network) -> Result<()> {
+        if self.params.network != expected {
+            return Err(SbdError::WrongNetwork {
+                expected,
+                found: self.params.network,
+            });
+        }
+        Ok(())
+    }
+
+    // utxos, read only so the reserved flags stay in step with the mempool
+    pub fn utxos(&self) -> UtxoView<'_> {
+        UtxoView(&self.utxos)
+    }
+
+    pub fn utxo(&self, hash: &Hash) -> Option<&TransactionOutput> {
+        self.utxos().get(hash)
+    }
+
+    // heights of the coinbase outputs of the last coinbase_maturity
+    // blocks, those the next block may not spend yet; a chain from a
+    // snapshot knows the ones below its base from the snapshot
+    pub fn immature_coinbases(&self) -> HashKeyedMap<u64> {
+        let height = self.chain_height();
+        let first = (height + 1).saturating_sub(self.params.coinbase_maturity);
+        let mut immature: HashKeyedMap<u64> = self
+            .snapshot_base
+            .iter()
+            .flat_map(|base| &base.coinbase_heights)
+            .filter(|&(_, &coinbase_height)| coinbase_height >= first)
+            .map(|(hash, &coinbase_height)| (*hash, coinbase_height))
+            .collect();
+        let base_height = self.base_height();
+        for coinbase_height in first.max(base_height)..height {
+            let block = &self.blocks[(coinbase_height - base_height) as usize];
+            for output in block.transactions.iter().take(1).flat_map(|tx| &tx.outputs) {
+                immature.insert(output.hash(), coinbase_height);
+            }
         }
+        immature
     }
 
-    // utxos
-    pub fn utxos(&self) -> &HashMap<Hash, (bool, TransactionOutput)> {
-        &self.utxos
+    pub fn contains_utxo(&self, hash: &Hash) -> bool {
+        self.utxos.contains_key(hash)
+    }
+
+    // whether a mempool transaction spends the utxo, None if there is no such utxo
+    pub fn is_utxo_reserved(&self, hash: &Hash) -> Option<bool> {
+        self.utxos().is_reserved(hash)
+    }
+
+    pub fn utxo_count(&self) -> usize {
+        self.utxos.len()
+    }
+
+    // the commitment to the utxos, what the header of the tip commits
+    // to if it commits to any
+    pub fn utxo_commitment(&self) -> Hash {
+        self.commitment().hash()
+    }
+
+    // the commitment to the utxos after a block of the transactions,
+    // for the header of a template; they must be valid against the
+    // utxos, as a block is before it's connected
+    pub fn utxo_commitment_after(&self, transactions: &[Transaction]) -> Hash {
+        self.commitment_after(transactions).hash()
+    }
+
+    fn commitment(&self) -> UtxoCommitment {
+        *self
+            .utxo_commitment
+            .get_or_init(|| UtxoCommitment::of(self.utxos.keys()))
+    }
+
+    // the commitment moved by what apply_utxo_delta would do to the
+    // set, without doing it: an input takes out an output if it's
+    // there, and an output adds one if it isn't already
+    fn commitment_after(&self, transactions: &[Transaction]) -> UtxoCommitment {
+        let mut commitment = self.commitment();
+        // the set as the transactions so far leave it
+        let mut removed = HashKeyedSet::default();
+        let mut added = HashKeyedSet::default();
+        let present = |hash: &Hash, removed: &HashKeyedSet, added: &HashKeyedSet| {
+            added.contains(hash) || (self.utxos.contains_key(hash) && !removed.contains(hash))
+        };
+        for transaction in transactions {
+            for input in &transaction.inputs {
+                let hash = input.prev_transaction_output_hash;
+                if present(&hash, &removed, &added) {
+                    commitment.remove(&hash);
+                    if !added.remove(&hash) {
+                        removed.insert(hash);
+                    }
+                }
+            }
+            for output in &transaction.outputs {
+                let hash = output.hash();
+                if !present(&hash, &removed, &added) {
+                    commitment.insert(&hash);
+                    if !removed.remove(&hash) {
+                        added.insert(hash);
+                    }
+                }
+            }
+        }
+        commitment
     }
     // target
     pub fn target(&self) -> U256 {
         self.target
     }
+
+    // the state of the named deployment for the next block, which
+    // validation branches on; a name the params don't define is
+    // never active
+    pub fn deployment_state(&self, name: &str) -> DeploymentState {
+        self.deployment_state_at(name, self.chain_height())
+    }
+
+    // the state for the block at height, up to the next block's
+    pub fn deployment_state_at(&self, name: &str, height: u64) -> DeploymentState {
+        let Some(deployment) = self.params.deployments.iter().find(|d| d.name == name) else {
+            return DeploymentState::Defined;
+        };
+        deployment::state_at(
+            deployment,
+            self.params.difficulty_update_interval,
+            height.min(self.chain_height()),
+            |height| self.header_at(height).map_or(0, |header| header.version),
+        )
+    }
+
+    // the version of the next block: the bits of the deployments
+    // in the params that are started or locked in
+    pub fn block_version(&self) -> u32 {
+        self.params
+            .deployments
+            .iter()
+            .filter(|deployment| self.deployment_state(&deployment.name).is_signaling())
+            .fold(0, |version, deployment| version | deployment.mask())
+    }
     // blocks
     pub fn blocks(&self) -> impl Iterator<Item = &Block> {
-        self.blocks.iter()
+        self.blocks.iter().map(|block| &**block)
     }
 
     //mempool
     pub fn mempool(&self) -> &[(DateTime<Utc>, Transaction)] {
-        &self.mempool
+        self.mempool.entries()
     }
 
-    pub fn block_height(&self) -> u64 {
-        self.blocks.len().try_into().unwrap()
+    // the mempool entry of the transaction with the txid
+    pub fn mempool_get(&self, txid: &Hash) -> Option<&(DateTime<Utc>, Transaction)> {
+        self.mempool.get(txid)
     }
 
-    // Rebuild UTXO set from the blockchain
-    pub fn rebuild_utxos(&mut self) {
-        for block in &self.blocks {
+    // the mempool transaction creating the output, which a
+    // transaction spending it can't be mined before
+    pub fn mempool_parent(&self, output_hash: &Hash) -> Option<&Transaction> {
+        self.mempool
+            .creator(output_hash)
+            .map(|(_, transaction)| transaction)
+    }
+
+    // the mempool highest fee first, the order to mine it in
+    pub fn mempool_by_priority(&self) -> impl Iterator<Item = &(DateTime<Utc>, Transaction)> {
+        self.mempool.iter().rev()
+    }
+
+    // fee paid by a transaction spending known utxos,
+    // None if an input is unknown or outputs exceed inputs
+    pub fn transaction_fee(&self, transaction: &Transaction) -> Option<u64> {
+        fee_in(&self.utxos, transaction)
+    }
+
+    // a transaction in the mempool or any block
+    pub fn find_transaction(&self, hash: &Hash) -> Option<&Transaction> {
+        if let Some((_, transaction)) = self.mempool.get(hash) {
+            return Some(transaction);
+        }
+        self.blocks
+            .iter()
+            .rev()
+            .flat_map(|block| &block.transactions)
+            .find(|transaction| transaction.hash() == *hash)
+    }
+
+    // 0 for a mempool transaction, else how many blocks
+    // include or follow it, None if it is unknown
+    pub fn confirmations(&self, hash: &Hash) -> Option<u64> {
+        if self.mempool.contains(hash) {
+            return Some(0);
+        }
+        self.blocks
+            .iter()
+            .rev()
+            .position(|block| block.transactions.iter().any(|tx| tx.hash() == *hash))
+            .map(|depth| depth as u64 + 1)
+    }
+
+    // the block a transaction was mined in, and a proof of it
+    // against that block's merkle root for a light client
+    pub fn transaction_proof(&self, hash: &Hash) -> Option<(Hash, MerkleProof)> {
+        self.blocks.iter().rev().find_map(|block| {
+            let index = block
+                .transactions
+                .iter()
+                .position(|tx| tx.hash() == *hash)?;
+            let proof = MerkleProof::new(&block.transactions, index)?;
+            Some((block.hash(), proof))
+        })
+    }
+
+    // hashes of the tip, then exponentially sparser back
+    // to genesis, empty for an empty chain
+    pub fn block_locator(&self) -> Vec<Hash> {
+        let mut locator = vec![];
+        let mut step = 1;
+        let mut height = self.chain_height();
+        while let Some(header) = height.checked_sub(1).and_then(|tip| self.header_at(tip)) {
+            locator.push(header.hash());
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        if let Some(genesis) = self.header_at(0)
+            && locator.last() != Some(&genesis.hash())
+        {
+            locator.push(genesis.hash());
+        }
+        locator
+    }
+
+    // height of the highest locator hash in our chain, None if
+    // there is no common block; hashes of blocks on other
+    // branches are skipped. below a snapshot base the base's
+    // headers are searched
+    pub fn find_fork_point(&self, locator: &[Hash]) -> Option<u64> {
+        let locator_set: HashKeyedSet = locator.iter().copied().collect();
+        let in_blocks = self
+            .blocks
+            .iter()
+            .rposition(|block| locator_set.contains(&block.hash()))
+            .map(|index| self.base_height() + index as u64);
+        in_blocks.or_else(|| {
+            let headers = &self.snapshot_base.as_ref()?.headers;
+            locator.iter().find_map(|hash| headers.height_of(hash))
+        })
+    }
+
+    // up to count headers from height on, those below a snapshot
+    // base from the base's headers
+    pub fn headers_from(&self, height: u64, count: usize) -> Vec<BlockHeader> {
+        (height..self.chain_height())
+            .map_while(|height| self.header_at(height).cloned())
+            .take(count)
+            .collect()
+    }
+
+    // value of the utxos of a key, reserved or not
+    pub fn balance_of(&self, pubkey: &PublicKey) -> u64 {
+        self.utxos
+            .values()
+            .filter(|(_, output)| output.pubkey == *pubkey)
+            .fold(0u64, |sum, (_, output)| sum.saturating_add(output.value))
+    }
+
+    // every key holding utxos with their value, in no order;
+    // sorted by value they make a rich list
+    pub fn iter_balances(&self) -> impl Iterator<Item = (PublicKey, u64)> + '_ {
+        let mut balances: HashMap<&PublicKey, u64> = HashMap::new();
+        for (_, output) in self.utxos.values() {
+            let balance = balances.entry(&output.pubkey).or_default();
+            *balance = balance.saturating_add(output.value);
+        }
+        balances
+            .into_iter()
+            .map(|(pubkey, balance)| (pubkey.clone(), balance))
+    }
+
+    // lifetime totals of a key in one pass over the blocks, following
+    // its outputs to tell its spends from anyone else's. outputs of a
+    // snapshot base count as received while still unspent; those
+    // spent since can't be told apart, and count as neither
+    pub fn address_stats(&self, pubkey: &PublicKey) -> AddressStats {
+        let base_height = self.base_height();
+        let mut stats = AddressStats::default();
+        // value of each output of the key not yet spent
+        let mut unspent: HashKeyedMap<u64> = HashKeyedMap::default();
+        for (index, block) in self.blocks.iter().enumerate() {
+            let height = base_height + index as u64;
             for transaction in &block.transactions {
+                let mut touched = false;
                 for input in &transaction.inputs {
-                    self.utxos.remove(&input.prev_transaction_output_hash);
+                    if let Some(value) = unspent.remove(&input.prev_transaction_output_hash) {
+                        stats.spent = stats.spent.saturating_add(value);
+                        touched = true;
+                    }
                 }
-                for output in transaction.outputs.iter() {
-                    self.utxos
-                        .insert(transaction.hash(), (false, output.clone()));
+                for output in &transaction.outputs {
+                    if output.pubkey == *pubkey {
+                        unspent.insert(output.hash(), output.value);
+                        stats.received = stats.received.saturating_add(output.value);
+                        touched = true;
+                    }
+                }
+                if touched {
+                    stats.transactions += 1;
+                    stats.first_seen.get_or_insert(height);
+                    stats.last_seen = Some(height);
+                }
+            }
+        }
+        for (hash, (_, output)) in self.utxos.iter() {
+            if output.pubkey == *pubkey && !unspent.contains_key(hash) {
+                stats.received = stats.received.saturating_add(output.value);
+            }
+        }
+        stats.balance = self.balance_of(pubkey);
+        stats
+    }
+
+    // hashes per second securing the chain over its last window_blocks
+    // blocks: the hashes each block after the first was expected to
+    // take at its own target, over the time from the first to the
+    // last. 0 for fewer than 2 blocks or no time between them
+    pub fn estimated_hashrate(&self, window_blocks: u64) -> f64 {
+        let window = window_blocks.min(self.block_height()) as usize;
+        if window < 2 {
+            return 0.0;
+        }
+        let blocks = &self.blocks[self.blocks.len() - window..];
+        let elapsed = blocks[window - 1].header.timestamp - blocks[0].header.timestamp;
+        let seconds = elapsed.num_milliseconds() as f64 / 1000.0;
+        if seconds <= 0.0 {
+            return 0.0;
+        }
+        let hashes: f64 = blocks[1..]
+            .iter()
+            .map(|block| expected_hashes(block.header.target))
+            .sum();
+        hashes / seconds
+    }
+
+    // median fee per byte of the mempool transactions,
+    // None while the mempool is empty
+    #[cfg(feature = "mempool-policy")]
+    pub fn estimate_fee_rate(&self) -> Option<u64> {
+        let mut rates: Vec<u64> = self
+            .mempool
+            .with_fees()
+            .map(|((_, transaction), fee)| fee / transaction.size().max(1))
+            .collect();
+        rates.sort_unstable();
+        rates.get(rates.len() / 2).copied()
+    }
+
+    // the mempool by fee per byte, in up to bucket_count buckets of
+    // equal width from the lowest rate to the highest, lowest first.
+    // empty while the mempool is
+    #[cfg(feature = "mempool-policy")]
+    pub fn fee_histogram(&self, bucket_count: usize) -> Vec<FeeBucket> {
+        let rated: Vec<(u64, u64)> = self
+            .mempool
+            .with_fees()
+            .map(|((_, transaction), fee)| {
+                let size = transaction.size();
+                (fee / size.max(1), size)
+            })
+            .collect();
+        fee_buckets(&rated, bucket_count)
+    }
+
+    // the mempool ranked by fee per byte, highest first, filling
+    // blocks of block_transaction_cap; the rate for a target is that
+    // of the last transaction those blocks would hold, 0 if they'd
+    // hold the whole mempool
+    #[cfg(feature = "mempool-policy")]
+    pub fn fee_summary(&self) -> FeeSummary {
+        let cap = self.params.block_transaction_cap;
+        let rate_within = |blocks: usize| {
+            let mut by_rate = self.mempool.rates().rev();
+            match by_rate.nth(blocks.saturating_mul(cap).saturating_sub(1)) {
+                Some(rate) if by_rate.next().is_some() => rate,
+                _ => 0,
+            }
+        };
+        FeeSummary {
+            next_block: rate_within(1),
+            within_3_blocks: rate_within(3),
+            within_6_blocks: rate_within(6),
+        }
+    }
+
+    pub fn block_height(&self) -> u64 {
+        // usize is at most 64 bits
+        self.blocks.len() as u64
+    }
+
+    // height of the next block, counting those below a
+    // snapshot base the chain doesn't hold
+    pub fn chain_height(&self) -> u64 {
+        self.base_height() + self.block_height()
+    }
+
+    // reward for the next block to be mined
+    #[cfg(feature = "mining")]
+    pub fn calculate_block_reward(&self) -> u64 {
+        self.params.reward_at_height(self.chain_height())
+    }
+
+    // coins in existence: what the coinbases actually paid out, less
+    // the fees they passed on, which is the value of the utxos. below
+    // the schedule's cumulative supply by whatever coinbases left
+    // unclaimed, and right for a chain from a snapshot too
+    pub fn circulating_supply(&self) -> u64 {
+        self.utxos
+            .values()
+            .fold(0u64, |sum, (_, output)| sum.saturating_add(output.value))
+    }
+
+    // the utxo set worked out again from every block, for recovery
+    // and verification only: it costs the whole chain, where add_block
+    // applies one block's delta. outputs the mempool spends stay
+    // reserved. a chain imported from a utxo snapshot has no blocks
+    // for the outputs of its base, so it's left as it is
+    pub fn rebuild_utxos(&mut self) {
+        if self.snapshot_base.is_some() {
+            warn!("not rebuilding the utxos of a chain from a snapshot");
+            return;
+        }
+        let mut utxos = UtxoSet::default();
+        for block in self.blocks.iter() {
+            apply_utxo_delta(&mut utxos, block);
+        }
+        for (_, transaction) in self.mempool.iter() {
+            for input in &transaction.inputs {
+                if let Some((reserved, _)) = utxos.get_mut(&input.prev_transaction_output_hash) {
+                    *reserved = true;
                 }
             }
         }
+        self.utxos = Arc::new(utxos);
+        self.utxo_commitment = OnceLock::new();
+    }
+
+    // spend the outputs the block's inputs refer to and add its
+    // outputs, changing the utxos only; add_block does this for each
+    // block it connects. the block isn't checked against the set
+    pub fn apply_block_utxo_delta(&mut self, block: &Block) -> UtxoDelta {
+        // left to be worked out when it's asked for, if it hasn't been
+        if self.utxo_commitment.get().is_some() {
+            let commitment = self.commitment_after(&block.transactions);
+            self.utxo_commitment = OnceLock::from(commitment);
+        }
+        apply_utxo_delta(Arc::make_mut(&mut self.utxos), block)
     }
 
     pub fn add_block(&mut self, block: Block) -> Result<()> {
-        //check if the block is valid
-        if self.blocks.is_empty() {
-            //if this is the first block, check if the prev_block_hash is all zeroes
-            if block.header.prev_block_hash != Hash::zero() {
-                println!("zero hash");
-                return Err(SbdError::InvalidBlock);
-            } else {
-                //if this is not the first block, check if the prev_block_hash is the hash of the last block
-                let last_block = self.blocks.last().unwrap();
+        block.freeze();
+        let _span =
+            info_span!("add_block", hash = %block.hash(), height = self.chain_height()).entered();
+        if let Err(e) = self.check_block(&block) {
+            warn!(reason = %e, "rejected block");
+            return Err(e);
+        }
+        self.connect_block(block)
+    }
 
-                if block.header.prev_block_hash != last_block.hash() {
-                    println!("prev hash is wrong");
+    // the hash meets the target and the header commits to the
+    // transactions, for any block, the first included
+    fn check_work(block: &Block) -> Result<()> {
+        if !block.header.hash().matches_target(block.header.target) {
+            return Err(SbdError::InvalidProofOfWork);
+        }
+        if MerkleRoot::calculate(&block.transactions) != block.header.merkle_root {
+            return Err(SbdError::InvalidMerkleRoot);
+        }
+        Ok(())
+    }
+
+    fn check_block(&self, block: &Block) -> Result<()> {
+        let height = self.chain_height();
+        if let Some(&expected) = self.checkpoints.get(&height)
+            && block.hash() != expected
+        {
+            return Err(SbdError::CheckpointConflict {
+                height,
+                expected,
+                found: block.hash(),
+            });
+        }
+        //check if the block is valid
+        let last_header = self.tip_header();
+        match (last_header, &self.snapshot_base) {
+            //if this is not the first block, check if the prev_block_hash is the hash of the last block
+            (Some(last_header), _) => {
+                if block.header.prev_block_hash != last_header.hash() {
+                    debug!(prev_hash = %block.header.prev_block_hash, "previous hash isn't the tip");
                     return Err(SbdError::InvalidBlock);
                 }
-
-                //check if the block's hash is less than the target
-                if !block.header.hash().matches_target(block.header.target) {
-                    println!("does not match target");
+            }
+            // a chain from a snapshot can't go on without its headers
+            (None, Some(base)) => {
+                debug!(base = %base.tip, "no headers for the snapshot base");
+                return Err(SbdError::InvalidBlock);
+            }
+            //if this is the first block, check if the prev_block_hash is all zeroes
+            (None, None) => {
+                if block.header.prev_block_hash != Hash::zero() {
+                    debug!(prev_hash = %block.header.prev_block_hash, "first block doesn't start from zero");
                     return Err(SbdError::InvalidBlock);
                 }
+            }
+        }
 
-                // check if the block's merkle root is correct
-                let calculated_merkle_root = MerkleRoot::calculate(&block.transactions);
-                if calculated_merkle_root != block.header.merkle_root {
-                    println!("invalid merkle root");
-                    return Err(SbdError::InvalidMerkleRoot);
-                }
+        // the target must be the one the schedule gives, or any
+        // block could claim an easier one
+        if block.header.target != self.target {
+            debug!(target = %block.header.target, expected = %self.target, "block is off the target schedule");
+            return Err(SbdError::InvalidBlock);
+        }
 
-                // check if the block's timestamp is after the
-                // last block's timestamp
-                if block.header.timestamp <= last_block.header.timestamp {
-                    return Err(SbdError::InvalidBlock);
-                }
+        Self::check_work(block)?;
+
+        // check if the block's timestamp is after the
+        // last block's timestamp
+        if let Some(last_header) = last_header
+            && block.header.timestamp <= last_header.timestamp
+        {
+            debug!(
+                timestamp = %block.header.timestamp,
+                tip_timestamp = %last_header.timestamp,
+                "block isn't newer than the tip"
+            );
+            return Err(SbdError::InvalidBlock);
+        }
+
+        // Verify all transactions in the block
+        block.verify_transactions(
+            &self.params,
+            self.chain_height(),
+            self.utxos(),
+            &self.immature_coinbases(),
+        )?;
+
+        // a commitment the header claims must be to the utxos after
+        // the block, and once the deployment is active it must claim one
+        let required = self.deployment_state(UTXO_COMMITMENT_DEPLOYMENT) == DeploymentState::Active;
+        if required || block.header.utxo_commitment != Hash::zero() {
+            let expected = self.utxo_commitment_after(&block.transactions);
+            if block.header.utxo_commitment != expected {
+                return Err(SbdError::UtxoCommitmentMismatch {
+                    expected,
+                    found: block.header.utxo_commitment,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // add a block from a trusted source, only checking it extends the
+    // tip; proof of work, merkle root and transactions aren't verified
+    pub fn add_trusted_block(&mut self, block: Block) -> Result<()> {
+        block.freeze();
+        let _span =
+            info_span!("add_trusted_block", hash = %block.hash(), height = self.chain_height())
+                .entered();
+        if block.header.prev_block_hash != self.tip_hash() {
+            warn!(reason = %SbdError::InvalidBlock, "rejected block, it doesn't extend the tip");
+            return Err(SbdError::InvalidBlock);
+        }
+        self.connect_block(block)
+    }
 
-                // Verify all transactions in the block
-                block.verify_transactions(self.block_height(), &self.utxos)?;
+    // add the blocks of a blocks.dat stream one at a time, so only one
+    // block is read ahead whatever the size of the stream; blocks that
+    // don't fit are rejected and the rest still tried, a record that
+    // can't be read ends the import
+    pub fn import_blocks<R: Read>(&mut self, reader: R, validate: bool) -> Result<ImportReport> {
+        let mut reader = BufReader::new(reader);
+        let mut report = ImportReport::default();
+        loop {
+            let block = match read_block_record(&mut reader) {
+                Ok(Some(block)) => block,
+                Ok(None) => break,
+                Err(e) if e.kind() == ErrorKind::InvalidData || e.kind() == ErrorKind::Other => {
+                    warn!(reason = %e, "unreadable block record, ending the import");
+                    report.rejected += 1;
+                    break;
+                }
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                    warn!("block stream ends mid-record");
+                    report.rejected += 1;
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let added = if validate {
+                self.add_block(block)
+            } else {
+                self.add_trusted_block(block)
+            };
+            match added {
+                Ok(()) => report.imported += 1,
+                // the store failing isn't the block's fault
+                Err(e @ SbdError::Storage(_)) => return Err(e),
+                // logged as it was rejected
+                Err(_) => report.rejected += 1,
             }
         }
+        report.height = self.chain_height();
+        Ok(report)
+    }
 
-        //Remove transactions from mempool that are now in blocks
-        let block_transactions: HashSet<_> =
+    fn connect_block(&mut self, block: Block) -> Result<()> {
+        // committed first, so a failed write leaves the chain as it was
+        Arc::make_mut(&mut self.blocks).push(Arc::new(block));
+        let target = self.adjusted_target();
+        let block = Arc::clone(self.blocks.last().unwrap());
+        if let Some(store) = &mut self.store {
+            let tip = ChainTip {
+                network: self.params.network,
+                height: self.blocks.len() as u64,
+                hash: block.hash(),
+                target,
+            };
+            if let Err(e) = store.commit(ChainBatch::connect(Block::clone(&block), tip)) {
+                error!(reason = %e, "failed to store block");
+                Arc::make_mut(&mut self.blocks).pop();
+                return Err(e);
+            }
+        }
+        debug!(transactions = block.transactions.len(), "connected block");
+        let location = ConflictLocation::Block(self.chain_height() - 1);
+        let mut conflicts = vec![];
+        for transaction in &block.transactions {
+            self.watched.see(transaction);
+            conflicts.extend(self.watched.conflicts(transaction, location));
+        }
+        self.push_events(conflicts);
+        //Remove transactions from mempool that are now in blocks,
+        // and those spending an output the block spends instead
+        let block_transactions: HashKeyedSet =
             block.transactions.iter().map(|tx| tx.hash()).collect();
-        self.mempool
-            .retain(|tx| !block_transactions.contains(&tx.1.hash()));
-        self.blocks.push(block);
-        self.try_adjust_target();
+        let block_inputs: HashKeyedSet = block
+            .transactions
+            .iter()
+            .flat_map(|tx| &tx.inputs)
+            .map(|input| input.prev_transaction_output_hash)
+            .collect();
+        let mut utxo_hashes_to_unmark: Vec<Hash> = vec![];
+        self.mempool.retain(|(_, tx)| {
+            if block_transactions.contains(&tx.hash()) {
+                return false;
+            }
+            let inputs = tx
+                .inputs
+                .iter()
+                .map(|input| input.prev_transaction_output_hash);
+            if inputs.clone().any(|hash| block_inputs.contains(&hash)) {
+                debug!(txid = %tx.hash(), "dropping a transaction the block conflicts with");
+                utxo_hashes_to_unmark.extend(inputs);
+                return false;
+            }
+            true
+        });
+        let delta = self.apply_block_utxo_delta(&block);
+        debug!(
+            spent = delta.spent.len(),
+            created = delta.created.len(),
+            "applied utxo delta"
+        );
+        // the block spent some of them, the rest are free again
+        for hash in utxo_hashes_to_unmark {
+            Arc::make_mut(&mut self.utxos)
+                .entry(hash)
+                .and_modify(|(marked, _)| {
+                    *marked = false;
+                });
+        }
+        // the next block may no longer include some of the rest
+        let height = self.chain_height();
+        self.evict_mempool(|(_, transaction)| transaction.is_expired_at(height));
+        self.target = target;
         Ok(())
     }
 
-    pub fn try_adjust_target(&mut self) {
-        if self.blocks.is_empty() {
-            return;
+    // remove the mempool entries evict picks and those spending their
+    // outputs, which can't be mined without them, and free the utxos
+    // they reserved
+    fn evict_mempool(&mut self, mut evict: impl FnMut(&(DateTime<Utc>, Transaction)) -> bool) {
+        let mut evicted: HashKeyedSet = self
+            .mempool
+            .iter()
+            .filter(|entry| evict(entry))
+            .map(|(_, transaction)| transaction.hash())
+            .collect();
+        // children first found can have children of their own
+        loop {
+            let children: Vec<Hash> = self
+                .mempool
+                .iter()
+                .map(|(_, transaction)| transaction)
+                .filter(|transaction| !evicted.contains(&transaction.hash()))
+                .filter(|transaction| {
+                    transaction.inputs.iter().any(|input| {
+                        self.mempool
+                            .creator(&input.prev_transaction_output_hash)
+                            .is_some_and(|(_, parent)| evicted.contains(&parent.hash()))
+                    })
+                })
+                .map(|transaction| transaction.hash())
+                .collect();
+            if children.is_empty() {
+                break;
+            }
+            evicted.extend(children);
         }
-
-        if self.blocks.len() % crate::DIFFICULTY_UPDATE_INTERVAL as usize != 0 {
+        if evicted.is_empty() {
             return;
         }
+        let mut utxo_hashes_to_unmark: Vec<Hash> = vec![];
+        self.mempool.retain(|(_, transaction)| {
+            if !evicted.contains(&transaction.hash()) {
+                return true;
+            }
+            debug!(txid = %transaction.hash(), "evicting a mempool transaction");
+            utxo_hashes_to_unmark.extend(
+                transaction
+                    .inputs
+                    .iter()
+                    .map(|input| input.prev_transaction_output_hash),
+            );
+            false
+        });
+        for hash in utxo_hashes_to_unmark {
+            Arc::make_mut(&mut self.utxos)
+                .entry(hash)
+                .and_modify(|(marked, _)| {
+                    *marked = false;
+                });
+        }
+    }
 
-        //measure the time it took to mine the last crate::DIFFICULTY_UPDATE_INTERVAL with chrono
-        let start_time = self.blocks
-            [self.blocks.len() - crate::DIFFICULTY_UPDATE_INTERVAL as usize]
-            .header
-            .timestamp;
-        let end_time = self.blocks.last().unwrap().header.timestamp;
-        let time_diff = end_time - start_time;
-        // convert time_diff to seconds
-        let time_diff_seconds = time_diff.num_seconds();
-        // calculate the ideal number of seconds
-        let target_seconds = crate::IDEAL_BLOCK_TIME * crate::DIFFICULTY_UPDATE_INTERVAL;
-
-        //multiply the current target with the actual time divided by the ideal time
-        let new_target = BigDecimal::parse_bytes(&self.target.to_string().as_bytes(), 10)
-            .expect("BUG: impossible")
-            * (BigDecimal::from(time_diff_seconds) / BigDecimal::from(target_seconds));
-
-        //cut off the decimal point and everything after it from string representation of new target
-        let new_target_str = new_target
-            .to_string()
-            .split('.')
-            .next()
-            .expect("BUG: Expected a decimal point")
-            .to_owned();
-        let new_target: U256 = U256::from_str_radix(&new_target_str, 10).expect("BUG: impossible");
-        //clamp new target to be within the range of 4 * self.target and self.target / 4
-        let new_target = if new_target < self.target / 4 {
-            self.target / 4
-        } else if new_target > self.target * 4 {
-            self.target * 4
-        } else {
-            new_target
+    // integrity check of a loaded chain: block linkage,
+    // proof of work, merkle roots and timestamp ordering
+    pub fn verify(&self) -> Result<()> {
+        let mut last_block: Option<&Block> = None;
+        // of the block before, the schedule goes on from it
+        let mut target = match &self.snapshot_base {
+            None => Some(self.params.min_target),
+            // without its headers a chain from a snapshot
+            // takes the target of its first block as given
+            Some(base) => base.headers.tip().map(|tip| tip.target),
         };
+        for (index, block) in self.blocks().enumerate() {
+            let expected = target.map_or(block.header.target, |target| {
+                self.next_target(self.base_height() + index as u64, target)
+            });
+            if block.header.target != expected {
+                return Err(SbdError::InvalidBlock);
+            }
+            target = Some(expected);
+            Self::check_work(block)?;
+            let Some(prev) = last_block else {
+                // a chain from a snapshot starts from its tip
+                let base_tip = self
+                    .snapshot_base
+                    .as_ref()
+                    .map_or(Hash::zero(), |base| base.tip);
+                if block.header.prev_block_hash != base_tip {
+                    return Err(SbdError::InvalidBlock);
+                }
+                last_block = Some(block);
+                continue;
+            };
+            if block.header.prev_block_hash != prev.hash()
+                || block.header.timestamp <= prev.header.timestamp
+            {
+                return Err(SbdError::InvalidBlock);
+            }
+            last_block = Some(block);
+        }
+        Ok(())
+    }
+
+    pub fn try_adjust_target(&mut self) {
+        self.target = self.adjusted_target();
+    }
+
+    // target after the last block
+    fn adjusted_target(&self) -> U256 {
+        self.next_target(self.chain_height(), self.target)
+    }
+
+    // target of the block at height, the block before it mined to target
+    fn next_target(&self, height: u64, target: U256) -> U256 {
+        if height == 0 {
+            return target;
+        }
+
+        // an interval of 0 never adjusts
+        let interval = self.params.difficulty_update_interval;
+        if !height.is_multiple_of(interval) {
+            return target;
+        }
 
-        //if the new target is more than the minimum target, set it to the minimum target
-        self.target = new_target.min(crate::MIN_TARGET);
+        //measure the time it took to mine the last interval blocks with chrono
+        let (Some(start), Some(end)) = (
+            self.header_at(height - interval),
+            self.header_at(height - 1),
+        ) else {
+            // a chain from a snapshot without its headers
+            return target;
+        };
+        self.params.retarget(target, start.timestamp, end.timestamp)
     }
 
+    #[cfg(all(feature = "mempool-policy", feature = "clock"))]
     pub fn add_to_mempool(&mut self, transaction: Transaction) -> Result<()> {
+        self.insert_mempool(Utc::now(), transaction)
+    }
+
+    // add_to_mempool as of the given time, which starts its expiry
+    #[cfg(feature = "mempool-policy")]
+    pub fn add_to_mempool_at(
+        &mut self,
+        now: DateTime<Utc>,
+        transaction: Transaction,
+    ) -> Result<()> {
+        self.insert_mempool(now, transaction)
+    }
+
+    #[cfg(feature = "mempool-policy")]
+    pub fn mempool_snapshot(&self) -> MempoolSnapshot {
+        MempoolSnapshot {
+            entries: self
+                .mempool
+                .with_fees()
+                .map(|((timestamp, transaction), fee)| MempoolEntry {
+                    timestamp: *timestamp,
+                    fee,
+                    transaction: transaction.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    // re-validate each saved transaction against the chain as it is
+    // now, keeping when it arrived; returns the ones dropped and why
+    #[cfg(feature = "mempool-policy")]
+    pub fn restore_mempool(&mut self, snapshot: MempoolSnapshot) -> Vec<(Transaction, SbdError)> {
+        let mut dropped = vec![];
+        for entry in snapshot.entries {
+            if let Err(e) = self.insert_mempool(entry.timestamp, entry.transaction.clone()) {
+                dropped.push((entry.transaction, e));
+            }
+        }
+        dropped
+    }
+
+    #[cfg(feature = "mempool-policy")]
+    fn insert_mempool(&mut self, timestamp: DateTime<Utc>, transaction: Transaction) -> Result<()> {
+        transaction.freeze();
+        let txid = transaction.hash();
+        let _span = info_span!("add_to_mempool", txid = %txid).entered();
+        // a conflict counts once the chain takes the transaction
+        let conflicts = self
+            .watched
+            .conflicts(&transaction, ConflictLocation::Mempool);
+        let result = self.try_insert_mempool(timestamp, transaction);
+        match &result {
+            Ok(()) => {
+                debug!(mempool = self.mempool.len(), "added transaction");
+                if let Some((_, transaction)) = self.mempool.get(&txid) {
+                    self.watched.see(transaction);
+                }
+                self.push_events(conflicts);
+            }
+            Err(e) => info!(reason = %e, "rejected transaction"),
+        }
+        result
+    }
+
+    #[cfg(feature = "mempool-policy")]
+    fn try_insert_mempool(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        transaction: Transaction,
+    ) -> Result<()> {
         // validate transaction before insertion
+        // only a block's coinbase spends nothing; one in the mempool
+        // would pay zero outputs out of nothing, for free and forever
+        if transaction.inputs.is_empty() {
+            return Err(SbdError::InvalidTransaction);
+        }
+        // the next block is the first that could include it
+        if let Some(expires_at_height) = transaction.expires_at_height
+            && transaction.is_expired_at(self.chain_height())
+        {
+            return Err(SbdError::ExpiredTransaction {
+                txid: transaction.hash(),
+                expires_at_height,
+            });
+        }
         // all inputs must match known UTXOs, and must be unique
-        let mut known_inputs = HashSet::new();
+        let mut known_inputs = HashKeyedSet::default();
+        let immature = self.immature_coinbases();
         for input in &transaction.inputs {
             if !self.utxos.contains_key(&input.prev_transaction_output_hash) {
-                println!("UTXO not found");
-                dbg!(&self.utxos);
-                return Err(SbdError::InvalidTransaction);
+                if let Some((_, parent)) = self.mempool.creator(&input.prev_transaction_output_hash)
+                {
+                    debug!(parent = %parent.hash(), "spends an unconfirmed output");
+                }
+                return Err(SbdError::MissingInput(input.prev_transaction_output_hash));
             }
             if known_inputs.contains(&input.prev_transaction_output_hash) {
-                println!("duplicate input");
-                return Err(SbdError::InvalidTransaction);
+                return Err(SbdError::DuplicateInput(input.prev_transaction_output_hash));
+            }
+            let (_, prev_output) = &self.utxos[&input.prev_transaction_output_hash];
+            // the next block is the first that could include it
+            if let Some(unlock_height) = prev_output.spendable_after_height
+                && !prev_output.is_spendable_at(self.chain_height())
+            {
+                return Err(SbdError::LockedOutput {
+                    hash: input.prev_transaction_output_hash,
+                    unlock_height,
+                });
+            }
+            if let Some(&coinbase_height) = immature.get(&input.prev_transaction_output_hash) {
+                return Err(SbdError::ImmatureCoinbase {
+                    hash: input.prev_transaction_output_hash,
+                    mature_height: coinbase_height + self.params.coinbase_maturity,
+                });
+            }
+            if !input.signature.verify(
+                &transaction.sighash(&input.prev_transaction_output_hash),
+                &prev_output.pubkey,
+            ) {
+                return Err(SbdError::InvalidSignature);
             }
             known_inputs.insert(input.prev_transaction_output_hash);
         }
-        // check if any of the utxos have the bool mark set to true
-        // and if so, find the transaction that references them
-        // in mempool, remove it, and set all the utxos it references
-        // to false
+        // a reserved utxo is spent by a mempool transaction, which
+        // this one replaces: remove it and release all it reserved
         for input in &transaction.inputs {
             if let Some((true, _)) = self.utxos.get(&input.prev_transaction_output_hash) {
-                // find the transaction that references the UTXO
-                // we are trying to reference
-
-                let referencing_transaction =
-                    self.mempool
-                        .iter()
-                        .enumerate()
-                        .find(|(_, (_, transaction))| {
-                            transaction
-                                .outputs
-                                .iter()
-                                .any(|output| output.hash() == input.prev_transaction_output_hash)
-                        });
-                // If we have found one, unmark all of its UTXOs
-                if let Some((idx, (_, referencing_transaction))) = referencing_transaction {
-                    for input in &referencing_transaction.inputs {
-                        // set all utxos from this transaction to false
-                        self.utxos
-                            .entry(input.prev_transaction_output_hash)
-                            .and_modify(|(marked, _)| {
-                                *marked = false;
-                            });
+                let replaced = self
+                    .mempool
+                    .spender(&input.prev_transaction_output_hash)
+                    .and_then(|txid| self.mempool.remove(&txid));
+                let released: Vec<Hash> = match &replaced {
+                    Some((_, replaced)) => {
+                        debug!(replaced = %replaced.hash(), "replacing a conflicting transaction");
+                        replaced
+                            .inputs
+                            .iter()
+                            .map(|input| input.prev_transaction_output_hash)
+                            .collect()
                     }
-                    // remove the transaction from the mempool
-                    self.mempool.remove(idx);
-                } else {
-                    // if, somehow, there is no matching transaction,
-                    // set this utxo to false
-                    self.utxos
-                        .entry(input.prev_transaction_output_hash)
-                        .and_modify(|(marked, _)| {
-                            *marked = false;
-                        });
+                    // if, somehow, no transaction spends it,
+                    // release this utxo alone
+                    None => vec![input.prev_transaction_output_hash],
+                };
+                let utxos = Arc::make_mut(&mut self.utxos);
+                for hash in released {
+                    utxos.entry(hash).and_modify(|(marked, _)| {
+                        *marked = false;
+                    });
                 }
             }
         }
         // all inputs must be lower than all outputs
-        let all_inputs = transaction
-            .inputs
+        let mut all_inputs = 0u64;
+        for input in &transaction.inputs {
+            let (_, output) = self
+                .utxos
+                .get(&input.prev_transaction_output_hash)
+                .ok_or(SbdError::MissingInput(input.prev_transaction_output_hash))?;
+            all_inputs = all_inputs
+                .checked_add(output.value)
+                .ok_or(SbdError::InvalidTransactionInput)?;
+        }
+        let all_outputs = transaction
+            .outputs
             .iter()
-            .map(|input| {
-                self.utxos
-                    .get(&input.prev_transaction_output_hash)
-                    .expect("BUG: impossible")
-                    .1
-                    .value
-            })
-            .sum::<u64>();
-        let all_outputs = transaction.outputs.iter().map(|output| output.value).sum();
+            .try_fold(0u64, |sum, output| sum.checked_add(output.value))
+            .ok_or(SbdError::InvalidTransactionOutput)?;
         if all_inputs < all_outputs {
-            print!("inputs are lower than outputs");
-            return Err(SbdError::InvalidTransaction);
+            return Err(SbdError::OutputsExceedInputs {
+                inputs: all_inputs,
+                outputs: all_outputs,
+            });
         }
         // Mark the UTXOs as used
         for input in &transaction.inputs {
-            self.utxos
+            Arc::make_mut(&mut self.utxos)
                 .entry(input.prev_transaction_output_hash)
                 .and_modify(|(marked, _)| {
                     *marked = true;
                 });
         }
-        // push the transaction to the mempool
-        self.mempool.push((Utc::now(), transaction));
-        // sort by miner fee
-        self.mempool.sort_by_key(|(_, transaction)| {
-            let all_inputs = transaction
-                .inputs
-                .iter()
-                .map(|input| {
-                    self.utxos
-                        .get(&input.prev_transaction_output_hash)
-                        .expect("BUG: impossible")
-                        .1
-                        .value
-                })
-                .sum::<u64>();
-            let all_outputs: u64 = transaction.outputs.iter().map(|output| output.value).sum();
-            let miner_fee = all_inputs - all_outputs;
-            miner_fee
-        });
+        // in order of miner fee, which the checks above found
+        self.mempool
+            .insert(timestamp, transaction, all_inputs - all_outputs);
         Ok(())
     }
 
     // Cleanup mempool - remove transactions older than
-    // MAX_MEMPOOL_TRANSACTION_AGE
+    // the network's max_mempool_transaction_age
+    #[cfg(all(feature = "mempool-policy", feature = "clock"))]
     pub fn cleanup_mempool(&mut self) {
-        let now = Utc::now();
-        let mut utxo_hashes_to_unmark: Vec<Hash> = vec![];
-        self.mempool.retain(|(timestamp, transaction)| {
-            if now - *timestamp
-                > chrono::Duration::seconds(crate::MAX_MEMPOOL_TRANSACTION_AGE as i64)
-            {
-                // push all utxos to unmark to the vector
-                // so we can unmark them later
-                utxo_hashes_to_unmark.extend(
-                    transaction
-                        .inputs
-                        .iter()
-                        .map(|input| input.prev_transaction_output_hash),
-                );
-                false
-            } else {
-                true
-            }
+        self.cleanup_mempool_at(Utc::now())
+    }
+
+    // cleanup_mempool as of the given time
+    #[cfg(feature = "mempool-policy")]
+    pub fn cleanup_mempool_at(&mut self, now: DateTime<Utc>) {
+        let max_age = i64::try_from(self.params.max_mempool_transaction_age).unwrap_or(i64::MAX);
+        let max_age = chrono::Duration::try_seconds(max_age).unwrap_or(chrono::Duration::MAX);
+        // and those the next block may no longer include
+        let height = self.chain_height();
+        self.evict_mempool(|(timestamp, transaction)| {
+            now - *timestamp > max_age || transaction.is_expired_at(height)
         });
-        // unmark all of the UTXOs
-        for hash in utxo_hashes_to_unmark {
-            self.utxos.entry(hash).and_modify(|(marked, _)| {
-                *marked = false;
-            });
+    }
+}
+
+// utxos of a loaded chain, whose mempool isn't saved with it and
+// so reserves none of them until restore_mempool brings it back
+fn unreserved<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Arc<UtxoSet>, D::Error> {
+    let mut utxos = UtxoSet::deserialize(deserializer)?;
+    for (reserved, _) in utxos.values_mut() {
+        *reserved = false;
+    }
+    Ok(Arc::new(utxos))
+}
+
+// the chain as Blockchain::snapshot found it, which blocks added
+// since don't change; without the mempool, whose reservations of
+// utxos are as they were then
+#[derive(Clone, Debug)]
+pub struct ChainSnapshot {
+    blocks: Arc<Vec<Arc<Block>>>,
+    utxos: Arc<UtxoSet>,
+    target: U256,
+    tip: Hash,
+    params: NetworkParams,
+}
+
+impl ChainSnapshot {
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.iter().map(|block| &**block)
+    }
+
+    pub fn block_height(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    // hash of the last block, or of the utxo snapshot tip
+    pub fn tip(&self) -> Hash {
+        self.tip
+    }
+
+    pub fn target(&self) -> U256 {
+        self.target
+    }
+
+    pub fn params(&self) -> &NetworkParams {
+        &self.params
+    }
+
+    pub fn utxos(&self) -> UtxoView<'_> {
+        UtxoView(&self.utxos)
+    }
+
+    pub fn utxo(&self, hash: &Hash) -> Option<&TransactionOutput> {
+        self.utxos().get(hash)
+    }
+}
+
+// the utxo set of a Blockchain, each reserved or not by a mempool
+// transaction spending it
+#[derive(Clone, Copy, Debug)]
+pub struct UtxoView<'a>(&'a UtxoSet);
+
+impl<'a> UtxoView<'a> {
+    pub fn get(&self, hash: &Hash) -> Option<&'a TransactionOutput> {
+        self.0.get(hash).map(|(_, output)| output.as_ref())
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.0.contains_key(hash)
+    }
+
+    pub fn is_reserved(&self, hash: &Hash) -> Option<bool> {
+        self.0.get(hash).map(|(reserved, _)| *reserved)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // hash, output and whether it is reserved, in no order
+    pub fn iter(&self) -> impl Iterator<Item = (&'a Hash, &'a TransactionOutput, bool)> + use<'a> {
+        self.0
+            .iter()
+            .map(|(hash, (reserved, output))| (hash, output.as_ref(), *reserved))
+    }
+}
+
+// (fee rate, size) pairs in up to bucket_count buckets of equal
+// width from the lowest rate to the highest, lowest first; the
+// arithmetic saturates, as rates may be anywhere in u64
+#[cfg(feature = "mempool-policy")]
+fn fee_buckets(rated: &[(u64, u64)], bucket_count: usize) -> Vec<FeeBucket> {
+    let rates = rated.iter().map(|&(rate, _)| rate);
+    let (Some(lowest), Some(highest)) = (rates.clone().min(), rates.max()) else {
+        return vec![];
+    };
+    // no more buckets than there are rates between them
+    let span = (highest - lowest).saturating_add(1);
+    let count = (bucket_count as u64).min(span);
+    if count == 0 {
+        return vec![];
+    }
+    let width = span.div_ceil(count);
+    let mut buckets: Vec<FeeBucket> = (0..count)
+        .map(|index| FeeBucket {
+            min_fee_rate: lowest.saturating_add(index.saturating_mul(width)),
+            // the last bucket ends at the highest rate, which the
+            // span saturated short of when it's all of u64
+            max_fee_rate: match index + 1 == count {
+                true => highest,
+                false => lowest.saturating_add((index + 1).saturating_mul(width) - 1),
+            },
+            ..FeeBucket::default()
+        })
+        .collect();
+    let last = buckets.len() - 1;
+    for &(rate, size) in rated {
+        let bucket = &mut buckets[(((rate - lowest) / width) as usize).min(last)];
+        bucket.count += 1;
+        bucket.size = bucket.size.saturating_add(size);
+    }
+    buckets
+}
+
+// 2^256 / (target + 1), the hashes it takes on average
+// to find one at or below the target
+fn expected_hashes(target: U256) -> f64 {
+    let target = target
+        .0
+        .iter()
+        .rev()
+        .fold(0.0, |value, limb| value * 2f64.powi(64) + *limb as f64);
+    2f64.powi(256) / (target + 1.0)
+}
+
+// fee of a transaction spending the given utxos, None if it
+// spends one that isn't there or its values overflow
+fn fee_in(utxos: &UtxoSet, transaction: &Transaction) -> Option<u64> {
+    let mut all_inputs = 0u64;
+    for input in &transaction.inputs {
+        let (_, output) = utxos.get(&input.prev_transaction_output_hash)?;
+        all_inputs = all_inputs.checked_add(output.value)?;
+    }
+    let all_outputs = transaction
+        .outputs
+        .iter()
+        .try_fold(0u64, |sum, output| sum.checked_add(output.value))?;
+    all_inputs.checked_sub(all_outputs)
+}
+
+// what one block changed in the utxo set: the outputs it spent, as
+// they were, and those it created, each in block order. an output
+// created and spent within the block is in neither
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UtxoDelta {
+    pub spent: Vec<(Hash, Arc<TransactionOutput>)>,
+    pub created: Vec<(Hash, Arc<TransactionOutput>)>,
+}
+
+fn apply_utxo_delta(utxos: &mut UtxoSet, block: &Block) -> UtxoDelta {
+    let mut delta = UtxoDelta::default();
+    // of the outputs created so far, those still unspent
+    let mut unspent = HashKeyedSet::default();
+    for transaction in &block.transactions {
+        for input in &transaction.inputs {
+            let hash = input.prev_transaction_output_hash;
+            let Some((_, output)) = utxos.remove(&hash) else {
+                continue;
+            };
+            if !unspent.remove(&hash) {
+                delta.spent.push((hash, output));
+            }
+        }
+        // inputs refer to outputs by the output hash
+        for output in transaction.outputs.iter() {
+            let hash = output.hash();
+            let output = Arc::new(output.clone());
+            utxos.insert(hash, (false, Arc::clone(&output)));
+            unspent.insert(hash);
+            delta.created.push((hash, output));
         }
     }
+    delta.created.retain(|(hash, _)| unspent.contains(hash));
+    delta
 }
 
 // save and load expecting CBOR from ciborium as format
+
 impl Saveable for Blockchain {
-    fn load<I: Read>(reader: I) -> IoResult<Self> {
-        ciborium::de::from_reader(reader)
-            .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Failed to deserialize Blockchain"))
+    const TYPE_TAG: &'static str = "Blockchain";
+    const SCHEMA_VERSION: u16 = 3;
+
+    fn migrate(version: u16, data: &[u8]) -> IoResult<Self> {
+        match version {
+            1 => {
+                let old = deserialize_all::<v1::Blockchain>(data, Self::TYPE_TAG)?;
+                if !old.links_headers() {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        "Blockchain links blocks by the hash of the whole block, as saved \
+                         before blocks were identified by their header; it can't be \
+                         converted, sync the chain again",
+                    ));
+                }
+                Ok(old.into())
+            }
+            2 => Ok(deserialize_all::<v2::Blockchain>(data, Self::TYPE_TAG)?.into()),
+            _ => Err(no_migration(Self::TYPE_TAG, version)),
+        }
     }
-    fn save<O: Write>(&self, writer: O) -> IoResult<()> {
-        ciborium::ser::into_writer(self, writer)
-            .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Failed to serialize Blockchain"))
+}
+
+// saved forms of older schema versions, as they were
+mod v1 {
+    use super::*;
+
+    // the mempool was saved with the chain; chains saved
+    // before networks existed are mainnet chains
+    #[derive(Deserialize)]
+    pub struct Blockchain {
+        pub blocks: Vec<Block>,
+        pub target: U256,
+        pub utxos: UtxoSet,
+        #[serde(default)]
+        pub mempool: Vec<(DateTime<Utc>, Transaction)>,
+        #[serde(default)]
+        pub network: Network,
+        #[serde(default)]
+        pub snapshot_base: Option<SnapshotBase>,
+    }
+
+    impl Blockchain {
+        // blocks were identified by the hash of the whole block until
+        // headers-first sync, which needs them linked by their headers.
+        // those older chains can't be relinked without mining them
+        // again, and are told apart by the first link
+        pub fn links_headers(&self) -> bool {
+            self.blocks
+                .windows(2)
+                .next()
+                .is_none_or(|pair| pair[1].header.prev_block_hash == pair[0].header.hash())
+        }
+    }
+}
+
+// the chain named its network only, whose rules were the constants
+mod v2 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct Blockchain {
+        pub blocks: Vec<Block>,
+        pub target: U256,
+        #[serde(deserialize_with = "unreserved")]
+        pub utxos: Arc<UtxoSet>,
+        pub network: Network,
+        #[serde(default)]
+        pub snapshot_base: Option<SnapshotBase>,
+    }
+}
+
+// the saved mempool is kept in memory, the node saves it separately.
+// the first of these chains keyed utxos by transaction hash, leaving
+// one output per transaction, so the utxos are rebuilt from the blocks
+impl From<v1::Blockchain> for Blockchain {
+    fn from(old: v1::Blockchain) -> Self {
+        let mut blockchain = Blockchain {
+            blocks: Arc::new(old.blocks.into_iter().map(Arc::new).collect()),
+            target: old.target,
+            utxos: Arc::new(old.utxos),
+            utxo_commitment: OnceLock::new(),
+            mempool: Mempool::default(),
+            params: old.network.params(),
+            snapshot_base: old.snapshot_base,
+            checkpoints: BTreeMap::new(),
+            watched: WatchList::default(),
+            events: VecDeque::new(),
+            store: None,
+        };
+        blockchain.rebuild_utxos();
+        // taken back as they were, by their fees against the new utxos
+        let utxos = Arc::make_mut(&mut blockchain.utxos);
+        for (timestamp, transaction) in old.mempool {
+            let fee = fee_in(utxos, &transaction).unwrap_or(0);
+            for input in &transaction.inputs {
+                if let Some((reserved, _)) = utxos.get_mut(&input.prev_transaction_output_hash) {
+                    *reserved = true;
+                }
+            }
+            blockchain.mempool.insert(timestamp, transaction, fee);
+        }
+        blockchain
+    }
+}
+
+impl From<v2::Blockchain> for Blockchain {
+    fn from(old: v2::Blockchain) -> Self {
+        Blockchain {
+            blocks: Arc::new(old.blocks.into_iter().map(Arc::new).collect()),
+            target: old.target,
+            utxos: old.utxos,
+            utxo_commitment: OnceLock::new(),
+            mempool: Mempool::default(),
+            params: old.network.params(),
+            snapshot_base: old.snapshot_base,
+            checkpoints: BTreeMap::new(),
+            watched: WatchList::default(),
+            events: VecDeque::new(),
+            store: None,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mempool-policy"))]
+mod tests {
+    use super::*;
+    use crate::test_utils::{ChainBuilder, instant_params};
+    use crate::types::PartiallySignedTransaction;
+
+    const EXPIRY: u64 = 3;
+    const FEE: u64 = 100;
+
+    #[test]
+    fn fee_buckets_span_the_rates() {
+        let buckets = fee_buckets(&[(1, 100), (5, 200), (10, 300)], 3);
+        let ranges: Vec<(u64, u64)> = buckets
+            .iter()
+            .map(|bucket| (bucket.min_fee_rate, bucket.max_fee_rate))
+            .collect();
+        assert_eq!(ranges, [(1, 4), (5, 8), (9, 10)]);
+        let counts: Vec<u64> = buckets.iter().map(|bucket| bucket.count).collect();
+        assert_eq!(counts, [1, 1, 1]);
+    }
+
+    #[test]
+    fn fee_buckets_at_the_extremes_of_u64() {
+        let rated = [(0, 1), (u64::MAX / 2, 1), (u64::MAX, u64::MAX)];
+        for bucket_count in [1, 2, 3, 7, 1000] {
+            let buckets = fee_buckets(&rated, bucket_count);
+            assert_eq!(buckets.first().unwrap().min_fee_rate, 0);
+            assert_eq!(buckets.last().unwrap().max_fee_rate, u64::MAX);
+            assert_eq!(buckets.iter().map(|bucket| bucket.count).sum::<u64>(), 3);
+            assert!(
+                buckets
+                    .windows(2)
+                    .all(|pair| pair[0].max_fee_rate < pair[1].min_fee_rate)
+            );
+        }
+        let high = fee_buckets(&[(u64::MAX - 1, 1), (u64::MAX, 1)], 10);
+        assert_eq!(high.len(), 2);
+        assert_eq!(high[1].min_fee_rate, u64::MAX);
+    }
+
+    // the first output of parent, which key owns, paid on to key 3
+    fn spend_of(builder: &mut ChainBuilder, parent: &Transaction, key: usize) -> Transaction {
+        let output = parent.outputs[0].clone();
+        let payment = builder.output(output.value - FEE, 3);
+        let mut unsigned =
+            PartiallySignedTransaction::new(vec![(output.hash(), output)], vec![payment]);
+        unsigned.sign(builder.key(key)).unwrap();
+        unsigned.finalize().unwrap()
+    }
+
+    // admission refuses spends of unconfirmed outputs, so the pool
+    // only holds children it didn't check; put there directly, they
+    // go with the parent that expires
+    #[test]
+    fn expired_parent_takes_its_children() {
+        let mut builder = ChainBuilder::with_params(719, instant_params());
+        builder.fund(1, &[10_000]).unwrap();
+        builder.fund(2, &[10_000]).unwrap();
+        let (hash, output) = builder.spendable(1).remove(0);
+        let paid = builder.output(output.value - FEE, 1);
+        let mut unsigned = PartiallySignedTransaction::new(vec![(hash, output)], vec![paid]);
+        unsigned.expires_at_height = Some(EXPIRY);
+        unsigned.sign(builder.key(1)).unwrap();
+        let parent = unsigned.finalize().unwrap();
+        let child = spend_of(&mut builder, &parent, 1);
+        let grandchild = spend_of(&mut builder, &child, 3);
+        let lasting = builder.spend(2, 3, 5_000, FEE).unwrap();
+        let chain = builder.chain_mut();
+        assert_eq!(chain.chain_height(), EXPIRY - 1);
+        chain.add_to_mempool(parent.clone()).unwrap();
+        chain.add_to_mempool(lasting.clone()).unwrap();
+        assert!(matches!(
+            chain.add_to_mempool(child.clone()),
+            Err(SbdError::MissingInput(_))
+        ));
+        // the grandchild paying more than the child, so it's ahead of
+        // it in the pool
+        let now = Utc::now();
+        chain.mempool.insert(now, child.clone(), FEE);
+        chain.mempool.insert(now, grandchild.clone(), 2 * FEE);
+        assert_eq!(chain.mempool_parent(&child.outputs[0].hash()), Some(&child));
+
+        builder.mine_blocks(1).unwrap();
+        let chain = builder.chain();
+        let left: Vec<Hash> = chain.mempool().iter().map(|(_, tx)| tx.hash()).collect();
+        assert_eq!(left, vec![lasting.hash()]);
+        assert_eq!(chain.is_utxo_reserved(&hash), Some(false));
+        for evicted in [&parent, &child, &grandchild] {
+            assert!(chain.mempool_get(&evicted.hash()).is_none());
+            assert!(chain.mempool_parent(&evicted.outputs[0].hash()).is_none());
+        }
     }
 }
@@ -0,0 +1,82 @@
+use crate::canonical::{Canonical, encode_struct, to_bytes};
+use crate::crypto::{PrivateKey, PublicKey, Signature};
+use crate::sha256::Hash;
+use crate::utils::Saveable;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// an operator's word that the block at height is hash, signed when
+// timestamp says; nodes trusting the key install it with
+// Blockchain::apply_signed_checkpoints and never leave that block
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SignedCheckpoint {
+    pub height: u64,
+    pub hash: Hash,
+    pub timestamp: DateTime<Utc>,
+    pub signature: Signature,
+}
+
+impl SignedCheckpoint {
+    pub fn sign(
+        height: u64,
+        hash: Hash,
+        timestamp: DateTime<Utc>,
+        private_key: &PrivateKey,
+    ) -> Self {
+        let message = to_bytes(&Attestation {
+            height,
+            hash: &hash,
+            timestamp: &timestamp,
+        });
+        SignedCheckpoint {
+            height,
+            hash,
+            timestamp,
+            signature: Signature::sign_message(&message, private_key),
+        }
+    }
+
+    pub fn verify(&self, public_key: &PublicKey) -> bool {
+        let message = to_bytes(&Attestation {
+            height: self.height,
+            hash: &self.hash,
+            timestamp: &self.timestamp,
+        });
+        self.signature.verify_message(&message, public_key)
+    }
+
+    // the first of the keys that signed it
+    pub fn signer<'a>(&self, keys: &'a [PublicKey]) -> Option<&'a PublicKey> {
+        keys.iter().find(|key| self.verify(key))
+    }
+}
+
+// what a checkpoint's signature signs, as a message
+struct Attestation<'a> {
+    height: u64,
+    hash: &'a Hash,
+    timestamp: &'a DateTime<Utc>,
+}
+
+impl Canonical for Attestation<'_> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_struct(
+            out,
+            &[
+                ("height", &self.height),
+                ("hash", self.hash),
+                ("timestamp", self.timestamp),
+            ],
+        );
+    }
+}
+
+// the checkpoints an operator hands out, as one file
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CheckpointBundle {
+    pub checkpoints: Vec<SignedCheckpoint>,
+}
+
+impl Saveable for CheckpointBundle {
+    const TYPE_TAG: &'static str = "CheckpointBundle";
+}
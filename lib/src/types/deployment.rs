@@ -0,0 +1,76 @@
+use crate::params::Deployment;
+use serde::{Deserialize, Serialize};
+
+// where a deployment is in its rollout, for the blocks of one window
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeploymentState {
+    // before its start height, or a deployment the params don't define
+    Defined,
+    // windows are tallied, blocks signal for it
+    Started,
+    // a window reached the threshold, active from the next one
+    LockedIn,
+    // its rules apply
+    Active,
+    // no window reached the threshold before the timeout
+    Failed,
+}
+
+impl DeploymentState {
+    // the states miners signal in
+    pub fn is_signaling(&self) -> bool {
+        matches!(self, DeploymentState::Started | DeploymentState::LockedIn)
+    }
+
+    fn is_final(&self) -> bool {
+        matches!(self, DeploymentState::Active | DeploymentState::Failed)
+    }
+}
+
+// the state for the block at height, from the versions of the blocks
+// before it; the state only changes on the first block of a window,
+// and only the windows it's started in are tallied. an interval of 0
+// has no windows, the deployment stays defined
+pub(super) fn state_at(
+    deployment: &Deployment,
+    interval: u64,
+    height: u64,
+    version: impl Fn(u64) -> u32,
+) -> DeploymentState {
+    let mut state = DeploymentState::Defined;
+    if interval == 0 {
+        return state;
+    }
+    let mut window = interval;
+    while window <= height && !state.is_final() {
+        state = match state {
+            DeploymentState::Defined if window >= deployment.timeout_height => {
+                DeploymentState::Failed
+            }
+            DeploymentState::Defined if window >= deployment.start_height => {
+                DeploymentState::Started
+            }
+            DeploymentState::Started => {
+                let signaling = (window - interval..window)
+                    .filter(|&height| deployment.signaled_by(version(height)))
+                    .count() as u64;
+                // locked in by the last window before the timeout too
+                if signaling >= deployment.threshold {
+                    DeploymentState::LockedIn
+                } else if window >= deployment.timeout_height {
+                    DeploymentState::Failed
+                } else {
+                    DeploymentState::Started
+                }
+            }
+            DeploymentState::LockedIn => DeploymentState::Active,
+            state => state,
+        };
+        let Some(next) = window.checked_add(interval) else {
+            break;
+        };
+        window = next;
+    }
+    state
+}
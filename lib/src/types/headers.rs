@@ -0,0 +1,162 @@
+use super::{Block, BlockHeader};
+use crate::U256;
+use crate::error::{Result, SbdError};
+use crate::params::NetworkParams;
+use crate::sha256::{Hash, HashKeyedMap};
+use crate::utils::Saveable;
+use serde::{Deserialize, Serialize};
+
+// chain of block headers without their transactions,
+// validated on proof of work and linkage alone
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(from = "Vec<BlockHeader>", into = "Vec<BlockHeader>")]
+pub struct HeaderChain {
+    headers: Vec<BlockHeader>,
+    heights: HashKeyedMap<u64>,
+    total_work: U256,
+}
+
+// expected number of hashes needed to meet a target
+pub fn work(target: U256) -> U256 {
+    U256::MAX / target.saturating_add(U256::one())
+}
+
+impl HeaderChain {
+    pub fn new() -> Self {
+        HeaderChain::default()
+    }
+
+    pub fn from_blocks<'a>(blocks: impl Iterator<Item = &'a Block>) -> Self {
+        let mut chain = HeaderChain::new();
+        for block in blocks {
+            chain.push(block.header.clone());
+        }
+        chain
+    }
+
+    pub fn headers(&self) -> &[BlockHeader] {
+        &self.headers
+    }
+
+    pub fn height(&self) -> u64 {
+        self.headers.len() as u64
+    }
+
+    pub fn tip(&self) -> Option<&BlockHeader> {
+        self.headers.last()
+    }
+
+    pub fn total_work(&self) -> U256 {
+        self.total_work
+    }
+
+    // height of a header in this chain
+    pub fn height_of(&self, hash: &Hash) -> Option<u64> {
+        self.heights.get(hash).copied()
+    }
+
+    pub fn get(&self, height: u64) -> Option<&BlockHeader> {
+        self.headers.get(height as usize)
+    }
+
+    // validate a header against the tip and append it; the target
+    // is the header's own, see next_target for the schedule's
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<()> {
+        match self.headers.last() {
+            None => {
+                if header.prev_block_hash != Hash::zero() {
+                    return Err(SbdError::InvalidBlockHeader);
+                }
+            }
+            Some(tip) => {
+                if header.prev_block_hash != tip.hash() || header.timestamp <= tip.timestamp {
+                    return Err(SbdError::InvalidBlockHeader);
+                }
+            }
+        }
+        if !header.hash().matches_target(header.target) {
+            return Err(SbdError::InvalidProofOfWork);
+        }
+        self.push(header);
+        Ok(())
+    }
+
+    // the target the next header must have, as Blockchain adjusts
+    // it: min_target for the first, then the tip's until a retarget
+    // over the interval ending at the tip
+    pub fn next_target(&self, params: &NetworkParams) -> U256 {
+        let Some(tip) = self.tip() else {
+            return params.min_target;
+        };
+        let height = self.height();
+        let interval = params.difficulty_update_interval;
+        if interval == 0 || !height.is_multiple_of(interval) {
+            return tip.target;
+        }
+        match self.get(height - interval) {
+            Some(start) => params.retarget(tip.target, start.timestamp, tip.timestamp),
+            None => tip.target,
+        }
+    }
+
+    // drop every header at or above the given height
+    pub fn truncate(&mut self, height: u64) {
+        if height >= self.height() {
+            return;
+        }
+        for header in self.headers.drain(height as usize..) {
+            self.heights.remove(&header.hash());
+            self.total_work = self.total_work.saturating_sub(work(header.target));
+        }
+    }
+
+    // hashes of the tip, then exponentially sparser
+    // back to the genesis header
+    pub fn locator(&self) -> Vec<Hash> {
+        let mut locator = vec![];
+        let mut step = 1;
+        let mut height = self.headers.len();
+        while height > 0 {
+            let index = height - 1;
+            locator.push(self.headers[index].hash());
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        if let Some(genesis) = self.headers.first()
+            && locator.last() != Some(&genesis.hash())
+        {
+            locator.push(genesis.hash());
+        }
+        locator
+    }
+
+    fn push(&mut self, header: BlockHeader) {
+        self.heights
+            .insert(header.hash(), self.headers.len() as u64);
+        self.total_work = self.total_work.saturating_add(work(header.target));
+        self.headers.push(header);
+    }
+}
+
+impl From<Vec<BlockHeader>> for HeaderChain {
+    fn from(headers: Vec<BlockHeader>) -> Self {
+        let mut chain = HeaderChain::new();
+        for header in headers {
+            chain.push(header);
+        }
+        chain
+    }
+}
+
+impl From<HeaderChain> for Vec<BlockHeader> {
+    fn from(chain: HeaderChain) -> Self {
+        chain.headers
+    }
+}
+
+// saved as the bare headers, heights and work are rebuilt on load
+impl Saveable for HeaderChain {
+    const TYPE_TAG: &'static str = "HeaderChain";
+}
@@ -0,0 +1,206 @@
+use super::Transaction;
+use crate::sha256::{Hash, HashKeyedMap};
+use chrono::{DateTime, Utc};
+
+// pending transactions with when each arrived, in order of the fee
+// each paid when admitted, lowest first; the best to mine are at the
+// end, and an insertion finds its place instead of sorting them all.
+// entries are indexed by txid, by the outputs they spend and by those
+// they create, so none of those lookups scans the pool
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Mempool {
+    entries: Vec<(DateTime<Utc>, Transaction)>,
+    // the fee of the entry at the same index, then the order it was
+    // inserted in, which orders those of the same fee
+    keys: Vec<(u64, u64)>,
+    // the fee per byte of every entry, then the order it was inserted
+    // in, lowest first; what fee estimates rank transactions by, kept
+    // in order the same way
+    rates: Vec<(u64, u64)>,
+    inserted: u64,
+    index: Index,
+}
+
+// where an entry is in keys and in rates
+#[derive(Clone, Copy, Debug)]
+struct EntryKeys {
+    fee: (u64, u64),
+    rate: (u64, u64),
+}
+
+#[derive(Clone, Debug, Default)]
+struct Index {
+    // the keys of each entry by txid, to binary search for
+    by_txid: HashKeyedMap<EntryKeys>,
+    // txid of the entry spending each output
+    spenders: HashKeyedMap<Hash>,
+    // txid of the entry creating each output
+    creators: HashKeyedMap<Hash>,
+}
+
+impl Mempool {
+    pub(crate) fn entries(&self) -> &[(DateTime<Utc>, Transaction)] {
+        &self.entries
+    }
+
+    #[cfg(feature = "mempool-policy")]
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn iter(&self) -> std::slice::Iter<'_, (DateTime<Utc>, Transaction)> {
+        self.entries.iter()
+    }
+
+    // each entry with its fee, lowest fee first
+    #[cfg(feature = "mempool-policy")]
+    pub(crate) fn with_fees(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = (&(DateTime<Utc>, Transaction), u64)> {
+        self.entries
+            .iter()
+            .zip(self.keys.iter().map(|&(fee, _)| fee))
+    }
+
+    // the fee per byte of each entry, lowest first
+    #[cfg(feature = "mempool-policy")]
+    pub(crate) fn rates(&self) -> impl DoubleEndedIterator<Item = u64> + '_ {
+        self.rates.iter().map(|&(rate, _)| rate)
+    }
+
+    pub(crate) fn get(&self, txid: &Hash) -> Option<&(DateTime<Utc>, Transaction)> {
+        self.position(txid).map(|index| &self.entries[index])
+    }
+
+    pub(crate) fn contains(&self, txid: &Hash) -> bool {
+        self.index.by_txid.contains_key(txid)
+    }
+
+    // txid of the entry spending the output
+    #[cfg(feature = "mempool-policy")]
+    pub(crate) fn spender(&self, output_hash: &Hash) -> Option<Hash> {
+        self.index.spenders.get(output_hash).copied()
+    }
+
+    // the entry creating the output, the parent of a transaction spending it
+    pub(crate) fn creator(&self, output_hash: &Hash) -> Option<&(DateTime<Utc>, Transaction)> {
+        self.index
+            .creators
+            .get(output_hash)
+            .and_then(|txid| self.get(txid))
+    }
+
+    // after those of the same fee, which arrived before it; a
+    // transaction already in the pool is left as it is
+    pub(crate) fn insert(&mut self, timestamp: DateTime<Utc>, transaction: Transaction, fee: u64) {
+        transaction.freeze();
+        let txid = transaction.hash();
+        if self.index.by_txid.contains_key(&txid) {
+            return;
+        }
+        let keys = EntryKeys {
+            fee: (fee, self.inserted),
+            rate: (fee / transaction.size().max(1), self.inserted),
+        };
+        self.inserted += 1;
+        let index = self.keys.partition_point(|&other| other <= keys.fee);
+        self.index.insert(&transaction, keys);
+        self.entries.insert(index, (timestamp, transaction));
+        self.keys.insert(index, keys.fee);
+        let rate_index = self.rates.partition_point(|&other| other <= keys.rate);
+        self.rates.insert(rate_index, keys.rate);
+        self.check();
+    }
+
+    // the entry with the txid, if it is in the pool
+    #[cfg(feature = "mempool-policy")]
+    pub(crate) fn remove(&mut self, txid: &Hash) -> Option<(DateTime<Utc>, Transaction)> {
+        let index = self.position(txid)?;
+        let rate = self.index.by_txid[txid].rate;
+        self.keys.remove(index);
+        if let Ok(rate_index) = self.rates.binary_search(&rate) {
+            self.rates.remove(rate_index);
+        }
+        let entry = self.entries.remove(index);
+        self.index.remove(&entry.1);
+        self.check();
+        Some(entry)
+    }
+
+    pub(crate) fn retain(&mut self, mut keep: impl FnMut(&(DateTime<Utc>, Transaction)) -> bool) {
+        let kept: Vec<bool> = self.entries.iter().map(&mut keep).collect();
+        let mut removed_rates = vec![];
+        for ((_, transaction), _) in self.entries.iter().zip(&kept).filter(|(_, kept)| !**kept) {
+            if let Some(keys) = self.index.by_txid.get(&transaction.hash()) {
+                removed_rates.push(keys.rate);
+            }
+            self.index.remove(transaction);
+        }
+        removed_rates.sort_unstable();
+        self.rates
+            .retain(|rate| removed_rates.binary_search(rate).is_err());
+        let mut entry_kept = kept.iter();
+        self.entries.retain(|_| *entry_kept.next().unwrap());
+        let mut key_kept = kept.iter();
+        self.keys.retain(|_| *key_kept.next().unwrap());
+        self.check();
+    }
+
+    fn position(&self, txid: &Hash) -> Option<usize> {
+        let keys = self.index.by_txid.get(txid)?;
+        self.keys.binary_search(&keys.fee).ok()
+    }
+
+    fn check(&self) {
+        debug_assert_eq!(
+            self.entries.len(),
+            self.keys.len(),
+            "BUG: mempool fees out of step"
+        );
+        debug_assert_eq!(
+            self.entries.len(),
+            self.index.by_txid.len(),
+            "BUG: mempool txid index out of step"
+        );
+        debug_assert_eq!(
+            self.entries.len(),
+            self.rates.len(),
+            "BUG: mempool rates out of step"
+        );
+        debug_assert!(self.keys.is_sorted(), "BUG: mempool out of fee order");
+        debug_assert!(self.rates.is_sorted(), "BUG: mempool out of rate order");
+    }
+}
+
+impl Index {
+    fn insert(&mut self, transaction: &Transaction, keys: EntryKeys) {
+        let txid = transaction.hash();
+        self.by_txid.insert(txid, keys);
+        for input in &transaction.inputs {
+            self.spenders
+                .insert(input.prev_transaction_output_hash, txid);
+        }
+        for output in &transaction.outputs {
+            self.creators.insert(output.hash(), txid);
+        }
+    }
+
+    fn remove(&mut self, transaction: &Transaction) {
+        let txid = transaction.hash();
+        self.by_txid.remove(&txid);
+        // only where it still points at this transaction: the pool
+        // never holds two spending one output, but a caller of insert
+        // isn't made to check that
+        for input in &transaction.inputs {
+            if self.spenders.get(&input.prev_transaction_output_hash) == Some(&txid) {
+                self.spenders.remove(&input.prev_transaction_output_hash);
+            }
+        }
+        for output in &transaction.outputs {
+            let hash = output.hash();
+            if self.creators.get(&hash) == Some(&txid) {
+                self.creators.remove(&hash);
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use super::{Block, ChainBatch, ChainStore, ChainTip, TransactionOutput, UtxoChange};
+use crate::error::{Result, SbdError};
+use crate::sha256::{Hash, HashKeyedMap};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sled::Transactional;
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::path::Path;
+
+const TIP_KEY: &[u8] = b"tip";
+
+// a sled database: blocks keyed by big endian height, so they
+// iterate in order, block heights and utxos keyed by hash
+#[derive(Clone, Debug)]
+pub struct SledStore {
+    db: sled::Db,
+    blocks: sled::Tree,
+    heights: sled::Tree,
+    utxos: sled::Tree,
+    meta: sled::Tree,
+}
+
+impl SledStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let db = sled::open(path).map_err(storage_error)?;
+        let tree = |name: &str| db.open_tree(name).map_err(storage_error);
+        Ok(SledStore {
+            blocks: tree("blocks")?,
+            heights: tree("heights")?,
+            utxos: tree("utxos")?,
+            meta: tree("meta")?,
+            db,
+        })
+    }
+}
+
+fn storage_error(e: sled::Error) -> SbdError {
+    SbdError::Storage(e.into())
+}
+
+// Display is unambiguous hex, unlike Hash::as_bytes
+fn hash_key(hash: &Hash) -> Vec<u8> {
+    hash.to_string().into_bytes()
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut data = vec![];
+    ciborium::into_writer(value, &mut data).map_err(SbdError::encode("store record"))?;
+    Ok(data)
+}
+
+fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+    ciborium::from_reader(data).map_err(SbdError::decode("store record"))
+}
+
+impl ChainStore for SledStore {
+    fn get_block_by_height(&self, height: u64) -> Result<Option<Block>> {
+        match self
+            .blocks
+            .get(height.to_be_bytes())
+            .map_err(storage_error)?
+        {
+            Some(data) => Ok(Some(decode(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_block_by_hash(&self, hash: &Hash) -> Result<Option<Block>> {
+        match self.heights.get(hash_key(hash)).map_err(storage_error)? {
+            Some(height) => {
+                let height =
+                    u64::from_be_bytes(height.as_ref().try_into().map_err(|_| {
+                        IoError::new(IoErrorKind::InvalidData, "Corrupt block height")
+                    })?);
+                self.get_block_by_height(height)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn get_utxo(&self, hash: &Hash) -> Result<Option<TransactionOutput>> {
+        match self.utxos.get(hash_key(hash)).map_err(storage_error)? {
+            Some(data) => Ok(Some(decode(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn tip(&self) -> Result<Option<ChainTip>> {
+        match self.meta.get(TIP_KEY).map_err(storage_error)? {
+            Some(data) => Ok(Some(decode(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    // one sled transaction over all trees
+    fn commit(&mut self, batch: ChainBatch) -> Result<()> {
+        // encoded up front, the transaction closure may run more than once
+        let block = match &batch.block {
+            Some(block) => Some((
+                (batch.tip.height - 1).to_be_bytes(),
+                hash_key(&block.hash()),
+                encode(block)?,
+            )),
+            None => None,
+        };
+        let mut changes = Vec::with_capacity(batch.changes.len());
+        for change in &batch.changes {
+            changes.push(match change {
+                UtxoChange::Put(hash, output) => (hash_key(hash), Some(encode(output)?)),
+                UtxoChange::Remove(hash) => (hash_key(hash), None),
+            });
+        }
+        let tip = encode(&batch.tip)?;
+        (&self.blocks, &self.heights, &self.utxos, &self.meta)
+            .transaction(|(blocks, heights, utxos, meta)| {
+                if let Some((height, hash, data)) = &block {
+                    blocks.insert(&height[..], data.as_slice())?;
+                    heights.insert(hash.as_slice(), &height[..])?;
+                }
+                for (hash, output) in &changes {
+                    match output {
+                        Some(data) => utxos.insert(hash.as_slice(), data.as_slice())?,
+                        None => utxos.remove(hash.as_slice())?,
+                    };
+                }
+                meta.insert(TIP_KEY, tip.as_slice())?;
+                Ok::<(), ConflictableTransactionError<()>>(())
+            })
+            .map_err(|e| match e {
+                TransactionError::Storage(e) => storage_error(e),
+                TransactionError::Abort(()) => unreachable!("commit never aborts"),
+            })
+    }
+
+    fn load(&self) -> Result<(Vec<Block>, HashKeyedMap<TransactionOutput>)> {
+        let mut blocks = vec![];
+        for entry in self.blocks.iter() {
+            let (_, data) = entry.map_err(storage_error)?;
+            blocks.push(decode(&data)?);
+        }
+        let mut utxos = HashKeyedMap::default();
+        for entry in self.utxos.iter() {
+            let (key, data) = entry.map_err(storage_error)?;
+            let hash = std::str::from_utf8(&key)
+                .ok()
+                .and_then(Hash::from_hex)
+                .ok_or_else(|| IoError::new(IoErrorKind::InvalidData, "Corrupt utxo key"))?;
+            utxos.insert(hash, decode(&data)?);
+        }
+        Ok((blocks, utxos))
+    }
+
+    // sled syncs in the background, this waits for it
+    fn flush(&mut self) -> Result<()> {
+        self.db.flush().map_err(storage_error)?;
+        Ok(())
+    }
+}
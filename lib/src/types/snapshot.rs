@@ -0,0 +1,146 @@
+use super::{HeaderChain, TransactionOutput};
+use crate::U256;
+use crate::error::{Result, SbdError};
+use crate::params::Network;
+use crate::sha256::{Hash, HashKeyedMap};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+// a utxo snapshot is a header and count entries sorted by hash, each
+// as cbor, then the commitment, a hash of those bytes, as 64 hex
+// digits; the same chain always gives the same bytes, so nodes can
+// compare commitments
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SnapshotHeader {
+    pub network: Network,
+    pub height: u64,
+    pub tip: Hash,
+    // target for the next block
+    pub target: U256,
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SnapshotEntry {
+    pub hash: Hash,
+    pub output: TransactionOutput,
+    // height of the block, for coinbase outputs
+    pub coinbase_height: Option<u64>,
+}
+
+// where the chain of an imported snapshot starts
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SnapshotBase {
+    pub height: u64,
+    pub tip: Hash,
+    // heights of the coinbase outputs in the snapshot
+    pub coinbase_heights: HashKeyedMap<u64>,
+    // from the genesis header to tip, for chains from
+    // Blockchain::from_snapshot, which carry on from them
+    #[serde(default)]
+    pub headers: HeaderChain,
+    // of the utxos in the snapshot, for Blockchain::connect_history
+    // to check the blocks below the base against
+    #[serde(default = "Hash::zero")]
+    pub utxo_commitment: Hash,
+}
+
+// a commitment to a utxo set, the sum of the hashes of its outputs
+// wrapping at 2^256. a sum is the same whatever order the outputs
+// are added in, and an output is added or taken out again without
+// the rest of the set, so a block changes it by its delta alone
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UtxoCommitment(U256);
+
+impl UtxoCommitment {
+    // of a whole set, by the hashes utxos are keyed by
+    pub fn of<'a>(hashes: impl IntoIterator<Item = &'a Hash>) -> Self {
+        let mut commitment = UtxoCommitment::default();
+        for hash in hashes {
+            commitment.insert(hash);
+        }
+        commitment
+    }
+
+    pub fn insert(&mut self, hash: &Hash) {
+        self.0 = self.0.overflowing_add(U256::from(*hash)).0;
+    }
+
+    pub fn remove(&mut self, hash: &Hash) {
+        self.0 = self.0.overflowing_sub(U256::from(*hash)).0;
+    }
+
+    // what a header commits to
+    pub fn hash(&self) -> Hash {
+        Hash::hash(&self.0)
+    }
+}
+
+// entries must already be sorted by hash
+pub fn write_snapshot<W: Write>(
+    mut writer: W,
+    header: &SnapshotHeader,
+    entries: &[SnapshotEntry],
+) -> Result<Hash> {
+    let mut data = vec![];
+    write_item(&mut data, header)?;
+    for entry in entries {
+        write_item(&mut data, entry)?;
+    }
+    let commitment = Hash::hash_bytes(&data);
+    writer.write_all(&data)?;
+    writer.write_all(commitment_hex(&commitment).as_bytes())?;
+    writer.flush()?;
+    Ok(commitment)
+}
+
+pub fn read_snapshot<R: Read>(reader: R) -> Result<(SnapshotHeader, Vec<SnapshotEntry>)> {
+    // what was read is kept to check it against the commitment
+    let mut reader = Recorder {
+        inner: reader,
+        data: vec![],
+    };
+    let header: SnapshotHeader = read_item(&mut reader)?;
+    // not preallocated, the count isn't trusted yet
+    let mut entries = vec![];
+    for _ in 0..header.count {
+        entries.push(read_item(&mut reader)?);
+    }
+    let expected = Hash::hash_bytes(&reader.data);
+    let mut commitment = [0u8; 64];
+    reader.inner.read_exact(&mut commitment)?;
+    // compared as bytes, any other spelling of the hash is tampering too
+    if commitment != commitment_hex(&expected).as_bytes() {
+        return Err(SbdError::SnapshotMismatch {
+            expected,
+            found: String::from_utf8_lossy(&commitment).into_owned(),
+        });
+    }
+    Ok((header, entries))
+}
+
+fn commitment_hex(commitment: &Hash) -> String {
+    format!("{:0>64}", commitment.to_string())
+}
+
+struct Recorder<R> {
+    inner: R,
+    data: Vec<u8>,
+}
+
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.data.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn write_item<W: Write, T: Serialize>(writer: &mut W, item: &T) -> Result<()> {
+    ciborium::into_writer(item, writer).map_err(SbdError::encode("snapshot"))
+}
+
+fn read_item<R: Read, T: DeserializeOwned>(reader: &mut R) -> Result<T> {
+    ciborium::from_reader(reader).map_err(SbdError::decode("snapshot"))
+}
@@ -0,0 +1,546 @@
+use super::{Block, TransactionOutput};
+use crate::U256;
+use crate::error::{Result, SbdError};
+use crate::params::Network;
+use crate::sha256::{Hash, HashKeyedMap, checksum};
+#[cfg(feature = "std-fs")]
+use crate::utils::BackupPolicy;
+use crate::utils::Saveable;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+#[cfg(feature = "std-fs")]
+use std::fs::{self, File, OpenOptions};
+#[cfg(feature = "std-fs")]
+use std::io::{BufReader, Seek, SeekFrom, Write};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult};
+#[cfg(feature = "std-fs")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "std-fs")]
+use tracing::warn;
+
+// where a Blockchain persists its blocks and utxo set; the chain
+// keeps working in memory and commits every connected block to
+// the store as one batch, so a store never holds half a block
+pub trait ChainStore: Debug + Send + Sync {
+    // the block at a height, the first block being at height 0
+    fn get_block_by_height(&self, height: u64) -> Result<Option<Block>>;
+    fn get_block_by_hash(&self, hash: &Hash) -> Result<Option<Block>>;
+    fn get_utxo(&self, hash: &Hash) -> Result<Option<TransactionOutput>>;
+    // the last committed tip, None while the store is empty
+    fn tip(&self) -> Result<Option<ChainTip>>;
+    // apply all of the batch or none of it
+    fn commit(&mut self, batch: ChainBatch) -> Result<()>;
+    // every block and the utxo set, to open a chain over the store
+    fn load(&self) -> Result<(Vec<Block>, HashKeyedMap<TransactionOutput>)>;
+    // make everything committed durable
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// chain metadata as of the last committed block
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChainTip {
+    pub network: Network,
+    // number of blocks
+    pub height: u64,
+    pub hash: Hash,
+    // target for the next block
+    pub target: U256,
+}
+
+#[derive(Clone, Debug)]
+pub enum UtxoChange {
+    Put(Hash, TransactionOutput),
+    Remove(Hash),
+}
+
+// the writes making up one commit, applied in order
+#[derive(Clone, Debug)]
+pub struct ChainBatch {
+    pub tip: ChainTip,
+    pub block: Option<Block>,
+    pub changes: Vec<UtxoChange>,
+}
+
+impl ChainBatch {
+    pub fn new(tip: ChainTip) -> Self {
+        ChainBatch {
+            tip,
+            block: None,
+            changes: vec![],
+        }
+    }
+
+    // the batch connecting a block: its inputs spent, its outputs added
+    pub fn connect(block: Block, tip: ChainTip) -> Self {
+        let mut batch = ChainBatch::new(tip);
+        for transaction in &block.transactions {
+            for input in &transaction.inputs {
+                batch.remove_utxo(input.prev_transaction_output_hash);
+            }
+            for output in &transaction.outputs {
+                batch.put_utxo(output.hash(), output.clone());
+            }
+        }
+        batch.put_block(block);
+        batch
+    }
+
+    pub fn put_block(&mut self, block: Block) {
+        self.block = Some(block);
+    }
+
+    pub fn put_utxo(&mut self, hash: Hash, output: TransactionOutput) {
+        self.changes.push(UtxoChange::Put(hash, output));
+    }
+
+    pub fn remove_utxo(&mut self, hash: Hash) {
+        self.changes.push(UtxoChange::Remove(hash));
+    }
+
+    fn apply_utxos(&self, utxos: &mut HashKeyedMap<TransactionOutput>) {
+        for change in &self.changes {
+            match change {
+                UtxoChange::Put(hash, output) => {
+                    utxos.insert(*hash, output.clone());
+                }
+                UtxoChange::Remove(hash) => {
+                    utxos.remove(hash);
+                }
+            }
+        }
+    }
+}
+
+// everything in memory, for chains that needn't outlive the process
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    blocks: Vec<Block>,
+    heights: HashKeyedMap<u64>,
+    utxos: HashKeyedMap<TransactionOutput>,
+    tip: Option<ChainTip>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ChainStore for MemoryStore {
+    fn get_block_by_height(&self, height: u64) -> Result<Option<Block>> {
+        Ok(self.blocks.get(height as usize).cloned())
+    }
+
+    fn get_block_by_hash(&self, hash: &Hash) -> Result<Option<Block>> {
+        match self.heights.get(hash) {
+            Some(height) => self.get_block_by_height(*height),
+            None => Ok(None),
+        }
+    }
+
+    fn get_utxo(&self, hash: &Hash) -> Result<Option<TransactionOutput>> {
+        Ok(self.utxos.get(hash).cloned())
+    }
+
+    fn tip(&self) -> Result<Option<ChainTip>> {
+        Ok(self.tip)
+    }
+
+    fn commit(&mut self, mut batch: ChainBatch) -> Result<()> {
+        batch.apply_utxos(&mut self.utxos);
+        if let Some(block) = batch.block.take() {
+            self.heights.insert(block.hash(), self.blocks.len() as u64);
+            self.blocks.push(block);
+        }
+        self.tip = Some(batch.tip);
+        Ok(())
+    }
+
+    fn load(&self) -> Result<(Vec<Block>, HashKeyedMap<TransactionOutput>)> {
+        Ok((self.blocks.clone(), self.utxos.clone()))
+    }
+}
+
+// a chain data directory: blocks.dat holds every block with the tip
+// after it as a u64 length, a checksum and cbor, and is only ever
+// appended to; manifest.cbor holds the utxo set as of a tip and is
+// rewritten every MANIFEST_INTERVAL blocks, blocks past it are
+// replayed when the store is opened
+pub const MANIFEST_INTERVAL: u64 = 100;
+#[cfg(feature = "std-fs")]
+const BLOCKS_FILE: &str = "blocks.dat";
+#[cfg(feature = "std-fs")]
+const MANIFEST_FILE: &str = "manifest.cbor";
+const RECORD_HEADER_SIZE: usize = 8 + 4;
+
+// utxo set as of the first tip.height blocks of blocks.dat
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Manifest {
+    pub tip: ChainTip,
+    pub utxos: HashKeyedMap<TransactionOutput>,
+}
+
+impl Saveable for Manifest {
+    const TYPE_TAG: &'static str = "Manifest";
+}
+
+// blocks stay on disk and are read back when asked for,
+// only their offsets and the utxo set are kept in memory
+#[cfg(feature = "std-fs")]
+#[derive(Clone, Debug)]
+pub struct BlockStore {
+    dir: PathBuf,
+    // zstd compress the manifest
+    compress: bool,
+    // back up the manifest before it's rewritten
+    backup: Option<BackupPolicy>,
+    // where each block's record starts in blocks.dat
+    offsets: Vec<u64>,
+    end: u64,
+    heights: HashKeyedMap<u64>,
+    utxos: HashKeyedMap<TransactionOutput>,
+    tip: Option<ChainTip>,
+    // blocks.dat mapped as of the last open or flush,
+    // records appended since are read from the file
+    #[cfg(feature = "mmap")]
+    map: Option<std::sync::Arc<memmap2::Mmap>>,
+}
+
+#[cfg(feature = "std-fs")]
+impl BlockStore {
+    // open or create the directory; a record torn by
+    // a crash mid-append is cut off
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let mut store = BlockStore {
+            dir: dir.as_ref().to_path_buf(),
+            compress: false,
+            backup: None,
+            offsets: vec![],
+            end: 0,
+            heights: HashKeyedMap::default(),
+            utxos: HashKeyedMap::default(),
+            tip: None,
+            #[cfg(feature = "mmap")]
+            map: None,
+        };
+        fs::create_dir_all(&store.dir)?;
+        let records = store.read_records()?;
+        let manifest_path = store.dir.join(MANIFEST_FILE);
+        let manifest = if manifest_path.exists() {
+            Some(Manifest::load_from_file(manifest_path)?)
+        } else {
+            None
+        };
+        // a manifest ahead of the blocks can't be trusted, replay all
+        let mut replay_from = 0;
+        if let Some(manifest) = manifest
+            && manifest.tip.height <= records.len() as u64
+        {
+            replay_from = manifest.tip.height;
+            store.utxos = manifest.utxos;
+            store.tip = Some(manifest.tip);
+        }
+        for (height, (offset, tip, block)) in records.into_iter().enumerate() {
+            store.offsets.push(offset);
+            store.heights.insert(block.hash(), height as u64);
+            if height as u64 >= replay_from {
+                ChainBatch::connect(block, tip).apply_utxos(&mut store.utxos);
+                store.tip = Some(tip);
+            }
+        }
+        Ok(store)
+    }
+
+    // open and serve blocks from a memory map of blocks.dat, falling
+    // back to reading the file where it can't be mapped
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let mut store = BlockStore::open(dir)?;
+        store.remap();
+        Ok(store)
+    }
+
+    // map what's in blocks.dat now; the file is only appended to,
+    // truncating it under the map would fault the reads
+    #[cfg(feature = "mmap")]
+    fn remap(&mut self) {
+        self.map = None;
+        if self.end == 0 {
+            return;
+        }
+        // safety: the mapped bytes are validated like any read ones
+        let map =
+            File::open(self.blocks_path()).and_then(|file| unsafe { memmap2::Mmap::map(&file) });
+        match map {
+            Ok(map) => self.map = Some(std::sync::Arc::new(map)),
+            Err(e) => warn!(
+                path = %self.blocks_path().display(),
+                reason = %e,
+                "can't map the block file, reading it instead"
+            ),
+        }
+    }
+
+    // the block of the record starting at an offset into blocks.dat
+    pub fn get_block_by_offset(&self, offset: u64) -> Result<Option<Block>> {
+        if offset >= self.end {
+            return Ok(None);
+        }
+        #[cfg(feature = "mmap")]
+        if let Some(map) = &self.map
+            && offset < map.len() as u64
+        {
+            let (_, block, _) = parse_record(map, offset)?;
+            return Ok(Some(block));
+        }
+        let mut file = File::open(self.blocks_path())?;
+        file.seek(SeekFrom::Start(offset))?;
+        let (_, block, _) = read_record(&mut BufReader::new(file))?;
+        Ok(Some(block))
+    }
+
+    // where the record of the block at a height starts
+    pub fn block_offset(&self, height: u64) -> Option<u64> {
+        self.offsets.get(height as usize).copied()
+    }
+
+    pub fn set_compress(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+
+    pub fn set_backup(&mut self, backup: Option<BackupPolicy>) {
+        self.backup = backup;
+    }
+
+    fn blocks_path(&self) -> PathBuf {
+        self.dir.join(BLOCKS_FILE)
+    }
+
+    // every record with its offset, setting where the next one goes
+    fn read_records(&mut self) -> IoResult<Vec<(u64, ChainTip, Block)>> {
+        let path = self.blocks_path();
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        let file = File::open(&path)?;
+        let length = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let mut records = vec![];
+        let mut offset = 0u64;
+        while offset < length {
+            match read_record(&mut reader) {
+                Ok((tip, block, size)) => {
+                    records.push((offset, tip, block));
+                    offset += (RECORD_HEADER_SIZE + size) as u64;
+                }
+                Err(e)
+                    if e.kind() == IoErrorKind::UnexpectedEof
+                        || e.kind() == IoErrorKind::InvalidData =>
+                {
+                    warn!(
+                        path = %path.display(),
+                        bytes = length - offset,
+                        "dropping a partially written block"
+                    );
+                    OpenOptions::new()
+                        .write(true)
+                        .open(&path)?
+                        .set_len(offset)?;
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        self.end = offset;
+        Ok(records)
+    }
+
+    // append a block and sync it to disk
+    fn append(&mut self, tip: &ChainTip, block: &Block) -> IoResult<()> {
+        let mut data = vec![];
+        ciborium::into_writer(&(tip, block), &mut data)
+            .map_err(SbdError::encode("Block record"))?;
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + data.len());
+        record.extend_from_slice(&(data.len() as u64).to_be_bytes());
+        record.extend_from_slice(&checksum(&data));
+        record.extend_from_slice(&data);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.blocks_path())?;
+        file.write_all(&record)?;
+        file.sync_data()?;
+        self.offsets.push(self.end);
+        self.end += record.len() as u64;
+        Ok(())
+    }
+
+    fn write_manifest(&self) -> IoResult<()> {
+        let Some(tip) = self.tip else {
+            return Ok(());
+        };
+        let manifest = Manifest {
+            tip,
+            utxos: self.utxos.clone(),
+        };
+        let save = |path: &Path| {
+            if self.compress {
+                manifest.save_compressed_to_file(path)
+            } else {
+                manifest.save_to_file(path)
+            }
+        };
+        let path = self.dir.join(MANIFEST_FILE);
+        match &self.backup {
+            Some(policy) => policy.save(path, save),
+            None => save(&path),
+        }
+    }
+}
+
+#[cfg(feature = "std-fs")]
+impl ChainStore for BlockStore {
+    fn get_block_by_height(&self, height: u64) -> Result<Option<Block>> {
+        match self.block_offset(height) {
+            Some(offset) => self.get_block_by_offset(offset),
+            None => Ok(None),
+        }
+    }
+
+    fn get_block_by_hash(&self, hash: &Hash) -> Result<Option<Block>> {
+        match self.heights.get(hash) {
+            Some(height) => self.get_block_by_height(*height),
+            None => Ok(None),
+        }
+    }
+
+    fn get_utxo(&self, hash: &Hash) -> Result<Option<TransactionOutput>> {
+        Ok(self.utxos.get(hash).cloned())
+    }
+
+    fn tip(&self) -> Result<Option<ChainTip>> {
+        Ok(self.tip)
+    }
+
+    // the appended record is the commit, replaying it
+    // redoes the utxo changes
+    fn commit(&mut self, batch: ChainBatch) -> Result<()> {
+        let Some(block) = &batch.block else {
+            return Err(IoError::new(
+                IoErrorKind::InvalidInput,
+                "BlockStore can only commit batches adding a block",
+            )
+            .into());
+        };
+        self.append(&batch.tip, block)?;
+        self.heights.insert(block.hash(), batch.tip.height - 1);
+        batch.apply_utxos(&mut self.utxos);
+        self.tip = Some(batch.tip);
+        if batch.tip.height.is_multiple_of(MANIFEST_INTERVAL) {
+            self.write_manifest()?;
+        }
+        Ok(())
+    }
+
+    fn load(&self) -> Result<(Vec<Block>, HashKeyedMap<TransactionOutput>)> {
+        let mut blocks = Vec::with_capacity(self.offsets.len());
+        #[cfg(feature = "mmap")]
+        if let Some(map) = &self.map
+            && map.len() as u64 == self.end
+        {
+            for offset in &self.offsets {
+                blocks.push(parse_record(map, *offset)?.1);
+            }
+            return Ok((blocks, self.utxos.clone()));
+        }
+        if !self.offsets.is_empty() {
+            let mut reader = BufReader::new(File::open(self.blocks_path())?);
+            for _ in &self.offsets {
+                blocks.push(read_record(&mut reader)?.1);
+            }
+        }
+        Ok((blocks, self.utxos.clone()))
+    }
+
+    // a manifest for the current tip, so opening needn't replay anything
+    fn flush(&mut self) -> Result<()> {
+        self.write_manifest()?;
+        #[cfg(feature = "mmap")]
+        if self.map.is_some() {
+            self.remap();
+        }
+        Ok(())
+    }
+}
+
+// the block of the next record of a blocks.dat stream, None at its end
+pub fn read_block_record<R: Read>(reader: &mut R) -> IoResult<Option<Block>> {
+    let mut first = [0u8; 1];
+    loop {
+        match reader.read(&mut first) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(e) if e.kind() == IoErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    let (_, block, _) = read_record(&mut (&first[..]).chain(reader))?;
+    Ok(Some(block))
+}
+
+// one record and the size of its cbor
+fn read_record<R: Read>(reader: &mut R) -> IoResult<(ChainTip, Block, usize)> {
+    let mut header = [0u8; RECORD_HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+    let size = u64::from_be_bytes(header[..8].try_into().unwrap());
+    // blocks travel in messages, so none can be larger
+    if size > crate::MAX_MESSAGE_SIZE {
+        return Err(IoError::new(
+            IoErrorKind::InvalidData,
+            "Block record too large",
+        ));
+    }
+    let mut data = vec![0u8; size as usize];
+    reader.read_exact(&mut data)?;
+    decode_record(&header, &data)
+}
+
+// one record from a mapped blocks.dat, which is as untrusted as
+// the file, so nothing is read past the end of the map
+#[cfg(feature = "mmap")]
+fn parse_record(map: &[u8], offset: u64) -> IoResult<(ChainTip, Block, usize)> {
+    let truncated = || IoError::new(IoErrorKind::UnexpectedEof, "Block record truncated");
+    let start = usize::try_from(offset).map_err(|_| truncated())?;
+    let header = start
+        .checked_add(RECORD_HEADER_SIZE)
+        .and_then(|end| map.get(start..end))
+        .ok_or_else(truncated)?;
+    let size = u64::from_be_bytes(header[..8].try_into().unwrap());
+    if size > crate::MAX_MESSAGE_SIZE {
+        return Err(IoError::new(
+            IoErrorKind::InvalidData,
+            "Block record too large",
+        ));
+    }
+    let data_start = start + RECORD_HEADER_SIZE;
+    let data = data_start
+        .checked_add(size as usize)
+        .and_then(|end| map.get(data_start..end))
+        .ok_or_else(truncated)?;
+    decode_record(header, data)
+}
+
+fn decode_record(header: &[u8], data: &[u8]) -> IoResult<(ChainTip, Block, usize)> {
+    if checksum(data) != header[8..] {
+        return Err(IoError::new(
+            IoErrorKind::InvalidData,
+            "Block record checksum mismatch",
+        ));
+    }
+    // an intact record that doesn't parse isn't a torn write
+    let (tip, block) = ciborium::from_reader(data)
+        .map_err(|e| IoError::other(SbdError::decode("Block record")(e)))?;
+    Ok((tip, block, data.len()))
+}
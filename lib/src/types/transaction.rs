@@ -1,61 +1,455 @@
-use crate::crypto::{PublicKey, Signature};
-use crate::sha256::Hash;
-use crate::utils::Saveable;
+use crate::canonical::{self, Canonical, encode_struct};
+use crate::crypto::{PrivateKey, PublicKey, Signature};
+use crate::error::{Result, SbdError};
+use crate::sha256::{Hash, HashCache};
+use crate::utils::{Saveable, deserialize_all, format_value, no_migration};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult, Write};
+use std::fmt;
+use std::io::Result as IoResult;
 use uuid::Uuid;
-#[derive(Serialize, Deserialize, Clone, Debug)]
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Transaction {
     pub inputs: Vec<TransactionInput>,
     pub outputs: Vec<TransactionOutput>,
+    // the first height of a block that may no longer include it, so
+    // an unmined payment lapses instead of waiting in mempools; left
+    // out of the encoding while None, as transactions were before
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at_height: Option<u64>,
+    #[serde(skip)]
+    cached_hash: HashCache,
 }
 
 impl Transaction {
     pub fn new(inputs: Vec<TransactionInput>, outputs: Vec<TransactionOutput>) -> Self {
         Transaction {
-            inputs: inputs,
-            outputs: outputs,
+            inputs,
+            outputs,
+            expires_at_height: None,
+            cached_hash: HashCache::default(),
         }
     }
 
+    // whether the block at height may no longer include it
+    pub fn is_expired_at(&self, height: u64) -> bool {
+        self.expires_at_height
+            .is_some_and(|expiry_height| height >= expiry_height)
+    }
+
+    // encoded and hashed again on every call until frozen
     pub fn hash(&self) -> Hash {
+        self.cached_hash.get(self)
+    }
+
+    // keep the hash from now on, for a transaction that won't change
+    // again; the chain freezes the ones it takes into blocks and the
+    // mempool. clones stay frozen, thaw one before editing it
+    pub fn freeze(&self) {
+        self.cached_hash.freeze(self);
+    }
+
+    pub fn thaw(&mut self) {
+        self.cached_hash.clear();
+    }
+
+    // hex of the canonical encoding, the raw transaction
+    pub fn to_hex(&self) -> String {
+        canonical::to_hex(self)
+    }
+
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        canonical::from_hex(hex, "transaction")
+    }
+
+    // encoded size in bytes, what fee rates are measured against
+    pub fn size(&self) -> u64 {
+        canonical::to_bytes(self).len() as u64
+    }
+
+    // what the input spending outpoint signs
+    pub fn sighash(&self, outpoint: &Hash) -> Hash {
+        SighashPreimage {
+            outpoint,
+            outputs: &self.outputs,
+            expires_at_height: self.expires_at_height,
+        }
+        .hash()
+    }
+}
+
+// what the signature of an input commits to: the output it spends
+// and all the transaction pays out, so a signature neither carries
+// over to another input nor survives a change to the outputs
+struct SighashPreimage<'a> {
+    outpoint: &'a Hash,
+    outputs: &'a Vec<TransactionOutput>,
+    expires_at_height: Option<u64>,
+}
+
+impl Canonical for SighashPreimage<'_> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let expiry_height = self.expires_at_height.unwrap_or(0);
+        let fields: [(&str, &dyn Canonical); 3] = [
+            ("outpoint", self.outpoint),
+            ("outputs", self.outputs),
+            ("expires_at_height", &expiry_height),
+        ];
+        let fields = match self.expires_at_height {
+            Some(_) => &fields[..],
+            None => &fields[..2],
+        };
+        encode_struct(out, fields);
+    }
+}
+
+impl SighashPreimage<'_> {
+    fn hash(&self) -> Hash {
         Hash::hash(self)
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TransactionInput {
     pub prev_transaction_output_hash: Hash,
     pub signature: Signature,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TransactionOutput {
     pub value: u64,
     pub unique_id: Uuid,
     pub pubkey: PublicKey,
+    // the first height of a block that may spend it, for vesting and
+    // escrow; left out of the encoding while None, as outputs were
+    // before they could be locked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spendable_after_height: Option<u64>,
 }
 
 impl TransactionOutput {
     pub fn hash(&self) -> Hash {
         Hash::hash(self)
     }
+
+    // whether the block at height may spend it
+    pub fn is_spendable_at(&self, height: u64) -> bool {
+        self.spendable_after_height
+            .is_none_or(|unlock_height| height >= unlock_height)
+    }
+}
+
+// a transaction being signed, possibly over several passes on
+// machines without the chain: every input carries the output it
+// spends, so signers can see what they are signing
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PartiallySignedTransaction {
+    pub inputs: Vec<PartialInput>,
+    pub outputs: Vec<TransactionOutput>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at_height: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PartialInput {
+    pub prev_transaction_output_hash: Hash,
+    pub prev_output: TransactionOutput,
+    pub signature: Option<Signature>,
+}
+
+impl PartiallySignedTransaction {
+    pub fn new(
+        prev_outputs: Vec<(Hash, TransactionOutput)>,
+        outputs: Vec<TransactionOutput>,
+    ) -> Self {
+        let inputs = prev_outputs
+            .into_iter()
+            .map(|(hash, prev_output)| PartialInput {
+                prev_transaction_output_hash: hash,
+                prev_output,
+                signature: None,
+            })
+            .collect();
+        PartiallySignedTransaction {
+            inputs,
+            outputs,
+            expires_at_height: None,
+        }
+    }
+
+    // sign the inputs spending outputs of the key, returns
+    // how many it signed; fails if an embedded previous output
+    // doesn't match the hash it claims to be
+    pub fn sign(&mut self, private_key: &PrivateKey) -> Result<usize> {
+        let pubkey = private_key.public_key();
+        let mut signed = 0;
+        for input in &mut self.inputs {
+            if input.signature.is_some() || input.prev_output.pubkey != pubkey {
+                continue;
+            }
+            if input.prev_output.hash() != input.prev_transaction_output_hash {
+                return Err(SbdError::InvalidTransactionInput);
+            }
+            let sighash = SighashPreimage {
+                outpoint: &input.prev_transaction_output_hash,
+                outputs: &self.outputs,
+                expires_at_height: self.expires_at_height,
+            }
+            .hash();
+            input.signature = Some(Signature::sign_input(&sighash, private_key));
+            signed += 1;
+        }
+        Ok(signed)
+    }
+
+    // indices of the inputs still missing a signature
+    pub fn unsigned(&self) -> Vec<usize> {
+        self.inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, input)| input.signature.is_none())
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    // value of the spent outputs not paid out again
+    pub fn fee(&self) -> Option<u64> {
+        let inputs = self
+            .inputs
+            .iter()
+            .try_fold(0u64, |sum, input| sum.checked_add(input.prev_output.value))?;
+        let outputs = self
+            .outputs
+            .iter()
+            .try_fold(0u64, |sum, output| sum.checked_add(output.value))?;
+        inputs.checked_sub(outputs)
+    }
+
+    // size of the final transaction, or a few bytes over it while
+    // inputs are unsigned, counted at the widest signature
+    pub fn size(&self) -> u64 {
+        self.transaction(|input| input.signature.clone().unwrap_or_else(Signature::widest))
+            .size()
+    }
+
+    pub fn finalize(&self) -> Result<Transaction> {
+        let unsigned = self.unsigned();
+        if !unsigned.is_empty() {
+            return Err(SbdError::UnsignedInputs(unsigned));
+        }
+        Ok(self.transaction(|input| input.signature.clone().expect("BUG: unsigned input")))
+    }
+
+    fn transaction(&self, signature: impl Fn(&PartialInput) -> Signature) -> Transaction {
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| TransactionInput {
+                prev_transaction_output_hash: input.prev_transaction_output_hash,
+                signature: signature(input),
+            })
+            .collect();
+        let mut transaction = Transaction::new(inputs, self.outputs.clone());
+        transaction.expires_at_height = self.expires_at_height;
+        transaction
+    }
+}
+
+impl Saveable for PartiallySignedTransaction {
+    const TYPE_TAG: &'static str = "PartiallySignedTransaction";
+}
+
+// one line for logs and listings, {:#?} shows every field
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self
+            .outputs
+            .iter()
+            .fold(0u64, |total, output| total.saturating_add(output.value));
+        write!(f, "tx {}: ", self.hash().to_hex())?;
+        if self.inputs.is_empty() {
+            write!(f, "coinbase")?;
+        } else {
+            write!(f, "{} in", self.inputs.len())?;
+        }
+        write!(
+            f,
+            " -> {} out, {} total",
+            self.outputs.len(),
+            format_value(total)
+        )
+    }
+}
+
+impl Canonical for Transaction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let expiry_height = self.expires_at_height.unwrap_or(0);
+        let fields: [(&str, &dyn Canonical); 3] = [
+            ("inputs", &self.inputs),
+            ("outputs", &self.outputs),
+            ("expires_at_height", &expiry_height),
+        ];
+        // transactions that don't expire hash as they did before the field
+        let fields = match self.expires_at_height {
+            Some(_) => &fields[..],
+            None => &fields[..2],
+        };
+        encode_struct(out, fields);
+    }
+}
+
+impl Canonical for TransactionInput {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_struct(
+            out,
+            &[
+                (
+                    "prev_transaction_output_hash",
+                    &self.prev_transaction_output_hash,
+                ),
+                ("signature", &self.signature),
+            ],
+        );
+    }
+}
+
+impl Canonical for TransactionOutput {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let unlock_height = self.spendable_after_height.unwrap_or(0);
+        let fields: [(&str, &dyn Canonical); 4] = [
+            ("value", &self.value),
+            ("unique_id", &self.unique_id),
+            ("pubkey", &self.pubkey),
+            ("spendable_after_height", &unlock_height),
+        ];
+        // unlocked outputs hash as they did before the field
+        let fields = match self.spendable_after_height {
+            Some(_) => &fields[..],
+            None => &fields[..3],
+        };
+        encode_struct(out, fields);
+    }
+}
+
+// pending transactions saved next to the chain, since
+// the mempool is not part of the saved Blockchain
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MempoolSnapshot {
+    pub entries: Vec<MempoolEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MempoolEntry {
+    // when the transaction entered the mempool, for expiry
+    pub timestamp: DateTime<Utc>,
+    // fee when saved, recomputed when restored
+    pub fee: u64,
+    pub transaction: Transaction,
+}
+
+impl Saveable for MempoolSnapshot {
+    const TYPE_TAG: &'static str = "Mempool";
+    const SCHEMA_VERSION: u16 = 2;
+
+    // schema 1 was the bare transactions, they restart their
+    // expiry and get their fees back when restored
+    fn migrate(version: u16, data: &[u8]) -> IoResult<Self> {
+        match version {
+            1 => {
+                let transactions: Vec<Transaction> = deserialize_all(data, Self::TYPE_TAG)?;
+                // without a clock they expire at the next cleanup
+                #[cfg(feature = "clock")]
+                let timestamp = Utc::now();
+                #[cfg(not(feature = "clock"))]
+                let timestamp = DateTime::<Utc>::UNIX_EPOCH;
+                Ok(MempoolSnapshot {
+                    entries: transactions
+                        .into_iter()
+                        .map(|transaction| MempoolEntry {
+                            timestamp,
+                            fee: 0,
+                            transaction,
+                        })
+                        .collect(),
+                })
+            }
+            _ => Err(no_migration(Self::TYPE_TAG, version)),
+        }
+    }
 }
 
-//save and load expecting CBOR from ciborium as format
+// cbor with a header, as all Saveables
 impl Saveable for Transaction {
-    fn load<I: Read>(reader: I) -> IoResult<Self> {
-        ciborium::de::from_reader(reader).map_err(|_| {
-            IoError::new(
-                IoErrorKind::InvalidData,
-                "Failed to Deserialize transaction",
-            )
-        })
-    }
-
-    fn save<O: Write>(&self, writer: O) -> IoResult<()> {
-        ciborium::ser::into_writer(self, writer)
-            .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Failed to Serialize Transaction"))
+    const TYPE_TAG: &'static str = "Transaction";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(value: u64, key: &PrivateKey) -> TransactionOutput {
+        TransactionOutput {
+            value,
+            unique_id: Uuid::new_v4(),
+            pubkey: key.public_key(),
+            spendable_after_height: None,
+        }
+    }
+
+    // two outputs of one key spent together, to another key
+    fn signed_spend() -> (Transaction, PrivateKey) {
+        let key = PrivateKey::new_key();
+        let payee = PrivateKey::new_key();
+        let spent = vec![output(50, &key), output(70, &key)];
+        let prev_outputs = spent.into_iter().map(|out| (out.hash(), out)).collect();
+        let mut unsigned = PartiallySignedTransaction::new(prev_outputs, vec![output(110, &payee)]);
+        assert_eq!(unsigned.sign(&key).unwrap(), 2);
+        (unsigned.finalize().unwrap(), key)
+    }
+
+    fn verifies(
+        transaction: &Transaction,
+        signature: &Signature,
+        input: usize,
+        key: &PrivateKey,
+    ) -> bool {
+        let outpoint = transaction.inputs[input].prev_transaction_output_hash;
+        signature.verify(&transaction.sighash(&outpoint), &key.public_key())
+    }
+
+    #[test]
+    fn signatures_verify_for_their_own_input() {
+        let (transaction, key) = signed_spend();
+        for (index, input) in transaction.inputs.iter().enumerate() {
+            assert!(verifies(&transaction, &input.signature, index, &key));
+        }
+    }
+
+    #[test]
+    fn signature_does_not_verify_for_another_input() {
+        let (transaction, key) = signed_spend();
+        let signature = &transaction.inputs[0].signature;
+        assert!(!verifies(&transaction, signature, 1, &key));
+    }
+
+    #[test]
+    fn signature_does_not_verify_for_changed_outputs() {
+        let (transaction, key) = signed_spend();
+        let signature = &transaction.inputs[0].signature;
+        let mut redirected = transaction.clone();
+        redirected.outputs[0].pubkey = key.public_key();
+        assert!(!verifies(&redirected, signature, 0, &key));
+        let mut lowered = transaction.clone();
+        lowered.outputs[0].value -= 1;
+        assert!(!verifies(&lowered, signature, 0, &key));
+    }
+
+    #[test]
+    fn signature_does_not_verify_for_changed_expiry() {
+        let (mut transaction, key) = signed_spend();
+        transaction.expires_at_height = Some(10);
+        let signature = transaction.inputs[0].signature.clone();
+        assert!(!verifies(&transaction, &signature, 0, &key));
     }
 }
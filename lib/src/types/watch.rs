@@ -0,0 +1,110 @@
+use super::Transaction;
+use crate::sha256::{Hash, HashKeyedMap};
+use serde::{Deserialize, Serialize};
+
+// where a transaction conflicting with a watched one turned up
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictLocation {
+    Mempool,
+    Block(u64),
+}
+
+// what the chain reports to those following it, taken with
+// Blockchain::take_events
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ChainEvent {
+    // conflicting spends the output the watched transaction original
+    // spends; both transactions are kept, as proof of the conflict
+    DoubleSpendDetected {
+        original: Hash,
+        conflicting: Hash,
+        outpoint: Hash,
+        #[serde(rename = "where")]
+        location: ConflictLocation,
+        original_transaction: Transaction,
+        conflicting_transaction: Transaction,
+    },
+}
+
+// transactions someone wants told of conflicts with, by txid, each
+// with the transaction once it's been seen; what they spend is
+// indexed so a conflict is found without scanning them
+#[derive(Clone, Debug, Default)]
+pub(crate) struct WatchList {
+    transactions: HashKeyedMap<Option<Transaction>>,
+    // txid of the watched transaction spending each output
+    spends: HashKeyedMap<Hash>,
+}
+
+impl WatchList {
+    pub(crate) fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub(crate) fn contains(&self, txid: &Hash) -> bool {
+        self.transactions.contains_key(txid)
+    }
+
+    pub(crate) fn insert(&mut self, txid: Hash) {
+        self.transactions.entry(txid).or_default();
+    }
+
+    pub(crate) fn remove(&mut self, txid: &Hash) -> bool {
+        let Some(transaction) = self.transactions.remove(txid) else {
+            return false;
+        };
+        for input in transaction
+            .iter()
+            .flat_map(|transaction| &transaction.inputs)
+        {
+            let outpoint = input.prev_transaction_output_hash;
+            if self.spends.get(&outpoint) == Some(txid) {
+                self.spends.remove(&outpoint);
+            }
+        }
+        true
+    }
+
+    // keep the transaction if it's watched and not yet seen, as
+    // what its conflicts are found against
+    pub(crate) fn see(&mut self, transaction: &Transaction) {
+        let txid = transaction.hash();
+        let Some(seen @ None) = self.transactions.get_mut(&txid) else {
+            return;
+        };
+        *seen = Some(transaction.clone());
+        for input in &transaction.inputs {
+            self.spends.insert(input.prev_transaction_output_hash, txid);
+        }
+    }
+
+    // a DoubleSpendDetected for each output the transaction spends
+    // that a different watched transaction spends
+    pub(crate) fn conflicts(
+        &self,
+        transaction: &Transaction,
+        location: ConflictLocation,
+    ) -> Vec<ChainEvent> {
+        let conflicting = transaction.hash();
+        transaction
+            .inputs
+            .iter()
+            .filter_map(|input| {
+                let outpoint = input.prev_transaction_output_hash;
+                let original = *self.spends.get(&outpoint)?;
+                if original == conflicting {
+                    return None;
+                }
+                let original_transaction = self.transactions.get(&original)?.clone()?;
+                Some(ChainEvent::DoubleSpendDetected {
+                    original,
+                    conflicting,
+                    outpoint,
+                    location,
+                    original_transaction,
+                    conflicting_transaction: transaction.clone(),
+                })
+            })
+            .collect()
+    }
+}
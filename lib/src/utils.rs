@@ -1,46 +1,680 @@
+use crate::canonical::Canonical;
+use crate::error::SbdError;
 use crate::sha256::Hash;
 use crate::types::Transaction;
+#[cfg(feature = "std-fs")]
+use chrono::Utc;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::{Read, Result as IoResult, Write};
-use std::path::Path;
+use std::cmp::Ordering;
+#[cfg(feature = "std-fs")]
+use std::ffi::OsString;
+use std::fmt;
+#[cfg(feature = "std-fs")]
+use std::fs::{self, File, OpenOptions};
+#[cfg(feature = "std-fs")]
+use std::io::BufWriter;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Result as IoResult, Write};
+#[cfg(feature = "std-fs")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "json")]
+use std::str::FromStr;
+#[cfg(feature = "std-fs")]
+use std::thread;
+#[cfg(feature = "std-fs")]
+use std::time::{Duration, Instant};
+use tracing::warn;
 
 #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct MerkleRoot(Hash);
 
+impl Canonical for MerkleRoot {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+    }
+}
+
+impl fmt::Display for MerkleRoot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 impl MerkleRoot {
     //calculate the MerkleRoot of a blocks transactions
     pub fn calculate(transactions: &[Transaction]) -> MerkleRoot {
-        let mut layer: Vec<Hash> = vec![];
-        for transaction in transactions {
-            layer.push(Hash::hash(transaction))
+        let mut layer: Vec<Hash> = transactions.iter().map(Hash::hash).collect();
+        while layer.len() > 1 {
+            layer = parent_layer(&layer);
         }
+        // no block without transactions is valid, but its
+        // root has to be something
+        MerkleRoot(layer.first().copied().unwrap_or_else(Hash::zero))
+    }
+}
+
+// hashes of each pair in a layer of the tree
+fn parent_layer(layer: &[Hash]) -> Vec<Hash> {
+    let mut new_layer = vec![];
+    for pair in layer.chunks(2) {
+        let left = pair[0];
+        //if there is no right, use the left hash again
+        let right = pair.get(1).unwrap_or(&pair[0]);
+        new_layer.push(Hash::hash(&[left, *right]));
+    }
+    new_layer
+}
+
+// path from one transaction of a block up to its merkle root: the
+// hash paired with at each layer, from the bottom up
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub index: u64,
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    // proof for the transaction at index, if there is one
+    pub fn new(transactions: &[Transaction], index: usize) -> Option<Self> {
+        if index >= transactions.len() {
+            return None;
+        }
+        let mut layer: Vec<Hash> = transactions.iter().map(Hash::hash).collect();
+        let mut position = index;
+        let mut siblings = vec![];
         while layer.len() > 1 {
-            let mut new_layer = vec![];
-            for pair in layer.chunks(2) {
-                let left = pair[0];
-                //if there is no right, use the left hash again
-                let right = pair.get(1).unwrap_or(&pair[0]);
-                new_layer.push(Hash::hash(&[left, *right]));
+            // the last hash of an odd layer is paired with itself
+            siblings.push(*layer.get(position ^ 1).unwrap_or(&layer[position]));
+            layer = parent_layer(&layer);
+            position /= 2;
+        }
+        Some(MerkleProof {
+            index: index as u64,
+            siblings,
+        })
+    }
+
+    // the root the proof leads to from the transaction hash
+    pub fn root(&self, txid: &Hash) -> MerkleRoot {
+        let mut hash = *txid;
+        let mut position = self.index;
+        for sibling in &self.siblings {
+            hash = if position.is_multiple_of(2) {
+                Hash::hash(&[hash, *sibling])
+            } else {
+                Hash::hash(&[*sibling, hash])
+            };
+            position /= 2;
+        }
+        MerkleRoot(hash)
+    }
+
+    // whether the transaction is under the root at the proof's index;
+    // an index past the end of the path would otherwise be read
+    // only in its low bits
+    pub fn verify(&self, txid: &Hash, root: &MerkleRoot) -> bool {
+        let in_range = self
+            .index
+            .checked_shr(self.siblings.len() as u32)
+            .unwrap_or(0)
+            == 0;
+        in_range && self.root(txid) == *root
+    }
+}
+
+// base units as coins with all eight decimals
+pub fn format_value(units: u64) -> String {
+    format!(
+        "{}.{:08}",
+        units / crate::UNITS_PER_COIN,
+        units % crate::UNITS_PER_COIN
+    )
+}
+
+// how a Saveable is stored: cbor by default, json for reading it
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum FileFormat {
+    #[default]
+    Cbor,
+    Json,
+}
+
+#[cfg(feature = "json")]
+impl FromStr for FileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cbor" => Ok(FileFormat::Cbor),
+            "json" => Ok(FileFormat::Json),
+            _ => Err(format!("Unknown format {s}, expected json or cbor")),
+        }
+    }
+}
+
+// write to a temporary sibling file, sync it and rename it over
+// the destination, so a crash mid-save never leaves a half written
+// file; rename replaces an existing file on windows too
+#[cfg(feature = "std-fs")]
+pub fn write_atomically<P: AsRef<Path>>(
+    path: P,
+    write: impl FnOnce(&mut BufWriter<File>) -> IoResult<()>,
+) -> IoResult<()> {
+    let path = path.as_ref();
+    let mut tmp_name = path.file_name().map(OsString::from).unwrap_or_default();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    let result = (|| {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        write(&mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+// copies of a file taken before each save overwrites it, in dir as
+// name.YYYYMMDD-HHMMSS.bak (utc, with -N added for more in the same
+// second); only the newest keep copies are kept, none if keep is 0
+#[cfg(feature = "std-fs")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackupPolicy {
+    pub keep: usize,
+    pub dir: PathBuf,
+}
+
+// how long a save waits for another one of the same file
+#[cfg(feature = "std-fs")]
+const BACKUP_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[cfg(feature = "std-fs")]
+impl BackupPolicy {
+    pub fn new<P: AsRef<Path>>(dir: P, keep: usize) -> Self {
+        BackupPolicy {
+            keep,
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    // back up the file at path and prune, then run the save; saves of
+    // the same file through the policy hold a lock file meanwhile, so
+    // concurrent ones take turns instead of racing on the backups
+    pub fn save<P: AsRef<Path>>(
+        &self,
+        path: P,
+        save: impl FnOnce(&Path) -> IoResult<()>,
+    ) -> IoResult<()> {
+        let path = path.as_ref();
+        let _lock = self.lock(path)?;
+        if self.keep > 0 && path.exists() {
+            self.copy(path)?;
+            let backups = self.backups(path)?;
+            for backup in &backups[..backups.len().saturating_sub(self.keep)] {
+                fs::remove_file(backup)?;
+            }
+        }
+        save(path)
+    }
+
+    // backups of the file at path, oldest first
+    pub fn backups<P: AsRef<Path>>(&self, path: P) -> IoResult<Vec<PathBuf>> {
+        let name = file_name(path.as_ref())?;
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == IoErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut backups = vec![];
+        for entry in entries {
+            let entry = entry?;
+            if let Some(stamp) = entry
+                .file_name()
+                .to_str()
+                .and_then(|backup| backup_stamp(&name, backup))
+            {
+                backups.push((stamp, entry.path()));
+            }
+        }
+        backups.sort();
+        Ok(backups.into_iter().map(|(_, path)| path).collect())
+    }
+
+    // put the newest backup back in place of the file, which is kept
+    // as name.before-restore in the backup dir; returns the backup
+    pub fn restore_latest_backup<P: AsRef<Path>>(&self, path: P) -> IoResult<PathBuf> {
+        let path = path.as_ref();
+        let _lock = self.lock(path)?;
+        let Some(backup) = self.backups(path)?.pop() else {
+            return Err(IoError::new(
+                IoErrorKind::NotFound,
+                format!("No backups of {} in {}", path.display(), self.dir.display()),
+            ));
+        };
+        if path.exists() {
+            let mut replaced = file_name(path)?;
+            replaced.push_str(".before-restore");
+            fs::copy(path, self.dir.join(replaced))?;
+        }
+        let data = fs::read(&backup)?;
+        write_atomically(path, |writer| writer.write_all(&data))?;
+        Ok(backup)
+    }
+
+    // copy through a temporary file, so a torn copy is never a backup
+    fn copy(&self, path: &Path) -> IoResult<PathBuf> {
+        let name = file_name(path)?;
+        let stamp = Utc::now().format("%Y%m%d-%H%M%S");
+        let mut backup = self.dir.join(format!("{name}.{stamp}.bak"));
+        let mut n = 1;
+        while backup.exists() {
+            backup = self.dir.join(format!("{name}.{stamp}-{n}.bak"));
+            n += 1;
+        }
+        let mut tmp_name = backup.file_name().map(OsString::from).unwrap_or_default();
+        tmp_name.push(".tmp");
+        let tmp_path = backup.with_file_name(tmp_name);
+        let result = fs::copy(path, &tmp_path).and_then(|_| fs::rename(&tmp_path, &backup));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result.map(|()| backup)
+    }
+
+    fn lock(&self, path: &Path) -> IoResult<BackupLock> {
+        fs::create_dir_all(&self.dir)?;
+        let lock_path = self.dir.join(format!("{}.lock", file_name(path)?));
+        let started = Instant::now();
+        loop {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&lock_path)
+            {
+                Ok(_) => return Ok(BackupLock(lock_path)),
+                Err(e) if e.kind() == IoErrorKind::AlreadyExists => {
+                    if started.elapsed() > BACKUP_LOCK_TIMEOUT {
+                        return Err(IoError::new(
+                            IoErrorKind::WouldBlock,
+                            format!(
+                                "{} is locked by another save, remove {} if none is running",
+                                path.display(),
+                                lock_path.display()
+                            ),
+                        ));
+                    }
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+// removes the lock file when the save is done
+#[cfg(feature = "std-fs")]
+struct BackupLock(PathBuf);
+
+#[cfg(feature = "std-fs")]
+impl Drop for BackupLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(feature = "std-fs")]
+fn file_name(path: &Path) -> IoResult<String> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(String::from)
+        .ok_or_else(|| {
+            IoError::new(
+                IoErrorKind::InvalidInput,
+                format!("Can't back up {}", path.display()),
+            )
+        })
+}
+
+// the timestamp and counter of a backup of the named file, to sort by
+#[cfg(feature = "std-fs")]
+fn backup_stamp(name: &str, backup: &str) -> Option<(String, u32)> {
+    let rest = backup.strip_prefix(name)?.strip_prefix('.')?;
+    let rest = rest.strip_suffix(".bak")?;
+    let (stamp, n) = match rest.get(15..) {
+        Some("") => (rest, 0),
+        Some(n) => (&rest[..15], n.strip_prefix('-')?.parse().ok()?),
+        None => return None,
+    };
+    let (date, time) = stamp.split_once('-')?;
+    (date.len() == 8
+        && time.len() == 6
+        && date.bytes().chain(time.bytes()).all(|b| b.is_ascii_digit()))
+    .then(|| (stamp.to_string(), n))
+}
+
+// zstd frames start with this, which is how load tells files
+// compressed before the flags byte apart
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+#[cfg(feature = "compression")]
+const COMPRESSION_LEVEL: i32 = 3;
+
+// saved files start with the magic, the format version as a u16,
+// the type tag, a length byte followed by the name, since version 3
+// the schema version of the type as a u16, and since version 2 a
+// flags byte; then comes the cbor, zstd compressed if flagged, and
+// since version 2 a sha256 of everything before it. files from
+// before the header are plain cbor and load as version 0, files
+// from before schema versions hold schema version 1
+const FILE_MAGIC: [u8; 4] = *b"SBDF";
+pub const FILE_FORMAT_VERSION: u16 = 3;
+const FLAG_COMPRESSED: u8 = 1;
+const CHECKSUM_SIZE: usize = 32;
+
+fn invalid_data(message: String) -> IoError {
+    IoError::new(IoErrorKind::InvalidData, message)
+}
+
+#[cfg(not(feature = "compression"))]
+fn compression_disabled() -> IoError {
+    IoError::new(
+        IoErrorKind::Unsupported,
+        "zstd compressed file, but built without the compression feature",
+    )
+}
+
+pub(crate) fn file_checksum(data: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let digest = hex::decode(sha256::digest(data)).unwrap();
+    digest.try_into().unwrap()
+}
+
+// a magic with one damaged byte still marks one of our files,
+// so the damage is reported as such
+fn has_magic(data: &[u8]) -> bool {
+    data.len() >= FILE_MAGIC.len()
+        && FILE_MAGIC
+            .iter()
+            .zip(data)
+            .filter(|(magic, byte)| magic == byte)
+            .count()
+            >= FILE_MAGIC.len() - 1
+}
+
+struct FileHeader<'a> {
+    version: u16,
+    tag: &'a [u8],
+    schema: u16,
+    flags: u8,
+    // what follows the header
+    body: &'a [u8],
+}
+
+fn parse_header(data: &[u8]) -> IoResult<FileHeader<'_>> {
+    let truncated = || invalid_data("Truncated file header".to_string());
+    let rest = data.get(FILE_MAGIC.len()..).ok_or_else(truncated)?;
+    let (version, rest) = rest.split_first_chunk::<2>().ok_or_else(truncated)?;
+    let version = u16::from_be_bytes(*version);
+    let (length, rest) = rest.split_first().ok_or_else(truncated)?;
+    let tag = rest.get(..*length as usize).ok_or_else(truncated)?;
+    let mut rest = &rest[tag.len()..];
+    let mut schema = 1;
+    if version >= 3 {
+        let (bytes, after) = rest.split_first_chunk::<2>().ok_or_else(truncated)?;
+        (schema, rest) = (u16::from_be_bytes(*bytes), after);
+    }
+    let mut flags = 0;
+    if version >= 2 {
+        (flags, rest) = rest
+            .split_first()
+            .map(|(f, r)| (*f, r))
+            .ok_or_else(truncated)?;
+    }
+    Ok(FileHeader {
+        version,
+        tag,
+        schema,
+        flags,
+        body: rest,
+    })
+}
+
+// true if the file was written by save_compressed
+#[cfg(feature = "std-fs")]
+pub fn is_compressed<P: AsRef<Path>>(path: P) -> IoResult<bool> {
+    // the header is at most 4 + 2 + 1 + 255 + 2 + 1 bytes
+    let mut start = vec![];
+    File::open(path)?.take(265).read_to_end(&mut start)?;
+    if start.starts_with(&ZSTD_MAGIC) {
+        return Ok(true);
+    }
+    Ok(has_magic(&start)
+        && parse_header(&start).is_ok_and(|header| header.flags & FLAG_COMPRESSED != 0))
+}
+
+fn decompress(data: &[u8]) -> IoResult<Vec<u8>> {
+    #[cfg(feature = "compression")]
+    {
+        // a chunk at a time, as read_to_end would grow the buffer
+        // past the limit before finding it full
+        let limit = crate::MAX_DECOMPRESSED_SIZE as usize;
+        let mut decoder = zstd::Decoder::new(data)?;
+        let mut decompressed = vec![];
+        let mut chunk = vec![0u8; 64 * 1024];
+        loop {
+            let read = decoder.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            if decompressed.len() + read > limit {
+                return Err(invalid_data(format!(
+                    "file decompresses to more than {limit} bytes"
+                )));
             }
-            layer = new_layer;
+            decompressed.extend_from_slice(&chunk[..read]);
         }
-        MerkleRoot(layer[0])
+        Ok(decompressed)
+    }
+    #[cfg(not(feature = "compression"))]
+    {
+        let _ = data;
+        Err(compression_disabled())
     }
 }
 
+pub fn no_migration(tag: &str, version: u16) -> IoError {
+    invalid_data(format!("No migration from {tag} schema version {version}"))
+}
+
+fn warn_unchecked(tag: &str) {
+    warn!(tag, "file has no checksum, saving it again adds one");
+}
+
+// cbor files with a header naming the type
 pub trait Saveable
 where
-    Self: Sized,
+    Self: Sized + Serialize + DeserializeOwned,
 {
-    fn load<I: Read>(reader: I) -> IoResult<Self>;
-    fn save<O: Write>(&self, writer: O) -> IoResult<()>;
+    // names the type in file headers and errors
+    const TYPE_TAG: &'static str;
+    // version of the serialized form, saved in the header; bump it
+    // when the form changes and have migrate read the old one
+    const SCHEMA_VERSION: u16 = 1;
+
+    // the cbor of an older schema version as the current type
+    fn migrate(version: u16, data: &[u8]) -> IoResult<Self> {
+        let _ = data;
+        Err(no_migration(Self::TYPE_TAG, version))
+    }
+
+    // reads compressed files too; the whole file is read
+    // so the checksum is verified before deserializing
+    fn load<I: Read>(mut reader: I) -> IoResult<Self> {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+        read_saved(&data)
+    }
+    fn save<O: Write>(&self, writer: O) -> IoResult<()> {
+        write_saved(self, writer, 0)
+    }
+    // the cbor zstd compressed, still under a plain header
+    fn save_compressed<O: Write>(&self, writer: O) -> IoResult<()> {
+        write_saved(self, writer, FLAG_COMPRESSED)
+    }
+    #[cfg(feature = "std-fs")]
+    fn save_compressed_to_file<P: AsRef<Path>>(&self, path: P) -> IoResult<()> {
+        write_atomically(path, |writer| self.save_compressed(writer))
+    }
+    #[cfg(feature = "std-fs")]
     fn save_to_file<P: AsRef<Path>>(&self, path: P) -> IoResult<()> {
-        let file = File::create(&path)?;
-        self.save(file)
+        write_atomically(path, |writer| self.save(writer))
     }
+    #[cfg(feature = "std-fs")]
     fn load_from_file<P: AsRef<Path>>(path: P) -> IoResult<Self> {
         let file = File::open(&path)?;
         Self::load(file)
     }
+    // pretty printed json, hashes as hex strings
+    #[cfg(feature = "json")]
+    fn save_json<O: Write>(&self, writer: O) -> IoResult<()> {
+        Ok(serde_json::to_writer_pretty(writer, self)?)
+    }
+    #[cfg(feature = "json")]
+    fn load_json<I: Read>(reader: I) -> IoResult<Self> {
+        Ok(serde_json::from_reader(reader)?)
+    }
+    #[cfg(all(feature = "std-fs", feature = "json"))]
+    fn save_json_to_file<P: AsRef<Path>>(&self, path: P) -> IoResult<()> {
+        write_atomically(path, |writer| self.save_json(writer))
+    }
+    #[cfg(all(feature = "std-fs", feature = "json"))]
+    fn load_json_from_file<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        let file = File::open(&path)?;
+        Self::load_json(file)
+    }
+    #[cfg(all(feature = "std-fs", feature = "json"))]
+    fn save_to_file_as<P: AsRef<Path>>(&self, path: P, format: FileFormat) -> IoResult<()> {
+        match format {
+            FileFormat::Cbor => self.save_to_file(path),
+            FileFormat::Json => self.save_json_to_file(path),
+        }
+    }
+    #[cfg(all(feature = "std-fs", feature = "json"))]
+    fn load_from_file_as<P: AsRef<Path>>(path: P, format: FileFormat) -> IoResult<Self> {
+        match format {
+            FileFormat::Cbor => Self::load_from_file(path),
+            FileFormat::Json => Self::load_json_from_file(path),
+        }
+    }
+}
+
+fn read_saved<T: Saveable>(data: &[u8]) -> IoResult<T> {
+    let tag = T::TYPE_TAG;
+    if data.starts_with(&ZSTD_MAGIC) {
+        // compressed with zstd around the whole file
+        return read_saved(&decompress(data)?);
+    }
+    if !has_magic(data) {
+        warn_unchecked(tag);
+        return deserialize_schema(data, 1);
+    }
+    // checked before anything in the header is believed
+    let split = data.len().saturating_sub(CHECKSUM_SIZE);
+    let (checked, trailer) = data.split_at(split);
+    let expected = file_checksum(checked);
+    let mismatch = || {
+        IoError::new(
+            IoErrorKind::InvalidData,
+            SbdError::ChecksumMismatch {
+                expected: hex::encode(expected),
+                actual: hex::encode(trailer),
+            },
+        )
+    };
+    let verified = trailer == expected;
+    let header = parse_header(if verified { checked } else { data })?;
+    if !verified && (header.version >= 2 || data[..FILE_MAGIC.len()] != FILE_MAGIC) {
+        return Err(mismatch());
+    }
+    if header.version > FILE_FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "{tag} file version {} was produced by a newer version, this one reads up to {FILE_FORMAT_VERSION}",
+            header.version
+        )));
+    }
+    if header.tag != tag.as_bytes() {
+        return Err(invalid_data(format!(
+            "Expected {tag}, file contains {}",
+            String::from_utf8_lossy(header.tag)
+        )));
+    }
+    if header.version < 2 {
+        // a version 2 file whose version got damaged has a trailer left over
+        if trailing_bytes(header.body) {
+            return Err(mismatch());
+        }
+        warn_unchecked(tag);
+        return deserialize_schema(header.body, header.schema);
+    }
+    if header.flags & FLAG_COMPRESSED != 0 {
+        return deserialize_schema(&decompress(header.body)?, header.schema);
+    }
+    deserialize_schema(header.body, header.schema)
+}
+
+fn deserialize_schema<T: Saveable>(data: &[u8], schema: u16) -> IoResult<T> {
+    match schema.cmp(&T::SCHEMA_VERSION) {
+        Ordering::Equal => deserialize_all(data, T::TYPE_TAG),
+        Ordering::Less => T::migrate(schema, data),
+        Ordering::Greater => Err(invalid_data(format!(
+            "{} schema version {schema} was produced by a newer version, this one reads up to {}",
+            T::TYPE_TAG,
+            T::SCHEMA_VERSION
+        ))),
+    }
+}
+
+fn write_saved<T: Saveable, O: Write>(value: &T, mut writer: O, flags: u8) -> IoResult<()> {
+    let tag = T::TYPE_TAG;
+    let mut data = vec![];
+    data.extend_from_slice(&FILE_MAGIC);
+    data.extend_from_slice(&FILE_FORMAT_VERSION.to_be_bytes());
+    data.push(tag.len() as u8);
+    data.extend_from_slice(tag.as_bytes());
+    data.extend_from_slice(&T::SCHEMA_VERSION.to_be_bytes());
+    data.push(flags);
+    let mut payload = vec![];
+    ciborium::ser::into_writer(value, &mut payload).map_err(SbdError::encode(tag))?;
+    if flags & FLAG_COMPRESSED != 0 {
+        #[cfg(feature = "compression")]
+        {
+            payload = zstd::encode_all(&payload[..], COMPRESSION_LEVEL)?;
+        }
+        #[cfg(not(feature = "compression"))]
+        return Err(compression_disabled());
+    }
+    data.extend_from_slice(&payload);
+    let checksum = file_checksum(&data);
+    data.extend_from_slice(&checksum);
+    writer.write_all(&data)
+}
+
+// cbor that must make up all of the data
+pub fn deserialize_all<T: DeserializeOwned>(mut data: &[u8], tag: &'static str) -> IoResult<T> {
+    let value = ciborium::de::from_reader(&mut data).map_err(SbdError::decode(tag))?;
+    if !data.is_empty() {
+        return Err(invalid_data(format!(
+            "{} bytes of trailing data after {tag}",
+            data.len()
+        )));
+    }
+    Ok(value)
+}
+
+// true if the data holds more than one cbor item
+fn trailing_bytes(mut data: &[u8]) -> bool {
+    ciborium::de::from_reader::<ciborium::Value, _>(&mut data).is_ok() && !data.is_empty()
 }
@@ -0,0 +1,176 @@
+// per-address analytics: a key paid, spending all of it and paid
+// again counts both receipts, the spend and the blocks it was seen
+// in; over a seeded chain of spends every key's balance agrees
+// everywhere it's reported and the balances add up to the
+// circulating supply
+use lib::sha256::Hash;
+use lib::test_utils::ChainBuilder;
+use lib::types::{AddressStats, Blockchain};
+use std::collections::HashSet;
+
+const SEED: u64 = 724;
+const FIRST: u64 = 5000;
+const AGAIN: u64 = 1000;
+const FEE: u64 = 100;
+const KEYS: usize = 6;
+const BLOCKS: usize = 20;
+
+// xorshift64*, deterministic for a seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+}
+
+#[test]
+fn received_spent_all_and_received_again() {
+    let mut builder = ChainBuilder::new(SEED);
+    builder.mine_blocks(1).unwrap();
+    let key = builder.key(1).public_key();
+    let never = builder.key(7).public_key();
+
+    let first_seen = builder.chain().chain_height();
+    let [paid] = builder.fund(1, &[FIRST]).unwrap().try_into().unwrap();
+    builder.mine_blocks(2).unwrap();
+    // all of it to key 2, nothing back
+    let payment = builder.output(FIRST - FEE, 2);
+    let spend = builder.sign(&[paid.hash()], vec![payment]).unwrap();
+    builder
+        .mine_block(|template| template.transactions = vec![spend])
+        .unwrap();
+    let emptied = builder.chain().address_stats(&key);
+    assert_eq!(emptied.received, FIRST);
+    assert_eq!(emptied.spent, FIRST);
+    assert_eq!(emptied.balance, 0);
+    assert_eq!(builder.chain().balance_of(&key), 0);
+    assert_eq!(emptied.transactions, 2);
+
+    builder.mine_blocks(1).unwrap();
+    let last_seen = builder.chain().chain_height();
+    builder.fund(1, &[AGAIN]).unwrap();
+    builder.mine_blocks(1).unwrap();
+    let chain = builder.chain();
+    assert_eq!(
+        chain.address_stats(&key),
+        AddressStats {
+            received: FIRST + AGAIN,
+            spent: FIRST,
+            balance: AGAIN,
+            first_seen: Some(first_seen),
+            last_seen: Some(last_seen),
+            transactions: 3,
+        }
+    );
+    assert_eq!(chain.balance_of(&key), AGAIN);
+    assert!(
+        chain
+            .iter_balances()
+            .any(|(pubkey, balance)| pubkey == key && balance == AGAIN)
+    );
+
+    // a key the chain never paid has nothing
+    assert_eq!(chain.address_stats(&never), AddressStats::default());
+    assert_eq!(chain.balance_of(&never), 0);
+    assert!(chain.iter_balances().all(|(pubkey, _)| pubkey != never));
+
+    // a pending spend is still the key's until it's mined
+    let stats = chain.address_stats(&key);
+    let spend = builder.spend(1, 2, AGAIN - FEE, FEE).unwrap();
+    let chain = builder.chain_mut();
+    chain.add_to_mempool(spend).unwrap();
+    assert_eq!(chain.address_stats(&key), stats);
+    assert_eq!(chain.balance_of(&key), AGAIN);
+}
+
+// a chain of spends of one or two outputs between KEYS keys
+fn traded() -> Blockchain {
+    let mut rng = Rng(SEED);
+    let mut builder = ChainBuilder::new(SEED).with_keys(KEYS);
+    for key in 1..KEYS {
+        builder.fund(key, &[100_000, 20_000]).unwrap();
+    }
+    for _ in 0..BLOCKS {
+        let mut spent: Vec<Hash> = vec![];
+        let mut spends = vec![];
+        for _ in 0..rng.below(4) {
+            let from = rng.below(KEYS);
+            let available: Vec<Hash> = builder
+                .spendable(from)
+                .into_iter()
+                .filter(|(hash, output)| !spent.contains(hash) && output.value > FEE)
+                .map(|(hash, _)| hash)
+                .take(1 + rng.below(2))
+                .collect();
+            if available.is_empty() {
+                continue;
+            }
+            let value: u64 = available
+                .iter()
+                .map(|hash| builder.chain().utxo(hash).unwrap().value)
+                .sum();
+            let to = rng.below(KEYS);
+            let outputs = vec![
+                builder.output((value - FEE) / 3, to),
+                builder.output(value - FEE - (value - FEE) / 3, from),
+            ];
+            spends.push(builder.sign(&available, outputs).unwrap());
+            spent.extend(available);
+        }
+        builder
+            .mine_block(|template| template.transactions = spends)
+            .unwrap();
+    }
+    builder.into_chain()
+}
+
+#[test]
+fn balances_add_up_to_the_circulating_supply() {
+    let chain = traded();
+    let balances: Vec<_> = chain.iter_balances().collect();
+    let keys: HashSet<_> = balances.iter().map(|(pubkey, _)| pubkey.clone()).collect();
+    assert_eq!(keys.len(), balances.len());
+    assert_eq!(balances.len(), KEYS);
+
+    let total: u64 = balances.iter().map(|(_, balance)| balance).sum();
+    assert_eq!(total, chain.circulating_supply());
+    let utxos: u64 = chain
+        .utxos()
+        .iter()
+        .map(|(_, output, _)| output.value)
+        .sum();
+    assert_eq!(total, utxos);
+
+    let mut spent = 0;
+    for (pubkey, balance) in &balances {
+        assert_eq!(chain.balance_of(pubkey), *balance);
+        let stats = chain.address_stats(pubkey);
+        assert_eq!(stats.balance, *balance);
+        assert_eq!(stats.received - stats.spent, *balance);
+        assert!(stats.first_seen <= stats.last_seen);
+        spent += stats.spent;
+    }
+    assert!(spent > 0);
+
+    // imported from a snapshot, the history is gone but not the
+    // balances, which count as received
+    let mut snapshot = vec![];
+    chain.export_utxo_snapshot(&mut snapshot).unwrap();
+    let imported = Blockchain::import_utxo_snapshot(&snapshot[..]).unwrap();
+    let mut total = 0;
+    for (pubkey, balance) in imported.iter_balances() {
+        assert_eq!(chain.balance_of(&pubkey), balance);
+        let stats = imported.address_stats(&pubkey);
+        assert_eq!((stats.received, stats.spent), (balance, 0));
+        total += balance;
+    }
+    assert_eq!(total, chain.circulating_supply());
+}
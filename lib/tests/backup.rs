@@ -0,0 +1,100 @@
+// BackupPolicy over saves of a chain file: each save backs up the
+// file it replaces, the oldest backups pruned at the cap, and a
+// restore puts the newest back byte for byte
+use lib::test_utils::ChainBuilder;
+use lib::utils::{BackupPolicy, Saveable};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use tempfile::TempDir;
+
+const KEEP: usize = 2;
+
+// the file of a chain of each height, saved in turn
+fn versions() -> Vec<Vec<u8>> {
+    let mut builder = ChainBuilder::new(679);
+    (0..4)
+        .map(|_| {
+            builder.mine_blocks(1).unwrap();
+            let mut data = vec![];
+            builder.chain().save(&mut data).unwrap();
+            data
+        })
+        .collect()
+}
+
+fn contents(paths: &[impl AsRef<Path>]) -> Vec<Vec<u8>> {
+    paths.iter().map(|path| fs::read(path).unwrap()).collect()
+}
+
+#[test]
+fn saves_rotate_and_prune_at_the_cap() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("blockchain.cbor");
+    let policy = BackupPolicy::new(dir.path().join("backups"), KEEP);
+    let versions = versions();
+    fs::write(&path, &versions[0]).unwrap();
+    for version in &versions[1..] {
+        policy.save(&path, |path| fs::write(path, version)).unwrap();
+        let backups = policy.backups(&path).unwrap();
+        assert!(backups.len() <= KEEP);
+        assert_eq!(fs::read(&path).unwrap(), *version);
+    }
+    // the file each save replaced, oldest first, the first pruned
+    let backups = policy.backups(&path).unwrap();
+    assert_eq!(contents(&backups), versions[1..=KEEP]);
+    // nothing but the backups left behind, no lock or temporary file
+    let mut names: Vec<_> = fs::read_dir(dir.path().join("backups"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    let mut backups = backups;
+    names.sort();
+    backups.sort();
+    assert_eq!(names, backups);
+}
+
+#[test]
+fn restore_puts_the_newest_back_byte_for_byte() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("blockchain.cbor");
+    let backups = dir.path().join("backups");
+    let policy = BackupPolicy::new(&backups, KEEP);
+    let versions = versions();
+    fs::write(&path, &versions[0]).unwrap();
+    for version in &versions[1..] {
+        policy.save(&path, |path| fs::write(path, version)).unwrap();
+    }
+    let newest = policy.backups(&path).unwrap().pop().unwrap();
+    assert_eq!(policy.restore_latest_backup(&path).unwrap(), newest);
+    assert_eq!(fs::read(&path).unwrap(), versions[2]);
+    // the file it replaced kept aside
+    assert_eq!(
+        fs::read(backups.join("blockchain.cbor.before-restore")).unwrap(),
+        versions[3]
+    );
+    // and what's restored loads
+    let chain = lib::types::Blockchain::load_from_file(&path).unwrap();
+    assert_eq!(chain.block_height(), 3);
+}
+
+#[test]
+fn no_backups_without_a_cap_or_a_file() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("blockchain.cbor");
+    let versions = versions();
+    // nothing to back up on the first save
+    let policy = BackupPolicy::new(dir.path().join("backups"), KEEP);
+    policy
+        .save(&path, |path| fs::write(path, &versions[0]))
+        .unwrap();
+    assert!(policy.backups(&path).unwrap().is_empty());
+    let e = policy.restore_latest_backup(&path).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+    assert_eq!(fs::read(&path).unwrap(), versions[0]);
+    // nor with a cap of none
+    let none = BackupPolicy::new(dir.path().join("backups"), 0);
+    none.save(&path, |path| fs::write(path, &versions[1]))
+        .unwrap();
+    assert!(none.backups(&path).unwrap().is_empty());
+}
@@ -0,0 +1,44 @@
+// the bench binary builds with the bench feature and, under --test,
+// builds its fixture, checks it and runs every benchmark once: a
+// benchmark that panics or a fixture that stops being valid fails here
+// rather than on the next timing run
+use assert_cmd::Command;
+
+const BENCHMARKS: [&str; 11] = [
+    "header_hash/hash",
+    "merkle_root/100",
+    "merkle_root/1000",
+    "merkle_root/10000",
+    "verify_transactions/inputs/1000",
+    "miner_fees/inputs/1000",
+    "add_block/inputs/1000",
+    "add_to_mempool/mempool/500",
+    "add_to_mempool/mempool/5000",
+    "mempool_conflict/mempool/500",
+    "mempool_conflict/mempool/5000",
+];
+
+#[test]
+fn every_benchmark_runs_once() {
+    let output = Command::cargo_bin("bench")
+        .unwrap()
+        .arg("--test")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let allocations = stdout.lines().next().unwrap();
+    assert!(
+        allocations.starts_with("allocations for 1000 inputs: verify_transactions "),
+        "{allocations}"
+    );
+    let lines: Vec<&str> = stdout.lines().collect();
+    for benchmark in BENCHMARKS {
+        let testing = format!("Testing {benchmark}");
+        let at = lines
+            .iter()
+            .position(|line| *line == testing)
+            .unwrap_or_else(|| panic!("{benchmark} didn't run:\n{stdout}"));
+        assert_eq!(lines.get(at + 1), Some(&"Success"), "{benchmark}");
+    }
+}
@@ -0,0 +1,143 @@
+// bloom filters never say no to what was inserted: hashes, keys and
+// raw data, in filters sized right, overfilled or at the size limit,
+// and after a trip over the wire; the transactions paying or spending
+// what a filter watches always match, the chain of spends from them
+// included, while false positives stay near the requested rate
+use lib::bloom::BloomFilter;
+use lib::sha256::Hash;
+use lib::test_utils::ChainBuilder;
+
+const SEED: u64 = 722;
+const ELEMENTS: usize = 1000;
+// tried against each filter for false positives
+const PROBES: usize = 20_000;
+
+// xorshift64*, deterministic for a seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn hash(&mut self) -> Hash {
+        Hash::hash_bytes(&self.next().to_be_bytes())
+    }
+}
+
+// filters of ELEMENTS sized for each rate, for a tenth of them and
+// at the size limit, with what each was sized for
+fn filters() -> Vec<(String, BloomFilter)> {
+    let mut filters = vec![];
+    for (tweak, rate) in [0.1, 0.01, 0.001].into_iter().enumerate() {
+        filters.push((
+            format!("{rate}"),
+            BloomFilter::new(ELEMENTS, rate, tweak as u32),
+        ));
+    }
+    filters.push((
+        "overfilled".to_string(),
+        BloomFilter::new(ELEMENTS / 10, 0.01, 3),
+    ));
+    filters.push((
+        "at the limit".to_string(),
+        BloomFilter::new(usize::MAX, 1e-9, 4),
+    ));
+    filters
+}
+
+#[test]
+fn inserted_elements_always_match() {
+    let mut rng = Rng(SEED);
+    let builder = ChainBuilder::new(SEED).with_keys(16);
+    for (name, mut filter) in filters() {
+        assert!(filter.is_within_limits(), "{name}");
+        let hashes: Vec<Hash> = (0..ELEMENTS).map(|_| rng.hash()).collect();
+        let data: Vec<Vec<u8>> = (0..ELEMENTS as u64)
+            .map(|i| rng.next().to_le_bytes()[..1 + (i % 8) as usize].to_vec())
+            .collect();
+        for hash in &hashes {
+            filter.insert_hash(hash);
+        }
+        for item in &data {
+            filter.insert(item);
+        }
+        for key in builder.keys() {
+            filter.insert_pubkey(&key.public_key());
+        }
+
+        // sent to a peer and back, too
+        let mut bytes = vec![];
+        ciborium::into_writer(&filter, &mut bytes).unwrap();
+        let received: BloomFilter = ciborium::from_reader(&bytes[..]).unwrap();
+        assert_eq!(received, filter, "{name}");
+        for filter in [&filter, &received] {
+            for hash in &hashes {
+                assert!(filter.contains_hash(hash), "{name} {hash}");
+            }
+            for item in &data {
+                assert!(filter.contains(item), "{name} {item:?}");
+            }
+            for key in builder.keys() {
+                assert!(filter.contains_pubkey(&key.public_key()), "{name}");
+            }
+        }
+    }
+}
+
+#[test]
+fn false_positives_near_the_requested_rate() {
+    let mut rng = Rng(SEED + 1);
+    for (tweak, rate) in [0.1, 0.01, 0.001].into_iter().enumerate() {
+        let mut filter = BloomFilter::new(ELEMENTS, rate, tweak as u32);
+        for _ in 0..ELEMENTS {
+            filter.insert_hash(&rng.hash());
+        }
+        let false_positives = (0..PROBES)
+            .filter(|_| filter.contains_hash(&rng.hash()))
+            .count();
+        let measured = false_positives as f64 / PROBES as f64;
+        assert!(measured < 2.0 * rate, "{rate}: {measured}");
+    }
+}
+
+#[test]
+fn spends_of_watched_outputs_match() {
+    // a wallet watching its key, the other keys paying it and it
+    // paying them back
+    let mut builder = ChainBuilder::new(SEED);
+    builder.fund(1, &[50_000]).unwrap();
+    let wallet = builder.key(2).public_key();
+    let mut filter = BloomFilter::new(10, 0.0001, 0);
+    filter.insert_pubkey(&wallet);
+
+    let paid = builder.spend(1, 2, 20_000, 10).unwrap();
+    builder
+        .mine_block(|template| template.transactions = vec![paid.clone()])
+        .unwrap();
+    // the wallet's output is its key's, its payer's change isn't
+    let received = builder.chain().blocks().last().unwrap().clone();
+    let matched = filter.filter_block(&received);
+    assert_eq!(matched.len(), 1);
+    assert_eq!(matched[0].0, paid);
+    assert!(!filter.contains_hash(&paid.outputs[1].hash()));
+    assert!(filter.contains_hash(&paid.outputs[0].hash()));
+
+    // paying all of it to another key, which the filter knows only
+    // from the output it spends
+    let value = paid.outputs[0].value;
+    let payment = builder.output(value - 10, 3);
+    let spent = builder
+        .sign(&[paid.outputs[0].hash()], vec![payment])
+        .unwrap();
+    assert!(filter.is_relevant_and_update(&spent));
+    builder
+        .mine_block(|template| template.transactions = vec![spent])
+        .unwrap();
+    // while what it paid is nothing the wallet watches
+    let onward = builder.spend(3, 1, value - 100, 10).unwrap();
+    assert!(!filter.is_relevant_and_update(&onward));
+}
@@ -0,0 +1,126 @@
+// BlockBuilder and BlockHeader::builder against blocks put together
+// by hand, hash for hash, what they leave unset, and the defaults of
+// Block, BlockHeader and Blockchain
+use chrono::{DateTime, Utc};
+use lib::error::SbdError;
+use lib::sha256::Hash;
+use lib::test_utils::ChainBuilder;
+use lib::types::{Block, BlockHeader, Blockchain, Transaction};
+use lib::utils::MerkleRoot;
+use lib::{MIN_TARGET, U256};
+
+fn timestamp() -> DateTime<Utc> {
+    "2024-03-01T08:00:00Z".parse().unwrap()
+}
+
+// a coinbase and a spend, as the next block of a chain has them
+fn transactions() -> Vec<Transaction> {
+    let mut builder = ChainBuilder::new(686);
+    builder.mine_blocks(2).unwrap();
+    let spend = builder.spend(0, 1, 1000, 10).unwrap();
+    builder
+        .block(|template| template.transactions = vec![spend])
+        .unwrap()
+        .transactions
+}
+
+#[test]
+fn builder_matches_a_block_built_by_hand() {
+    let transactions = transactions();
+    let prev_hash = Hash::hash_bytes(b"previous");
+    let commitment = Hash::hash_bytes(b"utxos");
+    let target = U256::from(0xFFFFu64) << 220;
+    let built = Block::builder()
+        .version(0x2000_0002)
+        .timestamp(timestamp())
+        .nonce(42)
+        .prev_hash(prev_hash)
+        .target(target)
+        .utxo_commitment(commitment)
+        .transactions(transactions.clone())
+        .build()
+        .unwrap();
+    let mut header = BlockHeader::new(
+        timestamp(),
+        42,
+        prev_hash,
+        MerkleRoot::calculate(&transactions),
+        target,
+    );
+    header.version = 0x2000_0002;
+    header.utxo_commitment = commitment;
+    let by_hand = Block::new(header, transactions);
+    assert_eq!(built, by_hand);
+    assert_eq!(built.hash(), by_hand.hash());
+    assert_eq!(built.header.merkle_root, by_hand.header.merkle_root);
+}
+
+#[test]
+fn transactions_one_at_a_time_are_the_same_block() {
+    let transactions = transactions();
+    let all_at_once = Block::builder()
+        .timestamp(timestamp())
+        .transactions(transactions.clone())
+        .build()
+        .unwrap();
+    let one_at_a_time = transactions
+        .into_iter()
+        .fold(Block::builder().timestamp(timestamp()), |builder, tx| {
+            builder.transaction(tx)
+        })
+        .build()
+        .unwrap();
+    assert_eq!(one_at_a_time.hash(), all_at_once.hash());
+}
+
+#[test]
+fn block_without_transactions_is_refused() {
+    let e = Block::builder()
+        .timestamp(timestamp())
+        .prev_hash(Hash::hash_bytes(b"previous"))
+        .build()
+        .unwrap_err();
+    assert!(matches!(e, SbdError::InvalidBlock), "{e}");
+}
+
+#[test]
+fn header_builder_leaves_the_rest_unset() {
+    let merkle_root = MerkleRoot::calculate(&transactions());
+    let header = BlockHeader::builder(merkle_root)
+        .timestamp(timestamp())
+        .build();
+    assert_eq!(header.merkle_root, merkle_root);
+    assert_eq!(header.version, 0);
+    assert_eq!(header.nonce, 0);
+    assert_eq!(header.prev_block_hash, Hash::zero());
+    assert_eq!(header.utxo_commitment, Hash::zero());
+    assert_eq!(header.target, MIN_TARGET);
+    let by_hand = BlockHeader::new(timestamp(), 0, Hash::zero(), merkle_root, MIN_TARGET);
+    assert_eq!(header.hash(), by_hand.hash());
+    // without a timestamp, the time it's built
+    let before = Utc::now();
+    let header = BlockHeader::builder(merkle_root).build();
+    assert!(header.timestamp >= before && header.timestamp <= Utc::now());
+}
+
+#[test]
+fn defaults() {
+    let header = BlockHeader::default();
+    assert_eq!(header.timestamp, DateTime::UNIX_EPOCH);
+    assert_eq!(
+        header,
+        BlockHeader::builder(MerkleRoot::calculate(&[]))
+            .timestamp(DateTime::UNIX_EPOCH)
+            .build()
+    );
+    let block = Block::default();
+    assert_eq!(block.header, header);
+    assert!(block.transactions.is_empty());
+    let chain = Blockchain::default();
+    let new = Blockchain::new();
+    assert_eq!(chain.block_height(), 0);
+    assert_eq!(chain.network(), new.network());
+    assert_eq!(chain.target(), new.target());
+    assert_eq!(chain.utxo_count(), 0);
+    assert!(chain.mempool().is_empty());
+}
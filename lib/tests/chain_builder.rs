@@ -0,0 +1,80 @@
+// the blocks ChainBuilder gets wrong on purpose are refused for that
+// reason alone, leaving the chain as it was
+use lib::error::SbdError;
+use lib::test_utils::{ChainBuilder, Defect};
+
+// a chain a few blocks in, with funds the overspend can take
+fn grown() -> ChainBuilder {
+    let mut builder = ChainBuilder::new(712);
+    builder.fund(1, &[1000, 2000]).unwrap();
+    builder.mine_blocks(2).unwrap();
+    builder
+}
+
+fn refused(defect: Defect) -> SbdError {
+    let mut builder = grown();
+    let height = builder.chain().block_height();
+    let commitment = builder.chain().utxo_commitment();
+    let block = builder.invalid_block(defect).unwrap();
+    let e = builder.chain_mut().add_block(block).unwrap_err();
+    assert_eq!(builder.chain().block_height(), height);
+    assert_eq!(builder.chain().utxo_commitment(), commitment);
+    // the next block the builder makes still fits
+    builder.mine_blocks(1).unwrap();
+    e
+}
+
+#[test]
+fn bad_merkle_root_is_refused() {
+    assert!(matches!(
+        refused(Defect::MerkleRoot),
+        SbdError::InvalidMerkleRoot
+    ));
+}
+
+#[test]
+fn bad_proof_of_work_is_refused() {
+    assert!(matches!(
+        refused(Defect::ProofOfWork),
+        SbdError::InvalidProofOfWork
+    ));
+}
+
+#[test]
+fn overspend_is_refused() {
+    let e = refused(Defect::Overspend);
+    assert!(matches!(e, SbdError::InvalidTransaction), "{e:?}");
+}
+
+#[test]
+fn bad_utxo_commitment_is_refused() {
+    let e = refused(Defect::UtxoCommitment);
+    assert!(
+        matches!(e, SbdError::UtxoCommitmentMismatch { .. }),
+        "{e:?}"
+    );
+}
+
+// a commitment to the wrong utxos is all that's wrong with that
+// block, resealing it mends it
+#[test]
+fn resealed_commitment_is_accepted() {
+    let mut builder = grown();
+    let mut block = builder.invalid_block(Defect::UtxoCommitment).unwrap();
+    builder.reseal(&mut block);
+    builder.chain_mut().add_block(block).unwrap();
+}
+
+#[test]
+fn invalid_blocks_are_deterministic() {
+    for defect in [
+        Defect::MerkleRoot,
+        Defect::ProofOfWork,
+        Defect::Overspend,
+        Defect::UtxoCommitment,
+    ] {
+        let first = grown().invalid_block(defect).unwrap();
+        let second = grown().invalid_block(defect).unwrap();
+        assert_eq!(first.hash(), second.hash(), "{defect:?}");
+    }
+}
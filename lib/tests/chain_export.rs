@@ -0,0 +1,214 @@
+// chain_export of the compat chain, three regtest blocks the last of
+// which spends the first coinbase: every record of each export pinned,
+// the height range cutting rows, and a data dir exporting the same
+use assert_cmd::Command;
+use lib::types::{BlockStore, Blockchain, ChainBatch, ChainStore, ChainTip};
+use lib::utils::Saveable;
+use serde_json::{Value, json};
+use std::path::Path;
+use tempfile::TempDir;
+
+const TARGET: &str = "7fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+const BLOCKS: [&str; 3] = [
+    "5702a0c3821165880056d57956ae4ee37080d5d6f3c6fb550e50b79020d92683",
+    "2a4e79403fd3145fac98c55c7e8518c59febf1003d89457fd455ddc41a59788a",
+    "44d09bba98495a920e42f4e549d8b45775fa18b9f87a2c36cf77c342a32be9e9",
+];
+// the coinbases, then the spend
+const TXIDS: [&str; 4] = [
+    "ded2fb6317ac080594dd0d938f9c39a830882e85f8d07affd92d797363de5b7a",
+    "30bd243e824b05f3da66587e32de61960a62668c618e575787a98f3673b321c8",
+    "8c24a33c522913e7da0ca0f465f952d81148e2400812621f5caba43f024a5d41",
+    "f769e2adeca7c699a1be86e803dff4f261e3692344a908665d153128fe5cb303",
+];
+const MINER: &str = "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f";
+const PAYEE: &str = "024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766";
+const REWARD: u64 = 5_000_000_000;
+const FEE: u64 = 1000;
+
+fn compat() -> String {
+    format!("{}/compat/blockchain.cbor", env!("CARGO_MANIFEST_DIR"))
+}
+
+fn export(path: impl AsRef<Path>, what: &str, args: &[&str]) -> Vec<Value> {
+    let output = Command::cargo_bin("chain_export")
+        .unwrap()
+        .arg(path.as_ref())
+        .args(["--what", what])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn timestamp(height: usize) -> String {
+    format!("2024-01-01T00:00:{:02}Z", height * 10)
+}
+
+#[test]
+fn blocks_are_pinned() {
+    // a block of one transaction has its txid as merkle root
+    let merkle_roots = [
+        TXIDS[0],
+        TXIDS[1],
+        "d6f90902b95fa4e40b6341d508238ece639c9103044ea9b3f99e2727c8e6a386",
+    ];
+    let sizes = [402, 434, 1063];
+    let expected: Vec<Value> = (0..3)
+        .map(|height| {
+            let prev_hash = match height {
+                0 => "0".repeat(64),
+                _ => BLOCKS[height - 1].to_string(),
+            };
+            json!({
+                "height": height,
+                "hash": BLOCKS[height],
+                "prev_hash": prev_hash,
+                "merkle_root": merkle_roots[height],
+                "timestamp": timestamp(height),
+                "nonce": 0,
+                "target": TARGET,
+                "transactions": if height == 2 { 2 } else { 1 },
+                "size": sizes[height],
+            })
+        })
+        .collect();
+    assert_eq!(export(compat(), "blocks", &[]), expected);
+}
+
+#[test]
+fn transactions_are_pinned() {
+    let coinbase = |height: usize, value: u64| {
+        json!({
+            "height": height,
+            "timestamp": timestamp(height),
+            "txid": TXIDS[height],
+            "coinbase": true,
+            "inputs": 0,
+            "outputs": 1,
+            "input_value": null,
+            "output_value": value,
+            "fee": null,
+            "size": 227,
+        })
+    };
+    let expected = vec![
+        coinbase(0, REWARD),
+        coinbase(1, REWARD),
+        // the fee of the spend claimed
+        coinbase(2, REWARD + FEE),
+        json!({
+            "height": 2,
+            "timestamp": timestamp(2),
+            "txid": TXIDS[3],
+            "coinbase": false,
+            "inputs": 1,
+            "outputs": 2,
+            "input_value": REWARD,
+            "output_value": REWARD - FEE,
+            "fee": FEE,
+            "size": 629,
+        }),
+    ];
+    assert_eq!(export(compat(), "txs", &[]), expected);
+}
+
+#[test]
+fn utxos_are_pinned() {
+    let utxo = |hash: &str, txid: &str, height: u64, value: u64, address: &str, coinbase: bool| {
+        json!({
+            "hash": hash,
+            "txid": txid,
+            "height": height,
+            "value": value,
+            "address": address,
+            "coinbase": coinbase,
+        })
+    };
+    // the first coinbase is spent, half of it paid on; sorted by
+    // height then hash
+    let expected = vec![
+        utxo(
+            "23a0f76d06033f6b17e778bb4da1e6e95c61de3bd9ff404ce16a631bea92e6cb",
+            TXIDS[1],
+            1,
+            REWARD,
+            MINER,
+            true,
+        ),
+        utxo(
+            "69d87fec128198da76ae7c37e682f0fd3c006cc93e01b33c98cd19170e7f432c",
+            TXIDS[3],
+            2,
+            REWARD / 2,
+            PAYEE,
+            false,
+        ),
+        utxo(
+            "bc833e68d01de685de20cc2064da49108955c9a7597bad960287eff617bdaa64",
+            TXIDS[3],
+            2,
+            REWARD / 2 - FEE,
+            MINER,
+            false,
+        ),
+        utxo(
+            "dc6371e29462cab30c0b91c6b4b038a2c716b6cc228a6aade0460292019e04e2",
+            TXIDS[2],
+            2,
+            REWARD + FEE,
+            MINER,
+            true,
+        ),
+    ];
+    let utxos = export(compat(), "utxos", &[]);
+    assert_eq!(utxos, expected);
+    // adding up to the coins mined
+    let total: u64 = utxos.iter().map(|u| u["value"].as_u64().unwrap()).sum();
+    assert_eq!(total, 3 * REWARD);
+}
+
+#[test]
+fn height_range_cuts_rows() {
+    let count = |what, args: &[&str]| export(compat(), what, args).len();
+    assert_eq!(count("blocks", &["--from-height", "1"]), 2);
+    assert_eq!(count("blocks", &["--to-height", "0"]), 1);
+    assert_eq!(count("txs", &["--from-height", "2"]), 2);
+    assert_eq!(count("txs", &["--to-height", "1"]), 2);
+    // as of height 1, before the spend: both coinbases
+    let utxos = export(compat(), "utxos", &["--to-height", "1"]);
+    let txids: Vec<&str> = utxos.iter().map(|u| u["txid"].as_str().unwrap()).collect();
+    assert_eq!(txids, TXIDS[..2]);
+    // created from height 2 on
+    assert_eq!(count("utxos", &["--from-height", "2"]), 3);
+}
+
+#[test]
+fn data_dir_exports_the_same() {
+    let dir = TempDir::new().unwrap();
+    let chain = Blockchain::load_from_file(compat()).unwrap();
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    for (height, block) in chain.blocks().enumerate() {
+        let tip = ChainTip {
+            network: chain.network(),
+            height: height as u64 + 1,
+            hash: block.hash(),
+            target: chain.target(),
+        };
+        store
+            .commit(ChainBatch::connect(block.clone(), tip))
+            .unwrap();
+    }
+    for what in ["blocks", "txs", "utxos"] {
+        assert_eq!(
+            export(dir.path(), what, &[]),
+            export(compat(), what, &[]),
+            "{what}"
+        );
+    }
+}
@@ -0,0 +1,210 @@
+// chain_init on regtest: a chain of COINBASE_MATURITY blocks past the
+// genesis block has mature coinbases, which tx_gen spends from the key
+// it wrote and a block on top mines; the balance and mature amounts it
+// prints are those of the chain. a shallower chain has nothing mature
+// to spend, and the same seed writes the same bytes
+use assert_cmd::Command;
+use chrono::Duration;
+use lib::COINBASE_MATURITY;
+use lib::crypto::PrivateKey;
+use lib::error::SbdError;
+use lib::sha256::Hash;
+use lib::types::{Block, Blockchain, PartiallySignedTransaction, Transaction, TransactionOutput};
+use lib::utils::{Saveable, format_value};
+use predicates::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+use uuid::Uuid;
+
+const SEED: &str = "708";
+const FEE: u64 = 1000;
+
+struct Init {
+    dir: TempDir,
+    chain: Blockchain,
+    stdout: String,
+}
+
+impl Init {
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    fn address(&self) -> String {
+        let coinbase = &self.chain.blocks().next().unwrap().transactions[0];
+        coinbase.outputs[0].pubkey.to_address()
+    }
+
+    fn tx_gen(&self, amount: u64) -> assert_cmd::assert::Assert {
+        Command::cargo_bin("tx_gen")
+            .unwrap()
+            .arg("spend")
+            .arg("--chain")
+            .arg(self.path("chain.cbor"))
+            .arg("--key")
+            .arg(self.path("key"))
+            .args(["--to", &self.address()])
+            .args(["--amount", &amount.to_string()])
+            .args(["--fee", &FEE.to_string()])
+            .arg("--out")
+            .arg(self.path("tx.cbor"))
+            .assert()
+    }
+}
+
+// a seeded regtest chain of blocks past the genesis block, paying a
+// new key
+fn init(blocks: u64) -> Init {
+    let dir = TempDir::new().unwrap();
+    let output = Command::cargo_bin("chain_init")
+        .unwrap()
+        .arg("--out")
+        .arg(dir.path().join("chain.cbor"))
+        .args(["--blocks", &blocks.to_string(), "--seed", SEED])
+        .args(["--network", "regtest"])
+        .arg("--new-key")
+        .arg(dir.path().join("key"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let chain = Blockchain::load_from_file(dir.path().join("chain.cbor")).unwrap();
+    Init {
+        dir,
+        chain,
+        stdout: String::from_utf8(output.stdout).unwrap(),
+    }
+}
+
+// what the chain holds for the key, and how much of it the next
+// block may spend
+fn balances(chain: &Blockchain) -> (u64, u64) {
+    let immature = chain.immature_coinbases();
+    let mut balance = 0;
+    let mut mature = 0;
+    for (hash, output, _) in chain.utxos().iter() {
+        balance += output.value;
+        if !immature.contains_key(hash) {
+            mature += output.value;
+        }
+    }
+    (balance, mature)
+}
+
+// the next block, its coinbase paying the reward and fees to the key
+fn next_block(chain: &Blockchain, transactions: Vec<Transaction>, fees: u64) -> Block {
+    let tip = chain.blocks().last().unwrap();
+    let spacing = chain.params().ideal_block_time as i64;
+    let coinbase = Transaction::new(
+        vec![],
+        vec![TransactionOutput {
+            value: chain.params().reward_at_height(chain.chain_height()) + fees,
+            unique_id: Uuid::from_u128(chain.chain_height() as u128),
+            pubkey: tip.transactions[0].outputs[0].pubkey.clone(),
+            spendable_after_height: None,
+        }],
+    );
+    let mut block = Block::builder()
+        .timestamp(tip.header.timestamp + Duration::seconds(spacing))
+        .prev_hash(tip.hash())
+        .target(chain.target())
+        .transactions([vec![coinbase], transactions].concat())
+        .build()
+        .unwrap();
+    while !block.header.mine(1_000_000) {}
+    block
+}
+
+#[test]
+fn deep_chain_funds_a_spendable_key() {
+    let init = init(COINBASE_MATURITY);
+    let chain = &init.chain;
+    assert_eq!(chain.block_height(), COINBASE_MATURITY + 1);
+    chain.verify().unwrap();
+    let address = init.address();
+    for block in chain.blocks() {
+        assert_eq!(
+            block.transactions[0].outputs[0].pubkey.to_address(),
+            address
+        );
+    }
+    // the genesis block and the next are mature
+    let (balance, mature) = balances(chain);
+    assert_eq!(
+        mature,
+        chain.params().reward_at_height(0) + chain.params().reward_at_height(1)
+    );
+    assert_eq!(
+        init.stdout.lines().next().unwrap(),
+        format!(
+            "{address} has {}, {} of it mature",
+            format_value(balance),
+            format_value(mature)
+        )
+    );
+    let tip = chain.blocks().last().unwrap().hash();
+    assert!(init.stdout.contains(&format!(
+        "tip {} at height {COINBASE_MATURITY}",
+        tip.to_hex()
+    )));
+
+    // no more than the mature coinbases can be spent
+    init.tx_gen(mature)
+        .failure()
+        .stderr(predicate::str::contains("insufficient funds"));
+    init.tx_gen(mature - FEE).success();
+    let spend = Transaction::load_from_file(init.path("tx.cbor")).unwrap();
+    let mut chain = init.chain.clone();
+    chain.add_to_mempool(spend.clone()).unwrap();
+    let block = next_block(&chain, vec![spend.clone()], FEE);
+    chain.add_block(block).unwrap();
+    assert_eq!(chain.confirmations(&spend.hash()), Some(1));
+    assert!(chain.mempool().is_empty());
+}
+
+#[test]
+fn shallow_chain_has_nothing_mature() {
+    let init = init(COINBASE_MATURITY - 2);
+    let (balance, mature) = balances(&init.chain);
+    assert_eq!(mature, 0);
+    assert!(init.stdout.contains(&format!(
+        "has {}, 0.00000000 of it mature",
+        format_value(balance)
+    )));
+    init.tx_gen(1)
+        .failure()
+        .stderr(predicate::str::contains("no spendable outputs"));
+    // a spend of the genesis coinbase is refused as immature
+    let coinbase = &init.chain.blocks().next().unwrap().transactions[0];
+    let spent: Hash = coinbase.outputs[0].hash();
+    let key = PrivateKey::from_hex(fs::read_to_string(init.path("key")).unwrap().trim()).unwrap();
+    let mut unsigned = PartiallySignedTransaction::new(
+        vec![(spent, coinbase.outputs[0].clone())],
+        vec![TransactionOutput {
+            value: coinbase.outputs[0].value - FEE,
+            ..coinbase.outputs[0].clone()
+        }],
+    );
+    unsigned.sign(&key).unwrap();
+    let spend = unsigned.finalize().unwrap();
+    let mut chain = init.chain.clone();
+    assert!(matches!(
+        chain.add_to_mempool(spend),
+        Err(SbdError::ImmatureCoinbase { .. })
+    ));
+}
+
+#[test]
+fn same_seed_same_chain() {
+    let first = init(3);
+    let second = init(3);
+    assert_eq!(
+        fs::read(first.path("chain.cbor")).unwrap(),
+        fs::read(second.path("chain.cbor")).unwrap()
+    );
+    assert_eq!(
+        fs::read(first.path("key")).unwrap(),
+        fs::read(second.path("key")).unwrap()
+    );
+    assert_eq!(first.stdout, second.stdout);
+}
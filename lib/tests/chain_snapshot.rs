@@ -0,0 +1,116 @@
+// Blockchain::snapshot and clones of a chain share its blocks and
+// utxos until it changes: a snapshot, or the chain it was cloned
+// from, reads the same after later blocks and mempool spends as it
+// did when taken, from another thread too
+use lib::sha256::Hash;
+use lib::test_utils::ChainBuilder;
+use lib::types::{Blockchain, ChainSnapshot};
+use std::thread;
+
+// a chain with spends and more to come
+fn builder() -> ChainBuilder {
+    let mut builder = ChainBuilder::new(695);
+    builder.fund(0, &[1000, 2000, 3000]).unwrap();
+    builder.mine_blocks(2).unwrap();
+    let spend = builder.spend(0, 1, 2500, 10).unwrap();
+    builder
+        .mine_block(|template| template.transactions = vec![spend])
+        .unwrap();
+    builder
+}
+
+// what a reader sees: the block hashes, the tip and target, and every
+// utxo with its value and whether it's reserved, sorted
+type Seen = (Vec<Hash>, Hash, lib::U256, Vec<(Hash, u64, bool)>);
+
+fn seen(snapshot: &ChainSnapshot) -> Seen {
+    let mut utxos: Vec<_> = snapshot
+        .utxos()
+        .iter()
+        .map(|(hash, output, reserved)| (*hash, output.value, reserved))
+        .collect();
+    utxos.sort();
+    (
+        snapshot.blocks().map(|block| block.hash()).collect(),
+        snapshot.tip(),
+        snapshot.target(),
+        utxos,
+    )
+}
+
+fn seen_of(chain: &Blockchain) -> Seen {
+    seen(&chain.snapshot())
+}
+
+// spend to the mempool, then mine it and a few more
+fn grow(builder: &mut ChainBuilder) {
+    let spend = builder.spend(1, 2, 1000, 10).unwrap();
+    builder.chain_mut().add_to_mempool(spend.clone()).unwrap();
+    builder
+        .mine_block(|template| template.transactions = vec![spend])
+        .unwrap();
+    let payment = builder.spend(0, 3, 500, 10).unwrap();
+    builder.chain_mut().add_to_mempool(payment).unwrap();
+    builder.mine_blocks(3).unwrap();
+}
+
+#[test]
+fn snapshot_is_unaffected_by_later_blocks() {
+    let mut builder = builder();
+    let snapshot = builder.chain().snapshot();
+    let before = seen(&snapshot);
+    assert_eq!(before, seen_of(builder.chain()));
+    assert_eq!(snapshot.block_height(), builder.chain().block_height());
+    assert_eq!(snapshot.tip(), builder.chain().tip_hash());
+    let spent = builder.chain().blocks().last().unwrap().transactions[1].inputs[0]
+        .prev_transaction_output_hash;
+    grow(&mut builder);
+    // the chain moved on
+    let chain = builder.chain();
+    assert_eq!(chain.block_height(), snapshot.block_height() + 4);
+    assert_ne!(seen_of(chain), before);
+    // the snapshot didn't
+    assert_eq!(seen(&snapshot), before);
+    assert!(snapshot.utxo(&spent).is_none());
+    let tip = chain.blocks().last().unwrap();
+    let coinbase = tip.transactions[0].outputs[0].hash();
+    assert!(chain.utxo(&coinbase).is_some());
+    assert!(snapshot.utxo(&coinbase).is_none());
+    // and nothing in it is reserved, the mempool's spends came later
+    assert!(seen(&snapshot).3.iter().all(|(_, _, reserved)| !reserved));
+}
+
+#[test]
+fn clone_is_unaffected_by_later_blocks() {
+    let mut builder = builder();
+    let clone = builder.chain().clone();
+    let before = seen_of(&clone);
+    grow(&mut builder);
+    assert_eq!(seen_of(&clone), before);
+    clone.verify().unwrap();
+    // and the clone grows on its own
+    let mut clone = clone;
+    let block = builder.chain().blocks().nth(clone.block_height() as usize);
+    clone.add_block(block.unwrap().clone()).unwrap();
+    assert_ne!(seen_of(&clone), before);
+    assert_eq!(
+        builder.chain().block_height(),
+        clone.block_height() + 3,
+        "the original kept its blocks"
+    );
+}
+
+#[test]
+fn snapshot_reads_the_same_from_another_thread() {
+    let mut builder = builder();
+    let snapshot = builder.chain().snapshot();
+    let before = seen(&snapshot);
+    let reader = thread::spawn({
+        let snapshot = snapshot.clone();
+        move || (0..50).map(|_| seen(&snapshot)).collect::<Vec<_>>()
+    });
+    grow(&mut builder);
+    for read in reader.join().unwrap() {
+        assert_eq!(read, before);
+    }
+}
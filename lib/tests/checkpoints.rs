@@ -0,0 +1,198 @@
+// signed checkpoints: a bundle signed by a trusted key is installed,
+// once, and holds the chain to its blocks, above the tip as below; a
+// checkpoint no trusted key signed, or changed after signing, and one
+// naming another block than the chain has installs none of its bundle
+use lib::crypto::PrivateKey;
+use lib::error::SbdError;
+use lib::sha256::Hash;
+use lib::test_utils::{ChainBuilder, seeded_key};
+use lib::types::{Blockchain, CheckpointBundle, SignedCheckpoint};
+use lib::utils::Saveable;
+
+const SEED: u64 = 726;
+const BLOCKS: u64 = 12;
+// below the tip of the node's chain, and above it
+const MINED: u64 = 3;
+const AHEAD: u64 = 8;
+const SIGNED: &str = "2024-02-01T00:00:00Z";
+
+fn operator() -> PrivateKey {
+    seeded_key(SEED, 100)
+}
+
+// the whole chain, and a node's chain of its first blocks, its tip
+// between MINED and AHEAD
+fn chains() -> (Blockchain, Blockchain) {
+    let mut builder = ChainBuilder::new(SEED);
+    builder.mine_blocks(BLOCKS).unwrap();
+    let full = builder.into_chain();
+    let mut node = Blockchain::with_params(full.params().clone());
+    for block in full.blocks().take(AHEAD as usize - 2) {
+        node.add_block(block.clone()).unwrap();
+    }
+    (full, node)
+}
+
+fn hash_at(chain: &Blockchain, height: u64) -> Hash {
+    chain.blocks().nth(height as usize).unwrap().hash()
+}
+
+fn signed(height: u64, hash: Hash, key: &PrivateKey) -> SignedCheckpoint {
+    SignedCheckpoint::sign(height, hash, SIGNED.parse().unwrap(), key)
+}
+
+// the chain's blocks at the heights, signed with the key
+fn bundle(chain: &Blockchain, heights: &[u64], key: &PrivateKey) -> CheckpointBundle {
+    CheckpointBundle {
+        checkpoints: heights
+            .iter()
+            .map(|&height| signed(height, hash_at(chain, height), key))
+            .collect(),
+    }
+}
+
+#[test]
+fn valid_bundle_is_applied() {
+    let (full, mut node) = chains();
+    let trusted = [seeded_key(SEED, 101).public_key(), operator().public_key()];
+    let signed = bundle(&full, &[MINED, AHEAD], &operator());
+    // as the operator hands it out
+    let mut file = vec![];
+    signed.save(&mut file).unwrap();
+    let signed = CheckpointBundle::load(&file[..]).unwrap();
+    for checkpoint in &signed.checkpoints {
+        assert_eq!(checkpoint.signer(&trusted), Some(&trusted[1]));
+    }
+
+    assert_eq!(node.apply_signed_checkpoints(&signed, &trusted).unwrap(), 2);
+    let installed: Vec<(u64, Hash)> = node
+        .checkpoints()
+        .iter()
+        .map(|(height, hash)| (*height, *hash))
+        .collect();
+    assert_eq!(
+        installed,
+        vec![
+            (MINED, hash_at(&full, MINED)),
+            (AHEAD, hash_at(&full, AHEAD))
+        ]
+    );
+    // nothing new the second time
+    assert_eq!(node.apply_signed_checkpoints(&signed, &trusted).unwrap(), 0);
+
+    // the chain goes on through the checkpoint above its tip
+    for block in full.blocks().skip(node.block_height() as usize) {
+        node.add_block(block.clone()).unwrap();
+    }
+    assert_eq!(node.tip_hash(), full.tip_hash());
+}
+
+#[test]
+fn forged_signature_is_rejected() {
+    let (full, mut node) = chains();
+    let trusted = [operator().public_key()];
+    let impostor = seeded_key(SEED, 101);
+
+    // signed by a key nobody trusts, after a good one
+    let mut forged = bundle(&full, &[MINED], &operator());
+    forged
+        .checkpoints
+        .extend(bundle(&full, &[AHEAD], &impostor).checkpoints);
+    assert!(matches!(
+        node.apply_signed_checkpoints(&forged, &trusted),
+        Err(SbdError::InvalidCheckpointSignature { height: AHEAD })
+    ));
+    assert!(node.checkpoints().is_empty());
+
+    // the operator's signature over another block, or another height
+    let mut altered = bundle(&full, &[AHEAD], &operator());
+    altered.checkpoints[0].hash = Hash::hash_bytes(b"another block");
+    let mut moved = bundle(&full, &[AHEAD], &operator());
+    moved.checkpoints[0].height += 1;
+    for bundle in [altered, moved] {
+        let height = bundle.checkpoints[0].height;
+        assert!(matches!(
+            node.apply_signed_checkpoints(&bundle, &trusted),
+            Err(SbdError::InvalidCheckpointSignature { height: refused }) if refused == height
+        ));
+    }
+    // and with no trusted keys nothing is
+    let signed = bundle(&full, &[MINED], &operator());
+    assert!(matches!(
+        node.apply_signed_checkpoints(&signed, &[]),
+        Err(SbdError::InvalidCheckpointSignature { height: MINED })
+    ));
+    assert!(node.checkpoints().is_empty());
+}
+
+#[test]
+fn another_branch_below_a_checkpoint_is_refused() {
+    let (full, mut node) = chains();
+    let trusted = [operator().public_key()];
+    node.apply_signed_checkpoints(&bundle(&full, &[AHEAD], &operator()), &trusted)
+        .unwrap();
+
+    // a branch from the node's tip, its own block at the checkpoint
+    let mut branch = ChainBuilder::new(SEED);
+    for block in node.blocks() {
+        branch.chain_mut().add_block(block.clone()).unwrap();
+    }
+    while branch.chain().chain_height() < AHEAD {
+        let block = branch.mine_block(|template| template.miner = 1).unwrap();
+        node.add_block(block).unwrap();
+    }
+    let replacing = branch.block(|template| template.miner = 1).unwrap();
+    let height = node.block_height();
+    match node.add_block(replacing.clone()) {
+        Err(SbdError::CheckpointConflict {
+            height: at,
+            expected,
+            found,
+        }) => {
+            assert_eq!(at, AHEAD);
+            assert_eq!(expected, hash_at(&full, AHEAD));
+            assert_eq!(found, replacing.hash());
+        }
+        other => panic!("{other:?}"),
+    }
+    assert_eq!(node.block_height(), height);
+
+    // a bundle the chain already went another way on installs none
+    // of it, the good checkpoint with it included
+    let (full, mut node) = chains();
+    let mut elsewhere = ChainBuilder::new(SEED + 1);
+    elsewhere.mine_blocks(AHEAD + 1).unwrap();
+    let theirs = |height| hash_at(elsewhere.chain(), height);
+    let conflicting = CheckpointBundle {
+        checkpoints: vec![
+            signed(AHEAD, hash_at(&full, AHEAD), &operator()),
+            signed(MINED, theirs(MINED), &operator()),
+        ],
+    };
+    match node.apply_signed_checkpoints(&conflicting, &trusted) {
+        Err(SbdError::CheckpointConflict {
+            height,
+            expected,
+            found,
+        }) => {
+            assert_eq!(height, MINED);
+            assert_eq!(expected, theirs(MINED));
+            assert_eq!(found, hash_at(&full, MINED));
+        }
+        other => panic!("{other:?}"),
+    }
+    assert!(node.checkpoints().is_empty());
+
+    // nor one against an installed checkpoint
+    node.apply_signed_checkpoints(&bundle(&full, &[AHEAD], &operator()), &trusted)
+        .unwrap();
+    let against = CheckpointBundle {
+        checkpoints: vec![signed(AHEAD, theirs(AHEAD), &operator())],
+    };
+    assert!(matches!(
+        node.apply_signed_checkpoints(&against, &trusted),
+        Err(SbdError::CheckpointConflict { height: AHEAD, .. })
+    ));
+    assert_eq!(node.checkpoints().len(), 1);
+    assert_eq!(node.checkpoints()[&AHEAD], hash_at(&full, AHEAD));
+}
@@ -0,0 +1,96 @@
+// the sha256 trailer of saved files: any one byte flipped, compressed
+// or not, is reported as a checksum mismatch, while files from before
+// the trailer still load and get one when saved again
+use lib::error::SbdError;
+use lib::test_utils::ChainBuilder;
+use lib::types::{Blockchain, Transaction};
+use lib::utils::Saveable;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use tempfile::TempDir;
+
+const TRAILER_SIZE: usize = 32;
+
+fn transaction() -> Transaction {
+    let mut builder = ChainBuilder::new(674);
+    builder.mine_blocks(2).unwrap();
+    builder.spend(0, 1, 1000, 10).unwrap()
+}
+
+// the expected and actual checksums of a mismatch
+fn mismatch(e: &Error) -> Option<(&str, &str)> {
+    assert_eq!(e.kind(), ErrorKind::InvalidData, "{e}");
+    match e.get_ref()?.downcast_ref::<SbdError>()? {
+        SbdError::ChecksumMismatch { expected, actual } => Some((expected, actual)),
+        _ => None,
+    }
+}
+
+#[test]
+fn any_flipped_byte_is_a_mismatch() {
+    let transaction = transaction();
+    let mut plain = vec![];
+    transaction.save(&mut plain).unwrap();
+    let mut compressed = vec![];
+    transaction.save_compressed(&mut compressed).unwrap();
+    for data in [plain, compressed] {
+        for i in 0..data.len() {
+            let mut damaged = data.clone();
+            damaged[i] ^= 1;
+            let e = Transaction::load(&damaged[..]).unwrap_err();
+            assert!(mismatch(&e).is_some(), "byte {i}: {e}");
+        }
+    }
+}
+
+#[test]
+fn mismatch_names_both_checksums() {
+    let mut data = vec![];
+    transaction().save(&mut data).unwrap();
+    let trailer = hex::encode(&data[data.len() - TRAILER_SIZE..]);
+    // a byte of the cbor
+    let body = data.len() - TRAILER_SIZE - 10;
+    data[body] ^= 0x80;
+    let e = Transaction::load(&data[..]).unwrap_err();
+    let expected = sha256::digest(&data[..data.len() - TRAILER_SIZE]);
+    assert_eq!(mismatch(&e), Some((expected.as_str(), trailer.as_str())));
+    assert_eq!(
+        e.to_string(),
+        format!("Checksum mismatch: expected {expected}, file has {trailer}")
+    );
+}
+
+#[test]
+fn file_without_a_trailer_loads() {
+    let dir = TempDir::new().unwrap();
+    let compat = format!("{}/compat/blockchain.cbor", env!("CARGO_MANIFEST_DIR"));
+    let chain = Blockchain::load_from_file(&compat).unwrap();
+    let current = fs::read(&compat).unwrap();
+    // the cbor, after the magic, version, tag, schema and flags
+    let tag = b"Blockchain";
+    let header = 4 + 2 + 1 + tag.len() + 2 + 1;
+    let body = &current[header..current.len() - TRAILER_SIZE];
+    let mut version_1 = b"SBDF".to_vec();
+    version_1.extend_from_slice(&1u16.to_be_bytes());
+    version_1.push(tag.len() as u8);
+    version_1.extend_from_slice(tag);
+    version_1.extend_from_slice(body);
+
+    let path = dir.path().join("blockchain.cbor");
+    for legacy in [body, &version_1[..]] {
+        fs::write(&path, legacy).unwrap();
+        let loaded = Blockchain::load_from_file(&path).unwrap();
+        assert_eq!(loaded.tip_hash(), chain.tip_hash());
+        // saved again with a trailer, which is then checked
+        loaded.save_to_file(&path).unwrap();
+        let mut saved = fs::read(&path).unwrap();
+        assert_eq!(saved[..header], current[..header]);
+        let checked = saved.len() - TRAILER_SIZE;
+        assert_eq!(
+            hex::encode(&saved[checked..]),
+            sha256::digest(&saved[..checked])
+        );
+        saved[header + body.len() / 2] ^= 1;
+        assert!(mismatch(&Blockchain::load(&saved[..]).unwrap_err()).is_some());
+    }
+}
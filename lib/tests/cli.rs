@@ -0,0 +1,282 @@
+// the binaries as a user runs them: --help, a usage error, which
+// exits 2, and one run doing what the binary is for
+use assert_cmd::Command;
+use lib::crypto::Signature;
+use lib::test_utils::ChainBuilder;
+use lib::types::{Transaction, TransactionInput};
+use lib::utils::Saveable;
+use predicates::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+const BINARIES: [&str; 15] = [
+    "bench",
+    "block_gen",
+    "block_print",
+    "chain_doctor",
+    "chain_export",
+    "chain_init",
+    "checkpoint_sign",
+    "compat_fixtures",
+    "explorer",
+    "fuzz",
+    "key_gen",
+    "replay",
+    "tx_gen",
+    "tx_print",
+    "tx_sign",
+];
+
+// the hash of the tip of the compat chain
+const TIP: &str = "44d09bba98495a920e42f4e549d8b45775fa18b9f87a2c36cf77c342a32be9e9";
+
+fn bin(name: &str) -> Command {
+    let mut command = Command::cargo_bin(name).unwrap();
+    command.current_dir(env!("CARGO_MANIFEST_DIR"));
+    command
+}
+
+fn compat(name: &str) -> PathBuf {
+    Path::new("compat").join(name)
+}
+
+#[test]
+fn help() {
+    for name in BINARIES {
+        bin(name)
+            .arg("--help")
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("Usage:").and(predicate::str::contains(name)));
+    }
+}
+
+#[test]
+fn unknown_flag_is_a_usage_error() {
+    for name in BINARIES {
+        bin(name)
+            .arg("--no-such-flag")
+            .assert()
+            .code(2)
+            .stderr(predicate::str::contains("unexpected argument"));
+    }
+}
+
+#[test]
+fn failing_work_exits_1() {
+    bin("block_print")
+        .arg("no-such-block.cbor")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("no-such-block.cbor"));
+}
+
+#[test]
+fn block_gen_writes_a_block() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("block.cbor");
+    bin("block_gen").arg(&path).assert().success();
+    bin("block_print").arg(&path).assert().success();
+}
+
+#[test]
+fn block_print_prints_the_hash() {
+    bin("block_print")
+        .arg(compat("block.cbor"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(TIP));
+}
+
+#[test]
+fn chain_doctor_finds_nothing_wrong() {
+    bin("chain_doctor")
+        .arg(compat("blockchain.cbor"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("no discrepancies"));
+}
+
+#[test]
+fn chain_export_writes_a_line_per_block() {
+    bin("chain_export")
+        .args(["--what", "blocks"])
+        .arg(compat("blockchain.cbor"))
+        .assert()
+        .success()
+        .stdout(predicate::function(|out: &str| out.lines().count() == 3))
+        .stdout(predicate::str::contains(TIP));
+}
+
+#[test]
+fn chain_init_mines_a_chain() {
+    let dir = TempDir::new().unwrap();
+    let key = dir.path().join("key");
+    bin("chain_init")
+        .arg("--out")
+        .arg(dir.path().join("chain.cbor"))
+        .args(["--blocks", "2", "--network", "regtest", "--seed", "713"])
+        .arg("--new-key")
+        .arg(&key)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("at height 2"));
+    assert!(key.exists());
+}
+
+#[test]
+fn checkpoint_sign_adds_the_tip() {
+    let dir = TempDir::new().unwrap();
+    let key = dir.path().join("operator.key");
+    bin("key_gen")
+        .arg("new")
+        .arg("--out")
+        .arg(&key)
+        .assert()
+        .success();
+    let bundle = dir.path().join("checkpoints");
+    bin("checkpoint_sign")
+        .arg(&key)
+        .arg(&bundle)
+        .args(["--height", "2", "--chain"])
+        .arg(compat("blockchain.cbor"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(TIP));
+    assert!(bundle.exists());
+}
+
+#[test]
+fn compat_fixtures_check() {
+    bin("compat_fixtures")
+        .args(["check", "compat"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("compatible"));
+}
+
+#[test]
+fn explorer_shows_the_tip() {
+    bin("explorer")
+        .arg(compat("blockchain.cbor"))
+        .arg("tip")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(TIP));
+}
+
+#[test]
+fn fuzz_runs_a_seed() {
+    bin("fuzz")
+        .args(["run", "block", "fuzz/seeds/block/saved"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ok"));
+}
+
+#[test]
+fn key_gen_inspects_the_key_it_made() {
+    let dir = TempDir::new().unwrap();
+    let key = dir.path().join("key");
+    bin("key_gen")
+        .arg("new")
+        .arg("--out")
+        .arg(&key)
+        .assert()
+        .success();
+    bin("key_gen")
+        .arg("inspect")
+        .arg(&key)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("address"));
+}
+
+#[test]
+fn replay_finds_no_divergence() {
+    bin("replay")
+        .arg(compat("blockchain.cbor"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"first_divergent_block\": null"));
+}
+
+#[test]
+fn tx_gen_writes_a_transaction() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("tx.cbor");
+    bin("tx_gen")
+        .arg("coinbase-like")
+        .arg(&path)
+        .assert()
+        .success();
+    bin("tx_print").arg(&path).assert().success();
+}
+
+#[test]
+fn tx_print_prints_the_txid() {
+    bin("tx_print")
+        .arg(compat("transaction.cbor"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "f769e2adeca7c699a1be86e803dff4f261e3692344a908665d153128fe5cb303",
+        ));
+}
+
+// an unsigned spend of the first coinbase of a chain, signed and
+// then checked
+#[test]
+fn tx_sign_signs_a_spend() {
+    let mut builder = ChainBuilder::new(713);
+    builder.mine_blocks(1).unwrap();
+    let (spent, output) = builder.spendable(0).remove(0);
+    let payment = builder.output(output.value, 1);
+    let unsigned = Transaction::new(
+        vec![TransactionInput {
+            prev_transaction_output_hash: spent,
+            signature: Signature::placeholder(),
+        }],
+        vec![payment],
+    );
+    let dir = TempDir::new().unwrap();
+    let path = |name: &str| dir.path().join(name);
+    fs::write(path("key"), builder.key(0).to_hex()).unwrap();
+    builder.chain().save_to_file(path("chain.cbor")).unwrap();
+    unsigned.save_to_file(path("unsigned.cbor")).unwrap();
+    bin("tx_sign")
+        .arg(path("unsigned.cbor"))
+        .arg(path("key"))
+        .arg("--utxos")
+        .arg(path("chain.cbor"))
+        .arg("--out")
+        .arg(path("signed.cbor"))
+        .assert()
+        .success();
+    bin("tx_sign")
+        .arg(path("signed.cbor"))
+        .arg("--check-only")
+        .arg("--utxos")
+        .arg(path("chain.cbor"))
+        .assert()
+        .success();
+    bin("tx_sign")
+        .arg(path("unsigned.cbor"))
+        .arg("--check-only")
+        .arg("--utxos")
+        .arg(path("chain.cbor"))
+        .assert()
+        .code(1);
+}
+
+// the fixture takes a minute to build without optimizations
+#[test]
+#[cfg_attr(debug_assertions, ignore = "slow in debug, run with --release")]
+fn bench_smoke_test() {
+    bin("bench")
+        .args(["--test", "header_hash"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Success"));
+}
@@ -0,0 +1,69 @@
+// a chain saved zstd compressed loads back the same, and files saved
+// before compression load through the same load_from_file
+use lib::sha256::Hash;
+use lib::test_utils::ChainBuilder;
+use lib::types::Blockchain;
+use lib::utils::{Saveable, is_compressed};
+use std::fs;
+use tempfile::TempDir;
+
+const BLOCKS: u64 = 12;
+
+// blocks with spends in them, not just coinbases
+fn chain() -> Blockchain {
+    let mut builder = ChainBuilder::new(669);
+    builder.mine_blocks(2).unwrap();
+    for i in 0..BLOCKS - 2 {
+        let spend = builder.spend(0, 1 + i as usize % 3, 1000, 10).unwrap();
+        builder
+            .mine_block(|template| template.transactions = vec![spend])
+            .unwrap();
+    }
+    builder.into_chain()
+}
+
+fn hashes(chain: &Blockchain) -> Vec<Hash> {
+    chain.blocks().map(|block| block.hash()).collect()
+}
+
+fn assert_same(loaded: &Blockchain, chain: &Blockchain) {
+    assert_eq!(hashes(loaded), hashes(chain));
+    assert_eq!(loaded.utxo_commitment(), chain.utxo_commitment());
+    assert_eq!(loaded.utxo_count(), chain.utxo_count());
+}
+
+#[test]
+fn compressed_chain_round_trips() {
+    let dir = TempDir::new().unwrap();
+    let chain = chain();
+    assert_eq!(chain.block_height(), BLOCKS);
+    let compressed = dir.path().join("compressed.cbor");
+    let plain = dir.path().join("plain.cbor");
+    chain.save_compressed_to_file(&compressed).unwrap();
+    chain.save_to_file(&plain).unwrap();
+    assert!(is_compressed(&compressed).unwrap());
+    assert!(!is_compressed(&plain).unwrap());
+    let size = |path| fs::metadata(path).unwrap().len();
+    assert!(size(&compressed) < size(&plain));
+
+    let loaded = Blockchain::load_from_file(&compressed).unwrap();
+    assert_same(&loaded, &chain);
+}
+
+#[test]
+fn uncompressed_files_still_load() {
+    let dir = TempDir::new().unwrap();
+    let chain = chain();
+    // saved with the header but no compression, and as plain cbor
+    // from before the header
+    let plain = dir.path().join("plain.cbor");
+    chain.save_to_file(&plain).unwrap();
+    let legacy = dir.path().join("legacy.cbor");
+    let mut data = vec![];
+    ciborium::into_writer(&chain, &mut data).unwrap();
+    fs::write(&legacy, data).unwrap();
+    for path in [plain, legacy] {
+        assert!(!is_compressed(&path).unwrap());
+        assert_same(&Blockchain::load_from_file(&path).unwrap(), &chain);
+    }
+}
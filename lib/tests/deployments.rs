@@ -0,0 +1,170 @@
+// version-bits deployments over windows of WINDOW blocks: one every
+// block signals for locks in after its first window and is active
+// after the next; one with a window a block short of the threshold
+// locks in a window later, on the window with exactly the threshold;
+// one no block signals for fails at its timeout. the states change
+// on the first block of a window and nowhere else, block_version
+// stops signaling once a deployment is settled, and a saved chain
+// keeps the versions it was tallied from
+use lib::params::{Deployment, NetworkParams};
+use lib::test_utils::{ChainBuilder, instant_params};
+use lib::types::{Blockchain, DeploymentState};
+use lib::utils::Saveable;
+use tempfile::TempDir;
+
+const WINDOW: u64 = 10;
+const START: u64 = 10;
+const THRESHOLD: u64 = 8;
+const BLOCKS: u64 = 55;
+
+// bits of the three deployments
+const EVERY: u8 = 0;
+const SHORT: u8 = 1;
+const NONE: u8 = 2;
+
+fn deployment(name: &str, bit: u8, timeout_height: u64) -> Deployment {
+    Deployment {
+        name: name.to_string(),
+        bit,
+        start_height: START,
+        timeout_height,
+        threshold: THRESHOLD,
+    }
+}
+
+fn params() -> NetworkParams {
+    NetworkParams {
+        difficulty_update_interval: WINDOW,
+        deployments: vec![
+            deployment("every", EVERY, 50),
+            deployment("short", SHORT, 50),
+            deployment("none", NONE, 30),
+        ],
+        ..instant_params()
+    }
+}
+
+// the version of the block at height: every block signals for EVERY
+// when the chain asks it to; SHORT is signaled by one block less than
+// the threshold in the first started window and by exactly the
+// threshold in the next; NONE by no block
+fn version(height: u64, suggested: u32) -> u32 {
+    let mut version = suggested & !(1 << NONE);
+    let offset = height % WINDOW;
+    let short = match height / WINDOW {
+        1 => offset < THRESHOLD - 1,
+        2 => offset < THRESHOLD,
+        _ => true,
+    };
+    if !short {
+        version &= !(1 << SHORT);
+    }
+    version
+}
+
+fn mined_chain() -> ChainBuilder {
+    let mut builder = ChainBuilder::with_params(716, params());
+    for height in 0..BLOCKS {
+        builder
+            .mine_block(|template| template.version = version(height, template.version))
+            .unwrap();
+    }
+    builder
+}
+
+// the state of a deployment for each height up to the next block
+fn states(chain: &Blockchain, name: &str) -> Vec<DeploymentState> {
+    (0..=chain.block_height())
+        .map(|height| chain.deployment_state_at(name, height))
+        .collect()
+}
+
+// the expected states: defined up to START, then each state from the
+// height given for it
+fn expected(changes: &[(u64, DeploymentState)]) -> Vec<DeploymentState> {
+    (0..=BLOCKS)
+        .map(|height| {
+            changes
+                .iter()
+                .rev()
+                .find(|(from, _)| height >= *from)
+                .map_or(DeploymentState::Defined, |(_, state)| *state)
+        })
+        .collect()
+}
+
+#[test]
+fn window_reaching_the_threshold_locks_in() {
+    let builder = mined_chain();
+    let chain = builder.chain();
+    assert_eq!(
+        states(chain, "every"),
+        expected(&[
+            (10, DeploymentState::Started),
+            (20, DeploymentState::LockedIn),
+            (30, DeploymentState::Active),
+        ])
+    );
+    // a window one block short keeps it started, one with exactly the
+    // threshold locks it in
+    assert_eq!(
+        states(chain, "short"),
+        expected(&[
+            (10, DeploymentState::Started),
+            (30, DeploymentState::LockedIn),
+            (40, DeploymentState::Active),
+        ])
+    );
+    assert_eq!(chain.deployment_state("every"), DeploymentState::Active);
+    assert_eq!(chain.deployment_state("short"), DeploymentState::Active);
+}
+
+#[test]
+fn window_short_of_the_threshold_times_out() {
+    let builder = mined_chain();
+    let chain = builder.chain();
+    assert_eq!(
+        states(chain, "none"),
+        expected(&[
+            (10, DeploymentState::Started),
+            (30, DeploymentState::Failed)
+        ])
+    );
+    assert_eq!(chain.deployment_state("none"), DeploymentState::Failed);
+    // a name the params don't have is never active
+    assert_eq!(chain.deployment_state("unknown"), DeploymentState::Defined);
+}
+
+#[test]
+fn blocks_signal_while_started_or_locked_in() {
+    let mut builder = ChainBuilder::with_params(716, params());
+    for height in 0..BLOCKS {
+        let expected = match height {
+            0..10 => 0,
+            10..30 => 1 << EVERY | 1 << SHORT | 1 << NONE,
+            30..40 => 1 << SHORT,
+            _ => 0,
+        };
+        assert_eq!(builder.chain().block_version(), expected, "height {height}");
+        let block = builder
+            .mine_block(|template| template.version = version(height, template.version))
+            .unwrap();
+        assert_eq!(block.header.version, version(height, expected));
+    }
+}
+
+#[test]
+fn saved_chain_keeps_its_states() {
+    let builder = mined_chain();
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("chain.cbor");
+    builder.chain().save_to_file(&path).unwrap();
+    let loaded = Blockchain::load_from_file(&path).unwrap();
+    for name in ["every", "short", "none"] {
+        assert_eq!(
+            states(&loaded, name),
+            states(builder.chain(), name),
+            "{name}"
+        );
+    }
+}
@@ -0,0 +1,63 @@
+// the hashes of values using every field of the hashed types, which
+// only change if the canonical encoding does, forking the chain
+use lib::U256;
+use lib::crypto::{PrivateKey, Signature};
+use lib::sha256::Hash;
+use lib::types::{Block, Transaction, TransactionInput, TransactionOutput};
+use uuid::Uuid;
+
+const TRANSACTION_HASH: &str = "e7e72647c64d181b0db2fc98052230131f74da46a043daef8d1c9653aaaf0f88";
+const HEADER_HASH: &str = "0e542521e7c67234538c11a49dd127e3d924d53c2f0a288217a01aaf357e6821";
+const OUTPUT_HASH: &str = "f3a7df01ea188cb177af9f6337a47c2fcd0cb8ccbfd196c202833447c1bc5ca0";
+
+fn key(byte: u8) -> PrivateKey {
+    PrivateKey::from_hex(&hex::encode([byte; 32])).unwrap()
+}
+
+// every optional field set and a timestamp with nanoseconds
+fn transaction() -> Transaction {
+    let outpoint = Hash::hash_bytes(b"outpoint");
+    let mut transaction = Transaction::new(
+        vec![TransactionInput {
+            prev_transaction_output_hash: outpoint,
+            signature: Signature::placeholder(),
+        }],
+        vec![TransactionOutput {
+            value: 4_999_999_000,
+            unique_id: Uuid::from_u128(0x0123_4567_89ab_cdef_0123_4567_89ab_cdef),
+            pubkey: key(2).public_key(),
+            spendable_after_height: Some(300),
+        }],
+    );
+    transaction.expires_at_height = Some(1000);
+    transaction.inputs[0].signature =
+        Signature::sign_input(&transaction.sighash(&outpoint), &key(1));
+    transaction
+}
+
+fn block() -> Block {
+    Block::builder()
+        .version(0x2000_0001)
+        .timestamp("2024-02-29T12:34:56.123456789Z".parse().unwrap())
+        .nonce(u64::MAX - 7)
+        .prev_hash(Hash::hash_bytes(b"previous"))
+        .target(U256::from(0xFFFF_FFFFu64) << 200)
+        .utxo_commitment(Hash::hash_bytes(b"utxos"))
+        .transactions(vec![transaction()])
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn transaction_digests_are_pinned() {
+    let transaction = transaction();
+    assert_eq!(transaction.hash().to_hex(), TRANSACTION_HASH);
+    assert_eq!(transaction.outputs[0].hash().to_hex(), OUTPUT_HASH);
+}
+
+#[test]
+fn block_digests_are_pinned() {
+    let block = block();
+    assert_eq!(block.header.hash().to_hex(), HEADER_HASH);
+    assert_eq!(block.hash(), block.header.hash());
+}
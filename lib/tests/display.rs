@@ -0,0 +1,69 @@
+// the one-line Display summaries of the compat block, its header and
+// its transactions, pinned so log and cli output can't drift, and
+// format_value at its edges
+use lib::types::{Block, BlockHeader, Transaction, TransactionOutput};
+use lib::utils::{Saveable, format_value};
+
+const BLOCK: &str = "block 44d09bba98495a920e42f4e549d8b45775fa18b9f87a2c36cf77c342a32be9e9, \
+2 txs, nonce 0, \
+target 7fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff, \
+2024-01-01T00:00:20Z";
+const HEADER: &str = "header 44d09bba98495a920e42f4e549d8b45775fa18b9f87a2c36cf77c342a32be9e9, \
+previous 2a4e79403fd3145fac98c55c7e8518c59febf1003d89457fd455ddc41a59788a, \
+nonce 0, \
+target 7fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff, \
+2024-01-01T00:00:20Z";
+const COINBASE: &str = "tx 8c24a33c522913e7da0ca0f465f952d81148e2400812621f5caba43f024a5d41: \
+coinbase -> 1 out, 50.00001000 total";
+const SPEND: &str = "tx f769e2adeca7c699a1be86e803dff4f261e3692344a908665d153128fe5cb303: \
+1 in -> 2 out, 49.99999000 total";
+
+fn load<T: Saveable>(name: &str) -> T {
+    T::load_from_file(format!("{}/compat/{name}", env!("CARGO_MANIFEST_DIR"))).unwrap()
+}
+
+#[test]
+fn compat_summaries_are_pinned() {
+    let block: Block = load("block.cbor");
+    assert_eq!(block.to_string(), BLOCK);
+    assert_eq!(block.header.to_string(), HEADER);
+    assert_eq!(load::<BlockHeader>("header.cbor").to_string(), HEADER);
+    assert_eq!(block.transactions[0].to_string(), COINBASE);
+    assert_eq!(block.transactions[1].to_string(), SPEND);
+    assert_eq!(load::<Transaction>("transaction.cbor").to_string(), SPEND);
+    // one line each
+    for line in [BLOCK, HEADER, COINBASE, SPEND] {
+        assert!(!line.contains('\n'));
+    }
+}
+
+#[test]
+fn total_saturates() {
+    let mut transaction: Transaction = load("transaction.cbor");
+    let output = TransactionOutput {
+        value: u64::MAX,
+        ..transaction.outputs[0].clone()
+    };
+    transaction.outputs.push(output);
+    transaction.thaw();
+    let summary = transaction.to_string();
+    assert!(
+        summary.ends_with(": 1 in -> 3 out, 184467440737.09551615 total"),
+        "{summary}"
+    );
+}
+
+#[test]
+fn values_have_eight_decimals() {
+    for (units, coins) in [
+        (0, "0.00000000"),
+        (1, "0.00000001"),
+        (1000, "0.00001000"),
+        (100_000_000, "1.00000000"),
+        (120_000_000, "1.20000000"),
+        (5_000_000_000, "50.00000000"),
+        (u64::MAX, "184467440737.09551615"),
+    ] {
+        assert_eq!(format_value(units), coins);
+    }
+}
@@ -0,0 +1,200 @@
+// equality of the hashed types against their hashes: values drawn
+// from so few choices per field that many come out equal, compared
+// pairwise, equal exactly when their hashes are and always when
+// their std hashes are, and equal to themselves decoded again
+use chrono::{DateTime, Utc};
+use lib::U256;
+use lib::canonical::Canonical;
+use lib::crypto::{PrivateKey, Signature};
+use lib::sha256::Hash;
+use lib::types::{Block, BlockHeader, Transaction, TransactionInput, TransactionOutput};
+use lib::utils::{MerkleRoot, Saveable};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash as StdHash, Hasher};
+use uuid::Uuid;
+
+const VALUES: usize = 80;
+
+// xorshift64*, so every run draws the same values
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+
+    fn flip(&mut self) -> bool {
+        self.below(2) == 0
+    }
+}
+
+fn key(rng: &mut Rng) -> PrivateKey {
+    PrivateKey::from_hex(&hex::encode([1 + rng.below(2) as u8; 32])).unwrap()
+}
+
+fn output(rng: &mut Rng) -> TransactionOutput {
+    TransactionOutput {
+        value: rng.below(2) as u64,
+        unique_id: Uuid::from_u128(rng.below(2) as u128),
+        pubkey: key(rng).public_key(),
+        spendable_after_height: rng.flip().then_some(300),
+    }
+}
+
+fn transaction(rng: &mut Rng) -> Transaction {
+    let inputs = (0..rng.below(2))
+        .map(|_| {
+            let outpoint = Hash::hash_bytes(&[rng.below(2) as u8]);
+            let signature = match rng.flip() {
+                true => Signature::placeholder(),
+                false => Signature::sign_input(&outpoint, &key(rng)),
+            };
+            TransactionInput {
+                prev_transaction_output_hash: outpoint,
+                signature,
+            }
+        })
+        .collect();
+    let outputs = (0..1 + rng.below(2)).map(|_| output(rng)).collect();
+    let mut transaction = Transaction::new(inputs, outputs);
+    transaction.expires_at_height = rng.flip().then_some(1000);
+    transaction
+}
+
+fn timestamp(rng: &mut Rng) -> DateTime<Utc> {
+    match rng.flip() {
+        true => "2024-01-01T00:00:00Z".parse().unwrap(),
+        false => "2024-01-01T00:00:00.000000001Z".parse().unwrap(),
+    }
+}
+
+// a few transactions to make up blocks of, so blocks come out equal
+fn pool() -> Vec<Transaction> {
+    let mut rng = Rng(687);
+    (0..3).map(|_| transaction(&mut rng)).collect()
+}
+
+fn header(rng: &mut Rng) -> BlockHeader {
+    let transactions = &pool()[..rng.below(2)];
+    BlockHeader::builder(MerkleRoot::calculate(transactions))
+        .version(rng.below(2) as u32)
+        .timestamp(timestamp(rng))
+        .nonce(rng.below(2) as u64)
+        .prev_hash(Hash::hash_bytes(&[rng.below(2) as u8]))
+        .target(U256::MAX >> rng.below(2))
+        .utxo_commitment(match rng.flip() {
+            true => Hash::zero(),
+            false => Hash::hash_bytes(b"utxos"),
+        })
+        .build()
+}
+
+fn block(rng: &mut Rng) -> Block {
+    Block::builder()
+        .timestamp(timestamp(rng))
+        .nonce(rng.below(2) as u64)
+        .transactions(
+            (0..1 + rng.below(2))
+                .map(|_| pool()[rng.below(3)].clone())
+                .collect(),
+        )
+        .build()
+        .unwrap()
+}
+
+fn std_hash<T: StdHash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+// every pair, equal when and only when the hashes are; returns how
+// many pairs came out equal, so a draw too spread out to test
+// anything fails
+fn check<T: PartialEq + std::fmt::Debug>(values: &[T], hash: impl Fn(&T) -> Hash) -> usize {
+    let mut equal = 0;
+    for (i, a) in values.iter().enumerate() {
+        for b in &values[i + 1..] {
+            assert_eq!(a == b, hash(a) == hash(b), "{a:?}\n{b:?}");
+            equal += usize::from(a == b);
+        }
+    }
+    equal
+}
+
+fn draw<T>(seed: u64, make: impl Fn(&mut Rng) -> T) -> Vec<T> {
+    let mut rng = Rng(seed);
+    (0..VALUES).map(|_| make(&mut rng)).collect()
+}
+
+fn reload<T: Saveable>(value: &T) -> T {
+    let mut saved = vec![];
+    value.save(&mut saved).unwrap();
+    T::load(&saved[..]).unwrap()
+}
+
+fn hash_of<T: Canonical>(value: &T) -> Hash {
+    Hash::hash(value)
+}
+
+#[test]
+fn outputs() {
+    let outputs = draw(6871, output);
+    assert!(check(&outputs, TransactionOutput::hash) > 0);
+    for (i, a) in outputs.iter().enumerate() {
+        for b in &outputs[i + 1..] {
+            if a == b {
+                assert_eq!(std_hash(a), std_hash(b));
+            }
+        }
+    }
+}
+
+#[test]
+fn transactions() {
+    let transactions = draw(6872, transaction);
+    assert!(check(&transactions, Transaction::hash) > 0);
+    for (i, a) in transactions.iter().enumerate() {
+        assert_eq!(reload(a), *a);
+        assert_eq!(Transaction::from_hex(&a.to_hex()).unwrap(), *a);
+        for b in &transactions[i + 1..] {
+            if a == b {
+                assert_eq!(std_hash(a), std_hash(b));
+            }
+        }
+        // a frozen copy is equal and hashes the same
+        let frozen = a.clone();
+        frozen.freeze();
+        assert_eq!(frozen, *a);
+        assert_eq!(frozen.hash(), a.hash());
+        assert_eq!(std_hash(&frozen), std_hash(a));
+    }
+    // and the inputs on their own
+    let inputs: Vec<TransactionInput> = transactions
+        .iter()
+        .flat_map(|transaction| transaction.inputs.clone())
+        .collect();
+    check(&inputs, hash_of);
+}
+
+#[test]
+fn headers_and_blocks() {
+    let headers = draw(6873, header);
+    assert!(check(&headers, BlockHeader::hash) > 0);
+    for header in &headers {
+        assert_eq!(reload(header), *header);
+    }
+    let blocks = draw(6874, block);
+    assert!(check(&blocks, Block::hash) > 0);
+    for block in &blocks {
+        assert_eq!(reload(block), *block);
+        assert_eq!(Block::from_hex(&block.to_hex()).unwrap(), *block);
+    }
+}
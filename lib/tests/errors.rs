@@ -0,0 +1,132 @@
+// SbdError as a std error: the messages it prints, the io and cbor
+// causes kept as its source, and its round trip through io::Error
+use lib::error::{Report, SbdError};
+use lib::params::Network;
+use lib::sha256::Hash;
+use lib::types::Transaction;
+use lib::utils::Saveable;
+use std::error::Error;
+use std::io::{Error as IoError, ErrorKind};
+
+#[test]
+fn display_strings() {
+    let hash = Hash::hash(&683u64);
+    let cases = [
+        (
+            SbdError::InvalidTransaction,
+            "Invalid transaction".to_string(),
+        ),
+        (
+            SbdError::InvalidMerkleRoot,
+            "Invalid Merkle root".to_string(),
+        ),
+        (
+            SbdError::MissingInput(hash),
+            format!("Input {hash} spends an unknown or already spent output"),
+        ),
+        (
+            SbdError::LockedOutput {
+                hash,
+                unlock_height: 12,
+            },
+            format!("Input {hash} spends an output locked until height 12"),
+        ),
+        (
+            SbdError::OutputsExceedInputs {
+                inputs: 10,
+                outputs: 11,
+            },
+            "Outputs of 11 exceed inputs of 10".to_string(),
+        ),
+        (
+            SbdError::UnsignedInputs(vec![0, 2]),
+            "Inputs [0, 2] are not signed yet".to_string(),
+        ),
+        (
+            SbdError::WrongNetwork {
+                expected: Network::Mainnet,
+                found: Network::Regtest,
+            },
+            "Wrong network: expected mainnet, found regtest".to_string(),
+        ),
+        (
+            SbdError::InvalidEncoding {
+                kind: "transaction",
+                reason: "2 trailing bytes".to_string(),
+            },
+            "Failed to decode transaction: 2 trailing bytes".to_string(),
+        ),
+        (
+            SbdError::Storage(IoError::new(ErrorKind::NotFound, "no blocks.dat")),
+            "Block storage failed: no blocks.dat".to_string(),
+        ),
+    ];
+    for (e, expected) in cases {
+        assert_eq!(e.to_string(), expected);
+    }
+}
+
+#[test]
+fn io_source_is_reachable() {
+    let e = SbdError::from(IoError::new(ErrorKind::PermissionDenied, "read only"));
+    let source = e.source().unwrap();
+    let io = source.downcast_ref::<IoError>().unwrap();
+    assert_eq!(io.kind(), ErrorKind::PermissionDenied);
+    assert_eq!(io.to_string(), "read only");
+    // and boxes like any other error
+    let boxed: Box<dyn Error> = Box::new(e);
+    assert!(boxed.source().unwrap().is::<IoError>());
+    // causes without one of their own have none
+    assert!(SbdError::InvalidBlock.source().is_none());
+}
+
+#[test]
+fn cbor_source_is_reachable() {
+    // a transaction cut short
+    let path = format!("{}/compat/transaction.hex", env!("CARGO_MANIFEST_DIR"));
+    let hex = std::fs::read_to_string(path).unwrap().trim().to_string();
+    let e = Transaction::from_hex(&hex[..hex.len() - 2]).unwrap_err();
+    assert_eq!(e.to_string(), "Failed to decode transaction: it ends early");
+    let source = e.source().unwrap();
+    let cbor = source
+        .downcast_ref::<ciborium::de::Error<IoError>>()
+        .unwrap();
+    assert!(matches!(cbor, ciborium::de::Error::Io(io) if io.kind() == ErrorKind::UnexpectedEof));
+}
+
+#[test]
+fn io_error_keeps_it_as_the_cause() {
+    // ours goes in as InvalidData and comes back out intact
+    let io = IoError::from(SbdError::InvalidHash);
+    assert_eq!(io.kind(), ErrorKind::InvalidData);
+    let inner = io.into_inner().unwrap().downcast::<SbdError>().unwrap();
+    assert!(matches!(*inner, SbdError::InvalidHash));
+    // an io error in ours comes back out as itself
+    let io = IoError::from(SbdError::Storage(IoError::new(ErrorKind::NotFound, "gone")));
+    assert_eq!(io.kind(), ErrorKind::NotFound);
+    assert_eq!(io.to_string(), "gone");
+    // a file that isn't cbor has the decode error as its cause, named
+    // by the type tag
+    let e = Transaction::load(&[0xff, 0xff][..]).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    let inner = e.get_ref().unwrap().downcast_ref::<SbdError>().unwrap();
+    assert!(
+        matches!(
+            inner,
+            SbdError::Decode {
+                kind: "Transaction",
+                ..
+            }
+        ),
+        "{inner}"
+    );
+    assert!(inner.source().is_some());
+}
+
+#[test]
+fn report_prints_the_message() {
+    let report = Report::from(SbdError::InvalidSignature);
+    assert_eq!(format!("{report:?}"), "Invalid signature");
+    let report = Report::new("no wallet at wallet.cbor");
+    assert_eq!(format!("{report:?}"), "no wallet at wallet.cbor");
+}
@@ -0,0 +1,119 @@
+// a spend expiring at EXPIRY: the mempool takes it and a block may
+// include it up to EXPIRY - 1, and from EXPIRY on both refuse it; a
+// pending one is evicted when the tip reaches EXPIRY, the output it
+// spent free again, and the entries that don't expire are left
+use lib::error::SbdError;
+use lib::sha256::Hash;
+use lib::test_utils::{ChainBuilder, instant_params};
+use lib::types::{PartiallySignedTransaction, Transaction};
+
+const EXPIRY: u64 = 4;
+const FEE: u64 = 100;
+
+// a chain with keys 1 and 2 funded, the next block below EXPIRY - 1
+fn funded() -> ChainBuilder {
+    let mut builder = ChainBuilder::with_params(719, instant_params());
+    builder.fund(1, &[10_000]).unwrap();
+    builder.fund(2, &[10_000]).unwrap();
+    assert!(builder.chain().chain_height() < EXPIRY - 1);
+    builder
+}
+
+// a spend of the key's output to key 3, expiring at expiry
+fn spend(builder: &mut ChainBuilder, key: usize, expiry: Option<u64>) -> Transaction {
+    let (hash, output) = builder.spendable(key).remove(0);
+    let payment = builder.output(output.value - FEE, 3);
+    let mut unsigned = PartiallySignedTransaction::new(vec![(hash, output)], vec![payment]);
+    unsigned.expires_at_height = expiry;
+    unsigned.sign(builder.key(key)).unwrap();
+    unsigned.finalize().unwrap()
+}
+
+fn until_next_block_is(builder: &mut ChainBuilder, height: u64) {
+    let blocks = height - builder.chain().chain_height();
+    builder.mine_blocks(blocks).unwrap();
+}
+
+fn is_expired(result: lib::error::Result<()>, transaction: &Transaction) -> bool {
+    matches!(
+        result,
+        Err(SbdError::ExpiredTransaction { txid, expires_at_height: EXPIRY })
+            if txid == transaction.hash()
+    )
+}
+
+#[test]
+fn included_up_to_the_block_before_expiry() {
+    let mut builder = funded();
+    let expiring = spend(&mut builder, 1, Some(EXPIRY));
+    until_next_block_is(&mut builder, EXPIRY - 1);
+
+    // the last block that may include it
+    let mut chain = builder.chain().clone();
+    chain.add_to_mempool(expiring.clone()).unwrap();
+    let last = builder
+        .block(|template| template.transactions = vec![expiring.clone()])
+        .unwrap();
+    chain.add_block(last).unwrap();
+    assert_eq!(chain.confirmations(&expiring.hash()), Some(1));
+
+    // a block later, neither the mempool nor a block takes it
+    builder.mine_blocks(1).unwrap();
+    assert_eq!(builder.chain().chain_height(), EXPIRY);
+    let mut chain = builder.chain().clone();
+    assert!(is_expired(
+        chain.add_to_mempool(expiring.clone()),
+        &expiring
+    ));
+    let late = builder
+        .block(|template| template.transactions = vec![expiring.clone()])
+        .unwrap();
+    assert!(is_expired(chain.add_block(late), &expiring));
+    assert_eq!(chain.block_height(), builder.chain().block_height());
+}
+
+#[test]
+fn evicted_when_the_tip_reaches_expiry() {
+    let mut builder = funded();
+    let expiring = spend(&mut builder, 1, Some(EXPIRY));
+    let lasting = spend(&mut builder, 2, None);
+    let spent = expiring.inputs[0].prev_transaction_output_hash;
+    until_next_block_is(&mut builder, EXPIRY - 1);
+    let chain = builder.chain_mut();
+    chain.add_to_mempool(expiring.clone()).unwrap();
+    chain.add_to_mempool(lasting.clone()).unwrap();
+    assert_eq!(chain.is_utxo_reserved(&spent), Some(true));
+
+    // a block without it, the last that could have had it
+    builder.mine_blocks(1).unwrap();
+    let chain = builder.chain();
+    assert!(chain.mempool_get(&expiring.hash()).is_none());
+    assert!(chain.mempool_parent(&expiring.outputs[0].hash()).is_none());
+    assert_eq!(chain.is_utxo_reserved(&spent), Some(false));
+    let left: Vec<Hash> = chain.mempool().iter().map(|(_, tx)| tx.hash()).collect();
+    assert_eq!(left, vec![lasting.hash()]);
+
+    // the output it spent can be spent again
+    let again = spend(&mut builder, 1, None);
+    assert_eq!(again.inputs[0].prev_transaction_output_hash, spent);
+    builder.chain_mut().add_to_mempool(again.clone()).unwrap();
+    builder
+        .mine_block(|template| template.transactions = vec![again.clone(), lasting.clone()])
+        .unwrap();
+    assert_eq!(builder.chain().confirmations(&again.hash()), Some(1));
+}
+
+#[test]
+fn a_later_expiry_outlives_the_tip() {
+    let mut builder = funded();
+    let expiring = spend(&mut builder, 1, Some(EXPIRY + 1));
+    until_next_block_is(&mut builder, EXPIRY - 1);
+    builder
+        .chain_mut()
+        .add_to_mempool(expiring.clone())
+        .unwrap();
+    builder.mine_blocks(1).unwrap();
+    assert!(builder.chain().mempool_get(&expiring.hash()).is_some());
+    builder.mine_blocks(1).unwrap();
+    assert!(builder.chain().mempool_get(&expiring.hash()).is_none());
+}
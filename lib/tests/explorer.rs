@@ -0,0 +1,494 @@
+// explorer over a regtest chain from chain_init, with a spend tx_gen
+// wrote mined on top and another left in the mempool.cbor beside the
+// chain: each subcommand as text and as json, the json keys and values
+// against the chain, pages and their totals, and unknown or malformed
+// ids failing
+use assert_cmd::Command;
+use chrono::Duration;
+use lib::COINBASE_MATURITY;
+use lib::crypto::PrivateKey;
+use lib::sha256::Hash;
+use lib::types::{Block, Blockchain, Transaction, TransactionOutput};
+use lib::utils::{Saveable, format_value};
+use predicates::prelude::*;
+use serde_json::Value;
+use std::path::PathBuf;
+use tempfile::TempDir;
+use uuid::Uuid;
+
+const SEED: &str = "709";
+const AMOUNT: u64 = 1_000_000;
+const FEE: u64 = 1000;
+
+struct Fixture {
+    dir: TempDir,
+    chain: Blockchain,
+    // the key chain_init paid, and the one the spends pay
+    miner: String,
+    payee: String,
+    // the spend mined at the tip, and the one in the mempool
+    mined: Transaction,
+    pending: Transaction,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        let chain_file = dir.path().join("chain.cbor");
+        Command::cargo_bin("chain_init")
+            .unwrap()
+            .arg("--out")
+            .arg(&chain_file)
+            .args(["--blocks", &COINBASE_MATURITY.to_string(), "--seed", SEED])
+            .args(["--network", "regtest"])
+            .arg("--new-key")
+            .arg(dir.path().join("key"))
+            .assert()
+            .success();
+        let mut chain = Blockchain::load_from_file(&chain_file).unwrap();
+        let miner = chain.blocks().next().unwrap().transactions[0].outputs[0]
+            .pubkey
+            .to_address();
+        let payee = PrivateKey::new_key().public_key().to_address();
+
+        let mined = tx_gen(&dir, &payee);
+        chain.add_to_mempool(mined.clone()).unwrap();
+        let block = next_block(&chain, vec![mined.clone()], FEE);
+        chain.add_block(block).unwrap();
+        chain.save_to_file(&chain_file).unwrap();
+
+        let pending = tx_gen(&dir, &payee);
+        chain.add_to_mempool(pending.clone()).unwrap();
+        chain
+            .mempool_snapshot()
+            .save_to_file(dir.path().join("mempool.cbor"))
+            .unwrap();
+        Fixture {
+            dir,
+            chain,
+            miner,
+            payee,
+            mined,
+            pending,
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    fn explorer(&self, args: &[&str]) -> assert_cmd::assert::Assert {
+        Command::cargo_bin("explorer")
+            .unwrap()
+            .arg(self.path("chain.cbor"))
+            .args(args)
+            .assert()
+    }
+
+    fn text(&self, args: &[&str]) -> String {
+        let output = self.explorer(args).success().get_output().stdout.clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn json(&self, args: &[&str]) -> Value {
+        serde_json::from_str(&self.text(&[args, &["--json"]].concat())).unwrap()
+    }
+
+    fn tip_height(&self) -> u64 {
+        self.chain.block_height() - 1
+    }
+}
+
+// a spend of AMOUNT to the address from the key chain_init wrote
+fn tx_gen(dir: &TempDir, to: &str) -> Transaction {
+    Command::cargo_bin("tx_gen")
+        .unwrap()
+        .arg("spend")
+        .arg("--chain")
+        .arg(dir.path().join("chain.cbor"))
+        .arg("--key")
+        .arg(dir.path().join("key"))
+        .args(["--to", to])
+        .args(["--amount", &AMOUNT.to_string()])
+        .args(["--fee", &FEE.to_string()])
+        .arg("--out")
+        .arg(dir.path().join("tx.cbor"))
+        .assert()
+        .success();
+    Transaction::load_from_file(dir.path().join("tx.cbor")).unwrap()
+}
+
+// the next block, its coinbase paying the reward and fees to the
+// key chain_init paid
+fn next_block(chain: &Blockchain, transactions: Vec<Transaction>, fees: u64) -> Block {
+    let tip = chain.blocks().last().unwrap();
+    let spacing = chain.params().ideal_block_time as i64;
+    let coinbase = Transaction::new(
+        vec![],
+        vec![TransactionOutput {
+            value: chain.params().reward_at_height(chain.chain_height()) + fees,
+            unique_id: Uuid::from_u128(chain.chain_height() as u128),
+            pubkey: tip.transactions[0].outputs[0].pubkey.clone(),
+            spendable_after_height: None,
+        }],
+    );
+    let mut block = Block::builder()
+        .timestamp(tip.header.timestamp + Duration::seconds(spacing))
+        .prev_hash(tip.hash())
+        .target(chain.target())
+        .transactions([vec![coinbase], transactions].concat())
+        .build()
+        .unwrap();
+    while !block.header.mine(1_000_000) {}
+    block
+}
+
+// the object has these keys and no others
+fn assert_keys(value: &Value, keys: &[&str]) {
+    let mut found: Vec<&str> = value
+        .as_object()
+        .unwrap_or_else(|| panic!("not an object: {value}"))
+        .keys()
+        .map(String::as_str)
+        .collect();
+    found.sort();
+    let mut keys = keys.to_vec();
+    keys.sort();
+    assert_eq!(found, keys, "{value}");
+}
+
+fn hex(hash: Hash) -> Value {
+    Value::from(hash.to_hex())
+}
+
+#[test]
+fn tip_and_supply() {
+    let fixture = Fixture::new();
+    let chain = &fixture.chain;
+    let tip = fixture.json(&["tip"]);
+    assert_keys(
+        &tip,
+        &[
+            "network",
+            "height",
+            "hash",
+            "timestamp",
+            "target",
+            "hashrate",
+            "window",
+            "utxos",
+            "mempool",
+        ],
+    );
+    let last = chain.blocks().last().unwrap();
+    assert_eq!(tip["network"], "regtest");
+    assert_eq!(tip["height"], fixture.tip_height());
+    assert_eq!(tip["hash"], hex(last.hash()));
+    assert_eq!(
+        tip["timestamp"],
+        serde_json::to_value(last.header.timestamp).unwrap()
+    );
+    assert_eq!(tip["target"], format!("{:064x}", chain.target()));
+    assert!(tip["hashrate"].as_f64().unwrap() > 0.0);
+    assert_eq!(tip["window"], 120);
+    assert_eq!(tip["utxos"], chain.utxo_count());
+    assert_eq!(tip["mempool"], 1);
+    let text = fixture.text(&["tip", "--window", "10"]);
+    assert!(text.contains("network       regtest"), "{text}");
+    assert!(text.contains(&format!("hash          {}", last.hash().to_hex())));
+    assert!(text.contains("over the last 10 blocks"));
+    assert!(text.contains("mempool       1"));
+
+    // every coin the schedule allowed is held, the fees having gone
+    // back to the miner
+    let supply = fixture.json(&["supply"]);
+    assert_keys(&supply, &["height", "supply", "scheduled", "max", "utxos"]);
+    let scheduled = chain
+        .params()
+        .cumulative_supply_at_height(fixture.tip_height());
+    assert_eq!(supply["height"], fixture.tip_height());
+    assert_eq!(supply["supply"], chain.circulating_supply());
+    assert_eq!(supply["scheduled"], scheduled);
+    assert_eq!(chain.circulating_supply(), scheduled);
+    assert_eq!(supply["max"], chain.params().max_supply());
+    assert_eq!(supply["utxos"], chain.utxo_count());
+    let text = fixture.text(&["supply"]);
+    assert!(text.contains(&format!("supply        {}", format_value(scheduled))));
+}
+
+#[test]
+fn block_by_height_and_hash() {
+    let fixture = Fixture::new();
+    let height = fixture.tip_height();
+    let last = fixture.chain.blocks().last().unwrap();
+    let by_height = fixture.json(&["block", &height.to_string()]);
+    assert_keys(
+        &by_height,
+        &[
+            "height",
+            "hash",
+            "prev_hash",
+            "merkle_root",
+            "timestamp",
+            "nonce",
+            "target",
+            "confirmations",
+            "transactions",
+        ],
+    );
+    assert_eq!(by_height, fixture.json(&["block", &last.hash().to_hex()]));
+    assert_eq!(by_height["height"], height);
+    assert_eq!(by_height["hash"], hex(last.hash()));
+    assert_eq!(by_height["prev_hash"], hex(last.header.prev_block_hash));
+    assert_eq!(by_height["nonce"], last.header.nonce);
+    assert_eq!(by_height["confirmations"], 1);
+    let transactions = &by_height["transactions"];
+    assert_keys(transactions, &["total", "offset", "items"]);
+    assert_eq!(transactions["total"], 2);
+    assert_eq!(
+        transactions["items"],
+        Value::from(vec![
+            hex(last.transactions[0].hash()),
+            hex(fixture.mined.hash())
+        ])
+    );
+    // the genesis block is as deep as the chain
+    let genesis = fixture.json(&["block", "0"]);
+    assert_eq!(genesis["confirmations"], fixture.chain.block_height());
+
+    // a page of one, past the coinbase
+    let page = fixture.json(&[
+        "block",
+        &height.to_string(),
+        "--limit",
+        "1",
+        "--offset",
+        "1",
+    ]);
+    assert_eq!(page["transactions"]["total"], 2);
+    assert_eq!(page["transactions"]["offset"], 1);
+    assert_eq!(
+        page["transactions"]["items"],
+        Value::from(vec![hex(fixture.mined.hash())])
+    );
+    let text = fixture.text(&[
+        "block",
+        &height.to_string(),
+        "--limit",
+        "1",
+        "--offset",
+        "1",
+    ]);
+    assert!(text.contains("transactions  2, showing 2 to 2"), "{text}");
+    assert!(text.contains(&format!("  {}", fixture.mined.hash().to_hex())));
+    assert!(!text.contains(&last.transactions[0].hash().to_hex()));
+}
+
+#[test]
+fn transactions_mined_and_pending() {
+    let fixture = Fixture::new();
+    let mined = fixture.json(&["tx", &fixture.mined.hash().to_hex()]);
+    assert_keys(
+        &mined,
+        &[
+            "txid",
+            "height",
+            "confirmations",
+            "coinbase",
+            "size",
+            "inputs",
+            "outputs",
+            "fee",
+        ],
+    );
+    assert_eq!(mined["txid"], hex(fixture.mined.hash()));
+    assert_eq!(mined["height"], fixture.tip_height());
+    assert_eq!(mined["confirmations"], 1);
+    assert_eq!(mined["coinbase"], false);
+    assert_eq!(mined["size"], fixture.mined.size());
+    assert_eq!(mined["fee"], FEE);
+    let input = &mined["inputs"][0];
+    assert_keys(input, &["output_hash", "value", "address"]);
+    assert_eq!(input["address"], fixture.miner.as_str());
+    let output = &mined["outputs"][0];
+    assert_keys(output, &["hash", "value", "address", "spent_by"]);
+    assert_eq!(output["value"], AMOUNT);
+    assert_eq!(output["address"], fixture.payee.as_str());
+    assert_eq!(output["spent_by"], Value::Null);
+
+    // the coinbase it spent names it as the spender
+    let spent = fixture.mined.inputs[0].prev_transaction_output_hash;
+    let (height, coinbase) = fixture
+        .chain
+        .blocks()
+        .enumerate()
+        .map(|(height, block)| (height, &block.transactions[0]))
+        .find(|(_, coinbase)| coinbase.outputs[0].hash() == spent)
+        .unwrap();
+    let coinbase = fixture.json(&["tx", &coinbase.hash().to_hex()]);
+    assert_eq!(coinbase["height"], height);
+    assert_eq!(coinbase["coinbase"], true);
+    assert_eq!(coinbase["inputs"], Value::from(Vec::<Value>::new()));
+    assert_eq!(coinbase["fee"], Value::Null);
+    assert_eq!(
+        coinbase["outputs"][0]["spent_by"],
+        hex(fixture.mined.hash())
+    );
+
+    // the pending one is read from the mempool file
+    let pending = fixture.json(&["tx", &fixture.pending.hash().to_hex()]);
+    assert_eq!(pending["height"], Value::Null);
+    assert_eq!(pending["confirmations"], 0);
+    assert_eq!(pending["fee"], FEE);
+    let text = fixture.text(&["tx", &fixture.pending.hash().to_hex()]);
+    assert!(
+        text.contains("height        none, in the mempool"),
+        "{text}"
+    );
+    assert!(text.contains(&format!("fee           {}", format_value(FEE))));
+
+    let mempool = fixture.json(&["mempool"]);
+    assert_keys(&mempool, &["total", "offset", "items"]);
+    assert_eq!(mempool["total"], 1);
+    let entry = &mempool["items"][0];
+    assert_keys(
+        entry,
+        &["txid", "received", "fee", "size", "inputs", "outputs"],
+    );
+    assert_eq!(entry["txid"], hex(fixture.pending.hash()));
+    assert_eq!(entry["fee"], FEE);
+    assert_eq!(entry["inputs"], fixture.pending.inputs.len());
+    assert_eq!(entry["outputs"], fixture.pending.outputs.len());
+    let text = fixture.text(&["mempool"]);
+    assert!(text.contains("mempool       1, showing 1 to 1"), "{text}");
+
+    let fees = fixture.json(&["fees"]);
+    assert_keys(&fees, &["summary", "histogram"]);
+    assert_keys(
+        &fees["summary"],
+        &["next_block", "within_3_blocks", "within_6_blocks"],
+    );
+    let histogram = fees["histogram"].as_array().unwrap();
+    assert_eq!(histogram.len(), 1);
+    assert_keys(
+        &histogram[0],
+        &["min_fee_rate", "max_fee_rate", "count", "size"],
+    );
+    assert_eq!(histogram[0]["count"], 1);
+    assert_eq!(histogram[0]["size"], fixture.pending.size());
+}
+
+#[test]
+fn addresses_and_rich_list() {
+    let fixture = Fixture::new();
+    let payee = fixture.json(&["address", &fixture.payee]);
+    assert_keys(
+        &payee,
+        &[
+            "address",
+            "balance",
+            "received",
+            "spent",
+            "utxos",
+            "transactions",
+            "first_seen",
+            "last_seen",
+        ],
+    );
+    assert_eq!(payee["balance"], AMOUNT);
+    assert_eq!(payee["received"], AMOUNT);
+    assert_eq!(payee["spent"], 0);
+    assert_eq!(payee["utxos"], 1);
+    assert_eq!(payee["transactions"], 1);
+    assert_eq!(payee["first_seen"], fixture.tip_height());
+    assert_eq!(payee["last_seen"], fixture.tip_height());
+
+    let history = fixture.json(&["address", &fixture.payee, "--history"]);
+    assert_eq!(history["total"], 1);
+    let record = &history["items"][0];
+    assert_keys(record, &["txid", "height", "timestamp", "change"]);
+    assert_eq!(record["txid"], hex(fixture.mined.hash()));
+    assert_eq!(record["change"], AMOUNT);
+
+    // the miner's newest is the tip coinbase, then the spend it paid
+    // AMOUNT and the fee from
+    let history = fixture.json(&["address", &fixture.miner, "--history", "--limit", "2"]);
+    assert_eq!(history["total"], fixture.chain.block_height() + 1);
+    let items = history["items"].as_array().unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[1]["txid"], hex(fixture.mined.hash()));
+    assert_eq!(items[1]["change"], -((AMOUNT + FEE) as i64));
+
+    // the miner's utxos, the one the pending spend takes reserved
+    let utxos = fixture.json(&["address", &fixture.miner, "--utxos", "--limit", "1000"]);
+    let items = utxos["items"].as_array().unwrap();
+    assert_eq!(utxos["total"], items.len());
+    assert_keys(&items[0], &["hash", "txid", "value", "reserved"]);
+    let reserved: Vec<&Value> = items
+        .iter()
+        .filter(|utxo| utxo["reserved"] == true)
+        .map(|utxo| &utxo["hash"])
+        .collect();
+    assert_eq!(
+        reserved,
+        vec![&hex(fixture.pending.inputs[0].prev_transaction_output_hash)]
+    );
+    let text = fixture.text(&["address", &fixture.miner, "--utxos", "--limit", "1000"]);
+    assert_eq!(text.matches("  reserved").count(), 1, "{text}");
+
+    // two holders, the miner first, holding the supply between them
+    let rich = fixture.json(&["rich-list"]);
+    assert_eq!(rich["total"], 2);
+    let items = rich["items"].as_array().unwrap();
+    assert_keys(&items[0], &["address", "balance"]);
+    assert_eq!(items[0]["address"], fixture.miner.as_str());
+    assert_eq!(items[1]["address"], fixture.payee.as_str());
+    assert_eq!(items[1]["balance"], AMOUNT);
+    let held: u64 = items
+        .iter()
+        .map(|item| item["balance"].as_u64().unwrap())
+        .sum();
+    assert_eq!(held, fixture.chain.circulating_supply());
+    let text = fixture.text(&["rich-list", "--offset", "1"]);
+    assert!(text.contains("addresses     2, showing 2 to 2"), "{text}");
+    assert!(text.contains(&fixture.payee));
+}
+
+#[test]
+fn unknown_and_malformed_ids_fail() {
+    let fixture = Fixture::new();
+    let past = fixture.chain.block_height().to_string();
+    fixture
+        .explorer(&["block", &past])
+        .code(1)
+        .stderr(predicate::str::contains(format!(
+            "no block at height {past}"
+        )));
+    let unknown = "ab".repeat(32);
+    fixture
+        .explorer(&["block", &unknown])
+        .code(1)
+        .stderr(predicate::str::contains(format!("no block {unknown}")));
+    fixture
+        .explorer(&["block", "tip"])
+        .code(1)
+        .stderr(predicate::str::contains("no block tip"));
+    fixture
+        .explorer(&["tx", &unknown])
+        .code(1)
+        .stderr(predicate::str::contains("no transaction"));
+    // rejected by the argument parser, before the chain is read
+    fixture.explorer(&["tx", "not-a-hash"]).code(2);
+    fixture.explorer(&["address", "beef"]).code(2);
+    fixture.explorer(&["tip", "--window", "1"]).code(2);
+    fixture
+        .explorer(&["address", &fixture.payee, "--utxos", "--history"])
+        .code(2);
+    Command::cargo_bin("explorer")
+        .unwrap()
+        .arg(fixture.path("missing.cbor"))
+        .arg("tip")
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("missing.cbor"));
+}
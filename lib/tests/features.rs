@@ -0,0 +1,135 @@
+// the public api each cargo feature brings, one test per feature
+// compiled only with it, so a feature that stops exposing what it
+// promises fails to build here; core alone is core-tests/
+#![allow(unused_imports)]
+use lib::params::NetworkParams;
+use lib::types::{Block, BlockHeader, Blockchain, Transaction};
+use lib::utils::{MerkleRoot, Saveable};
+use std::path::Path;
+
+fn compat_block() -> Block {
+    Block::from_hex(include_str!("../compat/block.hex")).unwrap()
+}
+
+#[cfg(feature = "clock")]
+#[test]
+fn clock() {
+    // a header without a timestamp is stamped now
+    let before = chrono::Utc::now();
+    let header = BlockHeader::builder(MerkleRoot::calculate(&[])).build();
+    assert!(header.timestamp >= before);
+}
+
+#[cfg(all(feature = "clock", feature = "mempool-policy"))]
+#[test]
+fn clock_and_mempool_policy() {
+    let _: fn(&mut Blockchain, Transaction) -> lib::error::Result<()> = Blockchain::add_to_mempool;
+    let _: fn(&mut Blockchain) = Blockchain::cleanup_mempool;
+}
+
+#[cfg(feature = "std-fs")]
+#[test]
+fn std_fs() {
+    let _ = <Block as Saveable>::save_to_file::<&Path>;
+    let _ = <Block as Saveable>::load_from_file::<&Path>;
+    let _ = lib::types::BlockStore::open::<&Path>;
+    let _ = lib::utils::BackupPolicy::new::<&Path>;
+}
+
+#[cfg(feature = "mining")]
+#[test]
+fn mining() {
+    let mut header = compat_block().header;
+    assert!(header.mine(1));
+    let chain = Blockchain::with_params(NetworkParams::regtest());
+    assert!(chain.calculate_block_reward() > 0);
+}
+
+#[cfg(feature = "mempool-policy")]
+#[test]
+fn mempool_policy() {
+    let chain = Blockchain::with_params(NetworkParams::regtest());
+    assert!(chain.mempool_snapshot().entries.is_empty());
+    assert_eq!(chain.estimate_fee_rate(), None);
+    let _ = Blockchain::add_to_mempool_at;
+    let _ = Blockchain::cleanup_mempool_at;
+    let _ = Blockchain::restore_mempool;
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json() {
+    let _ = <Block as Saveable>::save_json_to_file::<&Path>;
+    let _ = <Block as Saveable>::load_json_from_file::<&Path>;
+    assert_eq!(
+        lib::utils::FileFormat::default(),
+        lib::utils::FileFormat::Cbor
+    );
+}
+
+#[cfg(feature = "network")]
+#[test]
+fn network() {
+    use lib::network::Message;
+    let message = Message::NewBlock(compat_block());
+    let decoded = Message::decode(&message.encode().unwrap()).unwrap();
+    assert!(matches!(decoded, Message::NewBlock(block) if block == compat_block()));
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn r#async() {
+    // made but never run, there's no runtime here
+    let mut stream: &[u8] = &[];
+    drop(lib::network::Message::async_read_from([0; 4], &mut stream));
+}
+
+#[cfg(feature = "logging")]
+#[test]
+fn logging() {
+    let _: fn(u8) = lib::logging::init;
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compression() {
+    let mut data = vec![];
+    compat_block().save_compressed(&mut data).unwrap();
+    assert_eq!(Block::load(&data[..]).unwrap(), compat_block());
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mmap() {
+    let _ = lib::types::BlockStore::open_mmap::<&Path>;
+}
+
+#[cfg(feature = "store-sled")]
+#[test]
+fn store_sled() {
+    let _ = lib::types::SledStore::open::<&Path>;
+}
+
+#[cfg(feature = "fuzz")]
+#[test]
+fn fuzz() {
+    assert!(lib::fuzz::TARGETS.contains(&"block"));
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn test_utils() {
+    let mut builder = lib::test_utils::ChainBuilder::new(691);
+    builder.mine_blocks(1).unwrap();
+    assert_eq!(builder.chain().block_height(), 1);
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn cli() {
+    use lib::cli::{JsonArgs, NetworkArgs};
+    use lib::params::Network;
+    assert!(!JsonArgs::default().json);
+    let params = NetworkArgs::default().params_or(Network::Regtest);
+    assert_eq!(params.network, Network::Regtest);
+}
@@ -0,0 +1,74 @@
+// fee estimates from the mempool
+use lib::params::NetworkParams;
+use lib::sha256::Hash;
+use lib::test_utils::{ChainBuilder, instant_params};
+use lib::types::Transaction;
+
+// a spend of the output paying fee, to outputs many outputs
+fn spending(
+    builder: &mut ChainBuilder,
+    spent: Hash,
+    value: u64,
+    fee: u64,
+    outputs: u64,
+) -> Transaction {
+    let each = (value - fee) / outputs;
+    let mut paid: Vec<_> = (0..outputs).map(|_| builder.output(each, 1)).collect();
+    paid[0].value += value - fee - each * outputs;
+    builder.sign(&[spent], paid).unwrap()
+}
+
+#[test]
+fn summary_ranks_by_fee_rate() {
+    let params = NetworkParams {
+        block_transaction_cap: 1,
+        ..instant_params()
+    };
+    let mut builder = ChainBuilder::with_params(727, params);
+    let funded = builder.fund(0, &[100_000, 100_000, 100_000]).unwrap();
+    let hashes: Vec<Hash> = funded.iter().map(|output| output.hash()).collect();
+    // the highest fee, spread over many bytes
+    let bulky = spending(&mut builder, hashes[0], 100_000, 9_000, 40);
+    // less in fees, but far more per byte
+    let dense = spending(&mut builder, hashes[1], 100_000, 5_000, 1);
+    let cheap = spending(&mut builder, hashes[2], 100_000, 100, 1);
+    let rate = |transaction: &Transaction, fee: u64| fee / transaction.size();
+    let (bulky_rate, dense_rate, cheap_rate) =
+        (rate(&bulky, 9_000), rate(&dense, 5_000), rate(&cheap, 100));
+    assert!(bulky_rate < dense_rate && cheap_rate < bulky_rate);
+    for transaction in [bulky, dense, cheap] {
+        builder.chain_mut().add_to_mempool(transaction).unwrap();
+    }
+    let summary = builder.chain().fee_summary();
+    // one transaction a block: the densest, then the bulky one
+    assert_eq!(summary.next_block, dense_rate);
+    assert_eq!(summary.within_3_blocks, 0);
+    let histogram = builder.chain().fee_histogram(100);
+    assert_eq!(histogram.first().unwrap().min_fee_rate, cheap_rate);
+    assert_eq!(histogram.last().unwrap().max_fee_rate, dense_rate);
+}
+
+#[test]
+fn summary_follows_removals() {
+    let params = NetworkParams {
+        block_transaction_cap: 1,
+        ..instant_params()
+    };
+    let mut builder = ChainBuilder::with_params(728, params);
+    let funded = builder.fund(0, &[100_000, 100_000]).unwrap();
+    let dense = spending(&mut builder, funded[0].hash(), 100_000, 5_000, 1);
+    let cheap = spending(&mut builder, funded[1].hash(), 100_000, 100, 1);
+    let cheap_rate = 100 / cheap.size();
+    builder.chain_mut().add_to_mempool(dense.clone()).unwrap();
+    builder.chain_mut().add_to_mempool(cheap).unwrap();
+    // mined, the dense one leaves the mempool
+    builder
+        .mine_block(|template| template.transactions = vec![dense])
+        .unwrap();
+    let summary = builder.chain().fee_summary();
+    assert_eq!(summary.next_block, 0);
+    assert_eq!(builder.chain().mempool().len(), 1);
+    let histogram = builder.chain().fee_histogram(10);
+    assert_eq!(histogram.len(), 1);
+    assert_eq!(histogram[0].min_fee_rate, cheap_rate);
+}
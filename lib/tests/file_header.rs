@@ -0,0 +1,101 @@
+// the header saved files start with: a file of another type or from a
+// newer version is refused with a message saying so, and files from
+// before the header, or from before its checksum, still load
+use lib::test_utils::ChainBuilder;
+use lib::types::{Block, Transaction};
+use lib::utils::{FILE_FORMAT_VERSION, Saveable};
+use std::io::ErrorKind;
+
+fn transaction() -> Transaction {
+    let mut builder = ChainBuilder::new(667);
+    builder.mine_blocks(2).unwrap();
+    builder.spend(0, 1, 1000, 10).unwrap()
+}
+
+fn cbor(transaction: &Transaction) -> Vec<u8> {
+    let mut data = vec![];
+    ciborium::into_writer(transaction, &mut data).unwrap();
+    data
+}
+
+// a header of the version, as that version wrote it: no schema
+// version before 3, no flags byte nor checksum before 2
+fn file(version: u16, tag: &str, schema: u16, body: &[u8]) -> Vec<u8> {
+    let mut data = b"SBDF".to_vec();
+    data.extend_from_slice(&version.to_be_bytes());
+    data.push(tag.len() as u8);
+    data.extend_from_slice(tag.as_bytes());
+    if version >= 3 {
+        data.extend_from_slice(&schema.to_be_bytes());
+    }
+    if version >= 2 {
+        data.push(0);
+    }
+    data.extend_from_slice(body);
+    if version >= 2 {
+        data.extend_from_slice(&hex::decode(sha256::digest(&data[..])).unwrap());
+    }
+    data
+}
+
+fn refusal<T: Saveable + std::fmt::Debug>(data: &[u8]) -> String {
+    let e = T::load(data).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidData, "{e}");
+    e.to_string()
+}
+
+#[test]
+fn file_of_another_type_is_refused() {
+    let mut saved = vec![];
+    transaction().save(&mut saved).unwrap();
+    assert_eq!(
+        refusal::<Block>(&saved),
+        "Expected Block, file contains Transaction"
+    );
+    let mut chain = vec![];
+    ChainBuilder::new(667)
+        .into_chain()
+        .save(&mut chain)
+        .unwrap();
+    assert_eq!(
+        refusal::<Transaction>(&chain),
+        "Expected Transaction, file contains Blockchain"
+    );
+}
+
+#[test]
+fn file_from_a_newer_version_is_refused() {
+    let body = cbor(&transaction());
+    let newer = FILE_FORMAT_VERSION + 1;
+    let data = file(newer, "Transaction", 1, &body);
+    assert_eq!(
+        refusal::<Transaction>(&data),
+        format!(
+            "Transaction file version {newer} was produced by a newer version, \
+             this one reads up to {FILE_FORMAT_VERSION}"
+        )
+    );
+    // of the current file format, with a newer form of the type
+    let data = file(FILE_FORMAT_VERSION, "Transaction", 2, &body);
+    assert_eq!(
+        refusal::<Transaction>(&data),
+        "Transaction schema version 2 was produced by a newer version, this one reads up to 1"
+    );
+}
+
+#[test]
+fn legacy_files_load() {
+    let transaction = transaction();
+    let body = cbor(&transaction);
+    // plain cbor from before the header, then each header version
+    let mut legacy = vec![body.clone()];
+    legacy.extend((1..=FILE_FORMAT_VERSION).map(|version| file(version, "Transaction", 1, &body)));
+    for data in legacy {
+        let loaded = Transaction::load(&data[..]).unwrap();
+        assert_eq!(loaded.hash(), transaction.hash());
+        // saved again in the current format
+        let mut saved = vec![];
+        loaded.save(&mut saved).unwrap();
+        assert_eq!(saved, file(FILE_FORMAT_VERSION, "Transaction", 1, &body));
+    }
+}
@@ -0,0 +1,97 @@
+// the fuzz targets run over their seeds and regressions in lib/fuzz,
+// then a bounded number of mutations of the seeds, so the harness
+// can't rot between runs of `fuzz check`
+use lib::fuzz::{self, TARGETS};
+use lib::types::Block;
+use lib::utils::Saveable;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// mutated inputs per target, enough to reach past the headers
+const ITERATIONS: u64 = 200;
+
+fn fuzz_dir(kind: &str, target: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "fuzz", kind, target]
+        .iter()
+        .collect()
+}
+
+// the files of the dir, none if it doesn't exist
+fn inputs(dir: &Path) -> Vec<Vec<u8>> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut paths: Vec<PathBuf> = entries.map(|entry| entry.unwrap().path()).collect();
+    paths.sort();
+    paths.iter().map(|path| fs::read(path).unwrap()).collect()
+}
+
+// xorshift64*, so every run mutates the same way
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+}
+
+// a flipped bit, a random byte, a cut or an insertion
+fn mutate(rng: &mut Rng, data: &[u8]) -> Vec<u8> {
+    let mut data = data.to_vec();
+    for _ in 0..1 + rng.below(4) {
+        let at = rng.below(data.len());
+        match rng.below(4) {
+            0 if !data.is_empty() => data[at] ^= 1 << rng.below(8),
+            1 if !data.is_empty() => data[at] = rng.next() as u8,
+            2 => data.truncate(at),
+            _ => {
+                let bytes: Vec<u8> = (0..1 + rng.below(8)).map(|_| rng.next() as u8).collect();
+                data.splice(at..at, bytes);
+            }
+        }
+    }
+    data
+}
+
+#[test]
+fn seeds_and_regressions_fail_cleanly() {
+    for target in TARGETS {
+        let seeds = inputs(&fuzz_dir("seeds", target));
+        assert!(!seeds.is_empty(), "no seeds for {target}");
+        for data in seeds
+            .iter()
+            .chain(&inputs(&fuzz_dir("regressions", target)))
+        {
+            assert!(fuzz::run(target, data));
+        }
+    }
+}
+
+#[test]
+fn mutations_smoke_test() {
+    for target in TARGETS {
+        let seeds = inputs(&fuzz_dir("seeds", target));
+        let mut rng = Rng(711);
+        for _ in 0..ITERATIONS {
+            let seed = &seeds[rng.below(seeds.len())];
+            let data = mutate(&mut rng, seed);
+            fuzz::run(target, &data);
+        }
+    }
+}
+
+// a few kilobytes of zstd claiming far more than
+// MAX_DECOMPRESSED_SIZE once decompressed
+#[test]
+fn zstd_bomb_is_refused() {
+    let bomb = fs::read(fuzz_dir("regressions", "block").join("zstd-bomb")).unwrap();
+    assert!(bomb.len() < 16 * 1024);
+    assert!(Block::load(&bomb[..]).is_err());
+}
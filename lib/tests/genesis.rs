@@ -0,0 +1,79 @@
+// the first block of a chain is checked like any other, bar having
+// no block before it
+use lib::U256;
+use lib::error::SbdError;
+use lib::params::NetworkParams;
+use lib::test_utils::{ChainBuilder, Defect};
+use lib::types::Block;
+
+// regtest, whose target some hashes still miss
+fn builder() -> ChainBuilder {
+    ChainBuilder::with_params(631, NetworkParams::regtest())
+}
+
+fn rejected(block: Block) -> SbdError {
+    let mut builder = builder();
+    let e = builder.chain_mut().add_block(block).unwrap_err();
+    assert_eq!(builder.chain().block_height(), 0);
+    e
+}
+
+#[test]
+fn first_block_is_accepted() {
+    let mut builder = builder();
+    builder.mine_blocks(1).unwrap();
+    assert_eq!(builder.chain().block_height(), 1);
+}
+
+#[test]
+fn first_block_must_meet_its_target() {
+    let block = builder().invalid_block(Defect::ProofOfWork).unwrap();
+    assert!(matches!(rejected(block), SbdError::InvalidProofOfWork));
+}
+
+#[test]
+fn first_block_must_take_the_initial_target() {
+    let mut block = builder().block(|_| {}).unwrap();
+    block.header.target = U256::MAX;
+    block.header.thaw();
+    assert!(matches!(rejected(block), SbdError::InvalidBlock));
+}
+
+#[test]
+fn first_block_must_commit_to_its_transactions() {
+    let block = builder().invalid_block(Defect::MerkleRoot).unwrap();
+    assert!(matches!(rejected(block), SbdError::InvalidMerkleRoot));
+}
+
+#[test]
+fn first_block_coinbase_cannot_pay_more_than_the_reward() {
+    let mut builder = builder();
+    let mut block = builder.block(|_| {}).unwrap();
+    block.transactions[0].outputs[0].value += 1;
+    builder.reseal(&mut block);
+    assert!(matches!(rejected(block), SbdError::InvalidTransaction));
+}
+
+// held anyway, a bad first block doesn't verify
+fn verified(defect: Defect) -> SbdError {
+    let mut builder = builder();
+    let block = builder.invalid_block(defect).unwrap();
+    builder.chain_mut().add_trusted_block(block).unwrap();
+    builder.chain().verify().unwrap_err()
+}
+
+#[test]
+fn chain_with_a_first_block_missing_its_target_does_not_verify() {
+    assert!(matches!(
+        verified(Defect::ProofOfWork),
+        SbdError::InvalidProofOfWork
+    ));
+}
+
+#[test]
+fn chain_with_a_first_block_not_committing_to_its_transactions_does_not_verify() {
+    assert!(matches!(
+        verified(Defect::MerkleRoot),
+        SbdError::InvalidMerkleRoot
+    ));
+}
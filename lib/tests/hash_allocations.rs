@@ -0,0 +1,111 @@
+// Hash::hash encodes into a buffer each thread keeps, so once it's
+// warm hashing allocates no buffer, where encoding into a fresh vec
+// each time allocated one and grew it. counted per thread by a global
+// allocator, so tests running alongside don't count
+use lib::canonical;
+use lib::sha256::Hash;
+use lib::types::{Block, Transaction, TransactionOutput};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use uuid::Uuid;
+
+// the system allocator, counting allocations on each thread
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn allocations<T>(f: impl FnOnce() -> T) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    drop(f());
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+fn compat_block() -> Block {
+    Block::from_hex(include_str!("../compat/block.hex")).unwrap()
+}
+
+// a transaction whose encoding is larger than the buffer is kept at
+fn large_transaction(block: &Block) -> Transaction {
+    let output = &block.transactions[1].outputs[0];
+    let outputs = (0..2_000)
+        .map(|i| TransactionOutput {
+            unique_id: Uuid::from_u128(i),
+            ..output.clone()
+        })
+        .collect();
+    Transaction::new(block.transactions[1].inputs.clone(), outputs)
+}
+
+// what hashing allocated before the buffer: a fresh encoding, then
+// the digest
+fn unbuffered<T: canonical::Canonical>(value: &T) -> Hash {
+    Hash::hash_bytes(&canonical::to_bytes(value))
+}
+
+// what hashing allocates with a buffer already large enough, as
+// encoding the value can allocate for itself
+fn warm<T: canonical::Canonical>(value: &T) -> usize {
+    let mut buf = canonical::to_bytes(value);
+    allocations(|| Hash::hash_with_buf(value, &mut buf))
+}
+
+#[test]
+fn warm_hashing_allocates_no_buffer() {
+    let block = compat_block();
+    let header = &block.header;
+    let transaction = &block.transactions[1];
+
+    // the first hash on a thread may size its buffer, after that
+    // hashing allocates no more than with a buffer of its own
+    assert!(allocations(|| Hash::hash(header)) >= warm(header));
+    for _ in 0..3 {
+        assert_eq!(allocations(|| Hash::hash(header)), warm(header));
+    }
+    // where a fresh encoding allocates it, growing as it goes
+    assert_eq!(Hash::hash(header), unbuffered(header));
+    let fresh = allocations(|| unbuffered(header));
+    assert!(fresh > warm(header), "{fresh} against {}", warm(header));
+
+    // a transaction, encoding larger than the header, grows the
+    // buffer once and is as cheap from then on
+    allocations(|| Hash::hash(transaction));
+    assert_eq!(allocations(|| Hash::hash(transaction)), warm(transaction));
+    assert_eq!(allocations(|| Hash::hash(header)), warm(header));
+    assert_eq!(Hash::hash(transaction), unbuffered(transaction));
+    assert!(allocations(|| unbuffered(transaction)) > warm(transaction));
+
+    // something larger than the buffer is kept at is hashed right,
+    // and the buffer freed after, to be sized again
+    let large = large_transaction(&block);
+    assert_eq!(Hash::hash(&large), unbuffered(&large));
+    assert!(allocations(|| Hash::hash(header)) > warm(header));
+    assert_eq!(allocations(|| Hash::hash(header)), warm(header));
+}
+
+#[test]
+fn caller_buffer_is_reused() {
+    let block = compat_block();
+    let mut buf = vec![];
+    let hash = Hash::hash_with_buf(&block.header, &mut buf);
+    assert_eq!(hash, unbuffered(&block.header));
+    assert_eq!(buf, canonical::to_bytes(&block.header));
+    let capacity = buf.capacity();
+    Hash::hash_with_buf(&block.header, &mut buf);
+    assert_eq!(buf.capacity(), capacity);
+}
@@ -0,0 +1,116 @@
+// the hashes the chain keeps for the blocks and mempool transactions
+// it froze are the ones a fresh encoding gives, cloned or decoded
+// again; a frozen copy edited without thaw is caught in debug builds,
+// and thawed it hashes as edited
+use lib::sha256::Hash;
+use lib::test_utils::ChainBuilder;
+use lib::types::{Block, Transaction};
+use lib::utils::Saveable;
+
+// blocks with spends and a mempool of two more
+fn builder() -> ChainBuilder {
+    let mut builder = ChainBuilder::new(698);
+    builder.fund(0, &[1000, 2000, 3000, 4000]).unwrap();
+    let spend = builder.spend(0, 1, 2500, 10).unwrap();
+    builder
+        .mine_block(|template| template.transactions = vec![spend])
+        .unwrap();
+    builder.mine_blocks(2).unwrap();
+    for to in [2, 3] {
+        let spend = builder.spend(0, to, 500, 10).unwrap();
+        builder.chain_mut().add_to_mempool(spend).unwrap();
+    }
+    builder
+}
+
+fn check_transaction(transaction: &Transaction) {
+    let fresh = Hash::hash(transaction);
+    assert_eq!(transaction.hash(), fresh);
+    assert_eq!(transaction.clone().hash(), fresh);
+    let mut thawed = transaction.clone();
+    thawed.thaw();
+    assert_eq!(thawed.hash(), fresh);
+    assert_eq!(
+        Transaction::from_hex(&transaction.to_hex()).unwrap().hash(),
+        fresh
+    );
+}
+
+fn check_block(block: &Block) {
+    let fresh = Hash::hash(&block.header);
+    assert_eq!(block.hash(), fresh);
+    assert_eq!(block.header.hash(), fresh);
+    assert_eq!(block.clone().hash(), fresh);
+    let mut saved = vec![];
+    block.save(&mut saved).unwrap();
+    assert_eq!(Block::load(&saved[..]).unwrap().hash(), fresh);
+    for transaction in &block.transactions {
+        check_transaction(transaction);
+    }
+}
+
+#[test]
+fn kept_hashes_are_fresh() {
+    let builder = builder();
+    let chain = builder.chain();
+    assert_eq!(chain.block_height(), 4);
+    for block in chain.blocks() {
+        check_block(block);
+    }
+    assert_eq!(chain.mempool().len(), 2);
+    for (_, transaction) in chain.mempool() {
+        check_transaction(transaction);
+        assert!(chain.mempool_get(&Hash::hash(transaction)).is_some());
+    }
+}
+
+#[test]
+fn frozen_and_unfrozen_encode_the_same() {
+    let builder = builder();
+    let block = builder.chain().blocks().last().unwrap();
+    let mut thawed = block.clone();
+    thawed.header.thaw();
+    for transaction in &mut thawed.transactions {
+        transaction.thaw();
+    }
+    assert_eq!(&thawed, block);
+    assert_eq!(thawed.to_hex(), block.to_hex());
+}
+
+#[test]
+fn thawed_edit_hashes_as_edited() {
+    let builder = builder();
+    let block = builder.chain().blocks().last().unwrap();
+    let mut header = block.header.clone();
+    header.nonce += 1;
+    header.thaw();
+    assert_ne!(header.hash(), block.hash());
+    assert_eq!(header.hash(), Hash::hash(&header));
+    let (_, transaction) = &builder.chain().mempool()[0];
+    let mut edited = transaction.clone();
+    edited.outputs[0].value -= 1;
+    edited.thaw();
+    assert_ne!(edited.hash(), transaction.hash());
+    assert_eq!(edited.hash(), Hash::hash(&edited));
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "BUG: changed after freeze")]
+fn frozen_edit_is_caught() {
+    let builder = builder();
+    let (_, transaction) = &builder.chain().mempool()[0];
+    let mut edited = transaction.clone();
+    edited.outputs[0].value -= 1;
+    edited.hash();
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "BUG: changed after freeze")]
+fn frozen_header_edit_is_caught() {
+    let builder = builder();
+    let mut header = builder.chain().blocks().last().unwrap().header.clone();
+    header.nonce += 1;
+    header.hash();
+}
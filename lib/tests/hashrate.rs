@@ -0,0 +1,108 @@
+// the network hashrate estimated over chains mined at a known spacing:
+// at a constant target it's the hashes a block takes over the spacing,
+// whatever the window; across a retarget each block counts at its own
+// target; and without two blocks to time it's 0
+use lib::U256;
+use lib::params::{NetworkParams, REGTEST_MIN_TARGET};
+use lib::test_utils::{ChainBuilder, instant_params};
+use lib::types::Blockchain;
+
+const SEED: u64 = 723;
+// blocks of chains at a constant target
+const BLOCKS: u64 = 30;
+// REGTEST_MIN_TARGET is 2^255 - 1, a block takes 2 hashes at it
+const HASHES_AT_MIN: f64 = 2.0;
+// mined faster than the ideal, so the target falls at each retarget
+const FAST_SPACING: i64 = 2;
+const FAST_BLOCKS: u64 = 120;
+
+fn assert_close(found: f64, expected: f64) {
+    assert!(
+        (found - expected).abs() <= expected * 1e-12,
+        "{found} != {expected}"
+    );
+}
+
+// a chain that never retargets with a block every spacing seconds
+fn constant(spacing: u64) -> Blockchain {
+    let params = NetworkParams {
+        ideal_block_time: spacing,
+        difficulty_update_interval: 0,
+        ..NetworkParams::regtest()
+    };
+    let mut builder = ChainBuilder::with_params(SEED, params);
+    builder.mine_blocks(BLOCKS).unwrap();
+    builder.into_chain()
+}
+
+#[test]
+fn constant_target_gives_hashes_over_spacing() {
+    for spacing in [10, 3] {
+        let chain = constant(spacing);
+        let expected = HASHES_AT_MIN / spacing as f64;
+        for window in [2, 10, BLOCKS, BLOCKS + 100, u64::MAX] {
+            assert_close(chain.estimated_hashrate(window), expected);
+        }
+    }
+}
+
+#[test]
+fn blocks_count_at_their_own_target_across_a_retarget() {
+    let mut builder = ChainBuilder::with_params(SEED, instant_params());
+    let start = builder.timestamp(0);
+    for height in 0..FAST_BLOCKS {
+        builder
+            .mine_block(|template| {
+                template.timestamp =
+                    start + chrono::Duration::seconds(FAST_SPACING * height as i64);
+            })
+            .unwrap();
+    }
+    let chain = builder.chain();
+    let interval = chain.params().difficulty_update_interval;
+    assert_eq!(interval, 50);
+
+    // each window was mined in under a quarter of the ideal time, so
+    // the target falls to a quarter at 50 and again at 100
+    let targets: Vec<U256> = chain.blocks().map(|block| block.header.target).collect();
+    let hashes = |height: u64| {
+        let target = targets[height as usize];
+        let quarters = match height / interval {
+            0 => 0,
+            1 => 1,
+            _ => 2,
+        };
+        assert_eq!(target, REGTEST_MIN_TARGET >> (2 * quarters), "{height}");
+        HASHES_AT_MIN * 4f64.powi(quarters)
+    };
+    // every block after the first of a window, over its time
+    let expected = |window: u64| {
+        let first = FAST_BLOCKS - window;
+        let total: f64 = (first + 1..FAST_BLOCKS).map(hashes).sum();
+        total / ((window - 1) as i64 * FAST_SPACING) as f64
+    };
+
+    // within one target, then across the retarget at 100 and across
+    // both
+    assert_close(chain.estimated_hashrate(20), 32.0 / FAST_SPACING as f64);
+    assert_close(chain.estimated_hashrate(60), expected(60));
+    assert_close(chain.estimated_hashrate(60), 952.0 / 118.0);
+    assert_close(chain.estimated_hashrate(FAST_BLOCKS), expected(FAST_BLOCKS));
+    // a window straddling 100 is between the two targets' rates
+    let straddling = chain.estimated_hashrate(30);
+    assert!(8.0 / 2.0 < straddling && straddling < 32.0 / 2.0);
+}
+
+#[test]
+fn too_few_blocks_give_zero() {
+    let empty = Blockchain::with_params(instant_params());
+    assert_eq!(empty.estimated_hashrate(10), 0.0);
+    let mut builder = ChainBuilder::new(SEED);
+    builder.mine_blocks(1).unwrap();
+    for window in [0, 1, 2, 10] {
+        assert_eq!(builder.chain().estimated_hashrate(window), 0.0);
+    }
+    builder.mine_blocks(1).unwrap();
+    assert_eq!(builder.chain().estimated_hashrate(1), 0.0);
+    assert!(builder.chain().estimated_hashrate(2) > 0.0);
+}
@@ -0,0 +1,109 @@
+// an output locked until UNLOCK: a spend of it is refused by the
+// mempool while the next block is below UNLOCK and by a block at
+// UNLOCK - 1, and taken by both at UNLOCK exactly. the lock is part
+// of the output's hash, and a saved chain keeps it
+use lib::error::SbdError;
+use lib::sha256::Hash;
+use lib::test_utils::{ChainBuilder, instant_params};
+use lib::types::{Blockchain, Transaction, TransactionOutput};
+use lib::utils::Saveable;
+use tempfile::TempDir;
+
+const UNLOCK: u64 = 5;
+const VALUE: u64 = 10_000;
+const FEE: u64 = 100;
+
+// a chain whose genesis coinbase pays key 1 an output locked until
+// UNLOCK, and a spend of it to key 2
+fn locked() -> (ChainBuilder, Hash, Transaction) {
+    let mut builder = ChainBuilder::with_params(718, instant_params());
+    let output = TransactionOutput {
+        spendable_after_height: Some(UNLOCK),
+        ..builder.output(VALUE, 1)
+    };
+    let hash = output.hash();
+    builder
+        .mine_block(|template| template.payouts = vec![output])
+        .unwrap();
+    let payment = builder.output(VALUE - FEE, 2);
+    let spend = builder.sign(&[hash], vec![payment]).unwrap();
+    (builder, hash, spend)
+}
+
+fn is_locked(result: lib::error::Result<impl Sized>, hash: Hash) -> bool {
+    matches!(
+        result,
+        Err(SbdError::LockedOutput { hash: locked, unlock_height: UNLOCK }) if locked == hash
+    )
+}
+
+#[test]
+fn spend_one_block_early_is_refused() {
+    let (mut builder, hash, spend) = locked();
+    while builder.chain().chain_height() < UNLOCK - 1 {
+        // refused by the mempool for every block below the unlock height
+        let mut chain = builder.chain().clone();
+        assert!(is_locked(chain.add_to_mempool(spend.clone()), hash));
+        builder.mine_blocks(1).unwrap();
+    }
+    // the last block before it can't include it either
+    assert_eq!(builder.chain().chain_height(), UNLOCK - 1);
+    let mut chain = builder.chain().clone();
+    assert!(is_locked(chain.add_to_mempool(spend.clone()), hash));
+    let early = builder
+        .block(|template| template.transactions = vec![spend.clone()])
+        .unwrap();
+    assert!(is_locked(chain.add_block(early), hash));
+    assert!(chain.utxo(&hash).is_some());
+}
+
+#[test]
+fn spend_at_the_unlock_height_is_taken() {
+    let (mut builder, hash, spend) = locked();
+    builder
+        .mine_blocks(UNLOCK - builder.chain().chain_height())
+        .unwrap();
+    assert_eq!(builder.chain().chain_height(), UNLOCK);
+    // by the mempool, and by a block at the unlock height
+    let mut chain = builder.chain().clone();
+    chain.add_to_mempool(spend.clone()).unwrap();
+    let block = builder
+        .mine_block(|template| template.transactions = vec![spend.clone()])
+        .unwrap();
+    let chain = builder.chain();
+    assert_eq!(chain.block_height() - 1, UNLOCK);
+    assert!(block.transactions.contains(&spend));
+    assert!(chain.utxo(&hash).is_none());
+    assert_eq!(
+        chain.utxo(&spend.outputs[0].hash()),
+        Some(&spend.outputs[0])
+    );
+}
+
+#[test]
+fn lock_is_part_of_the_output() {
+    let (builder, hash, spend) = locked();
+    let output = builder.chain().utxo(&hash).unwrap().clone();
+    assert_eq!(output.spendable_after_height, Some(UNLOCK));
+    let unlocked = TransactionOutput {
+        spendable_after_height: None,
+        ..output.clone()
+    };
+    let later = TransactionOutput {
+        spendable_after_height: Some(UNLOCK + 1),
+        ..output.clone()
+    };
+    assert_ne!(unlocked.hash(), hash);
+    assert_ne!(later.hash(), hash);
+    assert!(!output.is_spendable_at(UNLOCK - 1));
+    assert!(output.is_spendable_at(UNLOCK));
+    assert!(unlocked.is_spendable_at(0));
+
+    // saved and loaded, the chain still holds it to the lock
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("chain.cbor");
+    builder.chain().save_to_file(&path).unwrap();
+    let mut loaded = Blockchain::load_from_file(&path).unwrap();
+    assert_eq!(loaded.utxo(&hash), Some(&output));
+    assert!(is_locked(loaded.add_to_mempool(spend), hash));
+}
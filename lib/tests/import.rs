@@ -0,0 +1,136 @@
+// import_blocks over a blocks.dat dump read a few bytes at a time: all
+// of it imported and validated, a foreign block in the middle rejected
+// with the rest still imported, a damaged record ending the import
+use lib::test_utils::{ChainBuilder, instant_params};
+use lib::types::{Block, BlockStore, Blockchain, ChainBatch, ChainStore, ChainTip};
+use std::fs;
+use std::io::{self, Read};
+use std::sync::OnceLock;
+use tempfile::TempDir;
+
+const BLOCKS: u64 = 1000;
+// smaller than a record header, so every record spans reads
+const READ_SIZE: usize = 7;
+
+// a blocks.dat of a chain, with where each record starts
+struct Dump {
+    chain: Blockchain,
+    data: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl Dump {
+    fn record(&self, index: usize) -> &[u8] {
+        let end = self
+            .offsets
+            .get(index + 1)
+            .copied()
+            .unwrap_or(self.data.len());
+        &self.data[self.offsets[index]..end]
+    }
+}
+
+fn dump_of(seed: u64, count: u64) -> Dump {
+    let mut builder = ChainBuilder::new(seed);
+    builder.mine_blocks(2).unwrap();
+    while builder.chain().block_height() < count {
+        let to = 1 + builder.chain().block_height() as usize % 5;
+        let spend = builder.spend(0, to, 100, 10).unwrap();
+        builder
+            .mine_block(|template| template.transactions = vec![spend])
+            .unwrap();
+    }
+    let chain = builder.into_chain();
+    let dir = TempDir::new().unwrap();
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    let mut replay = Blockchain::with_params(instant_params());
+    for block in chain.blocks() {
+        replay.add_trusted_block(block.clone()).unwrap();
+        let tip = ChainTip {
+            network: replay.network(),
+            height: replay.block_height(),
+            hash: block.hash(),
+            target: replay.target(),
+        };
+        store
+            .commit(ChainBatch::connect(block.clone(), tip))
+            .unwrap();
+    }
+    let offsets = (0..count)
+        .map(|height| store.block_offset(height).unwrap() as usize)
+        .collect();
+    let data = fs::read(dir.path().join("blocks.dat")).unwrap();
+    Dump {
+        chain,
+        data,
+        offsets,
+    }
+}
+
+fn dump() -> &'static Dump {
+    static DUMP: OnceLock<Dump> = OnceLock::new();
+    DUMP.get_or_init(|| dump_of(673, BLOCKS))
+}
+
+// hands out at most READ_SIZE bytes a read
+struct Trickle<'a>(&'a [u8]);
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(READ_SIZE).min(self.0.len());
+        buf[..n].copy_from_slice(&self.0[..n]);
+        self.0 = &self.0[n..];
+        Ok(n)
+    }
+}
+
+fn import(data: &[u8]) -> (Blockchain, u64, u64) {
+    let mut chain = Blockchain::with_params(instant_params());
+    let report = chain.import_blocks(Trickle(data), true).unwrap();
+    assert_eq!(report.height, chain.chain_height());
+    (chain, report.imported, report.rejected)
+}
+
+fn hashes(chain: &Blockchain) -> Vec<lib::sha256::Hash> {
+    chain.blocks().map(Block::hash).collect()
+}
+
+#[test]
+fn whole_dump_imports_in_small_reads() {
+    let dump = dump();
+    let (chain, imported, rejected) = import(&dump.data);
+    assert_eq!((imported, rejected), (BLOCKS, 0));
+    assert_eq!(hashes(&chain), hashes(&dump.chain));
+    assert_eq!(chain.utxo_commitment(), dump.chain.utxo_commitment());
+}
+
+#[test]
+fn foreign_block_mid_stream_is_rejected() {
+    let dump = dump();
+    let foreign = dump_of(1673, 2);
+    let middle = dump.offsets[BLOCKS as usize / 2];
+    let mut data = dump.data[..middle].to_vec();
+    data.extend_from_slice(foreign.record(0));
+    data.extend_from_slice(&dump.data[middle..]);
+    let (chain, imported, rejected) = import(&data);
+    assert_eq!((imported, rejected), (BLOCKS, 1));
+    assert_eq!(hashes(&chain), hashes(&dump.chain));
+}
+
+#[test]
+fn damaged_record_ends_the_import() {
+    let dump = dump();
+    let damaged = BLOCKS as usize / 2;
+    let mut data = dump.data.clone();
+    // a byte of the cbor, past the length and checksum
+    data[dump.offsets[damaged] + 20] ^= 0xff;
+    let (chain, imported, rejected) = import(&data);
+    assert_eq!((imported, rejected), (damaged as u64, 1));
+    assert_eq!(hashes(&chain)[..], hashes(&dump.chain)[..damaged]);
+
+    // cut off partway through a record
+    let cut = &dump.data[..dump.offsets[damaged] + dump.record(damaged).len() / 2];
+    let (chain, imported, rejected) = import(cut);
+    assert_eq!((imported, rejected), (damaged as u64, 1));
+    assert_eq!(chain.block_height(), damaged as u64);
+}
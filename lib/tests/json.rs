@@ -0,0 +1,93 @@
+// the json form of a Saveable is a way there and back: saved as json,
+// loaded and saved as cbor again, a transaction or block keeps its
+// hash, and the print tools read the json file as they do the cbor
+use assert_cmd::Command;
+use lib::test_utils::ChainBuilder;
+use lib::types::{Block, Transaction};
+use lib::utils::{FileFormat, Saveable};
+use predicates::prelude::*;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn compat(name: &str) -> String {
+    format!("{}/compat/{name}", env!("CARGO_MANIFEST_DIR"))
+}
+
+// the value saved as json, loaded from it, saved as cbor and
+// loaded from that, through files in dir
+fn through_json<T: Saveable>(value: &T, dir: &Path) -> T {
+    let json = dir.join("value.json");
+    let cbor = dir.join("value.cbor");
+    value.save_json_to_file(&json).unwrap();
+    T::load_json_from_file(&json)
+        .unwrap()
+        .save_to_file(&cbor)
+        .unwrap();
+    T::load_from_file(&cbor).unwrap()
+}
+
+#[test]
+fn fixture_transaction_keeps_its_hash() {
+    let dir = TempDir::new().unwrap();
+    let original = Transaction::load_from_file(compat("transaction.cbor")).unwrap();
+    let loaded = through_json(&original, dir.path());
+    assert_eq!(loaded.hash(), original.hash());
+    assert_eq!(loaded.to_hex(), original.to_hex());
+}
+
+#[test]
+fn fixture_block_keeps_its_hash() {
+    let dir = TempDir::new().unwrap();
+    let original = Block::load_from_file(compat("block.cbor")).unwrap();
+    let loaded = through_json(&original, dir.path());
+    assert_eq!(loaded.hash(), original.hash());
+    assert_eq!(loaded.header.merkle_root, original.header.merkle_root);
+    // hashes in it are hex, as block_print shows them
+    let json = std::fs::read_to_string(dir.path().join("value.json")).unwrap();
+    assert!(json.contains(&format!("\"{}\"", original.header.prev_block_hash)));
+}
+
+// inputs and their signatures too, which the fixture has none of
+#[test]
+fn signed_spend_keeps_its_hash() {
+    let dir = TempDir::new().unwrap();
+    let mut builder = ChainBuilder::new(666);
+    builder.mine_blocks(2).unwrap();
+    let spend = builder.spend(0, 1, 1000, 10).unwrap();
+    let loaded = through_json(&spend, dir.path());
+    assert_eq!(loaded.hash(), spend.hash());
+    // still a valid spend of the chain
+    builder.chain_mut().add_to_mempool(loaded).unwrap();
+}
+
+#[test]
+fn print_tools_read_the_json_form() {
+    let dir = TempDir::new().unwrap();
+    let transaction = Transaction::load_from_file(compat("transaction.cbor")).unwrap();
+    let block = Block::load_from_file(compat("block.cbor")).unwrap();
+    let tx_json = dir.path().join("transaction.json");
+    let block_json = dir.path().join("block.json");
+    transaction
+        .save_to_file_as(&tx_json, FileFormat::Json)
+        .unwrap();
+    block
+        .save_to_file_as(&block_json, FileFormat::Json)
+        .unwrap();
+    Command::cargo_bin("tx_print")
+        .unwrap()
+        .arg(&tx_json)
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "txid          {}",
+            transaction.hash()
+        )));
+    Command::cargo_bin("block_print")
+        .unwrap()
+        .arg(&block_json)
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(block.hash().to_string()));
+}
@@ -0,0 +1,201 @@
+// key_gen through each format: a new key and the public half it
+// extracts are read back by the lib and by inspect as the same key,
+// private files are only their owner's, a seed always gives the same
+// key, inspect never prints the secret, and existing files are only
+// overwritten with --force
+use assert_cmd::Command;
+use lib::crypto::{PrivateKey, PublicKey};
+use lib::sha256::Hash;
+use lib::utils::Saveable;
+use predicates::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+const FORMATS: [&str; 3] = ["cbor", "hex", "pem"];
+
+fn key_gen(args: &[&str]) -> assert_cmd::assert::Assert {
+    Command::cargo_bin("key_gen").unwrap().args(args).assert()
+}
+
+fn path(dir: &TempDir, name: &str) -> PathBuf {
+    dir.path().join(name)
+}
+
+fn stdout(assert: assert_cmd::assert::Assert) -> String {
+    String::from_utf8(assert.success().get_output().stdout.clone()).unwrap()
+}
+
+// the public key and address lines key_gen prints for a key
+fn public_lines(pubkey: &PublicKey) -> String {
+    format!(
+        "public key  {}\naddress     {}\n",
+        pubkey.to_uncompressed_hex(),
+        pubkey.to_address()
+    )
+}
+
+fn load_private(path: &Path, format: &str) -> PrivateKey {
+    match format {
+        "cbor" => PrivateKey::load_from_file(path).unwrap(),
+        "hex" => PrivateKey::from_hex(fs::read_to_string(path).unwrap().trim()).unwrap(),
+        _ => PrivateKey::from_pem(&fs::read_to_string(path).unwrap()).unwrap(),
+    }
+}
+
+fn load_public(path: &Path, format: &str) -> PublicKey {
+    match format {
+        "cbor" => PublicKey::load_from_file(path).unwrap(),
+        "hex" => PublicKey::from_address(fs::read_to_string(path).unwrap().trim()).unwrap(),
+        _ => PublicKey::from_pem(&fs::read_to_string(path).unwrap()).unwrap(),
+    }
+}
+
+#[test]
+fn each_format_round_trips() {
+    let dir = TempDir::new().unwrap();
+    for format in FORMATS {
+        let private = path(&dir, &format!("key.{format}"));
+        let printed = stdout(key_gen(&[
+            "new",
+            "--out",
+            private.to_str().unwrap(),
+            "--format",
+            format,
+        ]));
+        let key = load_private(&private, format);
+        let pubkey = key.public_key();
+        assert_eq!(printed, public_lines(&pubkey), "{format}");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&private).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600, "{format}");
+        }
+
+        // the public half, in every format, of the key in this one
+        for pub_format in FORMATS {
+            let public = path(&dir, &format!("key.{format}.pub.{pub_format}"));
+            let printed = stdout(key_gen(&[
+                "pub",
+                private.to_str().unwrap(),
+                "--out",
+                public.to_str().unwrap(),
+                "--format",
+                pub_format,
+            ]));
+            assert_eq!(printed, public_lines(&pubkey));
+            assert_eq!(load_public(&public, pub_format), pubkey);
+            key_gen(&["inspect", public.to_str().unwrap()])
+                .success()
+                .stdout(format!("kind        public, {pub_format}\n") + &public_lines(&pubkey));
+        }
+    }
+}
+
+#[test]
+fn seed_gives_the_same_key() {
+    let dir = TempDir::new().unwrap();
+    let mut keys = vec![];
+    for name in ["first", "second", "other"] {
+        let seed = if name == "other" { "711" } else { "710" };
+        let out = path(&dir, name);
+        key_gen(&["new", "--out", out.to_str().unwrap(), "--seed", seed]).success();
+        keys.push(fs::read(out).unwrap());
+    }
+    assert_eq!(keys[0], keys[1]);
+    assert_ne!(keys[0], keys[2]);
+    // the secret is the hash of the seed
+    let expected = Hash::hash_bytes(b"710").to_hex();
+    assert_eq!(String::from_utf8(keys[0].clone()).unwrap(), expected + "\n");
+    // and the format only changes how it's written
+    let pem = path(&dir, "seeded.pem");
+    key_gen(&[
+        "new",
+        "--out",
+        pem.to_str().unwrap(),
+        "--seed",
+        "710",
+        "--format",
+        "pem",
+    ])
+    .success();
+    assert_eq!(
+        load_private(&pem, "pem").to_hex(),
+        load_private(&path(&dir, "first"), "hex").to_hex()
+    );
+}
+
+#[test]
+fn inspect_never_shows_the_secret() {
+    let dir = TempDir::new().unwrap();
+    for format in FORMATS {
+        let private = path(&dir, &format!("key.{format}"));
+        key_gen(&[
+            "new",
+            "--out",
+            private.to_str().unwrap(),
+            "--format",
+            format,
+        ])
+        .success();
+        let key = load_private(&private, format);
+        let shown = stdout(key_gen(&["inspect", private.to_str().unwrap()]));
+        assert_eq!(
+            shown,
+            format!("kind        private, {format}\n") + &public_lines(&key.public_key())
+        );
+        assert!(!shown.contains(&key.to_hex()));
+    }
+    // nor is a public key taken for a private one
+    let public = path(&dir, "key.pub");
+    key_gen(&[
+        "pub",
+        path(&dir, "key.hex").to_str().unwrap(),
+        "--out",
+        public.to_str().unwrap(),
+    ])
+    .success();
+    key_gen(&[
+        "pub",
+        public.to_str().unwrap(),
+        "--out",
+        path(&dir, "again.pub").to_str().unwrap(),
+    ])
+    .failure()
+    .stderr(predicate::str::contains("holds a public key already"));
+    let junk = path(&dir, "junk");
+    fs::write(&junk, "not a key").unwrap();
+    key_gen(&["inspect", junk.to_str().unwrap()])
+        .failure()
+        .stderr(predicate::str::contains("not a key file"));
+}
+
+#[test]
+fn existing_files_need_force() {
+    let dir = TempDir::new().unwrap();
+    let out = path(&dir, "key");
+    let out = out.to_str().unwrap();
+    key_gen(&["new", "--out", out, "--seed", "710"]).success();
+    let first = fs::read(out).unwrap();
+    for args in [
+        &["new", "--out", out][..],
+        &["new", "--out", out, "--seed", "711"],
+        &["pub", out, "--out", out],
+    ] {
+        key_gen(args)
+            .failure()
+            .stderr(predicate::str::contains(format!(
+                "{out} exists, --force overwrites it"
+            )));
+        assert_eq!(fs::read(out).unwrap(), first);
+    }
+    key_gen(&["new", "--out", out, "--seed", "711", "--force"]).success();
+    let second = fs::read(out).unwrap();
+    assert_ne!(second, first);
+    // a public key file made with --force over a private one
+    key_gen(&["pub", out, "--out", out, "--force"]).success();
+    key_gen(&["inspect", out])
+        .success()
+        .stdout(predicate::str::starts_with("kind        public, hex"));
+}
@@ -0,0 +1,169 @@
+// a light client fed a full node's headers: proofs the node gives
+// for its transactions, sent over the wire, are accepted with the
+// confirmations the node counts, growing a header at a time; a proof
+// against a header off the best chain or one the client never saw is
+// refused, as is a proof for another transaction
+use lib::error::SbdError;
+use lib::light_client::LightClient;
+use lib::sha256::Hash;
+use lib::test_utils::ChainBuilder;
+use lib::types::{BlockHeader, Transaction};
+use lib::utils::{MerkleProof, Saveable};
+
+const SEED: u64 = 721;
+// spends in the proven block, so with the coinbase an odd layer
+const SPENDS: usize = 4;
+const LATER_BLOCKS: u64 = 5;
+
+// a chain with a block of SPENDS spends, and those spends
+fn chain() -> (ChainBuilder, Vec<Transaction>) {
+    let mut builder = ChainBuilder::new(SEED);
+    for key in 1..=SPENDS {
+        builder.fund(key, &[10_000]).unwrap();
+    }
+    let spends: Vec<Transaction> = (1..=SPENDS)
+        .map(|key| builder.spend(key, 0, 1000 * key as u64, 10).unwrap())
+        .collect();
+    builder
+        .mine_block(|template| template.transactions = spends.clone())
+        .unwrap();
+    (builder, spends)
+}
+
+// the header as a peer receives it
+fn sent(header: &BlockHeader) -> BlockHeader {
+    let mut bytes = vec![];
+    header.save(&mut bytes).unwrap();
+    BlockHeader::load(&bytes[..]).unwrap()
+}
+
+// the node's proof of a transaction, as the client receives it
+fn node_proof(builder: &ChainBuilder, txid: &Hash) -> (Hash, MerkleProof) {
+    let (block, proof) = builder.chain().transaction_proof(txid).unwrap();
+    let mut bytes = vec![];
+    ciborium::into_writer(&proof, &mut bytes).unwrap();
+    (block, ciborium::from_reader(&bytes[..]).unwrap())
+}
+
+// a client following the builder's chain
+fn synced(builder: &ChainBuilder) -> LightClient {
+    let mut client = LightClient::new(builder.chain().params().clone());
+    for block in builder.chain().blocks() {
+        client.add_header(sent(&block.header)).unwrap();
+    }
+    client
+}
+
+#[test]
+fn full_node_proofs_are_accepted() {
+    let (mut builder, spends) = chain();
+    let mut client = synced(&builder);
+    assert_eq!(client.height(), builder.chain().chain_height());
+    assert_eq!(client.tip().unwrap().hash(), builder.chain().tip_hash());
+
+    let block = builder.chain().blocks().last().unwrap().clone();
+    assert_eq!(block.transactions.len(), SPENDS + 1);
+    for transaction in &block.transactions {
+        let txid = transaction.hash();
+        let (block_hash, proof) = node_proof(&builder, &txid);
+        assert_eq!(block_hash, block.hash());
+        assert_eq!(
+            client
+                .verify_transaction_inclusion(&txid, &proof, &block_hash)
+                .unwrap(),
+            1
+        );
+    }
+
+    // each header after the block is another confirmation, as the
+    // node counts them
+    for _ in 0..LATER_BLOCKS {
+        builder.mine_blocks(1).unwrap();
+        let tip = builder.chain().blocks().last().unwrap().header.clone();
+        client.add_header(sent(&tip)).unwrap();
+        for spend in &spends {
+            let txid = spend.hash();
+            let (block_hash, proof) = node_proof(&builder, &txid);
+            assert_eq!(
+                client
+                    .verify_transaction_inclusion(&txid, &proof, &block_hash)
+                    .unwrap(),
+                builder.chain().confirmations(&txid).unwrap()
+            );
+        }
+    }
+    let (block_hash, proof) = node_proof(&builder, &spends[0].hash());
+    assert_eq!(
+        client
+            .verify_transaction_inclusion(&spends[0].hash(), &proof, &block_hash)
+            .unwrap(),
+        LATER_BLOCKS + 1
+    );
+
+    // the proof of one transaction doesn't prove another
+    assert!(matches!(
+        client.verify_transaction_inclusion(&spends[1].hash(), &proof, &block_hash),
+        Err(SbdError::InvalidMerkleRoot)
+    ));
+}
+
+#[test]
+fn off_chain_headers_are_refused() {
+    let (builder, spends) = chain();
+    let mut client = synced(&builder);
+    let height = client.height();
+
+    // another chain from the same genesis, a block shorter and then
+    // going its own way
+    let mut fork = ChainBuilder::new(SEED);
+    for block in builder.chain().blocks().take(height as usize - 1) {
+        fork.chain_mut().add_block(block.clone()).unwrap();
+    }
+    let mut other = vec![];
+    for _ in 0..2 {
+        let spent = fork.spend(1, 2, 500, 10).unwrap();
+        other.push(spent.clone());
+        fork.mine_block(|template| {
+            template.miner = 1;
+            template.transactions = vec![spent];
+        })
+        .unwrap();
+    }
+    let fork_headers: Vec<BlockHeader> = fork
+        .chain()
+        .blocks()
+        .skip(height as usize - 1)
+        .map(|block| block.header.clone())
+        .collect();
+
+    // a header whose parent the client never saw
+    assert!(matches!(
+        client.add_header(sent(&fork_headers[1])),
+        Err(SbdError::UnknownBlock(hash)) if hash == fork_headers[0].hash()
+    ));
+    let (block_hash, proof) = node_proof(&fork, &other[1].hash());
+    assert!(matches!(
+        client.verify_transaction_inclusion(&other[1].hash(), &proof, &block_hash),
+        Err(SbdError::UnknownBlock(hash)) if hash == block_hash
+    ));
+
+    // one on a branch with no more work is kept off the best chain,
+    // and so is what's proven against it
+    client.add_header(sent(&fork_headers[0])).unwrap();
+    assert_eq!(client.height(), height);
+    assert_eq!(client.tip().unwrap().hash(), builder.chain().tip_hash());
+    let (block_hash, proof) = node_proof(&fork, &other[0].hash());
+    assert_eq!(block_hash, fork_headers[0].hash());
+    assert!(matches!(
+        client.verify_transaction_inclusion(&other[0].hash(), &proof, &block_hash),
+        Err(SbdError::NotOnBestChain(hash)) if hash == block_hash
+    ));
+    // while the node's own proofs still hold
+    let (block_hash, proof) = node_proof(&builder, &spends[0].hash());
+    assert_eq!(
+        client
+            .verify_transaction_inclusion(&spends[0].hash(), &proof, &block_hash)
+            .unwrap(),
+        1
+    );
+}
@@ -0,0 +1,121 @@
+// block locators, and the fork point a peer's locator resolves to in
+// our chain: on the same chain, on a stale branch, on a chain sharing
+// nothing, and after the header chain switched branches
+use lib::sha256::Hash;
+use lib::test_utils::ChainBuilder;
+use lib::types::{Blockchain, HeaderChain};
+
+// blocks both branches share
+const SHARED: u64 = 5;
+
+// the chain of seed grown by count blocks after SHARED, which of two
+// branches depends on whether it pays key 1 first
+fn branch(seed: u64, funded: bool, count: u64) -> Blockchain {
+    let mut builder = ChainBuilder::new(seed);
+    builder.mine_blocks(SHARED).unwrap();
+    if funded {
+        builder.fund(1, &[100]).unwrap();
+        builder.mine_blocks(count - 1).unwrap();
+    } else {
+        builder.mine_blocks(count).unwrap();
+    }
+    builder.into_chain()
+}
+
+fn hashes(chain: &Blockchain) -> Vec<Hash> {
+    chain.blocks().map(|block| block.hash()).collect()
+}
+
+#[test]
+fn empty_chain_has_an_empty_locator_and_no_fork_point() {
+    let empty = Blockchain::new();
+    assert!(empty.block_locator().is_empty());
+    assert_eq!(empty.find_fork_point(&[]), None);
+    let other = branch(645, false, 3);
+    assert_eq!(empty.find_fork_point(&other.block_locator()), None);
+    assert_eq!(other.find_fork_point(&[]), None);
+}
+
+#[test]
+fn locator_steps_back_exponentially_to_genesis() {
+    let mut builder = ChainBuilder::new(645);
+    builder.mine_blocks(40).unwrap();
+    let chain = builder.chain();
+    let hashes = hashes(chain);
+    let heights: Vec<usize> = chain
+        .block_locator()
+        .iter()
+        .map(|hash| hashes.iter().position(|known| known == hash).unwrap())
+        .collect();
+    // ten back one at a time, then doubling, then genesis
+    assert_eq!(
+        heights,
+        [39, 38, 37, 36, 35, 34, 33, 32, 31, 30, 28, 24, 16, 0]
+    );
+    // a single block is its own tip and genesis
+    let mut single = ChainBuilder::new(645);
+    single.mine_blocks(1).unwrap();
+    assert_eq!(single.chain().block_locator(), hashes[..1]);
+}
+
+#[test]
+fn same_chain_forks_at_its_tip() {
+    let chain = branch(645, false, 4);
+    assert_eq!(
+        chain.find_fork_point(&chain.block_locator()),
+        Some(chain.chain_height() - 1)
+    );
+}
+
+// the node serves from genesis then
+#[test]
+fn unrelated_locator_has_no_fork_point() {
+    let ours = branch(645, false, 4);
+    let theirs = branch(646, false, 4);
+    assert_eq!(ours.find_fork_point(&theirs.block_locator()), None);
+}
+
+#[test]
+fn stale_branch_hashes_are_skipped() {
+    let ours = branch(645, false, 4);
+    let stale = branch(645, true, 6);
+    assert_eq!(
+        hashes(&ours)[..SHARED as usize],
+        hashes(&stale)[..SHARED as usize]
+    );
+    assert_ne!(
+        hashes(&ours)[SHARED as usize],
+        hashes(&stale)[SHARED as usize]
+    );
+    // the highest block both have
+    assert_eq!(
+        ours.find_fork_point(&stale.block_locator()),
+        Some(SHARED - 1)
+    );
+    assert_eq!(
+        stale.find_fork_point(&ours.block_locator()),
+        Some(SHARED - 1)
+    );
+}
+
+// a header chain that switched to a heavier branch, as header sync
+// does, describes only the new branch
+#[test]
+fn locator_after_a_reorg_describes_the_new_branch() {
+    let old = branch(645, false, 4);
+    let new = branch(645, true, 6);
+    let mut headers = HeaderChain::from_blocks(old.blocks());
+    headers.truncate(SHARED);
+    for block in new.blocks().skip(SHARED as usize) {
+        headers.add_header(block.header.clone()).unwrap();
+    }
+    assert!(headers.total_work() > HeaderChain::from_blocks(old.blocks()).total_work());
+    let locator = headers.locator();
+    assert_eq!(locator, HeaderChain::from_blocks(new.blocks()).locator());
+    assert_eq!(locator, new.block_locator());
+    assert_eq!(locator[0], *hashes(&new).last().unwrap());
+    // a node still on the old branch serves from the fork point,
+    // one on the new from its tip
+    assert_eq!(old.find_fork_point(&locator), Some(SHARED - 1));
+    assert_eq!(new.find_fork_point(&locator), Some(new.chain_height() - 1));
+}
@@ -0,0 +1,94 @@
+// the events the chain emits, caught by a subscriber of our own: a
+// refused block is warned of once, with the reason it was refused
+use lib::error::SbdError;
+use lib::test_utils::{ChainBuilder, Defect};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::registry::{LookupSpan, Registry};
+
+// an event's level, message and other fields, and the fields of the
+// spans it was emitted in
+#[derive(Debug, Default)]
+struct Captured {
+    level: Option<Level>,
+    fields: Vec<(String, String)>,
+}
+
+impl Captured {
+    fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl Visit for Captured {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.fields
+            .push((field.name().to_string(), format!("{value:?}")));
+    }
+}
+
+#[derive(Clone, Default)]
+struct Capture {
+    events: Arc<Mutex<Vec<Captured>>>,
+}
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Capture {
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::span::Id,
+        ctx: Context<'_, S>,
+    ) {
+        let mut fields = Captured::default();
+        attrs.record(&mut fields);
+        ctx.span(id).unwrap().extensions_mut().insert(fields);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut captured = Captured {
+            level: Some(*event.metadata().level()),
+            fields: vec![],
+        };
+        event.record(&mut captured);
+        for span in ctx.event_scope(event).into_iter().flatten() {
+            if let Some(fields) = span.extensions().get::<Captured>() {
+                captured.fields.extend(fields.fields.iter().cloned());
+            }
+        }
+        self.events.lock().unwrap().push(captured);
+    }
+}
+
+#[test]
+fn invalid_block_is_warned_of_once() {
+    let mut builder = ChainBuilder::new(685);
+    builder.mine_blocks(2).unwrap();
+    let block = builder.invalid_block(Defect::MerkleRoot).unwrap();
+    let hash = block.hash();
+    let height = builder.chain().block_height();
+
+    let capture = Capture::default();
+    let subscriber = Registry::default().with(capture.clone());
+    let result =
+        tracing::subscriber::with_default(subscriber, || builder.chain_mut().add_block(block));
+    assert!(result.is_err());
+
+    let events = capture.events.lock().unwrap();
+    let warnings: Vec<&Captured> = events
+        .iter()
+        .filter(|event| event.level == Some(Level::WARN))
+        .collect();
+    assert_eq!(warnings.len(), 1, "{events:#?}");
+    let warning = warnings[0];
+    assert_eq!(warning.field("message"), Some("rejected block"));
+    let reason = SbdError::InvalidMerkleRoot.to_string();
+    assert_eq!(warning.field("reason"), Some(reason.as_str()));
+    assert_eq!(warning.field("hash"), Some(hash.to_string().as_str()));
+    assert_eq!(warning.field("height"), Some(height.to_string().as_str()));
+}
@@ -0,0 +1,55 @@
+// coinbase outputs spendable only coinbase_maturity blocks on
+use lib::error::SbdError;
+use lib::params::NetworkParams;
+use lib::test_utils::{ChainBuilder, instant_params};
+
+const MATURITY: u64 = 3;
+
+// a chain whose first block pays key 1, and a spend of it to key 2
+fn funded() -> (ChainBuilder, lib::types::Transaction) {
+    let params = NetworkParams {
+        coinbase_maturity: MATURITY,
+        ..instant_params()
+    };
+    let mut builder = ChainBuilder::with_params(690, params);
+    builder.fund(1, &[1000]).unwrap();
+    let spend = builder.spend(1, 2, 900, 100).unwrap();
+    (builder, spend)
+}
+
+#[test]
+fn immature_coinbase_is_rejected_by_blocks() {
+    let (mut builder, spend) = funded();
+    while builder.chain().chain_height() < MATURITY {
+        let block = builder
+            .block(|template| template.transactions = vec![spend.clone()])
+            .unwrap();
+        assert!(matches!(
+            builder.chain_mut().add_block(block),
+            Err(SbdError::ImmatureCoinbase {
+                mature_height: MATURITY,
+                ..
+            })
+        ));
+        builder.mine_blocks(1).unwrap();
+    }
+    builder
+        .mine_block(|template| template.transactions = vec![spend])
+        .unwrap();
+}
+
+#[test]
+fn immature_coinbase_is_rejected_by_the_mempool() {
+    let (mut builder, spend) = funded();
+    while builder.chain().chain_height() < MATURITY {
+        assert!(matches!(
+            builder.chain_mut().add_to_mempool(spend.clone()),
+            Err(SbdError::ImmatureCoinbase {
+                mature_height: MATURITY,
+                ..
+            })
+        ));
+        builder.mine_blocks(1).unwrap();
+    }
+    builder.chain_mut().add_to_mempool(spend).unwrap();
+}
@@ -0,0 +1,59 @@
+// the mempool takes only spends signed by the owner of each output,
+// so a forged spend can't hold an output against the real one
+use lib::crypto::Signature;
+use lib::error::SbdError;
+use lib::test_utils::ChainBuilder;
+use lib::types::{Transaction, TransactionInput};
+
+// a spend of key 0's funding, signed as the builder signs it, and
+// the same spend with each input signed by key 1 instead
+fn spends() -> (ChainBuilder, Transaction, Transaction) {
+    let mut builder = ChainBuilder::new(631);
+    builder.fund(0, &[1000]).unwrap();
+    let payment = builder.spend(0, 2, 900, 100).unwrap();
+    let inputs = payment
+        .inputs
+        .iter()
+        .map(|input| TransactionInput {
+            prev_transaction_output_hash: input.prev_transaction_output_hash,
+            signature: Signature::sign_input(
+                &payment.sighash(&input.prev_transaction_output_hash),
+                builder.key(1),
+            ),
+        })
+        .collect();
+    let forged = Transaction::new(inputs, payment.outputs.clone());
+    (builder, payment, forged)
+}
+
+#[test]
+fn spend_signed_by_another_key_is_refused() {
+    let (mut builder, payment, forged) = spends();
+    let chain = builder.chain_mut();
+    assert!(matches!(
+        chain.add_to_mempool(forged),
+        Err(SbdError::InvalidSignature)
+    ));
+    assert!(chain.mempool().is_empty());
+    // the output is still free for its owner
+    chain.add_to_mempool(payment.clone()).unwrap();
+    assert_eq!(chain.mempool().len(), 1);
+}
+
+#[test]
+fn forged_spend_does_not_replace_the_real_one() {
+    let (mut builder, payment, forged) = spends();
+    let chain = builder.chain_mut();
+    chain.add_to_mempool(payment.clone()).unwrap();
+    assert!(matches!(
+        chain.add_to_mempool(forged),
+        Err(SbdError::InvalidSignature)
+    ));
+    assert_eq!(chain.mempool().len(), 1);
+    assert!(
+        chain
+            .mempool()
+            .iter()
+            .any(|(_, tx)| tx.hash() == payment.hash())
+    );
+}
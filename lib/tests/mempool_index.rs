@@ -0,0 +1,265 @@
+// the mempool's indexes by txid, by spent output and by created
+// output stay in step with its entries over a seeded run of new
+// spends, replacements, blocks confirming some entries and conflicting
+// with others, expiry and eviction by age: after every step each entry
+// is found through each index and nothing that left is
+use chrono::{DateTime, Duration, Utc};
+use lib::sha256::Hash;
+use lib::test_utils::{ChainBuilder, instant_params};
+use lib::types::{Blockchain, PartiallySignedTransaction, Transaction};
+use std::collections::{HashMap, HashSet};
+
+const SEED: u64 = 705;
+const KEYS: usize = 4;
+const STEPS: usize = 80;
+const MAX_AGE: u64 = 300;
+
+// xorshift64*, deterministic for a seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+}
+
+// how a transaction left the mempool, counted to show the run
+// covered each
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Left {
+    Replaced,
+    Confirmed,
+    Conflicted,
+    Expired,
+    Aged,
+}
+
+struct Run {
+    builder: ChainBuilder,
+    rng: Rng,
+    now: DateTime<Utc>,
+    // every transaction the mempool took, by txid
+    seen: HashMap<Hash, Transaction>,
+    left: HashMap<Left, usize>,
+}
+
+impl Run {
+    fn new() -> Self {
+        let params = lib::params::NetworkParams {
+            max_mempool_transaction_age: MAX_AGE,
+            ..instant_params()
+        };
+        let mut builder = ChainBuilder::with_params(SEED, params).with_keys(KEYS);
+        for key in 0..KEYS {
+            builder.fund(key, &[100_000; 12]).unwrap();
+        }
+        Run {
+            builder,
+            rng: Rng(SEED),
+            now: Utc::now(),
+            seen: HashMap::new(),
+            left: HashMap::new(),
+        }
+    }
+
+    fn chain(&self) -> &Blockchain {
+        self.builder.chain()
+    }
+
+    // the spent outputs paid to a random key, less a fee, sometimes
+    // expiring after the next block or the one after
+    fn sign(&mut self, spent: &[Hash], fee: u64) -> Transaction {
+        let prev_outputs: Vec<_> = spent
+            .iter()
+            .map(|hash| (*hash, self.chain().utxo(hash).unwrap().clone()))
+            .collect();
+        let value: u64 = prev_outputs.iter().map(|(_, output)| output.value).sum();
+        let key = self.rng.below(KEYS);
+        let output = self.builder.output(value - fee, key);
+        let mut unsigned = PartiallySignedTransaction::new(prev_outputs, vec![output]);
+        if self.rng.below(4) == 0 {
+            let expiry = self.chain().chain_height() + 1 + self.rng.below(2) as u64;
+            unsigned.expires_at_height = Some(expiry);
+        }
+        for key in self.builder.keys() {
+            unsigned.sign(key).unwrap();
+        }
+        unsigned.finalize().unwrap()
+    }
+
+    fn add(&mut self, transaction: Transaction) {
+        let before = self.txids();
+        let txid = transaction.hash();
+        self.builder
+            .chain_mut()
+            .add_to_mempool_at(self.now, transaction.clone())
+            .unwrap();
+        self.seen.insert(txid, transaction);
+        let replaced = before.difference(&self.txids()).count();
+        *self.left.entry(Left::Replaced).or_default() += replaced;
+    }
+
+    fn txids(&self) -> HashSet<Hash> {
+        self.chain()
+            .mempool()
+            .iter()
+            .map(|(_, transaction)| transaction.hash())
+            .collect()
+    }
+
+    // a spend of unreserved outputs of one key
+    fn spend(&mut self) {
+        let key = self.rng.below(KEYS);
+        let spendable = self.builder.spendable(key);
+        if spendable.is_empty() {
+            return;
+        }
+        let count = (1 + self.rng.below(2)).min(spendable.len());
+        let spent: Vec<Hash> = spendable[..count].iter().map(|(hash, _)| *hash).collect();
+        let fee = 10 + self.rng.below(100) as u64;
+        let transaction = self.sign(&spent, fee);
+        self.add(transaction);
+    }
+
+    // a spend of an output an entry already spends, replacing it
+    fn replace(&mut self) {
+        let entries = self.chain().mempool().len();
+        if entries == 0 {
+            return;
+        }
+        let index = self.rng.below(entries);
+        let (_, entry) = self.chain().mempool()[index].clone();
+        let spent = entry.inputs[self.rng.below(entry.inputs.len())].prev_transaction_output_hash;
+        let fee = 200 + self.rng.below(100) as u64;
+        let transaction = self.sign(&[spent], fee);
+        self.add(transaction);
+    }
+
+    // a block of some of the best entries, and sometimes a spend of an
+    // output another entry spends
+    fn mine(&mut self) {
+        let before = self.txids();
+        let take = self.rng.below(4);
+        let mut transactions: Vec<Transaction> = self
+            .chain()
+            .mempool_by_priority()
+            .take(take)
+            .map(|(_, transaction)| transaction.clone())
+            .collect();
+        let mined: HashSet<Hash> = transactions.iter().map(Transaction::hash).collect();
+        let rest: Vec<Transaction> = self
+            .chain()
+            .mempool()
+            .iter()
+            .map(|(_, transaction)| transaction.clone())
+            .filter(|transaction| !mined.contains(&transaction.hash()))
+            .collect();
+        if !rest.is_empty() && self.rng.below(2) == 0 {
+            let other = &rest[self.rng.below(rest.len())];
+            let spent = other.inputs[0].prev_transaction_output_hash;
+            let value = self.chain().utxo(&spent).unwrap().value;
+            let output = self.builder.output(value - 50, 0);
+            transactions.push(self.builder.sign(&[spent], vec![output]).unwrap());
+        }
+        let height = self.chain().chain_height();
+        self.builder
+            .mine_block(|template| template.transactions = transactions)
+            .unwrap();
+        for txid in before.difference(&self.txids()) {
+            let left = if mined.contains(txid) {
+                Left::Confirmed
+            } else if self.seen[txid].is_expired_at(height + 1) {
+                Left::Expired
+            } else {
+                Left::Conflicted
+            };
+            *self.left.entry(left).or_default() += 1;
+        }
+    }
+
+    fn cleanup(&mut self) {
+        let before = self.txids();
+        self.builder.chain_mut().cleanup_mempool_at(self.now);
+        *self.left.entry(Left::Aged).or_default() += before.difference(&self.txids()).count();
+    }
+
+    // each entry through every index, and what left through none
+    fn check(&self) {
+        let chain = self.chain();
+        let mut spent = HashSet::new();
+        for (_, transaction) in chain.mempool() {
+            let txid = transaction.hash();
+            let (_, found) = chain.mempool_get(&txid).unwrap();
+            assert_eq!(found, transaction);
+            assert_eq!(chain.find_transaction(&txid), Some(transaction));
+            assert_eq!(chain.confirmations(&txid), Some(0));
+            for input in &transaction.inputs {
+                let hash = input.prev_transaction_output_hash;
+                assert!(spent.insert(hash), "two entries spend {hash}");
+                assert_eq!(chain.is_utxo_reserved(&hash), Some(true));
+            }
+            for output in &transaction.outputs {
+                let parent = chain.mempool_parent(&output.hash()).unwrap();
+                assert_eq!(parent.hash(), txid);
+            }
+        }
+        // the reserved outputs are those the entries spend
+        let reserved: HashSet<Hash> = chain
+            .utxos()
+            .iter()
+            .filter(|(_, _, reserved)| *reserved)
+            .map(|(hash, _, _)| *hash)
+            .collect();
+        assert_eq!(reserved, spent);
+        let txids = self.txids();
+        assert_eq!(txids.len(), chain.mempool().len());
+        assert_eq!(chain.mempool_by_priority().count(), txids.len());
+        assert_eq!(chain.mempool_snapshot().entries.len(), txids.len());
+        for (txid, transaction) in &self.seen {
+            if txids.contains(txid) {
+                continue;
+            }
+            assert!(chain.mempool_get(txid).is_none());
+            assert_ne!(chain.confirmations(txid), Some(0));
+            for output in &transaction.outputs {
+                assert!(chain.mempool_parent(&output.hash()).is_none());
+            }
+        }
+    }
+}
+
+#[test]
+fn indexes_follow_the_entries() {
+    let mut run = Run::new();
+    for _ in 0..STEPS {
+        run.now += Duration::seconds(run.rng.below(120) as i64);
+        match run.rng.below(10) {
+            0..=4 => run.spend(),
+            5 | 6 => run.replace(),
+            7 | 8 => run.mine(),
+            _ => run.cleanup(),
+        }
+        run.check();
+    }
+    // the seed left the mempool every way
+    for left in [
+        Left::Replaced,
+        Left::Confirmed,
+        Left::Conflicted,
+        Left::Expired,
+        Left::Aged,
+    ] {
+        assert!(
+            run.left.get(&left).copied().unwrap_or(0) > 0,
+            "{left:?}: {:?}",
+            run.left
+        );
+    }
+}
@@ -0,0 +1,161 @@
+// a BlockStore opened over a memory map of its blocks.dat: blocks
+// read in any order are those a sequential read of the file gives,
+// and reads that would run past the map, or a file cut short, error
+// or drop the torn record rather than reading beyond it
+use lib::test_utils::ChainBuilder;
+use lib::types::{
+    Block, BlockStore, Blockchain, ChainBatch, ChainStore, ChainTip, read_block_record,
+};
+use std::fs::{self, File, OpenOptions};
+use std::io::BufReader;
+use std::path::Path;
+use tempfile::TempDir;
+
+const BLOCKS: u64 = 150;
+
+// a chain with spends, and its blocks committed to a store in dir
+fn stored(dir: &Path) -> Blockchain {
+    let mut builder = ChainBuilder::new(678);
+    builder.mine_blocks(2).unwrap();
+    while builder.chain().block_height() < BLOCKS {
+        let to = 1 + builder.chain().block_height() as usize % 3;
+        let spend = builder.spend(0, to, 500, 10).unwrap();
+        builder
+            .mine_block(|template| template.transactions = vec![spend])
+            .unwrap();
+    }
+    let chain = builder.into_chain();
+    let mut store = BlockStore::open(dir).unwrap();
+    commit(&mut store, &chain, 0);
+    store.flush().unwrap();
+    chain
+}
+
+fn commit(store: &mut BlockStore, chain: &Blockchain, from: usize) {
+    for (height, block) in chain.blocks().enumerate().skip(from) {
+        let tip = ChainTip {
+            network: chain.network(),
+            height: height as u64 + 1,
+            hash: block.hash(),
+            target: chain.target(),
+        };
+        store
+            .commit(ChainBatch::connect(block.clone(), tip))
+            .unwrap();
+    }
+}
+
+fn sequential(dir: &Path) -> Vec<Block> {
+    let mut reader = BufReader::new(File::open(dir.join("blocks.dat")).unwrap());
+    let mut blocks = vec![];
+    while let Some(block) = read_block_record(&mut reader).unwrap() {
+        blocks.push(block);
+    }
+    blocks
+}
+
+#[test]
+fn random_access_matches_a_sequential_read() {
+    let dir = TempDir::new().unwrap();
+    let chain = stored(dir.path());
+    let blocks = sequential(dir.path());
+    assert_eq!(blocks.len() as u64, BLOCKS);
+    let store = BlockStore::open_mmap(dir.path()).unwrap();
+    // every height once, in a scattered order
+    for i in 0..BLOCKS {
+        let height = i * 37 % BLOCKS;
+        let block = &blocks[height as usize];
+        assert_eq!(
+            store.get_block_by_height(height).unwrap().as_ref(),
+            Some(block)
+        );
+        let offset = store.block_offset(height).unwrap();
+        assert_eq!(
+            store.get_block_by_offset(offset).unwrap().as_ref(),
+            Some(block)
+        );
+        assert_eq!(
+            store.get_block_by_hash(&block.hash()).unwrap().as_ref(),
+            Some(block)
+        );
+    }
+    assert_eq!(store.load().unwrap().0, blocks);
+    let hashes: Vec<_> = blocks.iter().map(Block::hash).collect();
+    let expected: Vec<_> = chain.blocks().map(Block::hash).collect();
+    assert_eq!(hashes, expected);
+}
+
+#[test]
+fn blocks_appended_past_the_map_are_read_from_the_file() {
+    let dir = TempDir::new().unwrap();
+    let chain = stored(dir.path());
+    let kept = BLOCKS as usize - 10;
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    let cut = store.block_offset(kept as u64).unwrap();
+    drop(store);
+    OpenOptions::new()
+        .write(true)
+        .open(dir.path().join("blocks.dat"))
+        .unwrap()
+        .set_len(cut)
+        .unwrap();
+    store = BlockStore::open_mmap(dir.path()).unwrap();
+    commit(&mut store, &chain, kept);
+    let blocks: Vec<Block> = chain.blocks().cloned().collect();
+    for height in [0, kept as u64 - 1, kept as u64, BLOCKS - 1] {
+        assert_eq!(
+            store.get_block_by_height(height).unwrap().as_ref(),
+            Some(&blocks[height as usize])
+        );
+    }
+    // and from the map again once it's remapped
+    store.flush().unwrap();
+    assert_eq!(store.load().unwrap().0, blocks);
+}
+
+#[test]
+fn reads_past_the_map_error() {
+    let dir = TempDir::new().unwrap();
+    stored(dir.path());
+    let store = BlockStore::open_mmap(dir.path()).unwrap();
+    let end = fs::metadata(dir.path().join("blocks.dat")).unwrap().len();
+    let last = store.block_offset(BLOCKS - 1).unwrap();
+    // offsets into the map that aren't the start of a record, whose
+    // length would take the read past the end of the map
+    for offset in [end - 1, end - 5, end - 20, last + 1] {
+        assert!(
+            store.get_block_by_offset(offset).is_err(),
+            "read a block at {offset}"
+        );
+    }
+    assert!(store.get_block_by_offset(end).unwrap().is_none());
+    assert!(store.get_block_by_height(BLOCKS).unwrap().is_none());
+}
+
+#[test]
+fn file_cut_short_drops_the_torn_record() {
+    let dir = TempDir::new().unwrap();
+    stored(dir.path());
+    let blocks = sequential(dir.path());
+    let last = BLOCKS - 1;
+    let torn = BlockStore::open(dir.path())
+        .unwrap()
+        .block_offset(last)
+        .unwrap();
+    let path = dir.path().join("blocks.dat");
+    let end = fs::metadata(&path).unwrap().len();
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(end - 100)
+        .unwrap();
+    let store = BlockStore::open_mmap(dir.path()).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().len(), torn);
+    assert!(store.get_block_by_height(last).unwrap().is_none());
+    assert_eq!(
+        store.get_block_by_height(last - 1).unwrap().as_ref(),
+        Some(&blocks[last as usize - 1])
+    );
+    assert_eq!(store.load().unwrap().0, blocks[..last as usize]);
+}
@@ -0,0 +1,88 @@
+// framing of network messages: magic, payload length, checksum
+use lib::network::Message;
+use lib::params::NetworkParams;
+use std::io::ErrorKind;
+
+fn magic() -> [u8; 4] {
+    NetworkParams::regtest().magic
+}
+
+fn frame() -> Vec<u8> {
+    Message::FetchBlock(42).frame(magic()).unwrap()
+}
+
+// the reason read_from refuses the frame with
+fn refusal(frame: &[u8]) -> (ErrorKind, String) {
+    match Message::read_from(magic(), &mut &frame[..]) {
+        Ok(message) => panic!("read {message:?} from a bad frame"),
+        Err(ciborium::de::Error::Io(e)) => (e.kind(), e.to_string()),
+        Err(e) => panic!("not refused before decoding: {e}"),
+    }
+}
+
+#[test]
+fn frame_reads_back() {
+    let message = Message::read_from(magic(), &mut &frame()[..]).unwrap();
+    assert!(matches!(message, Message::FetchBlock(42)));
+}
+
+#[test]
+fn flipped_checksum_byte_is_refused() {
+    for byte in 12..16 {
+        let mut frame = frame();
+        frame[byte] ^= 1;
+        assert_eq!(
+            refusal(&frame),
+            (ErrorKind::InvalidData, "message checksum mismatch".into())
+        );
+    }
+}
+
+#[test]
+fn flipped_payload_byte_is_refused() {
+    let mut frame = frame();
+    *frame.last_mut().unwrap() ^= 1;
+    assert_eq!(
+        refusal(&frame),
+        (ErrorKind::InvalidData, "message checksum mismatch".into())
+    );
+}
+
+#[test]
+fn wrong_length_is_refused() {
+    let set_length = |frame: &mut Vec<u8>, length: u64| {
+        frame[4..12].copy_from_slice(&length.to_be_bytes());
+    };
+    let payload = (frame().len() - 16) as u64;
+    // shorter, the checksum is of fewer bytes
+    let mut short = frame();
+    set_length(&mut short, payload - 1);
+    assert_eq!(refusal(&short).0, ErrorKind::InvalidData);
+    // longer, the stream ends first
+    let mut long = frame();
+    set_length(&mut long, payload + 1);
+    assert_eq!(refusal(&long).0, ErrorKind::UnexpectedEof);
+    // past the limit, refused before anything is allocated for it
+    let mut oversize = frame();
+    set_length(&mut oversize, lib::MAX_MESSAGE_SIZE + 1);
+    assert_eq!(
+        refusal(&oversize),
+        (
+            ErrorKind::InvalidData,
+            "message exceeds maximum size".into()
+        )
+    );
+    set_length(&mut oversize, u64::MAX);
+    assert_eq!(refusal(&oversize).0, ErrorKind::InvalidData);
+}
+
+#[test]
+fn wrong_magic_is_refused() {
+    let frame = Message::FetchBlock(42)
+        .frame(NetworkParams::mainnet().magic)
+        .unwrap();
+    assert_eq!(
+        refusal(&frame),
+        (ErrorKind::InvalidData, "network magic mismatch".into())
+    );
+}
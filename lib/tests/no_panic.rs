@@ -0,0 +1,335 @@
+// arbitrary blocks and transactions offered to a live chain, made up
+// field by field with the edge values and the hashes of real outputs,
+// and decoded from mutated hex: add_block and add_to_mempool turn
+// every one away with an Err, never a panic, and leave the chain as
+// it was
+use chrono::{DateTime, Duration, Utc};
+use lib::U256;
+use lib::crypto::{PrivateKey, Signature};
+use lib::sha256::Hash;
+use lib::test_utils::{ChainBuilder, seeded_key};
+use lib::types::{Block, Blockchain, Transaction, TransactionInput, TransactionOutput};
+use lib::utils::MerkleRoot;
+use uuid::Uuid;
+
+const SEED: u64 = 684;
+const CASES: u64 = 300;
+const KEYS: usize = 4;
+
+// xorshift64*, so every run makes up the same values
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+
+    fn pick<T: Copy>(&mut self, values: &[T]) -> T {
+        values[self.below(values.len())]
+    }
+}
+
+// a chain with coinbases and spends to refer to, and a spend waiting
+// in the mempool
+fn builder() -> ChainBuilder {
+    let mut builder = ChainBuilder::new(SEED).with_keys(KEYS);
+    builder.fund(1, &[1000, 2000, 3000]).unwrap();
+    builder.mine_blocks(2).unwrap();
+    let spend = builder.spend(1, 2, 1500, 10).unwrap();
+    builder
+        .mine_block(|template| template.transactions = vec![spend])
+        .unwrap();
+    let waiting = builder.spend(0, 3, 700, 10).unwrap();
+    builder.chain_mut().add_to_mempool(waiting).unwrap();
+    builder
+}
+
+// what no rejected block or transaction may change
+fn state(chain: &Blockchain) -> (Hash, u64, Hash, usize, usize) {
+    (
+        chain.tip_hash(),
+        chain.block_height(),
+        chain.utxo_commitment(),
+        chain.utxo_count(),
+        chain.mempool().len(),
+    )
+}
+
+struct Arbitrary<'a> {
+    rng: Rng,
+    builder: &'a ChainBuilder,
+    // the outputs of the chain, spent or not
+    outputs: Vec<Hash>,
+}
+
+impl Arbitrary<'_> {
+    fn value(&mut self) -> u64 {
+        match self.rng.below(6) {
+            0 => 0,
+            1 => 1,
+            2 => u64::MAX,
+            3 => u64::MAX / 2 + 1,
+            4 => self.builder.chain().calculate_block_reward(),
+            _ => self.rng.next() >> self.rng.below(64),
+        }
+    }
+
+    fn height(&mut self) -> u64 {
+        let height = self.builder.chain().block_height();
+        match self.rng.below(5) {
+            0 => 0,
+            1 => u64::MAX,
+            2 => height,
+            3 => height + 1,
+            _ => self.rng.next(),
+        }
+    }
+
+    fn hash(&mut self) -> Hash {
+        match self.rng.below(4) {
+            0 => Hash::zero(),
+            1 => Hash::hash_bytes(&self.rng.next().to_le_bytes()),
+            _ => self.outputs[self.rng.below(self.outputs.len())],
+        }
+    }
+
+    fn key(&mut self) -> PrivateKey {
+        seeded_key(SEED, self.rng.below(KEYS))
+    }
+
+    // none of them sign the input they're on
+    fn signature(&mut self) -> Signature {
+        match self.rng.below(3) {
+            0 => Signature::placeholder(),
+            1 => Signature::widest(),
+            _ => {
+                let sighash = self.hash();
+                Signature::sign_input(&sighash, &self.key())
+            }
+        }
+    }
+
+    fn output(&mut self) -> TransactionOutput {
+        TransactionOutput {
+            value: self.value(),
+            unique_id: Uuid::from_u64_pair(self.rng.next(), self.rng.next()),
+            pubkey: self.key().public_key(),
+            spendable_after_height: match self.rng.below(2) {
+                0 => None,
+                _ => Some(self.height()),
+            },
+        }
+    }
+
+    fn outputs(&mut self) -> Vec<TransactionOutput> {
+        (0..self.rng.below(4)).map(|_| self.output()).collect()
+    }
+
+    fn expiry(&mut self) -> Option<u64> {
+        match self.rng.below(2) {
+            0 => None,
+            _ => Some(self.height()),
+        }
+    }
+
+    // either made up whole, with at least one input none of whose
+    // signatures are valid, or properly signed but paying out more
+    // than its inputs
+    fn transaction(&mut self) -> Transaction {
+        let spendable: Vec<Hash> = (0..KEYS)
+            .flat_map(|key| self.builder.spendable(key))
+            .map(|(hash, _)| hash)
+            .collect();
+        if self.rng.below(3) == 0 && !spendable.is_empty() {
+            let spent = self.rng.pick(&spendable);
+            let value = self.builder.chain().utxo(&spent).unwrap().value;
+            let mut outputs = self.outputs();
+            let mut overspent = self.output();
+            overspent.value = match self.rng.below(2) {
+                0 => value + 1,
+                _ => u64::MAX,
+            };
+            outputs.insert(self.rng.below(outputs.len() + 1), overspent);
+            let mut transaction = self.builder.sign(&[spent], outputs).unwrap();
+            if self.rng.below(2) == 0 {
+                transaction.inputs.push(transaction.inputs[0].clone());
+                transaction.thaw();
+            }
+            return transaction;
+        }
+        let inputs = (0..1 + self.rng.below(3))
+            .map(|_| TransactionInput {
+                prev_transaction_output_hash: self.hash(),
+                signature: self.signature(),
+            })
+            .collect();
+        let mut transaction = Transaction::new(inputs, self.outputs());
+        transaction.expires_at_height = self.expiry();
+        transaction
+    }
+
+    fn coinbase(&mut self) -> Transaction {
+        let mut coinbase = Transaction::new(vec![], self.outputs());
+        coinbase.expires_at_height = self.expiry();
+        coinbase
+    }
+
+    fn timestamp(&mut self) -> DateTime<Utc> {
+        let tip = self.builder.timestamp(self.builder.chain().block_height());
+        match self.rng.below(5) {
+            0 => DateTime::<Utc>::MIN_UTC,
+            1 => DateTime::<Utc>::MAX_UTC,
+            2 => DateTime::UNIX_EPOCH,
+            3 => tip - Duration::seconds(self.rng.below(1000) as i64),
+            _ => tip + Duration::seconds(self.rng.below(1 << 20) as i64),
+        }
+    }
+
+    // the coinbase or a made up one, then made up transactions of
+    // which at least one is invalid; the header committing to them
+    // and mined, or made up as well
+    fn block(&mut self) -> Block {
+        let chain = self.builder.chain();
+        let mut transactions = vec![];
+        if self.rng.below(4) > 0 {
+            transactions.push(self.coinbase());
+        }
+        for _ in 0..1 + self.rng.below(3) {
+            transactions.push(self.transaction());
+        }
+        let target = match self.rng.below(4) {
+            0 => U256::zero(),
+            1 => U256::MAX,
+            2 => U256([self.rng.next(), 0, 0, self.rng.next()]),
+            _ => chain.target(),
+        };
+        let prev_hash = match self.rng.below(4) {
+            0 => self.hash(),
+            _ => chain.tip_hash(),
+        };
+        let mut block = Block::builder()
+            .version(self.rng.pick(&[0, 1, chain.block_version(), u32::MAX]))
+            .timestamp(self.timestamp())
+            .nonce(self.rng.next())
+            .prev_hash(prev_hash)
+            .target(target)
+            .transactions(transactions)
+            .build()
+            .unwrap();
+        match self.rng.below(3) {
+            // only the easy target is sure to be mined
+            0 if target == chain.target() => self.builder.reseal(&mut block),
+            1 => block.header.merkle_root = MerkleRoot::calculate(&[self.transaction()]),
+            _ => {}
+        }
+        block
+    }
+}
+
+// every output the chain ever made
+fn outputs(chain: &Blockchain) -> Vec<Hash> {
+    chain
+        .blocks()
+        .flat_map(|block| &block.transactions)
+        .flat_map(|transaction| &transaction.outputs)
+        .map(TransactionOutput::hash)
+        .collect()
+}
+
+#[test]
+fn made_up_transactions_are_refused() {
+    let mut builder = builder();
+    let outputs = outputs(builder.chain());
+    let mut made_up = vec![];
+    let mut arbitrary = Arbitrary {
+        rng: Rng(SEED),
+        builder: &builder,
+        outputs,
+    };
+    for _ in 0..CASES {
+        made_up.push(arbitrary.transaction());
+        made_up.push(arbitrary.coinbase());
+    }
+    let before = state(builder.chain());
+    for (case, transaction) in made_up.into_iter().enumerate() {
+        let txid = transaction.hash();
+        let result = builder.chain_mut().add_to_mempool(transaction);
+        assert!(result.is_err(), "case {case}: accepted {txid}");
+        assert_eq!(state(builder.chain()), before, "case {case}");
+    }
+}
+
+#[test]
+fn made_up_blocks_are_refused() {
+    let mut builder = builder();
+    let outputs = outputs(builder.chain());
+    let mut made_up = vec![];
+    let mut arbitrary = Arbitrary {
+        rng: Rng(SEED + 1),
+        builder: &builder,
+        outputs,
+    };
+    for _ in 0..CASES {
+        made_up.push(arbitrary.block());
+    }
+    let before = state(builder.chain());
+    for (case, block) in made_up.into_iter().enumerate() {
+        let hash = block.hash();
+        let result = builder.chain_mut().add_block(block);
+        assert!(result.is_err(), "case {case}: accepted {hash}");
+        assert_eq!(state(builder.chain()), before, "case {case}");
+    }
+    builder.chain().verify().unwrap();
+}
+
+// a flipped bit, a random byte, a cut or an insertion
+fn mutate(rng: &mut Rng, data: &[u8]) -> Vec<u8> {
+    let mut data = data.to_vec();
+    for _ in 0..1 + rng.below(4) {
+        let at = rng.below(data.len());
+        match rng.below(4) {
+            0 if !data.is_empty() => data[at] ^= 1 << rng.below(8),
+            1 if !data.is_empty() => data[at] = rng.next() as u8,
+            2 => data.truncate(at),
+            _ => {
+                let bytes: Vec<u8> = (0..1 + rng.below(8)).map(|_| rng.next() as u8).collect();
+                data.splice(at..at, bytes);
+            }
+        }
+    }
+    data
+}
+
+// mutations of the next block and of a spend that decode; a changed
+// nonce can leave a block as valid as it was, so those are only held
+// to not panicking and to leaving a chain that verifies
+#[test]
+fn decoded_mutations_never_panic() {
+    let mut builder = builder();
+    let block = builder.block(|_| {}).unwrap();
+    let spend = builder.spend(1, 3, 100, 10).unwrap();
+    let mut rng = Rng(SEED + 2);
+    let (mut blocks, mut transactions) = (0, 0);
+    for _ in 0..CASES * 4 {
+        let data = mutate(&mut rng, &hex::decode(block.to_hex()).unwrap());
+        if let Ok(block) = Block::from_hex(&hex::encode(data)) {
+            blocks += 1;
+            let _ = builder.chain_mut().add_block(block);
+        }
+        let data = mutate(&mut rng, &hex::decode(spend.to_hex()).unwrap());
+        if let Ok(transaction) = Transaction::from_hex(&hex::encode(data)) {
+            transactions += 1;
+            let _ = builder.chain_mut().add_to_mempool(transaction);
+        }
+    }
+    // enough of them got as far as the chain
+    assert!(blocks > 10 && transactions > 10, "{blocks} {transactions}");
+    builder.chain().verify().unwrap();
+}
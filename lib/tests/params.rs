@@ -0,0 +1,68 @@
+// the rules of NetworkParams as chains enforce them
+use chrono::Duration;
+use lib::UNITS_PER_COIN;
+use lib::error::SbdError;
+use lib::params::NetworkParams;
+use lib::test_utils::{ChainBuilder, instant_params};
+use lib::types::Block;
+
+fn coinbase_value(block: &Block) -> u64 {
+    block.transactions[0]
+        .outputs
+        .iter()
+        .map(|output| output.value)
+        .sum()
+}
+
+// the next block of the builder's chain with a coinbase of value,
+// stamped seconds after the one before
+fn paying(builder: &mut ChainBuilder, value: u64, seconds: i64) -> Block {
+    let timestamp = match builder.chain().blocks().last() {
+        Some(tip) => tip.header.timestamp + Duration::seconds(seconds),
+        None => builder.timestamp(0),
+    };
+    let mut block = builder
+        .block(|template| template.timestamp = timestamp)
+        .unwrap();
+    block.transactions[0].outputs[0].value = value;
+    builder.reseal(&mut block);
+    block
+}
+
+#[test]
+fn reward_halves_at_halving_interval() {
+    let params = NetworkParams {
+        halving_interval: 10,
+        ..instant_params()
+    };
+    let initial = params.initial_reward * UNITS_PER_COIN;
+    let mut builder = ChainBuilder::with_params(10, params);
+    builder.mine_blocks(10).unwrap();
+    assert!(
+        builder
+            .chain()
+            .blocks()
+            .all(|block| coinbase_value(block) == initial)
+    );
+    // the eleventh block, at height 10, may claim only half
+    let full = paying(&mut builder, initial, 10);
+    assert!(matches!(
+        builder.chain_mut().add_block(full),
+        Err(SbdError::InvalidTransaction)
+    ));
+    let halved = paying(&mut builder, initial / 2, 10);
+    builder.chain_mut().add_block(halved).unwrap();
+    assert_eq!(builder.chain().block_height(), 11);
+}
+
+#[test]
+fn regtest_target_never_adjusts() {
+    let mut builder = ChainBuilder::with_params(1, NetworkParams::regtest());
+    // a second apart, which would make mainnet's target harder
+    for _ in 0..2 * lib::DIFFICULTY_UPDATE_INTERVAL {
+        let reward = builder.chain().calculate_block_reward();
+        let block = paying(&mut builder, reward, 1);
+        builder.chain_mut().add_block(block).unwrap();
+    }
+    assert_eq!(builder.chain().target(), lib::test_utils::INSTANT_TARGET);
+}
@@ -0,0 +1,150 @@
+// block_print and tx_print on the compat fixtures: the text pinned
+// with the inputs looked up in the compat chain, the fields of the
+// json, and fees left unknown without a chain
+use assert_cmd::Command;
+use serde_json::{Value, json};
+
+const BLOCK_TEXT: &str = "\
+hash          44d09bba98495a920e42f4e549d8b45775fa18b9f87a2c36cf77c342a32be9e9
+previous      2a4e79403fd3145fac98c55c7e8518c59febf1003d89457fd455ddc41a59788a
+merkle root   d6f90902b95fa4e40b6341d508238ece639c9103044ea9b3f99e2727c8e6a386
+timestamp     2024-01-01T00:00:20Z
+nonce         0
+target        7fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff
+size          1063 bytes
+fees          0.00001000
+transactions  2
+  8c24a33c522913e7da0ca0f465f952d81148e2400812621f5caba43f024a5d41    0 in    1 out           50.00001000  coinbase
+  f769e2adeca7c699a1be86e803dff4f261e3692344a908665d153128fe5cb303    1 in    2 out           49.99999000  fee 0.00001000
+";
+
+const TX_TEXT: &str = "\
+txid          f769e2adeca7c699a1be86e803dff4f261e3692344a908665d153128fe5cb303
+size          629 bytes
+inputs        1
+  f05ffcc3d8e6ce60d5fc3196af76b8157ec0c8dbe74c8f2f15c478b9152656f2           50.00000000  031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f
+outputs       2
+  69d87fec128198da76ae7c37e682f0fd3c006cc93e01b33c98cd19170e7f432c           25.00000000  024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766
+  bc833e68d01de685de20cc2064da49108955c9a7597bad960287eff617bdaa64           24.99999000  031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f
+output value  49.99999000
+fee           0.00001000
+";
+
+fn print(name: &str, file: &str, args: &[&str]) -> String {
+    let output = Command::cargo_bin(name)
+        .unwrap()
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .arg(format!("compat/{file}"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn chain() -> [&'static str; 2] {
+    ["--chain", "compat/blockchain.cbor"]
+}
+
+#[test]
+fn block_text_is_pinned() {
+    assert_eq!(print("block_print", "block.cbor", &chain()), BLOCK_TEXT);
+    // without the chain the fee of the spend is unknown
+    let text = print("block_print", "block.cbor", &[]);
+    assert!(text.contains("fees          unknown, --chain looks up the inputs\n"));
+    assert!(text.contains("49.99999000  fee unknown\n"));
+}
+
+#[test]
+fn tx_text_is_pinned() {
+    assert_eq!(print("tx_print", "transaction.cbor", &chain()), TX_TEXT);
+    let text = print("tx_print", "transaction.cbor", &[]);
+    assert!(text.contains(
+        "f05ffcc3d8e6ce60d5fc3196af76b8157ec0c8dbe74c8f2f15c478b9152656f2               unknown\n"
+    ));
+    assert!(text.ends_with("fee           unknown, --chain looks up the inputs\n"));
+}
+
+#[test]
+fn block_json_has_the_key_fields() {
+    let block: Value = serde_json::from_str(&print(
+        "block_print",
+        "block.cbor",
+        &[&chain()[..], &["--json"]].concat(),
+    ))
+    .unwrap();
+    assert_eq!(
+        block["hash"],
+        "44d09bba98495a920e42f4e549d8b45775fa18b9f87a2c36cf77c342a32be9e9"
+    );
+    assert_eq!(
+        block["prev_hash"],
+        "2a4e79403fd3145fac98c55c7e8518c59febf1003d89457fd455ddc41a59788a"
+    );
+    assert_eq!(block["size"], 1063);
+    assert_eq!(block["fees"], 1000);
+    let transactions = block["transactions"].as_array().unwrap();
+    assert_eq!(transactions.len(), 2);
+    assert_eq!(transactions[0]["coinbase"], true);
+    assert_eq!(transactions[0]["fee"], Value::Null);
+    assert_eq!(transactions[1]["fee"], 1000);
+    assert_eq!(transactions[1]["output_value"], 4_999_999_000u64);
+    // without the chain, no fees
+    let block: Value =
+        serde_json::from_str(&print("block_print", "block.cbor", &["--json"])).unwrap();
+    assert_eq!(block["fees"], Value::Null);
+}
+
+#[test]
+fn tx_json_has_the_key_fields() {
+    let tx: Value = serde_json::from_str(&print(
+        "tx_print",
+        "transaction.cbor",
+        &[&chain()[..], &["--json"]].concat(),
+    ))
+    .unwrap();
+    assert_eq!(
+        tx["txid"],
+        "f769e2adeca7c699a1be86e803dff4f261e3692344a908665d153128fe5cb303"
+    );
+    assert_eq!(tx["coinbase"], false);
+    assert_eq!(
+        tx["inputs"],
+        json!([{
+            "output_hash": "f05ffcc3d8e6ce60d5fc3196af76b8157ec0c8dbe74c8f2f15c478b9152656f2",
+            "value": 5_000_000_000u64,
+            "address": "031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f",
+        }])
+    );
+    let values: Vec<&Value> = tx["outputs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|output| &output["value"])
+        .collect();
+    assert_eq!(values, [2_500_000_000u64, 2_499_999_000]);
+    assert_eq!(tx["input_value"], 5_000_000_000u64);
+    assert_eq!(tx["fee"], 1000);
+}
+
+#[test]
+fn each_refuses_the_other_kind_of_file() {
+    Command::cargo_bin("tx_print")
+        .unwrap()
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .arg("compat/block.cbor")
+        .assert()
+        .code(1)
+        .stderr("compat/block.cbor holds a block, not a transaction, see block_print\n");
+    let output = Command::cargo_bin("block_print")
+        .unwrap()
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .arg("compat/transaction.cbor")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("tx_print"),
+        "{output:?}"
+    );
+}
@@ -0,0 +1,84 @@
+// raw hex of transactions and blocks: a signed spend round trips, the
+// compat hex decodes to its pinned txid, and anything but exactly one
+// value's hex is refused
+use lib::error::SbdError;
+use lib::test_utils::ChainBuilder;
+use lib::types::{Block, Transaction};
+
+const TRANSACTION_HASH: &str = "f769e2adeca7c699a1be86e803dff4f261e3692344a908665d153128fe5cb303";
+const BLOCK_HASH: &str = "44d09bba98495a920e42f4e549d8b45775fa18b9f87a2c36cf77c342a32be9e9";
+
+fn fixture(name: &str) -> String {
+    let path = format!("{}/compat/{name}", env!("CARGO_MANIFEST_DIR"));
+    std::fs::read_to_string(path).unwrap().trim().to_string()
+}
+
+#[test]
+fn signed_spend_round_trips() {
+    let mut builder = ChainBuilder::new(682);
+    builder.fund(0, &[1000, 2000]).unwrap();
+    let spend = builder.spend(0, 1, 2500, 100).unwrap();
+    let hex = spend.to_hex();
+    let decoded = Transaction::from_hex(&hex).unwrap();
+    assert_eq!(decoded, spend);
+    assert_eq!(decoded.hash(), spend.hash());
+    assert_eq!(decoded.to_hex(), hex);
+    // and still spends what it did
+    builder.chain_mut().add_to_mempool(decoded).unwrap();
+    let block = builder.chain().blocks().last().unwrap().clone();
+    assert_eq!(Block::from_hex(&block.to_hex()).unwrap(), block);
+}
+
+#[test]
+fn compat_hex_decodes_to_the_pinned_txid() {
+    let transaction = Transaction::from_hex(&fixture("transaction.hex")).unwrap();
+    assert_eq!(transaction.hash().to_hex(), TRANSACTION_HASH);
+    let block = Block::from_hex(&fixture("block.hex")).unwrap();
+    assert_eq!(block.hash().to_hex(), BLOCK_HASH);
+    assert_eq!(block.transactions[1], transaction);
+    // surrounding whitespace is no part of the value
+    let padded = format!("  {}\n", fixture("transaction.hex"));
+    assert_eq!(Transaction::from_hex(&padded).unwrap(), transaction);
+}
+
+#[test]
+fn trailing_bytes_are_refused() {
+    let hex = fixture("transaction.hex");
+    for extra in ["00", "deadbeef", &hex] {
+        let e = Transaction::from_hex(&format!("{hex}{extra}")).unwrap_err();
+        assert!(
+            matches!(
+                e,
+                SbdError::InvalidEncoding {
+                    kind: "transaction",
+                    ..
+                }
+            ),
+            "{extra}: {e}"
+        );
+        assert!(e.to_string().contains("trailing bytes"), "{e}");
+    }
+    let block = fixture("block.hex");
+    let e = Block::from_hex(&format!("{block}00")).unwrap_err();
+    assert!(
+        matches!(e, SbdError::InvalidEncoding { kind: "block", .. }),
+        "{e}"
+    );
+}
+
+#[test]
+fn malformed_hex_is_refused() {
+    let hex = fixture("transaction.hex");
+    // odd length, not hex, and empty
+    for bad in [&hex[1..], &format!("{}zz", &hex[2..]), ""] {
+        assert!(Transaction::from_hex(bad).is_err(), "{bad}");
+    }
+    assert!(matches!(
+        Transaction::from_hex(&hex[1..]),
+        Err(SbdError::InvalidHex(_))
+    ));
+    // cut short, the value itself doesn't decode
+    assert!(Transaction::from_hex(&hex[..hex.len() - 2]).is_err());
+    // nor does a block's hex as a transaction
+    assert!(Transaction::from_hex(&fixture("block.hex")).is_err());
+}
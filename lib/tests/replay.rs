@@ -0,0 +1,35 @@
+// replaying stored chains through add_block
+use chrono::Duration;
+use lib::replay::{Divergence, replay};
+use lib::test_utils::ChainBuilder;
+
+#[test]
+fn clean_chain_replays_clean() {
+    let mut builder = ChainBuilder::new(715);
+    builder.mine_blocks(5).unwrap();
+    let replay = replay(builder.chain(), None);
+    assert!(replay.is_clean());
+    assert_eq!(replay.valid_height, 5);
+}
+
+#[test]
+fn block_off_the_target_schedule_is_rejected() {
+    let mut builder = ChainBuilder::new(715);
+    builder.mine_blocks(3).unwrap();
+    let tip = builder.chain().blocks().last().unwrap().header.timestamp;
+    let mut block = builder
+        .block(|template| template.timestamp = tip + Duration::seconds(10))
+        .unwrap();
+    block.header.target = lib::U256::MAX;
+    builder.reseal(&mut block);
+    let hash = block.hash();
+    builder.chain_mut().add_trusted_block(block).unwrap();
+    builder.mine_blocks(1).unwrap();
+    let replay = replay(builder.chain(), None);
+    assert!(matches!(
+        replay.divergences.as_slice(),
+        [Divergence::Rejected { height: 3, hash: rejected, .. }] if *rejected == hash
+    ));
+    assert_eq!(replay.valid_height, 3);
+    assert_eq!(replay.first_divergent_block, Some(3));
+}
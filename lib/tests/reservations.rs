@@ -0,0 +1,115 @@
+// the single-utxo accessors of Blockchain and its UtxoView agree with
+// each other and with the mempool: an output is reserved while a
+// mempool transaction spends it, released when that one is replaced
+// or evicted, and gone once a block spends it
+use chrono::{Duration, Utc};
+use lib::sha256::Hash;
+use lib::test_utils::ChainBuilder;
+use lib::types::{Blockchain, Transaction};
+use lib::utils::Saveable;
+
+// what every accessor says of the output, checking they agree
+fn reserved(chain: &Blockchain, hash: &Hash) -> Option<bool> {
+    let view = chain.utxos();
+    let reserved = chain.is_utxo_reserved(hash);
+    assert_eq!(view.is_reserved(hash), reserved);
+    assert_eq!(chain.contains_utxo(hash), reserved.is_some());
+    assert_eq!(view.contains(hash), reserved.is_some());
+    assert_eq!(chain.utxo(hash).is_some(), reserved.is_some());
+    assert_eq!(chain.utxo(hash), view.get(hash));
+    let listed = view
+        .iter()
+        .find(|(listed, _, _)| *listed == hash)
+        .map(|(_, _, reserved)| reserved);
+    assert_eq!(listed, reserved);
+    assert_eq!(chain.utxo_count(), view.len());
+    assert_eq!(chain.utxo_count(), view.iter().count());
+    reserved
+}
+
+// key 0 funded with outputs of 1000, 2000 and 3000, their hashes
+// in that order
+fn funded() -> (ChainBuilder, [Hash; 3]) {
+    let mut builder = ChainBuilder::new(693);
+    let outputs = builder.fund(0, &[1000, 2000, 3000]).unwrap();
+    let hashes = [0, 1, 2].map(|i| outputs[i].hash());
+    (builder, hashes)
+}
+
+fn spending(builder: &mut ChainBuilder, spent: &[Hash], value: u64) -> Transaction {
+    let output = builder.output(value, 1);
+    builder.sign(spent, vec![output]).unwrap()
+}
+
+#[test]
+fn spend_reserves_its_inputs_only() {
+    let (mut builder, [small, middle, large]) = funded();
+    for hash in [small, middle, large] {
+        assert_eq!(reserved(builder.chain(), &hash), Some(false));
+    }
+    let spend = spending(&mut builder, &[middle, large], 4900);
+    builder.chain_mut().add_to_mempool(spend).unwrap();
+    let chain = builder.chain();
+    assert_eq!(reserved(chain, &small), Some(false));
+    assert_eq!(reserved(chain, &middle), Some(true));
+    assert_eq!(reserved(chain, &large), Some(true));
+    // unknown outputs are none of it
+    assert_eq!(reserved(chain, &Hash::zero()), None);
+    // a refused spend reserves nothing
+    let overspend = spending(&mut builder, &[small], 1001);
+    assert!(builder.chain_mut().add_to_mempool(overspend).is_err());
+    assert_eq!(reserved(builder.chain(), &small), Some(false));
+}
+
+#[test]
+fn replacement_releases_what_it_no_longer_spends() {
+    let (mut builder, [small, middle, large]) = funded();
+    let spend = spending(&mut builder, &[middle, large], 4900);
+    builder.chain_mut().add_to_mempool(spend).unwrap();
+    // spending the large one again, with a higher fee
+    let replacement = spending(&mut builder, &[large], 2500);
+    builder
+        .chain_mut()
+        .add_to_mempool(replacement.clone())
+        .unwrap();
+    let chain = builder.chain();
+    assert_eq!(chain.mempool().len(), 1);
+    assert_eq!(reserved(chain, &small), Some(false));
+    assert_eq!(reserved(chain, &middle), Some(false));
+    assert_eq!(reserved(chain, &large), Some(true));
+    // mined, the input is gone and its outputs are there, free
+    builder
+        .mine_block(|template| template.transactions = vec![replacement.clone()])
+        .unwrap();
+    let chain = builder.chain();
+    assert_eq!(reserved(chain, &large), None);
+    for output in &replacement.outputs {
+        assert_eq!(reserved(chain, &output.hash()), Some(false));
+    }
+    assert_eq!(reserved(chain, &middle), Some(false));
+}
+
+#[test]
+fn eviction_and_reload_release() {
+    let (mut builder, [small, middle, _]) = funded();
+    let max_age = builder.chain().params().max_mempool_transaction_age as i64;
+    let now = Utc::now();
+    let later = now + Duration::seconds(max_age);
+    let spend = spending(&mut builder, &[small], 900);
+    builder.chain_mut().add_to_mempool_at(now, spend).unwrap();
+    let spend = spending(&mut builder, &[middle], 1900);
+    builder.chain_mut().add_to_mempool_at(later, spend).unwrap();
+    // a loaded chain has no mempool, so nothing is reserved
+    let mut saved = vec![];
+    builder.chain().save(&mut saved).unwrap();
+    let loaded = Blockchain::load(&saved[..]).unwrap();
+    assert_eq!(reserved(&loaded, &small), Some(false));
+    assert_eq!(reserved(&loaded, &middle), Some(false));
+    // a second past its age the older spend is evicted, the newer kept
+    let later = later + Duration::seconds(1);
+    builder.chain_mut().cleanup_mempool_at(later);
+    let chain = builder.chain();
+    assert_eq!(chain.mempool().len(), 1);
+    assert_eq!(reserved(chain, &small), Some(false));
+    assert_eq!(reserved(chain, &middle), Some(true));
+}
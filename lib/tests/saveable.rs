@@ -0,0 +1,113 @@
+// Saveable round trips of block headers and mempools, and a saved
+// mempool restored after one of its transactions was mined, which
+// drops that one and keeps the rest
+use lib::error::SbdError;
+use lib::test_utils::ChainBuilder;
+use lib::types::{BlockHeader, Blockchain, MempoolSnapshot, Transaction};
+use lib::utils::Saveable;
+use tempfile::TempDir;
+
+fn reload<T: Saveable>(value: &T) -> T {
+    let mut saved = vec![];
+    value.save(&mut saved).unwrap();
+    T::load(&saved[..]).unwrap()
+}
+
+// the chain as a node opens it again, with no mempool
+fn reopened(chain: &Blockchain) -> Blockchain {
+    let chain = reload(chain);
+    assert!(chain.mempool().is_empty());
+    chain
+}
+
+// three spends of separate coins in the mempool
+fn with_mempool() -> (ChainBuilder, Vec<Transaction>) {
+    let mut builder = ChainBuilder::new(677);
+    builder.fund(0, &[1000, 2000, 3000]).unwrap();
+    let mut spends = vec![];
+    for (to, amount) in [(1, 900), (2, 1900), (3, 2900)] {
+        let spend = builder.spend(0, to, amount, 100).unwrap();
+        builder.chain_mut().add_to_mempool(spend.clone()).unwrap();
+        spends.push(spend);
+    }
+    (builder, spends)
+}
+
+#[test]
+fn block_header_round_trips() {
+    let dir = TempDir::new().unwrap();
+    let mut builder = ChainBuilder::new(677);
+    builder.mine_blocks(2).unwrap();
+    let header = builder.chain().blocks().last().unwrap().header.clone();
+    assert_eq!(reload(&header), header);
+    assert_eq!(reload(&header).hash(), header.hash());
+    let path = dir.path().join("header.cbor");
+    header.save_to_file(&path).unwrap();
+    assert_eq!(BlockHeader::load_from_file(&path).unwrap(), header);
+    header.save_compressed_to_file(&path).unwrap();
+    assert_eq!(BlockHeader::load_from_file(&path).unwrap(), header);
+    header.save_json_to_file(&path).unwrap();
+    assert_eq!(BlockHeader::load_json_from_file(&path).unwrap(), header);
+}
+
+#[test]
+fn mempool_round_trips() {
+    let (builder, spends) = with_mempool();
+    let snapshot = builder.chain().mempool_snapshot();
+    let loaded: MempoolSnapshot = reload(&snapshot);
+    assert_eq!(loaded.entries.len(), spends.len());
+    for (loaded, saved) in loaded.entries.iter().zip(&snapshot.entries) {
+        assert_eq!(loaded.transaction.hash(), saved.transaction.hash());
+        assert_eq!(loaded.timestamp, saved.timestamp);
+        assert_eq!(loaded.fee, 100);
+    }
+
+    let mut chain = reopened(builder.chain());
+    assert!(chain.restore_mempool(loaded).is_empty());
+    let restored: Vec<_> = chain
+        .mempool()
+        .iter()
+        .map(|(timestamp, transaction)| (*timestamp, transaction.hash()))
+        .collect();
+    let original: Vec<_> = builder
+        .chain()
+        .mempool()
+        .iter()
+        .map(|(timestamp, transaction)| (*timestamp, transaction.hash()))
+        .collect();
+    assert_eq!(restored, original);
+    for spend in &spends {
+        let input = spend.inputs[0].prev_transaction_output_hash;
+        assert_eq!(chain.is_utxo_reserved(&input), Some(true));
+    }
+}
+
+#[test]
+fn confirmed_entry_is_dropped_on_reload() {
+    let (mut builder, spends) = with_mempool();
+    let snapshot = reload(&builder.chain().mempool_snapshot());
+    // the second spend mined after the mempool was saved
+    let mined = spends[1].clone();
+    builder
+        .mine_block(|template| template.transactions = vec![mined.clone()])
+        .unwrap();
+    let mut chain = reopened(builder.chain());
+    let dropped = chain.restore_mempool(snapshot);
+    let [(transaction, e)] = dropped.as_slice() else {
+        panic!("dropped {dropped:?}");
+    };
+    assert_eq!(transaction.hash(), mined.hash());
+    // its input spent by itself, in the block
+    let input = mined.inputs[0].prev_transaction_output_hash;
+    assert!(
+        matches!(e, SbdError::MissingInput(hash) if *hash == input),
+        "{e}"
+    );
+    let kept: Vec<_> = chain
+        .mempool()
+        .iter()
+        .map(|(_, transaction)| transaction.hash())
+        .collect();
+    assert_eq!(kept, [spends[0].hash(), spends[2].hash()]);
+    assert!(chain.find_transaction(&mined.hash()).is_some());
+}
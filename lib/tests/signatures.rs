@@ -0,0 +1,132 @@
+// a block whose inputs all check out but for their signatures: one
+// bad signature anywhere in it, or several, rejects it as
+// InvalidSignature, and a spend that fails its bookkeeping is reported
+// first, whichever input it comes after. run it with --features
+// parallel too, where the signatures are checked across rayon's
+// threads: it must pass the same either way
+use lib::crypto::Signature;
+use lib::error::SbdError;
+use lib::test_utils::ChainBuilder;
+use lib::types::Block;
+
+const SPENDS: usize = 32;
+const INPUTS_PER_SPEND: usize = 4;
+const VALUE: u64 = 1000;
+const FEE: u64 = 10;
+
+// a chain funding key 0 and a valid, unmined block spending all of it,
+// SPENDS transactions of INPUTS_PER_SPEND inputs each
+fn fixture() -> (ChainBuilder, Block) {
+    let mut builder = ChainBuilder::new(697);
+    let outputs = builder
+        .fund(0, &[VALUE; SPENDS * INPUTS_PER_SPEND])
+        .unwrap();
+    let spends = outputs
+        .chunks(INPUTS_PER_SPEND)
+        .map(|chunk| {
+            let spent: Vec<_> = chunk.iter().map(|output| output.hash()).collect();
+            let value = VALUE * INPUTS_PER_SPEND as u64 - FEE;
+            let output = builder.output(value, 1);
+            builder.sign(&spent, vec![output]).unwrap()
+        })
+        .collect::<Vec<_>>();
+    let block = builder
+        .block(|template| template.transactions = spends)
+        .unwrap();
+    (builder, block)
+}
+
+// the signature of an input, in block order, replaced by one of the
+// right message from the wrong key
+fn forge(builder: &ChainBuilder, block: &mut Block, input: usize) {
+    let transaction = &mut block.transactions[1 + input / INPUTS_PER_SPEND];
+    let index = input % INPUTS_PER_SPEND;
+    let sighash = transaction.sighash(&transaction.inputs[index].prev_transaction_output_hash);
+    transaction.inputs[index].signature = Signature::sign_input(&sighash, builder.key(1));
+    transaction.thaw();
+}
+
+fn verify(builder: &ChainBuilder, block: &Block) -> lib::error::Result<()> {
+    let chain = builder.chain();
+    block.verify_transactions(
+        chain.params(),
+        chain.chain_height(),
+        chain.utxos(),
+        &chain.immature_coinbases(),
+    )
+}
+
+// the block added to a copy of the chain, which must be left as it was
+// if it's refused
+fn add(builder: &ChainBuilder, mut block: Block) -> lib::error::Result<()> {
+    builder.reseal(&mut block);
+    let mut chain = builder.chain().clone();
+    let result = chain.add_block(block);
+    if result.is_err() {
+        assert_eq!(chain.tip_hash(), builder.chain().tip_hash());
+        assert_eq!(chain.utxo_count(), builder.chain().utxo_count());
+    }
+    result
+}
+
+#[test]
+fn fixture_is_valid() {
+    let (builder, block) = fixture();
+    verify(&builder, &block).unwrap();
+    add(&builder, block).unwrap();
+}
+
+#[test]
+fn one_bad_signature_anywhere() {
+    let (builder, block) = fixture();
+    let inputs = SPENDS * INPUTS_PER_SPEND;
+    for input in [0, 1, inputs / 2 - 1, inputs / 2, inputs - 2, inputs - 1] {
+        let mut forged = block.clone();
+        forge(&builder, &mut forged, input);
+        assert!(
+            matches!(verify(&builder, &forged), Err(SbdError::InvalidSignature)),
+            "input {input}"
+        );
+        assert!(
+            matches!(add(&builder, forged), Err(SbdError::InvalidSignature)),
+            "input {input}"
+        );
+    }
+}
+
+#[test]
+fn several_bad_signatures() {
+    let (builder, block) = fixture();
+    let mut forged = block.clone();
+    for input in (0..SPENDS * INPUTS_PER_SPEND).step_by(7) {
+        forge(&builder, &mut forged, input);
+    }
+    assert!(matches!(
+        verify(&builder, &forged),
+        Err(SbdError::InvalidSignature)
+    ));
+    assert!(matches!(
+        add(&builder, forged),
+        Err(SbdError::InvalidSignature)
+    ));
+}
+
+#[test]
+fn bookkeeping_is_checked_before_signatures() {
+    let (builder, block) = fixture();
+    // the first signature bad, the last spend paying out more than it
+    // spends: the overspend is the error, though it comes later
+    let mut forged = block.clone();
+    forge(&builder, &mut forged, 0);
+    let last = forged.transactions.last_mut().unwrap();
+    last.outputs[0].value += FEE + 1;
+    last.thaw();
+    assert!(matches!(
+        verify(&builder, &forged),
+        Err(SbdError::InvalidTransaction)
+    ));
+    assert!(matches!(
+        add(&builder, forged),
+        Err(SbdError::InvalidTransaction)
+    ));
+}
@@ -0,0 +1,60 @@
+// chains carrying on from a utxo snapshot, which hold no blocks
+// below their base
+use lib::sha256::Hash;
+use lib::test_utils::{ChainBuilder, instant_params};
+use lib::types::{BlockHeader, Blockchain, HeaderChain};
+
+const BASE: u64 = 10;
+
+// a chain of BASE blocks and more, and the same chain from a
+// snapshot at BASE with the blocks after it
+fn full_and_from_snapshot(after: u64) -> (Blockchain, Blockchain) {
+    let mut builder = ChainBuilder::new(725);
+    builder.mine_blocks(BASE).unwrap();
+    let mut snapshot = vec![];
+    builder.chain().export_utxo_snapshot(&mut snapshot).unwrap();
+    let headers = HeaderChain::from_blocks(builder.chain().blocks());
+    let mut from_snapshot =
+        Blockchain::from_snapshot_with_params(instant_params(), headers, &snapshot[..]).unwrap();
+    builder.mine_blocks(after).unwrap();
+    for block in builder.chain().blocks().skip(BASE as usize) {
+        from_snapshot.add_block(block.clone()).unwrap();
+    }
+    (builder.into_chain(), from_snapshot)
+}
+
+#[test]
+fn fork_point_is_a_chain_height() {
+    let (full, from_snapshot) = full_and_from_snapshot(4);
+    assert_eq!(from_snapshot.chain_height(), BASE + 4);
+    let hashes: Vec<_> = full.blocks().map(|block| block.hash()).collect();
+    for height in [12, BASE, 5, 0] {
+        let locator = [hashes[height as usize]];
+        assert_eq!(full.find_fork_point(&locator), Some(height));
+        assert_eq!(from_snapshot.find_fork_point(&locator), Some(height));
+    }
+    assert_eq!(
+        from_snapshot.find_fork_point(&full.block_locator()),
+        Some(BASE + 3)
+    );
+}
+
+#[test]
+fn headers_served_from_a_fork_point() {
+    let (full, from_snapshot) = full_and_from_snapshot(4);
+    let tip = full.blocks().nth(12).unwrap().hash();
+    let below_base = full.blocks().nth(5).unwrap().hash();
+    for locator in [[tip], [below_base]] {
+        let served = headers(&from_snapshot, &locator);
+        assert_eq!(served, headers(&full, &locator));
+        assert!(!served.is_empty());
+    }
+}
+
+// what a node answers GetHeaders with
+fn headers(chain: &Blockchain, locator: &[Hash]) -> Vec<BlockHeader> {
+    let start = chain
+        .find_fork_point(locator)
+        .map_or(0, |height| height + 1);
+    chain.headers_from(start, 3)
+}
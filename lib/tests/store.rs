@@ -0,0 +1,203 @@
+// the stores against a MemoryStore fed the same blocks: what every
+// ChainStore must do, run over each of them, and the append-only
+// BlockStore dropped between manifests, with a record torn by the
+// kill, opening again to the blocks and utxos the reference holds
+use lib::error::SbdError;
+use lib::params::Network;
+use lib::sha256::Hash;
+use lib::test_utils::ChainBuilder;
+use lib::types::{
+    Block, BlockStore, Blockchain, ChainBatch, ChainStore, ChainTip, MANIFEST_INTERVAL,
+    MemoryStore, SledStore,
+};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use tempfile::TempDir;
+
+// past the first manifest, short of the second
+const KILLED_AT: u64 = MANIFEST_INTERVAL + 30;
+
+// blocks of a chain with spends in them, each with the tip it makes,
+// as the chain commits them
+fn blocks(count: u64) -> Vec<(Block, ChainTip)> {
+    let mut builder = ChainBuilder::new(670);
+    let mut blocks = vec![];
+    for height in 1..=count {
+        if height > 2 {
+            let spend = builder.spend(0, 1 + height as usize % 4, 100, 10).unwrap();
+            builder
+                .mine_block(|template| template.transactions = vec![spend])
+                .unwrap();
+        } else {
+            builder.mine_blocks(1).unwrap();
+        }
+        let chain = builder.chain();
+        let block = chain.blocks().last().unwrap().clone();
+        let tip = ChainTip {
+            network: chain.network(),
+            height: chain.block_height(),
+            hash: block.hash(),
+            target: chain.target(),
+        };
+        blocks.push((block, tip));
+    }
+    blocks
+}
+
+fn commit(store: &mut impl ChainStore, blocks: &[(Block, ChainTip)]) {
+    for (block, tip) in blocks {
+        store
+            .commit(ChainBatch::connect(block.clone(), *tip))
+            .unwrap();
+    }
+}
+
+fn assert_same(store: &impl ChainStore, reference: &MemoryStore) {
+    assert_eq!(store.tip().unwrap(), reference.tip().unwrap());
+    let (blocks, utxos) = store.load().unwrap();
+    let (expected_blocks, expected_utxos) = reference.load().unwrap();
+    let hashes = |blocks: &[Block]| blocks.iter().map(Block::hash).collect::<Vec<Hash>>();
+    assert_eq!(hashes(&blocks), hashes(&expected_blocks));
+    assert_eq!(utxos, expected_utxos);
+    for (height, block) in expected_blocks.iter().enumerate() {
+        let hash = block.hash();
+        let by_height = store.get_block_by_height(height as u64).unwrap().unwrap();
+        assert_eq!(by_height.hash(), hash);
+        assert_eq!(
+            store.get_block_by_hash(&hash).unwrap().unwrap().hash(),
+            hash
+        );
+    }
+    for (hash, output) in &expected_utxos {
+        assert_eq!(store.get_utxo(hash).unwrap().as_ref(), Some(output));
+    }
+}
+
+// what a kill leaves: half of the next record and half of
+// the next manifest in its temporary file
+fn kill(dir: &Path, next: &[u8]) {
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(dir.join("blocks.dat"))
+        .unwrap();
+    file.write_all(&next[..next.len() / 2]).unwrap();
+    fs::write(dir.join("manifest.cbor.tmp"), b"half a manifest").unwrap();
+}
+
+#[test]
+fn killed_store_reopens_to_the_reference() {
+    let dir = TempDir::new().unwrap();
+    let blocks = blocks(2 * MANIFEST_INTERVAL);
+    let (before, after) = blocks.split_at(KILLED_AT as usize);
+    let mut reference = MemoryStore::new();
+    commit(&mut reference, before);
+
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    commit(&mut store, before);
+    assert_same(&store, &reference);
+    // a record as the next one would be, its length read and
+    // then the file running out
+    let start = store.block_offset(KILLED_AT - 2).unwrap() as usize;
+    let end = store.block_offset(KILLED_AT - 1).unwrap() as usize;
+    let next = fs::read(dir.path().join("blocks.dat")).unwrap()[start..end].to_vec();
+    // dropped without a flush, the manifest is the one of the
+    // first MANIFEST_INTERVAL blocks
+    drop(store);
+    let blocks_size = fs::metadata(dir.path().join("blocks.dat")).unwrap().len();
+    kill(dir.path(), &next);
+
+    // the blocks past the manifest replayed, the torn record cut off
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    assert_eq!(
+        fs::metadata(dir.path().join("blocks.dat")).unwrap().len(),
+        blocks_size
+    );
+    assert_same(&store, &reference);
+
+    // and carrying on from there, across the next manifest
+    commit(&mut store, after);
+    commit(&mut reference, after);
+    assert_same(&store, &reference);
+    drop(store);
+    assert_same(&BlockStore::open(dir.path()).unwrap(), &reference);
+}
+
+#[test]
+fn manifest_ahead_of_the_blocks_is_replayed_over() {
+    let dir = TempDir::new().unwrap();
+    let blocks = blocks(MANIFEST_INTERVAL);
+    let mut store = BlockStore::open(dir.path()).unwrap();
+    commit(&mut store, &blocks);
+    let last = store.block_offset(MANIFEST_INTERVAL - 1).unwrap();
+    drop(store);
+    // the last block lost, but not the manifest written after it
+    OpenOptions::new()
+        .write(true)
+        .open(dir.path().join("blocks.dat"))
+        .unwrap()
+        .set_len(last)
+        .unwrap();
+    let mut reference = MemoryStore::new();
+    commit(&mut reference, &blocks[..blocks.len() - 1]);
+    assert_same(&BlockStore::open(dir.path()).unwrap(), &reference);
+}
+
+// what any store must do: start empty, serve what was committed by
+// height and hash, drop spent outputs, keep it all across a reopen,
+// and refuse a chain of another network
+fn suite<S: ChainStore + 'static>(open: impl Fn(&Path) -> S) {
+    let dir = TempDir::new().unwrap();
+    let blocks = blocks(20);
+    let mut store = open(dir.path());
+    assert_eq!(store.tip().unwrap(), None);
+    let (empty, utxos) = store.load().unwrap();
+    assert!(empty.is_empty() && utxos.is_empty());
+    assert!(store.get_block_by_height(0).unwrap().is_none());
+
+    let mut reference = MemoryStore::new();
+    commit(&mut store, &blocks);
+    commit(&mut reference, &blocks);
+    assert_same(&store, &reference);
+    assert!(store.get_block_by_height(20).unwrap().is_none());
+    assert!(
+        store
+            .get_block_by_hash(&Hash::hash_bytes(b"no such block"))
+            .unwrap()
+            .is_none()
+    );
+    // the coins spent by the last block are gone
+    let (last, _) = blocks.last().unwrap();
+    let spent: Vec<_> = last
+        .transactions
+        .iter()
+        .flat_map(|transaction| &transaction.inputs)
+        .map(|input| input.prev_transaction_output_hash)
+        .collect();
+    assert!(!spent.is_empty());
+    for hash in &spent {
+        assert!(store.get_utxo(hash).unwrap().is_none());
+    }
+
+    store.flush().unwrap();
+    drop(store);
+    let store = open(dir.path());
+    assert_same(&store, &reference);
+    assert!(matches!(
+        Blockchain::with_store(store, Network::Mainnet),
+        Err(SbdError::WrongNetwork {
+            expected: Network::Mainnet,
+            found: Network::Regtest,
+        })
+    ));
+}
+
+#[test]
+fn block_store_passes_the_suite() {
+    suite(|dir| BlockStore::open(dir).unwrap());
+}
+
+#[test]
+fn sled_store_passes_the_suite() {
+    suite(|dir| SledStore::open(dir.join("chain.sled")).unwrap());
+}
@@ -0,0 +1,162 @@
+// the supply schedule: the reward halves on the first block of each
+// halving interval and not before, reaches 0 and stays there, and the
+// closed form cumulative supply is the sum of the rewards block by
+// block; the circulating supply is what coinbases claimed, so a
+// coinbase claiming less than it may leaves it below the schedule
+use lib::error::SbdError;
+use lib::params::NetworkParams;
+use lib::test_utils::{ChainBuilder, instant_params};
+use lib::{INITIAL_REWARD, UNITS_PER_COIN};
+
+const BRUTE_FORCE_BLOCKS: u64 = 5000;
+const FEE: u64 = 500;
+const UNCLAIMED: u64 = 12_345;
+
+// mainnet's max supply, in units: 210 blocks of each halving of 50
+// coins, a thousandth of bitcoin's
+const MAINNET_MAX_SUPPLY: u64 = 2_099_999_997_690;
+
+fn schedules() -> Vec<(&'static str, NetworkParams)> {
+    vec![
+        ("mainnet", NetworkParams::mainnet()),
+        ("regtest", NetworkParams::regtest()),
+        // short enough for the reward to reach 0 within the blocks
+        // summed by brute force
+        (
+            "short",
+            NetworkParams {
+                halving_interval: 7,
+                ..NetworkParams::regtest()
+            },
+        ),
+    ]
+}
+
+// the last halving the reward isn't 0 in
+fn last_halving(params: &NetworkParams) -> u64 {
+    let initial = params.reward_at_height(0);
+    u64::from(63 - initial.leading_zeros())
+}
+
+#[test]
+fn reward_halves_on_the_boundary() {
+    for (name, params) in schedules() {
+        let interval = params.halving_interval;
+        let initial = INITIAL_REWARD * UNITS_PER_COIN;
+        assert_eq!(params.reward_at_height(0), initial, "{name}");
+        for halving in 1..=64u64 {
+            let boundary = halving * interval;
+            let before = initial.checked_shr(halving as u32 - 1).unwrap_or(0);
+            let after = initial.checked_shr(halving as u32).unwrap_or(0);
+            assert_eq!(
+                params.reward_at_height(boundary - 1),
+                before,
+                "{name} {halving}"
+            );
+            assert_eq!(params.reward_at_height(boundary), after, "{name} {halving}");
+            // and the supply grows by the new reward from there
+            assert_eq!(
+                params.cumulative_supply_at_height(boundary)
+                    - params.cumulative_supply_at_height(boundary - 1),
+                after,
+                "{name} {halving}"
+            );
+        }
+    }
+}
+
+#[test]
+fn supply_stops_where_the_reward_is_zero() {
+    for (name, params) in schedules() {
+        let interval = params.halving_interval;
+        let last = last_halving(&params);
+        assert_eq!(params.reward_at_height(0) >> last, 1, "{name}");
+        // the last unit paid, then nothing more
+        let last_paid = (last + 1) * interval - 1;
+        assert_eq!(params.reward_at_height(last_paid), 1, "{name}");
+        let max = params.max_supply();
+        assert_eq!(params.cumulative_supply_at_height(last_paid), max, "{name}");
+        assert!(params.cumulative_supply_at_height(last_paid - 1) < max);
+        for height in [last_paid + 1, last_paid * 2, u64::MAX / 2, u64::MAX] {
+            assert_eq!(params.reward_at_height(height), 0, "{name} {height}");
+            assert_eq!(
+                params.cumulative_supply_at_height(height),
+                max,
+                "{name} {height}"
+            );
+        }
+        // each halving pays interval blocks of its reward
+        let expected: u64 = (0..=last)
+            .map(|halving| interval * (params.reward_at_height(0) >> halving))
+            .sum();
+        assert_eq!(max, expected, "{name}");
+    }
+    assert_eq!(NetworkParams::mainnet().max_supply(), MAINNET_MAX_SUPPLY);
+}
+
+#[test]
+fn closed_form_matches_the_sum_of_rewards() {
+    for (name, params) in schedules() {
+        let mut sum = 0u64;
+        for height in 0..BRUTE_FORCE_BLOCKS {
+            sum += params.reward_at_height(height);
+            assert_eq!(
+                params.cumulative_supply_at_height(height),
+                sum,
+                "{name} {height}"
+            );
+        }
+    }
+}
+
+#[test]
+fn no_halving_saturates() {
+    let params = NetworkParams {
+        halving_interval: 0,
+        ..NetworkParams::regtest()
+    };
+    let initial = params.reward_at_height(0);
+    assert_eq!(params.reward_at_height(u64::MAX), initial);
+    assert_eq!(params.cumulative_supply_at_height(9), 10 * initial);
+    assert_eq!(params.cumulative_supply_at_height(u64::MAX), u64::MAX);
+    assert_eq!(params.max_supply(), u64::MAX);
+}
+
+#[test]
+fn under_claiming_coinbase_lowers_circulating_supply() {
+    let mut builder = ChainBuilder::with_params(717, instant_params());
+    builder.fund(1, &[10_000]).unwrap();
+    builder.mine_blocks(2).unwrap();
+    let height = builder.chain().block_height() - 1;
+    let scheduled = builder.chain().params().cumulative_supply_at_height(height);
+    // every block claimed its reward in full
+    assert_eq!(builder.chain().circulating_supply(), scheduled);
+
+    // a block with a fee, its coinbase claiming less than the reward
+    // and the fee
+    let spend = builder.spend(1, 2, 5_000, FEE).unwrap();
+    let mut block = builder
+        .block(|template| template.transactions = vec![spend])
+        .unwrap();
+    let claimed: u64 = block.transactions[0].outputs.iter().map(|o| o.value).sum();
+    let reward = builder.chain().params().reward_at_height(height + 1);
+    assert_eq!(claimed, reward + FEE);
+    block.transactions[0].outputs[0].value -= UNCLAIMED;
+    builder.reseal(&mut block);
+
+    // consensus has it claim exactly the reward and fees
+    let mut checked = builder.chain().clone();
+    assert!(matches!(
+        checked.add_block(block.clone()),
+        Err(SbdError::InvalidTransaction)
+    ));
+    let chain = builder.chain_mut();
+    chain.add_trusted_block(block).unwrap();
+    let scheduled = chain.params().cumulative_supply_at_height(height + 1);
+    assert_eq!(chain.circulating_supply(), scheduled - UNCLAIMED);
+    // and the unclaimed coins are gone for good
+    builder.mine_blocks(1).unwrap();
+    let chain = builder.chain();
+    let scheduled = chain.params().cumulative_supply_at_height(height + 2);
+    assert_eq!(chain.circulating_supply(), scheduled - UNCLAIMED);
+}
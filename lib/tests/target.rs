@@ -0,0 +1,71 @@
+// blocks must be mined to the target the schedule gives
+use chrono::Duration;
+use lib::U256;
+use lib::error::SbdError;
+use lib::test_utils::ChainBuilder;
+use lib::types::Block;
+
+// a chain whose target just adjusted, its blocks coming a second
+// apart until then
+fn retargeted() -> ChainBuilder {
+    let mut builder = ChainBuilder::new(714);
+    let interval = builder.chain().params().difficulty_update_interval;
+    let start = builder.timestamp(0);
+    for height in 0..interval {
+        let timestamp = start + Duration::seconds(height as i64);
+        builder
+            .mine_block(|template| template.timestamp = timestamp)
+            .unwrap();
+    }
+    assert!(builder.chain().target() < builder.chain().params().min_target);
+    builder
+}
+
+// the next block, mined to target instead
+fn mined_to(builder: &mut ChainBuilder, target: U256) -> Block {
+    let tip = builder.chain().blocks().last().unwrap().header.timestamp;
+    let mut block = builder
+        .block(|template| template.timestamp = tip + Duration::seconds(1))
+        .unwrap();
+    block.header.target = target;
+    builder.reseal(&mut block);
+    block
+}
+
+#[test]
+fn retargeted_chain_verifies() {
+    let mut builder = retargeted();
+    let target = builder.chain().target();
+    let block = mined_to(&mut builder, target);
+    builder.chain_mut().add_block(block).unwrap();
+    builder.chain().verify().unwrap();
+}
+
+#[test]
+fn block_easier_than_the_schedule_is_rejected() {
+    let mut builder = retargeted();
+    let easier = builder.chain().params().min_target;
+    let block = mined_to(&mut builder, easier);
+    assert!(block.header.hash().matches_target(block.header.target));
+    assert!(matches!(
+        builder.chain_mut().add_block(block.clone()),
+        Err(SbdError::InvalidBlock)
+    ));
+    // nor does a chain verify that holds one anyway
+    builder.chain_mut().add_trusted_block(block).unwrap();
+    assert!(matches!(
+        builder.chain().verify(),
+        Err(SbdError::InvalidBlock)
+    ));
+}
+
+#[test]
+fn block_harder_than_the_schedule_is_rejected() {
+    let mut builder = retargeted();
+    let harder = builder.chain().target() / 2;
+    let block = mined_to(&mut builder, harder);
+    assert!(matches!(
+        builder.chain_mut().add_block(block),
+        Err(SbdError::InvalidBlock)
+    ));
+}
@@ -0,0 +1,150 @@
+// tx_gen spend against a saved chain: the spend it writes is taken
+// into the mempool and mined, paying the address and the change back
+// to the key, its fee claimed by the block. the largest output of the
+// key is a coinbase not yet mature, which it must pass over for the
+// largest the next block may spend
+use assert_cmd::Command;
+use lib::error::SbdError;
+use lib::params::NetworkParams;
+use lib::test_utils::{ChainBuilder, instant_params};
+use lib::types::Transaction;
+use lib::utils::Saveable;
+use predicates::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+const FUNDED: [u64; 3] = [1000, 2000, 3000];
+const AMOUNT: u64 = 2500;
+const FEE: u64 = 100;
+
+// key 0 funded, the rest of that block's reward paid to it too, then
+// a block whose coinbase pays it the whole reward, which the next
+// block may not spend yet; the chain and key 0 saved in dir
+fn fixture(dir: &TempDir) -> ChainBuilder {
+    let params = NetworkParams {
+        coinbase_maturity: 2,
+        ..instant_params()
+    };
+    let mut builder = ChainBuilder::with_params(706, params);
+    builder.fund(0, &FUNDED).unwrap();
+    builder.mine_blocks(1).unwrap();
+    builder
+        .chain()
+        .save_to_file(dir.path().join("chain.cbor"))
+        .unwrap();
+    fs::write(dir.path().join("key"), builder.key(0).to_hex()).unwrap();
+    builder
+}
+
+fn tx_gen(dir: &TempDir, amount: u64, args: &[&str]) -> Command {
+    let mut command = Command::cargo_bin("tx_gen").unwrap();
+    command
+        .arg("spend")
+        .arg("--chain")
+        .arg(dir.path().join("chain.cbor"))
+        .arg("--key")
+        .arg(dir.path().join("key"))
+        .args(["--amount", &amount.to_string()])
+        .args(["--fee", &FEE.to_string()])
+        .arg("--out")
+        .arg(out(dir))
+        .args(args);
+    command
+}
+
+fn out(dir: &TempDir) -> PathBuf {
+    dir.path().join("tx.cbor")
+}
+
+#[test]
+fn generated_spend_is_mined() {
+    let dir = TempDir::new().unwrap();
+    let mut builder = fixture(&dir);
+    let to = builder.key(1).public_key();
+    tx_gen(&dir, AMOUNT, &["--to", &to.to_address()])
+        .assert()
+        .success();
+    let spend = Transaction::load_from_file(out(&dir)).unwrap();
+
+    // the rest of the funding block's reward, the largest mature
+    // output, paid on and the change back
+    let reward = builder.chain().params().reward_at_height(0);
+    let rest = reward - FUNDED.iter().sum::<u64>();
+    let spendable = builder.spendable(0);
+    assert_eq!(spendable[0].1.value, reward, "the immature coinbase");
+    let largest = spendable
+        .into_iter()
+        .find(|(_, output)| output.value == rest)
+        .unwrap();
+    assert_eq!(spend.inputs.len(), 1);
+    assert_eq!(spend.inputs[0].prev_transaction_output_hash, largest.0);
+    assert_eq!(spend.outputs.len(), 2);
+    assert_eq!(spend.outputs[0].value, AMOUNT);
+    assert_eq!(spend.outputs[0].pubkey, to);
+    assert_eq!(spend.outputs[1].value, rest - AMOUNT - FEE);
+    assert_eq!(spend.outputs[1].pubkey, builder.key(0).public_key());
+
+    builder.chain_mut().add_to_mempool(spend.clone()).unwrap();
+    let height = builder.chain().chain_height();
+    let block = builder
+        .mine_block(|template| template.transactions = vec![spend.clone()])
+        .unwrap();
+    let chain = builder.chain();
+    assert!(chain.mempool().is_empty());
+    assert_eq!(chain.confirmations(&spend.hash()), Some(1));
+    for output in &spend.outputs {
+        assert_eq!(chain.utxo(&output.hash()), Some(output));
+    }
+    assert!(chain.utxo(&largest.0).is_none());
+    let coinbase: u64 = block.transactions[0]
+        .outputs
+        .iter()
+        .map(|output| output.value)
+        .sum();
+    assert_eq!(coinbase, chain.params().reward_at_height(height) + FEE);
+}
+
+#[test]
+fn expiring_spend_is_mined_in_time() {
+    let dir = TempDir::new().unwrap();
+    let mut builder = fixture(&dir);
+    let to = builder.key(1).public_key().to_address();
+    tx_gen(&dir, AMOUNT, &["--to", &to, "--expires-in", "1"])
+        .assert()
+        .success();
+    let spend = Transaction::load_from_file(out(&dir)).unwrap();
+    let height = builder.chain().chain_height();
+    assert_eq!(spend.expires_at_height, Some(height + 1));
+    // a block later it's too late
+    let mut late = builder.chain().clone();
+    let next = builder.block(|_| {}).unwrap();
+    late.add_block(next).unwrap();
+    assert!(matches!(
+        late.add_to_mempool(spend.clone()),
+        Err(SbdError::ExpiredTransaction { .. })
+    ));
+    // the next block is in time
+    builder
+        .mine_block(|template| template.transactions = vec![spend.clone()])
+        .unwrap();
+    assert_eq!(builder.chain().confirmations(&spend.hash()), Some(1));
+}
+
+#[test]
+fn immature_funds_are_not_enough() {
+    let dir = TempDir::new().unwrap();
+    let builder = fixture(&dir);
+    let to = builder.key(1).public_key().to_address();
+    // the mature outputs add up to one reward, as does the immature
+    // coinbase
+    let mature = builder.chain().params().reward_at_height(0);
+    tx_gen(&dir, mature, &["--to", &to])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("insufficient funds"));
+    assert!(!out(&dir).exists());
+    tx_gen(&dir, mature - FEE, &["--to", &to])
+        .assert()
+        .success();
+}
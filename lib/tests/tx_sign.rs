@@ -0,0 +1,245 @@
+// tx_sign over a spend of two inputs owned by two keys: each key signs
+// its own input in turn, reporting the one it can't and leaving the one
+// already signed as it is; --check-only fails the partly signed file
+// and one whose signature was corrupted, against a chain or a utxo
+// snapshot, and passes the signed one, which the chain then mines
+use assert_cmd::Command;
+use lib::crypto::Signature;
+use lib::error::SbdError;
+use lib::test_utils::ChainBuilder;
+use lib::types::{Transaction, TransactionInput};
+use lib::utils::Saveable;
+use predicates::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+const FEE: u64 = 100;
+
+struct Fixture {
+    dir: TempDir,
+    builder: ChainBuilder,
+    unsigned: Transaction,
+}
+
+impl Fixture {
+    // keys 0 and 1 funded, and an unsigned spend of one output of
+    // each to key 2; the chain, a snapshot of it and both keys saved
+    fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        let mut builder = ChainBuilder::new(707);
+        let first = builder.fund(0, &[1000]).unwrap().remove(0);
+        let second = builder.fund(1, &[2000]).unwrap().remove(0);
+        let payment = builder.output(first.value + second.value - FEE, 2);
+        let inputs = [&first, &second]
+            .map(|output| TransactionInput {
+                prev_transaction_output_hash: output.hash(),
+                signature: Signature::placeholder(),
+            })
+            .to_vec();
+        let unsigned = Transaction::new(inputs, vec![payment]);
+        let fixture = Fixture {
+            dir,
+            builder,
+            unsigned,
+        };
+        let chain = fixture.builder.chain();
+        chain.save_to_file(fixture.path("chain.cbor")).unwrap();
+        let mut snapshot = vec![];
+        chain.export_utxo_snapshot(&mut snapshot).unwrap();
+        fs::write(fixture.path("utxos.snapshot"), snapshot).unwrap();
+        for key in 0..2 {
+            let secret = fixture.builder.key(key).to_hex();
+            fs::write(fixture.path(&format!("key{key}")), secret).unwrap();
+        }
+        fixture
+            .unsigned
+            .save_to_file(fixture.path("unsigned.cbor"))
+            .unwrap();
+        fixture
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    fn address(&self, key: usize) -> String {
+        self.builder.key(key).public_key().to_address()
+    }
+
+    fn input(&self, index: usize) -> String {
+        self.unsigned.inputs[index]
+            .prev_transaction_output_hash
+            .to_string()
+    }
+
+    fn sign(&self, tx: &str, key: usize, out: &str) -> Command {
+        let mut command = Command::cargo_bin("tx_sign").unwrap();
+        command
+            .arg(self.path(tx))
+            .arg(self.path(&format!("key{key}")))
+            .arg("--utxos")
+            .arg(self.path("chain.cbor"))
+            .arg("--out")
+            .arg(self.path(out));
+        command
+    }
+
+    fn check(&self, tx: &str, utxos: &str) -> Command {
+        let mut command = Command::cargo_bin("tx_sign").unwrap();
+        command
+            .arg(self.path(tx))
+            .arg("--check-only")
+            .arg("--utxos")
+            .arg(self.path(utxos));
+        command
+    }
+
+    fn load(&self, name: &str) -> Transaction {
+        Transaction::load_from_file(self.path(name)).unwrap()
+    }
+}
+
+#[test]
+fn two_keys_sign_in_turn() {
+    let mut fixture = Fixture::new();
+    // the first key signs its input and reports the other's
+    fixture
+        .sign("unsigned.cbor", 0, "partial.cbor")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("signed 1 of 2 inputs"))
+        .stderr(predicate::str::contains(format!(
+            "input 1 {}: not signed, it belongs to {}",
+            fixture.input(1),
+            fixture.address(1)
+        )));
+    let partial = fixture.load("partial.cbor");
+    for utxos in ["chain.cbor", "utxos.snapshot"] {
+        fixture
+            .check("partial.cbor", utxos)
+            .assert()
+            .code(1)
+            .stdout(
+                predicate::str::contains(format!(
+                    "input 0 {}: valid, {}",
+                    fixture.input(0),
+                    fixture.address(0)
+                ))
+                .and(predicate::str::contains(format!(
+                    "input 1 {}: invalid signature for {}",
+                    fixture.input(1),
+                    fixture.address(1)
+                ))),
+            );
+    }
+
+    // the second signs the other, leaving the first as it was
+    fixture
+        .sign("partial.cbor", 1, "signed.cbor")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("signed 1 of 2 inputs"))
+        .stderr(predicate::str::is_empty());
+    let signed = fixture.load("signed.cbor");
+    assert_eq!(signed.inputs[0].signature, partial.inputs[0].signature);
+    assert_eq!(signed.outputs, fixture.unsigned.outputs);
+    for utxos in ["chain.cbor", "utxos.snapshot"] {
+        fixture.check("signed.cbor", utxos).assert().success();
+    }
+    // signing again changes nothing
+    fixture
+        .sign("signed.cbor", 0, "again.cbor")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("signed 0 of 2 inputs"));
+    assert_eq!(fixture.load("again.cbor"), signed);
+
+    // the chain takes it, and mines it
+    fixture
+        .builder
+        .chain_mut()
+        .add_to_mempool(signed.clone())
+        .unwrap();
+    fixture
+        .builder
+        .mine_block(|template| template.transactions = vec![signed.clone()])
+        .unwrap();
+    assert_eq!(
+        fixture.builder.chain().confirmations(&signed.hash()),
+        Some(1)
+    );
+}
+
+#[test]
+fn check_catches_a_corrupted_signature() {
+    let fixture = Fixture::new();
+    fixture
+        .sign("unsigned.cbor", 0, "partial.cbor")
+        .assert()
+        .success();
+    fixture
+        .sign("partial.cbor", 1, "signed.cbor")
+        .assert()
+        .success();
+    let mut corrupted = fixture.load("signed.cbor");
+    let mut bytes = hex::decode(corrupted.inputs[1].signature.to_hex()).unwrap();
+    bytes[40] ^= 1;
+    corrupted.inputs[1].signature = Signature::from_hex(&hex::encode(bytes)).unwrap();
+    corrupted.thaw();
+    corrupted
+        .save_to_file(fixture.path("corrupted.cbor"))
+        .unwrap();
+    for utxos in ["chain.cbor", "utxos.snapshot"] {
+        fixture
+            .check("corrupted.cbor", utxos)
+            .assert()
+            .code(1)
+            .stdout(
+                predicate::str::contains(format!("input 0 {}: valid", fixture.input(0))).and(
+                    predicate::str::contains(format!(
+                        "input 1 {}: invalid signature for {}",
+                        fixture.input(1),
+                        fixture.address(1)
+                    )),
+                ),
+            );
+    }
+    // and the chain refuses it as well
+    let mut chain = fixture.builder.chain().clone();
+    assert!(matches!(
+        chain.add_to_mempool(corrupted),
+        Err(SbdError::InvalidSignature)
+    ));
+}
+
+#[test]
+fn check_reports_a_spent_output() {
+    let mut fixture = Fixture::new();
+    fixture
+        .sign("unsigned.cbor", 0, "partial.cbor")
+        .assert()
+        .success();
+    fixture
+        .sign("partial.cbor", 1, "signed.cbor")
+        .assert()
+        .success();
+    let signed = fixture.load("signed.cbor");
+    fixture
+        .builder
+        .mine_block(|template| template.transactions = vec![signed])
+        .unwrap();
+    fixture
+        .builder
+        .chain()
+        .save_to_file(fixture.path("chain.cbor"))
+        .unwrap();
+    fixture
+        .check("signed.cbor", "chain.cbor")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains(format!(
+            "input 0 {}: spends an unknown or spent output",
+            fixture.input(0)
+        )));
+}
@@ -0,0 +1,284 @@
+// the utxo commitment in block headers: the commitment the chain
+// moves by each block's delta is the one worked out over the whole
+// set, after every block of a seeded run of spends, reloaded and
+// rebuilt; a header committing to other utxos is refused, and one
+// committing to none only until the deployment is active; a snapshot
+// imported for a header holds the utxos that header commits to
+use lib::error::SbdError;
+use lib::params::{Deployment, NetworkParams, UTXO_COMMITMENT_DEPLOYMENT};
+use lib::sha256::Hash;
+use lib::test_utils::{ChainBuilder, Defect, instant_params};
+use lib::types::{
+    Block, BlockHeader, Blockchain, DeploymentState, Transaction, UtxoCommitment, read_snapshot,
+    write_snapshot,
+};
+use lib::utils::Saveable;
+
+const SEED: u64 = 720;
+const KEYS: usize = 4;
+const BLOCKS: usize = 20;
+const MAX_SPENDS: usize = 3;
+// blocks the deployment is tallied over, and a height it must be
+// active well before
+const WINDOW: u64 = 5;
+const MAX_HEIGHT: u64 = 10 * WINDOW;
+
+// xorshift64*, deterministic for a seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+}
+
+// the commitment worked out over the whole set, in the order given
+fn full(hashes: &[Hash]) -> Hash {
+    UtxoCommitment::of(hashes).hash()
+}
+
+fn utxo_hashes(chain: &Blockchain) -> Vec<Hash> {
+    chain.utxos().iter().map(|(hash, _, _)| *hash).collect()
+}
+
+// spends of the next block, each of one or two of a key's outputs to
+// one or two keys, less a fee
+fn spends(builder: &mut ChainBuilder, rng: &mut Rng) -> Vec<Transaction> {
+    let mut spent = vec![];
+    let mut transactions = vec![];
+    for _ in 0..rng.below(MAX_SPENDS + 1) {
+        let key = rng.below(KEYS);
+        let available: Vec<Hash> = builder
+            .spendable(key)
+            .into_iter()
+            .filter(|(hash, output)| !spent.contains(hash) && output.value > 100)
+            .map(|(hash, _)| hash)
+            .collect();
+        if available.is_empty() {
+            continue;
+        }
+        let inputs = available[..(1 + rng.below(2)).min(available.len())].to_vec();
+        let value: u64 = inputs
+            .iter()
+            .map(|hash| builder.chain().utxo(hash).unwrap().value)
+            .sum();
+        let fee = 1 + rng.below(50) as u64;
+        let half = (value - fee) / 2;
+        let outputs = if rng.below(2) == 0 {
+            vec![builder.output(value - fee, rng.below(KEYS))]
+        } else {
+            vec![
+                builder.output(half, rng.below(KEYS)),
+                builder.output(value - fee - half, rng.below(KEYS)),
+            ]
+        };
+        spent.extend(inputs.iter().copied());
+        transactions.push(builder.sign(&inputs, outputs).unwrap());
+    }
+    transactions
+}
+
+// the next block with its header committing to commitment, mined
+fn committing(builder: &mut ChainBuilder, commitment: Hash) -> Block {
+    let mut block = builder.block(|_| {}).unwrap();
+    block.header.utxo_commitment = commitment;
+    while !block.header.mine(1_000_000) {}
+    block
+}
+
+#[test]
+fn incremental_matches_full() {
+    let mut rng = Rng(SEED);
+    let mut builder = ChainBuilder::new(SEED).with_keys(KEYS);
+    for key in 0..KEYS {
+        builder.fund(key, &[1_000_000, 300_000, 50_000]).unwrap();
+    }
+    let mut spent = 0;
+    for _ in 0..BLOCKS {
+        let transactions = spends(&mut builder, &mut rng);
+        spent += transactions.len();
+        let block = builder
+            .mine_block(|template| template.transactions = transactions)
+            .unwrap();
+        let chain = builder.chain();
+        let mut hashes = utxo_hashes(chain);
+        assert_eq!(chain.utxo_commitment(), full(&hashes));
+        // the same in any order
+        hashes.reverse();
+        assert_eq!(chain.utxo_commitment(), full(&hashes));
+        // and what the header of the block claimed
+        assert_eq!(block.header.utxo_commitment, chain.utxo_commitment());
+    }
+    assert!(spent > BLOCKS, "{spent} spends");
+
+    // worked out again from scratch, for a loaded and a rebuilt chain
+    let chain = builder.chain();
+    let mut saved = vec![];
+    chain.save(&mut saved).unwrap();
+    let loaded = Blockchain::load(&saved[..]).unwrap();
+    assert_eq!(loaded.utxo_commitment(), chain.utxo_commitment());
+    let mut rebuilt = chain.clone();
+    rebuilt.rebuild_utxos();
+    assert_eq!(rebuilt.utxo_commitment(), chain.utxo_commitment());
+
+    // a commitment moves by an output in and out again
+    let mut commitment = UtxoCommitment::of(&utxo_hashes(chain));
+    let extra = Hash::hash_bytes(b"extra");
+    commitment.insert(&extra);
+    assert_ne!(commitment.hash(), chain.utxo_commitment());
+    commitment.remove(&extra);
+    assert_eq!(commitment.hash(), chain.utxo_commitment());
+}
+
+#[test]
+fn mismatch_is_refused() {
+    let mut builder = ChainBuilder::new(SEED);
+    builder.fund(1, &[5000]).unwrap();
+    let height = builder.chain().block_height();
+    let before = builder.chain().utxo_commitment();
+
+    // a block with a spend, committing to the utxos after it
+    let spend = builder.spend(1, 2, 1000, 10).unwrap();
+    let block = builder
+        .block(|template| template.transactions = vec![spend.clone()])
+        .unwrap();
+    let expected = block.header.utxo_commitment;
+    assert_eq!(
+        expected,
+        builder.chain().utxo_commitment_after(&block.transactions)
+    );
+    // the same worked out by hand, and an output short of it
+    let mut short = UtxoCommitment::of(&utxo_hashes(builder.chain()));
+    short.remove(&spend.inputs[0].prev_transaction_output_hash);
+    let mut outputs: Vec<Hash> = block
+        .transactions
+        .iter()
+        .flat_map(|tx| tx.outputs.iter().map(|output| output.hash()))
+        .collect();
+    let left_out = outputs.pop().unwrap();
+    for hash in &outputs {
+        short.insert(hash);
+    }
+    let mut whole = short;
+    whole.insert(&left_out);
+    assert_eq!(whole.hash(), expected);
+
+    // committing to the utxos as they were before the block, or to
+    // those after it less an output
+    let stale = builder.invalid_block(Defect::UtxoCommitment).unwrap();
+    assert_eq!(stale.header.utxo_commitment, before);
+    let mut partial = block.clone();
+    partial.header.utxo_commitment = short.hash();
+    while !partial.header.mine(1_000_000) {}
+    for refused in [stale, partial] {
+        let claimed = refused.header.utxo_commitment;
+        let mut chain = builder.chain().clone();
+        match chain.add_block(refused.clone()) {
+            Err(SbdError::UtxoCommitmentMismatch { expected, found }) => {
+                assert_eq!(found, claimed);
+                assert_eq!(expected, chain.utxo_commitment_after(&refused.transactions));
+            }
+            other => panic!("{other:?}"),
+        }
+        // and the chain is as it was
+        assert_eq!(chain.block_height(), height);
+        assert_eq!(chain.utxo_commitment(), before);
+    }
+
+    // the block committing to the right utxos is taken
+    builder.chain_mut().add_block(block).unwrap();
+    assert_eq!(builder.chain().utxo_commitment(), expected);
+}
+
+#[test]
+fn zero_commitment_only_until_active() {
+    let params = NetworkParams {
+        difficulty_update_interval: WINDOW,
+        deployments: vec![Deployment {
+            name: UTXO_COMMITMENT_DEPLOYMENT.to_string(),
+            bit: 0,
+            start_height: 0,
+            timeout_height: MAX_HEIGHT,
+            threshold: WINDOW,
+        }],
+        ..instant_params()
+    };
+    let mut builder = ChainBuilder::with_params(SEED, params);
+    // a header committing to nothing is taken while it isn't active
+    while builder.chain().deployment_state(UTXO_COMMITMENT_DEPLOYMENT) != DeploymentState::Active {
+        assert!(builder.chain().chain_height() < MAX_HEIGHT);
+        let block = committing(&mut builder, Hash::zero());
+        builder.chain_mut().add_block(block).unwrap();
+    }
+    // the commitment the chain moved along is still that of the set
+    let chain = builder.chain();
+    assert_eq!(chain.utxo_commitment(), full(&utxo_hashes(chain)));
+
+    // and once it's active, refused
+    let height = chain.block_height();
+    let block = committing(&mut builder, Hash::zero());
+    let mut chain = builder.chain().clone();
+    match chain.add_block(block) {
+        Err(SbdError::UtxoCommitmentMismatch { expected, found }) => {
+            assert_eq!(found, Hash::zero());
+            assert_ne!(expected, Hash::zero());
+        }
+        other => panic!("{other:?}"),
+    }
+    assert_eq!(chain.block_height(), height);
+    // a block committing to the utxos is taken
+    builder.mine_blocks(1).unwrap();
+    assert_eq!(builder.chain().block_height(), height + 1);
+}
+
+#[test]
+fn snapshot_is_checked_against_the_header() {
+    let mut builder = ChainBuilder::new(SEED);
+    builder.fund(1, &[5000, 7000]).unwrap();
+    let spend = builder.spend(1, 2, 6000, 10).unwrap();
+    builder
+        .mine_block(|template| template.transactions = vec![spend])
+        .unwrap();
+    let chain = builder.chain();
+    let headers: Vec<BlockHeader> = chain.blocks().map(|block| block.header.clone()).collect();
+    let [.., previous, tip] = headers.as_slice() else {
+        panic!("{} blocks", headers.len());
+    };
+    assert_eq!(tip.utxo_commitment, chain.utxo_commitment());
+    let mut snapshot = vec![];
+    chain.export_utxo_snapshot(&mut snapshot).unwrap();
+
+    // the tip's header commits to what the snapshot holds
+    let imported = Blockchain::import_utxo_snapshot_for(&snapshot[..], tip).unwrap();
+    assert_eq!(imported.utxo_commitment(), tip.utxo_commitment);
+    assert_eq!(imported.utxo_count(), chain.utxo_count());
+
+    // a header of another block is not the snapshot's
+    assert!(matches!(
+        Blockchain::import_utxo_snapshot_for(&snapshot[..], previous),
+        Err(SbdError::InvalidBlock)
+    ));
+
+    // a snapshot consistent in itself, an output short of the header's
+    // commitment
+    let (mut header, entries) = read_snapshot(&snapshot[..]).unwrap();
+    header.count -= 1;
+    let mut short = vec![];
+    write_snapshot(&mut short, &header, &entries[1..]).unwrap();
+    Blockchain::import_utxo_snapshot(&short[..]).unwrap();
+    match Blockchain::import_utxo_snapshot_for(&short[..], tip) {
+        Err(SbdError::UtxoCommitmentMismatch { expected, found }) => {
+            assert_eq!(found, tip.utxo_commitment);
+            let hashes: Vec<Hash> = entries[1..].iter().map(|entry| entry.hash).collect();
+            assert_eq!(expected, full(&hashes));
+        }
+        other => panic!("{:?}", other.map(|chain| chain.utxo_count())),
+    }
+}
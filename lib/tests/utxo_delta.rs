@@ -0,0 +1,174 @@
+// add_block applies each block's utxo delta where rebuild_utxos
+// works the set out from every block: over a seeded chain of spends
+// between keys, of one or two inputs and one to three outputs each,
+// paying fees, the set after each block is the rebuilt one, and each
+// delta is exactly what that block changed
+use lib::sha256::Hash;
+use lib::test_utils::ChainBuilder;
+use lib::types::{Blockchain, Transaction, TransactionOutput};
+use std::collections::BTreeMap;
+
+const SEED: u64 = 699;
+const KEYS: usize = 4;
+const BLOCKS: usize = 24;
+const MAX_SPENDS: usize = 4;
+
+// xorshift64*, deterministic for a seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+}
+
+type Entries = BTreeMap<Hash, (TransactionOutput, bool)>;
+
+fn entries(chain: &Blockchain) -> Entries {
+    chain
+        .utxos()
+        .iter()
+        .map(|(hash, output, reserved)| (*hash, (output.clone(), reserved)))
+        .collect()
+}
+
+fn rebuilt(chain: &Blockchain) -> Entries {
+    let mut chain = chain.clone();
+    chain.rebuild_utxos();
+    entries(&chain)
+}
+
+// value split over one to three outputs to random keys, less a fee
+fn split(builder: &mut ChainBuilder, rng: &mut Rng, value: u64) -> Vec<TransactionOutput> {
+    let fee = 1 + rng.below(50) as u64;
+    let mut left = value - fee;
+    let count = 1 + rng.below(3);
+    (0..count)
+        .map(|i| {
+            let paid = if i + 1 == count { left } else { left / 2 };
+            left -= paid;
+            builder.output(paid, rng.below(KEYS))
+        })
+        .collect()
+}
+
+// spends of the next block, each of some of one key's outputs
+fn spends(builder: &mut ChainBuilder, rng: &mut Rng) -> Vec<Transaction> {
+    let mut spent = vec![];
+    let mut transactions = vec![];
+    for _ in 0..rng.below(MAX_SPENDS + 1) {
+        let key = rng.below(KEYS);
+        let available: Vec<(Hash, TransactionOutput)> = builder
+            .spendable(key)
+            .into_iter()
+            .filter(|(hash, output)| !spent.contains(hash) && output.value > 100)
+            .collect();
+        if available.is_empty() {
+            continue;
+        }
+        let count = (1 + rng.below(2)).min(available.len());
+        let inputs = &available[..count];
+        spent.extend(inputs.iter().map(|(hash, _)| *hash));
+        let hashes: Vec<Hash> = inputs.iter().map(|(hash, _)| *hash).collect();
+        let value = inputs.iter().map(|(_, output)| output.value).sum();
+        let outputs = split(builder, rng, value);
+        transactions.push(builder.sign(&hashes, outputs).unwrap());
+    }
+    transactions
+}
+
+#[test]
+fn deltas_match_rebuilds() {
+    let mut rng = Rng(SEED);
+    let mut builder = ChainBuilder::new(SEED).with_keys(KEYS);
+    for key in 0..KEYS {
+        builder.fund(key, &[1_000_000, 300_000, 50_000]).unwrap();
+    }
+    // the utxos of the blocks applied one delta at a time, checked
+    // against the set before and after each
+    let mut applied = Blockchain::with_params(builder.chain().params().clone());
+    for block in builder.chain().blocks() {
+        applied.apply_block_utxo_delta(block);
+    }
+    assert_eq!(entries(&applied), entries(builder.chain()));
+    let (mut multi_input, mut multi_output) = (0, 0);
+    for _ in 0..BLOCKS {
+        let transactions = spends(&mut builder, &mut rng);
+        for transaction in &transactions {
+            multi_input += usize::from(transaction.inputs.len() > 1);
+            multi_output += usize::from(transaction.outputs.len() > 1);
+        }
+        let block = builder
+            .mine_block(|template| template.transactions = transactions)
+            .unwrap();
+        let after = entries(builder.chain());
+        assert_eq!(after, rebuilt(builder.chain()));
+
+        let before = entries(&applied);
+        let delta = applied.apply_block_utxo_delta(&block);
+        assert_eq!(entries(&applied), after);
+        // what it spent was there before and is gone, as it was
+        for (hash, output) in &delta.spent {
+            assert_eq!(before.get(hash).map(|(output, _)| output), Some(&**output));
+            assert!(!after.contains_key(hash));
+        }
+        // what it created is there after, and wasn't before
+        for (hash, output) in &delta.created {
+            assert_eq!(after.get(hash).map(|(output, _)| output), Some(&**output));
+            assert!(!before.contains_key(hash));
+        }
+        // and nothing else changed
+        let mut undone = after.clone();
+        for (hash, _) in &delta.created {
+            undone.remove(hash);
+        }
+        for (hash, output) in &delta.spent {
+            undone.insert(*hash, ((**output).clone(), false));
+        }
+        assert_eq!(undone, before);
+        // the coinbase claimed the fees, so the spends paid some
+        let coinbase: u64 = block.transactions[0]
+            .outputs
+            .iter()
+            .map(|output| output.value)
+            .sum();
+        let height = builder.chain().chain_height() - 1;
+        let reward = builder.chain().params().reward_at_height(height);
+        assert_eq!(coinbase > reward, block.transactions.len() > 1);
+    }
+    // the seed gave the cases worth checking
+    assert!(multi_output >= BLOCKS, "{multi_output} multi-output spends");
+    assert!(
+        multi_input >= BLOCKS / 2,
+        "{multi_input} multi-input spends"
+    );
+}
+
+#[test]
+fn rebuild_keeps_mempool_reservations() {
+    let mut builder = ChainBuilder::new(SEED);
+    builder.fund(0, &[1000, 2000]).unwrap();
+    let spend = builder.spend(0, 1, 1500, 10).unwrap();
+    builder.chain_mut().add_to_mempool(spend.clone()).unwrap();
+    let before = entries(builder.chain());
+    assert_eq!(rebuilt(builder.chain()), before);
+    let reserved: Vec<Hash> = before
+        .iter()
+        .filter(|(_, (_, reserved))| *reserved)
+        .map(|(hash, _)| *hash)
+        .collect();
+    let mut spent: Vec<Hash> = spend
+        .inputs
+        .iter()
+        .map(|input| input.prev_transaction_output_hash)
+        .collect();
+    spent.sort();
+    assert_eq!(reserved, spent);
+}
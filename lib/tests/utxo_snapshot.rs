@@ -0,0 +1,95 @@
+// utxo snapshots: imported, a snapshot holds the utxo set it was
+// exported from, the same chain always exports the same bytes, and
+// a snapshot changed after export fails its commitment
+use lib::error::SbdError;
+use lib::test_utils::ChainBuilder;
+use lib::types::{Blockchain, read_snapshot, write_snapshot};
+use lib::utils::Saveable;
+
+// spends in some blocks, so the set isn't only coinbases
+fn chain() -> Blockchain {
+    let mut builder = ChainBuilder::new(672);
+    builder.mine_blocks(3).unwrap();
+    for to in 1..4 {
+        let spend = builder.spend(0, to, 1000 * to as u64, 10).unwrap();
+        builder
+            .mine_block(|template| template.transactions = vec![spend])
+            .unwrap();
+    }
+    builder.into_chain()
+}
+
+fn export(chain: &Blockchain) -> Vec<u8> {
+    let mut snapshot = vec![];
+    chain.export_utxo_snapshot(&mut snapshot).unwrap();
+    snapshot
+}
+
+#[test]
+fn imported_snapshot_holds_the_exported_utxos() {
+    let chain = chain();
+    let snapshot = export(&chain);
+    let imported = Blockchain::import_utxo_snapshot(&snapshot[..]).unwrap();
+    assert_eq!(imported.utxo_count(), chain.utxo_count());
+    for (hash, output, _) in chain.utxos().iter() {
+        assert_eq!(imported.utxo(hash), Some(output), "{hash}");
+    }
+    assert_eq!(imported.utxo_commitment(), chain.utxo_commitment());
+    assert_eq!(imported.chain_height(), chain.chain_height());
+    assert_eq!(imported.tip_hash(), chain.tip_hash());
+    assert_eq!(imported.target(), chain.target());
+    // and exports the same snapshot again
+    assert_eq!(export(&imported), snapshot);
+}
+
+#[test]
+fn exports_are_byte_identical() {
+    let chain = chain();
+    let first = export(&chain);
+    assert_eq!(export(&chain), first);
+    // a chain loaded from a file holds its utxos in another
+    // map, in whatever order that gives them
+    let mut saved = vec![];
+    chain.save(&mut saved).unwrap();
+    let loaded = Blockchain::load(&saved[..]).unwrap();
+    assert_eq!(export(&loaded), first);
+    let mut rebuilt = chain.clone();
+    rebuilt.rebuild_utxos();
+    assert_eq!(export(&rebuilt), first);
+}
+
+#[test]
+fn tampered_snapshot_fails_its_commitment() {
+    let chain = chain();
+    let snapshot = export(&chain);
+    let (body, commitment) = snapshot.split_at(snapshot.len() - 64);
+    let mismatch = |data: &[u8]| match Blockchain::import_utxo_snapshot(data) {
+        Err(SbdError::SnapshotMismatch { found, .. }) => found,
+        other => panic!("imported {:?}", other.map(|chain| chain.utxo_count())),
+    };
+
+    // an output worth more, written out whole again under
+    // the commitment of the original
+    let (header, mut entries) = read_snapshot(&snapshot[..]).unwrap();
+    entries[0].output.value += 1;
+    let mut tampered = vec![];
+    write_snapshot(&mut tampered, &header, &entries).unwrap();
+    tampered.truncate(tampered.len() - 64);
+    tampered.extend_from_slice(commitment);
+    assert_eq!(mismatch(&tampered).as_bytes(), commitment);
+
+    // an output left out, the count to match
+    let mut header = header;
+    header.count -= 1;
+    let mut tampered = vec![];
+    write_snapshot(&mut tampered, &header, &entries[1..]).unwrap();
+    tampered.truncate(tampered.len() - 64);
+    tampered.extend_from_slice(commitment);
+    mismatch(&tampered);
+
+    // the commitment itself, spelled in capitals
+    let mut tampered = body.to_vec();
+    tampered.extend_from_slice(&commitment.to_ascii_uppercase());
+    assert_ne!(tampered, snapshot);
+    mismatch(&tampered);
+}
@@ -0,0 +1,28 @@
+// the compat block, compiled in, checked with nothing but core: its
+// proof of work and merkle root. under wasm32 it runs in the browser
+// or node through wasm-bindgen-test, see the dev-dependencies in
+// Cargo.toml, and natively as a plain test
+use lib::types::Block;
+use lib::utils::MerkleRoot;
+
+const BLOCK_HEX: &str = include_str!("../compat/block.hex");
+const BLOCK_HASH: &str = "44d09bba98495a920e42f4e549d8b45775fa18b9f87a2c36cf77c342a32be9e9";
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn hard_coded_block_verifies() {
+    let block = Block::from_hex(BLOCK_HEX).unwrap();
+    assert_eq!(block.hash().to_hex(), BLOCK_HASH);
+    assert!(block.header.hash().matches_target(block.header.target));
+    assert_eq!(
+        MerkleRoot::calculate(&block.transactions),
+        block.header.merkle_root
+    );
+    // a transaction dropped no longer matches the root
+    let mut damaged = block.clone();
+    damaged.transactions.pop();
+    assert_ne!(
+        MerkleRoot::calculate(&damaged.transactions),
+        damaged.header.merkle_root
+    );
+}
@@ -0,0 +1,78 @@
+// double spends reported for watched transactions
+use lib::error::SbdError;
+use lib::test_utils::ChainBuilder;
+use lib::types::{ChainEvent, ConflictLocation, Transaction, TransactionInput};
+
+// a watched payment from key 0 to key 1 in the mempool, and the
+// chain it waits in
+fn watched_payment() -> (ChainBuilder, Transaction) {
+    let mut builder = ChainBuilder::new(728);
+    builder.fund(0, &[1000]).unwrap();
+    let payment = builder.spend(0, 1, 600, 10).unwrap();
+    let chain = builder.chain_mut();
+    assert!(chain.watch_transaction(payment.hash()));
+    chain.add_to_mempool(payment.clone()).unwrap();
+    (builder, payment)
+}
+
+// a spend of what the payment spends, paying key 2 instead, that
+// carries the signatures of the payment
+fn reusing_signatures(builder: &mut ChainBuilder, payment: &Transaction) -> Transaction {
+    let outputs = vec![builder.output(990, 2)];
+    let inputs = payment
+        .inputs
+        .iter()
+        .map(|input| TransactionInput {
+            prev_transaction_output_hash: input.prev_transaction_output_hash,
+            signature: input.signature.clone(),
+        })
+        .collect();
+    Transaction::new(inputs, outputs)
+}
+
+#[test]
+fn double_spend_of_watched_transaction_is_reported() {
+    let (mut builder, payment) = watched_payment();
+    let spent: Vec<_> = payment
+        .inputs
+        .iter()
+        .map(|input| input.prev_transaction_output_hash)
+        .collect();
+    let outputs = vec![builder.output(980, 2)];
+    let conflicting = builder.sign(&spent, outputs).unwrap();
+    builder
+        .chain_mut()
+        .add_to_mempool(conflicting.clone())
+        .unwrap();
+    let events = builder.chain_mut().take_events();
+    assert_eq!(events.len(), 1);
+    let ChainEvent::DoubleSpendDetected {
+        original,
+        conflicting: conflicting_hash,
+        location,
+        ..
+    } = &events[0];
+    assert_eq!(*original, payment.hash());
+    assert_eq!(*conflicting_hash, conflicting.hash());
+    assert_eq!(*location, ConflictLocation::Mempool);
+}
+
+#[test]
+fn conflict_reusing_signatures_is_rejected() {
+    let (mut builder, payment) = watched_payment();
+    let forged = reusing_signatures(&mut builder, &payment);
+    assert!(matches!(
+        builder.chain_mut().add_to_mempool(forged.clone()),
+        Err(SbdError::InvalidSignature)
+    ));
+    assert!(builder.chain_mut().take_events().is_empty());
+    // nor may a block carry it
+    let block = builder
+        .block(|template| template.transactions = vec![forged])
+        .unwrap();
+    assert!(matches!(
+        builder.chain_mut().add_block(block),
+        Err(SbdError::InvalidSignature)
+    ));
+    assert!(builder.chain_mut().take_events().is_empty());
+}
@@ -0,0 +1,50 @@
+// a save that fails partway leaves the file it was replacing as it
+// was, and no temporary file behind
+use lib::utils::write_atomically;
+use std::fs;
+use std::io::{Error, ErrorKind, Write};
+use tempfile::TempDir;
+
+const ORIGINAL: &[u8] = b"the original contents";
+
+#[test]
+fn failing_writer_leaves_the_original_untouched() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("file.cbor");
+    fs::write(&path, ORIGINAL).unwrap();
+    // half of a larger file written, flushed even, then an error
+    let result = write_atomically(&path, |writer| {
+        writer.write_all(&[7; 64 * 1024])?;
+        writer.flush()?;
+        Err(Error::other("disk on fire"))
+    });
+    assert_eq!(result.unwrap_err().to_string(), "disk on fire");
+    assert_eq!(fs::read(&path).unwrap(), ORIGINAL);
+    let names: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["file.cbor"]);
+}
+
+#[test]
+fn failing_writer_creates_no_file() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("file.cbor");
+    let result = write_atomically(&path, |writer| {
+        writer.write_all(b"partial")?;
+        Err(Error::from(ErrorKind::WriteZero))
+    });
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::WriteZero);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn successful_writer_replaces_the_original() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("file.cbor");
+    fs::write(&path, ORIGINAL).unwrap();
+    write_atomically(&path, |writer| writer.write_all(b"new")).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"new");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
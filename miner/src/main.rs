@@ -1,37 +1,40 @@
+use clap::Parser;
+use lib::cli::Verbosity;
+use lib::error::Report;
 use lib::types::Block;
 use lib::utils::Saveable;
-use std::env;
-use std::process::exit;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
 
-fn main() {
-    // parse block path and steps count from the
-    // first and second argument respectively
-    let (path, steps) = if let (Some(arg), Some(arg2)) = (env::args().nth(1), env::args().nth(2)) {
-        (arg, arg2)
-    } else {
-        eprintln!("Usage: miner <block_file> <steps>");
-        exit(1);
-    };
-    // parse steps count
-    let steps: usize = if let Ok(s @ 1..=usize::MAX) = steps.parse() {
-        s
-    } else {
-        eprintln!("<steps> should be a positive integer");
-        exit(1);
-    };
+/// Mine a saved block, printing it before and after
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// Block to mine
+    block_file: PathBuf,
+    /// Nonces to try between progress lines
+    steps: NonZeroUsize,
+    // each -v shows more of what mining does
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+fn main() -> Result<(), Report> {
+    let cli = Cli::parse();
+    lib::logging::init(cli.verbosity.verbose);
 
     //load block from a file
-    let og_block = Block::load_from_file(path).expect("Failed to load block");
+    let path = &cli.block_file;
+    let og_block =
+        Block::load_from_file(path).map_err(|e| Report::new(format!("{}: {e}", path.display())))?;
     let mut block = og_block.clone();
 
-    while !block.header.mine(steps) {
+    while !block.header.mine(cli.steps.get()) {
         println!("mining...");
     }
 
-    // print original block and its hash
-    println!("original: {:#?}", og_block);
-    println!("hash: {}", og_block.header.hash());
-    // print mined block and its hash
-    println!("final: {:#?}", block);
-    println!("hash: {}", block.header.hash());
+    // print original and mined block, with their hashes
+    println!("original: {og_block}");
+    println!("final: {block}");
+    Ok(())
 }
@@ -0,0 +1,50 @@
+// the miner as a user runs it: --help, a usage error, which exits 2,
+// and mining a saved block
+use assert_cmd::Command;
+use predicates::prelude::*;
+
+fn miner() -> Command {
+    let mut command = Command::cargo_bin("miner").unwrap();
+    command.current_dir(env!("CARGO_MANIFEST_DIR"));
+    command
+}
+
+#[test]
+fn help() {
+    miner()
+        .arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Usage: miner"));
+}
+
+#[test]
+fn zero_steps_is_a_usage_error() {
+    miner()
+        .args(["../lib/compat/block.cbor", "0"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("<STEPS>"));
+}
+
+#[test]
+fn missing_block_exits_1() {
+    miner()
+        .args(["no-such-block.cbor", "1000"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("no-such-block.cbor"));
+}
+
+// the fixture's target is regtest's, so a few steps find a nonce
+#[test]
+fn mines_a_saved_block() {
+    miner()
+        .args(["../lib/compat/block.cbor", "1000"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("original: block")
+                .and(predicate::str::contains("final: block")),
+        );
+}
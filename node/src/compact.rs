@@ -0,0 +1,250 @@
+use chrono::{DateTime, Duration, Utc};
+use lib::sha256::{Hash, HashKeyedMap};
+use lib::types::{Block, BlockHeader, Transaction};
+use lib::utils::MerkleRoot;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// compact blocks waiting for transactions at once, and from one peer
+pub const MAX_PENDING_BLOCKS: usize = 64;
+pub const MAX_PENDING_BLOCKS_PER_PEER: usize = 4;
+// seconds a compact block waits for its transactions before it's dropped
+pub const PENDING_BLOCK_TIMEOUT: i64 = 30;
+
+// how many compact block transactions were taken from the
+// mempool versus fetched from the announcing peer
+#[derive(Debug, Default)]
+pub struct CompactBlockStats {
+    pub reused: AtomicU64,
+    pub fetched: AtomicU64,
+    pub fallbacks: AtomicU64,
+}
+
+impl CompactBlockStats {
+    pub fn record(&self, reused: usize, fetched: usize) {
+        self.reused.fetch_add(reused as u64, Ordering::Relaxed);
+        self.fetched.fetch_add(fetched as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_fallback(&self) {
+        self.fallbacks.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// a compact block waiting for its missing transactions
+#[derive(Debug, Clone)]
+pub struct PartialBlock {
+    header: BlockHeader,
+    txids: Vec<Hash>,
+    transactions: Vec<Option<Transaction>>,
+    reused: usize,
+}
+
+impl PartialBlock {
+    // fill in every announced transaction found in the mempool
+    pub fn new(
+        header: BlockHeader,
+        txids: Vec<Hash>,
+        mempool: &[(DateTime<Utc>, Transaction)],
+    ) -> Self {
+        let mempool: HashKeyedMap<&Transaction> = mempool
+            .iter()
+            .map(|(_, transaction)| (transaction.hash(), transaction))
+            .collect();
+        let transactions: Vec<Option<Transaction>> = txids
+            .iter()
+            .map(|txid| mempool.get(txid).map(|transaction| (*transaction).clone()))
+            .collect();
+        let reused = transactions.iter().flatten().count();
+        PartialBlock {
+            header,
+            txids,
+            transactions,
+            reused,
+        }
+    }
+
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn reused(&self) -> usize {
+        self.reused
+    }
+
+    // indexes of the transactions we still need
+    pub fn missing(&self) -> Vec<usize> {
+        self.transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, transaction)| transaction.is_none())
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    // fill the missing transactions in order, returns false if
+    // the peer sent the wrong amount of them
+    pub fn fill(&mut self, transactions: Vec<Transaction>) -> bool {
+        let missing = self.missing();
+        if missing.len() != transactions.len() {
+            return false;
+        }
+        for (index, transaction) in missing.into_iter().zip(transactions) {
+            self.transactions[index] = Some(transaction);
+        }
+        true
+    }
+
+    // assemble the block, or None if a transaction is missing,
+    // does not match its announced hash, or the merkle root is off
+    pub fn into_block(self) -> Option<Block> {
+        let transactions: Vec<Transaction> =
+            self.transactions.into_iter().collect::<Option<_>>()?;
+        let hashes_match = transactions
+            .iter()
+            .zip(&self.txids)
+            .all(|(transaction, txid)| transaction.hash() == *txid);
+        if !hashes_match || MerkleRoot::calculate(&transactions) != self.header.merkle_root {
+            return None;
+        }
+        Some(Block::new(self.header, transactions))
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    peer: SocketAddr,
+    received: DateTime<Utc>,
+    partial: PartialBlock,
+}
+
+// compact blocks waiting for the transactions asked of the peer that
+// sent them, by header hash; bounded in total and per peer, the
+// oldest making room, and times are passed in by the caller
+#[derive(Debug, Default)]
+pub struct PendingBlocks {
+    blocks: HashKeyedMap<Pending>,
+}
+
+impl PendingBlocks {
+    pub fn insert(&mut self, peer: SocketAddr, partial: PartialBlock, now: DateTime<Utc>) {
+        let hash = partial.header().hash();
+        self.blocks.remove(&hash);
+        let from_peer = self.blocks.values().filter(|p| p.peer == peer).count();
+        if from_peer >= MAX_PENDING_BLOCKS_PER_PEER {
+            self.evict_oldest(|pending| pending.peer == peer);
+        }
+        if self.blocks.len() >= MAX_PENDING_BLOCKS {
+            self.evict_oldest(|_| true);
+        }
+        self.blocks.insert(
+            hash,
+            Pending {
+                peer,
+                received: now,
+                partial,
+            },
+        );
+    }
+
+    // the block, if it's the peer's to complete
+    pub fn take(&mut self, peer: SocketAddr, hash: &Hash) -> Option<PartialBlock> {
+        if self.blocks.get(hash)?.peer != peer {
+            return None;
+        }
+        self.blocks.remove(hash).map(|pending| pending.partial)
+    }
+
+    // drop the blocks that waited too long, returns how many
+    pub fn expire(&mut self, now: DateTime<Utc>) -> usize {
+        let before = self.blocks.len();
+        let cutoff = now - Duration::seconds(PENDING_BLOCK_TIMEOUT);
+        self.blocks.retain(|_, pending| pending.received > cutoff);
+        before - self.blocks.len()
+    }
+
+    // its transactions will never come
+    pub fn peer_disconnected(&mut self, peer: SocketAddr) {
+        self.blocks.retain(|_, pending| pending.peer != peer);
+    }
+
+    fn evict_oldest(&mut self, matches: impl Fn(&Pending) -> bool) {
+        let oldest = self
+            .blocks
+            .iter()
+            .filter(|(_, pending)| matches(pending))
+            .min_by_key(|(_, pending)| pending.received)
+            .map(|(hash, _)| *hash);
+        if let Some(hash) = oldest {
+            self.blocks.remove(&hash);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(nonce: u64) -> PartialBlock {
+        let header = BlockHeader::builder(MerkleRoot::calculate(&[]))
+            .timestamp(DateTime::UNIX_EPOCH)
+            .nonce(nonce)
+            .build();
+        PartialBlock::new(header, vec![Hash::zero()], &[])
+    }
+
+    fn peer(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    fn seconds(seconds: i64) -> DateTime<Utc> {
+        DateTime::UNIX_EPOCH + Duration::seconds(seconds)
+    }
+
+    #[test]
+    fn a_peer_only_holds_its_share() {
+        let mut pending = PendingBlocks::default();
+        for nonce in 0..=MAX_PENDING_BLOCKS_PER_PEER as u64 {
+            pending.insert(peer(1), partial(nonce), seconds(nonce as i64));
+        }
+        // the oldest made room
+        let oldest = partial(0).header().hash();
+        assert!(pending.take(peer(1), &oldest).is_none());
+        for nonce in 1..=MAX_PENDING_BLOCKS_PER_PEER as u64 {
+            let hash = partial(nonce).header().hash();
+            assert!(pending.take(peer(1), &hash).is_some());
+        }
+    }
+
+    #[test]
+    fn the_oldest_of_all_makes_room() {
+        let mut pending = PendingBlocks::default();
+        for nonce in 0..=MAX_PENDING_BLOCKS as u64 {
+            pending.insert(peer(nonce as u16), partial(nonce), seconds(nonce as i64));
+        }
+        assert_eq!(pending.blocks.len(), MAX_PENDING_BLOCKS);
+        assert!(pending.take(peer(0), &partial(0).header().hash()).is_none());
+        assert!(pending.take(peer(1), &partial(1).header().hash()).is_some());
+    }
+
+    #[test]
+    fn only_the_sender_completes_a_block() {
+        let mut pending = PendingBlocks::default();
+        let hash = partial(7).header().hash();
+        pending.insert(peer(1), partial(7), seconds(0));
+        assert!(pending.take(peer(2), &hash).is_none());
+        assert!(pending.take(peer(1), &hash).is_some());
+    }
+
+    #[test]
+    fn blocks_expire_and_leave_with_their_peer() {
+        let mut pending = PendingBlocks::default();
+        pending.insert(peer(1), partial(1), seconds(0));
+        pending.insert(peer(2), partial(2), seconds(20));
+        pending.insert(peer(3), partial(3), seconds(25));
+        assert_eq!(pending.expire(seconds(PENDING_BLOCK_TIMEOUT)), 1);
+        pending.peer_disconnected(peer(2));
+        assert!(pending.take(peer(2), &partial(2).header().hash()).is_none());
+        assert!(pending.take(peer(3), &partial(3).header().hash()).is_some());
+    }
+}
@@ -0,0 +1,380 @@
+use crate::peers;
+use crate::ratelimit::RateLimits;
+use clap::Parser;
+use lib::cli::{NetworkArgs, Verbosity};
+use lib::crypto::PublicKey;
+use lib::params::Network;
+use lib::utils::BackupPolicy;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+/// Run a node, with the config file if given and these overrides applied
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct NodeArgs {
+    /// Toml file of the settings, the defaults if not given
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Where the chain, mempool and address book are kept
+    #[arg(long, value_name = "DIR")]
+    datadir: Option<PathBuf>,
+    #[command(flatten)]
+    network: NetworkArgs,
+    /// Address to accept peers on
+    #[arg(long, value_name = "IP")]
+    listen: Option<IpAddr>,
+    /// Port to accept peers on, 0 for any free one
+    #[arg(long)]
+    port: Option<u16>,
+    /// Most inbound connections at once
+    #[arg(long, value_name = "N")]
+    max_inbound: Option<usize>,
+    /// Most outbound connections at once
+    #[arg(long, value_name = "N")]
+    max_outbound: Option<usize>,
+    /// Outbound connections to try to keep open
+    #[arg(long, value_name = "N")]
+    target_outbound: Option<usize>,
+    /// Address of a separate listener for administrative messages
+    #[arg(long, value_name = "IP:PORT")]
+    rpc_bind: Option<SocketAddr>,
+    /// Serve block templates to miners
+    #[arg(long)]
+    mine: bool,
+    /// Peer to try before any other
+    #[arg(long, value_name = "IP:PORT", value_parser = parse_node)]
+    addnode: Vec<String>,
+    /// File of more peers to try first, one address per line
+    #[arg(long, value_name = "FILE")]
+    peers_file: Option<PathBuf>,
+    /// Zstd compress the block store manifests
+    #[arg(long)]
+    compress: bool,
+    /// Bundle of signed checkpoints to hold the chain to
+    #[arg(long, value_name = "FILE")]
+    checkpoint_file: Option<PathBuf>,
+    /// Address of an operator key whose checkpoints are trusted
+    #[arg(long, value_name = "ADDRESS", value_parser = parse_checkpoint_key)]
+    checkpoint_key: Vec<String>,
+    /// Where copies of files go before they're rewritten
+    #[arg(long, value_name = "DIR")]
+    backup_dir: Option<PathBuf>,
+    /// Copies of each file to keep
+    #[arg(long, value_name = "N")]
+    keep_backups: Option<usize>,
+    #[command(flatten)]
+    verbosity: Verbosity,
+    /// Peers to try first, like --addnode
+    nodes: Vec<String>,
+}
+
+fn parse_checkpoint_key(address: &str) -> Result<String, String> {
+    PublicKey::from_address(address).ok_or("expected the address of a public key")?;
+    Ok(address.to_string())
+}
+
+fn parse_node(address: &str) -> Result<String, String> {
+    if !peers::is_valid_address(address) {
+        return Err("expected ip:port or host:port".to_string());
+    }
+    Ok(address.to_string())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    pub network: Network,
+    pub listen_address: IpAddr,
+    // 0 picks a free port, which is handy for tests
+    pub port: u16,
+    pub max_inbound: usize,
+    pub max_outbound: usize,
+    // outbound connections the node tries to keep open
+    pub target_outbound: usize,
+    pub datadir: PathBuf,
+    // serve block templates to miners
+    pub mining: bool,
+    // separate listener for administrative messages
+    pub rpc_bind: Option<SocketAddr>,
+    pub ban_threshold: u32,
+    // seconds between pings to every peer
+    pub ping_interval: u64,
+    // manual peers, tried before any other address
+    pub nodes: Vec<String>,
+    // file with more manual peers, one address per line
+    pub peers_file: Option<PathBuf>,
+    pub rate_limits: RateLimits,
+    // zstd compress the block store manifests
+    pub compress: bool,
+    // where copies of the manifest, mempool and address book
+    // go before they're rewritten, no backups if unset
+    pub backup_dir: Option<PathBuf>,
+    pub keep_backups: usize,
+    // bundle of signed checkpoints applied on startup, and the
+    // addresses of the operator keys trusted to sign them
+    pub checkpoint_file: Option<PathBuf>,
+    pub checkpoint_keys: Vec<String>,
+    // library events shown past warnings, one level per -v;
+    // RUST_LOG overrides it
+    pub verbosity: u8,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            network: Network::Mainnet,
+            listen_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 9000,
+            max_inbound: 117,
+            max_outbound: 11,
+            target_outbound: 8,
+            datadir: env::var_os("HOME")
+                .map(PathBuf::from)
+                .unwrap_or_default()
+                .join(".ssebidecoin"),
+            mining: false,
+            rpc_bind: None,
+            ban_threshold: 100,
+            ping_interval: 120,
+            nodes: vec![],
+            peers_file: None,
+            rate_limits: RateLimits::default(),
+            compress: false,
+            backup_dir: None,
+            keep_backups: 5,
+            checkpoint_file: None,
+            checkpoint_keys: vec![],
+            verbosity: 0,
+        }
+    }
+}
+
+impl NodeConfig {
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read config {}: {e}", path.display()))?;
+        toml::from_str(&contents).map_err(|e| format!("invalid config {}: {e}", path.display()))
+    }
+
+    // config file (if any) with command line overrides applied
+    pub fn from_args(args: NodeArgs) -> Result<Self, String> {
+        let mut config = match &args.config {
+            Some(path) => NodeConfig::load_from_file(path)?,
+            None => NodeConfig::default(),
+        };
+        config.datadir = args.datadir.unwrap_or(config.datadir);
+        config.network = args.network.network.unwrap_or(config.network);
+        config.listen_address = args.listen.unwrap_or(config.listen_address);
+        config.port = args.port.unwrap_or(config.port);
+        config.max_inbound = args.max_inbound.unwrap_or(config.max_inbound);
+        config.max_outbound = args.max_outbound.unwrap_or(config.max_outbound);
+        config.target_outbound = args.target_outbound.unwrap_or(config.target_outbound);
+        config.rpc_bind = args.rpc_bind.or(config.rpc_bind);
+        config.peers_file = args.peers_file.or(config.peers_file);
+        config.backup_dir = args.backup_dir.or(config.backup_dir);
+        config.keep_backups = args.keep_backups.unwrap_or(config.keep_backups);
+        config.checkpoint_file = args.checkpoint_file.or(config.checkpoint_file);
+        config.checkpoint_keys.extend(args.checkpoint_key);
+        config.mining |= args.mine;
+        config.compress |= args.compress;
+        config.verbosity = config.verbosity.saturating_add(args.verbosity.verbose);
+        config.nodes.extend(args.addnode);
+        config.nodes.extend(args.nodes);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.target_outbound > self.max_outbound {
+            return Err(format!(
+                "target_outbound ({}) can't exceed max_outbound ({})",
+                self.target_outbound, self.max_outbound
+            ));
+        }
+        if self.ban_threshold == 0 {
+            return Err("ban_threshold must be at least 1".to_string());
+        }
+        if self.ping_interval == 0 {
+            return Err("ping_interval must be at least 1 second".to_string());
+        }
+        if self
+            .rpc_bind
+            .is_some_and(|rpc| rpc.port() != 0 && rpc.port() == self.port)
+        {
+            return Err("rpc_bind can't use the same port as the node".to_string());
+        }
+        if self.checkpoint_file.is_some() && self.checkpoint_keys.is_empty() {
+            return Err("checkpoint_file needs checkpoint_keys to trust".to_string());
+        }
+        self.trusted_checkpoint_keys()?;
+        let limits = &self.rate_limits;
+        for (name, limit) in [
+            ("control", limits.control),
+            ("inventory", limits.inventory),
+            ("data_request", limits.data_request),
+        ] {
+            if limits.enabled && (limit.burst == 0 || limit.per_second <= 0.0) {
+                return Err(format!("rate limit {name} must allow some messages"));
+            }
+        }
+        Ok(())
+    }
+
+    // configured nodes plus the ones in the peers file
+    pub fn manual_peers(&self) -> Result<Vec<String>, String> {
+        let mut nodes = self.nodes.clone();
+        if let Some(path) = &self.peers_file {
+            let contents = fs::read_to_string(path)
+                .map_err(|e| format!("failed to read peers file {}: {e}", path.display()))?;
+            nodes.extend(peers::parse_peers_file(&contents));
+        }
+        Ok(nodes)
+    }
+
+    // the keys of checkpoint_keys
+    pub fn trusted_checkpoint_keys(&self) -> Result<Vec<PublicKey>, String> {
+        self.checkpoint_keys
+            .iter()
+            .map(|address| {
+                PublicKey::from_address(address)
+                    .ok_or_else(|| format!("invalid checkpoint key {address}"))
+            })
+            .collect()
+    }
+
+    pub fn backup_policy(&self) -> Option<BackupPolicy> {
+        self.backup_dir
+            .as_ref()
+            .map(|dir| BackupPolicy::new(dir, self.keep_backups))
+    }
+
+    // test networks keep their files apart from mainnet
+    pub fn network_datadir(&self) -> PathBuf {
+        match self.network {
+            Network::Mainnet => self.datadir.clone(),
+            network => self.datadir.join(network.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    // the config of a file of toml, with args after the binary name
+    fn config(toml: &str, args: &[&str]) -> Result<NodeConfig, String> {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("node.toml");
+        fs::write(&path, toml).unwrap();
+        let path = path.to_str().unwrap();
+        NodeConfig::from_args(NodeArgs::parse_from(
+            ["node", "--config", path].iter().chain(args),
+        ))
+    }
+
+    const FILE: &str = r#"
+        network = "regtest"
+        port = 9100
+        max_inbound = 5
+        nodes = ["10.0.0.1:9000"]
+        verbosity = 1
+
+        [rate_limits.control]
+        burst = 7
+        per_second = 0.5
+    "#;
+
+    #[test]
+    fn file_settings_are_read() {
+        let config = config(FILE, &[]).unwrap();
+        assert_eq!(config.network, Network::Regtest);
+        assert_eq!(config.port, 9100);
+        assert_eq!(config.max_inbound, 5);
+        assert_eq!(config.nodes, ["10.0.0.1:9000"]);
+        assert_eq!(config.rate_limits.control.burst, 7);
+        // what the file leaves out keeps its default
+        let defaults = NodeConfig::default();
+        assert_eq!(config.max_outbound, defaults.max_outbound);
+        assert_eq!(
+            config.rate_limits.inventory.burst,
+            defaults.rate_limits.inventory.burst
+        );
+        assert!(!config.mining);
+    }
+
+    #[test]
+    fn args_override_the_file() {
+        let config = config(
+            FILE,
+            &[
+                "--port",
+                "9200",
+                "--network",
+                "testnet",
+                "--addnode",
+                "10.0.0.2:9000",
+                "--mine",
+                "-v",
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.port, 9200);
+        assert_eq!(config.network, Network::Testnet);
+        assert_eq!(config.max_inbound, 5);
+        // lists and counts add to the file's
+        assert_eq!(config.nodes, ["10.0.0.1:9000", "10.0.0.2:9000"]);
+        assert_eq!(config.verbosity, 2);
+        assert!(config.mining);
+    }
+
+    // good lines are taken in order after --addnode, bad ones skipped
+    #[test]
+    fn peers_file_is_merged_with_addnode() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/peers.txt");
+        let config = config("", &["--addnode", "10.0.0.2:9000", "--peers-file", path]).unwrap();
+        assert_eq!(
+            config.manual_peers().unwrap(),
+            ["10.0.0.2:9000", "127.0.0.1:1", "localhost:1", "[::1]:1"]
+        );
+    }
+
+    #[test]
+    fn missing_peers_file_is_an_error() {
+        let config = config("", &["--peers-file", "/nonexistent/peers.txt"]).unwrap();
+        let e = config.manual_peers().unwrap_err();
+        assert!(e.starts_with("failed to read peers file"), "{e}");
+    }
+
+    #[test]
+    fn unknown_setting_is_rejected() {
+        let e = config("max_inbund = 5", &[]).unwrap_err();
+        assert!(e.starts_with("invalid config"), "{e}");
+        assert!(e.contains("max_inbund"), "{e}");
+    }
+
+    #[test]
+    fn setting_of_the_wrong_type_is_rejected() {
+        let e = config("port = \"ninety\"", &[]).unwrap_err();
+        assert!(e.starts_with("invalid config"), "{e}");
+    }
+
+    #[test]
+    fn inconsistent_settings_are_rejected() {
+        let e = config("max_outbound = 2\ntarget_outbound = 3", &[]).unwrap_err();
+        assert_eq!(e, "target_outbound (3) can't exceed max_outbound (2)");
+        // nor does an override make a valid file invalid unnoticed
+        let e = config("max_outbound = 4", &["--target-outbound", "5"]).unwrap_err();
+        assert_eq!(e, "target_outbound (5) can't exceed max_outbound (4)");
+        let e = config(
+            "[rate_limits.data_request]\nburst = 0\nper_second = 1.0",
+            &[],
+        )
+        .unwrap_err();
+        assert_eq!(e, "rate limit data_request must allow some messages");
+    }
+}
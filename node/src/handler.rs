@@ -0,0 +1,920 @@
+use crate::Node;
+use crate::compact::PartialBlock;
+use crate::inventory;
+use crate::peers::{self, Counted, Direction, Negotiated, Offense, PeerHandle, PeerStats};
+use crate::ratelimit::PeerLimiter;
+use crate::sync::NextStep;
+use chrono::Utc;
+use lib::crypto::PublicKey;
+use lib::error::SbdError;
+use lib::network::{InvItem, InvKind, Message, Services};
+use lib::sha256::Hash;
+use lib::types::{Block, BlockHeader, Transaction, TransactionOutput};
+use std::io::ErrorKind as IoErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, info_span, warn};
+use uuid::Uuid;
+
+// messages queued for a peer before it counts as too slow
+const SEND_QUEUE_SIZE: usize = 1024;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// one peer connection as seen by the message handlers
+struct Session {
+    peer: SocketAddr,
+    direction: Direction,
+    negotiated: Negotiated,
+    sender: mpsc::Sender<Message>,
+}
+
+impl Session {
+    async fn reply(&self, message: Message) {
+        if self.sender.send(message).await.is_err() {
+            debug!(peer = %self.peer, "failed to send message, connection closed");
+        }
+    }
+}
+
+// the caller must have reserved a slot for the direction
+pub async fn handle_connection(
+    node: Arc<Node>,
+    stream: TcpStream,
+    peer: SocketAddr,
+    direction: Direction,
+) {
+    let stats = Arc::new(PeerStats::default());
+    let shutdown = node.shutdown.child_token();
+    let (reader, writer) = stream.into_split();
+    let mut reader = Counted::new(reader, stats.clone());
+    let (sender, receiver) = mpsc::channel(SEND_QUEUE_SIZE);
+    let writer = tokio::spawn(write_loop(
+        node.params.magic,
+        Counted::new(writer, stats.clone()),
+        receiver,
+        shutdown.clone(),
+    ));
+    let handshake = tokio::select! {
+        // a node shutting down doesn't wait out a silent peer
+        _ = shutdown.cancelled() => None,
+        result = timeout(HANDSHAKE_TIMEOUT, handshake(&node, &sender, &mut reader)) => Some(result),
+    };
+    match handshake {
+        None => {}
+        Some(Ok(Ok((peer_height, negotiated)))) => {
+            let session = Session {
+                peer,
+                direction,
+                negotiated,
+                sender,
+            };
+            // keep a handle around for broadcasting
+            if direction != Direction::Rpc {
+                node.peers.lock().unwrap().add_connection(
+                    peer,
+                    PeerHandle {
+                        sender: session.sender.clone(),
+                        stats,
+                        negotiated,
+                        shutdown: shutdown.clone(),
+                        filter: None,
+                    },
+                );
+            }
+            start_sync(&node, &session, peer_height).await;
+            message_loop(&node, &session, &mut reader, &shutdown).await;
+        }
+        Some(Ok(Err(offense))) => {
+            warn!(%peer, ?offense, "handshake failed");
+            penalize(&node, peer, offense);
+        }
+        Some(Err(_)) => info!(%peer, "handshake timed out"),
+    }
+    {
+        let mut peers = node.peers.lock().unwrap();
+        if direction != Direction::Rpc {
+            peers.remove_connection(&peer);
+        }
+        peers.release_slot(direction);
+    }
+    shutdown.cancel();
+    let _ = writer.await;
+    if direction == Direction::Outbound {
+        node.outbound.disconnected(&peer);
+    }
+    node.pending_blocks.lock().unwrap().peer_disconnected(peer);
+    // ask other peers for what this one still owed us
+    let requests = node
+        .inventory
+        .lock()
+        .unwrap()
+        .peer_disconnected(peer, Utc::now());
+    inventory::send_requests(&node, requests);
+}
+
+// writes queued messages until the connection is shut down
+async fn write_loop(
+    magic: [u8; 4],
+    mut writer: Counted<OwnedWriteHalf>,
+    mut messages: mpsc::Receiver<Message>,
+    shutdown: CancellationToken,
+) {
+    loop {
+        let message = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            message = messages.recv() => match message {
+                Some(message) => message,
+                None => break,
+            },
+        };
+        let result = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            result = message.async_write_to(magic, &mut writer) => result,
+        };
+        if let Err(e) = result {
+            debug!(error = %e, "failed to send message");
+            break;
+        }
+    }
+    // a broken writer ends the whole connection
+    shutdown.cancel();
+    let _ = writer.shutdown().await;
+}
+
+// both sides open with a Version message for the same network,
+// returns the block height the peer reported and what the
+// connection supports
+async fn handshake(
+    node: &Node,
+    sender: &mpsc::Sender<Message>,
+    reader: &mut Counted<OwnedReadHalf>,
+) -> Result<(u64, Negotiated), Offense> {
+    let magic = node.params.magic;
+    let block_height = node.blockchain.read().await.block_height();
+    sender
+        .send(Message::Version {
+            magic,
+            version: lib::PROTOCOL_VERSION,
+            services: Services::ALL,
+            block_height,
+        })
+        .await
+        .map_err(|_| Offense::ProtocolViolation)?;
+    match Message::async_read_from(magic, reader).await {
+        Ok(Message::Version {
+            magic: peer_magic,
+            version,
+            services,
+            block_height,
+        }) if peer_magic == magic => {
+            let negotiated = Negotiated {
+                version: version.min(lib::PROTOCOL_VERSION),
+                services: services.intersection(Services::ALL),
+            };
+            Ok((block_height, negotiated))
+        }
+        Ok(_) | Err(_) => Err(Offense::ProtocolViolation),
+    }
+}
+
+// catch up with the peer and learn what it knows
+async fn start_sync(node: &Node, session: &Session, peer_height: u64) {
+    let block_height = node.blockchain.read().await.block_height();
+    if peer_height > block_height {
+        if session
+            .negotiated
+            .services
+            .contains(Services::HEADERS_FIRST)
+        {
+            // sync headers first from peers that are ahead of us
+            let locator = node.header_sync.lock().unwrap().locator();
+            session.reply(Message::GetHeaders { locator }).await;
+        } else {
+            // older peers only serve blocks by height
+            for height in block_height..peer_height {
+                session.reply(Message::FetchBlock(height as usize)).await;
+            }
+        }
+    }
+    // learn about more nodes from peers
+    if session.direction != Direction::Rpc {
+        session.reply(Message::DiscoverNodes).await;
+    }
+    // fill our mempool from what the peer has pending
+    if session.negotiated.services.contains(Services::INVENTORY) {
+        session.reply(Message::GetMempool { min_fee: None }).await;
+    }
+}
+
+async fn message_loop(
+    node: &Node,
+    session: &Session,
+    reader: &mut Counted<OwnedReadHalf>,
+    shutdown: &CancellationToken,
+) {
+    let peer = session.peer;
+    let magic = node.params.magic;
+    let mut limiter = PeerLimiter::new(&node.config.rate_limits);
+    loop {
+        let received = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            received = Message::async_read_from(magic, reader) => received,
+        };
+        let result = match received {
+            // messages for features the peer never advertised
+            Ok(message)
+                if !session
+                    .negotiated
+                    .services
+                    .contains(message.required_services()) =>
+            {
+                Err(Offense::ProtocolViolation)
+            }
+            Ok(message) => match limiter.check(&message) {
+                Ok(true) => handle_message(node, session, message).await,
+                // over the limit, drop the message
+                Ok(false) => Ok(()),
+                Err(offense) => Err(offense),
+            },
+            // a corrupt or oversize frame leaves the stream out
+            // of sync, so it cannot be trusted anymore
+            Err(ciborium::de::Error::Io(e)) if e.kind() == IoErrorKind::InvalidData => {
+                warn!(%peer, error = %e, "disconnecting");
+                penalize(node, peer, Offense::ProtocolViolation);
+                break;
+            }
+            // connection closed or broken
+            Err(ciborium::de::Error::Io(_)) => break,
+            Err(_) => Err(Offense::MalformedMessage),
+        };
+        if let Err(offense) = result
+            && penalize(node, peer, offense)
+        {
+            break;
+        }
+    }
+}
+
+// score an offense, returns true if the peer got banned
+fn penalize(node: &Node, peer: SocketAddr, offense: Offense) -> bool {
+    let banned = node.peers.lock().unwrap().misbehaving(peer.ip(), offense);
+    if banned {
+        node.save_address_book();
+    }
+    banned
+}
+
+// how far our height is ahead of the peer's, saturating at what the
+// message can carry rather than wrapping
+fn difference(block_height: u64, height: u32) -> i32 {
+    let difference = i64::try_from(block_height)
+        .unwrap_or(i64::MAX)
+        .saturating_sub(i64::from(height));
+    i32::try_from(difference).unwrap_or(if difference < 0 { i32::MIN } else { i32::MAX })
+}
+
+// administrative messages are only accepted from the local
+// machine or over the rpc listener
+fn require_local(session: &Session) -> Result<(), Offense> {
+    if session.direction == Direction::Rpc || session.peer.ip().is_loopback() {
+        Ok(())
+    } else {
+        Err(Offense::ProtocolViolation)
+    }
+}
+
+async fn handle_message(node: &Node, session: &Session, message: Message) -> Result<(), Offense> {
+    use Message::*;
+    let peer = session.peer;
+    match message {
+        FetchUTXOs(pubkey) => {
+            let blockchain = node.blockchain.read().await;
+            let utxos = blockchain
+                .utxos()
+                .iter()
+                .filter(|(_, output, _)| output.pubkey == pubkey)
+                .map(|(_, output, reserved)| (output.clone(), reserved))
+                .collect();
+            session.reply(UTXOs(utxos)).await;
+        }
+        SubmitTransaction(transaction) => {
+            let hash = transaction.hash();
+            let reply = match handle_transaction(node, peer, transaction).await {
+                Ok(()) => TransactionAccepted(hash),
+                Err(e) => TransactionRejected {
+                    hash,
+                    reason: e.to_string(),
+                },
+            };
+            session.reply(reply).await;
+        }
+        SendRawTransaction(hex) => {
+            let reply = match Transaction::from_hex(&hex) {
+                Ok(transaction) => {
+                    let hash = transaction.hash();
+                    match handle_transaction(node, peer, transaction).await {
+                        Ok(()) => TransactionAccepted(hash),
+                        Err(e) => TransactionRejected {
+                            hash,
+                            reason: e.to_string(),
+                        },
+                    }
+                }
+                Err(e) => TransactionRejected {
+                    hash: Hash::zero(),
+                    reason: e.to_string(),
+                },
+            };
+            session.reply(reply).await;
+        }
+        GetRawTransaction(hash) => {
+            let hex = node
+                .blockchain
+                .read()
+                .await
+                .find_transaction(&hash)
+                .map(Transaction::to_hex);
+            session.reply(RawTransaction { hash, hex }).await;
+        }
+        NewTransaction(transaction) => {
+            if let Err(SbdError::InvalidSignature) =
+                handle_transaction(node, peer, transaction).await
+            {
+                return Err(Offense::InvalidSignature);
+            }
+        }
+        GetTransactionStatus(hash) => {
+            let confirmations = node.blockchain.read().await.confirmations(&hash);
+            session
+                .reply(TransactionStatus {
+                    hash,
+                    confirmations,
+                })
+                .await;
+        }
+        FetchTemplate(pubkey) => {
+            if !node.config.mining {
+                info!(%peer, "mining is disabled, ignoring template request");
+                return Ok(());
+            }
+            let template = create_template(node, pubkey).await;
+            session.reply(Template(template)).await;
+        }
+        ValidateTemplate(block) => {
+            let blockchain = node.blockchain.read().await;
+            let tip = blockchain
+                .blocks()
+                .last()
+                .map(|block| block.hash())
+                .unwrap_or_else(Hash::zero);
+            session
+                .reply(TemplateValidity(block.header.prev_block_hash == tip))
+                .await;
+        }
+        SubmitTemplate(block) => {
+            if !node.config.mining {
+                info!(%peer, "mining is disabled, ignoring submitted template");
+                return Ok(());
+            }
+            handle_block(node, peer, block).await?;
+        }
+        NewBlock(block) => {
+            handle_block(node, peer, block).await?;
+        }
+        DiscoverNodes => {
+            let nodes = node
+                .peers
+                .lock()
+                .unwrap()
+                .address_book()
+                .nodes
+                .iter()
+                .take(lib::MAX_NODE_LIST)
+                .cloned()
+                .collect();
+            session.reply(NodeList(nodes)).await;
+        }
+        NodeList(nodes) => {
+            if nodes.len() > lib::MAX_NODE_LIST {
+                return Err(Offense::ProtocolViolation);
+            }
+            let mut peers = node.peers.lock().unwrap();
+            for address in nodes {
+                if peers::is_valid_address(&address) {
+                    peers.add_node(address);
+                }
+            }
+            drop(peers);
+            node.outbound.wake();
+        }
+        AskDifference(height) => {
+            let block_height = node.blockchain.read().await.block_height();
+            session
+                .reply(Difference(difference(block_height, height)))
+                .await;
+        }
+        FetchBlock(height) => {
+            let block = node.blockchain.read().await.blocks().nth(height).cloned();
+            if let Some(block) = block {
+                session.reply(NewBlock(block)).await;
+            }
+        }
+        CompactBlock { header, txids } => {
+            let item = InvItem {
+                kind: InvKind::Block,
+                hash: header.hash(),
+            };
+            node.inventory.lock().unwrap().received(peer, item);
+            handle_compact_block(node, session, header, txids).await?;
+        }
+        GetBlockTxn {
+            block_hash,
+            indexes,
+        } => {
+            let blockchain = node.blockchain.read().await;
+            let Some(block) = blockchain
+                .blocks()
+                .find(|block| block.header.hash() == block_hash)
+            else {
+                return Ok(());
+            };
+            let transactions = indexes
+                .iter()
+                .map(|index| block.transactions.get(*index).cloned())
+                .collect::<Option<Vec<_>>>()
+                .ok_or(Offense::ProtocolViolation)?;
+            session
+                .reply(BlockTxn {
+                    block_hash,
+                    transactions,
+                })
+                .await;
+        }
+        BlockTxn {
+            block_hash,
+            transactions,
+        } => {
+            let partial = node.pending_blocks.lock().unwrap().take(peer, &block_hash);
+            // asked of another peer, or dropped while waiting
+            let Some(mut partial) = partial else {
+                debug!(%peer, %block_hash, "ignoring transactions for a block no longer pending");
+                return Ok(());
+            };
+            let fetched = transactions.len();
+            if !partial.fill(transactions) {
+                return Err(Offense::ProtocolViolation);
+            }
+            finish_compact_block(node, session, partial, fetched).await?;
+        }
+        FetchBlockByHash(hash) => {
+            let block = node
+                .blockchain
+                .read()
+                .await
+                .blocks()
+                .find(|block| block.hash() == hash)
+                .cloned();
+            if let Some(block) = block {
+                session.reply(NewBlock(block)).await;
+            }
+        }
+        GetHeaders { locator } => {
+            let headers = {
+                let blockchain = node.blockchain.read().await;
+                // start after the highest locator hash we know, or at genesis
+                let start = blockchain
+                    .find_fork_point(&locator)
+                    .map_or(0, |height| height + 1);
+                blockchain.headers_from(start, lib::MAX_HEADERS_PER_MESSAGE)
+            };
+            session.reply(Headers(headers)).await;
+        }
+        Headers(headers) => {
+            if headers.len() > lib::MAX_HEADERS_PER_MESSAGE {
+                return Err(Offense::ProtocolViolation);
+            }
+            let block_height = node.blockchain.read().await.block_height();
+            let next = node
+                .header_sync
+                .lock()
+                .unwrap()
+                .add_headers(block_height, headers)?;
+            match next {
+                NextStep::MoreHeaders(locator) => session.reply(GetHeaders { locator }).await,
+                NextStep::Bodies(hashes) => {
+                    if !hashes.is_empty() {
+                        info!(blocks = hashes.len(), "headers synced, fetching bodies");
+                    }
+                    for hash in hashes {
+                        session.reply(FetchBlockByHash(hash)).await;
+                    }
+                }
+            }
+        }
+        SetBan(address, seconds) => {
+            require_local(session)?;
+            let Ok(ip) = address.parse::<IpAddr>() else {
+                warn!(%address, "cannot ban invalid address");
+                let reason = "invalid address".to_string();
+                session.reply(BanRefused { address, reason }).await;
+                return Ok(());
+            };
+            let Some(seconds) = i64::try_from(seconds)
+                .ok()
+                .filter(|seconds| *seconds <= peers::MAX_BAN_DURATION)
+            else {
+                warn!(%address, seconds, "cannot ban for that long");
+                let reason = format!("bans last at most {} seconds", peers::MAX_BAN_DURATION);
+                session.reply(BanRefused { address, reason }).await;
+                return Ok(());
+            };
+            node.peers.lock().unwrap().ban(ip, seconds);
+            node.save_address_book();
+        }
+        AddNode(address) => {
+            require_local(session)?;
+            if !peers::is_valid_address(&address) {
+                warn!(%address, "cannot add invalid address");
+                return Ok(());
+            }
+            node.peers.lock().unwrap().add_manual_node(address);
+            node.save_address_book();
+            node.outbound.wake();
+        }
+        GetPeerInfo => {
+            require_local(session)?;
+            let peers = node.peers.lock().unwrap().peer_info();
+            session.reply(PeerList(peers)).await;
+        }
+        ListBanned => {
+            require_local(session)?;
+            let bans = node
+                .peers
+                .lock()
+                .unwrap()
+                .banned()
+                .into_iter()
+                .map(|(ip, expiry)| (ip.to_string(), expiry))
+                .collect();
+            session.reply(BannedList(bans)).await;
+        }
+        Inv(items) => {
+            if items.len() > lib::MAX_INV_ITEMS {
+                return Err(Offense::ProtocolViolation);
+            }
+            let mut unknown = vec![];
+            for item in items {
+                if !inventory::have(node, &item).await {
+                    unknown.push(item);
+                }
+            }
+            let wanted = node
+                .inventory
+                .lock()
+                .unwrap()
+                .announced(peer, unknown, Utc::now());
+            if !wanted.is_empty() {
+                session.reply(GetData(wanted)).await;
+            }
+        }
+        GetData(items) => {
+            if items.len() > lib::MAX_INV_ITEMS {
+                return Err(Offense::ProtocolViolation);
+            }
+            let mut not_found = vec![];
+            for item in items {
+                match find_item(node, peer, &item, session.negotiated.services).await {
+                    Some(message) => session.reply(message).await,
+                    None => not_found.push(item),
+                }
+            }
+            if !not_found.is_empty() {
+                session.reply(NotFound(not_found)).await;
+            }
+        }
+        NotFound(items) => {
+            if items.len() > lib::MAX_INV_ITEMS {
+                return Err(Offense::ProtocolViolation);
+            }
+            let requests = node
+                .inventory
+                .lock()
+                .unwrap()
+                .not_found(peer, &items, Utc::now());
+            inventory::send_requests(node, requests);
+        }
+        GetMempool { min_fee } => {
+            if !node
+                .inventory
+                .lock()
+                .unwrap()
+                .allow_mempool_request(peer, Utc::now())
+            {
+                debug!(%peer, "ignoring repeated mempool request");
+                return Ok(());
+            }
+            let items: Vec<InvItem> = {
+                let blockchain = node.blockchain.read().await;
+                blockchain
+                    .mempool()
+                    .iter()
+                    .filter(|(_, transaction)| {
+                        blockchain
+                            .transaction_fee(transaction)
+                            .is_some_and(|fee| fee >= min_fee.unwrap_or(0))
+                    })
+                    .map(|(_, transaction)| InvItem {
+                        kind: InvKind::Tx,
+                        hash: transaction.hash(),
+                    })
+                    .collect()
+            };
+            // skip what the peer already knows about
+            let items: Vec<InvItem> = {
+                let mut inventory = node.inventory.lock().unwrap();
+                items
+                    .into_iter()
+                    .filter(|item| inventory.should_announce(peer, *item))
+                    .collect()
+            };
+            for chunk in items.chunks(lib::MAX_INV_ITEMS) {
+                session.reply(Inv(chunk.to_vec())).await;
+            }
+        }
+        FilterLoad(filter) => {
+            if !filter.is_within_limits() {
+                return Err(Offense::ProtocolViolation);
+            }
+            node.peers.lock().unwrap().set_filter(peer, Some(filter));
+        }
+        FilterClear => node.peers.lock().unwrap().set_filter(peer, None),
+        Ping(nonce) => session.reply(Pong(nonce)).await,
+        Pong(_) => {}
+        // the handshake is already done
+        Version { .. } => return Err(Offense::ProtocolViolation),
+        // responses we never asked for
+        UTXOs(_)
+        | Template(_)
+        | TemplateValidity(_)
+        | Difference(_)
+        | BannedList(_)
+        | BanRefused { .. }
+        | PeerList(_)
+        | TransactionAccepted(_)
+        | TransactionRejected { .. }
+        | RawTransaction { .. }
+        | TransactionStatus { .. }
+        | FilteredBlock { .. } => {
+            return Err(Offense::ProtocolViolation);
+        }
+    }
+    Ok(())
+}
+
+// add a transaction to the mempool and announce it, already
+// known transactions count as added
+async fn handle_transaction(
+    node: &Node,
+    peer: SocketAddr,
+    transaction: Transaction,
+) -> Result<(), SbdError> {
+    let hash = transaction.hash();
+    let item = InvItem {
+        kind: InvKind::Tx,
+        hash,
+    };
+    node.inventory.lock().unwrap().received(peer, item);
+    let result = {
+        let mut blockchain = node.blockchain.write().await;
+        // ignore transactions we already have to avoid relay loops
+        if blockchain.mempool_get(&hash).is_some() {
+            return Ok(());
+        }
+        blockchain.add_to_mempool(transaction.clone())
+    };
+    match &result {
+        Ok(()) => {
+            info!(%hash, "added transaction to mempool");
+            inventory::announce(node, item, &Message::NewTransaction(transaction));
+        }
+        Err(e) => info!(%peer, reason = %e, "rejected transaction"),
+    }
+    result
+}
+
+async fn handle_block(node: &Node, peer: SocketAddr, block: Block) -> Result<(), Offense> {
+    if !block.header.hash().matches_target(block.header.target) {
+        return Err(Offense::InvalidProofOfWork);
+    }
+    let item = InvItem {
+        kind: InvKind::Block,
+        hash: block.hash(),
+    };
+    node.inventory.lock().unwrap().received(peer, item);
+    let result = {
+        let mut blockchain = node.blockchain.write().await;
+        let mut header_sync = node.header_sync.lock().unwrap();
+        // bodies have to match the already verified headers
+        if !header_sync.expects(blockchain.block_height(), &block.header) {
+            info!(hash = %block.hash(), "ignoring block not on the best header chain");
+            return Ok(());
+        }
+        // as for compact blocks, work only counts at the chain's target
+        if block.header.prev_block_hash == blockchain.tip_hash()
+            && block.header.target != blockchain.target()
+        {
+            return Err(Offense::InvalidProofOfWork);
+        }
+        // the chain's events about the block name it and the peer
+        let result = info_span!("block", %peer, hash = %block.hash())
+            .in_scope(|| blockchain.add_block(block.clone()));
+        if result.is_ok() {
+            header_sync.block_connected(blockchain.block_height(), &block.header);
+        }
+        result
+    };
+    match result {
+        Ok(()) => {
+            info!(hash = %block.hash(), "added block");
+            inventory::announce_block(node, &block);
+        }
+        Err(SbdError::InvalidSignature) => return Err(Offense::InvalidSignature),
+        Err(SbdError::InvalidProofOfWork) => return Err(Offense::InvalidProofOfWork),
+        Err(SbdError::InvalidMerkleRoot | SbdError::InvalidBlock) => {
+            return Err(Offense::ProtocolViolation);
+        }
+        // the chain already warned of it
+        Err(e) => debug!(%peer, reason = %e, "rejected block"),
+    }
+    Ok(())
+}
+
+// the answer to a GetData item, blocks are sent filtered to peers
+// with a filter loaded, and compact to peers supporting it since
+// they most likely have the transactions already
+async fn find_item(
+    node: &Node,
+    peer: SocketAddr,
+    item: &InvItem,
+    services: Services,
+) -> Option<Message> {
+    let blockchain = node.blockchain.read().await;
+    match item.kind {
+        InvKind::Tx => blockchain
+            .mempool_get(&item.hash)
+            .map(|(_, transaction)| Message::NewTransaction(transaction.clone())),
+        InvKind::Block => blockchain
+            .blocks()
+            .find(|block| block.hash() == item.hash)
+            .map(|block| {
+                if let Some(filtered) = node.peers.lock().unwrap().filtered_block(peer, block) {
+                    return filtered;
+                }
+                if !services.contains(Services::COMPACT_BLOCKS) {
+                    return Message::NewBlock(block.clone());
+                }
+                Message::CompactBlock {
+                    header: block.header.clone(),
+                    txids: block
+                        .transactions
+                        .iter()
+                        .map(|transaction| transaction.hash())
+                        .collect(),
+                }
+            }),
+    }
+}
+
+async fn handle_compact_block(
+    node: &Node,
+    session: &Session,
+    header: BlockHeader,
+    txids: Vec<Hash>,
+) -> Result<(), Offense> {
+    if !header.hash().matches_target(header.target) {
+        return Err(Offense::InvalidProofOfWork);
+    }
+    if txids.is_empty() {
+        return Err(Offense::ProtocolViolation);
+    }
+    let partial = {
+        let blockchain = node.blockchain.read().await;
+        // only a block on our tip is worth waiting on transactions
+        // for, and its work only counts at the target the chain sets
+        if header.prev_block_hash != blockchain.tip_hash()
+            || !node
+                .header_sync
+                .lock()
+                .unwrap()
+                .expects(blockchain.block_height(), &header)
+        {
+            info!(hash = %header.hash(), "ignoring compact block not on our tip");
+            return Ok(());
+        }
+        if header.target != blockchain.target() {
+            return Err(Offense::InvalidProofOfWork);
+        }
+        PartialBlock::new(header, txids, blockchain.mempool())
+    };
+    let missing = partial.missing();
+    if missing.is_empty() {
+        return finish_compact_block(node, session, partial, 0).await;
+    }
+    let block_hash = partial.header().hash();
+    node.pending_blocks
+        .lock()
+        .unwrap()
+        .insert(session.peer, partial, Utc::now());
+    session
+        .reply(Message::GetBlockTxn {
+            block_hash,
+            indexes: missing,
+        })
+        .await;
+    Ok(())
+}
+
+async fn finish_compact_block(
+    node: &Node,
+    session: &Session,
+    partial: PartialBlock,
+    fetched: usize,
+) -> Result<(), Offense> {
+    let reused = partial.reused();
+    let Some(block) = partial.into_block() else {
+        // ask for the whole block instead
+        info!("failed to reconstruct compact block, fetching full block");
+        node.compact_stats.record_fallback();
+        let height = node.blockchain.read().await.block_height();
+        session.reply(Message::FetchBlock(height as usize)).await;
+        return Ok(());
+    };
+    node.compact_stats.record(reused, fetched);
+    info!(reused, fetched, "reconstructed block");
+    handle_block(node, session.peer, block).await
+}
+
+async fn create_template(node: &Node, pubkey: PublicKey) -> Block {
+    let blockchain = node.blockchain.read().await;
+    let mut transactions = vec![Transaction::new(
+        vec![],
+        vec![TransactionOutput {
+            pubkey,
+            unique_id: Uuid::new_v4(),
+            value: 0,
+            spendable_after_height: None,
+        }],
+    )];
+    transactions.extend(
+        blockchain
+            .mempool_by_priority()
+            .take(blockchain.params().block_transaction_cap)
+            .map(|(_, transaction)| transaction.clone()),
+    );
+    let prev_block_hash = blockchain
+        .blocks()
+        .last()
+        .map(|block| block.hash())
+        .unwrap_or_else(Hash::zero);
+    // signaling for the deployments this node's params define
+    let builder = Block::builder()
+        .version(blockchain.block_version())
+        .prev_hash(prev_block_hash)
+        .target(blockchain.target());
+    // built once unpaid to find the fees the coinbase claims
+    let unpaid = builder
+        .clone()
+        .transactions(transactions)
+        .build()
+        .expect("BUG: template without a coinbase");
+    let miner_fees = unpaid.calculate_miner_fees(blockchain.utxos()).unwrap_or(0);
+    let mut transactions = unpaid.transactions;
+    transactions[0].outputs[0].value = blockchain.calculate_block_reward() + miner_fees;
+    // as the coinbase pays now
+    builder
+        .utxo_commitment(blockchain.utxo_commitment_after(&transactions))
+        .transactions(transactions)
+        .build()
+        .expect("BUG: template without a coinbase")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn difference_saturates_instead_of_wrapping() {
+        assert_eq!(difference(10, 4), 6);
+        assert_eq!(difference(4, 10), -6);
+        assert_eq!(difference(0, u32::MAX), i32::MIN);
+        assert_eq!(difference(u64::MAX, 0), i32::MAX);
+        assert_eq!(difference(u64::from(u32::MAX) + 1, 0), i32::MAX);
+    }
+}
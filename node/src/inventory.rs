@@ -0,0 +1,337 @@
+use crate::Node;
+use chrono::{DateTime, Duration, Utc};
+use lib::network::{InvItem, InvKind, Message, Services};
+use lib::types::Block;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use tracing::info;
+
+// seconds to wait for a GetData answer before asking someone else
+pub const REQUEST_TIMEOUT: i64 = 30;
+// seconds a peer has to wait between GetMempool requests
+pub const MEMPOOL_REQUEST_INTERVAL: i64 = 60;
+// items remembered per peer, the set is reset once it grows past this
+const MAX_KNOWN_ITEMS: usize = 100_000;
+
+#[derive(Debug, Default)]
+struct PeerInventory {
+    // items we announced to the peer
+    sent: HashSet<InvItem>,
+    // items the peer announced to us
+    received: HashSet<InvItem>,
+    last_mempool_request: Option<DateTime<Utc>>,
+}
+
+impl PeerInventory {
+    fn knows(&self, item: &InvItem) -> bool {
+        self.sent.contains(item) || self.received.contains(item)
+    }
+}
+
+fn remember(set: &mut HashSet<InvItem>, item: InvItem) {
+    if set.len() >= MAX_KNOWN_ITEMS {
+        set.clear();
+    }
+    set.insert(item);
+}
+
+#[derive(Debug, Clone, Copy)]
+struct InFlight {
+    peer: SocketAddr,
+    requested: DateTime<Utc>,
+}
+
+// what every peer knows about and which announced items are
+// being fetched from whom, times are passed in by the caller
+#[derive(Debug, Default)]
+pub struct InventoryTracker {
+    peers: HashMap<SocketAddr, PeerInventory>,
+    in_flight: HashMap<InvItem, InFlight>,
+}
+
+impl InventoryTracker {
+    // record an announcement and return the items to request from
+    // the peer, the caller filters out the ones we already have;
+    // items already requested from another peer are left alone
+    pub fn announced(
+        &mut self,
+        peer: SocketAddr,
+        items: impl IntoIterator<Item = InvItem>,
+        now: DateTime<Utc>,
+    ) -> Vec<InvItem> {
+        let inventory = self.peers.entry(peer).or_default();
+        let mut wanted = vec![];
+        for item in items {
+            remember(&mut inventory.received, item);
+            if self.in_flight.contains_key(&item) {
+                continue;
+            }
+            self.in_flight.insert(
+                item,
+                InFlight {
+                    peer,
+                    requested: now,
+                },
+            );
+            wanted.push(item);
+        }
+        wanted
+    }
+
+    // an item arrived, from whichever peer
+    pub fn received(&mut self, peer: SocketAddr, item: InvItem) {
+        self.in_flight.remove(&item);
+        remember(&mut self.peers.entry(peer).or_default().received, item);
+    }
+
+    // true if the item should be announced to the peer,
+    // which is then assumed to know about it
+    pub fn should_announce(&mut self, peer: SocketAddr, item: InvItem) -> bool {
+        let inventory = self.peers.entry(peer).or_default();
+        if inventory.knows(&item) {
+            return false;
+        }
+        remember(&mut inventory.sent, item);
+        true
+    }
+
+    // false if the peer asked for our mempool too recently
+    pub fn allow_mempool_request(&mut self, peer: SocketAddr, now: DateTime<Utc>) -> bool {
+        let inventory = self.peers.entry(peer).or_default();
+        if inventory
+            .last_mempool_request
+            .is_some_and(|last| now - last < Duration::seconds(MEMPOOL_REQUEST_INTERVAL))
+        {
+            return false;
+        }
+        inventory.last_mempool_request = Some(now);
+        true
+    }
+
+    // the peer doesn't have items we asked it for, returns
+    // the requests to send to other peers instead
+    pub fn not_found(
+        &mut self,
+        peer: SocketAddr,
+        items: &[InvItem],
+        now: DateTime<Utc>,
+    ) -> Vec<(SocketAddr, InvItem)> {
+        if let Some(inventory) = self.peers.get_mut(&peer) {
+            for item in items {
+                inventory.received.remove(item);
+            }
+        }
+        let failed: Vec<InvItem> = items
+            .iter()
+            .filter(|item| {
+                self.in_flight
+                    .get(item)
+                    .is_some_and(|in_flight| in_flight.peer == peer)
+            })
+            .copied()
+            .collect();
+        self.rerequest(failed, peer, now)
+    }
+
+    // requests that went unanswered for too long, moved to
+    // other peers that announced the same items
+    pub fn expire(&mut self, now: DateTime<Utc>) -> Vec<(SocketAddr, InvItem)> {
+        let deadline = now - Duration::seconds(REQUEST_TIMEOUT);
+        let expired: Vec<(InvItem, SocketAddr)> = self
+            .in_flight
+            .iter()
+            .filter(|(_, in_flight)| in_flight.requested <= deadline)
+            .map(|(item, in_flight)| (*item, in_flight.peer))
+            .collect();
+        let mut requests = vec![];
+        for (item, peer) in expired {
+            info!(kind = ?item.kind, hash = %item.hash, %peer, "request timed out");
+            if let Some(inventory) = self.peers.get_mut(&peer) {
+                inventory.received.remove(&item);
+            }
+            requests.extend(self.rerequest(vec![item], peer, now));
+        }
+        requests
+    }
+
+    // forget a closed connection, returns the requests
+    // it had in flight, moved to other peers
+    pub fn peer_disconnected(
+        &mut self,
+        peer: SocketAddr,
+        now: DateTime<Utc>,
+    ) -> Vec<(SocketAddr, InvItem)> {
+        self.peers.remove(&peer);
+        let pending: Vec<InvItem> = self
+            .in_flight
+            .iter()
+            .filter(|(_, in_flight)| in_flight.peer == peer)
+            .map(|(item, _)| *item)
+            .collect();
+        self.rerequest(pending, peer, now)
+    }
+
+    fn rerequest(
+        &mut self,
+        items: Vec<InvItem>,
+        failed_peer: SocketAddr,
+        now: DateTime<Utc>,
+    ) -> Vec<(SocketAddr, InvItem)> {
+        let mut requests = vec![];
+        for item in items {
+            let next = self
+                .peers
+                .iter()
+                .find(|(addr, inventory)| {
+                    **addr != failed_peer && inventory.received.contains(&item)
+                })
+                .map(|(addr, _)| *addr);
+            match next {
+                Some(peer) => {
+                    self.in_flight.insert(
+                        item,
+                        InFlight {
+                            peer,
+                            requested: now,
+                        },
+                    );
+                    requests.push((peer, item));
+                }
+                None => {
+                    self.in_flight.remove(&item);
+                }
+            }
+        }
+        requests
+    }
+}
+
+// true if the item is in the mempool or the chain
+pub async fn have(node: &Node, item: &InvItem) -> bool {
+    let blockchain = node.blockchain.read().await;
+    match item.kind {
+        InvKind::Tx => blockchain.mempool_get(&item.hash).is_some(),
+        InvKind::Block => blockchain.blocks().any(|block| block.hash() == item.hash),
+    }
+}
+
+// announce an item to every peer that doesn't know about it yet,
+// peers without inventory support are sent the full item instead
+pub fn announce(node: &Node, item: InvItem, full: &Message) {
+    announce_to_peers(node, item, full, None);
+}
+
+// announce a block like any item, except that peers with
+// a filter loaded get it as a FilteredBlock
+pub fn announce_block(node: &Node, block: &Block) {
+    let item = InvItem {
+        kind: InvKind::Block,
+        hash: block.hash(),
+    };
+    announce_to_peers(node, item, &Message::NewBlock(block.clone()), Some(block));
+}
+
+fn announce_to_peers(node: &Node, item: InvItem, full: &Message, block: Option<&Block>) {
+    let mut peers = node.peers.lock().unwrap();
+    let mut inventory = node.inventory.lock().unwrap();
+    for (peer, services) in peers.connected() {
+        if !inventory.should_announce(peer, item) {
+            continue;
+        }
+        if let Some(filtered) = block.and_then(|block| peers.filtered_block(peer, block)) {
+            peers.send(peer, &filtered);
+        } else if services.contains(Services::INVENTORY) {
+            peers.send(peer, &Message::Inv(vec![item]));
+        } else {
+            peers.send(peer, full);
+        }
+    }
+}
+
+// send GetData for re-requested items, grouped by peer
+pub fn send_requests(node: &Node, requests: Vec<(SocketAddr, InvItem)>) {
+    let mut grouped: HashMap<SocketAddr, Vec<InvItem>> = HashMap::new();
+    for (peer, item) in requests {
+        grouped.entry(peer).or_default().push(item);
+    }
+    let mut peers = node.peers.lock().unwrap();
+    for (peer, items) in grouped {
+        peers.send(peer, &Message::GetData(items));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib::sha256::Hash;
+
+    fn peer(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    fn seconds(seconds: i64) -> DateTime<Utc> {
+        DateTime::UNIX_EPOCH + Duration::seconds(seconds)
+    }
+
+    fn block(name: &[u8]) -> InvItem {
+        InvItem {
+            kind: InvKind::Block,
+            hash: Hash::hash_bytes(name),
+        }
+    }
+
+    #[test]
+    fn same_hash_from_two_peers_is_requested_once() {
+        let mut tracker = InventoryTracker::default();
+        let item = block(b"a");
+        assert_eq!(tracker.announced(peer(1), [item], seconds(0)), [item]);
+        assert!(tracker.announced(peer(2), [item], seconds(1)).is_empty());
+        // nor again from the first once it arrived
+        tracker.received(peer(1), item);
+        assert!(tracker.expire(seconds(1000)).is_empty());
+    }
+
+    #[test]
+    fn unanswered_request_moves_to_another_announcer() {
+        let mut tracker = InventoryTracker::default();
+        let item = block(b"a");
+        tracker.announced(peer(1), [item], seconds(0));
+        tracker.announced(peer(2), [item], seconds(5));
+        assert!(tracker.expire(seconds(REQUEST_TIMEOUT - 1)).is_empty());
+        assert_eq!(tracker.expire(seconds(REQUEST_TIMEOUT)), [(peer(2), item)]);
+        // the second request gets a timeout of its own
+        assert!(tracker.expire(seconds(2 * REQUEST_TIMEOUT - 1)).is_empty());
+        // and once no one else has it, it's given up on
+        assert!(tracker.expire(seconds(2 * REQUEST_TIMEOUT)).is_empty());
+        assert_eq!(tracker.announced(peer(3), [item], seconds(100)), [item]);
+    }
+
+    #[test]
+    fn answer_after_the_timeout_settles_the_rerequest() {
+        let mut tracker = InventoryTracker::default();
+        let item = block(b"a");
+        tracker.announced(peer(1), [item], seconds(0));
+        tracker.announced(peer(2), [item], seconds(0));
+        assert_eq!(tracker.expire(seconds(REQUEST_TIMEOUT)), [(peer(2), item)]);
+        // the slow peer answers after all
+        tracker.received(peer(1), item);
+        assert!(tracker.expire(seconds(10 * REQUEST_TIMEOUT)).is_empty());
+    }
+
+    #[test]
+    fn not_found_and_disconnect_move_requests_on() {
+        let mut tracker = InventoryTracker::default();
+        let (a, b) = (block(b"a"), block(b"b"));
+        tracker.announced(peer(1), [a, b], seconds(0));
+        tracker.announced(peer(2), [a, b], seconds(0));
+        tracker.announced(peer(3), [a], seconds(0));
+        // only the peer a request went to can fail it, though
+        // one saying it hasn't got the item is no longer asked
+        assert!(tracker.not_found(peer(2), &[a], seconds(1)).is_empty());
+        assert_eq!(tracker.not_found(peer(1), &[a], seconds(1)), [(peer(3), a)]);
+        assert_eq!(
+            tracker.peer_disconnected(peer(1), seconds(2)),
+            [(peer(2), b)]
+        );
+    }
+}
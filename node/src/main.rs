@@ -0,0 +1,347 @@
+use clap::Parser;
+use compact::{CompactBlockStats, PendingBlocks};
+use config::{NodeArgs, NodeConfig};
+use inventory::InventoryTracker;
+use lib::error::Report;
+use lib::network::Message;
+use lib::params::NetworkParams;
+use lib::types::{BlockStore, Blockchain, CheckpointBundle, MempoolSnapshot};
+use lib::utils::Saveable;
+use outbound::OutboundManager;
+use peers::{AddressBook, Direction, PeerManager};
+use std::fmt::Display;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use sync::HeaderSync;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::RwLock;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+mod compact;
+mod config;
+mod handler;
+mod inventory;
+mod outbound;
+mod peers;
+mod ratelimit;
+mod store;
+mod sync;
+
+pub struct Node {
+    pub config: NodeConfig,
+    pub params: NetworkParams,
+    pub blockchain: RwLock<Blockchain>,
+    pub peers: Mutex<PeerManager>,
+    pub datadir: PathBuf,
+    // compact blocks waiting for missing transactions
+    pub pending_blocks: Mutex<PendingBlocks>,
+    pub compact_stats: CompactBlockStats,
+    pub header_sync: Mutex<HeaderSync>,
+    pub outbound: OutboundManager,
+    pub inventory: Mutex<InventoryTracker>,
+    // cancelled once the node starts shutting down
+    pub shutdown: CancellationToken,
+    // every spawned task, waited for on shutdown
+    pub tasks: TaskTracker,
+}
+
+impl Node {
+    // the whole chain as saved before the block store
+    pub fn blockchain_path(&self) -> PathBuf {
+        self.datadir.join("blockchain.cbor")
+    }
+
+    pub fn mempool_path(&self) -> PathBuf {
+        self.datadir.join("mempool.cbor")
+    }
+
+    pub fn address_book_path(&self) -> PathBuf {
+        self.datadir.join("peers.cbor")
+    }
+
+    // persist known nodes and bans
+    pub fn save_address_book(&self) {
+        let peers = self.peers.lock().unwrap();
+        if let Err(e) = peers.address_book().save_to_file(self.address_book_path()) {
+            warn!(error = %e, "failed to save address book");
+        }
+    }
+
+    // flush the blockchain manifest, mempool and address book
+    // to the data directory, blocks are stored as they come in
+    pub async fn save(&self) -> std::io::Result<()> {
+        let mut blockchain = self.blockchain.write().await;
+        blockchain.flush_store().map_err(std::io::Error::other)?;
+        self.save_file(&blockchain.mempool_snapshot(), self.mempool_path())?;
+        let peers = self.peers.lock().unwrap();
+        self.save_file(peers.address_book(), self.address_book_path())
+    }
+
+    // backed up first if the node keeps backups
+    fn save_file<T: Saveable>(&self, value: &T, path: PathBuf) -> std::io::Result<()> {
+        match self.config.backup_policy() {
+            Some(policy) => policy.save(path, |path| value.save_to_file(path)),
+            None => value.save_to_file(path),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Report> {
+    let config = match NodeConfig::from_args(NodeArgs::parse()) {
+        Ok(config) => config,
+        // before logging is set up, and for the user to read
+        Err(e) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+    };
+    lib::logging::init_daemon("node", config.verbosity);
+    let datadir = config.network_datadir();
+    fs::create_dir_all(&datadir)
+        .map_err(|e| Report::new(format!("Failed to create {}: {e}", datadir.display())))?;
+    let params = config.network.params();
+    let node = Arc::new(Node {
+        blockchain: RwLock::new(Blockchain::with_network(config.network)),
+        peers: Mutex::new(PeerManager::new(AddressBook::default(), &config)),
+        header_sync: Mutex::new(HeaderSync::new(params.clone(), [].iter())),
+        params,
+        datadir,
+        pending_blocks: Mutex::new(PendingBlocks::default()),
+        compact_stats: CompactBlockStats::default(),
+        outbound: OutboundManager::default(),
+        inventory: Mutex::new(InventoryTracker::default()),
+        shutdown: CancellationToken::new(),
+        tasks: TaskTracker::new(),
+        config,
+    });
+    restore(&node).await?;
+
+    // keep connected to the manual peers and every node we already know about
+    let manual_peers = node.config.manual_peers().map_err(Report::new)?;
+    {
+        let mut peers = node.peers.lock().unwrap();
+        for address in manual_peers {
+            peers.add_manual_node(address);
+        }
+    }
+    node.tasks.spawn(outbound::run(node.clone()));
+
+    if let Some(rpc_bind) = node.config.rpc_bind {
+        let listener = TcpListener::bind(rpc_bind)
+            .await
+            .map_err(|e| Report::new(format!("Failed to bind rpc listener to {rpc_bind}: {e}")))?;
+        if let Ok(addr) = listener.local_addr() {
+            info!(%addr, "listening for rpc");
+        }
+        let node = node.clone();
+        node.clone().tasks.spawn(async move {
+            loop {
+                let accepted = tokio::select! {
+                    _ = node.shutdown.cancelled() => break,
+                    accepted = listener.accept() => accepted,
+                };
+                let Ok((stream, addr)) = accepted else {
+                    continue;
+                };
+                spawn_handler(&node, stream, addr, Direction::Rpc);
+            }
+        });
+    }
+
+    // keep idle connections alive and drop the dead ones
+    spawn_periodic(
+        &node,
+        Duration::from_secs(node.config.ping_interval),
+        |node| {
+            let nonce = Uuid::new_v4().as_u64_pair().0;
+            node.peers
+                .lock()
+                .unwrap()
+                .broadcast(&Message::Ping(nonce), None);
+        },
+    );
+
+    // move unanswered data requests to other peers, and stop
+    // waiting on compact blocks whose transactions never came
+    spawn_periodic(&node, Duration::from_secs(5), |node| {
+        let now = chrono::Utc::now();
+        let requests = node.inventory.lock().unwrap().expire(now);
+        inventory::send_requests(node, requests);
+        let expired = node.pending_blocks.lock().unwrap().expire(now);
+        if expired > 0 {
+            info!(
+                expired,
+                "dropped compact blocks whose transactions never came"
+            );
+        }
+    });
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let listener = TcpListener::bind((node.config.listen_address, node.config.port))
+        .await
+        .map_err(|e| Report::new(format!("Failed to bind listener: {e}")))?;
+    if let Ok(addr) = listener.local_addr() {
+        info!(%addr, "listening for peers");
+    }
+    loop {
+        let accepted = tokio::select! {
+            _ = &mut shutdown => break,
+            accepted = listener.accept() => accepted,
+        };
+        let Ok((stream, addr)) = accepted else {
+            continue;
+        };
+        let mut peers = node.peers.lock().unwrap();
+        // refuse reconnects from banned peers
+        if peers.is_banned(addr.ip()) {
+            info!(%addr, "refusing connection from banned peer");
+            continue;
+        }
+        if !peers.reserve_slot(Direction::Inbound) {
+            info!(%addr, "refusing connection, inbound limit reached");
+            continue;
+        }
+        drop(peers);
+        spawn_handler(&node, stream, addr, Direction::Inbound);
+    }
+
+    // stop every task, then flush everything to disk before exiting
+    info!("shutting down");
+    node.shutdown.cancel();
+    node.tasks.close();
+    node.tasks.wait().await;
+    node.save()
+        .await
+        .map_err(|e| Report::new(format!("failed to save node state: {e}")))
+}
+
+// resolves on ctrl-c, or on SIGTERM where there is one; the handlers
+// are set up before it's first polled, so that a signal arriving right
+// after startup still stops the node cleanly
+fn shutdown_signal() -> impl Future<Output = ()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut interrupt = signal(SignalKind::interrupt()).expect("Failed to set signal handler");
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to set signal handler");
+        async move {
+            tokio::select! {
+                _ = interrupt.recv() => {}
+                _ = terminate.recv() => {}
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    async {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+// load the state saved by a previous run, re-validating it
+async fn restore(node: &Node) -> Result<(), Report> {
+    let mut blockchain = BlockStore::open(&node.datadir)
+        .and_then(|mut store| {
+            store.set_compress(node.config.compress);
+            store.set_backup(node.config.backup_policy());
+            Blockchain::with_store(store, node.params.network)
+        })
+        .map_err(|e| Report::new(format!("refusing to load {}: {e}", node.datadir.display())))?;
+    if blockchain.block_height() == 0 && node.blockchain_path().exists() {
+        import_chain_file(node, &mut blockchain)?;
+    }
+    if let Some(path) = &node.config.checkpoint_file {
+        apply_checkpoints(node, path, &mut blockchain)?;
+    }
+    let mempool: MempoolSnapshot = store::load_or_default(node.mempool_path())
+        .map_err(|e| Report::new(format!("Failed to load mempool: {e}")))?;
+    // drop anything that no longer fits the restored chain
+    for (_, e) in blockchain.restore_mempool(mempool) {
+        warn!(reason = %e, "dropping saved mempool transaction");
+    }
+    info!(
+        blocks = blockchain.block_height(),
+        mempool = blockchain.mempool().len(),
+        "restored chain"
+    );
+    *node.header_sync.lock().unwrap() = HeaderSync::new(node.params.clone(), blockchain.blocks())
+        .with_checkpoints(blockchain.checkpoints().clone());
+    *node.blockchain.write().await = blockchain;
+    let address_book: AddressBook = store::load_or_default(node.address_book_path())
+        .map_err(|e| Report::new(format!("Failed to load address book: {e}")))?;
+    *node.peers.lock().unwrap() = PeerManager::new(address_book, &node.config);
+    Ok(())
+}
+
+// hold the chain to the checkpoints of the bundle; a bundle the
+// chain already disagrees with is refused, as is one badly signed
+fn apply_checkpoints(node: &Node, path: &Path, blockchain: &mut Blockchain) -> Result<(), Report> {
+    let refused = |e: &dyn Display| Report::new(format!("refusing {}: {e}", path.display()));
+    let keys = node
+        .config
+        .trusted_checkpoint_keys()
+        .map_err(|e| refused(&e))?;
+    let bundle = CheckpointBundle::load_from_file(path).map_err(|e| refused(&e))?;
+    let added = blockchain
+        .apply_signed_checkpoints(&bundle, &keys)
+        .map_err(|e| refused(&e))?;
+    info!(added, path = %path.display(), "applied signed checkpoints");
+    Ok(())
+}
+
+// move the blocks of a chain saved whole by older versions into
+// the block store; the old file is left alone
+fn import_chain_file(node: &Node, blockchain: &mut Blockchain) -> Result<(), Report> {
+    let path = node.blockchain_path();
+    let refused =
+        |e: &dyn Display| Report::new(format!("refusing to load {}: {e}", path.display()));
+    let saved = Blockchain::load_from_file(&path).map_err(|e| refused(&e))?;
+    saved
+        .check_network(node.params.network)
+        .map_err(|e| refused(&e))?;
+    saved
+        .verify()
+        .map_err(|e| Report::new(format!("saved blockchain failed verification: {e}")))?;
+    let failed = |e| Report::new(format!("failed to import {}: {e}", path.display()));
+    for block in saved.blocks() {
+        blockchain.add_block(block.clone()).map_err(failed)?;
+    }
+    blockchain.flush_store().map_err(failed)?;
+    info!(
+        blocks = blockchain.block_height(),
+        path = %path.display(),
+        "imported chain file"
+    );
+    Ok(())
+}
+
+fn spawn_handler(node: &Arc<Node>, stream: TcpStream, addr: SocketAddr, direction: Direction) {
+    node.tasks.spawn(handler::handle_connection(
+        node.clone(),
+        stream,
+        addr,
+        direction,
+    ));
+}
+
+// run a job every period until shutdown
+fn spawn_periodic(node: &Arc<Node>, period: Duration, job: impl Fn(&Node) + Send + 'static) {
+    let node = node.clone();
+    node.clone().tasks.spawn(async move {
+        loop {
+            tokio::select! {
+                _ = node.shutdown.cancelled() => break,
+                _ = sleep(period) => job(&node),
+            }
+        }
+    });
+}
@@ -0,0 +1,352 @@
+use crate::Node;
+use crate::peers::Direction;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{HashMap, HashSet};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Result as IoResult};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration as StdDuration;
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+use tokio::time::{sleep, timeout};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+// delay before retrying an address after its first failure,
+// in seconds, doubled after every further failure
+pub const BASE_BACKOFF: i64 = 5;
+pub const MAX_BACKOFF: i64 = 60 * 60;
+// consecutive failures after which an address is marked
+// failed in the address book
+pub const MAX_FAILURES: u32 = 8;
+const CONNECT_TIMEOUT: StdDuration = StdDuration::from_secs(5);
+// how long the manager sleeps when nothing is scheduled
+const IDLE_WAIT: StdDuration = StdDuration::from_secs(30);
+
+// delay before the next attempt after the given number of
+// consecutive failures, a jitter in [0, 1) adds up to a quarter
+pub fn backoff(failures: u32, jitter: f64) -> Duration {
+    let exponent = failures.saturating_sub(1).min(20);
+    let seconds = BASE_BACKOFF.saturating_mul(1 << exponent).min(MAX_BACKOFF);
+    Duration::milliseconds((seconds as f64 * 1000.0 * (1.0 + jitter.clamp(0.0, 1.0) / 4.0)) as i64)
+}
+
+#[derive(Debug, Clone, Default)]
+struct Attempts {
+    failures: u32,
+    last_tried: Option<DateTime<Utc>>,
+    next_attempt: Option<DateTime<Utc>>,
+}
+
+// when each address was tried and may be tried again,
+// times are passed in so the schedule can be replayed
+#[derive(Debug, Default)]
+pub struct OutboundSchedule {
+    attempts: HashMap<String, Attempts>,
+    // open connections we dialed, by the address we dialed
+    connected: HashMap<SocketAddr, String>,
+}
+
+impl OutboundSchedule {
+    // addresses due for an attempt, manual ones first, then those
+    // not marked failed, then the least recently tried
+    pub fn candidates(
+        &self,
+        nodes: &HashSet<String>,
+        manual: &HashSet<String>,
+        failed: &HashSet<String>,
+        now: DateTime<Utc>,
+        count: usize,
+    ) -> Vec<String> {
+        let connected: HashSet<&String> = self.connected.values().collect();
+        let mut due: Vec<_> = nodes
+            .iter()
+            .filter(|address| !connected.contains(address))
+            .filter_map(|address| {
+                let attempts = self.attempts.get(address);
+                if attempts
+                    .and_then(|attempts| attempts.next_attempt)
+                    .is_some_and(|next| next > now)
+                {
+                    return None;
+                }
+                let last_tried = attempts.and_then(|attempts| attempts.last_tried);
+                Some((
+                    address,
+                    !manual.contains(address),
+                    failed.contains(address),
+                    last_tried,
+                ))
+            })
+            .collect();
+        due.sort_by_key(|(_, automatic, failed, last_tried)| (*automatic, *failed, *last_tried));
+        due.into_iter()
+            .take(count)
+            .map(|(address, _, _, _)| address.clone())
+            .collect()
+    }
+
+    pub fn record_success(&mut self, address: &str, peer: SocketAddr, now: DateTime<Utc>) {
+        self.attempts.insert(
+            address.to_string(),
+            Attempts {
+                failures: 0,
+                last_tried: Some(now),
+                next_attempt: None,
+            },
+        );
+        self.connected.insert(peer, address.to_string());
+    }
+
+    // schedule the next attempt, returns the consecutive failures
+    pub fn record_failure(&mut self, address: &str, now: DateTime<Utc>, jitter: f64) -> u32 {
+        let attempts = self.attempts.entry(address.to_string()).or_default();
+        attempts.failures += 1;
+        attempts.last_tried = Some(now);
+        attempts.next_attempt = Some(now + backoff(attempts.failures, jitter));
+        attempts.failures
+    }
+
+    // forget a closed connection, returns false if we didn't dial it;
+    // the address is held off briefly so a peer dropping us right
+    // after the handshake isn't redialed in a loop
+    pub fn disconnected(&mut self, peer: &SocketAddr, now: DateTime<Utc>) -> bool {
+        let Some(address) = self.connected.remove(peer) else {
+            return false;
+        };
+        let attempts = self.attempts.entry(address).or_default();
+        attempts.next_attempt = Some(now + Duration::seconds(BASE_BACKOFF));
+        true
+    }
+
+    // earliest attempt scheduled after now
+    pub fn next_attempt(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.attempts
+            .values()
+            .filter_map(|attempts| attempts.next_attempt)
+            .filter(|next| *next > now)
+            .min()
+    }
+}
+
+// keeps the node at its target number of outbound peers
+#[derive(Debug, Default)]
+pub struct OutboundManager {
+    schedule: Mutex<OutboundSchedule>,
+    wakeup: Notify,
+}
+
+impl OutboundManager {
+    // an outbound connection closed, dial a replacement
+    pub fn disconnected(&self, peer: &SocketAddr) {
+        if self.schedule.lock().unwrap().disconnected(peer, Utc::now()) {
+            self.wakeup.notify_one();
+        }
+    }
+
+    // run another round of attempts, e.g. after a node was added
+    pub fn wake(&self) {
+        self.wakeup.notify_one();
+    }
+}
+
+// dial known addresses until target_outbound peers are connected,
+// then wait for a disconnect or the next scheduled retry
+pub async fn run(node: Arc<Node>) {
+    loop {
+        let now = Utc::now();
+        let (wanted, nodes, manual, failed) = {
+            let peers = node.peers.lock().unwrap();
+            let book = peers.address_book();
+            (
+                node.config
+                    .target_outbound
+                    .saturating_sub(peers.outbound_count()),
+                book.nodes.clone(),
+                book.manual.clone(),
+                book.failed.clone(),
+            )
+        };
+        let candidates = if wanted > 0 {
+            let schedule = node.outbound.schedule.lock().unwrap();
+            schedule.candidates(&nodes, &manual, &failed, now, wanted)
+        } else {
+            vec![]
+        };
+        for address in candidates {
+            attempt(&node, &address).await;
+        }
+
+        let wanted = node
+            .config
+            .target_outbound
+            .saturating_sub(node.peers.lock().unwrap().outbound_count());
+        let now = Utc::now();
+        let next_attempt = node.outbound.schedule.lock().unwrap().next_attempt(now);
+        let wait = match next_attempt {
+            Some(next) if wanted > 0 => (next - now)
+                .to_std()
+                .unwrap_or(StdDuration::ZERO)
+                .min(IDLE_WAIT),
+            _ => IDLE_WAIT,
+        };
+        tokio::select! {
+            _ = node.shutdown.cancelled() => break,
+            _ = node.outbound.wakeup.notified() => {}
+            _ = sleep(wait) => {}
+        }
+    }
+}
+
+async fn attempt(node: &Arc<Node>, address: &str) {
+    match dial(node, address).await {
+        Ok((stream, peer)) => {
+            info!(%peer, "connected");
+            node.outbound
+                .schedule
+                .lock()
+                .unwrap()
+                .record_success(address, peer, Utc::now());
+            if node.peers.lock().unwrap().mark_reachable(address) {
+                node.save_address_book();
+            }
+            crate::spawn_handler(node, stream, peer, Direction::Outbound);
+        }
+        Err(e) => {
+            let failures = node.outbound.schedule.lock().unwrap().record_failure(
+                address,
+                Utc::now(),
+                jitter(),
+            );
+            warn!(address, failures, error = %e, "failed to connect");
+            if failures == MAX_FAILURES {
+                node.peers.lock().unwrap().mark_failed(address);
+                node.save_address_book();
+            }
+        }
+    }
+}
+
+// open a connection holding an outbound slot, the
+// slot is released again if it fails
+async fn dial(node: &Node, address: &str) -> IoResult<(TcpStream, SocketAddr)> {
+    if !node.peers.lock().unwrap().reserve_slot(Direction::Outbound) {
+        return Err(IoError::other("outbound limit reached"));
+    }
+    let result = connect(address).await.and_then(|stream| {
+        let peer = stream.peer_addr()?;
+        if node.peers.lock().unwrap().is_banned(peer.ip()) {
+            return Err(IoError::new(
+                IoErrorKind::PermissionDenied,
+                "peer is banned",
+            ));
+        }
+        Ok((stream, peer))
+    });
+    if result.is_err() {
+        node.peers.lock().unwrap().release_slot(Direction::Outbound);
+    }
+    result
+}
+
+async fn connect(address: &str) -> IoResult<TcpStream> {
+    timeout(CONNECT_TIMEOUT, TcpStream::connect(address))
+        .await
+        .map_err(|_| IoError::new(IoErrorKind::TimedOut, "connection timed out"))?
+}
+
+// random fraction in [0, 1)
+fn jitter() -> f64 {
+    (Uuid::new_v4().as_u64_pair().0 >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REACHABLE: &str = "10.0.0.1:9000";
+    const UNREACHABLE: &str = "10.0.0.2:9000";
+
+    fn peer() -> SocketAddr {
+        REACHABLE.parse().unwrap()
+    }
+
+    fn seconds(seconds: i64) -> DateTime<Utc> {
+        DateTime::UNIX_EPOCH + Duration::seconds(seconds)
+    }
+
+    // run the schedule a second at a time until end, dialing what it
+    // offers without jitter; the times each address was tried
+    fn replay(
+        schedule: &mut OutboundSchedule,
+        from: i64,
+        end: i64,
+    ) -> HashMap<&'static str, Vec<i64>> {
+        let nodes: HashSet<String> = [REACHABLE, UNREACHABLE].map(String::from).into();
+        let mut tried: HashMap<&str, Vec<i64>> = HashMap::new();
+        for second in from..end {
+            let now = seconds(second);
+            for address in schedule.candidates(&nodes, &HashSet::new(), &HashSet::new(), now, 2) {
+                if address == REACHABLE {
+                    tried.entry(REACHABLE).or_default().push(second);
+                    schedule.record_success(&address, peer(), now);
+                } else {
+                    tried.entry(UNREACHABLE).or_default().push(second);
+                    schedule.record_failure(&address, now, 0.0);
+                }
+            }
+        }
+        tried
+    }
+
+    #[test]
+    fn unreachable_address_backs_off_while_the_reachable_stays_connected() {
+        let mut schedule = OutboundSchedule::default();
+        let tried = replay(&mut schedule, 0, 5 * 60 * 60);
+        // dialed once, and not again while connected
+        assert_eq!(tried[REACHABLE], [0]);
+        let gaps: Vec<i64> = tried[UNREACHABLE]
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .collect();
+        // doubling from BASE_BACKOFF until capped at MAX_BACKOFF
+        assert_eq!(
+            gaps,
+            [
+                5, 10, 20, 40, 80, 160, 320, 640, 1280, 2560, 3600, 3600, 3600
+            ]
+        );
+        assert!(gaps.iter().all(|gap| *gap <= MAX_BACKOFF));
+    }
+
+    #[test]
+    fn dropped_connection_is_redialed_after_a_pause() {
+        let mut schedule = OutboundSchedule::default();
+        replay(&mut schedule, 0, 100);
+        assert!(schedule.disconnected(&peer(), seconds(100)));
+        // not ours to redial
+        assert!(!schedule.disconnected(&"10.0.0.3:9000".parse().unwrap(), seconds(100)));
+        let tried = replay(&mut schedule, 100, 200);
+        assert_eq!(tried[REACHABLE], [100 + BASE_BACKOFF]);
+    }
+
+    #[test]
+    fn next_attempt_is_the_earliest_pending_retry() {
+        let mut schedule = OutboundSchedule::default();
+        assert_eq!(schedule.next_attempt(seconds(0)), None);
+        schedule.record_failure(UNREACHABLE, seconds(0), 0.0);
+        schedule.record_failure("10.0.0.3:9000", seconds(1), 0.0);
+        assert_eq!(schedule.next_attempt(seconds(2)), Some(seconds(5)));
+        assert_eq!(schedule.next_attempt(seconds(5)), Some(seconds(6)));
+    }
+
+    #[test]
+    fn jitter_adds_at_most_a_quarter() {
+        assert_eq!(backoff(1, 0.0), Duration::seconds(BASE_BACKOFF));
+        assert_eq!(backoff(3, 1.0), Duration::seconds(25));
+        assert_eq!(
+            backoff(u32::MAX, 0.5),
+            Duration::seconds(MAX_BACKOFF * 9 / 8)
+        );
+    }
+}
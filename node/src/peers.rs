@@ -0,0 +1,406 @@
+use crate::config::NodeConfig;
+use chrono::{DateTime, Duration, Utc};
+use lib::bloom::BloomFilter;
+use lib::network::{Message, PeerInfo, Services};
+use lib::types::Block;
+use lib::utils::Saveable;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::Result as IoResult;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+// how long a misbehaving peer stays banned, in seconds
+pub const BAN_DURATION: i64 = 24 * 60 * 60;
+// the longest a ban can last, in seconds
+pub const MAX_BAN_DURATION: i64 = 10 * 365 * 24 * 60 * 60;
+
+// offenses a peer can commit, each weighted by how
+// unlikely it is to happen to an honest peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offense {
+    // a message that could not be deserialized
+    MalformedMessage,
+    // a block whose hash does not match its target
+    InvalidProofOfWork,
+    // a transaction or block with an invalid input signature
+    InvalidSignature,
+    // oversize payloads, unsolicited responses and other
+    // messages that break the protocol
+    ProtocolViolation,
+}
+
+impl Offense {
+    pub fn weight(&self) -> u32 {
+        match self {
+            Offense::MalformedMessage => 10,
+            Offense::InvalidProofOfWork => 100,
+            Offense::InvalidSignature => 50,
+            Offense::ProtocolViolation => 20,
+        }
+    }
+}
+
+// known node addresses and banned peers, persisted
+// next to the blockchain file
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AddressBook {
+    pub nodes: HashSet<String>,
+    pub bans: HashMap<IpAddr, DateTime<Utc>>,
+    // addresses that kept failing, tried only after the others
+    #[serde(default)]
+    pub failed: HashSet<String>,
+    // addresses given by the operator, tried first and never
+    // marked failed
+    #[serde(default)]
+    pub manual: HashSet<String>,
+}
+
+// host:port with a numeric port, hostnames are resolved when dialing
+pub fn is_valid_address(address: &str) -> bool {
+    if address.parse::<SocketAddr>().is_ok() {
+        return true;
+    }
+    match address.rsplit_once(':') {
+        Some((host, port)) => {
+            !host.is_empty()
+                && !host.contains(|c: char| c.is_whitespace() || c == ':')
+                && port.parse::<u16>().is_ok()
+        }
+        None => false,
+    }
+}
+
+// one address per line, # starts a comment; invalid
+// lines are skipped with a warning
+pub fn parse_peers_file(contents: &str) -> Vec<String> {
+    let mut addresses = vec![];
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if is_valid_address(line) {
+            addresses.push(line.to_string());
+        } else {
+            warn!(
+                line = index + 1,
+                address = line,
+                "invalid address in peers file"
+            );
+        }
+    }
+    addresses
+}
+
+impl Saveable for AddressBook {
+    const TYPE_TAG: &'static str = "AddressBook";
+}
+
+// cumulative traffic of a connection
+#[derive(Debug, Default)]
+pub struct PeerStats {
+    pub bytes_sent: AtomicU64,
+    pub bytes_received: AtomicU64,
+}
+
+// half of a tcp stream counting the bytes that cross it
+#[derive(Debug)]
+pub struct Counted<T> {
+    inner: T,
+    stats: Arc<PeerStats>,
+}
+
+impl<T> Counted<T> {
+    pub fn new(inner: T, stats: Arc<PeerStats>) -> Self {
+        Counted { inner, stats }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Counted<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - before;
+        self.stats
+            .bytes_received
+            .fetch_add(read as u64, Ordering::Relaxed);
+        result
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Counted<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<IoResult<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            self.stats
+                .bytes_sent
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// who opened a connection, rpc connections come from the
+// admin listener and don't count against the peer caps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+    Rpc,
+}
+
+// what both sides agreed on in the handshake: the lower of the
+// two protocol versions and the services both support
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+    pub version: u32,
+    pub services: Services,
+}
+
+// how the rest of the node reaches a connected peer
+pub struct PeerHandle {
+    // queue of the peer's writer task
+    pub sender: mpsc::Sender<Message>,
+    pub stats: Arc<PeerStats>,
+    pub negotiated: Negotiated,
+    // cancelling this disconnects the peer
+    pub shutdown: CancellationToken,
+    // what the peer loaded with FilterLoad, blocks are
+    // sent to it as FilteredBlock while it has one
+    pub filter: Option<BloomFilter>,
+}
+
+impl PeerHandle {
+    fn queue(&self, message: &Message) -> bool {
+        if self.sender.try_send(message.clone()).is_ok() {
+            return true;
+        }
+        self.shutdown.cancel();
+        false
+    }
+}
+
+pub struct PeerManager {
+    address_book: AddressBook,
+    // misbehavior score at which a peer gets disconnected and banned
+    ban_threshold: u32,
+    max_inbound: usize,
+    max_outbound: usize,
+    // slots taken by open connections, including
+    // those still in the handshake
+    inbound: usize,
+    outbound: usize,
+    scores: HashMap<IpAddr, u32>,
+    connections: HashMap<SocketAddr, PeerHandle>,
+}
+
+impl PeerManager {
+    pub fn new(address_book: AddressBook, config: &NodeConfig) -> Self {
+        PeerManager {
+            address_book,
+            ban_threshold: config.ban_threshold,
+            max_inbound: config.max_inbound,
+            max_outbound: config.max_outbound,
+            inbound: 0,
+            outbound: 0,
+            scores: HashMap::new(),
+            connections: HashMap::new(),
+        }
+    }
+
+    pub fn address_book(&self) -> &AddressBook {
+        &self.address_book
+    }
+
+    // check if an address is banned, dropping the ban if it expired
+    pub fn is_banned(&mut self, ip: IpAddr) -> bool {
+        match self.address_book.bans.get(&ip) {
+            Some(expiry) if *expiry > Utc::now() => true,
+            Some(_) => {
+                self.address_book.bans.remove(&ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    // ban an address and disconnect all of its connections, for at
+    // most MAX_BAN_DURATION
+    pub fn ban(&mut self, ip: IpAddr, seconds: i64) {
+        let now = Utc::now();
+        let expiry = Duration::try_seconds(seconds.clamp(0, MAX_BAN_DURATION))
+            .and_then(|duration| now.checked_add_signed(duration))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        self.address_book.bans.insert(ip, expiry);
+        self.scores.remove(&ip);
+        self.connections.retain(|addr, connection| {
+            if addr.ip() == ip {
+                connection.shutdown.cancel();
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    // all bans that have not yet expired
+    pub fn banned(&self) -> Vec<(IpAddr, DateTime<Utc>)> {
+        let now = Utc::now();
+        self.address_book
+            .bans
+            .iter()
+            .filter(|(_, expiry)| **expiry > now)
+            .map(|(ip, expiry)| (*ip, *expiry))
+            .collect()
+    }
+
+    // record an offense, returns true if the peer got banned
+    pub fn misbehaving(&mut self, ip: IpAddr, offense: Offense) -> bool {
+        let score = self.scores.entry(ip).or_insert(0);
+        *score += offense.weight();
+        warn!(%ip, ?offense, score, "peer misbehaved");
+        if *score >= self.ban_threshold {
+            warn!(%ip, "banning peer");
+            self.ban(ip, BAN_DURATION);
+            return true;
+        }
+        false
+    }
+
+    pub fn add_node(&mut self, node: String) {
+        self.address_book.nodes.insert(node);
+    }
+
+    // take a connection slot, false if the cap is reached
+    pub fn reserve_slot(&mut self, direction: Direction) -> bool {
+        let (used, max) = match direction {
+            Direction::Inbound => (&mut self.inbound, self.max_inbound),
+            Direction::Outbound => (&mut self.outbound, self.max_outbound),
+            Direction::Rpc => return true,
+        };
+        if *used >= max {
+            return false;
+        }
+        *used += 1;
+        true
+    }
+
+    pub fn release_slot(&mut self, direction: Direction) {
+        match direction {
+            Direction::Inbound => self.inbound = self.inbound.saturating_sub(1),
+            Direction::Outbound => self.outbound = self.outbound.saturating_sub(1),
+            Direction::Rpc => {}
+        }
+    }
+
+    pub fn outbound_count(&self) -> usize {
+        self.outbound
+    }
+
+    pub fn add_manual_node(&mut self, address: String) {
+        self.address_book.failed.remove(&address);
+        self.address_book.manual.insert(address.clone());
+        self.address_book.nodes.insert(address);
+    }
+
+    pub fn mark_failed(&mut self, address: &str) {
+        if self.address_book.manual.contains(address) {
+            return;
+        }
+        self.address_book.failed.insert(address.to_string());
+    }
+
+    // returns true if the address was marked failed before
+    pub fn mark_reachable(&mut self, address: &str) -> bool {
+        self.address_book.failed.remove(address)
+    }
+
+    pub fn add_connection(&mut self, addr: SocketAddr, handle: PeerHandle) {
+        self.connections.insert(addr, handle);
+    }
+
+    pub fn remove_connection(&mut self, addr: &SocketAddr) {
+        self.connections.remove(addr);
+    }
+
+    // connected peers and the services they share with us
+    pub fn connected(&self) -> Vec<(SocketAddr, Services)> {
+        self.connections
+            .iter()
+            .map(|(addr, connection)| (*addr, connection.negotiated.services))
+            .collect()
+    }
+
+    // replace the filter of a peer, None clears it
+    pub fn set_filter(&mut self, addr: SocketAddr, filter: Option<BloomFilter>) {
+        if let Some(connection) = self.connections.get_mut(&addr) {
+            connection.filter = filter;
+        }
+    }
+
+    // a block as a peer with a filter loaded gets it, None for
+    // peers without one; matches teach the filter their outputs
+    pub fn filtered_block(&mut self, addr: SocketAddr, block: &Block) -> Option<Message> {
+        let filter = self.connections.get_mut(&addr)?.filter.as_mut()?;
+        Some(Message::FilteredBlock {
+            header: block.header.clone(),
+            transactions: filter.filter_block(block),
+        })
+    }
+
+    // queue a message for one peer, dropping the connection
+    // if it is gone or too slow to keep up
+    pub fn send(&mut self, addr: SocketAddr, message: &Message) {
+        let Some(connection) = self.connections.get(&addr) else {
+            return;
+        };
+        if !connection.queue(message) {
+            self.connections.remove(&addr);
+        }
+    }
+
+    pub fn peer_info(&self) -> Vec<PeerInfo> {
+        self.connections
+            .iter()
+            .map(|(addr, connection)| PeerInfo {
+                address: addr.to_string(),
+                version: connection.negotiated.version,
+                services: connection.negotiated.services,
+                bytes_sent: connection.stats.bytes_sent.load(Ordering::Relaxed),
+                bytes_received: connection.stats.bytes_received.load(Ordering::Relaxed),
+                misbehavior_score: self.scores.get(&addr.ip()).copied().unwrap_or(0),
+            })
+            .collect()
+    }
+
+    // queue a message for every connected peer except the origin,
+    // dropping connections that can't take it
+    pub fn broadcast(&mut self, message: &Message, except: Option<SocketAddr>) {
+        self.connections
+            .retain(|addr, connection| Some(*addr) == except || connection.queue(message));
+    }
+}
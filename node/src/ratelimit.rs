@@ -0,0 +1,154 @@
+use crate::peers::Offense;
+use lib::network::Message;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+// messages are rate limited per class, so a peer flooding
+// cheap control messages can't starve its block requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageClass {
+    Control,
+    Inventory,
+    DataRequest,
+}
+
+impl MessageClass {
+    pub fn of(message: &Message) -> Self {
+        use Message::*;
+        match message {
+            FetchUTXOs(_)
+            | FetchTemplate(_)
+            | ValidateTemplate(_)
+            | FetchBlock(_)
+            | GetBlockTxn { .. }
+            | GetData(_)
+            | GetMempool { .. }
+            | GetRawTransaction(_) => MessageClass::DataRequest,
+            SubmitTransaction(_)
+            | SendRawTransaction(_)
+            | NewTransaction(_)
+            | SubmitTemplate(_)
+            | NewBlock(_)
+            | CompactBlock { .. }
+            | BlockTxn { .. }
+            | Inv(_) => MessageClass::Inventory,
+            _ => MessageClass::Control,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RateLimit {
+    // how many messages may arrive back to back
+    pub burst: u32,
+    // how many messages per second are allowed on average
+    pub per_second: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+    pub enabled: bool,
+    pub control: RateLimit,
+    pub inventory: RateLimit,
+    pub data_request: RateLimit,
+    // dropped messages after which a peer counts as abusive
+    pub abuse_threshold: u32,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            enabled: true,
+            control: RateLimit {
+                burst: 20,
+                per_second: 2.0,
+            },
+            inventory: RateLimit {
+                burst: 200,
+                per_second: 50.0,
+            },
+            data_request: RateLimit {
+                burst: 50,
+                per_second: 10.0,
+            },
+            abuse_threshold: 100,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        TokenBucket {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// token buckets of a single connection
+#[derive(Debug)]
+pub struct PeerLimiter {
+    enabled: bool,
+    control: TokenBucket,
+    inventory: TokenBucket,
+    data_request: TokenBucket,
+    dropped: u32,
+    abuse_threshold: u32,
+}
+
+impl PeerLimiter {
+    pub fn new(limits: &RateLimits) -> Self {
+        PeerLimiter {
+            enabled: limits.enabled,
+            control: TokenBucket::new(limits.control),
+            inventory: TokenBucket::new(limits.inventory),
+            data_request: TokenBucket::new(limits.data_request),
+            dropped: 0,
+            abuse_threshold: limits.abuse_threshold,
+        }
+    }
+
+    // Ok(true) if the message may be handled, Ok(false) if it
+    // should be dropped, and an offense once drops pile up
+    pub fn check(&mut self, message: &Message) -> Result<bool, Offense> {
+        if !self.enabled {
+            return Ok(true);
+        }
+        let bucket = match MessageClass::of(message) {
+            MessageClass::Control => &mut self.control,
+            MessageClass::Inventory => &mut self.inventory,
+            MessageClass::DataRequest => &mut self.data_request,
+        };
+        if bucket.try_take() {
+            return Ok(true);
+        }
+        self.dropped += 1;
+        if self.dropped >= self.abuse_threshold {
+            self.dropped = 0;
+            return Err(Offense::ProtocolViolation);
+        }
+        Ok(false)
+    }
+}
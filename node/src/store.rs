@@ -0,0 +1,12 @@
+use lib::utils::Saveable;
+use std::io::Result as IoResult;
+use std::path::Path;
+
+// load a file from the data directory, or start fresh if it does not exist yet
+pub fn load_or_default<T: Saveable + Default, P: AsRef<Path>>(path: P) -> IoResult<T> {
+    if path.as_ref().exists() {
+        T::load_from_file(path)
+    } else {
+        Ok(T::default())
+    }
+}
@@ -0,0 +1,147 @@
+use crate::peers::Offense;
+use lib::error::SbdError;
+use lib::params::NetworkParams;
+use lib::sha256::Hash;
+use lib::types::{Block, BlockHeader, HeaderChain};
+use std::collections::BTreeMap;
+use tracing::info;
+
+// what to ask the peer for after a batch of headers
+#[derive(Debug, PartialEq, Eq)]
+pub enum NextStep {
+    // the batch was full, the peer probably has more
+    MoreHeaders(Vec<Hash>),
+    // headers are complete, fetch these block bodies
+    Bodies(Vec<Hash>),
+}
+
+// best known header chain, ahead of the validated
+// blocks while a headers-first sync is running
+#[derive(Debug)]
+pub struct HeaderSync {
+    params: NetworkParams,
+    headers: HeaderChain,
+    // of the blockchain; branches replacing these are never adopted
+    checkpoints: BTreeMap<u64, Hash>,
+}
+
+impl HeaderSync {
+    pub fn new<'a>(params: NetworkParams, blocks: impl Iterator<Item = &'a Block>) -> Self {
+        HeaderSync {
+            params,
+            headers: HeaderChain::from_blocks(blocks),
+            checkpoints: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_checkpoints(mut self, checkpoints: BTreeMap<u64, Hash>) -> Self {
+        self.checkpoints = checkpoints;
+        self
+    }
+
+    pub fn locator(&self) -> Vec<Hash> {
+        self.headers.locator()
+    }
+
+    // connect a batch of headers from a peer, switching to
+    // its branch only if it carries more work than ours
+    pub fn add_headers(
+        &mut self,
+        block_height: u64,
+        headers: Vec<BlockHeader>,
+    ) -> Result<NextStep, Offense> {
+        let Some(first) = headers.first() else {
+            return Ok(NextStep::Bodies(self.missing_bodies(block_height)));
+        };
+        let fork_height = if first.prev_block_hash == Hash::zero() {
+            0
+        } else {
+            match self.headers.height_of(&first.prev_block_hash) {
+                Some(height) => height + 1,
+                // headers that don't connect to anything we know
+                None => return Err(Offense::ProtocolViolation),
+            }
+        };
+        let full_batch = headers.len() >= lib::MAX_HEADERS_PER_MESSAGE;
+        let mut candidate = self.headers.clone();
+        candidate.truncate(fork_height);
+        for header in headers {
+            // a target off the schedule claims work that wasn't done
+            if header.target != candidate.next_target(&self.params) {
+                return Err(Offense::InvalidProofOfWork);
+            }
+            match candidate.add_header(header) {
+                Ok(()) => {}
+                Err(SbdError::InvalidProofOfWork) => return Err(Offense::InvalidProofOfWork),
+                Err(_) => return Err(Offense::ProtocolViolation),
+            }
+        }
+        // a branch going back on a checkpoint is never adopted,
+        // nor is any more of it wanted
+        if let Some(height) = self.conflicting_checkpoint(&candidate, fork_height) {
+            info!(height, "ignoring header branch replacing a checkpoint");
+            return Ok(NextStep::Bodies(self.missing_bodies(block_height)));
+        }
+        let locator = candidate.locator();
+        // validated blocks can't be replaced, so branches forking
+        // below them are never adopted
+        if candidate.total_work() > self.headers.total_work() {
+            if fork_height < block_height {
+                info!(
+                    fork_height,
+                    "ignoring header branch forking below our blocks"
+                );
+            } else {
+                self.headers = candidate;
+            }
+        }
+        if full_batch {
+            return Ok(NextStep::MoreHeaders(locator));
+        }
+        Ok(NextStep::Bodies(self.missing_bodies(block_height)))
+    }
+
+    // the first checkpoint a branch forking at fork_height has another
+    // header at, or goes back on by leaving our headers below it
+    fn conflicting_checkpoint(&self, candidate: &HeaderChain, fork_height: u64) -> Option<u64> {
+        // headers a peer sent again that we already have go back on nothing
+        let diverges_at = (fork_height..candidate.height()).find(|height| {
+            candidate.get(*height).map(BlockHeader::hash)
+                != self.headers.get(*height).map(BlockHeader::hash)
+        })?;
+        self.checkpoints
+            .range(diverges_at..)
+            .find(|(height, hash)| match candidate.get(**height) {
+                Some(header) => header.hash() != **hash,
+                None => self.headers.height() > **height,
+            })
+            .map(|(height, _)| *height)
+    }
+
+    // hashes of headers we don't have blocks for yet
+    pub fn missing_bodies(&self, block_height: u64) -> Vec<Hash> {
+        self.headers
+            .headers()
+            .get(block_height as usize..)
+            .unwrap_or_default()
+            .iter()
+            .map(|header| header.hash())
+            .collect()
+    }
+
+    // false if the best header chain has a different
+    // header at the height the block would connect at
+    pub fn expects(&self, block_height: u64, header: &BlockHeader) -> bool {
+        match self.headers.get(block_height) {
+            Some(expected) => expected.hash() == header.hash(),
+            None => true,
+        }
+    }
+
+    // keep the header chain in step with newly added blocks
+    pub fn block_connected(&mut self, block_height: u64, header: &BlockHeader) {
+        if self.headers.height() < block_height {
+            let _ = self.headers.add_header(header.clone());
+        }
+    }
+}
@@ -0,0 +1,118 @@
+// misbehavior scoring: offenses add up until the peer is banned, and
+// a banned peer can't connect again
+mod common;
+
+use common::{StubPeer, TestNode, regtest_builder, save_chain};
+use lib::U256;
+use lib::network::Message;
+use lib::sha256::Hash;
+
+// a header building on nothing the node knows, a protocol violation
+fn dangling_headers() -> Message {
+    let mut builder = regtest_builder(631);
+    let mut header = builder.block(|_| {}).unwrap().header;
+    header.prev_block_hash = Hash::hash(&header);
+    header.thaw();
+    Message::Headers(vec![header])
+}
+
+#[test]
+fn offenses_add_up_to_a_ban() {
+    let node = TestNode::start();
+    let mut peer = node.connect(0);
+    // four protocol violations, 20 each, stay under the threshold of 100
+    for offense in 1..=4u64 {
+        peer.send(dangling_headers());
+        node.wait_for(&format!("offense=ProtocolViolation score={}", 20 * offense));
+        peer.send(Message::Ping(offense));
+        let pong = peer.expect(|message| match message {
+            Message::Pong(nonce) => Some(nonce),
+            _ => None,
+        });
+        assert_eq!(pong, offense);
+    }
+    assert!(!node.printed("banning peer"));
+
+    peer.send(dangling_headers());
+    assert!(peer.disconnected());
+    node.wait_for("banning peer ip=127.0.0.1");
+
+    assert!(StubPeer::connect(node.addr, 0).is_none());
+    node.wait_for("refusing connection from banned peer addr=127.0.0.1");
+}
+
+// mined to an easier target than the chain's, the block claims work
+// it doesn't have
+#[test]
+fn block_off_the_chain_target_is_banned() {
+    let mut builder = regtest_builder(631);
+    let mut block = builder.block(|_| {}).unwrap();
+    block.header.target = U256::MAX;
+    block.header.thaw();
+    let node = TestNode::start();
+    let mut peer = node.connect(1);
+    peer.send(Message::NewBlock(block));
+    assert!(peer.disconnected());
+    node.wait_for("offense=InvalidProofOfWork score=100");
+    node.wait_for("banning peer ip=127.0.0.1");
+    assert!(!node.printed("added block"));
+}
+
+// a block the chain refuses counts against the peer that sent it
+#[test]
+fn block_with_a_timestamp_before_the_tip_is_a_violation() {
+    let mut builder = regtest_builder(631);
+    builder.mine_blocks(1).unwrap();
+    let chain = builder.chain().clone();
+    let tip = chain.blocks().last().unwrap().header.timestamp;
+    let block = builder.block(|template| template.timestamp = tip).unwrap();
+    let hash = block.hash();
+    let node = TestNode::start_with(|datadir| save_chain(datadir, &chain));
+    node.wait_for("restored chain blocks=1");
+    let mut peer = node.connect(2);
+    peer.send(Message::NewBlock(block));
+    node.wait_for("offense=ProtocolViolation score=20");
+    assert!(!node.printed("added block"));
+    // warned of once, naming the peer, the block and why
+    let warnings: Vec<String> = node
+        .lines("rejected block")
+        .into_iter()
+        .filter(|line| line.contains("WARN"))
+        .collect();
+    assert_eq!(warnings.len(), 1, "{warnings:#?}");
+    assert!(warnings[0].contains("peer=127.0.0.1:"), "{}", warnings[0]);
+    assert!(
+        warnings[0].contains(&format!("hash={hash}")),
+        "{}",
+        warnings[0]
+    );
+    assert!(warnings[0].contains("reason="), "{}", warnings[0]);
+}
+
+// a ban too long to represent is refused, and the node goes on
+#[test]
+fn ban_past_the_longest_is_refused() {
+    let node = TestNode::start();
+    let mut admin = node.connect(0);
+    admin.send(Message::SetBan("10.0.0.1".into(), u64::MAX));
+    let reason = admin.expect(|message| match message {
+        Message::BanRefused { address, reason } if address == "10.0.0.1" => Some(reason),
+        _ => None,
+    });
+    assert!(reason.contains("at most"), "{reason}");
+    admin.send(Message::ListBanned);
+    let bans = admin.expect(|message| match message {
+        Message::BannedList(bans) => Some(bans),
+        _ => None,
+    });
+    assert!(bans.is_empty());
+
+    // the longest ban allowed still goes through
+    admin.send(Message::SetBan("10.0.0.1".into(), 10 * 365 * 24 * 60 * 60));
+    admin.send(Message::ListBanned);
+    let bans = admin.expect(|message| match message {
+        Message::BannedList(bans) => Some(bans),
+        _ => None,
+    });
+    assert_eq!(bans.len(), 1);
+}
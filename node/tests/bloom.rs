@@ -0,0 +1,190 @@
+// a light client syncing from a node with a bloom filter loaded:
+// blocks fetched with GetData and one announced arrive filtered, and
+// hold exactly the wallet's transactions, each proven against a
+// header the client checks; after FilterClear blocks come whole again
+mod common;
+
+use common::{TestNode, regtest_builder, save_chain};
+use lib::bloom::BloomFilter;
+use lib::light_client::LightClient;
+use lib::network::{InvItem, InvKind, Message};
+use lib::sha256::Hash;
+use lib::test_utils::ChainBuilder;
+use lib::types::{Block, BlockHeader, Blockchain, Transaction};
+use lib::utils::MerkleProof;
+
+const SEED: u64 = 722;
+const WALLET: usize = 1;
+const KEYS: usize = 4;
+// blocks the client syncs, the last announced rather than fetched
+const SYNCED: usize = 20;
+const FEE: u64 = 100;
+
+// xorshift64*, deterministic for a seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+}
+
+// a block of up to three spends between the funded keys, the
+// wallet's among them, key 0 only mining
+fn next_block(builder: &mut ChainBuilder, rng: &mut Rng) -> Block {
+    let mut spends: Vec<Transaction> = vec![];
+    let mut from: Vec<usize> = vec![];
+    for _ in 0..1 + rng.below(3) {
+        let key = 1 + rng.below(KEYS - 1);
+        // a key spends at most once a block, its change unconfirmed
+        if from.contains(&key) {
+            continue;
+        }
+        let to = 1 + rng.below(KEYS - 1);
+        let amount = 1000 + rng.below(10_000) as u64;
+        spends.push(builder.spend(key, to, amount, FEE).unwrap());
+        from.push(key);
+    }
+    builder
+        .mine_block(|template| template.transactions = spends)
+        .unwrap()
+}
+
+// the transactions paying or spending the wallet's key
+fn wallet_transactions(builder: &ChainBuilder, block: &Block, owned: &[Hash]) -> Vec<Transaction> {
+    let wallet = builder.key(WALLET).public_key();
+    block
+        .transactions
+        .iter()
+        .filter(|tx| {
+            tx.outputs.iter().any(|output| output.pubkey == wallet)
+                || tx
+                    .inputs
+                    .iter()
+                    .any(|input| owned.contains(&input.prev_transaction_output_hash))
+        })
+        .cloned()
+        .collect()
+}
+
+// a FilteredBlock's header and transactions
+fn filtered(message: Message) -> Option<(BlockHeader, Vec<(Transaction, MerkleProof)>)> {
+    match message {
+        Message::FilteredBlock {
+            header,
+            transactions,
+        } => Some((header, transactions)),
+        _ => None,
+    }
+}
+
+#[test]
+fn filtered_sync_of_twenty_blocks() {
+    let mut rng = Rng(SEED);
+    let mut builder = regtest_builder(SEED).with_keys(KEYS);
+    let reward = builder.chain().params().reward_at_height(0);
+    for key in 1..KEYS {
+        builder.fund(key, &[reward / 4; 4]).unwrap();
+    }
+    let maturity = builder.chain().params().coinbase_maturity;
+    builder.mine_blocks(maturity).unwrap();
+
+    // the client trusts the tip before the blocks it syncs, and the
+    // wallet knows its outputs up to there
+    let checkpoint = builder.chain().block_height();
+    let tip = builder.chain().blocks().last().unwrap().header.clone();
+    let mut client =
+        LightClient::from_checkpoint(builder.chain().params().clone(), checkpoint - 1, tip)
+            .unwrap();
+    let wallet = builder.key(WALLET).public_key();
+    let mut owned: Vec<Hash> = builder
+        .spendable(WALLET)
+        .into_iter()
+        .map(|(hash, _)| hash)
+        .collect();
+    let mut filter = BloomFilter::new(100, 0.0001, SEED as u32);
+    filter.insert_pubkey(&wallet);
+    for hash in &owned {
+        filter.insert_hash(hash);
+    }
+
+    let mut blocks = vec![];
+    let mut expected = vec![];
+    // what the node starts with, all but the last block
+    let mut chain: Option<Blockchain> = None;
+    for _ in 0..SYNCED {
+        if blocks.len() == SYNCED - 1 {
+            chain = Some(builder.chain().clone());
+        }
+        let block = next_block(&mut builder, &mut rng);
+        let ours = wallet_transactions(&builder, &block, &owned);
+        for tx in &ours {
+            owned.extend(
+                tx.outputs
+                    .iter()
+                    .filter(|output| output.pubkey == wallet)
+                    .map(|output| output.hash()),
+            );
+        }
+        expected.push(ours);
+        blocks.push(block);
+    }
+    assert!(expected.iter().filter(|ours| !ours.is_empty()).count() > SYNCED / 4);
+    assert!(expected.iter().any(|ours| ours.is_empty()));
+
+    let chain = chain.unwrap();
+    let last = blocks.pop().unwrap();
+    let height = chain.block_height();
+    let node = TestNode::start_with(|datadir| save_chain(datadir, &chain));
+    node.wait_for(&format!("restored chain blocks={height}"));
+
+    let mut peer = node.connect(checkpoint);
+    peer.send(Message::FilterLoad(filter));
+    peer.send(Message::GetData(
+        blocks
+            .iter()
+            .map(|block| InvItem {
+                kind: InvKind::Block,
+                hash: block.hash(),
+            })
+            .collect(),
+    ));
+    for (block, ours) in blocks.iter().zip(&expected) {
+        let (header, transactions) = peer.expect(filtered);
+        assert_eq!(header, block.header);
+        let verified = client.add_filtered_block(header, transactions).unwrap();
+        assert_eq!(&verified, ours, "block {}", block.hash());
+    }
+    assert_eq!(client.tip().unwrap().hash(), chain.tip_hash());
+
+    // the last comes announced, filtered the same way
+    let mut other = node.connect(height);
+    other.send(Message::NewBlock(last.clone()));
+    node.wait_for(&format!("added block hash={}", last.hash()));
+    let (header, transactions) = peer.expect(filtered);
+    assert_eq!(header, last.header);
+    let verified = client.add_filtered_block(header, transactions).unwrap();
+    assert_eq!(&verified, expected.last().unwrap());
+    assert_eq!(client.height(), checkpoint + SYNCED as u64);
+    assert_eq!(client.tip().unwrap().hash(), last.hash());
+
+    // with the filter cleared, a block comes whole
+    peer.send(Message::FilterClear);
+    peer.send(Message::GetData(vec![InvItem {
+        kind: InvKind::Block,
+        hash: last.hash(),
+    }]));
+    let txids = peer.expect(|message| match message {
+        Message::CompactBlock { header, txids } if header == last.header => Some(txids),
+        _ => None,
+    });
+    let all: Vec<Hash> = last.transactions.iter().map(|tx| tx.hash()).collect();
+    assert_eq!(txids, all);
+}
@@ -0,0 +1,63 @@
+// peers the operator lists are dialed before any the node learned of
+// from other peers, and a bad line in the list is skipped, not fatal
+mod common;
+
+use common::TestNode;
+use lib::network::Message;
+use std::fs;
+use std::net::TcpListener;
+use tempfile::TempDir;
+
+const FIXTURE: &str = include_str!("fixtures/peers.txt");
+
+// the index of the first line printed containing text
+fn position(node: &TestNode, text: &str) -> usize {
+    node.wait_for(text);
+    node.lines("")
+        .iter()
+        .position(|line| line.contains(text))
+        .unwrap()
+}
+
+#[test]
+fn bad_lines_in_the_peers_file_are_warned_of() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("peers.txt");
+    fs::write(&path, FIXTURE).unwrap();
+    let node = TestNode::start_with_args(&["--peers-file", path.to_str().unwrap()], |_| {});
+    for line in 6..=9 {
+        node.wait_for(&format!("invalid address in peers file line={line} "));
+    }
+    assert_eq!(node.lines("invalid address in peers file").len(), 4);
+    // and the good ones are dialed
+    node.wait_for("failed to connect address=\"127.0.0.1:1\"");
+    node.wait_for("failed to connect address=\"localhost:1\"");
+}
+
+#[test]
+fn manual_peers_are_dialed_before_gossiped_ones() {
+    let gossiped = TcpListener::bind("127.0.0.1:0").unwrap();
+    let gossiped = gossiped.local_addr().unwrap();
+    let manual = TcpListener::bind("127.0.0.1:0").unwrap();
+    let manual = manual.local_addr().unwrap();
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("peers.txt");
+    fs::write(&path, "").unwrap();
+
+    // learn of a node from a peer, which the node remembers
+    let node = TestNode::start_with_args(&["--peers-file", path.to_str().unwrap()], |_| {});
+    let mut peer = node.connect(0);
+    peer.send(Message::NodeList(vec![gossiped.to_string()]));
+    node.wait_for(&format!("connected peer={gossiped}"));
+    drop(peer);
+
+    // then start again with the operator listing one of their own
+    fs::write(&path, format!("{FIXTURE}{manual}\n")).unwrap();
+    let node = node.restart();
+    let manual_at = position(&node, &format!("connected peer={manual}"));
+    let gossiped_at = position(&node, &format!("connected peer={gossiped}"));
+    assert!(manual_at < gossiped_at);
+    for address in ["127.0.0.1:1", "localhost:1", "[::1]:1"] {
+        assert!(position(&node, &format!("failed to connect address={address:?}")) < gossiped_at);
+    }
+}
@@ -0,0 +1,125 @@
+// a node holding its chain to a signed checkpoint above its blocks:
+// a longer header branch forking below the checkpoint is ignored and
+// the node keeps fetching its own bodies, where without the bundle it
+// switches to that branch
+mod common;
+
+use common::{TestNode, regtest_builder, save_chain};
+use lib::network::Message;
+use lib::sha256::Hash;
+use lib::test_utils::seeded_key;
+use lib::types::{Block, BlockHeader, CheckpointBundle, SignedCheckpoint};
+use lib::utils::Saveable;
+use tempfile::TempDir;
+
+const SEED: u64 = 726;
+const BLOCKS: usize = 12;
+// the node's blocks, where the branch forks off
+const FORK: usize = 5;
+const CHECKPOINT: usize = 8;
+// more than BLOCKS, so the branch has more work
+const BRANCH_BLOCKS: usize = 20;
+
+fn headers(blocks: &[Block]) -> Vec<BlockHeader> {
+    blocks.iter().map(|block| block.header.clone()).collect()
+}
+
+// the chain, and a branch of it forking at FORK and going on longer
+fn chains() -> (Vec<Block>, Vec<Block>) {
+    let mut builder = regtest_builder(SEED);
+    builder.mine_blocks(BLOCKS as u64).unwrap();
+    let blocks: Vec<Block> = builder.chain().blocks().cloned().collect();
+    let mut branch = regtest_builder(SEED);
+    for block in &blocks[..FORK] {
+        branch.chain_mut().add_block(block.clone()).unwrap();
+    }
+    while branch.chain().block_height() < BRANCH_BLOCKS as u64 {
+        branch.mine_block(|template| template.miner = 1).unwrap();
+    }
+    let branch: Vec<Block> = branch.chain().blocks().cloned().collect();
+    (blocks, branch)
+}
+
+// a node with the first FORK blocks, told the headers of the chain
+// and then those of the branch; the hash it asks the branch's peer
+// for first
+fn first_asked_after_the_branch(checkpointed: bool) -> (TestNode, Hash) {
+    let (blocks, branch) = chains();
+    let mut chain = regtest_builder(SEED).into_chain();
+    for block in &blocks[..FORK] {
+        chain.add_block(block.clone()).unwrap();
+    }
+
+    let dir = TempDir::new().unwrap();
+    let bundle_path = dir.path().join("checkpoints.cbor");
+    let operator = seeded_key(SEED, 100);
+    let address = operator.public_key().to_address();
+    let bundle = CheckpointBundle {
+        checkpoints: vec![SignedCheckpoint::sign(
+            CHECKPOINT as u64,
+            blocks[CHECKPOINT].hash(),
+            blocks[CHECKPOINT].header.timestamp,
+            &operator,
+        )],
+    };
+    bundle.save_to_file(&bundle_path).unwrap();
+    let bundle_path = bundle_path.to_str().unwrap();
+    let args = match checkpointed {
+        true => vec![
+            "--checkpoint-file",
+            bundle_path,
+            "--checkpoint-key",
+            &address,
+        ],
+        false => vec![],
+    };
+    let node = TestNode::start_with_args(&args, |datadir| save_chain(datadir, &chain));
+    node.wait_for(&format!("restored chain blocks={FORK}"));
+    if checkpointed {
+        node.wait_for("applied signed checkpoints added=1");
+    }
+
+    let mut honest = node.connect(BLOCKS as u64);
+    honest.expect(|message| match message {
+        Message::GetHeaders { .. } => Some(()),
+        _ => None,
+    });
+    // answering the locator, with the headers after the node's blocks
+    honest.send(Message::Headers(headers(&blocks[FORK..])));
+    for block in &blocks[FORK..] {
+        honest.expect(|message| match message {
+            Message::FetchBlockByHash(hash) if hash == block.hash() => Some(()),
+            _ => None,
+        });
+    }
+
+    let mut other = node.connect(BRANCH_BLOCKS as u64);
+    other.expect(|message| match message {
+        Message::GetHeaders { .. } => Some(()),
+        _ => None,
+    });
+    other.send(Message::Headers(headers(&branch[FORK..])));
+    let asked = other.expect(|message| match message {
+        Message::FetchBlockByHash(hash) => Some(hash),
+        _ => None,
+    });
+    (node, asked)
+}
+
+#[test]
+fn branch_replacing_a_checkpoint_is_ignored() {
+    let (blocks, _) = chains();
+    let (node, asked) = first_asked_after_the_branch(true);
+    node.wait_for(&format!(
+        "ignoring header branch replacing a checkpoint height={CHECKPOINT}"
+    ));
+    assert_eq!(asked, blocks[FORK].hash());
+}
+
+#[test]
+fn branch_is_taken_without_the_checkpoint() {
+    let (_, branch) = chains();
+    let (node, asked) = first_asked_after_the_branch(false);
+    assert_eq!(asked, branch[FORK].hash());
+    assert!(!node.printed("replacing a checkpoint"));
+}
@@ -0,0 +1,60 @@
+// the node as a user runs it: --help, a usage error, which exits 2,
+// and a regtest node coming up, until it's killed
+use assert_cmd::Command;
+use predicates::prelude::*;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn node() -> Command {
+    Command::cargo_bin("node").unwrap()
+}
+
+#[test]
+fn help() {
+    node()
+        .arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Usage: node").and(predicate::str::contains("--datadir")));
+}
+
+#[test]
+fn invalid_port_is_a_usage_error() {
+    node()
+        .args(["--port", "not-a-port"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--port"));
+}
+
+#[test]
+fn missing_config_exits_1() {
+    node()
+        .args(["--config", "no-such-config.toml"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("no-such-config.toml"));
+}
+
+#[test]
+fn regtest_node_listens() {
+    let dir = TempDir::new().unwrap();
+    node()
+        .arg("--datadir")
+        .arg(dir.path())
+        .args([
+            "--network",
+            "regtest",
+            "--listen",
+            "127.0.0.1",
+            "--port",
+            "0",
+        ])
+        .timeout(Duration::from_secs(5))
+        .assert()
+        .interrupted()
+        .stdout(predicate::str::contains(
+            "listening for peers addr=127.0.0.1:",
+        ));
+    assert!(dir.path().join("regtest").is_dir());
+}
@@ -0,0 +1,263 @@
+// a regtest node run from its binary, and stub peers talking to it
+// over the wire protocol, frame by frame
+#![allow(dead_code)]
+use assert_cmd::cargo::cargo_bin;
+use lib::network::{Message, Services};
+use lib::params::NetworkParams;
+use lib::test_utils::ChainBuilder;
+use lib::types::Blockchain;
+use lib::utils::Saveable;
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// how long a test waits on the node before failing
+pub const WAIT: Duration = Duration::from_secs(20);
+
+pub fn magic() -> [u8; 4] {
+    NetworkParams::regtest().magic
+}
+
+// a chain the node accepts, grown as ChainBuilder does
+pub fn regtest_builder(seed: u64) -> ChainBuilder {
+    ChainBuilder::with_params(seed, NetworkParams::regtest())
+}
+
+// the lines the node printed, and a wakeup for each new one
+#[derive(Default)]
+struct Output {
+    lines: Mutex<Vec<String>>,
+    printed: Condvar,
+}
+
+pub struct TestNode {
+    child: Child,
+    pub addr: SocketAddr,
+    output: Arc<Output>,
+    args: Vec<String>,
+    // kept until the node is dropped, or handed on by restart
+    dir: Option<TempDir>,
+}
+
+impl TestNode {
+    pub fn start() -> Self {
+        Self::start_with(|_| {})
+    }
+
+    // prepare gets the network's data dir before the node starts,
+    // to leave a chain or mempool there for it to restore
+    pub fn start_with(prepare: impl FnOnce(&Path)) -> Self {
+        Self::start_with_args(&[], prepare)
+    }
+
+    // with args passed to the node after the usual ones
+    pub fn start_with_args(args: &[&str], prepare: impl FnOnce(&Path)) -> Self {
+        let dir = TempDir::new().unwrap();
+        let datadir: PathBuf = dir.path().join("regtest");
+        std::fs::create_dir_all(&datadir).unwrap();
+        prepare(&datadir);
+        let args = args.iter().map(|arg| arg.to_string()).collect();
+        Self::spawn(dir, args)
+    }
+
+    fn spawn(dir: TempDir, args: Vec<String>) -> Self {
+        let mut child = Command::new(cargo_bin("node"))
+            .arg("--datadir")
+            .arg(dir.path())
+            .args([
+                "--network",
+                "regtest",
+                "--listen",
+                "127.0.0.1",
+                "--port",
+                "0",
+            ])
+            .args(&args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let output = Arc::new(Output::default());
+        let stdout = child.stdout.take().unwrap();
+        let lines = output.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                lines.lines.lock().unwrap().push(line);
+                lines.printed.notify_all();
+            }
+        });
+        let mut node = TestNode {
+            child,
+            addr: "127.0.0.1:0".parse().unwrap(),
+            output,
+            args,
+            dir: Some(dir),
+        };
+        let listening = node.wait_for("listening for peers");
+        node.addr = field(&listening, "addr").parse().unwrap();
+        node
+    }
+
+    // stop the node as an operator would, letting it save its state,
+    // and start it again on the same data dir
+    pub fn restart(mut self) -> Self {
+        let status = Command::new("kill")
+            .args(["-TERM", &self.child.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let status = self.child.wait().unwrap();
+        assert!(status.success(), "node exited with {status}");
+        let dir = self.dir.take().unwrap();
+        Self::spawn(dir, std::mem::take(&mut self.args))
+    }
+
+    // the first line printed containing text, waiting up to WAIT
+    pub fn wait_for(&self, text: &str) -> String {
+        let deadline = Instant::now() + WAIT;
+        let mut lines = self.output.lines.lock().unwrap();
+        loop {
+            if let Some(line) = lines.iter().find(|line| line.contains(text)) {
+                return line.clone();
+            }
+            let left = deadline
+                .checked_duration_since(Instant::now())
+                .unwrap_or_else(|| panic!("node never printed {text:?}, only {lines:#?}"));
+            lines = self.output.printed.wait_timeout(lines, left).unwrap().0;
+        }
+    }
+
+    // every line printed so far containing text
+    pub fn lines(&self, text: &str) -> Vec<String> {
+        let lines = self.output.lines.lock().unwrap();
+        lines
+            .iter()
+            .filter(|line| line.contains(text))
+            .cloned()
+            .collect()
+    }
+
+    pub fn printed(&self, text: &str) -> bool {
+        let lines = self.output.lines.lock().unwrap();
+        lines.iter().any(|line| line.contains(text))
+    }
+
+    pub fn connect(&self, block_height: u64) -> StubPeer {
+        StubPeer::connect(self.addr, block_height).expect("handshake with the node failed")
+    }
+}
+
+impl Drop for TestNode {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// the value of a field of an event the node printed
+pub fn field<'a>(line: &'a str, name: &str) -> &'a str {
+    let prefix = format!("{name}=");
+    line.split(' ')
+        .find_map(|part| part.strip_prefix(&prefix))
+        .unwrap_or_else(|| panic!("no {name} in {line:?}"))
+}
+
+// leave the chain for the node to import, as an older version saved it
+pub fn save_chain(datadir: &Path, chain: &Blockchain) {
+    chain.save_to_file(datadir.join("blockchain.cbor")).unwrap();
+    chain
+        .mempool_snapshot()
+        .save_to_file(datadir.join("mempool.cbor"))
+        .unwrap();
+}
+
+// one connection to the node, claiming every service and a height
+pub struct StubPeer {
+    stream: TcpStream,
+}
+
+impl StubPeer {
+    // the node opens with its Version, then reads ours; None if it
+    // hangs up instead
+    pub fn connect(addr: SocketAddr, block_height: u64) -> Option<Self> {
+        Self::connect_as(addr, block_height, lib::PROTOCOL_VERSION, Services::ALL)
+    }
+
+    // as a peer of another version, supporting only services
+    pub fn connect_as(
+        addr: SocketAddr,
+        block_height: u64,
+        version: u32,
+        services: Services,
+    ) -> Option<Self> {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(WAIT)).unwrap();
+        let mut peer = StubPeer { stream };
+        match Message::read_from(magic(), &mut peer.stream) {
+            Ok(Message::Version { .. }) => {}
+            _ => return None,
+        }
+        peer.send(Message::Version {
+            magic: magic(),
+            version,
+            services,
+            block_height,
+        });
+        Some(peer)
+    }
+
+    // writing to a connection the node dropped may fail, which the
+    // tests find out from what they read next
+    pub fn send(&mut self, message: Message) {
+        let _ = message.write_to(magic(), &mut self.stream);
+    }
+
+    // the next message, None once the node hangs up
+    pub fn recv(&mut self) -> Option<Message> {
+        match Message::read_from(magic(), &mut self.stream) {
+            Ok(message) => Some(message),
+            Err(ciborium::de::Error::Io(e))
+                if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut =>
+            {
+                panic!("the node sent nothing for {WAIT:?}")
+            }
+            Err(_) => None,
+        }
+    }
+
+    // every message that arrives until none has for quiet
+    pub fn drain(&mut self, quiet: Duration) -> Vec<Message> {
+        self.stream.set_read_timeout(Some(quiet)).unwrap();
+        let mut messages = vec![];
+        while let Ok(message) = Message::read_from(magic(), &mut self.stream) {
+            messages.push(message);
+        }
+        self.stream.set_read_timeout(Some(WAIT)).unwrap();
+        messages
+    }
+
+    // the first message f maps to Some, answering pings on the way
+    pub fn expect<T>(&mut self, mut f: impl FnMut(Message) -> Option<T>) -> T {
+        loop {
+            let message = self.recv().expect("the node hung up");
+            if let Message::Ping(nonce) = message {
+                self.send(Message::Pong(nonce));
+                continue;
+            }
+            if let Some(found) = f(message) {
+                return found;
+            }
+        }
+    }
+
+    // true once the node hangs up, skipping whatever it sends first
+    pub fn disconnected(&mut self) -> bool {
+        while self.recv().is_some() {}
+        true
+    }
+}
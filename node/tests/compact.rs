@@ -0,0 +1,93 @@
+// compact blocks: the node rebuilds a block from its mempool and
+// fetches only the transactions it doesn't hold
+mod common;
+
+use common::{StubPeer, TestNode, regtest_builder, save_chain};
+use lib::U256;
+use lib::network::Message;
+use lib::sha256::Hash;
+
+// spends in the block, after its coinbase
+const FUNDED: usize = 9;
+const FEE: u64 = 1000;
+
+#[test]
+fn block_connects_with_one_of_ten_fetched() {
+    let mut builder = regtest_builder(632);
+    let reward = builder.chain().params().reward_at_height(0);
+    let funded = builder.fund(0, &[reward / FUNDED as u64; FUNDED]).unwrap();
+    let maturity = builder.chain().params().coinbase_maturity;
+    builder.mine_blocks(maturity).unwrap();
+    let spends: Vec<_> = funded
+        .iter()
+        .map(|output| {
+            let payment = builder.output(output.value - FEE, 1);
+            builder.sign(&[output.hash()], vec![payment]).unwrap()
+        })
+        .collect();
+    // the node holds every spend, the coinbase is never in a mempool
+    let mut chain = builder.chain().clone();
+    for spend in &spends {
+        chain.add_to_mempool(spend.clone()).unwrap();
+    }
+    let height = chain.block_height();
+    let node = TestNode::start_with(|datadir| save_chain(datadir, &chain));
+    node.wait_for(&format!("restored chain blocks={height} mempool={FUNDED}"));
+
+    let block = builder
+        .block(|template| template.transactions = spends.clone())
+        .unwrap();
+    let block_hash = block.hash();
+    let mut peer = node.connect(height);
+    peer.send(Message::CompactBlock {
+        header: block.header.clone(),
+        txids: block.transactions.iter().map(|tx| tx.hash()).collect(),
+    });
+    let indexes = peer.expect(|message| match message {
+        Message::GetBlockTxn {
+            block_hash: hash,
+            indexes,
+        } if hash == block_hash => Some(indexes),
+        _ => None,
+    });
+    assert_eq!(block.transactions.len(), 10);
+    assert_eq!(indexes, vec![0]);
+    peer.send(Message::BlockTxn {
+        block_hash,
+        transactions: vec![block.transactions[0].clone()],
+    });
+    node.wait_for("reconstructed block reused=9 fetched=1");
+    node.wait_for(&format!("added block hash={block_hash}"));
+
+    peer.send(Message::FetchBlockByHash(block_hash));
+    let connected = peer.expect(|message| match message {
+        Message::NewBlock(block) => Some(block),
+        _ => None,
+    });
+    assert_eq!(connected, block);
+    assert_ne!(connected.header.prev_block_hash, Hash::zero());
+}
+
+// work at a target of the peer's choosing counts for nothing; a
+// header claiming one gets the peer banned before anything is kept
+#[test]
+fn compact_block_off_the_target_schedule_bans_the_peer() {
+    let mut builder = regtest_builder(633);
+    builder.mine_blocks(2).unwrap();
+    let chain = builder.chain().clone();
+    let node = TestNode::start_with(|datadir| save_chain(datadir, &chain));
+    node.wait_for("restored chain blocks=2");
+
+    let block = builder.block(|_| {}).unwrap();
+    let mut header = block.header.clone();
+    header.target = U256::MAX;
+    header.thaw();
+    assert!(header.hash().matches_target(header.target));
+    let mut peer = node.connect(2);
+    peer.send(Message::CompactBlock {
+        header,
+        txids: block.transactions.iter().map(|tx| tx.hash()).collect(),
+    });
+    assert!(peer.disconnected());
+    assert!(StubPeer::connect(node.addr, 2).is_none());
+}
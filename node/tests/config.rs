@@ -0,0 +1,53 @@
+// the config as the running node applies it: a bad file stops it
+// before it starts, and max_inbound caps the peers it accepts
+mod common;
+
+use assert_cmd::Command;
+use common::{StubPeer, TestNode};
+use lib::network::Message;
+use predicates::prelude::*;
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+#[test]
+fn invalid_config_file_exits_1() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("node.toml");
+    fs::write(&path, "max_outbound = 2\ntarget_outbound = 3\n").unwrap();
+    Command::cargo_bin("node")
+        .unwrap()
+        .arg("--config")
+        .arg(&path)
+        .arg("--datadir")
+        .arg(dir.path())
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(
+            "target_outbound (3) can't exceed max_outbound (2)",
+        ));
+}
+
+#[test]
+fn accept_past_max_inbound_is_refused() {
+    let node = TestNode::start_with_args(&["--max-inbound", "1"], |_| {});
+    let mut first = node.connect(0);
+    assert!(StubPeer::connect(node.addr, 0).is_none());
+    node.wait_for("refusing connection, inbound limit reached");
+    // the first is still served
+    first.send(Message::Ping(7));
+    let pong = first.expect(|message| match message {
+        Message::Pong(nonce) => Some(nonce),
+        _ => None,
+    });
+    assert_eq!(pong, 7);
+
+    // and its slot is free again once it leaves
+    drop(first);
+    let deadline = Instant::now() + common::WAIT;
+    while StubPeer::connect(node.addr, 0).is_none() {
+        assert!(Instant::now() < deadline, "slot never freed");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,91 @@
+// a node connecting to a peer asks for its mempool, and fills its
+// own with what of it passes its policy
+mod common;
+
+use common::{TestNode, regtest_builder, save_chain};
+use lib::network::{InvKind, Message};
+use lib::sha256::Hash;
+use std::collections::HashSet;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PENDING: usize = 20;
+// of the pending, spending outputs the other node's tip spent already
+const CONFLICTING: usize = 2;
+const FEE: u64 = 1000;
+
+// wait for the node to print text count times
+fn wait_for_count(node: &TestNode, text: &str, count: usize) {
+    let deadline = Instant::now() + common::WAIT;
+    while node.lines(text).len() < count {
+        assert!(
+            Instant::now() < deadline,
+            "node printed {text:?} too few times"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn fresh_node_takes_the_mempool_of_its_peer() {
+    let mut builder = regtest_builder(641);
+    let reward = builder.chain().params().reward_at_height(0);
+    let funded = builder
+        .fund(1, &[reward / PENDING as u64; PENDING])
+        .unwrap();
+    let maturity = builder.chain().params().coinbase_maturity;
+    builder.mine_blocks(maturity).unwrap();
+    let pending: Vec<_> = funded
+        .iter()
+        .map(|output| {
+            let payment = builder.output(output.value - FEE, 2);
+            builder.sign(&[output.hash()], vec![payment]).unwrap()
+        })
+        .collect();
+    let conflicting: Vec<_> = funded[..CONFLICTING]
+        .iter()
+        .map(|output| {
+            let payment = builder.output(output.value - 2 * FEE, 3);
+            builder.sign(&[output.hash()], vec![payment]).unwrap()
+        })
+        .collect();
+    // two tips of the same height, so neither node syncs from the
+    // other; only b's confirms the conflicting spends
+    let tip_a = builder.block(|_| {}).unwrap();
+    let tip_b = builder
+        .block(|template| template.transactions = conflicting)
+        .unwrap();
+    let mut chain_a = builder.chain().clone();
+    chain_a.add_block(tip_a).unwrap();
+    for transaction in &pending {
+        chain_a.add_to_mempool(transaction.clone()).unwrap();
+    }
+    let mut chain_b = builder.chain().clone();
+    chain_b.add_block(tip_b).unwrap();
+    let height = chain_a.block_height();
+
+    let a = TestNode::start_with(|datadir| save_chain(datadir, &chain_a));
+    a.wait_for(&format!("restored chain blocks={height} mempool={PENDING}"));
+    let addnode = a.addr.to_string();
+    let b = TestNode::start_with_args(&["--addnode", &addnode], |datadir| {
+        save_chain(datadir, &chain_b)
+    });
+    b.wait_for(&format!("restored chain blocks={height} mempool=0"));
+
+    wait_for_count(&b, "added transaction to mempool", PENDING - CONFLICTING);
+    wait_for_count(&b, "rejected transaction", CONFLICTING);
+    let expected: HashSet<Hash> = pending[CONFLICTING..]
+        .iter()
+        .map(|transaction| transaction.hash())
+        .collect();
+    let mut peer = b.connect(height);
+    peer.send(Message::GetMempool { min_fee: None });
+    let held = peer.expect(|message| match message {
+        Message::Inv(items) => Some(items),
+        _ => None,
+    });
+    assert!(held.iter().all(|item| item.kind == InvKind::Tx));
+    let held: HashSet<Hash> = held.iter().map(|item| item.hash).collect();
+    assert_eq!(held, expected);
+    assert!(!b.printed("misbehaved"));
+}
@@ -0,0 +1,91 @@
+// a peer of an older version, without any of the optional services,
+// is served what it understands instead of being dropped
+mod common;
+
+use common::{StubPeer, TestNode, regtest_builder, save_chain};
+use lib::network::{Message, Services};
+use lib::types::Block;
+use std::time::Duration;
+
+const OLD_VERSION: u32 = lib::PROTOCOL_VERSION - 1;
+
+// a node holding the first `held` of `mined` blocks
+fn behind(seed: u64, held: usize, mined: usize) -> (TestNode, Vec<Block>) {
+    let mut builder = regtest_builder(seed);
+    builder.mine_blocks(held as u64).unwrap();
+    let chain = builder.chain().clone();
+    builder.mine_blocks((mined - held) as u64).unwrap();
+    let blocks = builder.chain().blocks().cloned().collect();
+    let node = TestNode::start_with(|datadir| save_chain(datadir, &chain));
+    node.wait_for(&format!("restored chain blocks={held}"));
+    (node, blocks)
+}
+
+fn old_peer(node: &TestNode, block_height: u64) -> StubPeer {
+    StubPeer::connect_as(node.addr, block_height, OLD_VERSION, Services::NONE)
+        .expect("handshake with the node failed")
+}
+
+// nothing the old peer never advertised is sent its way
+fn understood(message: &Message) {
+    assert_eq!(
+        message.required_services(),
+        Services::NONE,
+        "sent to an old peer: {message:?}"
+    );
+}
+
+#[test]
+fn old_peer_ahead_is_synced_by_height() {
+    let (node, blocks) = behind(640, 2, 4);
+    let mut peer = old_peer(&node, 4);
+    for (height, block) in blocks.iter().enumerate().skip(2) {
+        let requested = peer.expect(|message| {
+            understood(&message);
+            match message {
+                Message::FetchBlock(requested) => Some(requested),
+                _ => None,
+            }
+        });
+        assert_eq!(requested, height);
+        peer.send(Message::NewBlock(block.clone()));
+    }
+    node.wait_for(&format!("added block hash={}", blocks[3].hash()));
+    for message in peer.drain(Duration::from_millis(500)) {
+        understood(&message);
+    }
+    assert!(!node.printed("misbehaved"));
+}
+
+#[test]
+fn block_from_a_new_peer_reaches_an_old_peer_in_full() {
+    let (node, blocks) = behind(641, 2, 3);
+    let mut old = old_peer(&node, 2);
+    let mut new = node.connect(2);
+    new.send(Message::NewBlock(blocks[2].clone()));
+    let relayed = old.expect(|message| {
+        understood(&message);
+        match message {
+            Message::NewBlock(block) => Some(block),
+            _ => None,
+        }
+    });
+    assert_eq!(relayed.hash(), blocks[2].hash());
+
+    // a message for a service it never advertised is held against
+    // the peer, but doesn't cost it the connection
+    old.send(Message::CompactBlock {
+        header: blocks[2].header.clone(),
+        txids: vec![blocks[2].transactions[0].hash()],
+    });
+    node.wait_for("offense=ProtocolViolation score=20");
+    old.send(Message::Ping(640));
+    let nonce = old.expect(|message| {
+        understood(&message);
+        match message {
+            Message::Pong(nonce) => Some(nonce),
+            _ => None,
+        }
+    });
+    assert_eq!(nonce, 640);
+}
@@ -0,0 +1,85 @@
+// networks kept apart: a peer speaking another network's magic fails
+// the handshake, and a node won't load another network's chain
+mod common;
+
+use assert_cmd::Command;
+use common::{TestNode, magic};
+use lib::network::{Message, Services};
+use lib::params::NetworkParams;
+use lib::test_utils::{ChainBuilder, INSTANT_TARGET};
+use lib::utils::Saveable;
+use predicates::prelude::*;
+use std::net::TcpStream;
+use tempfile::TempDir;
+
+// a peer answering the node's Version with a Version in framing of
+// frame_magic, claiming version_magic; true if the node hangs up
+// without a word more
+fn handshake_refused(frame_magic: [u8; 4], version_magic: [u8; 4]) -> bool {
+    let node = TestNode::start();
+    let mut stream = TcpStream::connect(node.addr).unwrap();
+    stream.set_read_timeout(Some(common::WAIT)).unwrap();
+    assert!(matches!(
+        Message::read_from(magic(), &mut stream),
+        Ok(Message::Version { .. })
+    ));
+    Message::Version {
+        magic: version_magic,
+        version: lib::PROTOCOL_VERSION,
+        services: Services::ALL,
+        block_height: 0,
+    }
+    .write_to(frame_magic, &mut stream)
+    .unwrap();
+    node.wait_for("handshake failed");
+    assert!(node.printed("offense=ProtocolViolation"));
+    Message::read_from(magic(), &mut stream).is_err()
+}
+
+#[test]
+fn mainnet_peer_is_refused_by_a_regtest_node() {
+    let mainnet = NetworkParams::mainnet().magic;
+    assert!(handshake_refused(mainnet, mainnet));
+}
+
+// framed for our network, but claiming another in its Version
+#[test]
+fn version_claiming_another_network_is_refused() {
+    assert!(handshake_refused(magic(), NetworkParams::testnet().magic));
+}
+
+#[test]
+fn testnet_chain_is_refused_by_a_mainnet_node() {
+    // testnet rules, on a target the builder mines at once
+    let params = NetworkParams {
+        min_target: INSTANT_TARGET,
+        ..NetworkParams::testnet()
+    };
+    let mut builder = ChainBuilder::with_params(634, params);
+    builder.mine_blocks(3).unwrap();
+    let dir = TempDir::new().unwrap();
+    // mainnet keeps its files in the data dir itself
+    builder
+        .chain()
+        .save_to_file(dir.path().join("blockchain.cbor"))
+        .unwrap();
+    Command::cargo_bin("node")
+        .unwrap()
+        .arg("--datadir")
+        .arg(dir.path())
+        .args([
+            "--network",
+            "mainnet",
+            "--listen",
+            "127.0.0.1",
+            "--port",
+            "0",
+        ])
+        .timeout(common::WAIT)
+        .assert()
+        .code(1)
+        .stderr(
+            predicate::str::contains("refusing to load")
+                .and(predicate::str::contains("expected mainnet, found testnet")),
+        );
+}
@@ -0,0 +1,44 @@
+// rate limiting is per connection: a peer flooding pings gets only
+// its burst answered, while another peer is still served at once
+mod common;
+
+use common::TestNode;
+use lib::network::Message;
+use std::time::{Duration, Instant};
+
+// well over the control burst of 20, and under the 100 drops that
+// would count as abuse and end in a ban covering both peers
+const FLOOD: u64 = 60;
+const NONCE: u64 = 1_000_000;
+
+#[test]
+fn flooding_peer_is_throttled_while_another_is_served() {
+    let node = TestNode::start();
+    let mut flooder = node.connect(0);
+    let mut normal = node.connect(0);
+    for nonce in 0..FLOOD {
+        flooder.send(Message::Ping(nonce));
+    }
+
+    let sent = Instant::now();
+    normal.send(Message::Ping(NONCE));
+    let pong = normal.expect(|message| match message {
+        Message::Pong(nonce) => Some(nonce),
+        _ => None,
+    });
+    assert_eq!(pong, NONCE);
+    assert!(
+        sent.elapsed() < Duration::from_secs(1),
+        "{:?}",
+        sent.elapsed()
+    );
+
+    let answered = flooder
+        .drain(Duration::from_millis(500))
+        .into_iter()
+        .filter(|message| matches!(message, Message::Pong(_)))
+        .count() as u64;
+    // the burst, and what refilled while the flood was read
+    assert!((20..FLOOD / 2).contains(&answered), "{answered} answered");
+    assert!(!node.printed("misbehaved"));
+}
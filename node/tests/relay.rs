@@ -0,0 +1,57 @@
+// nodes on ephemeral ports find each other through --addnode and
+// relay a block from either end of a line of them to the other
+mod common;
+
+use common::{TestNode, regtest_builder, save_chain};
+use lib::network::Message;
+use lib::types::Blockchain;
+
+fn start(chain: &Blockchain, addnode: Option<&TestNode>) -> TestNode {
+    let addnode = addnode.map(|node| node.addr.to_string());
+    let args: Vec<&str> = match &addnode {
+        Some(addr) => vec!["--addnode", addr],
+        None => vec![],
+    };
+    let node = TestNode::start_with_args(&args, |datadir| save_chain(datadir, chain));
+    if let Some(addr) = &addnode {
+        node.wait_for(&format!("connected peer={addr}"));
+    }
+    node
+}
+
+#[test]
+fn block_crosses_a_line_of_three_nodes() {
+    let mut builder = regtest_builder(644);
+    builder.mine_blocks(2).unwrap();
+    let chain = builder.chain().clone();
+    let height = chain.block_height();
+    // a <- b <- c, each dialing the one before
+    let a = start(&chain, None);
+    let b = start(&chain, Some(&a));
+    let c = start(&chain, Some(&b));
+    for node in [&a, &b, &c] {
+        assert!(!node.printed("handshake failed"));
+    }
+
+    let first = builder.mine_block(|_| {}).unwrap();
+    let mut peer = a.connect(height);
+    peer.send(Message::NewBlock(first.clone()));
+    for node in [&a, &b, &c] {
+        node.wait_for(&format!("added block hash={}", first.hash()));
+    }
+
+    // and back the other way
+    let second = builder.mine_block(|_| {}).unwrap();
+    let mut peer = c.connect(height + 1);
+    peer.send(Message::NewBlock(second.clone()));
+    for node in [&c, &b, &a] {
+        node.wait_for(&format!("added block hash={}", second.hash()));
+    }
+    let mut peer = a.connect(height + 2);
+    peer.send(Message::FetchBlock(height as usize + 1));
+    let tip = peer.expect(|message| match message {
+        Message::NewBlock(block) => Some(block),
+        _ => None,
+    });
+    assert_eq!(tip, second);
+}
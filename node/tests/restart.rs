@@ -0,0 +1,55 @@
+// a node stopped and started again on the same data dir picks up
+// where it left off: same height, same tip, same mempool
+mod common;
+
+use common::{TestNode, regtest_builder, save_chain};
+use lib::network::{InvKind, Message};
+
+#[test]
+fn chain_and_mempool_survive_a_restart() {
+    let mut builder = regtest_builder(633);
+    builder.fund(1, &[1000]).unwrap();
+    builder
+        .mine_blocks(builder.chain().params().coinbase_maturity)
+        .unwrap();
+    let restored = builder.chain().block_height();
+    let chain = builder.chain().clone();
+    let node = TestNode::start_with(|datadir| save_chain(datadir, &chain));
+    node.wait_for(&format!("restored chain blocks={restored} mempool=0"));
+
+    // grow the chain by a block, and leave a spend pending
+    builder.mine_blocks(1).unwrap();
+    let tip = builder.chain().blocks().last().unwrap().clone();
+    let spend = builder.spend(1, 2, 900, 100).unwrap();
+    let mut peer = node.connect(restored + 1);
+    peer.send(Message::NewBlock(tip.clone()));
+    node.wait_for(&format!("added block hash={}", tip.hash()));
+    peer.send(Message::SubmitTransaction(spend.clone()));
+    let accepted = peer.expect(|message| match message {
+        Message::TransactionAccepted(hash) => Some(hash),
+        Message::TransactionRejected { reason, .. } => panic!("spend rejected: {reason}"),
+        _ => None,
+    });
+    assert_eq!(accepted, spend.hash());
+    drop(peer);
+
+    let node = node.restart();
+    node.wait_for(&format!("restored chain blocks={} mempool=1", restored + 1));
+    let mut peer = node.connect(restored + 1);
+    peer.send(Message::FetchBlock(restored as usize));
+    let served = peer.expect(|message| match message {
+        Message::NewBlock(block) => Some(block),
+        _ => None,
+    });
+    assert_eq!(served.hash(), tip.hash());
+    peer.send(Message::GetMempool { min_fee: None });
+    let pending = peer.expect(|message| match message {
+        Message::Inv(items) => Some(items),
+        _ => None,
+    });
+    assert!(
+        pending
+            .iter()
+            .any(|item| item.kind == InvKind::Tx && item.hash == spend.hash())
+    );
+}
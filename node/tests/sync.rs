@@ -0,0 +1,147 @@
+// headers-first sync: the node takes every header before asking for
+// a body, and keeps to the branch with the most work
+mod common;
+
+use common::{TestNode, regtest_builder};
+use lib::U256;
+use lib::network::Message;
+use lib::sha256::Hash;
+use lib::types::{Block, BlockHeader};
+
+const BLOCKS: usize = 100;
+// a branch of fewer blocks, with less work at the same target
+const BOGUS_BLOCKS: usize = 30;
+
+fn mine(seed: u64, count: usize) -> Vec<Block> {
+    let mut builder = regtest_builder(seed);
+    builder.mine_blocks(count as u64).unwrap();
+    builder.chain().blocks().cloned().collect()
+}
+
+fn headers(blocks: &[Block]) -> Vec<BlockHeader> {
+    blocks.iter().map(|block| block.header.clone()).collect()
+}
+
+#[test]
+fn syncs_headers_before_bodies_and_ignores_a_low_work_branch() {
+    let blocks = mine(636, BLOCKS);
+    let bogus = mine(637, BOGUS_BLOCKS);
+    let hashes: Vec<Hash> = blocks.iter().map(Block::hash).collect();
+    let node = TestNode::start();
+    node.wait_for("restored chain blocks=0");
+
+    // nothing is fetched before the headers
+    let mut honest = node.connect(BLOCKS as u64);
+    honest.expect(|message| match message {
+        Message::GetHeaders { .. } => Some(()),
+        Message::FetchBlock(_) | Message::FetchBlockByHash(_) => {
+            panic!("fetched a block before syncing headers: {message:?}")
+        }
+        _ => None,
+    });
+    honest.send(Message::Headers(headers(&blocks)));
+    let mut fetched = vec![];
+    while fetched.len() < BLOCKS {
+        fetched.push(honest.expect(|message| match message {
+            Message::FetchBlockByHash(hash) => Some(hash),
+            _ => None,
+        }));
+    }
+    assert_eq!(fetched, hashes);
+    node.wait_for(&format!("headers synced, fetching bodies blocks={BLOCKS}"));
+
+    // a peer claiming more blocks, with a branch of less work
+    let mut liar = node.connect(2 * BLOCKS as u64);
+    liar.expect(|message| match message {
+        Message::GetHeaders { .. } => Some(()),
+        _ => None,
+    });
+    liar.send(Message::Headers(headers(&bogus)));
+    // it's asked for the bodies of the best chain, not its own
+    let asked = liar.expect(|message| match message {
+        Message::FetchBlockByHash(hash) => Some(hash),
+        _ => None,
+    });
+    assert_eq!(asked, hashes[0]);
+
+    assert!(!node.printed("added block"));
+    for block in &blocks {
+        honest.send(Message::NewBlock(block.clone()));
+    }
+    let tip = hashes.last().unwrap();
+    node.wait_for(&format!("added block hash={tip}"));
+    assert!(!node.printed("not on the best header chain"));
+    honest.send(Message::FetchBlockByHash(*tip));
+    let served = honest.expect(|message| match message {
+        Message::NewBlock(block) => Some(block),
+        _ => None,
+    });
+    assert_eq!(&served, blocks.last().unwrap());
+}
+
+// the honest peer serves the chain first, as the liar's ban covers the
+// address they share; the liar is then banned without the node asking
+// it for a single body
+fn liar_is_banned(seed: u64, lie: impl FnOnce(&[Block]) -> Vec<BlockHeader>) {
+    let blocks = mine(seed, 5);
+    let node = TestNode::start();
+    node.wait_for("restored chain blocks=0");
+    let mut honest = node.connect(blocks.len() as u64);
+    honest.expect(|message| match message {
+        Message::GetHeaders { .. } => Some(()),
+        _ => None,
+    });
+    honest.send(Message::Headers(headers(&blocks)));
+    for block in &blocks {
+        honest.expect(|message| match message {
+            Message::FetchBlockByHash(hash) if hash == block.hash() => Some(()),
+            _ => None,
+        });
+        honest.send(Message::NewBlock(block.clone()));
+    }
+    node.wait_for(&format!(
+        "added block hash={}",
+        blocks.last().unwrap().hash()
+    ));
+
+    let mut liar = node.connect(2 * BLOCKS as u64);
+    liar.expect(|message| match message {
+        Message::GetHeaders { .. } => Some(()),
+        _ => None,
+    });
+    liar.send(Message::Headers(lie(&blocks)));
+    while let Some(message) = liar.recv() {
+        assert!(
+            !matches!(message, Message::FetchBlockByHash(_)),
+            "adopted the lie: {message:?}"
+        );
+    }
+    node.wait_for("offense=InvalidProofOfWork score=100");
+    node.wait_for("banning peer ip=127.0.0.1");
+}
+
+// a first header claiming the hardest target, which would outweigh
+// any real chain, and has no work behind it
+#[test]
+fn max_work_genesis_header_is_refused() {
+    liar_is_banned(638, |blocks| {
+        let mut header = blocks[0].header.clone();
+        header.target = U256::zero();
+        header.thaw();
+        vec![header]
+    });
+}
+
+// a branch whose last header gives itself an easier target than the
+// schedule, with work enough for that target only
+#[test]
+fn header_off_the_target_schedule_is_refused() {
+    liar_is_banned(639, |blocks| {
+        let mut headers = headers(&blocks[..3]);
+        let last = headers.last_mut().unwrap();
+        last.target = U256::MAX;
+        last.thaw();
+        assert!(last.hash().matches_target(last.target));
+        headers
+    });
+}
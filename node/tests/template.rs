@@ -0,0 +1,50 @@
+// block templates: the node fills them from its mempool up to the
+// cap and pays the coinbase the reward of the height plus the fees
+mod common;
+
+use common::{TestNode, regtest_builder, save_chain};
+use lib::network::Message;
+
+const FUNDED: usize = 25;
+const FEE: u64 = 1000;
+
+#[test]
+fn template_is_capped_and_pays_reward_plus_fees() {
+    let mut builder = regtest_builder(631);
+    let params = builder.chain().params().clone();
+    let reward = params.reward_at_height(0);
+    let funded = builder.fund(0, &[reward / FUNDED as u64; FUNDED]).unwrap();
+    builder.mine_blocks(params.coinbase_maturity).unwrap();
+    let mut chain = builder.chain().clone();
+    for output in &funded {
+        let payment = builder.output(output.value - FEE, 1);
+        let spend = builder.sign(&[output.hash()], vec![payment]).unwrap();
+        chain.add_to_mempool(spend).unwrap();
+    }
+    let height = chain.block_height();
+    let node = TestNode::start_with_args(&["--mine"], |datadir| save_chain(datadir, &chain));
+    node.wait_for(&format!("restored chain blocks={height} mempool={FUNDED}"));
+
+    let mut peer = node.connect(height);
+    let miner = builder.key(2).public_key();
+    peer.send(Message::FetchTemplate(miner.clone()));
+    let mut template = peer.expect(|message| match message {
+        Message::Template(block) => Some(block),
+        _ => None,
+    });
+    let cap = params.block_transaction_cap;
+    assert!(cap < FUNDED);
+    assert_eq!(template.transactions.len(), 1 + cap);
+    let coinbase = &template.transactions[0].outputs[0];
+    assert_eq!(coinbase.pubkey, miner);
+    assert_eq!(
+        coinbase.value,
+        params.reward_at_height(height) + cap as u64 * FEE
+    );
+
+    // and the node takes it back once mined
+    while !template.header.mine(1_000_000) {}
+    let hash = template.hash();
+    peer.send(Message::SubmitTemplate(template));
+    node.wait_for(&format!("added block hash={hash}"));
+}
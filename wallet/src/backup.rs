@@ -0,0 +1,160 @@
+use crate::wallet::Wallet;
+use lib::crypto::{PrivateKey, PublicKey};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// portable JSON backup of a wallet's keys:
+// {
+//   "version": 1,
+//   "keys": [{ "private_key": hex secret, "address": hex public key,
+//              "label": string or null, "created_height": number or null,
+//              "change": bool, missing means false }],
+//   "watch_only": [{ "address": hex public key,
+//                    "label": string or null, "created_height": number or null }]
+// }
+// created_height is the height of the first block paying the key,
+// null if none was seen yet; scan state is not part of the backup
+pub const BACKUP_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum BackupError {
+    #[error("Unsupported backup version {0}, expected {BACKUP_VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("Invalid private key for address {0}")]
+    InvalidKey(String),
+    #[error("Invalid address {0}")]
+    InvalidAddress(String),
+    #[error("Private key does not match address {0}")]
+    AddressMismatch(String),
+    #[error("Invalid backup: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupKey {
+    pub private_key: String,
+    pub address: String,
+    pub label: Option<String>,
+    pub created_height: Option<u64>,
+    // reserved for change rather than handed out
+    #[serde(default)]
+    pub change: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupWatchOnly {
+    pub address: String,
+    pub label: Option<String>,
+    pub created_height: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Backup {
+    pub version: u32,
+    pub keys: Vec<BackupKey>,
+    pub watch_only: Vec<BackupWatchOnly>,
+}
+
+// what an import added to the wallet
+#[derive(Debug, Default)]
+pub struct Imported {
+    pub keys: usize,
+    pub watch_only: usize,
+    pub labels: usize,
+}
+
+impl Backup {
+    pub fn from_wallet(wallet: &Wallet) -> Self {
+        let label = |address: &String| wallet.labels.get(address).cloned();
+        Backup {
+            version: BACKUP_VERSION,
+            keys: wallet
+                .keys
+                .iter()
+                .map(|key| {
+                    let pubkey = key.public_key();
+                    let address = pubkey.to_address();
+                    BackupKey {
+                        private_key: key.to_hex(),
+                        label: label(&address),
+                        change: wallet.change.contains(&address),
+                        address,
+                        created_height: wallet.first_seen_height(&pubkey),
+                    }
+                })
+                .collect(),
+            watch_only: wallet
+                .watch_only
+                .iter()
+                .map(|pubkey| {
+                    let address = pubkey.to_address();
+                    BackupWatchOnly {
+                        label: label(&address),
+                        address,
+                        created_height: wallet.first_seen_height(pubkey),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).expect("BUG: backup encoding failed")
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self, BackupError> {
+        let backup: Backup = serde_json::from_slice(data)?;
+        if backup.version != BACKUP_VERSION {
+            return Err(BackupError::UnsupportedVersion(backup.version));
+        }
+        Ok(backup)
+    }
+
+    // add the keys the wallet doesn't have yet, keeping its own
+    // labels on conflict; nothing is added if any entry is invalid
+    pub fn merge_into(&self, wallet: &mut Wallet) -> Result<Imported, BackupError> {
+        let mut keys = vec![];
+        for entry in &self.keys {
+            let key = PrivateKey::from_hex(&entry.private_key)
+                .ok_or_else(|| BackupError::InvalidKey(entry.address.clone()))?;
+            if key.public_key().to_address() != entry.address {
+                return Err(BackupError::AddressMismatch(entry.address.clone()));
+            }
+            keys.push((key, entry));
+        }
+        let mut watch_only = vec![];
+        for entry in &self.watch_only {
+            let pubkey = PublicKey::from_address(&entry.address)
+                .ok_or_else(|| BackupError::InvalidAddress(entry.address.clone()))?;
+            watch_only.push((pubkey, &entry.label));
+        }
+
+        let mut imported = Imported::default();
+        let mut labels = vec![];
+        for (key, entry) in keys {
+            let address = key.public_key().to_address();
+            if entry.change {
+                wallet.change.insert(address.clone());
+            }
+            labels.push((address, &entry.label));
+            if wallet.add_key(key) {
+                imported.keys += 1;
+            }
+        }
+        for (pubkey, label) in watch_only {
+            labels.push((pubkey.to_address(), label));
+            if wallet.add_watch_only(pubkey) {
+                imported.watch_only += 1;
+            }
+        }
+        for (address, label) in labels {
+            if let Some(label) = label
+                && !wallet.labels.contains_key(&address)
+            {
+                wallet.labels.insert(address, label.clone());
+                imported.labels += 1;
+            }
+        }
+        Ok(imported)
+    }
+}
@@ -0,0 +1,317 @@
+use crate::wallet::Wallet;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use lib::sha256::checksum;
+use lib::utils::{BackupPolicy, Saveable, write_atomically};
+use std::fs::{self, File};
+use std::io::{Error as IoError, Read, Write};
+use std::path::Path;
+use thiserror::Error;
+
+// wallet file layout: magic, format version, argon2 costs and salt,
+// check bytes of the derived key, nonce, then the encrypted wallet;
+// everything before the ciphertext is authenticated with it
+const MAGIC: [u8; 4] = *b"SBDW";
+pub const FORMAT_VERSION: u8 = 1;
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+const HEADER_SIZE: usize = 4 + 1 + 3 * 4 + SALT_SIZE + 4 + NONCE_SIZE;
+
+#[derive(Error, Debug)]
+pub enum FileError {
+    #[error("Not a wallet file")]
+    NotAWallet,
+    #[error("Unsupported wallet file version {0}, expected {FORMAT_VERSION}")]
+    UnsupportedVersion(u8),
+    #[error("Wallet file is truncated")]
+    Truncated,
+    #[error("Wrong passphrase")]
+    WrongPassphrase,
+    #[error("Wallet file is corrupted or was tampered with")]
+    Tampered,
+    #[error("Invalid key derivation parameters: {0}")]
+    InvalidKdf(argon2::Error),
+    #[error("Failed to read wallet: {0}")]
+    Contents(IoError),
+    #[error("Failed to access wallet file: {0}")]
+    Io(#[from] IoError),
+}
+
+// argon2id costs and salt the file key was derived with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_cost: u32,
+    pub time_cost: u32,
+    pub parallelism: u32,
+    pub salt: [u8; SALT_SIZE],
+}
+
+impl KdfParams {
+    // current default costs with a fresh salt
+    pub fn generate() -> Self {
+        let mut salt = [0u8; SALT_SIZE];
+        OsRng.fill_bytes(&mut salt);
+        KdfParams {
+            memory_cost: Params::DEFAULT_M_COST,
+            time_cost: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+            salt,
+        }
+    }
+
+    fn derive_key(&self, passphrase: &str) -> Result<Key, FileError> {
+        let params = Params::new(self.memory_cost, self.time_cost, self.parallelism, Some(32))
+            .map_err(FileError::InvalidKdf)?;
+        let mut key = Key::default();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &self.salt, &mut key)
+            .map_err(FileError::InvalidKdf)?;
+        Ok(key)
+    }
+}
+
+// an unlocked wallet file, remembering the derived key so
+// saving doesn't need the passphrase again
+pub struct WalletFile {
+    kdf: KdfParams,
+    key: Key,
+    // back up the file before each save over it
+    backup: Option<BackupPolicy>,
+}
+
+impl WalletFile {
+    pub fn new(passphrase: &str) -> Result<Self, FileError> {
+        let kdf = KdfParams::generate();
+        let key = kdf.derive_key(passphrase)?;
+        Ok(WalletFile {
+            kdf,
+            key,
+            backup: None,
+        })
+    }
+
+    pub fn set_backup(&mut self, backup: Option<BackupPolicy>) {
+        self.backup = backup;
+    }
+
+    // true if the file starts like one written by save_bytes
+    pub fn is_encrypted<P: AsRef<Path>>(path: P) -> Result<bool, FileError> {
+        let mut magic = [0u8; MAGIC.len()];
+        let read = File::open(path)?.read(&mut magic)?;
+        Ok(read == MAGIC.len() && magic == MAGIC)
+    }
+
+    // re-derive the key with a fresh salt and the default costs
+    pub fn rekey(&mut self, passphrase: &str) -> Result<(), FileError> {
+        let backup = self.backup.take();
+        *self = WalletFile::new(passphrase)?;
+        self.backup = backup;
+        Ok(())
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<(Self, Wallet), FileError> {
+        let (file, plaintext) = WalletFile::open_bytes(path, passphrase)?;
+        // decrypted fine, so a failure here is the format, not tampering
+        let wallet = Wallet::load(&plaintext[..]).map_err(FileError::Contents)?;
+        Ok((file, wallet))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, wallet: &Wallet) -> Result<(), FileError> {
+        let mut plaintext = vec![];
+        wallet.save(&mut plaintext)?;
+        self.save_bytes(path, &plaintext)
+    }
+
+    // decrypt any file sealed by save_bytes, e.g. a backup
+    pub fn open_bytes<P: AsRef<Path>>(
+        path: P,
+        passphrase: &str,
+    ) -> Result<(Self, Vec<u8>), FileError> {
+        let data = fs::read(path)?;
+        if !data.starts_with(&MAGIC) {
+            return Err(FileError::NotAWallet);
+        }
+        match data.get(4) {
+            Some(&FORMAT_VERSION) => {}
+            Some(&version) => return Err(FileError::UnsupportedVersion(version)),
+            None => return Err(FileError::Truncated),
+        }
+        if data.len() < HEADER_SIZE {
+            return Err(FileError::Truncated);
+        }
+        let (header, ciphertext) = data.split_at(HEADER_SIZE);
+        let u32_at =
+            |offset: usize| u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+        let kdf = KdfParams {
+            memory_cost: u32_at(5),
+            time_cost: u32_at(9),
+            parallelism: u32_at(13),
+            salt: header[17..17 + SALT_SIZE].try_into().unwrap(),
+        };
+        let key_check = &header[33..37];
+        let nonce = Nonce::from(<[u8; NONCE_SIZE]>::try_from(&header[37..]).unwrap());
+        let key = kdf.derive_key(passphrase)?;
+        if checksum(&key) != key_check {
+            return Err(FileError::WrongPassphrase);
+        }
+        let plaintext = ChaCha20Poly1305::new(&key)
+            .decrypt(
+                &nonce,
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| FileError::Tampered)?;
+        Ok((
+            WalletFile {
+                kdf,
+                key,
+                backup: None,
+            },
+            plaintext,
+        ))
+    }
+
+    // encrypt with a fresh nonce and write atomically
+    pub fn save_bytes<P: AsRef<Path>>(&self, path: P, plaintext: &[u8]) -> Result<(), FileError> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut data = Vec::with_capacity(HEADER_SIZE + plaintext.len() + 16);
+        data.extend_from_slice(&MAGIC);
+        data.push(FORMAT_VERSION);
+        data.extend_from_slice(&self.kdf.memory_cost.to_be_bytes());
+        data.extend_from_slice(&self.kdf.time_cost.to_be_bytes());
+        data.extend_from_slice(&self.kdf.parallelism.to_be_bytes());
+        data.extend_from_slice(&self.kdf.salt);
+        data.extend_from_slice(&checksum(&self.key));
+        data.extend_from_slice(&nonce);
+        let ciphertext = ChaCha20Poly1305::new(&self.key)
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: &data,
+                },
+            )
+            .expect("BUG: wallet encryption failed");
+        data.extend_from_slice(&ciphertext);
+        let write = |path: &Path| write_atomically(path, |writer| writer.write_all(&data));
+        match &self.backup {
+            Some(policy) => policy.save(path, write)?,
+            None => write(path.as_ref())?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib::crypto::PrivateKey;
+    use tempfile::TempDir;
+
+    const PASSPHRASE: &str = "correct horse";
+    const LABEL: &str = "savings, not to be read off the disk";
+
+    // the lowest costs argon2 takes, the default ones are slow
+    fn cheap(passphrase: &str) -> WalletFile {
+        let kdf = KdfParams {
+            memory_cost: 8,
+            time_cost: 1,
+            parallelism: 1,
+            salt: [7; SALT_SIZE],
+        };
+        let key = kdf.derive_key(passphrase).unwrap();
+        WalletFile {
+            kdf,
+            key,
+            backup: None,
+        }
+    }
+
+    // a saved wallet of one labelled key, and the file's bytes
+    fn saved(dir: &TempDir) -> (Wallet, Vec<u8>) {
+        let mut wallet = Wallet::new(vec![PrivateKey::new_key()]);
+        let address = wallet.keys[0].public_key().to_address();
+        wallet.labels.insert(address, LABEL.to_string());
+        let path = dir.path().join("wallet.cbor");
+        cheap(PASSPHRASE).save(&path, &wallet).unwrap();
+        let data = fs::read(&path).unwrap();
+        (wallet, data)
+    }
+
+    // open the bytes as a wallet file
+    fn open(dir: &TempDir, data: &[u8], passphrase: &str) -> Result<Wallet, FileError> {
+        let path = dir.path().join("opened.cbor");
+        fs::write(&path, data).unwrap();
+        WalletFile::open(&path, passphrase).map(|(_, wallet)| wallet)
+    }
+
+    #[test]
+    fn saved_wallet_opens_as_it_was() {
+        let dir = TempDir::new().unwrap();
+        let (wallet, data) = saved(&dir);
+        assert!(data.starts_with(&MAGIC));
+        assert_eq!(data[MAGIC.len()], FORMAT_VERSION);
+        // encrypted, so the label isn't in there
+        assert!(
+            !data
+                .windows(LABEL.len())
+                .any(|window| window == LABEL.as_bytes())
+        );
+        let opened = open(&dir, &data, PASSPHRASE).unwrap();
+        assert_eq!(opened.public_keys(), wallet.public_keys());
+        assert_eq!(opened.labels, wallet.labels);
+        assert!(WalletFile::is_encrypted(dir.path().join("opened.cbor")).unwrap());
+    }
+
+    #[test]
+    fn wrong_passphrase_is_told_apart() {
+        let dir = TempDir::new().unwrap();
+        let (_, data) = saved(&dir);
+        assert!(matches!(
+            open(&dir, &data, "battery staple"),
+            Err(FileError::WrongPassphrase)
+        ));
+    }
+
+    #[test]
+    fn changed_ciphertext_or_nonce_is_tampering() {
+        let dir = TempDir::new().unwrap();
+        let (_, data) = saved(&dir);
+        for index in [data.len() - 1, HEADER_SIZE, HEADER_SIZE - 1] {
+            let mut tampered = data.clone();
+            tampered[index] ^= 1;
+            assert!(
+                matches!(open(&dir, &tampered, PASSPHRASE), Err(FileError::Tampered)),
+                "byte {index}"
+            );
+        }
+    }
+
+    #[test]
+    fn other_versions_and_short_files_are_refused() {
+        let dir = TempDir::new().unwrap();
+        let (_, data) = saved(&dir);
+        let mut future = data.clone();
+        future[MAGIC.len()] = FORMAT_VERSION + 1;
+        assert!(matches!(
+            open(&dir, &future, PASSPHRASE),
+            Err(FileError::UnsupportedVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+        assert!(matches!(
+            open(&dir, &data[..HEADER_SIZE - 1], PASSPHRASE),
+            Err(FileError::Truncated)
+        ));
+        assert!(matches!(
+            open(&dir, &data[..MAGIC.len()], PASSPHRASE),
+            Err(FileError::Truncated)
+        ));
+        assert!(matches!(
+            open(&dir, b"not a wallet", PASSPHRASE),
+            Err(FileError::NotAWallet)
+        ));
+    }
+}
@@ -0,0 +1,1287 @@
+use backup::Backup;
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use file::WalletFile;
+use lib::cli::{JsonArgs, NetworkArgs, Verbosity};
+use lib::crypto::{PublicKey, Signature};
+use lib::params::Network;
+use lib::sha256::Hash;
+use lib::types::{Blockchain, MempoolSnapshot, PartiallySignedTransaction, Transaction};
+use lib::utils::{BackupPolicy, Saveable, format_value, is_compressed, write_atomically};
+use rpc::NodeClient;
+use seed::Seed;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::thread;
+use std::time::Duration;
+use wallet::{Payment, Wallet, WalletError};
+use watch::WalletWatcher;
+
+mod backup;
+mod file;
+mod rpc;
+mod seed;
+mod wallet;
+mod watch;
+
+/// Keep keys, follow a chain file and pay from it
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    #[command(flatten)]
+    unlock: Unlock,
+    // show what the chain checks do, each -v one more;
+    // RUST_LOG picks the events instead if set
+    #[command(flatten)]
+    verbosity: Verbosity,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Make a new wallet with a new seed phrase
+    Create { wallet_file: String },
+    /// Make a wallet from its seed phrase
+    Restore {
+        wallet_file: String,
+        /// The 24 words, quoted as one argument
+        phrase: String,
+        /// Chain file to find the keys in use in
+        #[arg(long, value_name = "BLOCKCHAIN_FILE")]
+        blockchain: Option<String>,
+    },
+    /// Hand out a new receive address
+    NewAddress { wallet_file: String },
+    /// List the addresses handed out or paid
+    Address { wallet_file: String },
+    /// Follow an address without its key
+    ImportWatch {
+        wallet_file: String,
+        address: PublicKey,
+    },
+    /// Sign a message with the key of an address
+    SignMessage {
+        wallet_file: String,
+        address: String,
+        message: String,
+    },
+    /// Check a message signature, no wallet needed
+    VerifyMessage {
+        address: PublicKey,
+        message: String,
+        signature: String,
+    },
+    /// Keep outputs from being spent, as of the last scan
+    Lock {
+        wallet_file: String,
+        #[arg(value_name = "OUTPUT_HASH_OR_TXID")]
+        target: Hash,
+    },
+    /// Let locked outputs be spent again
+    Unlock {
+        wallet_file: String,
+        #[arg(value_name = "OUTPUT_HASH_OR_TXID")]
+        target: Hash,
+    },
+    /// List the locked outputs
+    ListLocked { wallet_file: String },
+    /// Label an address of the wallet
+    Label {
+        wallet_file: String,
+        address: String,
+        label: String,
+    },
+    /// Write the keys, watch-only addresses and labels as json
+    Export {
+        wallet_file: String,
+        #[arg(long, value_name = "BACKUP_FILE")]
+        out: String,
+        /// Encrypt the backup with a passphrase of its own
+        #[arg(long)]
+        encrypt: bool,
+    },
+    /// Merge a backup into the wallet, creating it if needed
+    Import {
+        wallet_file: String,
+        backup_file: String,
+    },
+    /// Put the newest backup copy back in place of the wallet file
+    RestoreBackup { wallet_file: String },
+    /// Confirmed, pending, immature and timelocked balance
+    Balance {
+        wallet_file: String,
+        blockchain_file: String,
+        /// One line per address
+        #[arg(long)]
+        by_address: bool,
+        #[command(flatten)]
+        json: JsonArgs,
+    },
+    /// The outputs of the wallet
+    Utxos {
+        wallet_file: String,
+        blockchain_file: String,
+        /// Only the outputs of this address
+        #[arg(long)]
+        address: Option<PublicKey>,
+        /// Largest or oldest first
+        #[arg(long, value_enum, default_value_t = UtxoSort::Value)]
+        sort: UtxoSort,
+        #[command(flatten)]
+        json: JsonArgs,
+    },
+    /// The transactions paying or spending the wallet
+    History {
+        wallet_file: String,
+        blockchain_file: String,
+    },
+    /// Pay an address
+    ///
+    /// the transaction goes into the chain file's mempool, unless it's
+    /// saved with --out or --unsigned or submitted to a --node instead
+    Send {
+        wallet_file: String,
+        blockchain_file: String,
+        #[command(flatten)]
+        options: Box<SendOptions>,
+    },
+    /// Sign a transaction made with send --unsigned
+    Sign {
+        unsigned_tx_file: String,
+        wallet_file: String,
+        #[arg(long, value_name = "TX_FILE")]
+        out: String,
+    },
+    /// Submit a transaction to a node
+    #[command(group(ArgGroup::new("transaction").required(true).args(["tx_file", "hex"])))]
+    #[command(mut_arg("node", |node| node.required(true)))]
+    Broadcast {
+        tx_file: Option<String>,
+        /// The transaction in hex instead of a file
+        #[arg(long, value_name = "RAW_TX")]
+        hex: Option<String>,
+        #[command(flatten)]
+        broadcast: BroadcastOptions,
+        #[command(flatten)]
+        network: NetworkArgs,
+    },
+    /// Merge small outputs into a fresh key, while fees are low
+    Consolidate {
+        wallet_file: String,
+        blockchain_file: String,
+        /// Highest fee rate to consolidate at
+        #[arg(long, default_value_t = DEFAULT_FEE_RATE)]
+        max_fee_rate: u64,
+        /// Merge outputs worth less than this
+        #[arg(long, default_value_t = DEFAULT_CONSOLIDATE_BELOW)]
+        below: u64,
+        /// Merge locked outputs too
+        #[arg(long)]
+        allow_locked: bool,
+    },
+    /// Follow the chain file, printing the wallet's transactions as json lines
+    Watch {
+        wallet_file: String,
+        blockchain_file: String,
+        /// Confirmations after which a transaction is no longer followed
+        #[arg(long, default_value_t = 6)]
+        depth: u64,
+        /// Seconds between reads of the chain file
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+        /// Command to run with each event
+        #[arg(long)]
+        exec: Option<String>,
+    },
+    /// Pay everything the wallet has to an address
+    Sweep {
+        wallet_file: String,
+        blockchain_file: String,
+        /// Address to pay
+        #[arg(long, value_name = "ADDRESS")]
+        to: PublicKey,
+        /// Base units per byte, what the mempool pays if not given
+        #[arg(long)]
+        fee_rate: Option<u64>,
+        /// Spend locked outputs too
+        #[arg(long)]
+        allow_locked: bool,
+    },
+}
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("destination").args(["out", "unsigned", "node"])))]
+struct SendOptions {
+    /// Address to pay
+    #[arg(long, value_name = "ADDRESS")]
+    to: PublicKey,
+    /// Value to pay, in base units
+    #[arg(long)]
+    amount: u64,
+    /// Base units per byte, what the mempool pays if not given
+    #[arg(long)]
+    fee_rate: Option<u64>,
+    /// Pay the fee out of the amount
+    #[arg(long)]
+    subtract_fee_from_amount: bool,
+    /// Address for the change, a fresh key of the wallet if not given
+    #[arg(long, value_name = "ADDRESS")]
+    change_address: Option<PublicKey>,
+    /// Spend locked outputs too
+    #[arg(long)]
+    allow_locked: bool,
+    /// Blocks from the next one that may include it, after which it lapses
+    #[arg(long, value_name = "BLOCKS", value_parser = clap::value_parser!(u64).range(1..))]
+    expires_in: Option<u64>,
+    /// Save the signed transaction here
+    #[arg(long, value_name = "TX_FILE")]
+    out: Option<String>,
+    /// Save it unsigned here, for a wallet with the keys to sign
+    #[arg(long, value_name = "UNSIGNED_TX_FILE")]
+    unsigned: Option<String>,
+    #[command(flatten)]
+    broadcast: BroadcastOptions,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum UtxoSort {
+    Value,
+    Confirmations,
+}
+
+// copies of the wallet file kept by default
+const DEFAULT_KEEP_BACKUPS: usize = 5;
+
+// how to unlock and back up the wallet file,
+// given anywhere on the command line
+#[derive(Args, Debug)]
+struct Unlock {
+    /// Read the passphrase from a file instead of prompting
+    #[arg(long, global = true, value_name = "FILE")]
+    passphrase_file: Option<String>,
+    /// Re-encrypt the wallet with a fresh salt and default costs
+    #[arg(long, global = true)]
+    rekey: bool,
+    /// Where the wallet is copied before each save, next to it by default
+    #[arg(long, global = true, value_name = "DIR")]
+    backup_dir: Option<String>,
+    /// Copies to keep, 0 for none
+    #[arg(long, global = true, value_name = "N", default_value_t = DEFAULT_KEEP_BACKUPS)]
+    keep_backups: usize,
+}
+
+impl Unlock {
+    fn passphrase(&self, prompt: &str) -> String {
+        let passphrase = match &self.passphrase_file {
+            Some(path) => fs::read_to_string(path)
+                .map(|passphrase| passphrase.trim_end_matches(['\r', '\n']).to_string()),
+            None => rpassword::prompt_password(prompt),
+        };
+        passphrase.unwrap_or_else(|e| {
+            eprintln!("Failed to read passphrase: {e}");
+            exit(1);
+        })
+    }
+
+    // prompts twice for a new passphrase
+    fn new_passphrase(&self) -> String {
+        let passphrase = self.passphrase("New passphrase: ");
+        if self.passphrase_file.is_none() && self.passphrase("Repeat passphrase: ") != passphrase {
+            eprintln!("Passphrases do not match");
+            exit(1);
+        }
+        passphrase
+    }
+
+    fn backup_dir(&self, wallet_path: &str) -> PathBuf {
+        match &self.backup_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => Path::new(wallet_path).with_file_name("backups"),
+        }
+    }
+
+    fn backup_policy(&self, wallet_path: &str) -> Option<BackupPolicy> {
+        (self.keep_backups > 0)
+            .then(|| BackupPolicy::new(self.backup_dir(wallet_path), self.keep_backups))
+    }
+
+    fn open(&self, wallet_path: &str) -> (WalletFile, Wallet) {
+        let passphrase = self.passphrase("Passphrase: ");
+        let (mut file, wallet) = WalletFile::open(wallet_path, &passphrase).unwrap_or_else(|e| {
+            eprintln!("{wallet_path}: {e}");
+            exit(1);
+        });
+        file.set_backup(self.backup_policy(wallet_path));
+        if self.rekey {
+            file.rekey(&passphrase).unwrap_or_else(|e| {
+                eprintln!("{e}");
+                exit(1);
+            });
+            save(wallet_path, &file, &wallet);
+        }
+        (file, wallet)
+    }
+}
+
+fn save(wallet_path: &str, file: &WalletFile, wallet: &Wallet) {
+    if let Err(e) = file.save(wallet_path, wallet) {
+        eprintln!("{wallet_path}: {e}");
+        exit(1);
+    }
+}
+
+// base units per byte of the signed transaction
+const DEFAULT_FEE_RATE: u64 = 1;
+// outputs worth less than this are merged by consolidate
+const DEFAULT_CONSOLIDATE_BELOW: u64 = 10_000;
+
+fn main() {
+    let Cli {
+        command,
+        unlock,
+        verbosity,
+    } = Cli::parse();
+    lib::logging::init(verbosity.verbose);
+    match command {
+        Command::Create {
+            wallet_file: wallet_path,
+        } => {
+            if Path::new(&wallet_path).exists() {
+                eprintln!("{wallet_path} already exists");
+                exit(1);
+            }
+            let mut wallet = Wallet::from_seed(Seed::generate());
+            let file = WalletFile::new(&unlock.new_passphrase()).unwrap_or_else(|e| {
+                eprintln!("{e}");
+                exit(1);
+            });
+            let address = wallet.new_address().to_address();
+            save(&wallet_path, &file, &wallet);
+            println!("created wallet {wallet_path}");
+            println!("receive address: {address}");
+            println!("write down the seed phrase, it restores the wallet:");
+            println!(
+                "{}",
+                wallet.seed_phrase().expect("BUG: wallet without seed")
+            );
+        }
+        Command::Restore {
+            wallet_file,
+            phrase,
+            blockchain,
+        } => restore(&unlock, &wallet_file, &phrase, blockchain.as_deref()),
+        Command::NewAddress {
+            wallet_file: wallet_path,
+        } => {
+            let (file, mut wallet) = unlock.open(&wallet_path);
+            let address = wallet.new_address().to_address();
+            save(&wallet_path, &file, &wallet);
+            println!("{address}");
+        }
+        Command::Address {
+            wallet_file: wallet_path,
+        } => {
+            let (_, wallet) = unlock.open(&wallet_path);
+            let label = |address: &String| {
+                wallet
+                    .labels
+                    .get(address)
+                    .map(|label| format!(" {label}"))
+                    .unwrap_or_default()
+            };
+            // pool keys stay hidden until handed out or paid
+            for key in &wallet.keys {
+                let address = key.public_key().to_address();
+                if !wallet.is_used(&address) {
+                    continue;
+                }
+                let change = if wallet.change.contains(&address) {
+                    " (change)"
+                } else {
+                    ""
+                };
+                println!("{address}{}{change}", label(&address));
+            }
+            for pubkey in &wallet.watch_only {
+                let address = pubkey.to_address();
+                println!("{address}{} (watch-only)", label(&address));
+            }
+        }
+        Command::Label {
+            wallet_file: wallet_path,
+            address,
+            label,
+        } => {
+            let (file, mut wallet) = unlock.open(&wallet_path);
+            if !wallet
+                .public_keys()
+                .iter()
+                .any(|pubkey| pubkey.to_address() == address)
+            {
+                eprintln!("{address} is not in the wallet");
+                exit(1);
+            }
+            wallet.labels.insert(address, label);
+            save(&wallet_path, &file, &wallet);
+        }
+        Command::Export {
+            wallet_file,
+            out,
+            encrypt,
+        } => export(&unlock, &wallet_file, &out, encrypt),
+        Command::Import {
+            wallet_file,
+            backup_file,
+        } => import(&unlock, &wallet_file, &backup_file),
+        Command::RestoreBackup { wallet_file } => restore_backup(&unlock, &wallet_file),
+        Command::Lock {
+            wallet_file,
+            target,
+        } => lock(&unlock, &wallet_file, &target, true),
+        Command::Unlock {
+            wallet_file,
+            target,
+        } => lock(&unlock, &wallet_file, &target, false),
+        Command::ListLocked {
+            wallet_file: wallet_path,
+        } => {
+            let (_, wallet) = unlock.open(&wallet_path);
+            let mut locked = wallet.locked_outputs();
+            locked.sort_by_key(|(_, owned)| std::cmp::Reverse(owned.output.value));
+            for (hash, owned) in locked {
+                println!(
+                    "{hash}  {}  {}  {}",
+                    owned
+                        .txid
+                        .map_or("unknown".to_string(), |txid| txid.to_string()),
+                    owned.output.value,
+                    owned.output.pubkey.to_address()
+                );
+            }
+        }
+        Command::SignMessage {
+            wallet_file: wallet_path,
+            address,
+            message,
+        } => {
+            let (_, wallet) = unlock.open(&wallet_path);
+            match wallet.sign_message(&address, &message) {
+                Ok(signature) => println!("{}", signature.to_hex()),
+                Err(e) => {
+                    eprintln!("{e}");
+                    exit(1);
+                }
+            }
+        }
+        // needs no wallet, anyone can check a signature
+        Command::VerifyMessage {
+            address: pubkey,
+            message,
+            signature,
+        } => {
+            let Some(signature) = Signature::from_hex(&signature) else {
+                eprintln!("Invalid signature, expected 64 hex encoded bytes");
+                exit(1);
+            };
+            if !signature.verify_message(message.as_bytes(), &pubkey) {
+                eprintln!(
+                    "Signature is not valid for {} and this message",
+                    pubkey.to_address()
+                );
+                exit(1);
+            }
+            println!("signature is valid");
+        }
+        Command::ImportWatch {
+            wallet_file: wallet_path,
+            address: pubkey,
+        } => {
+            let address = pubkey.to_address();
+            let (file, mut wallet) = unlock.open(&wallet_path);
+            if !wallet.add_watch_only(pubkey) {
+                println!("{address} is already in the wallet");
+                return;
+            }
+            save(&wallet_path, &file, &wallet);
+            println!("watching {address}, the chain will be rescanned");
+        }
+        Command::Balance {
+            wallet_file,
+            blockchain_file,
+            by_address,
+            json,
+        } => balance(
+            &unlock,
+            &wallet_file,
+            &blockchain_file,
+            by_address,
+            json.json,
+        ),
+        Command::Utxos {
+            wallet_file,
+            blockchain_file,
+            address,
+            sort,
+            json,
+        } => utxos(
+            &unlock,
+            &wallet_file,
+            &blockchain_file,
+            address,
+            sort,
+            json.json,
+        ),
+        Command::History {
+            wallet_file,
+            blockchain_file,
+        } => {
+            let blockchain = load_chain(&blockchain_file);
+            let (_, wallet) = scan(&unlock, &wallet_file, &blockchain);
+            println!(
+                "{:<64}  {:>8}  {:>13}  {:<13}  {:>20}  {:>12}",
+                "txid", "height", "confirmations", "direction", "net", "fee"
+            );
+            for record in wallet.history(&blockchain) {
+                let (height, confirmations) = match record.height {
+                    Some(height) => (height.to_string(), wallet.confirmations(height)),
+                    None => ("pending".to_string(), 0),
+                };
+                let fee = record
+                    .fee
+                    .map(format_value)
+                    .unwrap_or_else(|| "-".to_string());
+                println!(
+                    "{:<64}  {:>8}  {:>13}  {:<13}  {:>20}  {:>12}",
+                    record.txid.to_string(),
+                    height,
+                    confirmations,
+                    format!("{:?}", record.direction),
+                    format_net(record.net()),
+                    fee
+                );
+            }
+        }
+        Command::Send {
+            wallet_file,
+            blockchain_file,
+            options,
+        } => send(&unlock, &wallet_file, &blockchain_file, *options),
+        Command::Sign {
+            unsigned_tx_file,
+            wallet_file,
+            out,
+        } => sign(&unlock, &unsigned_tx_file, &wallet_file, &out),
+        Command::Broadcast {
+            tx_file,
+            hex,
+            broadcast,
+            network,
+        } => {
+            let transaction = match (&tx_file, hex) {
+                (_, Some(hex)) => Transaction::from_hex(&hex).unwrap_or_else(|e| {
+                    eprintln!("{e}");
+                    exit(1);
+                }),
+                (Some(path), None) => Transaction::load_from_file(path).unwrap_or_else(|e| {
+                    eprintln!("{path}: {e}");
+                    exit(1);
+                }),
+                (None, None) => unreachable!("clap requires a tx_file or --hex"),
+            };
+            let network = network.network.unwrap_or_default();
+            let mut client = broadcast.submit(network, &transaction, tx_file.is_none());
+            broadcast.wait(&mut client, transaction.hash());
+        }
+        Command::Consolidate {
+            wallet_file,
+            blockchain_file,
+            max_fee_rate,
+            below,
+            allow_locked,
+        } => consolidate(
+            &unlock,
+            &wallet_file,
+            &blockchain_file,
+            max_fee_rate,
+            below,
+            allow_locked,
+        ),
+        Command::Watch {
+            wallet_file,
+            blockchain_file,
+            depth,
+            interval,
+            exec,
+        } => watch(
+            &unlock,
+            &wallet_file,
+            &blockchain_file,
+            depth,
+            interval,
+            exec.as_deref(),
+        ),
+        Command::Sweep {
+            wallet_file,
+            blockchain_file,
+            to,
+            fee_rate,
+            allow_locked,
+        } => sweep(
+            &unlock,
+            &wallet_file,
+            &blockchain_file,
+            &to,
+            fee_rate,
+            allow_locked,
+        ),
+    }
+}
+
+// derive the wallet from its phrase, finding the keys the chain
+// shows in use; labels and unused issued addresses are not kept
+fn restore(unlock: &Unlock, wallet_path: &str, phrase: &str, blockchain_path: Option<&str>) {
+    if Path::new(wallet_path).exists() {
+        eprintln!("{wallet_path} already exists");
+        exit(1);
+    }
+    let seed = Seed::from_phrase(phrase).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        exit(1);
+    });
+    let blockchain = blockchain_path.map(load_chain);
+    let wallet = match &blockchain {
+        Some(blockchain) => Wallet::restore(seed, blockchain),
+        None => Wallet::from_seed(seed),
+    };
+    let file = WalletFile::new(&unlock.new_passphrase()).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        exit(1);
+    });
+    save(wallet_path, &file, &wallet);
+    println!("restored wallet {wallet_path}");
+    if let Some(blockchain) = &blockchain {
+        let balance = wallet.balance(blockchain);
+        println!(
+            "scanned {} blocks, confirmed balance {}",
+            wallet.scanned_height(),
+            balance.confirmed
+        );
+    }
+}
+
+// lock or unlock outputs as of the last scan
+fn lock(unlock: &Unlock, wallet_path: &str, target: &Hash, lock: bool) {
+    let (file, mut wallet) = unlock.open(wallet_path);
+    let result = if lock {
+        wallet.lock(target)
+    } else {
+        wallet.unlock(target)
+    };
+    let outputs = result.unwrap_or_else(|e| {
+        eprintln!("{e}");
+        exit(1);
+    });
+    save(wallet_path, &file, &wallet);
+    for output in outputs {
+        println!("{} {output}", if lock { "locked" } else { "unlocked" });
+    }
+}
+
+fn export(unlock: &Unlock, wallet_path: &str, backup_path: &str, encrypt: bool) {
+    let (_, wallet) = unlock.open(wallet_path);
+    let json = Backup::from_wallet(&wallet).to_json();
+    if encrypt {
+        let file = WalletFile::new(&unlock.new_passphrase()).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1);
+        });
+        if let Err(e) = file.save_bytes(backup_path, &json) {
+            eprintln!("{backup_path}: {e}");
+            exit(1);
+        }
+    } else if let Err(e) = write_atomically(backup_path, |writer| writer.write_all(&json)) {
+        eprintln!("{backup_path}: {e}");
+        exit(1);
+    }
+    println!("exported {wallet_path} to {backup_path}");
+}
+
+// merge a backup into the wallet, creating it if needed
+fn import(unlock: &Unlock, wallet_path: &str, backup_path: &str) {
+    let encrypted = WalletFile::is_encrypted(backup_path).unwrap_or_else(|e| {
+        eprintln!("{backup_path}: {e}");
+        exit(1);
+    });
+    let json = if encrypted {
+        let passphrase = unlock.passphrase("Backup passphrase: ");
+        WalletFile::open_bytes(backup_path, &passphrase).map(|(_, json)| json)
+    } else {
+        fs::read(backup_path).map_err(Into::into)
+    };
+    let backup = json
+        .map_err(|e| e.to_string())
+        .and_then(|json| Backup::from_json(&json).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("{backup_path}: {e}");
+            exit(1);
+        });
+    let (file, mut wallet) = if Path::new(wallet_path).exists() {
+        unlock.open(wallet_path)
+    } else {
+        let file = WalletFile::new(&unlock.new_passphrase()).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1);
+        });
+        (file, Wallet::new(vec![]))
+    };
+    let imported = backup.merge_into(&mut wallet).unwrap_or_else(|e| {
+        eprintln!("{backup_path}: {e}");
+        exit(1);
+    });
+    wallet.top_up_keypool();
+    save(wallet_path, &file, &wallet);
+    println!(
+        "imported {} keys, {} watch-only addresses and {} labels",
+        imported.keys, imported.watch_only, imported.labels
+    );
+}
+
+// put the newest backup copy back in place of the wallet file
+fn restore_backup(unlock: &Unlock, wallet_path: &str) {
+    let policy = BackupPolicy::new(unlock.backup_dir(wallet_path), 0);
+    match policy.restore_latest_backup(wallet_path) {
+        Ok(backup) => println!("restored {wallet_path} from {}", backup.display()),
+        Err(e) => {
+            eprintln!("{wallet_path}: {e}");
+            exit(1);
+        }
+    }
+}
+
+fn balance(
+    unlock: &Unlock,
+    wallet_path: &str,
+    blockchain_path: &str,
+    by_address: bool,
+    json: bool,
+) {
+    let blockchain = load_chain(blockchain_path);
+    let (_, wallet) = scan(unlock, wallet_path, &blockchain);
+    if by_address {
+        let balances = wallet.balances_by_address(&blockchain);
+        if json {
+            println!("{}", serde_json::to_string_pretty(&balances).unwrap());
+            return;
+        }
+        println!(
+            "{:<66}  {:>20}  {:>20}  {:>20}  {:>20}  {:>5}  label",
+            "address", "confirmed", "pending", "immature", "timelocked", "utxos"
+        );
+        for balance in balances {
+            let watch_only = if balance.watch_only {
+                " (watch-only)"
+            } else {
+                ""
+            };
+            println!(
+                "{:<66}  {:>20}  {:>20}  {:>20}  {:>20}  {:>5}  {}{watch_only}",
+                balance.address,
+                format_value(balance.confirmed),
+                format_net(balance.pending),
+                format_value(balance.immature),
+                format_value(balance.timelocked),
+                balance.utxos,
+                balance.label.unwrap_or_default()
+            );
+        }
+        return;
+    }
+    let balance = wallet.balance(&blockchain);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&balance).unwrap());
+        return;
+    }
+    println!("scanned {} blocks", wallet.scanned_height());
+    println!("confirmed: {}", format_value(balance.confirmed));
+    println!("pending: {}", format_value(balance.pending));
+    println!("immature: {}", format_value(balance.immature));
+    println!("timelocked: {}", format_value(balance.timelocked));
+}
+
+fn utxos(
+    unlock: &Unlock,
+    wallet_path: &str,
+    blockchain_path: &str,
+    address: Option<PublicKey>,
+    sort: UtxoSort,
+    json: bool,
+) {
+    let blockchain = load_chain(blockchain_path);
+    let (_, wallet) = scan(unlock, wallet_path, &blockchain);
+    let mut utxos = wallet.utxos(&blockchain);
+    if let Some(address) = address.map(|pubkey| pubkey.to_address()) {
+        utxos.retain(|utxo| utxo.address == address);
+    }
+    // largest or oldest first
+    match sort {
+        UtxoSort::Value => {
+            utxos.sort_by_key(|utxo| std::cmp::Reverse((utxo.value, utxo.confirmations)))
+        }
+        UtxoSort::Confirmations => {
+            utxos.sort_by_key(|utxo| std::cmp::Reverse((utxo.confirmations, utxo.value)))
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&utxos).unwrap());
+        return;
+    }
+    println!(
+        "{:<64}  {:<64}  {:>20}  {:>13}  {:<16}  address",
+        "output", "txid", "value", "confirmations", "status"
+    );
+    for utxo in utxos {
+        let status = if utxo.spending {
+            "spending".to_string()
+        } else if let Some(height) = utxo.unlock_height.filter(|_| utxo.timelocked) {
+            format!("until height {height}")
+        } else if utxo.locked {
+            "locked".to_string()
+        } else if !utxo.mature {
+            "immature".to_string()
+        } else {
+            "spendable".to_string()
+        };
+        let watch_only = if utxo.watch_only { " (watch-only)" } else { "" };
+        println!(
+            "{:<64}  {:<64}  {:>20}  {:>13}  {:<16}  {}{watch_only}",
+            utxo.output.to_string(),
+            utxo.txid
+                .map_or("unknown".to_string(), |txid| txid.to_string()),
+            format_value(utxo.value),
+            utxo.confirmations,
+            status,
+            utxo.address
+        );
+    }
+}
+
+fn send(unlock: &Unlock, wallet_path: &str, blockchain_path: &str, options: SendOptions) {
+    let SendOptions {
+        to,
+        amount,
+        fee_rate,
+        subtract_fee_from_amount: subtract_fee,
+        change_address,
+        allow_locked,
+        expires_in,
+        out,
+        unsigned: unsigned_out,
+        broadcast,
+    } = options;
+    let mut blockchain = load_chain(blockchain_path);
+    let (file, mut wallet) = scan(unlock, wallet_path, &blockchain);
+    let fee_rate = fee_rate.unwrap_or_else(|| default_fee_rate(&blockchain));
+    let payment = Payment {
+        to,
+        amount,
+        change_to: change_address.unwrap_or_else(|| wallet.change_key()),
+        fee_rate,
+        subtract_fee,
+        allow_locked,
+        // the next block is at the chain's height
+        expires_at_height: expires_in
+            .map(|blocks| blockchain.block_height().saturating_add(blocks)),
+    };
+    // leave the signing to a machine with the keys
+    if let Some(unsigned_out) = unsigned_out {
+        let unsigned = wallet
+            .create_unsigned(&blockchain, &payment)
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                exit(1);
+            });
+        unsigned.save_to_file(&unsigned_out).unwrap_or_else(|e| {
+            eprintln!("Failed to save unsigned transaction: {e}");
+            exit(1);
+        });
+        save(wallet_path, &file, &wallet);
+        println!(
+            "saved unsigned transaction with {} inputs to {unsigned_out}",
+            unsigned.inputs.len()
+        );
+        let fee = unsigned.fee().unwrap_or(0);
+        println!(
+            "fee: {} ({:.2} units per byte)",
+            format_value(fee),
+            fee as f64 / unsigned.size() as f64
+        );
+        return;
+    }
+    let transaction = match wallet.create_transaction(&blockchain, &payment) {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+    };
+    let fee = blockchain.transaction_fee(&transaction).unwrap_or(0);
+    let transaction_size = transaction.size();
+    let transaction_hash = transaction.hash();
+    // check it against the chain, but leave the chain as is
+    if (out.is_some() || broadcast.node.is_some())
+        && let Err(e) = blockchain.clone().add_to_mempool(transaction.clone())
+    {
+        eprintln!("Transaction rejected by the chain: {e}");
+        exit(1);
+    }
+    let mut client = None;
+    match out {
+        Some(out) => {
+            transaction.save_to_file(&out).unwrap_or_else(|e| {
+                eprintln!("Failed to save transaction: {e}");
+                exit(1);
+            });
+            save(wallet_path, &file, &wallet);
+            println!("saved transaction {} to {out}", transaction.hash());
+        }
+        None => {
+            let hash = transaction.hash();
+            // the node goes first, the chain file follows it
+            if broadcast.node.is_some() {
+                // the change key is taken even if the node refuses
+                save(wallet_path, &file, &wallet);
+                client = Some(broadcast.submit(blockchain.network(), &transaction, false));
+            }
+            if let Err(e) = blockchain.add_to_mempool(transaction) {
+                eprintln!("Transaction rejected by the chain: {e}");
+                exit(1);
+            }
+            save_chain(blockchain_path, &blockchain);
+            save_mempool(blockchain_path, &blockchain);
+            save(wallet_path, &file, &wallet);
+            println!("added transaction {hash} to the mempool");
+        }
+    }
+    println!(
+        "fee: {} ({:.2} units per byte)",
+        format_value(fee),
+        fee as f64 / transaction_size as f64
+    );
+    if let Some(mut client) = client {
+        broadcast.wait(&mut client, transaction_hash);
+    }
+}
+
+// sign a transaction built with send --unsigned, saving it final
+// once every input is signed, or still partial for another wallet
+fn sign(unlock: &Unlock, unsigned_path: &str, wallet_path: &str, out: &str) {
+    let mut transaction =
+        PartiallySignedTransaction::load_from_file(unsigned_path).unwrap_or_else(|e| {
+            eprintln!("{unsigned_path}: {e}");
+            exit(1);
+        });
+    let (_, wallet) = unlock.open(wallet_path);
+    // show what is being signed, the signer can't ask the chain
+    let ours = wallet.public_keys();
+    for output in &transaction.outputs {
+        let owner = if ours.contains(&output.pubkey) {
+            " (ours)"
+        } else {
+            ""
+        };
+        println!(
+            "pays {} to {}{owner}",
+            format_value(output.value),
+            output.pubkey.to_address()
+        );
+    }
+    let Some(fee) = transaction.fee() else {
+        eprintln!("Outputs exceed the inputs, refusing to sign");
+        exit(1);
+    };
+    println!("fee: {}", format_value(fee));
+    if let Some(height) = transaction.expires_at_height {
+        println!("expires at height {height}");
+    }
+    let signed = wallet
+        .sign_transaction(&mut transaction)
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1);
+        });
+    println!("signed {signed} inputs");
+    match transaction.finalize() {
+        Ok(transaction) => {
+            transaction.save_to_file(out).unwrap_or_else(|e| {
+                eprintln!("Failed to save transaction: {e}");
+                exit(1);
+            });
+            println!("saved transaction {} to {out}", transaction.hash());
+        }
+        Err(e) => {
+            transaction.save_to_file(out).unwrap_or_else(|e| {
+                eprintln!("Failed to save transaction: {e}");
+                exit(1);
+            });
+            println!("{e}, saved the partially signed transaction to {out}");
+        }
+    }
+}
+
+fn sweep(
+    unlock: &Unlock,
+    wallet_path: &str,
+    blockchain_path: &str,
+    to: &PublicKey,
+    fee_rate: Option<u64>,
+    allow_locked: bool,
+) {
+    let mut blockchain = load_chain(blockchain_path);
+    let (_, wallet) = scan(unlock, wallet_path, &blockchain);
+    let fee_rate = fee_rate.unwrap_or_else(|| default_fee_rate(&blockchain));
+    let transactions = wallet
+        .sweep(&blockchain, to, fee_rate, allow_locked)
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1);
+        });
+    let fees = add_to_mempool(blockchain_path, &mut blockchain, &transactions);
+    for (transaction, fee) in transactions.iter().zip(fees) {
+        println!(
+            "swept {} in {}, fee {}",
+            format_value(transaction.outputs[0].value),
+            transaction.hash(),
+            format_value(fee)
+        );
+    }
+}
+
+// merge small outputs into a fresh key of ours, but only while
+// fees are low enough that it pays off later
+fn consolidate(
+    unlock: &Unlock,
+    wallet_path: &str,
+    blockchain_path: &str,
+    max_fee_rate: u64,
+    below: u64,
+    allow_locked: bool,
+) {
+    let mut blockchain = load_chain(blockchain_path);
+    let (file, mut wallet) = scan(unlock, wallet_path, &blockchain);
+    let fee_rate = default_fee_rate(&blockchain);
+    if fee_rate > max_fee_rate {
+        println!("fee rate {fee_rate} is above the cap of {max_fee_rate}, not consolidating");
+        return;
+    }
+    let to = wallet.change_key();
+    let transactions = match wallet.consolidate(&blockchain, &to, below, fee_rate, allow_locked) {
+        Ok(transactions) => transactions,
+        Err(
+            e @ (WalletError::NothingToConsolidate { .. }
+            | WalletError::Dust(_)
+            | WalletError::FeeExceedsAmount { .. }),
+        ) => {
+            println!("consolidation isn't economical: {e}");
+            return;
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+    };
+    let fees = add_to_mempool(blockchain_path, &mut blockchain, &transactions);
+    save(wallet_path, &file, &wallet);
+    let mut merged = 0;
+    for (transaction, fee) in transactions.iter().zip(&fees) {
+        println!(
+            "merged {} outputs in {}, fee {}",
+            transaction.inputs.len(),
+            transaction.hash(),
+            format_value(*fee)
+        );
+        merged += transaction.inputs.len();
+    }
+    println!(
+        "merged {merged} outputs for {} in fees",
+        format_value(fees.iter().sum())
+    );
+}
+
+// add the transactions to the chain file's mempool, all of
+// them or none, returns the fee each pays
+fn add_to_mempool(
+    blockchain_path: &str,
+    blockchain: &mut Blockchain,
+    transactions: &[Transaction],
+) -> Vec<u64> {
+    // check them all before touching the chain file
+    let mut checked = blockchain.clone();
+    for transaction in transactions {
+        if let Err(e) = checked.add_to_mempool(transaction.clone()) {
+            eprintln!("Transaction rejected by the chain: {e}");
+            exit(1);
+        }
+    }
+    let mut fees = vec![];
+    for transaction in transactions {
+        fees.push(blockchain.transaction_fee(transaction).unwrap_or(0));
+        blockchain
+            .add_to_mempool(transaction.clone())
+            .expect("BUG: checked transaction rejected");
+    }
+    save_chain(blockchain_path, blockchain);
+    save_mempool(blockchain_path, blockchain);
+    fees
+}
+
+// where to submit a transaction and how long to follow it
+#[derive(Args, Debug)]
+struct BroadcastOptions {
+    /// Submit to the rpc listener of a running node
+    #[arg(long, value_name = "IP:PORT")]
+    node: Option<String>,
+    /// Wait until the transaction has this many confirmations
+    #[arg(long, value_name = "N", requires = "node")]
+    wait_confirmations: Option<u64>,
+    /// Seconds to wait for them
+    #[arg(long, value_name = "SECONDS", default_value_t = 600)]
+    timeout: u64,
+}
+
+impl BroadcastOptions {
+    // exits with the node's reason if it refuses the transaction
+    fn submit(&self, network: Network, transaction: &Transaction, raw: bool) -> NodeClient {
+        let node = self.node.as_ref().expect("BUG: submit without a --node");
+        let result = NodeClient::connect(node, network).and_then(|mut client| {
+            let hash = if raw {
+                client.submit_raw(transaction)?
+            } else {
+                client.submit(transaction)?
+            };
+            println!("submitted transaction {hash} to {node}");
+            Ok(client)
+        });
+        result.unwrap_or_else(|e| {
+            eprintln!("{e}");
+            exit(1);
+        })
+    }
+
+    fn wait(&self, client: &mut NodeClient, hash: Hash) {
+        let Some(confirmations) = self.wait_confirmations else {
+            return;
+        };
+        let timeout = Duration::from_secs(self.timeout);
+        if let Err(e) = client.wait_for_confirmations(hash, confirmations, timeout) {
+            eprintln!("{e}");
+            exit(1);
+        }
+        println!("transaction {hash} has {confirmations} confirmations");
+    }
+}
+
+// follow the chain file and print an event per change to the
+// wallet's transactions, as json lines; the wallet file itself
+// is only read, so other commands can keep using it
+fn watch(
+    unlock: &Unlock,
+    wallet_path: &str,
+    blockchain_path: &str,
+    depth: u64,
+    interval: u64,
+    command: Option<&str>,
+) {
+    let (_, mut wallet) = unlock.open(wallet_path);
+    let blockchain = load_chain(blockchain_path);
+    wallet.scan(&blockchain);
+    let mut watcher = WalletWatcher::new(&wallet, &blockchain, depth);
+    println!(
+        "watching {wallet_path} at height {}",
+        wallet.scanned_height()
+    );
+    loop {
+        thread::sleep(Duration::from_secs(interval));
+        let blockchain = load_chain(blockchain_path);
+        wallet.scan(&blockchain);
+        for event in watcher.update(&wallet, &blockchain) {
+            println!("{}", serde_json::to_string(&event).unwrap());
+            if let Some(command) = command
+                && let Err(e) = event.notify(command)
+            {
+                eprintln!("failed to run {command}: {e}");
+            }
+        }
+    }
+}
+
+// pay what the mempool pays, but never below the default
+fn default_fee_rate(blockchain: &Blockchain) -> u64 {
+    blockchain
+        .estimate_fee_rate()
+        .unwrap_or(DEFAULT_FEE_RATE)
+        .max(DEFAULT_FEE_RATE)
+}
+
+// a signed change in value as coins, with its sign
+fn format_net(net: i64) -> String {
+    let sign = if net < 0 { '-' } else { '+' };
+    format!("{sign}{}", format_value(net.unsigned_abs()))
+}
+
+// the mempool lives next to the chain file, as in a node's data directory
+fn mempool_path(blockchain_path: &str) -> PathBuf {
+    Path::new(blockchain_path).with_file_name("mempool.cbor")
+}
+
+// load a chain file with its mempool
+fn load_chain(blockchain_path: &str) -> Blockchain {
+    let mut blockchain = Blockchain::load_from_file(blockchain_path).unwrap_or_else(|e| {
+        eprintln!("{blockchain_path}: {e}");
+        exit(1);
+    });
+    let mempool_path = mempool_path(blockchain_path);
+    if mempool_path.exists() {
+        let mempool = MempoolSnapshot::load_from_file(&mempool_path).unwrap_or_else(|e| {
+            eprintln!("{}: {e}", mempool_path.display());
+            exit(1);
+        });
+        for (_, e) in blockchain.restore_mempool(mempool) {
+            eprintln!("dropping saved mempool transaction: {e}");
+        }
+    }
+    blockchain
+}
+
+// keep the chain file compressed if it was
+fn save_chain(blockchain_path: &str, blockchain: &Blockchain) {
+    let result = if is_compressed(blockchain_path).unwrap_or(false) {
+        blockchain.save_compressed_to_file(blockchain_path)
+    } else {
+        blockchain.save_to_file(blockchain_path)
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to save blockchain: {e}");
+        exit(1);
+    }
+}
+
+fn save_mempool(blockchain_path: &str, blockchain: &Blockchain) {
+    blockchain
+        .mempool_snapshot()
+        .save_to_file(mempool_path(blockchain_path))
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to save mempool: {e}");
+            exit(1);
+        });
+}
+
+// catch the wallet up with the chain and remember
+// how far it got for the next run
+fn scan(unlock: &Unlock, wallet_path: &str, blockchain: &Blockchain) -> (WalletFile, Wallet) {
+    let (file, mut wallet) = unlock.open(wallet_path);
+    wallet.scan(blockchain);
+    save(wallet_path, &file, &wallet);
+    (file, wallet)
+}
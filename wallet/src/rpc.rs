@@ -0,0 +1,136 @@
+use lib::network::{Message, Services};
+use lib::params::Network;
+use lib::sha256::Hash;
+use lib::types::Transaction;
+use std::io::Error as IoError;
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+// how often to ask for the confirmations of a transaction
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+// how long to wait for any single answer from the node
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Error, Debug)]
+pub enum RpcError {
+    #[error("Failed to reach the node: {0}")]
+    Io(#[from] IoError),
+    #[error("Unexpected answer from the node: {0}")]
+    Protocol(String),
+    #[error("Transaction {hash} rejected by the node: {reason}")]
+    Rejected { hash: Hash, reason: String },
+    #[error("Transaction {hash} has {confirmations} confirmations after waiting")]
+    Timeout { hash: Hash, confirmations: u64 },
+}
+
+// a connection to a node's rpc listener
+pub struct NodeClient {
+    stream: TcpStream,
+    magic: [u8; 4],
+}
+
+impl NodeClient {
+    // connect and exchange Version messages
+    pub fn connect(address: &str, network: Network) -> Result<Self, RpcError> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut client = NodeClient {
+            stream,
+            magic: network.params().magic,
+        };
+        client.send(Message::Version {
+            magic: client.magic,
+            version: lib::PROTOCOL_VERSION,
+            services: Services::NONE,
+            block_height: 0,
+        })?;
+        client.expect(|message| matches!(message, Message::Version { .. }).then_some(()))?;
+        Ok(client)
+    }
+
+    pub fn submit(&mut self, transaction: &Transaction) -> Result<Hash, RpcError> {
+        self.send(Message::SubmitTransaction(transaction.clone()))?;
+        self.submitted(transaction.hash())
+    }
+
+    // submit as the raw hex, as pasted
+    pub fn submit_raw(&mut self, transaction: &Transaction) -> Result<Hash, RpcError> {
+        self.send(Message::SendRawTransaction(transaction.to_hex()))?;
+        self.submitted(transaction.hash())
+    }
+
+    fn submitted(&mut self, hash: Hash) -> Result<Hash, RpcError> {
+        self.expect(|message| match message {
+            Message::TransactionAccepted(accepted) if accepted == hash => Some(Ok(hash)),
+            Message::TransactionRejected {
+                hash: rejected,
+                reason,
+            } if rejected == hash => Some(Err(RpcError::Rejected { hash, reason })),
+            _ => None,
+        })?
+    }
+
+    // None if the node doesn't know the transaction
+    pub fn confirmations(&mut self, hash: Hash) -> Result<Option<u64>, RpcError> {
+        self.send(Message::GetTransactionStatus(hash))?;
+        self.expect(|message| match message {
+            Message::TransactionStatus {
+                hash: status,
+                confirmations,
+            } if status == hash => Some(confirmations),
+            _ => None,
+        })
+    }
+
+    // poll until the transaction has the confirmations or time runs out
+    pub fn wait_for_confirmations(
+        &mut self,
+        hash: Hash,
+        confirmations: u64,
+        timeout: Duration,
+    ) -> Result<(), RpcError> {
+        let started = Instant::now();
+        loop {
+            let current = self.confirmations(hash)?.unwrap_or(0);
+            if current >= confirmations {
+                return Ok(());
+            }
+            if started.elapsed() >= timeout {
+                return Err(RpcError::Timeout {
+                    hash,
+                    confirmations: current,
+                });
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn send(&mut self, message: Message) -> Result<(), RpcError> {
+        message
+            .write_to(self.magic, &mut self.stream)
+            .map_err(|e| match e {
+                ciborium::ser::Error::Io(e) => RpcError::Io(e),
+                e => RpcError::Protocol(e.to_string()),
+            })
+    }
+
+    // read until the answer we wait for, answering pings on the way
+    fn expect<T>(&mut self, mut answer: impl FnMut(Message) -> Option<T>) -> Result<T, RpcError> {
+        loop {
+            let message =
+                Message::read_from(self.magic, &mut self.stream).map_err(|e| match e {
+                    ciborium::de::Error::Io(e) => RpcError::Io(e),
+                    e => RpcError::Protocol(e.to_string()),
+                })?;
+            if let Message::Ping(nonce) = message {
+                self.send(Message::Pong(nonce))?;
+                continue;
+            }
+            if let Some(answer) = answer(message) {
+                return Ok(answer);
+            }
+        }
+    }
+}
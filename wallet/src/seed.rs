@@ -0,0 +1,86 @@
+use bip32::{ChildNumber, XPrv};
+use bip39::Mnemonic;
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::aead::rand_core::RngCore;
+use lib::crypto::PrivateKey;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+// keys derive from the 24 word phrase (bip39, english, no
+// passphrase) along bip44 style paths, which must never change:
+//     receive keys  m/44'/5342'/0'/0/<index>
+//     change keys   m/44'/5342'/0'/1/<index>
+// 5342 is "SB" in hex, there is no registered coin type
+const PURPOSE: u32 = 44;
+pub const COIN_TYPE: u32 = 0x5342;
+const ACCOUNT: u32 = 0;
+pub const RECEIVE_CHAIN: u32 = 0;
+pub const CHANGE_CHAIN: u32 = 1;
+const WORDS: usize = 24;
+
+#[derive(Error, Debug)]
+pub enum SeedError {
+    #[error("Invalid seed phrase: {0}")]
+    InvalidPhrase(bip39::Error),
+    #[error("Expected a {WORDS} word seed phrase, got {0} words")]
+    WordCount(usize),
+}
+
+// the phrase of an hd wallet and how many keys of each
+// chain were derived from it so far
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Seed {
+    phrase: String,
+    next_index: [u32; 2],
+}
+
+impl Seed {
+    pub fn generate() -> Self {
+        let mut entropy = [0u8; 32];
+        OsRng.fill_bytes(&mut entropy);
+        let mnemonic = Mnemonic::from_entropy(&entropy).expect("BUG: 32 bytes of entropy");
+        Seed {
+            phrase: mnemonic.to_string(),
+            next_index: [0; 2],
+        }
+    }
+
+    // accepts any spacing and case
+    pub fn from_phrase(phrase: &str) -> Result<Self, SeedError> {
+        let mnemonic =
+            Mnemonic::parse_normalized(&phrase.to_lowercase()).map_err(SeedError::InvalidPhrase)?;
+        if mnemonic.word_count() != WORDS {
+            return Err(SeedError::WordCount(mnemonic.word_count()));
+        }
+        Ok(Seed {
+            phrase: mnemonic.to_string(),
+            next_index: [0; 2],
+        })
+    }
+
+    pub fn phrase(&self) -> &str {
+        &self.phrase
+    }
+
+    // derive the next count keys of a chain
+    pub fn derive(&mut self, chain: u32, count: usize) -> Vec<PrivateKey> {
+        let mnemonic = Mnemonic::parse_normalized(&self.phrase).expect("BUG: invalid saved phrase");
+        let child = |key: XPrv, index: u32, hardened: bool| {
+            key.derive_child(ChildNumber::new(index, hardened).expect("BUG: index too large"))
+                .expect("BUG: key derivation failed")
+        };
+        let root = XPrv::new(mnemonic.to_seed("")).expect("BUG: invalid seed");
+        let account = child(
+            child(child(root, PURPOSE, true), COIN_TYPE, true),
+            ACCOUNT,
+            true,
+        );
+        let chain_key = child(account, chain, false);
+        let next = &mut self.next_index[chain as usize];
+        let keys = (*next..*next + count as u32)
+            .map(|index| PrivateKey(child(chain_key.clone(), index, false).private_key().clone()))
+            .collect();
+        *next += count as u32;
+        keys
+    }
+}
@@ -0,0 +1,1680 @@
+use crate::seed::{CHANGE_CHAIN, RECEIVE_CHAIN, Seed};
+use lib::crypto::{PrivateKey, PublicKey, Signature};
+use lib::error::SbdError;
+use lib::sha256::{Hash, HashKeyedMap, HashKeyedSet};
+use lib::types::{Blockchain, PartiallySignedTransaction, Transaction, TransactionOutput};
+use lib::utils::{Saveable, deserialize_all, no_migration};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{HashMap, HashSet};
+use std::io::Result as IoResult;
+use thiserror::Error;
+use tracing::warn;
+use uuid::Uuid;
+
+// smallest amount worth sending, in base units
+pub const DUST_LIMIT: u64 = 546;
+// rounds of fee estimation before giving up on a coin selection
+const MAX_FEE_ROUNDS: usize = 8;
+// unused receive and change keys kept generated ahead, so a
+// backup also covers the addresses handed out after it; for a
+// seeded wallet this is also the gap limit of a restore
+pub const KEYPOOL_SIZE: usize = 20;
+// largest transaction the wallet builds, in bytes
+pub const MAX_TRANSACTION_SIZE: u64 = 100_000;
+
+#[derive(Error, Debug)]
+pub enum WalletError {
+    #[error("Insufficient funds: {available} available, {needed} needed")]
+    InsufficientFunds { available: u64, needed: u64 },
+    #[error("Amount {0} is below the dust limit of {DUST_LIMIT}")]
+    Dust(u64),
+    #[error("Amount {amount} does not cover the fee of {fee}")]
+    FeeExceedsAmount { amount: u64, fee: u64 },
+    #[error("No spendable outputs to sweep")]
+    NothingToSweep,
+    #[error("Fewer than two spendable outputs below {below}")]
+    NothingToConsolidate { below: u64 },
+    #[error("Input {output} pays watch-only address {address}, which has no private key")]
+    WatchOnly { output: Hash, address: String },
+    #[error("Address {0} is watch-only, the wallet has no private key for it")]
+    WatchOnlyAddress(String),
+    #[error("Address {0} is not in the wallet")]
+    UnknownAddress(String),
+    #[error("No unspent output of ours with output hash or txid {0}")]
+    UnknownOutput(Hash),
+    #[error("No locked output with output hash or txid {0}")]
+    NotLocked(Hash),
+    #[error("Invalid transaction: {0}")]
+    Invalid(SbdError),
+    #[error("Transaction rejected by the chain: {0}")]
+    Rejected(#[from] SbdError),
+}
+
+// an output paying one of the wallet's keys
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OwnedOutput {
+    pub output: TransactionOutput,
+    // transaction creating it, unknown until a rescan for
+    // outputs found by older versions
+    #[serde(default)]
+    pub txid: Option<Hash>,
+    // height of the block containing it
+    pub height: u64,
+    pub coinbase: bool,
+    pub spent: bool,
+}
+
+// an unspent output as listed by the utxos command
+#[derive(Debug, Clone, Serialize)]
+pub struct Utxo {
+    #[serde(serialize_with = "as_hex")]
+    pub output: Hash,
+    #[serde(serialize_with = "as_hex_opt")]
+    pub txid: Option<Hash>,
+    pub address: String,
+    pub value: u64,
+    pub confirmations: u64,
+    // coinbase outputs are immature for coinbase_maturity blocks
+    pub mature: bool,
+    // the first height of a block that may spend it, if it's locked
+    pub unlock_height: Option<u64>,
+    // the next block is below its unlock height
+    pub timelocked: bool,
+    // a mempool transaction spends it already
+    pub spending: bool,
+    pub watch_only: bool,
+    // kept out of coin selection
+    pub locked: bool,
+}
+
+// what a single address holds, the per-address split of Balance
+#[derive(Debug, Clone, Serialize)]
+pub struct AddressBalance {
+    pub address: String,
+    pub label: Option<String>,
+    pub watch_only: bool,
+    pub confirmed: u64,
+    pub immature: u64,
+    pub timelocked: u64,
+    // mempool outputs paying it minus its outputs the mempool spends
+    pub pending: i64,
+    pub utxos: usize,
+}
+
+pub(crate) fn as_hex<S: Serializer>(hash: &Hash, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(hash)
+}
+
+fn as_hex_opt<S: Serializer>(hash: &Option<Hash>, serializer: S) -> Result<S::Ok, S::Error> {
+    match hash {
+        Some(hash) => serializer.collect_str(hash),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    Mined,
+    Received,
+    Sent,
+    // every output pays the wallet
+    SelfTransfer,
+}
+
+// how a transaction touched the wallet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionRecord {
+    pub txid: Hash,
+    // None while in the mempool
+    pub height: Option<u64>,
+    pub direction: Direction,
+    // value of our outputs it created and spent
+    pub received: u64,
+    pub spent: u64,
+    // only known when every input was ours
+    pub fee: Option<u64>,
+}
+
+impl TransactionRecord {
+    pub fn net(&self) -> i64 {
+        self.received as i64 - self.spent as i64
+    }
+}
+
+// what a send pays, and where its change goes
+#[derive(Debug, Clone)]
+pub struct Payment {
+    pub to: PublicKey,
+    pub amount: u64,
+    pub change_to: PublicKey,
+    // fee per byte of the signed transaction
+    pub fee_rate: u64,
+    // take the fee out of the amount instead of on top of it
+    pub subtract_fee: bool,
+    // let coin selection spend locked outputs too
+    pub allow_locked: bool,
+    // the first height of a block that may no longer include it
+    pub expires_at_height: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Balance {
+    // mature unspent outputs not spent by the mempool
+    pub confirmed: u64,
+    // mempool outputs paying us, including change
+    pub pending: u64,
+    // coinbase outputs with fewer than coinbase_maturity confirmations
+    pub immature: u64,
+    // outputs the next block is below the unlock height of
+    pub timelocked: u64,
+}
+
+// key pairs and the outputs found for them, scanned
+// incrementally from the last scanned block
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Wallet {
+    pub keys: Vec<PrivateKey>,
+    // phrase the key pool derives from, random keys without it
+    seed: Option<Seed>,
+    // keys we track without being able to spend
+    pub watch_only: Vec<PublicKey>,
+    // names for addresses, by address
+    pub labels: HashMap<String, String>,
+    // addresses of the keys reserved for change
+    pub change: HashSet<String>,
+    // addresses handed out, used even before anything pays them
+    issued: HashSet<String>,
+    // output hashes kept out of coin selection
+    locked: HashKeyedSet,
+    // outputs by output hash
+    outputs: HashKeyedMap<OwnedOutput>,
+    // confirmed transactions touching our keys, in chain order
+    history: Vec<TransactionRecord>,
+    scanned_height: u64,
+    // hash of the last scanned block, to notice reorgs
+    scanned_tip: Option<Hash>,
+}
+
+impl Wallet {
+    pub fn new(keys: Vec<PrivateKey>) -> Self {
+        Wallet {
+            keys,
+            ..Default::default()
+        }
+    }
+
+    // true once an address was handed out or paid
+    pub fn is_used(&self, address: &str) -> bool {
+        self.issued.contains(address)
+            || self
+                .outputs
+                .values()
+                .any(|owned| owned.output.pubkey.to_address() == address)
+    }
+
+    // keys of a kind after the last used one, in pool order
+    fn unused_keys(&self, change: bool) -> Vec<usize> {
+        let used: HashSet<String> = self
+            .outputs
+            .values()
+            .map(|owned| owned.output.pubkey.to_address())
+            .chain(self.issued.iter().cloned())
+            .collect();
+        let kind: Vec<(usize, String)> = self
+            .keys
+            .iter()
+            .enumerate()
+            .map(|(index, key)| (index, key.public_key().to_address()))
+            .filter(|(_, address)| self.change.contains(address) == change)
+            .collect();
+        let first_unused = kind
+            .iter()
+            .rposition(|(_, address)| used.contains(address))
+            .map_or(0, |last_used| last_used + 1);
+        kind[first_unused..]
+            .iter()
+            .map(|(index, _)| *index)
+            .collect()
+    }
+
+    // generate keys until KEYPOOL_SIZE unused ones of each kind
+    // exist; fresh keys have no history, so no rescan is needed
+    pub fn top_up_keypool(&mut self) {
+        for change in [false, true] {
+            let missing = KEYPOOL_SIZE.saturating_sub(self.unused_keys(change).len());
+            let keys = match &mut self.seed {
+                Some(seed) => {
+                    seed.derive(if change { CHANGE_CHAIN } else { RECEIVE_CHAIN }, missing)
+                }
+                None => (0..missing).map(|_| PrivateKey::new_key()).collect(),
+            };
+            for key in keys {
+                if change {
+                    self.change.insert(key.public_key().to_address());
+                }
+                self.keys.push(key);
+            }
+        }
+    }
+
+    // a wallet deriving its keys from a seed phrase
+    pub fn from_seed(seed: Seed) -> Self {
+        let mut wallet = Wallet {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        wallet.top_up_keypool();
+        wallet
+    }
+
+    // recreate a seeded wallet from the chain: scan, and while
+    // that uses keys of the pool, derive further ones and rescan
+    pub fn restore(seed: Seed, blockchain: &Blockchain) -> Self {
+        let mut wallet = Wallet::from_seed(seed);
+        loop {
+            wallet.scan(blockchain);
+            let derived = wallet.keys.len();
+            wallet.top_up_keypool();
+            if wallet.keys.len() == derived {
+                return wallet;
+            }
+            wallet.reset_scan();
+        }
+    }
+
+    pub fn seed_phrase(&self) -> Option<&str> {
+        self.seed.as_ref().map(Seed::phrase)
+    }
+
+    fn take_key(&mut self, change: bool) -> PublicKey {
+        self.top_up_keypool();
+        let index = self.unused_keys(change)[0];
+        let pubkey = self.keys[index].public_key();
+        self.issued.insert(pubkey.to_address());
+        self.top_up_keypool();
+        pubkey
+    }
+
+    // the next unused receive key
+    pub fn new_address(&mut self) -> PublicKey {
+        self.take_key(false)
+    }
+
+    // a fresh key for the change of a send
+    pub fn change_key(&mut self) -> PublicKey {
+        self.take_key(true)
+    }
+
+    // spendable keys first, then the watch-only ones
+    pub fn public_keys(&self) -> Vec<PublicKey> {
+        self.keys
+            .iter()
+            .map(|key| key.public_key())
+            .chain(self.watch_only.iter().cloned())
+            .collect()
+    }
+
+    fn is_watch_only(&self, pubkey: &PublicKey) -> bool {
+        !self.keys.iter().any(|key| key.public_key() == *pubkey)
+    }
+
+    // track a public key, returns false if it is already tracked;
+    // its past outputs are unknown, so the chain is rescanned
+    pub fn add_watch_only(&mut self, pubkey: PublicKey) -> bool {
+        if self.public_keys().contains(&pubkey) {
+            return false;
+        }
+        self.watch_only.push(pubkey);
+        self.reset_scan();
+        true
+    }
+
+    // add a spendable key, returns false if we already have it;
+    // a watch-only entry for it becomes spendable
+    pub fn add_key(&mut self, key: PrivateKey) -> bool {
+        let pubkey = key.public_key();
+        if !self.is_watch_only(&pubkey) {
+            return false;
+        }
+        let watched = self.watch_only.contains(&pubkey);
+        self.watch_only.retain(|watched| *watched != pubkey);
+        self.keys.push(key);
+        // outputs of a watched key are already known
+        if !watched {
+            self.reset_scan();
+        }
+        true
+    }
+
+    // height of the first block paying the key
+    pub fn first_seen_height(&self, pubkey: &PublicKey) -> Option<u64> {
+        self.outputs
+            .values()
+            .filter(|owned| owned.output.pubkey == *pubkey)
+            .map(|owned| owned.height)
+            .min()
+    }
+
+    fn reset_scan(&mut self) {
+        self.outputs.clear();
+        self.history.clear();
+        self.scanned_height = 0;
+        self.scanned_tip = None;
+    }
+
+    pub fn scanned_height(&self) -> u64 {
+        self.scanned_height
+    }
+
+    fn owns(&self, pubkeys: &[PublicKey], output: &TransactionOutput) -> bool {
+        pubkeys.contains(&output.pubkey)
+    }
+
+    // process the blocks added since the last scan, starting
+    // over if the last scanned block left the chain
+    pub fn scan(&mut self, blockchain: &Blockchain) {
+        let last_scanned = self
+            .scanned_height
+            .checked_sub(1)
+            .and_then(|height| blockchain.blocks().nth(height as usize))
+            .map(|block| block.hash());
+        if last_scanned != self.scanned_tip {
+            warn!(
+                scanned_height = self.scanned_height,
+                "chain changed since the last scan, rescanning"
+            );
+            self.reset_scan();
+        }
+        let pubkeys = self.public_keys();
+        for (height, block) in blockchain
+            .blocks()
+            .enumerate()
+            .skip(self.scanned_height as usize)
+        {
+            for (index, transaction) in block.transactions.iter().enumerate() {
+                if let Some(record) =
+                    self.record(&pubkeys, transaction, Some(height as u64), index == 0)
+                {
+                    self.history.push(record);
+                }
+                for input in &transaction.inputs {
+                    if let Some(owned) = self.outputs.get_mut(&input.prev_transaction_output_hash) {
+                        owned.spent = true;
+                    }
+                }
+                for output in &transaction.outputs {
+                    if !self.owns(&pubkeys, output) {
+                        continue;
+                    }
+                    self.outputs.insert(
+                        output.hash(),
+                        OwnedOutput {
+                            output: output.clone(),
+                            txid: Some(transaction.hash()),
+                            height: height as u64,
+                            coinbase: index == 0,
+                            spent: false,
+                        },
+                    );
+                }
+            }
+            self.scanned_height = height as u64 + 1;
+            self.scanned_tip = Some(block.hash());
+        }
+        // forget locks on outputs spent or reorged away
+        let outputs = &self.outputs;
+        self.locked
+            .retain(|hash| outputs.get(hash).is_some_and(|owned| !owned.spent));
+    }
+
+    // unspent outputs with the output hash, or else those of the
+    // transaction with the txid
+    fn matching_outputs(&self, target: &Hash) -> Vec<Hash> {
+        if let Some(owned) = self.outputs.get(target) {
+            return if owned.spent { vec![] } else { vec![*target] };
+        }
+        self.outputs
+            .iter()
+            .filter(|(_, owned)| !owned.spent && owned.txid == Some(*target))
+            .map(|(hash, _)| *hash)
+            .collect()
+    }
+
+    // keep outputs out of coin selection, by output hash or
+    // txid; returns the output hashes locked
+    pub fn lock(&mut self, target: &Hash) -> Result<Vec<Hash>, WalletError> {
+        let outputs = self.matching_outputs(target);
+        if outputs.is_empty() {
+            return Err(WalletError::UnknownOutput(*target));
+        }
+        self.locked.extend(outputs.iter().cloned());
+        Ok(outputs)
+    }
+
+    // returns the output hashes unlocked
+    pub fn unlock(&mut self, target: &Hash) -> Result<Vec<Hash>, WalletError> {
+        let mut outputs = self.matching_outputs(target);
+        outputs.retain(|hash| self.locked.remove(hash));
+        if outputs.is_empty() {
+            return Err(WalletError::NotLocked(*target));
+        }
+        Ok(outputs)
+    }
+
+    // locked outputs as of the last scan
+    pub fn locked_outputs(&self) -> Vec<(Hash, &OwnedOutput)> {
+        self.locked
+            .iter()
+            .filter_map(|hash| self.outputs.get(hash).map(|owned| (*hash, owned)))
+            .collect()
+    }
+
+    // the wallet's involvement in a transaction, None if it
+    // neither pays nor spends any of our outputs
+    fn record(
+        &self,
+        pubkeys: &[PublicKey],
+        transaction: &Transaction,
+        height: Option<u64>,
+        coinbase: bool,
+    ) -> Option<TransactionRecord> {
+        let ours: Vec<u64> = transaction
+            .inputs
+            .iter()
+            .filter_map(|input| self.outputs.get(&input.prev_transaction_output_hash))
+            .map(|owned| owned.output.value)
+            .collect();
+        let spent: u64 = ours.iter().sum();
+        let received: u64 = transaction
+            .outputs
+            .iter()
+            .filter(|output| self.owns(pubkeys, output))
+            .map(|output| output.value)
+            .sum();
+        let all_outputs_ours = transaction
+            .outputs
+            .iter()
+            .all(|output| self.owns(pubkeys, output));
+        if ours.is_empty() && received == 0 {
+            return None;
+        }
+        let all_inputs_ours = !ours.is_empty() && ours.len() == transaction.inputs.len();
+        let fee = all_inputs_ours.then(|| {
+            let outputs: u64 = transaction.outputs.iter().map(|output| output.value).sum();
+            spent.saturating_sub(outputs)
+        });
+        let direction = if coinbase {
+            Direction::Mined
+        } else if ours.is_empty() {
+            Direction::Received
+        } else if all_outputs_ours {
+            Direction::SelfTransfer
+        } else {
+            Direction::Sent
+        };
+        Some(TransactionRecord {
+            txid: transaction.hash(),
+            height,
+            direction,
+            received,
+            spent,
+            fee,
+        })
+    }
+
+    // mempool transactions, then confirmed ones newest first
+    pub fn history(&self, blockchain: &Blockchain) -> Vec<TransactionRecord> {
+        let pubkeys = self.public_keys();
+        let mut history: Vec<TransactionRecord> = blockchain
+            .mempool()
+            .iter()
+            .rev()
+            .filter_map(|(_, transaction)| self.record(&pubkeys, transaction, None, false))
+            .collect();
+        history.extend(self.history.iter().rev().cloned());
+        history
+    }
+
+    // blocks on top of the one at the height, including it
+    pub fn confirmations(&self, height: u64) -> u64 {
+        self.scanned_height.saturating_sub(height)
+    }
+
+    // mature unspent outputs the mempool doesn't spend yet and the
+    // next block may, unlocked ones only unless allow_locked
+    fn spendable(&self, blockchain: &Blockchain, allow_locked: bool) -> Vec<(Hash, &OwnedOutput)> {
+        let spent_in_mempool = spent_in_mempool(blockchain);
+        self.outputs
+            .iter()
+            .filter(|(hash, owned)| {
+                !owned.spent
+                    && !spent_in_mempool.contains(*hash)
+                    && self.is_mature(owned, blockchain)
+                    && self.is_unlocked(owned)
+                    && (allow_locked || !self.locked.contains(*hash))
+            })
+            .map(|(hash, owned)| (*hash, owned))
+            .collect()
+    }
+
+    // the next block, the one after the last scanned, may spend it
+    fn is_mature(&self, owned: &OwnedOutput, blockchain: &Blockchain) -> bool {
+        !owned.coinbase
+            || self.scanned_height - owned.height >= blockchain.params().coinbase_maturity
+    }
+
+    // past its unlock height by the next block, the one after the
+    // last scanned; unrelated to the coin control locks
+    fn is_unlocked(&self, owned: &OwnedOutput) -> bool {
+        owned.output.is_spendable_at(self.scanned_height)
+    }
+
+    // make the payment from the largest coins first
+    pub fn create_transaction(
+        &self,
+        blockchain: &Blockchain,
+        payment: &Payment,
+    ) -> Result<Transaction, WalletError> {
+        let mut unsigned = self.create_unsigned(blockchain, payment)?;
+        self.sign_transaction(&mut unsigned)?;
+        unsigned.finalize().map_err(|_| {
+            let input = &unsigned.inputs[unsigned.unsigned()[0]];
+            WalletError::WatchOnly {
+                output: input.prev_transaction_output_hash,
+                address: input.prev_output.pubkey.to_address(),
+            }
+        })
+    }
+
+    // select coins for the payment without signing, watch-only
+    // coins included, for signing elsewhere
+    pub fn create_unsigned(
+        &self,
+        blockchain: &Blockchain,
+        payment: &Payment,
+    ) -> Result<PartiallySignedTransaction, WalletError> {
+        let amount = payment.amount;
+        if amount < DUST_LIMIT {
+            return Err(WalletError::Dust(amount));
+        }
+        // coins we can sign for first, largest first
+        let mut coins = self.spendable(blockchain, payment.allow_locked);
+        coins.sort_by_key(|(_, owned)| {
+            (
+                self.is_watch_only(&owned.output.pubkey),
+                std::cmp::Reverse(owned.output.value),
+            )
+        });
+        let available = coins.iter().map(|(_, owned)| owned.output.value).sum();
+        let mut selected = vec![];
+        let mut total = 0u64;
+        let mut needed = amount;
+        for coin in coins {
+            total += coin.1.output.value;
+            selected.push(coin);
+            match self.fund(&selected, total, payment)? {
+                Ok(unsigned) => return Ok(unsigned),
+                Err(fee) => needed = amount.saturating_add(fee),
+            }
+        }
+        Err(WalletError::InsufficientFunds { available, needed })
+    }
+
+    // sign whatever inputs our keys can, returns how many
+    pub fn sign_transaction(
+        &self,
+        transaction: &mut PartiallySignedTransaction,
+    ) -> Result<usize, WalletError> {
+        let mut signed = 0;
+        for key in &self.keys {
+            signed += transaction.sign(key).map_err(WalletError::Invalid)?;
+        }
+        Ok(signed)
+    }
+
+    // spend every coin we can sign for to the address
+    pub fn sweep(
+        &self,
+        blockchain: &Blockchain,
+        to: &PublicKey,
+        fee_rate: u64,
+        allow_locked: bool,
+    ) -> Result<Vec<Transaction>, WalletError> {
+        let coins = self.signable(blockchain, allow_locked);
+        if coins.is_empty() {
+            return Err(WalletError::NothingToSweep);
+        }
+        self.merge(coins, to, fee_rate)
+    }
+
+    // merge the coins worth less than below into the key, in as
+    // few transactions as MAX_TRANSACTION_SIZE allows
+    pub fn consolidate(
+        &self,
+        blockchain: &Blockchain,
+        to: &PublicKey,
+        below: u64,
+        fee_rate: u64,
+        allow_locked: bool,
+    ) -> Result<Vec<Transaction>, WalletError> {
+        let mut coins = self.signable(blockchain, allow_locked);
+        coins.retain(|(_, owned)| owned.output.value < below);
+        if coins.len() < 2 {
+            return Err(WalletError::NothingToConsolidate { below });
+        }
+        self.merge(coins, to, fee_rate)
+    }
+
+    // mature coins we have the keys for
+    fn signable(&self, blockchain: &Blockchain, allow_locked: bool) -> Vec<(Hash, &OwnedOutput)> {
+        let mut coins = self.spendable(blockchain, allow_locked);
+        coins.retain(|(_, owned)| !self.is_watch_only(&owned.output.pubkey));
+        coins
+    }
+
+    // pay all the coins to the key without change, the fee coming
+    // out of their value, split over transactions of at most
+    // MAX_TRANSACTION_SIZE
+    fn merge(
+        &self,
+        mut coins: Vec<(Hash, &OwnedOutput)>,
+        to: &PublicKey,
+        fee_rate: u64,
+    ) -> Result<Vec<Transaction>, WalletError> {
+        coins.sort_by_key(|(_, owned)| std::cmp::Reverse(owned.output.value));
+        let mut transactions = vec![];
+        let mut rest = &coins[..];
+        while !rest.is_empty() {
+            let mut count = rest.len();
+            loop {
+                let batch = &rest[..count];
+                let total = batch.iter().map(|(_, owned)| owned.output.value).sum();
+                let payment = Payment {
+                    to: to.clone(),
+                    amount: total,
+                    change_to: to.clone(),
+                    fee_rate,
+                    subtract_fee: true,
+                    allow_locked: true,
+                    expires_at_height: None,
+                };
+                let mut unsigned = match self.fund(batch, total, &payment)? {
+                    Ok(unsigned) => unsigned,
+                    Err(fee) => {
+                        return Err(WalletError::FeeExceedsAmount { amount: total, fee });
+                    }
+                };
+                let size = unsigned.size();
+                if size <= MAX_TRANSACTION_SIZE || count == 1 {
+                    self.sign_transaction(&mut unsigned)?;
+                    transactions.push(unsigned.finalize().map_err(WalletError::Invalid)?);
+                    rest = &rest[count..];
+                    break;
+                }
+                // scale the batch down to fit and try again
+                count = ((count as u64 * MAX_TRANSACTION_SIZE / size) as usize).clamp(1, count - 1);
+            }
+        }
+        Ok(transactions)
+    }
+
+    // build the transaction from the selected coins, raising the fee
+    // until it pays fee_rate for the signed size; sub-dust change is
+    // left to the fee. Returns the fee needed if the coins fall short
+    fn fund(
+        &self,
+        selected: &[(Hash, &OwnedOutput)],
+        total: u64,
+        payment: &Payment,
+    ) -> Result<Result<PartiallySignedTransaction, u64>, WalletError> {
+        let amount = payment.amount;
+        let mut fee = 0u64;
+        for _ in 0..MAX_FEE_ROUNDS {
+            let (paid, change) = if payment.subtract_fee {
+                let Some(change) = total.checked_sub(amount) else {
+                    return Ok(Err(fee));
+                };
+                let Some(paid) = amount.checked_sub(fee) else {
+                    return Err(WalletError::FeeExceedsAmount { amount, fee });
+                };
+                if paid < DUST_LIMIT {
+                    return Err(WalletError::Dust(paid));
+                }
+                (paid, change)
+            } else {
+                let Some(change) = total.checked_sub(amount.saturating_add(fee)) else {
+                    return Ok(Err(fee));
+                };
+                (amount, change)
+            };
+            let change = if change < DUST_LIMIT { 0 } else { change };
+            let unsigned = build(selected, payment, paid, change);
+            let required = payment.fee_rate.saturating_mul(unsigned.size());
+            if total - paid - change >= required {
+                return Ok(Ok(unsigned));
+            }
+            fee = required;
+        }
+        Ok(Err(fee))
+    }
+
+    // prove owning the address by signing the message with its key
+    pub fn sign_message(&self, address: &str, message: &str) -> Result<Signature, WalletError> {
+        if let Some(key) = self
+            .keys
+            .iter()
+            .find(|key| key.public_key().to_address() == address)
+        {
+            return Ok(Signature::sign_message(message.as_bytes(), key));
+        }
+        if self
+            .watch_only
+            .iter()
+            .any(|pubkey| pubkey.to_address() == address)
+        {
+            return Err(WalletError::WatchOnlyAddress(address.to_string()));
+        }
+        Err(WalletError::UnknownAddress(address.to_string()))
+    }
+
+    // balances as of the last scan, with the mempool
+    // of the given chain counted as pending
+    pub fn balance(&self, blockchain: &Blockchain) -> Balance {
+        let pubkeys = self.public_keys();
+        let mut balance = Balance::default();
+        let spent_in_mempool = spent_in_mempool(blockchain);
+        for (_, transaction) in blockchain.mempool() {
+            balance.pending += transaction
+                .outputs
+                .iter()
+                .filter(|output| self.owns(&pubkeys, output))
+                .map(|output| output.value)
+                .sum::<u64>();
+        }
+        for (hash, owned) in &self.outputs {
+            if owned.spent || spent_in_mempool.contains(hash) {
+                continue;
+            }
+            if !self.is_unlocked(owned) {
+                balance.timelocked += owned.output.value;
+            } else if self.is_mature(owned, blockchain) {
+                balance.confirmed += owned.output.value;
+            } else {
+                balance.immature += owned.output.value;
+            }
+        }
+        balance
+    }
+
+    // unspent outputs as of the last scan, including the
+    // ones a mempool transaction is about to spend
+    pub fn utxos(&self, blockchain: &Blockchain) -> Vec<Utxo> {
+        let spent_in_mempool = spent_in_mempool(blockchain);
+        self.outputs
+            .iter()
+            .filter(|(_, owned)| !owned.spent)
+            .map(|(hash, owned)| Utxo {
+                output: *hash,
+                txid: owned.txid,
+                address: owned.output.pubkey.to_address(),
+                value: owned.output.value,
+                confirmations: self.confirmations(owned.height),
+                mature: self.is_mature(owned, blockchain),
+                unlock_height: owned.output.spendable_after_height,
+                timelocked: !self.is_unlocked(owned),
+                spending: spent_in_mempool.contains(hash),
+                watch_only: self.is_watch_only(&owned.output.pubkey),
+                locked: self.locked.contains(hash),
+            })
+            .collect()
+    }
+
+    // balance of every address handed out, paid or watched, in wallet
+    // order; confirmed, immature and timelocked add up to balance()
+    pub fn balances_by_address(&self, blockchain: &Blockchain) -> Vec<AddressBalance> {
+        let utxos = self.utxos(blockchain);
+        self.public_keys()
+            .into_iter()
+            .filter_map(|pubkey| {
+                let address = pubkey.to_address();
+                let watch_only = self.is_watch_only(&pubkey);
+                if !watch_only && !self.is_used(&address) {
+                    return None;
+                }
+                let mut balance = AddressBalance {
+                    label: self.labels.get(&address).cloned(),
+                    address,
+                    watch_only,
+                    confirmed: 0,
+                    immature: 0,
+                    timelocked: 0,
+                    pending: 0,
+                    utxos: 0,
+                };
+                for utxo in utxos.iter().filter(|utxo| utxo.address == balance.address) {
+                    balance.utxos += 1;
+                    if utxo.spending {
+                        balance.pending -= utxo.value as i64;
+                    } else if utxo.timelocked {
+                        balance.timelocked += utxo.value;
+                    } else if utxo.mature {
+                        balance.confirmed += utxo.value;
+                    } else {
+                        balance.immature += utxo.value;
+                    }
+                }
+                for (_, transaction) in blockchain.mempool() {
+                    for output in &transaction.outputs {
+                        if output.pubkey == pubkey {
+                            balance.pending += output.value as i64;
+                        }
+                    }
+                }
+                Some(balance)
+            })
+            .collect()
+    }
+}
+
+// pay the amount, and the change if any, from the coins
+fn build(
+    coins: &[(Hash, &OwnedOutput)],
+    payment: &Payment,
+    amount: u64,
+    change: u64,
+) -> PartiallySignedTransaction {
+    let prev_outputs = coins
+        .iter()
+        .map(|(hash, owned)| (*hash, owned.output.clone()))
+        .collect();
+    let mut outputs = vec![TransactionOutput {
+        value: amount,
+        unique_id: Uuid::new_v4(),
+        pubkey: payment.to.clone(),
+        spendable_after_height: None,
+    }];
+    if change > 0 {
+        outputs.push(TransactionOutput {
+            value: change,
+            unique_id: Uuid::new_v4(),
+            pubkey: payment.change_to.clone(),
+            spendable_after_height: None,
+        });
+    }
+    let mut unsigned = PartiallySignedTransaction::new(prev_outputs, outputs);
+    unsigned.expires_at_height = payment.expires_at_height;
+    unsigned
+}
+
+fn spent_in_mempool(blockchain: &Blockchain) -> HashKeyedSet {
+    blockchain
+        .mempool()
+        .iter()
+        .flat_map(|(_, transaction)| &transaction.inputs)
+        .map(|input| input.prev_transaction_output_hash)
+        .collect()
+}
+
+//save and load expecting CBOR from ciborium as format
+impl Saveable for Wallet {
+    const TYPE_TAG: &'static str = "Wallet";
+    const SCHEMA_VERSION: u16 = 2;
+
+    fn migrate(version: u16, data: &[u8]) -> IoResult<Self> {
+        match version {
+            1 => Ok(deserialize_all::<v1::Wallet>(data, Self::TYPE_TAG)?.into()),
+            _ => Err(no_migration(Self::TYPE_TAG, version)),
+        }
+    }
+}
+
+// saved forms of older schema versions, as they were
+mod v1 {
+    use super::*;
+
+    // everything past the keys and the scan was added over time
+    #[derive(Deserialize)]
+    pub struct Wallet {
+        pub keys: Vec<PrivateKey>,
+        #[serde(default)]
+        pub seed: Option<Seed>,
+        #[serde(default)]
+        pub watch_only: Vec<PublicKey>,
+        #[serde(default)]
+        pub labels: HashMap<String, String>,
+        #[serde(default)]
+        pub change: HashSet<String>,
+        #[serde(default)]
+        pub issued: HashSet<String>,
+        #[serde(default)]
+        pub locked: HashKeyedSet,
+        pub outputs: HashKeyedMap<OwnedOutput>,
+        #[serde(default)]
+        pub history: Vec<TransactionRecord>,
+        pub scanned_height: u64,
+        pub scanned_tip: Option<Hash>,
+    }
+}
+
+// wallets from before txids and history were kept have outputs
+// without a txid and no history, scanning again fills both in
+impl From<v1::Wallet> for Wallet {
+    fn from(old: v1::Wallet) -> Self {
+        let mut wallet = Wallet {
+            keys: old.keys,
+            seed: old.seed,
+            watch_only: old.watch_only,
+            labels: old.labels,
+            change: old.change,
+            issued: old.issued,
+            locked: old.locked,
+            outputs: old.outputs,
+            history: old.history,
+            scanned_height: old.scanned_height,
+            scanned_tip: old.scanned_tip,
+        };
+        let incomplete = wallet.outputs.values().any(|owned| owned.txid.is_none())
+            || (wallet.history.is_empty() && !wallet.outputs.is_empty());
+        if incomplete {
+            wallet.reset_scan();
+        }
+        wallet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib::params::NetworkParams;
+    use lib::test_utils::{ChainBuilder, instant_params};
+
+    // blocks on top of a coinbase before it may be spent
+    const MATURITY: u64 = 3;
+    // the builder keys of the wallet and of someone else
+    const OURS: usize = 1;
+    const THEIRS: usize = 2;
+    // watched without its private key
+    const WATCHED: usize = 3;
+
+    fn builder(seed: u64) -> ChainBuilder {
+        let params = NetworkParams {
+            coinbase_maturity: MATURITY,
+            ..instant_params()
+        };
+        ChainBuilder::with_params(seed, params)
+    }
+
+    fn wallet(builder: &ChainBuilder) -> Wallet {
+        Wallet::new(vec![builder.key(OURS).clone()])
+    }
+
+    // amount to THEIRS at a fee rate of 1, the change back to us
+    fn payment(builder: &ChainBuilder, amount: u64) -> Payment {
+        Payment {
+            to: builder.key(THEIRS).public_key(),
+            amount,
+            change_to: builder.key(OURS).public_key(),
+            fee_rate: 1,
+            subtract_fee: false,
+            allow_locked: false,
+            expires_at_height: None,
+        }
+    }
+
+    // a block of everything in the mempool
+    fn mine_mempool(builder: &mut ChainBuilder) {
+        let transactions: Vec<Transaction> = builder
+            .chain()
+            .mempool()
+            .iter()
+            .map(|(_, transaction)| transaction.clone())
+            .collect();
+        builder
+            .mine_block(|template| template.transactions = transactions)
+            .unwrap();
+    }
+
+    fn balance(wallet: &mut Wallet, builder: &ChainBuilder) -> Balance {
+        wallet.scan(builder.chain());
+        wallet.balance(builder.chain())
+    }
+
+    #[test]
+    fn coinbase_is_immature_until_maturity_blocks_are_on_it() {
+        let mut builder = builder(646);
+        let mut wallet = wallet(&builder);
+        builder.fund(OURS, &[1000, 2000]).unwrap();
+        for _ in 1..MATURITY {
+            let balance = balance(&mut wallet, &builder);
+            assert_eq!(
+                balance,
+                Balance {
+                    immature: 3000,
+                    ..Balance::default()
+                }
+            );
+            builder.mine_blocks(1).unwrap();
+        }
+        let balance = balance(&mut wallet, &builder);
+        assert_eq!(
+            balance,
+            Balance {
+                confirmed: 3000,
+                ..Balance::default()
+            }
+        );
+    }
+
+    #[test]
+    fn receive_is_pending_until_mined() {
+        let mut builder = builder(646);
+        let mut wallet = wallet(&builder);
+        builder.fund(THEIRS, &[5000]).unwrap();
+        builder.mine_blocks(MATURITY).unwrap();
+        let receive = builder.spend(THEIRS, OURS, 1500, 100).unwrap();
+        builder.chain_mut().add_to_mempool(receive).unwrap();
+        assert_eq!(
+            balance(&mut wallet, &builder),
+            Balance {
+                pending: 1500,
+                ..Balance::default()
+            }
+        );
+        mine_mempool(&mut builder);
+        assert_eq!(
+            balance(&mut wallet, &builder),
+            Balance {
+                confirmed: 1500,
+                ..Balance::default()
+            }
+        );
+    }
+
+    #[test]
+    fn spend_leaves_the_change_pending_then_confirmed() {
+        let mut builder = builder(646);
+        let mut wallet = wallet(&builder);
+        builder.fund(OURS, &[5000]).unwrap();
+        builder.mine_blocks(MATURITY).unwrap();
+        assert_eq!(balance(&mut wallet, &builder).confirmed, 5000);
+        let send = wallet
+            .create_transaction(builder.chain(), &payment(&builder, 1200))
+            .unwrap();
+        let fee = 5000 - send.outputs.iter().map(|output| output.value).sum::<u64>();
+        assert!(fee > 0);
+        let change = 5000 - 1200 - fee;
+        builder.chain_mut().add_to_mempool(send).unwrap();
+        // the coin spent is gone, the change not here yet
+        assert_eq!(
+            balance(&mut wallet, &builder),
+            Balance {
+                pending: change,
+                ..Balance::default()
+            }
+        );
+        mine_mempool(&mut builder);
+        assert_eq!(
+            balance(&mut wallet, &builder),
+            Balance {
+                confirmed: change,
+                ..Balance::default()
+            }
+        );
+    }
+
+    // a wallet of OURS and WATCHED, each paid a matured coin
+    fn mixed(builder: &mut ChainBuilder) -> Wallet {
+        let mut wallet = wallet(builder);
+        assert!(wallet.add_watch_only(builder.key(WATCHED).public_key()));
+        builder.fund(OURS, &[4000]).unwrap();
+        builder.fund(WATCHED, &[6000]).unwrap();
+        builder.mine_blocks(MATURITY).unwrap();
+        wallet.scan(builder.chain());
+        wallet
+    }
+
+    #[test]
+    fn watched_coins_count_towards_the_balance() {
+        let mut builder = builder(650);
+        let wallet = mixed(&mut builder);
+        assert_eq!(wallet.balance(builder.chain()).confirmed, 10_000);
+        let utxos = wallet.utxos(builder.chain());
+        let watched: Vec<u64> = utxos
+            .iter()
+            .filter(|utxo| utxo.watch_only)
+            .map(|utxo| utxo.value)
+            .collect();
+        assert_eq!(watched, [6000]);
+        let by_address = wallet.balances_by_address(builder.chain());
+        let total: u64 = by_address.iter().map(|balance| balance.confirmed).sum();
+        assert_eq!(total, 10_000);
+    }
+
+    #[test]
+    fn watched_coins_are_not_spent() {
+        let mut builder = builder(650);
+        let wallet = mixed(&mut builder);
+        // our coin alone covers it
+        let send = wallet
+            .create_transaction(builder.chain(), &payment(&builder, 3000))
+            .unwrap();
+        assert_eq!(send.inputs.len(), 1);
+        // it takes the watched coin too, which can't be signed
+        let watched = builder.key(WATCHED).public_key();
+        match wallet.create_transaction(builder.chain(), &payment(&builder, 8000)) {
+            Err(WalletError::WatchOnly { address, .. }) => {
+                assert_eq!(address, watched.to_address())
+            }
+            result => panic!("{result:?}"),
+        }
+        // left unsigned for the key's owner to sign
+        let mut unsigned = wallet
+            .create_unsigned(builder.chain(), &payment(&builder, 8000))
+            .unwrap();
+        assert_eq!(wallet.sign_transaction(&mut unsigned).unwrap(), 1);
+        assert_eq!(unsigned.unsigned().len(), 1);
+        // a sweep leaves it where it is
+        let sweep = wallet.sweep(builder.chain(), &watched, 1, false).unwrap();
+        assert_eq!(sweep.len(), 1);
+        assert_eq!(sweep[0].inputs.len(), 1);
+        assert!(matches!(
+            wallet.sign_message(&watched.to_address(), "mine"),
+            Err(WalletError::WatchOnlyAddress(_))
+        ));
+    }
+
+    fn fee(transaction: &Transaction, spent: u64) -> u64 {
+        spent
+            - transaction
+                .outputs
+                .iter()
+                .map(|output| output.value)
+                .sum::<u64>()
+    }
+
+    #[test]
+    fn history_lists_each_kind_of_transaction() {
+        let mut builder = builder(651);
+        let mut wallet = wallet(&builder);
+        let ours = builder.key(OURS).public_key();
+        // heights 0 to 3: mined to us and matured
+        builder.fund(OURS, &[5000]).unwrap();
+        builder.mine_blocks(MATURITY).unwrap();
+        let mined = builder.chain().blocks().next().unwrap().transactions[0].clone();
+        // 4 to 7: someone else's coin to pay us from
+        builder.fund(THEIRS, &[9000]).unwrap();
+        builder.mine_blocks(MATURITY).unwrap();
+        // 8: a receive
+        let received = builder.spend(THEIRS, OURS, 2000, 100).unwrap();
+        builder
+            .chain_mut()
+            .add_to_mempool(received.clone())
+            .unwrap();
+        mine_mempool(&mut builder);
+        // 9: a send, from the larger coin
+        wallet.scan(builder.chain());
+        let sent = wallet
+            .create_transaction(builder.chain(), &payment(&builder, 1200))
+            .unwrap();
+        builder.chain_mut().add_to_mempool(sent.clone()).unwrap();
+        mine_mempool(&mut builder);
+        // 10: paying ourselves, from the change of the send
+        wallet.scan(builder.chain());
+        let to_self = Payment {
+            to: ours.clone(),
+            ..payment(&builder, 1500)
+        };
+        let moved = wallet
+            .create_transaction(builder.chain(), &to_self)
+            .unwrap();
+        builder.chain_mut().add_to_mempool(moved.clone()).unwrap();
+        mine_mempool(&mut builder);
+        // and one more receive, still pending
+        let pending = builder.spend(THEIRS, OURS, 700, 100).unwrap();
+        builder.chain_mut().add_to_mempool(pending.clone()).unwrap();
+        wallet.scan(builder.chain());
+
+        let change = 5000 - 1200 - fee(&sent, 5000);
+        let moved_in = change.max(2000);
+        let rows: Vec<_> = wallet
+            .history(builder.chain())
+            .into_iter()
+            .map(|record| {
+                (
+                    record.txid,
+                    record.height,
+                    record.direction,
+                    record.received,
+                    record.spent,
+                    record.fee,
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (pending.hash(), None, Direction::Received, 700, 0, None),
+                (
+                    moved.hash(),
+                    Some(10),
+                    Direction::SelfTransfer,
+                    moved_in - fee(&moved, moved_in),
+                    moved_in,
+                    Some(fee(&moved, moved_in)),
+                ),
+                (
+                    sent.hash(),
+                    Some(9),
+                    Direction::Sent,
+                    change,
+                    5000,
+                    Some(fee(&sent, 5000)),
+                ),
+                (received.hash(), Some(8), Direction::Received, 2000, 0, None),
+                (mined.hash(), Some(0), Direction::Mined, 5000, 0, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn fee_rate_is_met_within_a_unit_per_byte() {
+        // coins, amount to send from them
+        let shapes: [(&[u64], u64); 4] = [
+            (&[1_000_000], 300_000),
+            (&[100_000; 10], 750_000),
+            (&[600_000, 90_000, 20_000, 20_000], 650_000),
+            (&[30_000; 40], 900_000),
+        ];
+        for (coins, amount) in shapes {
+            let mut builder = builder(654);
+            let mut wallet = wallet(&builder);
+            builder.fund(OURS, coins).unwrap();
+            builder.mine_blocks(MATURITY).unwrap();
+            wallet.scan(builder.chain());
+            // unsigned inputs are sized at the widest signature, a few
+            // bytes over most, which stays under a unit a byte up to here
+            for fee_rate in [1, 2, 5, 10] {
+                let payment = Payment {
+                    fee_rate,
+                    ..payment(&builder, amount)
+                };
+                let send = wallet
+                    .create_transaction(builder.chain(), &payment)
+                    .unwrap();
+                let spent: u64 = send
+                    .inputs
+                    .iter()
+                    .map(|input| {
+                        builder
+                            .chain()
+                            .utxo(&input.prev_transaction_output_hash)
+                            .unwrap()
+                            .value
+                    })
+                    .sum();
+                let size = send.size();
+                let fee = fee(&send, spent);
+                assert!(
+                    fee >= fee_rate * size && fee < (fee_rate + 1) * size,
+                    "{coins:?} at {fee_rate}: fee {fee} for {size} bytes"
+                );
+            }
+        }
+    }
+
+    // a block paying each key its value
+    fn pay(builder: &mut ChainBuilder, payments: &[(&PublicKey, u64)]) {
+        let payouts = payments
+            .iter()
+            .map(|(pubkey, value)| TransactionOutput {
+                pubkey: (*pubkey).clone(),
+                unique_id: Uuid::new_v4(),
+                value: *value,
+                spendable_after_height: None,
+            })
+            .collect();
+        builder
+            .mine_block(|template| template.payouts = payouts)
+            .unwrap();
+    }
+
+    #[test]
+    fn change_goes_to_a_key_of_its_own() {
+        let mut builder = builder(656);
+        let mut wallet = Wallet::from_seed(Seed::generate());
+        let receive = wallet.new_address();
+        pay(&mut builder, &[(&receive, 5000)]);
+        builder.mine_blocks(MATURITY).unwrap();
+        wallet.scan(builder.chain());
+
+        let change_to = wallet.change_key();
+        assert_ne!(change_to, receive);
+        assert!(wallet.change.contains(&change_to.to_address()));
+        assert!(!wallet.change.contains(&receive.to_address()));
+        let payment = Payment {
+            change_to: change_to.clone(),
+            ..payment(&builder, 1200)
+        };
+        let send = wallet
+            .create_transaction(builder.chain(), &payment)
+            .unwrap();
+        let change: Vec<&PublicKey> = send
+            .outputs
+            .iter()
+            .map(|output| &output.pubkey)
+            .filter(|pubkey| **pubkey != payment.to)
+            .collect();
+        assert_eq!(change, [&change_to]);
+        builder.chain_mut().add_to_mempool(send).unwrap();
+        mine_mempool(&mut builder);
+        // the next send's change goes to another key again
+        wallet.scan(builder.chain());
+        assert_ne!(wallet.change_key(), change_to);
+        assert_ne!(wallet.new_address(), receive);
+    }
+
+    #[test]
+    fn balance_adds_up_the_coins_of_every_key() {
+        let mut builder = builder(656);
+        let mut wallet = Wallet::from_seed(Seed::generate());
+        let keys: Vec<PublicKey> = (0..3).map(|_| wallet.new_address()).collect();
+        pay(
+            &mut builder,
+            &[(&keys[0], 1000), (&keys[1], 2000), (&keys[2], 4000)],
+        );
+        builder.mine_blocks(MATURITY).unwrap();
+        pay(&mut builder, &[(&keys[0], 8000)]);
+        assert_eq!(
+            balance(&mut wallet, &builder),
+            Balance {
+                confirmed: 7000,
+                immature: 8000,
+                ..Balance::default()
+            }
+        );
+        let by_address: Vec<(String, u64, u64)> = wallet
+            .balances_by_address(builder.chain())
+            .into_iter()
+            .map(|balance| (balance.address, balance.confirmed, balance.immature))
+            .collect();
+        assert_eq!(
+            by_address,
+            [
+                (keys[0].to_address(), 1000, 8000),
+                (keys[1].to_address(), 2000, 0),
+                (keys[2].to_address(), 4000, 0),
+            ]
+        );
+        // a send may take coins of several keys at once
+        let send = wallet
+            .create_transaction(builder.chain(), &payment(&builder, 4500))
+            .unwrap();
+        assert!(send.inputs.len() > 1);
+    }
+
+    // the keys paid lie beyond the first pool, each within
+    // KEYPOOL_SIZE of the last one used, change included
+    #[test]
+    fn restored_pool_finds_every_coin() {
+        let mut builder = builder(656);
+        let seed = Seed::generate();
+        let phrase = seed.phrase().to_string();
+        let mut wallet = Wallet::from_seed(seed);
+        let keys: Vec<PublicKey> = (0..2 * KEYPOOL_SIZE)
+            .map(|_| wallet.new_address())
+            .collect();
+        let paid = [0, KEYPOOL_SIZE - 1, 2 * KEYPOOL_SIZE - 2];
+        let payments: Vec<(&PublicKey, u64)> =
+            paid.iter().map(|index| (&keys[*index], 3000)).collect();
+        pay(&mut builder, &payments);
+        builder.mine_blocks(MATURITY).unwrap();
+        wallet.scan(builder.chain());
+        let payment = Payment {
+            change_to: wallet.change_key(),
+            ..payment(&builder, 4000)
+        };
+        let send = wallet
+            .create_transaction(builder.chain(), &payment)
+            .unwrap();
+        builder.chain_mut().add_to_mempool(send).unwrap();
+        mine_mempool(&mut builder);
+        let expected = balance(&mut wallet, &builder);
+        // what's left is the coin not spent and the change of the send
+        let utxos = wallet.utxos(builder.chain());
+        assert_eq!(utxos.len(), 2);
+        assert!(
+            utxos
+                .iter()
+                .any(|utxo| wallet.change.contains(&utxo.address))
+        );
+
+        let seed = Seed::from_phrase(&phrase).unwrap();
+        let restored = Wallet::restore(seed, builder.chain());
+        assert_eq!(restored.balance(builder.chain()), expected);
+        let rows = |wallet: &Wallet| -> Vec<(Hash, Option<u64>, i64)> {
+            wallet
+                .history(builder.chain())
+                .iter()
+                .map(|record| (record.txid, record.height, record.net()))
+                .collect()
+        };
+        assert_eq!(rows(&restored), rows(&wallet));
+    }
+
+    fn value(transaction: &Transaction) -> u64 {
+        transaction.outputs.iter().map(|output| output.value).sum()
+    }
+
+    #[test]
+    fn sweep_merges_small_coins_into_one_output() {
+        let mut builder = builder(657);
+        let mut wallet = wallet(&builder);
+        builder.fund(OURS, &[2000; 30]).unwrap();
+        builder.mine_blocks(MATURITY).unwrap();
+        wallet.scan(builder.chain());
+        let to = builder.key(THEIRS).public_key();
+        let sweep = wallet.sweep(builder.chain(), &to, 1, false).unwrap();
+        let [transaction] = sweep.as_slice() else {
+            panic!("{sweep:?}");
+        };
+        assert_eq!(transaction.inputs.len(), 30);
+        assert_eq!(transaction.outputs.len(), 1);
+        assert_eq!(transaction.outputs[0].pubkey, to);
+        let fee = 60_000 - value(transaction);
+        assert!(fee >= transaction.size(), "fee {fee}");
+        builder
+            .chain_mut()
+            .add_to_mempool(transaction.clone())
+            .unwrap();
+        mine_mempool(&mut builder);
+        assert_eq!(balance(&mut wallet, &builder), Balance::default());
+    }
+
+    // more coins than fit one transaction of MAX_TRANSACTION_SIZE
+    #[test]
+    fn sweep_splits_over_transactions_that_fit() {
+        const COINS: usize = 600;
+        let mut builder = builder(657);
+        let mut wallet = wallet(&builder);
+        builder.fund(OURS, &[5000; COINS]).unwrap();
+        builder.mine_blocks(MATURITY).unwrap();
+        wallet.scan(builder.chain());
+        let to = builder.key(THEIRS).public_key();
+        let sweep = wallet.sweep(builder.chain(), &to, 1, false).unwrap();
+        assert!(sweep.len() > 1, "{} transactions", sweep.len());
+        let mut spent = HashKeyedSet::default();
+        for transaction in &sweep {
+            assert!(transaction.size() <= MAX_TRANSACTION_SIZE);
+            assert_eq!(transaction.outputs.len(), 1);
+            for input in &transaction.inputs {
+                assert!(spent.insert(input.prev_transaction_output_hash));
+            }
+            builder
+                .chain_mut()
+                .add_to_mempool(transaction.clone())
+                .unwrap();
+        }
+        assert_eq!(spent.len(), COINS);
+        mine_mempool(&mut builder);
+        assert_eq!(balance(&mut wallet, &builder), Balance::default());
+    }
+
+    #[test]
+    fn sweep_refuses_to_pay_dust() {
+        let mut builder = builder(657);
+        let mut wallet = wallet(&builder);
+        builder.fund(OURS, &[DUST_LIMIT + 100]).unwrap();
+        builder.mine_blocks(MATURITY).unwrap();
+        wallet.scan(builder.chain());
+        let to = builder.key(THEIRS).public_key();
+        match wallet.sweep(builder.chain(), &to, 1, false) {
+            Err(WalletError::Dust(paid)) => assert!(paid < DUST_LIMIT),
+            result => panic!("{result:?}"),
+        }
+        // nothing left to sweep at all
+        let empty = Wallet::new(vec![]);
+        assert!(matches!(
+            empty.sweep(builder.chain(), &to, 1, false),
+            Err(WalletError::NothingToSweep)
+        ));
+    }
+
+    // the outputs of the transactions paying anything but `to`
+    fn paying_others(transactions: &[Transaction], to: &PublicKey) -> usize {
+        transactions
+            .iter()
+            .flat_map(|transaction| &transaction.outputs)
+            .filter(|output| output.pubkey != *to)
+            .count()
+    }
+
+    #[test]
+    fn consolidate_merges_the_small_coins_only() {
+        let mut builder = builder(662);
+        let mut wallet = wallet(&builder);
+        builder.fund(OURS, &[1000; 50]).unwrap();
+        builder.fund(OURS, &[400_000]).unwrap();
+        builder.mine_blocks(MATURITY).unwrap();
+        wallet.scan(builder.chain());
+        let to = builder.key(OURS).public_key();
+        let merged = wallet
+            .consolidate(builder.chain(), &to, 10_000, 1, false)
+            .unwrap();
+        let [transaction] = merged.as_slice() else {
+            panic!("{merged:?}");
+        };
+        assert_eq!(transaction.inputs.len(), 50);
+        assert_eq!(transaction.outputs.len(), 1);
+        assert_eq!(paying_others(&merged, &to), 0);
+        let fee = 50_000 - value(transaction);
+        builder
+            .chain_mut()
+            .add_to_mempool(transaction.clone())
+            .unwrap();
+        mine_mempool(&mut builder);
+        assert_eq!(balance(&mut wallet, &builder).confirmed, 450_000 - fee);
+        let mut values: Vec<u64> = wallet
+            .utxos(builder.chain())
+            .iter()
+            .map(|utxo| utxo.value)
+            .collect();
+        values.sort();
+        assert_eq!(values, [50_000 - fee, 400_000]);
+    }
+
+    #[test]
+    fn consolidate_splits_at_the_size_limit() {
+        const COINS: usize = 600;
+        let mut builder = builder(662);
+        let mut wallet = wallet(&builder);
+        builder.fund(OURS, &[5000; COINS]).unwrap();
+        builder.mine_blocks(MATURITY).unwrap();
+        wallet.scan(builder.chain());
+        let to = builder.key(OURS).public_key();
+        let merged = wallet
+            .consolidate(builder.chain(), &to, 10_000, 1, false)
+            .unwrap();
+        assert!(merged.len() > 1, "{} transactions", merged.len());
+        assert!(
+            merged
+                .iter()
+                .all(|transaction| transaction.size() <= MAX_TRANSACTION_SIZE)
+        );
+        let inputs: usize = merged
+            .iter()
+            .map(|transaction| transaction.inputs.len())
+            .sum();
+        assert_eq!(inputs, COINS);
+        assert_eq!(paying_others(&merged, &to), 0);
+        for transaction in merged {
+            builder.chain_mut().add_to_mempool(transaction).unwrap();
+        }
+    }
+
+    #[test]
+    fn consolidate_refuses_what_isnt_worth_merging() {
+        let mut builder = builder(662);
+        let mut wallet = wallet(&builder);
+        builder.fund(OURS, &[600, 700, 50_000]).unwrap();
+        builder.mine_blocks(MATURITY).unwrap();
+        wallet.scan(builder.chain());
+        let to = builder.key(OURS).public_key();
+        // a single coin below the threshold
+        assert!(matches!(
+            wallet.consolidate(builder.chain(), &to, 650, 1, false),
+            Err(WalletError::NothingToConsolidate { below: 650 })
+        ));
+        // two, paying more in fees than they're worth
+        match wallet.consolidate(builder.chain(), &to, 10_000, 5, false) {
+            Err(WalletError::Dust(_) | WalletError::FeeExceedsAmount { .. }) => {}
+            result => panic!("{result:?}"),
+        }
+    }
+
+    #[test]
+    fn locked_coin_is_left_out_even_if_that_falls_short() {
+        let mut builder = builder(665);
+        let mut wallet = wallet(&builder);
+        let coins = builder.fund(OURS, &[10_000, 3000]).unwrap();
+        builder.mine_blocks(MATURITY).unwrap();
+        wallet.scan(builder.chain());
+        let large = coins[0].hash();
+        assert_eq!(wallet.lock(&large).unwrap(), [large]);
+
+        // the small coin alone pays for a small payment
+        let send = wallet
+            .create_transaction(builder.chain(), &payment(&builder, 1000))
+            .unwrap();
+        let spent: Vec<Hash> = send
+            .inputs
+            .iter()
+            .map(|input| input.prev_transaction_output_hash)
+            .collect();
+        assert_eq!(spent, [coins[1].hash()]);
+        // but not for one only the locked coin could
+        match wallet.create_transaction(builder.chain(), &payment(&builder, 8000)) {
+            Err(WalletError::InsufficientFunds { available, .. }) => {
+                assert_eq!(available, 3000)
+            }
+            result => panic!("{result:?}"),
+        }
+        // a sweep leaves it too, unless asked not to
+        let to = builder.key(THEIRS).public_key();
+        let sweep = wallet.sweep(builder.chain(), &to, 1, false).unwrap();
+        assert_eq!(sweep[0].inputs.len(), 1);
+        let sweep = wallet.sweep(builder.chain(), &to, 1, true).unwrap();
+        assert_eq!(sweep[0].inputs.len(), 2);
+        // still counted in the balance
+        assert_eq!(wallet.balance(builder.chain()).confirmed, 13_000);
+
+        assert_eq!(wallet.unlock(&large).unwrap(), [large]);
+        let send = wallet
+            .create_transaction(builder.chain(), &payment(&builder, 8000))
+            .unwrap();
+        assert_eq!(send.inputs[0].prev_transaction_output_hash, large);
+        assert!(matches!(
+            wallet.unlock(&large),
+            Err(WalletError::NotLocked(hash)) if hash == large
+        ));
+    }
+}
@@ -0,0 +1,249 @@
+use crate::wallet::{Direction, TransactionRecord, Wallet, as_hex};
+use lib::sha256::{Hash, HashKeyedMap};
+use lib::types::Blockchain;
+use serde::Serialize;
+use std::io::Result as IoResult;
+use std::process::{Command, ExitStatus};
+
+// a change in how settled one of the wallet's transactions is
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WalletEvent {
+    // first sight of the transaction, in the mempool or a block
+    Seen {
+        #[serde(serialize_with = "as_hex")]
+        txid: Hash,
+        direction: Direction,
+        net: i64,
+    },
+    // one more block on top of it, up to the watched depth
+    Confirmed {
+        #[serde(serialize_with = "as_hex")]
+        txid: Hash,
+        net: i64,
+        confirmations: u64,
+    },
+    // a reorg took blocks with it, 0 means back in the mempool
+    Unconfirmed {
+        #[serde(serialize_with = "as_hex")]
+        txid: Hash,
+        net: i64,
+        confirmations: u64,
+    },
+    // gone from both the chain and the mempool
+    Dropped {
+        #[serde(serialize_with = "as_hex")]
+        txid: Hash,
+        net: i64,
+    },
+}
+
+impl WalletEvent {
+    pub fn txid(&self) -> Hash {
+        match self {
+            WalletEvent::Seen { txid, .. }
+            | WalletEvent::Confirmed { txid, .. }
+            | WalletEvent::Unconfirmed { txid, .. }
+            | WalletEvent::Dropped { txid, .. } => *txid,
+        }
+    }
+
+    // run `sh -c command` with the event name, txid, net
+    // value and confirmations as $1 to $4
+    pub fn notify(&self, command: &str) -> IoResult<ExitStatus> {
+        let (name, net, confirmations) = match self {
+            WalletEvent::Seen { net, .. } => ("seen", net, 0),
+            WalletEvent::Confirmed {
+                net, confirmations, ..
+            } => ("confirmed", net, *confirmations),
+            WalletEvent::Unconfirmed {
+                net, confirmations, ..
+            } => ("unconfirmed", net, *confirmations),
+            WalletEvent::Dropped { net, .. } => ("dropped", net, 0),
+        };
+        Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .arg("sh")
+            .arg(name)
+            .arg(self.txid().to_string())
+            .arg(net.to_string())
+            .arg(confirmations.to_string())
+            .status()
+    }
+}
+
+// turns successive scans of a wallet into events, counting
+// confirmations only up to depth
+pub struct WalletWatcher {
+    depth: u64,
+    // confirmations last reported, by txid
+    reported: HashKeyedMap<(u64, i64)>,
+}
+
+impl WalletWatcher {
+    // start from what the wallet already knows, without events for it
+    pub fn new(wallet: &Wallet, blockchain: &Blockchain, depth: u64) -> Self {
+        let mut watcher = WalletWatcher {
+            depth,
+            reported: HashKeyedMap::default(),
+        };
+        for record in wallet.history(blockchain) {
+            let confirmations = watcher.confirmations(wallet, &record);
+            watcher
+                .reported
+                .insert(record.txid, (confirmations, record.net()));
+        }
+        watcher
+    }
+
+    fn confirmations(&self, wallet: &Wallet, record: &TransactionRecord) -> u64 {
+        record
+            .height
+            .map_or(0, |height| wallet.confirmations(height))
+            .min(self.depth)
+    }
+
+    // events since the last update, the wallet scanned up to the chain
+    pub fn update(&mut self, wallet: &Wallet, blockchain: &Blockchain) -> Vec<WalletEvent> {
+        let mut events = vec![];
+        let mut current = HashKeyedMap::default();
+        // oldest first, so events come in chain order
+        for record in wallet.history(blockchain).into_iter().rev() {
+            let txid = record.txid;
+            let net = record.net();
+            let confirmations = self.confirmations(wallet, &record);
+            let reported = match self.reported.get(&txid) {
+                Some((reported, _)) => *reported,
+                None => {
+                    events.push(WalletEvent::Seen {
+                        txid,
+                        direction: record.direction,
+                        net,
+                    });
+                    0
+                }
+            };
+            if confirmations < reported {
+                events.push(WalletEvent::Unconfirmed {
+                    txid,
+                    net,
+                    confirmations,
+                });
+            }
+            for confirmations in reported + 1..=confirmations {
+                events.push(WalletEvent::Confirmed {
+                    txid,
+                    net,
+                    confirmations,
+                });
+            }
+            current.insert(txid, (confirmations, net));
+        }
+        for (txid, (_, net)) in &self.reported {
+            if !current.contains_key(txid) {
+                events.push(WalletEvent::Dropped {
+                    txid: *txid,
+                    net: *net,
+                });
+            }
+        }
+        self.reported = current;
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib::test_utils::ChainBuilder;
+
+    // the builder key of the wallet
+    const OURS: usize = 1;
+    // blocks both branches share
+    const SHARED: u64 = 2;
+    const DEPTH: u64 = 2;
+    const PAID: u64 = 3000;
+
+    fn shared() -> ChainBuilder {
+        let mut builder = ChainBuilder::new(659);
+        builder.mine_blocks(SHARED).unwrap();
+        builder
+    }
+
+    fn update(
+        watcher: &mut WalletWatcher,
+        wallet: &mut Wallet,
+        blockchain: &Blockchain,
+    ) -> Vec<WalletEvent> {
+        wallet.scan(blockchain);
+        watcher.update(wallet, blockchain)
+    }
+
+    #[test]
+    fn reorg_unconfirms_and_the_new_branch_confirms_again() {
+        let mut first = shared();
+        let mut wallet = Wallet::new(vec![first.key(OURS).clone()]);
+        wallet.scan(first.chain());
+        let mut watcher = WalletWatcher::new(&wallet, first.chain(), DEPTH);
+        let payment = first.spend(0, OURS, PAID, 100).unwrap();
+        let txid = payment.hash();
+        let confirmed = |confirmations| WalletEvent::Confirmed {
+            txid,
+            net: PAID as i64,
+            confirmations,
+        };
+
+        first.chain_mut().add_to_mempool(payment.clone()).unwrap();
+        assert_eq!(
+            update(&mut watcher, &mut wallet, first.chain()),
+            [WalletEvent::Seen {
+                txid,
+                direction: Direction::Received,
+                net: PAID as i64,
+            }]
+        );
+        let transactions = vec![payment.clone()];
+        first
+            .mine_block(|template| template.transactions = transactions)
+            .unwrap();
+        assert_eq!(
+            update(&mut watcher, &mut wallet, first.chain()),
+            [confirmed(1)]
+        );
+        first.mine_blocks(1).unwrap();
+        assert_eq!(
+            update(&mut watcher, &mut wallet, first.chain()),
+            [confirmed(2)]
+        );
+        // nothing past the depth watched
+        first.mine_blocks(1).unwrap();
+        assert_eq!(update(&mut watcher, &mut wallet, first.chain()), []);
+
+        // a longer branch without it, which has it back in the mempool
+        let mut second = shared();
+        second.mine_blocks(4).unwrap();
+        second.chain_mut().add_to_mempool(payment.clone()).unwrap();
+        assert_eq!(
+            update(&mut watcher, &mut wallet, second.chain()),
+            [WalletEvent::Unconfirmed {
+                txid,
+                net: PAID as i64,
+                confirmations: 0,
+            }]
+        );
+        let transactions = vec![payment];
+        second
+            .mine_block(|template| template.transactions = transactions)
+            .unwrap();
+        assert_eq!(
+            update(&mut watcher, &mut wallet, second.chain()),
+            [confirmed(1)]
+        );
+        second.mine_blocks(1).unwrap();
+        assert_eq!(
+            update(&mut watcher, &mut wallet, second.chain()),
+            [confirmed(2)]
+        );
+    }
+}
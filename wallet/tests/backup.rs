@@ -0,0 +1,69 @@
+// a wallet exported to json and imported into a new file scans to
+// the same balances and history, and importing again changes nothing
+mod common;
+
+use common::{Files, payment};
+use lib::test_utils::ChainBuilder;
+use serde_json::Value;
+
+const RECEIVED: u64 = 80_000;
+const WATCHED: u64 = 20_000;
+const SENT: u64 = 30_000;
+
+// what a scan of the chain makes of the wallet
+fn state(files: &Files, wallet: &str) -> (Value, Value, String) {
+    (
+        files.json(&["balance", wallet, "chain.cbor"]),
+        files.json(&["balance", wallet, "chain.cbor", "--by-address"]),
+        files.wallet(&["history", wallet, "chain.cbor"]),
+    )
+}
+
+#[test]
+fn imported_backup_matches_the_original() {
+    let files = Files::new();
+    let receive = files.create("wallet.cbor");
+    files.wallet(&["label", "wallet.cbor", &receive, "savings"]);
+    let mut builder = ChainBuilder::new(655);
+    let watched = builder.key(2).public_key().to_address();
+    files.wallet(&["import-watch", "wallet.cbor", &watched]);
+
+    let payouts = vec![payment(&receive, RECEIVED), payment(&watched, WATCHED)];
+    builder
+        .mine_block(|template| template.payouts = payouts)
+        .unwrap();
+    builder
+        .mine_blocks(builder.chain().params().coinbase_maturity)
+        .unwrap();
+    files.save_chain(&builder);
+    let payee = builder.key(1).public_key().to_address();
+    let amount = SENT.to_string();
+    files.wallet(&[
+        "send",
+        "wallet.cbor",
+        "chain.cbor",
+        "--to",
+        &payee,
+        "--amount",
+        &amount,
+    ]);
+    assert_eq!(files.mine_mempool(&mut builder).len(), 1);
+    let original = state(&files, "wallet.cbor");
+    assert!(original.2.contains("Sent"), "{}", original.2);
+
+    files.wallet(&["export", "wallet.cbor", "--out", "backup.json"]);
+    let imported = files.wallet(&["import", "imported.cbor", "backup.json"]);
+    assert!(
+        imported.contains("1 watch-only addresses and 1 labels"),
+        "{imported}"
+    );
+    assert_eq!(state(&files, "imported.cbor"), original);
+
+    // everything in the backup is already there the second time
+    let again = files.wallet(&["import", "imported.cbor", "backup.json"]);
+    assert_eq!(
+        again.trim(),
+        "imported 0 keys, 0 watch-only addresses and 0 labels"
+    );
+    assert_eq!(state(&files, "imported.cbor"), original);
+}
@@ -0,0 +1,176 @@
+// balance --by-address and utxos as scripts read them: the json of
+// each pinned, and the outputs of one address at a time adding up
+// to the whole
+mod common;
+
+use common::{Files, payment};
+use lib::params::NetworkParams;
+use lib::test_utils::{ChainBuilder, instant_params};
+use lib::utils::Saveable;
+use serde_json::{Value, json};
+
+// the builder keys watched, and paid by the watched one
+const WATCHED: usize = 2;
+const PAYEE: usize = 3;
+// so the coinbase of the tip is still immature
+const MATURITY: u64 = 2;
+
+fn utxos(files: &Files, address: Option<&str>) -> Vec<Value> {
+    let mut args = vec!["utxos", "wallet.cbor", "chain.cbor"];
+    if let Some(address) = address {
+        args.extend(["--address", address]);
+    }
+    files.json(&args).as_array().unwrap().clone()
+}
+
+fn total(utxos: &[Value]) -> u64 {
+    utxos
+        .iter()
+        .map(|utxo| utxo["value"].as_u64().unwrap())
+        .sum()
+}
+
+#[test]
+fn breakdown_by_address_adds_up_to_the_balance() {
+    let files = Files::new();
+    let savings = files.create("wallet.cbor");
+    files.wallet(&["label", "wallet.cbor", &savings, "savings"]);
+    let spending = files.wallet(&["new-address", "wallet.cbor"]);
+    let spending = spending.trim();
+    let params = NetworkParams {
+        coinbase_maturity: MATURITY,
+        ..instant_params()
+    };
+    let mut builder = ChainBuilder::with_params(663, params);
+    let watched = builder.key(WATCHED).public_key().to_address();
+    files.wallet(&["import-watch", "wallet.cbor", &watched]);
+
+    // confirmed coins on all three, an immature one on the second,
+    // and the watched coin spent in the mempool
+    let payouts = vec![
+        payment(&savings, 5000),
+        payment(&savings, 7000),
+        payment(spending, 2000),
+        payment(&watched, 9000),
+    ];
+    builder
+        .mine_block(|template| template.payouts = payouts)
+        .unwrap();
+    builder.mine_blocks(MATURITY).unwrap();
+    builder
+        .mine_block(|template| template.payouts = vec![payment(spending, 3000)])
+        .unwrap();
+    let spend = builder.spend(WATCHED, PAYEE, 4000, 100).unwrap();
+    builder.chain_mut().add_to_mempool(spend).unwrap();
+    files.save_chain(&builder);
+    builder
+        .chain()
+        .mempool_snapshot()
+        .save_to_file(files.path("mempool.cbor"))
+        .unwrap();
+
+    let by_address = files.json(&["balance", "wallet.cbor", "chain.cbor", "--by-address"]);
+    assert_eq!(
+        by_address,
+        json!([
+            {
+                "address": savings,
+                "label": "savings",
+                "watch_only": false,
+                "confirmed": 12_000,
+                "immature": 0,
+                "timelocked": 0,
+                "pending": 0,
+                "utxos": 2,
+            },
+            {
+                "address": spending,
+                "label": null,
+                "watch_only": false,
+                "confirmed": 2000,
+                "immature": 3000,
+                "timelocked": 0,
+                "pending": 0,
+                "utxos": 2,
+            },
+            {
+                "address": watched,
+                "label": null,
+                "watch_only": true,
+                "confirmed": 0,
+                "immature": 0,
+                "timelocked": 0,
+                // the change back, less the coin spent
+                "pending": 4900 - 9000,
+                "utxos": 1,
+            },
+        ])
+    );
+    let balance = files.json(&["balance", "wallet.cbor", "chain.cbor"]);
+    assert_eq!(
+        balance,
+        json!({
+            "confirmed": 14_000,
+            "pending": 4900,
+            "immature": 3000,
+            "timelocked": 0,
+        })
+    );
+
+    let all = utxos(&files, None);
+    let immature = all
+        .iter()
+        .find(|utxo| utxo["value"] == 3000)
+        .unwrap()
+        .as_object()
+        .unwrap();
+    let fields: Vec<&str> = immature.keys().map(String::as_str).collect();
+    assert_eq!(
+        fields,
+        [
+            "address",
+            "confirmations",
+            "locked",
+            "mature",
+            "output",
+            "spending",
+            "timelocked",
+            "txid",
+            "unlock_height",
+            "value",
+            "watch_only",
+        ]
+    );
+    for hash in ["output", "txid"] {
+        let hex = immature[hash].as_str().unwrap();
+        assert!(hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+    assert_eq!(immature["address"], spending);
+    assert_eq!(immature["confirmations"], 1);
+    assert_eq!(immature["mature"], false);
+    assert_eq!(immature["unlock_height"], Value::Null);
+    assert_eq!(immature["timelocked"], false);
+    assert_eq!(immature["spending"], false);
+    assert_eq!(immature["watch_only"], false);
+    assert_eq!(immature["locked"], false);
+
+    // each address alone, against its row and the whole list
+    let mut listed = 0;
+    for row in by_address.as_array().unwrap() {
+        let address = row["address"].as_str().unwrap();
+        let own = utxos(&files, Some(address));
+        assert_eq!(own.len() as u64, row["utxos"].as_u64().unwrap());
+        assert!(own.iter().all(|utxo| utxo["address"] == address));
+        let held: Vec<Value> = own
+            .into_iter()
+            .filter(|utxo| utxo["spending"] == false)
+            .collect();
+        let expected = ["confirmed", "immature", "timelocked"]
+            .iter()
+            .map(|field| row[field].as_u64().unwrap())
+            .sum::<u64>();
+        assert_eq!(total(&held), expected, "{address}");
+        listed += row["utxos"].as_u64().unwrap() as usize;
+    }
+    assert_eq!(listed, all.len());
+}
@@ -0,0 +1,53 @@
+// the wallet as a user runs it: --help, a usage error, which exits 2,
+// and checking a signed message, which needs no wallet file
+use assert_cmd::Command;
+use lib::crypto::{PrivateKey, Signature};
+use predicates::prelude::*;
+
+const MESSAGE: &str = "pay to the order of";
+
+fn wallet() -> Command {
+    Command::cargo_bin("wallet").unwrap()
+}
+
+fn key() -> PrivateKey {
+    PrivateKey::from_hex(&"07".repeat(32)).unwrap()
+}
+
+#[test]
+fn help() {
+    wallet().arg("--help").assert().success().stdout(
+        predicate::str::contains("Usage: wallet").and(predicate::str::contains("verify-message")),
+    );
+}
+
+#[test]
+fn invalid_address_is_a_usage_error() {
+    wallet()
+        .args(["verify-message", "not-an-address", MESSAGE, "00"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("<ADDRESS>"));
+}
+
+#[test]
+fn verifies_a_signed_message() {
+    let key = key();
+    let signature = Signature::sign_message(MESSAGE.as_bytes(), &key);
+    let address = key.public_key().to_address();
+    wallet()
+        .args(["verify-message", &address, MESSAGE, &signature.to_hex()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("signature is valid"));
+    wallet()
+        .args([
+            "verify-message",
+            &address,
+            "another message",
+            &signature.to_hex(),
+        ])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("not valid"));
+}
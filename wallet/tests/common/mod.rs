@@ -0,0 +1,112 @@
+// the wallet binary run on files in a temp dir, and a chain grown
+// with ChainBuilder for it to scan
+#![allow(dead_code)]
+use assert_cmd::Command;
+use assert_cmd::cargo::cargo_bin;
+use lib::crypto::PublicKey;
+use lib::test_utils::ChainBuilder;
+use lib::types::{MempoolSnapshot, Transaction, TransactionOutput};
+use lib::utils::Saveable;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use tempfile::TempDir;
+use uuid::Uuid;
+
+pub struct Files {
+    pub dir: TempDir,
+}
+
+impl Files {
+    pub fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("passphrase"), "correct horse").unwrap();
+        Files { dir }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    pub fn command(&self) -> Command {
+        Command::from_std(self.process())
+    }
+
+    // the same, to spawn and talk to while it runs
+    pub fn process(&self) -> process::Command {
+        let mut command = process::Command::new(cargo_bin("wallet"));
+        command.current_dir(self.dir.path()).args([
+            "--passphrase-file",
+            "passphrase",
+            "--keep-backups",
+            "0",
+        ]);
+        command
+    }
+
+    // runs the wallet with args, returning what it printed
+    pub fn wallet(&self, args: &[&str]) -> String {
+        let output = self
+            .command()
+            .args(args)
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    // runs the wallet with args and --json
+    pub fn json(&self, args: &[&str]) -> serde_json::Value {
+        let output = self.wallet(&[args, &["--json"]].concat());
+        serde_json::from_str(&output).unwrap_or_else(|e| panic!("{e}: {output}"))
+    }
+
+    // a new wallet file, returning its receive address
+    pub fn create(&self, wallet: &str) -> String {
+        let created = self.wallet(&["create", wallet]);
+        line_after(&created, "receive address: ").to_string()
+    }
+
+    pub fn save_chain(&self, builder: &ChainBuilder) {
+        builder
+            .chain()
+            .save_to_file(self.path("chain.cbor"))
+            .unwrap();
+    }
+
+    // mine what the wallet put in the chain file's mempool,
+    // returning the transactions mined
+    pub fn mine_mempool(&self, builder: &mut ChainBuilder) -> Vec<Transaction> {
+        let mempool = self.path("mempool.cbor");
+        let snapshot = MempoolSnapshot::load_from_file(&mempool).unwrap();
+        fs::remove_file(&mempool).unwrap();
+        let chain = builder.chain_mut();
+        assert!(chain.restore_mempool(snapshot).is_empty());
+        let transactions: Vec<_> = chain.mempool().iter().map(|(_, tx)| tx.clone()).collect();
+        let mined = transactions.clone();
+        builder
+            .mine_block(|template| template.transactions = transactions)
+            .unwrap();
+        self.save_chain(builder);
+        mined
+    }
+}
+
+pub fn line_after<'a>(output: &'a str, prefix: &str) -> &'a str {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix(prefix))
+        .unwrap_or_else(|| panic!("no {prefix:?} in {output}"))
+}
+
+// an output of value to an address, for a coinbase to pay
+pub fn payment(address: &str, value: u64) -> TransactionOutput {
+    TransactionOutput {
+        pubkey: PublicKey::from_address(address).unwrap(),
+        unique_id: Uuid::new_v4(),
+        value,
+        spendable_after_height: None,
+    }
+}
@@ -0,0 +1,61 @@
+// consolidate as run from the command line: nothing happens while it
+// isn't worth it or the fees are too high, otherwise the small coins
+// end up as one
+mod common;
+
+use common::{Files, payment};
+use lib::test_utils::ChainBuilder;
+
+fn consolidate(files: &Files, args: &[&str]) -> String {
+    files.wallet(&[&["consolidate", "wallet.cbor", "chain.cbor"], args].concat())
+}
+
+#[test]
+fn small_coins_are_merged_once_it_pays() {
+    let files = Files::new();
+    let address = files.create("wallet.cbor");
+    let mut builder = ChainBuilder::new(662);
+    let payouts = vec![payment(&address, 300), payment(&address, 300)];
+    builder
+        .mine_block(|template| template.payouts = payouts)
+        .unwrap();
+    builder
+        .mine_blocks(builder.chain().params().coinbase_maturity)
+        .unwrap();
+    files.save_chain(&builder);
+
+    // the fee would take more than the coins are worth
+    let printed = consolidate(&files, &[]);
+    assert!(
+        printed.starts_with("consolidation isn't economical"),
+        "{printed}"
+    );
+    assert!(!files.path("mempool.cbor").exists());
+
+    let payouts = (0..50).map(|_| payment(&address, 1000)).collect();
+    builder
+        .mine_block(|template| template.payouts = payouts)
+        .unwrap();
+    builder
+        .mine_blocks(builder.chain().params().coinbase_maturity)
+        .unwrap();
+    files.save_chain(&builder);
+    let printed = consolidate(&files, &["--max-fee-rate", "0"]);
+    assert_eq!(
+        printed.trim(),
+        "fee rate 1 is above the cap of 0, not consolidating"
+    );
+    assert!(!files.path("mempool.cbor").exists());
+
+    let printed = consolidate(&files, &[]);
+    assert!(printed.contains("merged 52 outputs for"), "{printed}");
+    let mined = files.mine_mempool(&mut builder);
+    let [merge] = mined.as_slice() else {
+        panic!("mined {mined:?}");
+    };
+    assert_eq!(merge.inputs.len(), 52);
+    let utxos = files.json(&["utxos", "wallet.cbor", "chain.cbor"]);
+    let utxos = utxos.as_array().unwrap();
+    assert_eq!(utxos.len(), 1);
+    assert_eq!(utxos[0]["value"], merge.outputs[0].value);
+}
@@ -0,0 +1,60 @@
+// a message signed with a key of the wallet checks out against its
+// address alone, and against nothing else
+mod common;
+
+use assert_cmd::Command;
+use common::Files;
+use lib::crypto::PrivateKey;
+use predicates::prelude::*;
+
+const MESSAGE: &str = "I control this address";
+
+// verify-message run outside the wallet's directory, with no
+// wallet or passphrase to hand
+fn verify(address: &str, message: &str, signature: &str) -> assert_cmd::assert::Assert {
+    Command::cargo_bin("wallet")
+        .unwrap()
+        .args(["verify-message", address, message, signature])
+        .assert()
+}
+
+#[test]
+fn signed_message_verifies_against_its_address_only() {
+    let files = Files::new();
+    let address = files.create("wallet.cbor");
+    let signed = files.wallet(&["sign-message", "wallet.cbor", &address, MESSAGE]);
+    let signature = signed.trim();
+
+    verify(&address, MESSAGE, signature)
+        .success()
+        .stdout("signature is valid\n");
+    // the message changed by a character
+    verify(&address, "I control this addresS", signature)
+        .code(1)
+        .stderr(predicate::str::contains("not valid"));
+    // another address of the wallet, or someone else's
+    let other = files.wallet(&["new-address", "wallet.cbor"]);
+    let stranger = PrivateKey::new_key().public_key().to_address();
+    for address in [other.trim(), &stranger] {
+        verify(address, MESSAGE, signature)
+            .code(1)
+            .stderr(predicate::str::contains(format!(
+                "Signature is not valid for {address}"
+            )));
+    }
+}
+
+#[test]
+fn only_addresses_of_the_wallet_are_signed_for() {
+    let files = Files::new();
+    files.create("wallet.cbor");
+    let stranger = PrivateKey::new_key().public_key().to_address();
+    files
+        .command()
+        .args(["sign-message", "wallet.cbor", &stranger, MESSAGE])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains(format!(
+            "Address {stranger} is not in the wallet"
+        )));
+}
@@ -0,0 +1,164 @@
+// a send broadcast to a running node: pending in the wallet until a
+// block with it reaches the node, confirmed after
+mod common;
+
+use common::{Files, payment};
+use lib::network::{Message, Services};
+use lib::params::NetworkParams;
+use lib::test_utils::ChainBuilder;
+use lib::utils::Saveable;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Lines};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::time::Duration;
+
+const FUNDED: u64 = 100_000;
+const SENT: u64 = 30_000;
+// how long the wallet waits on the node for its confirmation
+const TIMEOUT: &str = "20";
+
+// the node binary, built from its crate next to this one
+fn node_binary() -> PathBuf {
+    let node = Path::new(env!("CARGO_MANIFEST_DIR")).join("../node");
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(cargo)
+        .args(["build", "--quiet", "--manifest-path"])
+        .arg(node.join("Cargo.toml"))
+        .status()
+        .unwrap();
+    assert!(status.success(), "building the node failed");
+    node.join("target/debug/node")
+}
+
+// a child process killed when the test is done with it
+struct Running(Child);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// the peer and rpc addresses the node listens on, in the order
+// it prints them
+fn listening(lines: &mut Lines<BufReader<ChildStdout>>) -> (String, String) {
+    let mut addr = |text: &str| {
+        let line = lines
+            .map_while(Result::ok)
+            .find(|line| line.contains(text))
+            .unwrap_or_else(|| panic!("the node never printed {text:?}"));
+        line.split(' ')
+            .find_map(|part| part.strip_prefix("addr="))
+            .unwrap_or_else(|| panic!("no addr in {line:?}"))
+            .to_string()
+    };
+    let rpc = addr("listening for rpc");
+    (addr("listening for peers"), rpc)
+}
+
+fn balance(files: &Files) -> Value {
+    files.json(&["balance", "wallet.cbor", "chain.cbor"])
+}
+
+#[test]
+fn broadcast_send_is_confirmed_by_a_mined_block() {
+    let files = Files::new();
+    let address = files.create("wallet.cbor");
+    let params = NetworkParams::regtest();
+    let magic = params.magic;
+    let mut builder = ChainBuilder::with_params(658, params);
+    builder
+        .mine_block(|template| template.payouts = vec![payment(&address, FUNDED)])
+        .unwrap();
+    builder
+        .mine_blocks(builder.chain().params().coinbase_maturity)
+        .unwrap();
+    files.save_chain(&builder);
+    let datadir = files.path("node/regtest");
+    std::fs::create_dir_all(&datadir).unwrap();
+    builder
+        .chain()
+        .save_to_file(datadir.join("blockchain.cbor"))
+        .unwrap();
+
+    let mut child = Command::new(node_binary())
+        .arg("--datadir")
+        .arg(files.path("node"))
+        .args([
+            "--network",
+            "regtest",
+            "--listen",
+            "127.0.0.1",
+            "--port",
+            "0",
+        ])
+        .args(["--rpc-bind", "127.0.0.1:0"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut output = BufReader::new(child.stdout.take().unwrap()).lines();
+    let _node = Running(child);
+    let (peers, rpc) = listening(&mut output);
+
+    let payee = builder.key(1).public_key().to_address();
+    let amount = SENT.to_string();
+    // it exits by itself, confirmed or timed out
+    let mut wallet = files
+        .process()
+        .args(["send", "wallet.cbor", "chain.cbor", "--to", &payee])
+        .args(["--amount", &amount, "--node", &rpc])
+        .args(["--wait-confirmations", "1", "--timeout", TIMEOUT])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut printed = BufReader::new(wallet.stdout.take().unwrap()).lines();
+    printed
+        .by_ref()
+        .map_while(Result::ok)
+        .find(|line| line.starts_with("submitted transaction"))
+        .expect("the node never took the send");
+    printed
+        .by_ref()
+        .map_while(Result::ok)
+        .find(|line| line.starts_with("added transaction"))
+        .expect("the send never reached the chain file");
+
+    // spent, its change pending
+    let pending = balance(&files);
+    assert_eq!(pending["confirmed"], 0);
+    let change = pending["pending"].as_u64().unwrap();
+    assert!(change > 0 && change < FUNDED - SENT, "{pending}");
+
+    let mined = files.mine_mempool(&mut builder);
+    assert_eq!(mined.len(), 1);
+    let tip = builder.chain().blocks().last().unwrap().clone();
+    let mut peer = TcpStream::connect(peers).unwrap();
+    peer.set_read_timeout(Some(Duration::from_secs(20)))
+        .unwrap();
+    assert!(matches!(
+        Message::read_from(magic, &mut peer),
+        Ok(Message::Version { .. })
+    ));
+    Message::Version {
+        magic,
+        version: lib::PROTOCOL_VERSION,
+        services: Services::ALL,
+        block_height: builder.chain().block_height(),
+    }
+    .write_to(magic, &mut peer)
+    .unwrap();
+    Message::NewBlock(tip).write_to(magic, &mut peer).unwrap();
+
+    let confirmed = printed
+        .map_while(Result::ok)
+        .find(|line| line.contains("has 1 confirmations"));
+    assert!(wallet.wait().unwrap().success());
+    assert!(confirmed.is_some(), "the node never confirmed the send");
+    let balance = balance(&files);
+    assert_eq!(balance["confirmed"].as_u64(), Some(change));
+    assert_eq!(balance["pending"], 0);
+}
@@ -0,0 +1,91 @@
+// a wallet restored from its seed phrase alone ends up where the
+// original was: same balance, same history, same next address
+mod common;
+
+use common::{Files, line_after, payment};
+use lib::test_utils::ChainBuilder;
+use lib::utils::format_value;
+use std::fs;
+
+// receive addresses paid, leaving a gap at 2 to 4
+const PAID: [usize; 3] = [0, 1, 5];
+const RECEIVED: u64 = 50_000;
+const SENT: u64 = 70_000;
+
+fn state(files: &Files, wallet: &str) -> (String, String) {
+    (
+        files.wallet(&["balance", wallet, "chain.cbor"]),
+        files.wallet(&["history", wallet, "chain.cbor"]),
+    )
+}
+
+#[test]
+fn restored_wallet_matches_the_original() {
+    let files = Files::new();
+    let created = files.wallet(&["create", "wallet.cbor"]);
+    let phrase = created
+        .lines()
+        .skip_while(|line| !line.starts_with("write down the seed phrase"))
+        .nth(1)
+        .unwrap()
+        .to_string();
+    let mut addresses = vec![line_after(&created, "receive address: ").to_string()];
+    while addresses.len() <= PAID[PAID.len() - 1] {
+        addresses.push(
+            files
+                .wallet(&["new-address", "wallet.cbor"])
+                .trim()
+                .to_string(),
+        );
+    }
+
+    let mut builder = ChainBuilder::new(664);
+    let payouts = PAID
+        .iter()
+        .map(|index| payment(&addresses[*index], RECEIVED))
+        .collect();
+    builder
+        .mine_block(|template| template.payouts = payouts)
+        .unwrap();
+    builder
+        .mine_blocks(builder.chain().params().coinbase_maturity)
+        .unwrap();
+    files.save_chain(&builder);
+
+    // spending two of the payments leaves change on the change chain
+    let payee = builder.key(1).public_key().to_address();
+    let amount = SENT.to_string();
+    files.wallet(&[
+        "send",
+        "wallet.cbor",
+        "chain.cbor",
+        "--to",
+        &payee,
+        "--amount",
+        &amount,
+    ]);
+    assert_eq!(files.mine_mempool(&mut builder).len(), 1);
+    let original = state(&files, "wallet.cbor");
+    let (_, history) = &original;
+    assert!(history.contains(&format!("+{}", format_value(3 * RECEIVED))));
+    assert!(history.contains("Sent"), "{history}");
+    // kept aside to hand out the original's next address
+    fs::rename(files.path("wallet.cbor"), files.path("original.cbor")).unwrap();
+
+    let restored = files.wallet(&[
+        "restore",
+        "restored.cbor",
+        &phrase,
+        "--blockchain",
+        "chain.cbor",
+    ]);
+    assert!(
+        restored.contains("restored wallet restored.cbor"),
+        "{restored}"
+    );
+    assert_eq!(state(&files, "restored.cbor"), original);
+    // both hand out the address after the highest paid
+    let next = files.wallet(&["new-address", "restored.cbor"]);
+    assert_eq!(next, files.wallet(&["new-address", "original.cbor"]));
+    assert!(!addresses.contains(&next.trim().to_string()));
+}
@@ -0,0 +1,75 @@
+// a payment from one wallet to another, from a matured coinbase
+// through the mempool into a block
+mod common;
+
+use common::{Files, payment};
+use lib::params::NetworkParams;
+use lib::test_utils::{ChainBuilder, instant_params};
+use serde_json::{Value, json};
+
+const MATURITY: u64 = 3;
+const FUNDED: u64 = 100_000;
+const SENT: u64 = 30_000;
+
+fn balance(files: &Files, wallet: &str) -> Value {
+    files.json(&["balance", wallet, "chain.cbor"])
+}
+
+fn expected(confirmed: u64, pending: u64, immature: u64) -> Value {
+    json!({
+        "confirmed": confirmed,
+        "pending": pending,
+        "immature": immature,
+        "timelocked": 0,
+    })
+}
+
+#[test]
+fn payment_reaches_the_other_wallet() {
+    let files = Files::new();
+    let sender = files.create("sender.cbor");
+    let receiver = files.create("receiver.cbor");
+    let params = NetworkParams {
+        coinbase_maturity: MATURITY,
+        ..instant_params()
+    };
+    let mut builder = ChainBuilder::with_params(648, params);
+    builder
+        .mine_block(|template| template.payouts = vec![payment(&sender, FUNDED)])
+        .unwrap();
+    files.save_chain(&builder);
+    assert_eq!(balance(&files, "sender.cbor"), expected(0, 0, FUNDED));
+    builder.mine_blocks(MATURITY - 1).unwrap();
+    files.save_chain(&builder);
+    assert_eq!(balance(&files, "sender.cbor"), expected(FUNDED, 0, 0));
+
+    let amount = SENT.to_string();
+    files.wallet(&[
+        "send",
+        "sender.cbor",
+        "chain.cbor",
+        "--to",
+        &receiver,
+        "--amount",
+        &amount,
+        "--fee-rate",
+        "1",
+    ]);
+    // waiting in the mempool, the coin spent and the change pending
+    assert_eq!(balance(&files, "receiver.cbor"), expected(0, SENT, 0));
+    let pending = balance(&files, "sender.cbor");
+    assert_eq!(pending["confirmed"], 0);
+    let change = pending["pending"].as_u64().unwrap();
+    let mined = files.mine_mempool(&mut builder);
+    let [send] = mined.as_slice() else {
+        panic!("mined {mined:?}");
+    };
+    let fee = FUNDED - send.outputs.iter().map(|output| output.value).sum::<u64>();
+    assert!(fee > 0);
+    assert_eq!(change, FUNDED - SENT - fee);
+    assert_eq!(balance(&files, "receiver.cbor"), expected(SENT, 0, 0));
+    assert_eq!(
+        balance(&files, "sender.cbor"),
+        expected(FUNDED - SENT - fee, 0, 0)
+    );
+}
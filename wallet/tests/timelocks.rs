@@ -0,0 +1,129 @@
+// outputs locked until a height: the balance counts them as
+// timelocked, apart from what can be spent, until the next block is
+// at their unlock height; utxos shows the height, and send can't use
+// them before then and uses them from then on
+mod common;
+
+use common::{Files, payment};
+use lib::test_utils::{ChainBuilder, instant_params};
+use lib::types::TransactionOutput;
+use predicates::prelude::*;
+use serde_json::{Value, json};
+
+const UNLOCK: u64 = 4;
+const FAR: u64 = 100;
+const LOCKED: u64 = 5000;
+const LOCKED_FAR: u64 = 3000;
+const SPENDABLE: u64 = 2000;
+const SENT: u64 = 3000;
+
+fn locked(address: &str, value: u64, unlock_height: u64) -> TransactionOutput {
+    TransactionOutput {
+        spendable_after_height: Some(unlock_height),
+        ..payment(address, value)
+    }
+}
+
+fn balance(files: &Files) -> Value {
+    files.json(&["balance", "wallet.cbor", "chain.cbor"])
+}
+
+fn expected(confirmed: u64, timelocked: u64) -> Value {
+    json!({
+        "confirmed": confirmed,
+        "pending": 0,
+        "immature": 0,
+        "timelocked": timelocked,
+    })
+}
+
+// the utxo of the wallet holding value
+fn utxo(files: &Files, value: u64) -> Value {
+    let utxos = files.json(&["utxos", "wallet.cbor", "chain.cbor"]);
+    utxos
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|utxo| utxo["value"] == value)
+        .unwrap()
+        .clone()
+}
+
+fn send(files: &Files, to: &str) -> assert_cmd::assert::Assert {
+    files
+        .command()
+        .args(["send", "wallet.cbor", "chain.cbor", "--to", to])
+        .args(["--amount", &SENT.to_string(), "--fee-rate", "1"])
+        .assert()
+}
+
+#[test]
+fn locked_outputs_wait_for_their_height() {
+    let files = Files::new();
+    let address = files.create("wallet.cbor");
+    let mut builder = ChainBuilder::with_params(718, instant_params());
+    let to = builder.key(1).public_key().to_address();
+    let payouts = vec![
+        locked(&address, LOCKED, UNLOCK),
+        locked(&address, LOCKED_FAR, FAR),
+        payment(&address, SPENDABLE),
+    ];
+    builder
+        .mine_block(|template| template.payouts = payouts)
+        .unwrap();
+    builder
+        .mine_blocks(UNLOCK - 1 - builder.chain().chain_height())
+        .unwrap();
+    files.save_chain(&builder);
+
+    // the next block is one short of the unlock height
+    assert_eq!(builder.chain().chain_height(), UNLOCK - 1);
+    assert_eq!(balance(&files), expected(SPENDABLE, LOCKED + LOCKED_FAR));
+    let early = utxo(&files, LOCKED);
+    assert_eq!(early["unlock_height"], UNLOCK);
+    assert_eq!(early["timelocked"], true);
+    assert_eq!(early["mature"], true);
+    assert_eq!(utxo(&files, SPENDABLE)["unlock_height"], Value::Null);
+    let listed = files.wallet(&["utxos", "wallet.cbor", "chain.cbor"]);
+    assert!(
+        listed.contains(&format!("until height {UNLOCK}")),
+        "{listed}"
+    );
+    assert!(listed.contains(&format!("until height {FAR}")));
+    // the unlocked coin isn't enough
+    send(&files, &to)
+        .failure()
+        .stderr(predicate::str::contains(format!(
+            "Insufficient funds: {SPENDABLE} available"
+        )));
+
+    // at the unlock height it's spendable, the other still locked
+    builder.mine_blocks(1).unwrap();
+    files.save_chain(&builder);
+    assert_eq!(balance(&files), expected(SPENDABLE + LOCKED, LOCKED_FAR));
+    let unlocked = utxo(&files, LOCKED);
+    assert_eq!(unlocked["unlock_height"], UNLOCK);
+    assert_eq!(unlocked["timelocked"], false);
+    let listed = files.wallet(&["utxos", "wallet.cbor", "chain.cbor"]);
+    assert!(
+        !listed.contains(&format!("until height {UNLOCK}")),
+        "{listed}"
+    );
+    assert!(listed.contains(&format!("until height {FAR}")));
+
+    // and the send spends it, the largest coin, in a block at that
+    // height
+    send(&files, &to).success();
+    let mined = files.mine_mempool(&mut builder);
+    let [spend] = mined.as_slice() else {
+        panic!("mined {mined:?}");
+    };
+    let spent = unlocked["output"].as_str().unwrap();
+    assert_eq!(spend.inputs.len(), 1);
+    assert_eq!(spend.inputs[0].prev_transaction_output_hash.to_hex(), spent);
+    assert_eq!(builder.chain().block_height() - 1, UNLOCK);
+    let after = balance(&files);
+    assert_eq!(after["timelocked"], LOCKED_FAR);
+    let fee = LOCKED - spend.outputs.iter().map(|o| o.value).sum::<u64>();
+    assert_eq!(after["confirmed"], SPENDABLE + LOCKED - SENT - fee);
+}